@@ -18,12 +18,15 @@ fn updata_version_number() -> Result<()> {
     
     let ni = format!(r#"//! This is a uname constant, and will be update automatically on building.
 /// NOTE: following line will be found and modified by build.rs. ***DONT CHANGE THIS LINE MANUALLY!!!!***
-pub const VERSION : &[u8] = b"{}\0";"#, now.to_rfc2822());
+pub const VERSION : &[u8] = b"{}\0";
+/// Kernel release, from the crate version
+pub const RELEASE : &[u8] = b"{}-riscv64\0";"#, now.to_rfc2822(), env!("CARGO_PKG_VERSION"));
     writeln!(fo, "{}", ni)?;
     Ok(())
 }
 
 static TARGET_PATH: &str = "built_in_elfs/";
+static INITRAMFS_NAME: &str = "initramfs.cpio";
 
 fn insert_app_data() -> Result<()> {
     let mut f = File::create("src/link_app.asm").unwrap();
@@ -70,6 +73,24 @@ app_{0}_start:
     .incbin "{2}{1}"
 app_{0}_end:"#, idx, app, TARGET_PATH)?;
     }
+    insert_initramfs(&mut f)?;
     writeln!(f, "# Try to make cargo happy: last compiled @ {}", Utc::now().to_rfc2822()).unwrap();
     Ok(())
+}
+
+/// Embed built_in_elfs/initramfs.cpio (newc format) if it exists, an empty archive otherwise
+fn insert_initramfs(f: &mut File) -> Result<()> {
+    let path = format!("{}{}", TARGET_PATH, INITRAMFS_NAME);
+    writeln!(f, r#"
+    .section .data
+    .global _initramfs_start
+    .global _initramfs_end
+    .align 3
+_initramfs_start:"#)?;
+    if std::path::Path::new(&path).exists() {
+        println!("initramfs: {}", path);
+        writeln!(f, r#"    .incbin "{}""#, path)?;
+    }
+    writeln!(f, "_initramfs_end:")?;
+    Ok(())
 }
\ No newline at end of file
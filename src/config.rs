@@ -7,19 +7,31 @@ pub const KERNEL_STACK_SIZE : usize = 4096 * 32;
 /// Default user stack size. Will be override by `sys_clone()` arguments
 pub const USER_STACK_SIZE   : usize = 4096 * 32;
 
+/// Pages reserved (never mapped) below the user stack, so that an overflow faults instead of running into other mappings
+pub const USER_STACK_GUARD_PAGES : usize = 4;
+
 /// Kernel heap size, used in dynamic memory allocation (like vec and String)
 pub const KERNEL_HEAP_SIZE  : usize = 0x100000;
 
+/// Heap set aside for a syscall that exhausted the kernel heap, so that it can finish before its process is killed
+pub const KERNEL_HEAP_OOM_RESERVE   : usize = 0x10000;
+
+/// Pages taken from the frame allocator each time the kernel heap grows, 0 disables growing
+pub const KERNEL_HEAP_GROW_PAGES    : usize = 64;
+
+/// The kernel heap never grows beyond this
+pub const KERNEL_HEAP_MAX_SIZE      : usize = 0x1000000;
+
 /// Bits reperensenting page offset
 pub const PAGE_OFFSET       : usize = 12;
 
 /// 4KiB per page
 pub const PAGE_SIZE         : usize = 1 << PAGE_OFFSET;
 
-/// This is where the physical memory ends.
+/// This is where the physical memory ends if the device tree does not tell.
 /// ref: [k210-sdk-stuff/memory_map.md](https://github.com/laanwj/k210-sdk-stuff/blob/master/doc/memory_map.md)
-// pub const MEM_END           : usize = 0x80800000;  
-pub const MEM_END           : usize = 0x90000000;  
+// pub const DEFAULT_MEM_END   : usize = 0x80800000;  
+pub const DEFAULT_MEM_END   : usize = 0x90000000;  
 
 /// Position of Trampoline, which is a piece of code use for context switching when we switch priviledge levels (`ecall`/`sret`)
 #[no_mangle]
@@ -32,6 +44,58 @@ pub static TRAP_CONTEXT      : usize = TRAMPOLINE - PAGE_SIZE;
 /// Position of UTrampoline, which is a piece of code use for context switching when we switch priviledge levels (`ecall`/`sret`)
 pub static U_TRAMPOLINE      : usize = TRAP_CONTEXT - PAGE_SIZE;
 
+/// End of the lower half of the Sv39 address space
+pub const SV39_LOW_END      : usize = 1 << 38;
+
+/// Start of the higher half of the Sv39 address space, where the user stack and mmap areas live
+pub const SV39_HIGH_START   : usize = !(SV39_LOW_END - 1);
+
+/// Bit of an ISA extension letter in AT_HWCAP, same as in `misa`
+const fn isa_ext(ext: u8) -> usize {
+    1 << (ext - b'A')
+}
+
+/// AT_HWCAP reported to user programs. Both k210 and qemu virt are RV64GC (IMAFDC).
+pub const HWCAP             : usize = isa_ext(b'I') | isa_ext(b'M') | isa_ext(b'A') | isa_ext(b'F') | isa_ext(b'D') | isa_ext(b'C');
+
+/// Load base for position independent executables (ET_DYN)
+pub const ELF_DYN_BASE      : usize = 0x20_0000;
+
+/// Load base for the program interpreter (dynamic linker)
+pub const ELF_INTERP_BASE   : usize = 0x10_0000_0000;
+
+/// The exec image cache holds at most 1/ELF_CACHE_SHARE of the physical frames
+pub const ELF_CACHE_SHARE   : usize = 16;
+
+/// Interval between two runs of the background flush, in milliseconds
+pub const BDFLUSH_INTERVAL_MS   : u64 = 5000;
+
+/// Dirty blocks older than this are written back by the background flush, in milliseconds
+pub const BDFLUSH_DIRTY_AGE_MS  : u64 = 30000;
+
+/// Max blocks written back per run of the background flush, so that it never stalls foreground I/O for long
+pub const BDFLUSH_BATCH         : usize = 8;
+
+/// Kill the process with the largest resident set when user memory runs out,
+/// instead of just failing the allocation that ran out
+pub const OOM_KILLER            : bool = false;
+
+/// Free frames below this make the frame allocator warn and reclaim clean pages, cached exec images and block caches
+pub const FRAME_LOW_WATERMARK   : usize = 256;
+
+/// Carry out misaligned integer loads and stores of user programs byte by byte,
+/// instead of sending them SIGBUS
+pub const EMULATE_UNALIGNED     : bool = true;
+
+/// Have the SD card check command and data CRCs (CMD59), and check the CRC16 of every block read from it
+pub const SD_CRC_CHECK          : bool = false;
+/// Times a block read with a bad CRC is tried again before giving up
+pub const SD_CRC_RETRIES        : usize = 3;
+/// Times an SD card command that got no response is sent again before giving up
+pub const SD_CMD_RETRIES        : usize = 3;
+/// Wait before the first resend of a command that timed out, doubled for each further one
+pub const SD_RETRY_BACKOFF_US   : usize = 100;
+
 /// Max pipe ring buffer size. Same as linux.
 pub const PIP_BUF_MAX       : usize = 65536;
 
@@ -45,14 +109,12 @@ pub const CLOCK_FREQ: u64 = 12500000;
 
 /// UName constants, name of our OS
 pub const SYSNAME       : &[u8] = b"OSHIT Kernel (Pre-Alpha)\0";
+/// UName constants, hostname at boot, changed with sethostname
+pub const NODENAME      : &[u8] = b"oshit\0";
 /// UName constants
-pub const NODENAME      : &[u8] = b"Network currently unsupported\0";
-/// UName constants, OS version
-pub const RELEASE       : &[u8] = b"10.10.10-10-riscv64\0";
-/// UName constants
-pub const MACHINE       : &[u8] = b"UNKNOWN MACHINE\0";
-/// UName constants
-pub const DOMAINNAME    : &[u8] = b"UNKNOWN DOMAIN NAME\0";
+pub const MACHINE       : &[u8] = b"riscv64\0";
+/// UName constants, NIS domain name at boot, changed with setdomainname
+pub const DOMAINNAME    : &[u8] = b"(none)\0";
 /// Length of each field in `struct uname`
 pub const UTSNAME_LEN   : usize = 65;
 
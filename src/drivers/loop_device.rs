@@ -0,0 +1,110 @@
+//! Loop device
+//! # Description
+//! A block device backed by a file, so that a file system image stored as a file can be mounted.
+//! Block `n` is the `blk_sz` bytes at offset `n * blk_sz` of the file.
+//! Each block transfer seeks the shared file cursor, so transfers are serialized and the cursor is put back afterwards.
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+use crate::fs::{File, SeekOp};
+use super::{BlockDevice, IoError};
+
+pub struct LoopDevice {
+        file: Arc<dyn File>,
+        blk_sz: usize,
+        /// held across the seek and the transfer of a block
+        io_lock: Mutex<()>,
+}
+
+impl LoopDevice {
+        /// Create a loop device on `file`
+        /// # Description
+        /// `file` must be seekable, a regular file in practice. `blk_sz` must be a power of 2.
+        pub fn new(file: Arc<dyn File>, blk_sz: usize) -> Self {
+                if blk_sz == 0 || blk_sz & (blk_sz - 1) != 0 {
+                        panic!("Block size must be power of 2!")
+                }
+                Self {
+                        file,
+                        blk_sz,
+                        io_lock: Mutex::new(()),
+                }
+        }
+
+        /// The file behind the device
+        pub fn file(&self) -> Arc<dyn File> {
+                self.file.clone()
+        }
+
+        pub fn blk_sz(&self) -> usize {
+                self.blk_sz
+        }
+
+        /// Run `io` with the file cursor at block `block_id`, the cursor is restored after
+        /// # Description
+        /// Callers of the block device and users of the file itself may share the cursor,
+        /// so a seek and the read / write after it must not be split by another transfer.
+        fn at_block<T>(&self, block_id: usize, io: impl FnOnce(&dyn File) -> Result<T, IoError>) -> Result<T, IoError> {
+                let _guard = self.io_lock.lock();
+                let saved = self.file.get_cursor().map_err(|_| IoError)?;
+                self.file.seek((block_id * self.blk_sz) as isize, SeekOp::SET).map_err(|_| IoError)?;
+                let res = io(self.file.as_ref());
+                self.file.seek(saved as isize, SeekOp::SET).map_err(|_| IoError)?;
+                res
+        }
+}
+
+impl BlockDevice for LoopDevice {
+        /// Blocks past the end of the backing file read as zeros
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IoError> {
+                assert_eq!(buf.len(), self.blk_sz, "Buffer size != blk_sz!");
+                let len = self.at_block(block_id, |file| {
+                        let mut off = 0;
+                        while off < buf.len() {
+                                match file.read(&mut buf[off..]) {
+                                        Ok(0) => break,
+                                        Ok(len) => off += len,
+                                        Err(errno) => {
+                                                error!("Loop device read of block {} failed: {}", block_id, errno);
+                                                return Err(IoError);
+                                        },
+                                }
+                        }
+                        Ok(off)
+                })?;
+                buf[len..].fill(0);
+                Ok(())
+        }
+
+        /// Writing past the end of the backing file extends it
+        fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IoError> {
+                assert_eq!(buf.len(), self.blk_sz, "Buffer size != blk_sz!");
+                self.at_block(block_id, |file| {
+                        let mut off = 0;
+                        while off < buf.len() {
+                                match file.write(&buf[off..]) {
+                                        Ok(0) => {
+                                                error!("Loop device write of block {} made no progress", block_id);
+                                                return Err(IoError);
+                                        },
+                                        Ok(len) => off += len,
+                                        Err(errno) => {
+                                                error!("Loop device write of block {} failed: {}", block_id, errno);
+                                                return Err(IoError);
+                                        },
+                                }
+                        }
+                        Ok(())
+                })
+        }
+
+        fn clear_block(&self, block_id: usize) -> Result<(), IoError> {
+                let mut zeros: Vec<u8> = Vec::new();
+                zeros.resize(self.blk_sz, 0);
+                self.write_block(block_id, &zeros)
+        }
+
+        fn block_cnt(&self) -> u64 {
+                self.file.poll().size / self.blk_sz as u64
+        }
+}
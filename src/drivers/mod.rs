@@ -2,12 +2,15 @@
 
 pub mod sdcard;
 mod virt;
+mod loop_device;
 use core::any::Any;
 
 pub use sdcard::SDCard0WithLock;
+pub use loop_device::LoopDevice;
 
 use lazy_static::*;
 use alloc::sync::Arc;
+use crate::process::ErrNo;
 
 /// Selecting block device depending on conditional compiling
 #[cfg(feature = "board_qemu")]
@@ -22,6 +25,16 @@ lazy_static! {
         pub static ref BLOCK_DEVICE: Arc<dyn BlockDevice> = Arc::new(BlockDeviceImpl::new());
 }
 
+/// A block transfer the device failed to carry out, after its own retries if it has any
+#[derive(Debug, Copy, Clone)]
+pub struct IoError;
+
+impl From<IoError> for ErrNo {
+        fn from(_: IoError) -> Self {
+                ErrNo::IOError
+        }
+}
+
 /// A trait representing any block devices. If a struct implemented this trait, it can be mounted.
 pub trait BlockDevice : Send + Sync + Any {
 
@@ -33,11 +46,11 @@ pub trait BlockDevice : Send + Sync + Any {
         /// pub const BLK_SZ = 512;
         /// let mut buf = [0u8; BLK_SZ];
         /// let block_id: isize = 10;
-        /// BLOCK_DEVICE.read_block(block_id, &mut buf)
+        /// BLOCK_DEVICE.read_block(block_id, &mut buf)?;
         /// ```
         /// # Returns
-        /// No returns
-        fn read_block(&self, block_id: usize, buf: &mut [u8]);
+        /// Err(IoError) if the device failed, `buf` is undefined then
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IoError>;
 
         /// Write a block to the block device.
         /// # Description
@@ -47,22 +60,22 @@ pub trait BlockDevice : Send + Sync + Any {
         /// pub const BLK_SZ = 512;
         /// let buf = [10u8; BLK_SZ];
         /// let block_id: isize = 10;
-        /// BLOCK_DEVICE.write_block(block_id, buf)
+        /// BLOCK_DEVICE.write_block(block_id, buf)?;
         /// ```
         /// # Returns
-        /// No returns
-        fn write_block(&self, block_id: usize, buf: &[u8]);
+        /// Err(IoError) if the device failed, the block is undefined then
+        fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IoError>;
 
         /// Clear a spcific block in the block device.
         /// # Description
         /// Clear the block with id=`block_id` on the block device.
         /// # Examples
         /// ```
-        /// BLOCK_DEVICE.clear_block(10)
+        /// BLOCK_DEVICE.clear_block(10)?;
         /// ```
         /// # Returns
-        /// No returns
-        fn clear_block(&self, block_id: usize);
+        /// Err(IoError) if the device failed
+        fn clear_block(&self, block_id: usize) -> Result<(), IoError>;
 
         /// Get block count from a block device.
         /// # Description
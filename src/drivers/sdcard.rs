@@ -15,8 +15,9 @@ use k210_soc::{
 };
 use spin::Mutex;
 use lazy_static::*;
-use super::BlockDevice;
+use super::{BlockDevice, IoError};
 use core::convert::TryInto;
+use crate::config::{SD_CRC_CHECK, SD_CRC_RETRIES, SD_CMD_RETRIES, SD_RETRY_BACKOFF_US};
 
 pub const SD_START_DATA_SINGLE_BLOCK_READ: u8 = 0xFE;
 
@@ -56,6 +57,86 @@ pub enum InitError {
         CannotGetCardInfo,
 }
 
+/// Errors of a single data transfer
+#[derive(Debug, Copy, Clone)]
+enum TransferError {
+        /// The card answered a command with the R1 response
+        CMDFailed(CMD, u8),
+        /// No start token or a bad data response
+        DataToken(u8),
+        /// The CRC16 of a block read doesn't match its data
+        CRCMismatch,
+}
+
+impl TransferError {
+        /// The card didn't answer at all, `get_response` gives 0xFF on timeout.
+        /// A rejected data block also reads as 0xFF from `get_dataresponse`, resending it is harmless.
+        fn is_timeout(&self) -> bool {
+                match self {
+                        TransferError::CMDFailed(_, 0xFF) | TransferError::DataToken(0xFF) => true,
+                        _ => false,
+                }
+        }
+}
+
+/// Run a transfer on the card, again if it times out or has a bad CRC
+/// # Description
+/// Timeouts are retried `SD_CMD_RETRIES` times, waiting twice as long before each try.
+/// CRC mismatches are retried `SD_CRC_RETRIES` times.
+fn with_retry(what: &str, sector: u32, mut transfer: impl FnMut() -> Result<(), TransferError>) -> Result<(), TransferError> {
+        let mut timeouts = 0;
+        let mut crc_errors = 0;
+        loop {
+                let err = match transfer() {
+                        Ok(()) => return Ok(()),
+                        Err(err) => err,
+                };
+                if err.is_timeout() && timeouts < SD_CMD_RETRIES {
+                        usleep(SD_RETRY_BACKOFF_US << timeouts);
+                        timeouts += 1;
+                        warning!("SD card: timeout {} sector {}, retry {}", what, sector, timeouts);
+                } else if matches!(err, TransferError::CRCMismatch) && crc_errors < SD_CRC_RETRIES {
+                        crc_errors += 1;
+                        warning!("SD card: CRC mismatch {} sector {}, retry {}", what, sector, crc_errors);
+                } else {
+                        error!("SD card: failed {} sector {}: {:?}", what, sector, err);
+                        return Err(err);
+                }
+        }
+}
+
+/// CRC7 of a command frame, shifted into place with the end bit set
+fn crc7(data: &[u8]) -> u8 {
+        let mut crc = 0u8;
+        for byte in data {
+                let mut byte = *byte;
+                for _ in 0..8 {
+                        crc <<= 1;
+                        if (byte ^ crc) & 0x80 != 0 {
+                                crc ^= 0x09;
+                        }
+                        byte <<= 1;
+                }
+        }
+        (crc << 1) | 1
+}
+
+/// CRC16-CCITT (XMODEM) of a data block
+fn crc16(data: &[u8]) -> u16 {
+        let mut crc = 0u16;
+        for byte in data {
+                crc ^= (*byte as u16) << 8;
+                for _ in 0..8 {
+                        crc = if crc & 0x8000 != 0 {
+                                (crc << 1) ^ 0x1021
+                        } else {
+                                crc << 1
+                        };
+                }
+        }
+        crc
+}
+
 /// Card Identification Data: CID Register
 #[derive(Debug, Copy, Clone)]
 pub struct SDCardCID {
@@ -216,11 +297,12 @@ impl SDCard0 {
         }
 
         /// send commands to SD Card
+        /// # Description
+        /// `crc` is only sent as given while the card doesn't check CRCs, otherwise the real CRC7 is sent.
         fn send_cmd(&self, cmd: CMD, arg: u32, crc: u8) {
                 /* SD chip select low */
                 self.CS_LOW();
-                /* Send the Cmd bytes */
-                self.write_data(&[
+                let mut frame = [
                         /* Construct byte 1 */
                         ((cmd as u8) | 0x40),
                         /* Construct byte 2 */
@@ -233,7 +315,12 @@ impl SDCard0 {
                         (arg & 0xff) as u8,
                         /* Construct CRC: byte 6 */
                         crc,
-                ]);
+                ];
+                if SD_CRC_CHECK {
+                        frame[5] = crc7(&frame[..5]);
+                }
+                /* Send the Cmd bytes */
+                self.write_data(&frame);
         }
 
         /// end SD Card command sequence
@@ -525,38 +612,54 @@ impl SDCard0 {
                 if (frame[0] & 0x40) == 0 {
                         self.byte_addr = true;
                 }
+                if SD_CRC_CHECK {
+                        self.send_cmd(CMD::CMD59, 1, 0);
+                        let result = self.get_response();
+                        self.end_cmd();
+                        if result != 0x00 {
+                                return Err(InitError::CMDFailed(CMD::CMD59, result));
+                        }
+                }
                 self.HIGH_SPEED_ENABLE();
                 self.get_cardinfo().map_err(|_| InitError::CannotGetCardInfo)
         }
 
         /// read a sector in the SD Card
+        /// # Description
+        /// A read that times out or whose CRC doesn't match (with `SD_CRC_CHECK`) is tried again, see `with_retry`.
         pub fn read_sector(&self, data_buf: &mut [u8], sector: u32) -> Result<(), ()> {
                 if data_buf.len() < SEC_LEN || (data_buf.len() % SEC_LEN) != 0 {
                         return Err(());
                 }
+                with_retry("reading", sector, || self.read_sector_once(data_buf, sector)).map_err(|_| ())
+        }
+
+        /// read a sector in the SD Card, without retrying
+        fn read_sector_once(&self, data_buf: &mut [u8], sector: u32) -> Result<(), TransferError> {
                 let sector = if self.byte_addr {
                         sector * 512
                 } else {
                         sector
                 };
                 /* Send CMD17 to read one block, or CMD18 for multiple */
-                let flag = if data_buf.len() == SEC_LEN {
-                        self.send_cmd(CMD::CMD17, sector, 0);
-                        false
+                let (cmd, flag) = if data_buf.len() == SEC_LEN {
+                        (CMD::CMD17, false)
                 } else {
-                        self.send_cmd(CMD::CMD18, sector, 0);
-                        true
+                        (CMD::CMD18, true)
                 };
+                self.send_cmd(cmd, sector, 0);
                 /* Check if the SD acknowledged the read block command: R1 response (0x00: no errors) */
-                if self.get_response() != 0x00 {
+                let result = self.get_response();
+                if result != 0x00 {
                         self.end_cmd();
-                        return Err(());
+                        return Err(TransferError::CMDFailed(cmd, result));
                 }
-                let mut error = false;
+                let mut error = None;
                 let mut tmp_chunk= [0u8; SEC_LEN];
                 for chunk in data_buf.chunks_mut(SEC_LEN) {
-                        if self.get_response() != SD_START_DATA_SINGLE_BLOCK_READ {
-                                error = true;
+                        let token = self.get_response();
+                        if token != SD_START_DATA_SINGLE_BLOCK_READ {
+                                error = Some(TransferError::DataToken(token));
                                 break;
                         }
                         /* Read the SD block data : read NumByteToRead data */
@@ -567,16 +670,13 @@ impl SDCard0 {
                                 //*a = (b & 0xff) as u8;
                                 *a = *b;
                         }
-                        /* Get CRC bytes (not really needed by us, but required by SD) */
+                        /* Get CRC bytes, big endian */
                         let mut frame = [0u8; 2];
                         self.read_data(&mut frame);
-                        // for i in 0..32 {
-                        //         for j in 0..16 {
-                        //                 print!("{:02X} ", tmp_chunk[i * 16 + j]);
-                        //         }
-                        //         println!();
-                        // }
-                        // println!();
+                        if SD_CRC_CHECK && u16::from_be_bytes(frame) != crc16(chunk) {
+                                error = Some(TransferError::CRCMismatch);
+                                break;
+                        }
                 }
                 self.end_cmd();
                 if flag {
@@ -586,27 +686,34 @@ impl SDCard0 {
                         self.end_cmd();
                 }
                 /* It is an error if not everything requested was read */
-                if error {
-                        Err(())
-                } else {
-                        Ok(())
+                match error {
+                        Some(err) => Err(err),
+                        None => Ok(()),
                 }
         }
 
         /// write data to a sector on SD Card
+        /// # Description
+        /// A write that times out is tried again, see `with_retry`.
         pub fn write_sector(&self, data_buf: &[u8], sector: u32) -> Result<(), ()> {
                 if data_buf.len() < SEC_LEN || (data_buf.len() % SEC_LEN) != 0 {
                         return Err(());
                 }
+                with_retry("writing", sector, || self.write_sector_once(data_buf, sector)).map_err(|_| ())
+        }
+
+        /// write data to a sector on SD Card, without retrying
+        fn write_sector_once(&self, data_buf: &[u8], sector: u32) -> Result<(), TransferError> {
                 let sector = if self.byte_addr {
                         sector * 512
                 } else {
                         sector
                 };
                 let mut frame = [0xff, 0x00];
-                if data_buf.len() == SEC_LEN {
+                let cmd = if data_buf.len() == SEC_LEN {
                         frame[1] = SD_START_DATA_SINGLE_BLOCK_WRITE;
                         self.send_cmd(CMD::CMD24, sector, 0);
+                        CMD::CMD24
                 } else {
                         frame[1] = SD_START_DATA_MULTIPLE_BLOCK_WRITE;
                         self.send_cmd(
@@ -617,11 +724,13 @@ impl SDCard0 {
                         self.get_response();
                         self.end_cmd();
                         self.send_cmd(CMD::CMD25, sector, 0);
-                }
+                        CMD::CMD25
+                };
                 /* Check if the SD acknowledged the write block command: R1 response (0x00: no errors) */
-                if self.get_response() != 0x00 {
+                let result = self.get_response();
+                if result != 0x00 {
                         self.end_cmd();
-                        return Err(());
+                        return Err(TransferError::CMDFailed(cmd, result));
                 }
                 //let mut dma_chunk = [0u32; SEC_LEN];
                 let mut tmp_chunk = [0u8; SEC_LEN];
@@ -635,12 +744,13 @@ impl SDCard0 {
                         }
                         //self.write_data_dma(&mut dma_chunk);
                         self.write_data(&mut tmp_chunk);
-                        /* Put dummy CRC bytes */
-                        self.write_data(&[0xff, 0xff]);
+                        /* Put CRC bytes, only checked by the card with CMD59 */
+                        self.write_data(&crc16(chunk).to_be_bytes());
                         /* Read data response */
-                        if self.get_dataresponse() != 0x00 {
+                        let result = self.get_dataresponse();
+                        if result != 0x00 {
                                 self.end_cmd();
-                                return Err(());
+                                return Err(TransferError::DataToken(result));
                         }
                 }
                 self.end_cmd();
@@ -711,17 +821,64 @@ impl SDCard0WithLock {
 
 const ZEROS: [u8;512] = [0u8; 512];
 impl BlockDevice for SDCard0WithLock {
-        fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-                self.0.lock().read_sector(buf,block_id as u32).unwrap();
+        fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IoError> {
+                self.0.lock().read_sector(buf,block_id as u32).map_err(|_| IoError)
         }
-        fn write_block(&self, block_id: usize, buf: &[u8]) {
-                self.0.lock().write_sector(buf,block_id as u32).unwrap();
+        fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IoError> {
+                self.0.lock().write_sector(buf,block_id as u32).map_err(|_| IoError)
         }
-        fn clear_block(&self, block_id: usize) {
-                self.0.lock().write_sector(&ZEROS, block_id as u32).unwrap();
+        fn clear_block(&self, block_id: usize) -> Result<(), IoError> {
+                self.0.lock().write_sector(&ZEROS, block_id as u32).map_err(|_| IoError)
         }
         fn block_cnt(&self) -> u64{
                 let info = self.0.lock().info.unwrap();
                 info.CardBlockCnt * (info.CardBlockSize >> 9)
         }
 }
+
+/// The CRCs against their check values, and the retries of timeouts and bad CRCs with a faked card
+/// # Description
+/// No card is touched, the transfers fail as told.
+#[cfg(feature = "kernel_tests")]
+pub fn crc_retry_test() {
+        verbose!("Testing SD card CRCs and retries...");
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        // CMD0, whose CRC is the only one a card in SPI mode checks by default
+        assert_eq!(crc7(&[0x40, 0, 0, 0, 0]), 0x95);
+        // bad CRCs, then a good block
+        let mut tries = 0;
+        assert!(with_retry("reading", 0, || {
+                tries += 1;
+                if tries <= SD_CRC_RETRIES { Err(TransferError::CRCMismatch) } else { Ok(()) }
+        }).is_ok());
+        assert_eq!(tries, SD_CRC_RETRIES + 1);
+        // the first command gets no response, the resent one goes through
+        let mut tries = 0;
+        assert!(with_retry("reading", 0, || {
+                tries += 1;
+                if tries == 1 { Err(TransferError::CMDFailed(CMD::CMD17, 0xFF)) } else { Ok(()) }
+        }).is_ok());
+        assert_eq!(tries, 2);
+        // a card that never answers
+        let mut tries = 0;
+        assert!(matches!(with_retry("writing", 0, || {
+                tries += 1;
+                Err(TransferError::DataToken(0xFF))
+        }), Err(TransferError::DataToken(0xFF))));
+        assert_eq!(tries, SD_CMD_RETRIES + 1);
+        // retries run out
+        let mut tries = 0;
+        assert!(matches!(with_retry("reading", 0, || {
+                tries += 1;
+                Err(TransferError::CRCMismatch)
+        }), Err(TransferError::CRCMismatch)));
+        assert_eq!(tries, SD_CRC_RETRIES + 1);
+        // other errors are not retried
+        let mut tries = 0;
+        assert!(with_retry("reading", 0, || {
+                tries += 1;
+                Err(TransferError::DataToken(0x0B))
+        }).is_err());
+        assert_eq!(tries, 1);
+        verbose!("SD card CRC and retry test passed!");
+}
@@ -3,7 +3,7 @@ use virtio_drivers::{VirtIOBlk, VirtIOHeader};
 use crate::memory::{FrameTracker, PageTable, PhysAddr, PhysPageNum, VirtAddr, alloc_continuous, alloc_frame, free_frame, kernel_satp};
 use crate::sbi::get_time_ms;
 use crate::utils::StepByOne;
-use super::BlockDevice;
+use super::{BlockDevice, IoError};
 use spin::Mutex;
 use alloc::vec::Vec;
 use lazy_static::*;
@@ -20,8 +20,11 @@ lazy_static! {
 
 const ZEROS: [u8;512] = [0u8; 512];
 impl BlockDevice for VirtIOBlock {
-    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        self.0.lock().read_block(block_id, buf).expect("Error when reading VirtIOBlk");
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IoError> {
+        if let Err(err) = self.0.lock().read_block(block_id, buf) {
+            error!("Error when reading VirtIOBlk block {}: {:?}", block_id, err);
+            return Err(IoError);
+        }
         
         unsafe { asm!("fence.i"); }
         for i in 0..512 {
@@ -34,12 +37,16 @@ impl BlockDevice for VirtIOBlock {
             }
         }
         unsafe { asm!("fence.i"); }
+        Ok(())
     }
-    fn write_block(&self, block_id: usize, buf: &[u8]) {
-        self.0.lock().write_block(block_id, buf).expect("Error when writing VirtIOBlk");
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IoError> {
+        self.0.lock().write_block(block_id, buf).map_err(|err| {
+            error!("Error when writing VirtIOBlk block {}: {:?}", block_id, err);
+            IoError
+        })
     }
-    fn clear_block(&self, block_id: usize) {
-        self.0.lock().write_block(block_id, &ZEROS).unwrap();
+    fn clear_block(&self, block_id: usize) -> Result<(), IoError> {
+        self.write_block(block_id, &ZEROS)
     }
     fn block_cnt(&self) -> u64 {
         0
@@ -0,0 +1,57 @@
+//! Background write back of dirty blocks
+//! # Description
+//! Without it dirty blocks only reach the disk on sync or unmount.
+//! Driven by the kernel timer check, both on timer interrupts and in the idle loop: every `BDFLUSH_INTERVAL_MS`,
+//! blocks dirty for more than `BDFLUSH_DIRTY_AGE_MS` are written back, at most `BDFLUSH_BATCH` of them per run.
+//! Busy file systems and caches are skipped and retried on the next run.
+
+use crate::config::{CLOCK_FREQ, BDFLUSH_INTERVAL_MS, BDFLUSH_DIRTY_AGE_MS, BDFLUSH_BATCH};
+use crate::sbi::get_time;
+use lazy_static::*;
+use spin::Mutex;
+use super::try_flush_older;
+
+lazy_static! {
+    /// time of the next run
+    static ref NEXT_FLUSH: Mutex<u64> = Mutex::new(0);
+}
+
+fn ms_to_ticks(ms: u64) -> u64 {
+    ms * CLOCK_FREQ / 1000
+}
+
+/// Write back old dirty blocks if the flush interval has passed since the last run.
+/// # Description
+/// Called with the other kernel timers, see `check_timers`.
+pub fn bdflush_tick() {
+    let now = get_time();
+    {
+        let mut next = match NEXT_FLUSH.try_lock() {
+            Some(next) => next,
+            None => return,
+        };
+        if now < *next {
+            return;
+        }
+        *next = now + ms_to_ticks(BDFLUSH_INTERVAL_MS);
+    }
+    let written = bdflush_at(now);
+    if written != 0 {
+        verbose!("bdflush: wrote back {} blocks", written);
+    }
+}
+
+/// Time of the next run, the idle loop must wake up for it
+pub fn next_bdflush() -> u64 {
+    *NEXT_FLUSH.lock()
+}
+
+/// Write back blocks that are too old at time "now"
+/// # Description
+/// One run of the background flush, regardless of the interval.
+/// # Return
+/// # of blocks written
+pub fn bdflush_at(now: u64) -> usize {
+    let deadline = now.saturating_sub(ms_to_ticks(BDFLUSH_DIRTY_AGE_MS));
+    try_flush_older(deadline, BDFLUSH_BATCH)
+}
@@ -254,7 +254,7 @@ pub fn get_cluster_cache(cluster: u32, offset: u32) -> Option<u32> {
                 return None;
         }
         let cluster = cluster - DBR_INST.root;
-        if cluster > DBR_INST.clst_cnt || offset > DBR_INST.clst_size {
+        if cluster > DBR_INST.clst_cnt || offset >= DBR_INST.clst_size {
                 return None;
         }
         let mut sector: u32 = (*DBR_INST).data_sec_base + (*DBR_INST).clst_sec * cluster;
@@ -270,7 +270,8 @@ pub fn read_cluster(cluster: u32, offset: u32, buf: &mut [u8]) ->Result<u32,&str
                 return Err("read_cluster: Invalid Offset");
         }
         
-        let mut len = buf.len();
+        // clamp the transfer to the cluster boundary, never spill into the next cluster
+        let mut len = buf.len().min((DBR_INST.clst_size - offset) as usize);
         let mut read:u32 = 0;
         let mut offset = offset;
         while len > 0 {
@@ -289,7 +290,7 @@ pub fn read_cluster(cluster: u32, offset: u32, buf: &mut [u8]) ->Result<u32,&str
                         return Ok(read);
                 } 
         }
-        return Ok(buf.len() as u32);
+        return Ok(read);
 }
 
 pub fn write_cluster(cluster: u32, offset: u32, buf: &[u8]) -> Result<u32, &str> {
@@ -300,7 +301,8 @@ pub fn write_cluster(cluster: u32, offset: u32, buf: &[u8]) -> Result<u32, &str>
                 return Err("write_cluster: Invalid Offset");
         }
 
-        let mut len = buf.len();
+        // clamp the transfer to the cluster boundary, never spill into the next cluster
+        let mut len = buf.len().min((DBR_INST.clst_size - offset) as usize);
         let mut write: u32 = 0;
         let mut offset = offset;
         while len > 0 {
@@ -319,7 +321,7 @@ pub fn write_cluster(cluster: u32, offset: u32, buf: &[u8]) -> Result<u32, &str>
                         return Ok(write);
                 } 
         }
-        return Ok(buf.len() as u32);
+        return Ok(write);
 }
 
 pub fn clear_cluster(cluster:u32) -> Result<(), &'static str> {
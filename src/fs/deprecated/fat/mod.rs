@@ -517,11 +517,24 @@ pub fn new_entry_at(parent: &DirEntry, new: &DirEntry, name: &String) -> Result<
 #[allow(unused)]
 pub fn ls_root() {
         let chain = get_file_chain(*ROOT_DIR);
+        for name in ls_entries(|offset| read_dirent(&chain, offset)) {
+                println!("{}", name);
+        }
+}
+
+/// List the names in a directory
+/// # Description
+/// `read` returns the entry at the given offset, or None at the first zero-name entry,
+/// which terminates the directory. Deleted and volume entries are skipped.
+pub fn ls_entries<F: Fn(u32) -> Option<DirEntry>>(read: F) -> Vec<String> {
+        let mut names = Vec::new();
         let mut offset = 0;
         let mut dex = Vec::<DirEntryExt>::new();
         loop{
-                if let Some(dirent) = read_dirent(&chain, offset) {
+                if let Some(dirent) = read(offset) {
+                        offset += 1;
                         if dirent.deleted() || dirent.is_vol() {
+                                dex.clear();
                                 continue;
                         } 
                         if dirent.is_ext() { 
@@ -529,16 +542,21 @@ pub fn ls_root() {
                                         let dirent = *((&dirent as *const _) as *const DirEntryExt);
                                         dex.push(dirent);
                                 }
+                                continue;
                         }
                         if dirent.is_dir() || dirent.is_file() {
-                                println!("{}", get_full_name(&mut dex).unwrap());
+                                if dex.len() > 0 {
+                                        names.push(get_full_name(&mut dex).unwrap());
+                                        dex.clear();
+                                } else {
+                                        names.push(dirent.get_name());
+                                }
                         }
-                        // print!("ls_root:{} {} \t", cluster, offset - 1);
-                        dirent.print();
                 } else {
                         break;
                 }
         }
+        return names;
 }
 
 pub fn flush() {
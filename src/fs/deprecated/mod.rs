@@ -127,6 +127,44 @@ pub fn sdcard_test() {
         info!("sdcard test passed");
 }
 
+/// Test for ls_root
+/// # Description
+/// A deleted entry followed by a live one must list the live entry and terminate.
+#[allow(unused)]
+pub fn ls_root_test() {
+        let deleted = DirEntry {
+                name: *b"\xe5OO     ",
+                ext: *b"TXT",
+                attr: DirEntry::attr_file(),
+                reserved: 0,
+                created_minisec: 0,
+                created_sec: 0,
+                created_date: 0,
+                accessed_sec: 0,
+                start_h: 0,
+                mod_sec: 0,
+                mod_date: 0,
+                start_l: 0,
+                size: 0,
+        };
+        let mut live = deleted;
+        live.name = *b"BAR     ";
+        let mut end = deleted;
+        end.name = [0u8; 8];
+        let dir = [deleted, live, end];
+        let names = fat::ls_entries(|offset| {
+                let dirent = dir[offset as usize];
+                if dirent.name[0] == 0 {
+                        None
+                } else {
+                        Some(dirent)
+                }
+        });
+        assert_eq!(names.len(), 1);
+        assert_eq!(names[0], "BAR.TXT");
+        info!("ls_root test passed");
+}
+
 pub fn stat_file(path_s:& str) -> Result<DirEntry, &'static str> {
         match path::parse_path(path_s) {
                 Ok(path_v) => {
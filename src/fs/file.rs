@@ -52,8 +52,8 @@ pub struct FileStatus {
     // todo: finish this
 }
 
-/// File traits. Mostly inspired by linux file_operations struct. Implements Drop Trait.
-pub trait File: Drop + Send + Sync {
+/// File traits. Mostly inspired by linux file_operations struct.
+pub trait File: Send + Sync {
     /// seek cursor. Some type of file not support this (like char device)
     fn seek(&self, offset: isize, op: SeekOp) -> Result<(), ErrNo>;
 
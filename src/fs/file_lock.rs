@@ -0,0 +1,363 @@
+//! Advisory file locks
+//! # Description
+//! Whole file locks taken with flock. A lock belongs to an open file, i.e. the `Arc<dyn File>`
+//! shared by dup'ed and inherited fds, and is released once the open file is dropped.
+//! Byte range locks taken with fcntl belong to a process instead. They are not inherited by fork,
+//! and all of them on a file go when the process closes any fd of that file, or exits.
+//! Files are told apart by fs and path, as FAT32 has no inode numbers and empty files have no start cluster.
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+use lazy_static::*;
+use super::File;
+use crate::process::{ErrNo, WaitQueue, current_signal_pending};
+
+/// Shared lock
+pub const LOCK_SH: usize = 1;
+/// Exclusive lock
+pub const LOCK_EX: usize = 2;
+/// Don't block, fail with EWOULDBLOCK instead
+pub const LOCK_NB: usize = 4;
+/// Unlock
+pub const LOCK_UN: usize = 8;
+
+/// (`fs_id()` of the fs, path), or (address of the open file, "") for files out of any fs
+type FileKey = (usize, String);
+
+/// An open file holding a flock
+/// # Description
+/// Weak, so that the lock goes with the last reference to the open file wherever that is dropped.
+/// It also keeps the allocation alive, no new open file can show up at the same address while the entry exists.
+type Owner = Weak<dyn File>;
+
+/// flock state of a file
+struct FlockState {
+    /// open files holding a shared lock
+    shared: Vec<Owner>,
+    /// open file holding the exclusive lock
+    exclusive: Option<Owner>,
+    /// processes waiting for the locks to change
+    queue: Arc<WaitQueue>,
+}
+
+/// Check if `owner` is the open file `file`
+fn is_owner(owner: &Owner, file: &Arc<dyn File>) -> bool {
+    Weak::as_ptr(owner) as *const u8 == Arc::as_ptr(file) as *const u8
+}
+
+impl FlockState {
+    fn new() -> Self {
+        Self {
+            shared: Vec::new(),
+            exclusive: None,
+            queue: Arc::new(WaitQueue::new()),
+        }
+    }
+
+    fn is_free(&self) -> bool {
+        self.shared.is_empty() && self.exclusive.is_none()
+    }
+
+    /// Drop the locks of open files that are gone
+    /// # Return
+    /// True if there were any
+    fn purge(&mut self) -> bool {
+        let len = self.shared.len();
+        self.shared.retain(|owner| owner.strong_count() != 0);
+        if self.exclusive.as_ref().map_or(false, |owner| owner.strong_count() == 0) {
+            self.exclusive = None;
+            return true;
+        }
+        len != self.shared.len()
+    }
+
+    /// Drop the lock of `file`, if any
+    /// # Return
+    /// True if there was one
+    fn unlock(&mut self, file: &Arc<dyn File>) -> bool {
+        let len = self.shared.len();
+        self.shared.retain(|owner| !is_owner(owner, file));
+        if self.exclusive.as_ref().map_or(false, |owner| is_owner(owner, file)) {
+            self.exclusive = None;
+            return true;
+        }
+        len != self.shared.len()
+    }
+
+    /// Take the lock for `file` if nobody else holds a conflicting one
+    fn try_lock(&mut self, file: &Arc<dyn File>, exclusive: bool) -> bool {
+        self.purge();
+        let others_shared = self.shared.iter().any(|owner| !is_owner(owner, file));
+        let others_exclusive = self.exclusive.as_ref().map_or(false, |owner| !is_owner(owner, file));
+        if others_exclusive || (exclusive && others_shared) {
+            return false;
+        }
+        self.unlock(file);
+        if exclusive {
+            self.exclusive = Some(Arc::downgrade(file));
+        } else {
+            self.shared.push(Arc::downgrade(file));
+        }
+        true
+    }
+}
+
+lazy_static! {
+    static ref FLOCKS: Mutex<BTreeMap<FileKey, FlockState>> = Mutex::new(BTreeMap::new());
+}
+
+/// Identity of the file behind `file`, shared by all its opens
+fn key_of(file: &Arc<dyn File>) -> FileKey {
+    match file.get_vfs() {
+        Ok(vfs) => (vfs.fs_id(), file.get_path().to_string()),
+        Err(_) => (Arc::as_ptr(file) as *const u8 as usize, String::new()),
+    }
+}
+
+/// Apply flock operation `op` to the open file `file`
+/// # Description
+/// Sleeps while a conflicting lock is held, unless LOCK_NB is set.
+/// Changing a held lock drops it first, like Linux does.
+/// # Return
+/// Err(TryAgain) (EWOULDBLOCK) with LOCK_NB, Err(InterruptedSystemCall) if a signal came while waiting.
+pub fn flock(file: &Arc<dyn File>, op: usize) -> Result<(), ErrNo> {
+    let exclusive = match op & !LOCK_NB {
+        LOCK_SH => false,
+        LOCK_EX => true,
+        LOCK_UN => {
+            unlock_file(file);
+            return Ok(());
+        },
+        _ => return Err(ErrNo::InvalidArgument),
+    };
+    let key = key_of(file);
+    loop {
+        let mut locks = FLOCKS.lock();
+        let state = locks.entry(key.clone()).or_insert_with(FlockState::new);
+        if state.try_lock(file, exclusive) {
+            return Ok(());
+        }
+        if op & LOCK_NB != 0 {
+            return Err(ErrNo::TryAgain);
+        }
+        let queue = state.queue.clone();
+        drop(locks);
+        // a signal needs to be handled first
+        if current_signal_pending() {
+            return Err(ErrNo::InterruptedSystemCall);
+        }
+        queue.wait();
+    }
+}
+
+/// Drop the flock held through `file` and wake up the waiters
+fn unlock_file(file: &Arc<dyn File>) {
+    let key = key_of(file);
+    let mut locks = FLOCKS.lock();
+    let queue = match locks.get_mut(&key) {
+        Some(state) => {
+            if !state.unlock(file) {
+                return;
+            }
+            let queue = state.queue.clone();
+            if state.is_free() {
+                locks.remove(&key);
+            }
+            queue
+        },
+        None => return,
+    };
+    drop(locks);
+    queue.wake_all();
+}
+
+/// Release the flocks of open files that are gone
+/// # Description
+/// Called by `Drop` of the files, the last reference to an open file may go anywhere:
+/// close, dup2 over it, exit, or the reaping of a zombie still holding it.
+/// Waking up the waiters takes their PCB locks, so no lock of a waiting process may be held.
+pub fn release_dropped_flocks() {
+    let mut queues = Vec::new();
+    let mut locks = FLOCKS.lock();
+    if locks.is_empty() {
+        return;
+    }
+    for state in locks.values_mut() {
+        if state.purge() {
+            queues.push(state.queue.clone());
+        }
+    }
+    locks.retain(|_, state| !state.is_free());
+    drop(locks);
+    for queue in queues.iter() {
+        queue.wake_all();
+    }
+}
+
+/// Shared record lock
+pub const F_RDLCK: usize = 0;
+/// Exclusive record lock
+pub const F_WRLCK: usize = 1;
+/// Remove record locks
+pub const F_UNLCK: usize = 2;
+
+/// A byte range locked by a process
+#[derive(Clone, Copy, Debug)]
+pub struct RecordLock {
+    /// F_RDLCK, F_WRLCK, or F_UNLCK for a request dropping the range
+    pub ltype: usize,
+    /// first byte
+    pub start: usize,
+    /// byte after the last one, usize::MAX if the range goes on to EOF and beyond
+    pub end: usize,
+    /// owner
+    pub pid: usize,
+}
+
+impl RecordLock {
+    fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.start < end && start < self.end
+    }
+
+    /// Another process wants an overlapping range, and one of the two is a write lock
+    fn conflicts(&self, other: &RecordLock) -> bool {
+        self.pid != other.pid && self.overlaps(other.start, other.end)
+            && (self.ltype == F_WRLCK || other.ltype == F_WRLCK)
+    }
+}
+
+/// fcntl locks on a file
+struct RecordState {
+    /// ranges held, ranges of the same process never overlap
+    locks: Vec<RecordLock>,
+    /// processes waiting for the locks to change
+    queue: Arc<WaitQueue>,
+}
+
+impl RecordState {
+    fn new() -> Self {
+        Self {
+            locks: Vec::new(),
+            queue: Arc::new(WaitQueue::new()),
+        }
+    }
+
+    fn conflict(&self, lock: &RecordLock) -> Option<RecordLock> {
+        self.locks.iter().find(|held| held.conflicts(lock)).copied()
+    }
+
+    /// Take [start, end) out of the ranges of `pid`, ranges sticking out on either side are cut
+    /// # Return
+    /// True if any range was touched
+    fn clear(&mut self, pid: usize, start: usize, end: usize) -> bool {
+        let mut changed = false;
+        let mut kept = Vec::with_capacity(self.locks.len());
+        for held in self.locks.iter() {
+            if held.pid != pid || !held.overlaps(start, end) {
+                kept.push(*held);
+                continue;
+            }
+            changed = true;
+            if held.start < start {
+                kept.push(RecordLock { end: start, ..*held });
+            }
+            if held.end > end {
+                kept.push(RecordLock { start: end, ..*held });
+            }
+        }
+        self.locks = kept;
+        changed
+    }
+}
+
+lazy_static! {
+    static ref RECORDS: Mutex<BTreeMap<FileKey, RecordState>> = Mutex::new(BTreeMap::new());
+}
+
+/// Find a lock of another process standing in the way of `lock` on `file`, for F_GETLK
+pub fn test_record_lock(file: &Arc<dyn File>, lock: &RecordLock) -> Option<RecordLock> {
+    RECORDS.lock().get(&key_of(file))?.conflict(lock)
+}
+
+/// Lock or unlock a byte range of `file`, for F_SETLK and F_SETLKW
+/// # Description
+/// The range replaces whatever `lock.pid` held over it, so a lock can be converted or split.
+/// With `wait` set, sleeps until no other process holds a conflicting lock.
+/// # Return
+/// Err(TryAgain) on a conflict without `wait`, Err(InterruptedSystemCall) if a signal came while waiting.
+pub fn set_record_lock(file: &Arc<dyn File>, lock: RecordLock, wait: bool) -> Result<(), ErrNo> {
+    let key = key_of(file);
+    loop {
+        let mut records = RECORDS.lock();
+        let state = records.entry(key.clone()).or_insert_with(RecordState::new);
+        if lock.ltype != F_UNLCK && state.conflict(&lock).is_some() {
+            if !wait {
+                return Err(ErrNo::TryAgain);
+            }
+            let queue = state.queue.clone();
+            drop(records);
+            // a signal needs to be handled first
+            if current_signal_pending() {
+                return Err(ErrNo::InterruptedSystemCall);
+            }
+            queue.wait();
+            continue;
+        }
+        let changed = state.clear(lock.pid, lock.start, lock.end);
+        if lock.ltype != F_UNLCK {
+            state.locks.push(lock);
+        }
+        let queue = state.queue.clone();
+        if state.locks.is_empty() {
+            records.remove(&key);
+        }
+        drop(records);
+        // a downgrade or a partial unlock may let a waiter in
+        if changed {
+            queue.wake_all();
+        }
+        return Ok(());
+    }
+}
+
+/// Release the record locks of process `pid` on `file`, which it is closing
+/// # Description
+/// Same as `release_dropped_flocks()`, no lock of a waiting process may be held.
+pub fn release_record_locks(file: &Arc<dyn File>, pid: usize) {
+    let key = key_of(file);
+    let mut records = RECORDS.lock();
+    let queue = match records.get_mut(&key) {
+        Some(state) => {
+            if !state.clear(pid, 0, usize::MAX) {
+                return;
+            }
+            let queue = state.queue.clone();
+            if state.locks.is_empty() {
+                records.remove(&key);
+            }
+            queue
+        },
+        None => return,
+    };
+    drop(records);
+    queue.wake_all();
+}
+
+/// Release all record locks of the exiting process `pid`
+pub fn release_process_record_locks(pid: usize) {
+    let mut queues = Vec::new();
+    let mut records = RECORDS.lock();
+    for state in records.values_mut() {
+        if state.clear(pid, 0, usize::MAX) {
+            queues.push(state.queue.clone());
+        }
+    }
+    records.retain(|_, state| !state.locks.is_empty());
+    drop(records);
+    for queue in queues.iter() {
+        queue.wake_all();
+    }
+}
@@ -5,6 +5,8 @@ use alloc::sync::Arc;
 use super::BLOCK_SZ;
 
 use crate::fs::fs_impl::BlockDeviceFile;
+use crate::drivers::IoError;
+use crate::sbi::get_time;
 
 /// Struct of cache for a block (size: 512B)
 pub struct BlockCache {
@@ -14,6 +16,8 @@ pub struct BlockCache {
         block_id: usize,
         /// Indecate whe the block has been modified
         modified: bool,
+        /// Time when the block became dirty, meaningless if not modified
+        dirty_since: u64,
         device: Arc<dyn BlockDeviceFile>,
 }
 
@@ -21,18 +25,54 @@ impl BlockCache {
         // const block_device: Arc<SDCard0WithLock> = BLOCK_DEVICE.clone();
 
         /// Load a new BlockCache from disk.
+        /// # Return
+        /// Err(IoError) if the block can't be read
         pub fn new(
                 block_id: usize,
                 device: Arc<dyn BlockDeviceFile>,
-        ) -> Self {
+        ) -> Result<Self, IoError> {
                 let mut to_ret = Self {
                         cache: [0b10101010u8; BLOCK_SZ],
                         block_id,
                         modified: false,
+                        dirty_since: 0,
                         device: device.clone(),
                 };
-                device.read_block(block_id, &mut to_ret.cache);
-                to_ret
+                device.read_block(block_id, &mut to_ret.cache)?;
+                Ok(to_ret)
+        }
+
+        /// Create a zero-filled BlockCache without reading the disk.
+        /// # Description
+        /// The cache is marked as modified, so that zeros reach the disk on sync.
+        pub fn zeroed(
+                block_id: usize,
+                device: Arc<dyn BlockDeviceFile>,
+        ) -> Self {
+                Self {
+                        cache: [0u8; BLOCK_SZ],
+                        block_id,
+                        modified: true,
+                        dirty_since: get_time(),
+                        device,
+                }
+        }
+
+        /// Set modified, remembering when the block became dirty
+        fn mark_dirty(&mut self) {
+                if !self.modified {
+                        self.modified = true;
+                        self.dirty_since = get_time();
+                }
+        }
+
+        /// Time when the block became dirty, None if it is clean
+        pub fn dirty_since(&self) -> Option<u64> {
+                if self.modified {
+                        Some(self.dirty_since)
+                } else {
+                        None
+                }
         }
 
         /// Get the memory address that points to the content from cache at the specified offset
@@ -69,7 +109,7 @@ impl BlockCache {
         pub fn get_mut<T>(&mut self, offset: usize) -> &mut T where T: Sized {
                 let type_size = core::mem::size_of::<T>();
                 assert!(offset + type_size <= BLOCK_SZ);
-                self.modified = true;
+                self.mark_dirty();
                 let addr = self.addr_of_offset(offset);
                 unsafe { &mut *(addr as *mut T) }
         }
@@ -84,15 +124,26 @@ impl BlockCache {
                 }
         }
 
+        /// Zero cache
+        /// # Description 
+        /// Set content to zero and set modified, zeros are written on next sync
+        pub fn zero(&mut self) {
+                self.mark_dirty();
+                for i in 0..BLOCK_SZ {
+                        self.cache[i] = 0;
+                }
+        }
+
         /// Write cache content back to block device
         /// # Description
         /// Write only occured when 'modified' flag is set
-        /// 'Modified' flag will be reset during this operation 
-        pub fn sync(&mut self) {
+        /// 'Modified' flag will be reset during this operation, unless the write fails
+        pub fn sync(&mut self) -> Result<(), IoError> {
                 if self.modified {
+                        self.device.write_block(self.block_id, &self.cache)?;
                         self.modified = false;
-                        self.device.write_block(self.block_id, &self.cache);
                 }
+                Ok(())
         }
 
         #[allow(unused)]
@@ -112,8 +163,10 @@ impl Drop for BlockCache {
 
         /// Drop trait for BlockCache
         /// # Description
-        /// Call sync before dropping blockcache
+        /// Call sync before dropping blockcache, the content is lost if that fails
         fn drop(&mut self) {
-                self.sync()
+                if self.sync().is_err() {
+                        error!("BlockCache: lost dirty block {}, write back failed", self.block_id);
+                }
         }
 }
\ No newline at end of file
@@ -2,11 +2,13 @@
 pub mod blkcache;
 
 use alloc::sync::Arc;
-use alloc::collections::VecDeque;
+use alloc::collections::{BTreeSet, VecDeque};
+use alloc::vec::Vec;
 use spin::Mutex;
 use blkcache::BlockCache;
 
 use super::BlockDeviceFile;
+use crate::drivers::IoError;
 
 pub const BLOCK_SZ: usize = 512;
 
@@ -16,6 +18,8 @@ const BLOCK_CACHE_SIZE: usize = 16;
 pub struct BlockCacheManager {
         /// vector queue of block cache  
         queue: VecDeque<(usize, Arc<Mutex<BlockCache>>)>,
+        /// blocks that are logically zero but not yet zeroed on the device
+        zeroed: BTreeSet<usize>,
         device: Arc<dyn BlockDeviceFile>,
 }
 
@@ -24,6 +28,7 @@ impl BlockCacheManager {
         pub fn new(device: Arc<dyn BlockDeviceFile>) -> Self {
                 Self { 
                         queue: VecDeque::new(),
+                        zeroed: BTreeSet::new(),
                         device: device.clone(),
                 }
         }
@@ -32,15 +37,17 @@ impl BlockCacheManager {
         /// # Description 
         /// Returns a cache of a block at specified offset of the block device 
         /// Drops earliest allocate cache when necessary
+        /// # Return
+        /// Err(IoError) if the block is not cached and can't be read
         pub fn get_block_cache(
                 &mut self,
                 block_id: usize,
-        ) -> Arc<Mutex<BlockCache>> {
+        ) -> Result<Arc<Mutex<BlockCache>>, IoError> {
                 // debug!("inner get block cache");
                 if let Some(pair) = self.queue
                 .iter()
                 .find(|pair| pair.0 == block_id) {
-                        Arc::clone(&pair.1)
+                        Ok(Arc::clone(&pair.1))
                 } else {
                         // substitute
                         if self.queue.len() == BLOCK_CACHE_SIZE {
@@ -55,12 +62,18 @@ impl BlockCacheManager {
                                 }
                         }
                         // load block into mem and push back
-                        let block_cache = Arc::new(Mutex::new(
-                                BlockCache::new(block_id, self.device.clone())
-                        ));
+                        let block_cache = if self.zeroed.remove(&block_id) {
+                                Arc::new(Mutex::new(
+                                        BlockCache::zeroed(block_id, self.device.clone())
+                                ))
+                        } else {
+                                Arc::new(Mutex::new(
+                                        BlockCache::new(block_id, self.device.clone())?
+                                ))
+                        };
                         // debug!("New Block Cache, addr @ {:x}", (&block_cache.lock().cache[0]) as *const u8 as usize);
                         self.queue.push_back((block_id, Arc::clone(&block_cache)));
-                        block_cache
+                        Ok(block_cache)
                 }
         }
 
@@ -68,23 +81,139 @@ impl BlockCacheManager {
         /// # Description 
         /// Reset content of a block at specified offset 
         /// Block cache will be cleared if it is allocated
-        pub fn clear_block_cache(&mut self, block_id: usize) {
+        pub fn clear_block_cache(&mut self, block_id: usize) -> Result<(), IoError> {
                 if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
                         pair.1.lock().clear();
                 }
-                self.device.clear_block(block_id);
-                return;
+                self.device.clear_block(block_id)
+        }
+
+        /// Mark block content as zero
+        /// # Description
+        /// Unlike clear_block_cache, nothing is written to the block device now.
+        /// If the block is not cached, it is remembered as zero and the next
+        /// get_block_cache returns a zero-filled cache without reading the disk,
+        /// zeros reach the disk together with the first write to the block, or on the next flush.
+        pub fn zero_block_cache(&mut self, block_id: usize) {
+                if let Some(pair) = self.queue.iter().find(|pair| pair.0 == block_id) {
+                        pair.1.lock().zero();
+                } else {
+                        self.zeroed.insert(block_id);
+                }
         }
 
         /// Flush all caches
         /// # Description  
-        /// Write all caches back to Block device without freeing them
-        pub fn flush_all(&self) {
+        /// Write all caches back to Block device without freeing them,
+        /// and zero the blocks marked zero on the device
+        /// # Return
+        /// Err(IoError) if any block failed to be written, the others are written anyway
+        pub fn flush_all(&mut self) -> Result<(), IoError> {
+                let mut res = Ok(());
+                for cache in self.queue.iter() {
+                        if let Err(err) = cache.1.lock().sync() {
+                                res = Err(err);
+                        }
+                }
+                for block_id in core::mem::take(&mut self.zeroed) {
+                        if let Err(err) = self.device.clear_block(block_id) {
+                                // still zero logically, try again on the next flush
+                                self.zeroed.insert(block_id);
+                                res = Err(err);
+                        }
+                }
+                res
+        }
+
+        /// Flush the caches that are not locked
+        /// # Description
+        /// Same as flush_all, but never waits on a cache lock.
+        /// A block that fails to be written stays dirty and is tried again next time.
+        /// # Return
+        /// false if some cache was locked and skipped
+        pub fn try_flush_all(&mut self) -> bool {
+                let mut done = true;
+                for cache in self.queue.iter() {
+                        match cache.1.try_lock() {
+                                Some(mut cache) => {
+                                        let _ = cache.sync();
+                                },
+                                None => done = false,
+                        }
+                }
+                for block_id in core::mem::take(&mut self.zeroed) {
+                        if self.device.clear_block(block_id).is_err() {
+                                self.zeroed.insert(block_id);
+                        }
+                }
+                return done;
+        }
+
+        /// Write a block back to the device now if it is cached and dirty
+        /// # Description
+        /// Used as a write barrier: once this returns Ok, the block is on the device.
+        /// A block marked zero but never cached is zeroed on the device now.
+        pub fn flush_block(&mut self, block_id: usize) -> Result<(), IoError> {
+                if self.zeroed.remove(&block_id) {
+                        if let Err(err) = self.device.clear_block(block_id) {
+                                self.zeroed.insert(block_id);
+                                return Err(err);
+                        }
+                }
+                match self.queue.iter().find(|pair| pair.0 == block_id) {
+                        Some(pair) => pair.1.lock().sync(),
+                        None => Ok(()),
+                }
+        }
+
+        /// Flush caches that have been dirty since "deadline" or earlier
+        /// # Description
+        /// Used by the background flush. Blocks are written oldest dirty first, so the device sees
+        /// them in the order they were modified, and at most "budget" blocks are written.
+        /// A locked cache may hold an older block, so nothing is written if any cache is locked.
+        /// # Return
+        /// # of blocks written
+        pub fn flush_older(&self, deadline: u64, budget: usize) -> usize {
+                let mut due = Vec::new();
                 for cache in self.queue.iter() {
-                        cache.1.lock().sync();
+                        match cache.1.try_lock() {
+                                Some(cache) => match cache.dirty_since() {
+                                        Some(since) if since <= deadline => due.push(cache),
+                                        _ => {},
+                                },
+                                None => return 0,
+                        }
+                }
+                // stable, blocks dirtied in the same tick keep the queue order
+                due.sort_by_key(|cache| cache.dirty_since());
+                let mut written = 0;
+                for cache in due.iter_mut().take(budget) {
+                        // a failed block stays dirty and is tried again next time
+                        if cache.sync().is_ok() {
+                                written += 1;
+                        }
                 }
+                return written;
         }
 
+        /// Release caches that are clean and not in use
+        /// # Description
+        /// Used by memory reclaim. Caches locked by someone else are kept.
+        /// # Return
+        /// # of caches released
+        pub fn drop_clean(&mut self) -> usize {
+                let before = self.queue.len();
+                self.queue.retain(|pair| {
+                        if Arc::strong_count(&pair.1) != 1 {
+                                return true;
+                        }
+                        match pair.1.try_lock() {
+                                Some(cache) => cache.dirty_since().is_some(),
+                                None => true,
+                        }
+                });
+                return before - self.queue.len();
+        }
 }
 
 pub type BCMgr = Arc<Mutex<BlockCacheManager>>; 
@@ -94,7 +223,7 @@ pub type BCMgr = Arc<Mutex<BlockCacheManager>>;
 pub fn get_block_cache(
         bcmgr: BCMgr,
         block_id: usize,
-) -> Arc<Mutex<BlockCache>> {
+) -> Result<Arc<Mutex<BlockCache>>, IoError> {
         let mut locked = bcmgr.lock();
         // debug!("get_block_cache enter {:0x}", BlockCacheManager::get_block_cache as usize);
         locked.get_block_cache(block_id)
@@ -102,18 +231,18 @@ pub fn get_block_cache(
 
 #[allow(unused)]
 /// Wrapper function of clear_block_cache of singleton block cache manager
-pub fn clear_block_cache (bcmgr: BCMgr, block_id: usize) {
-        bcmgr.lock().clear_block_cache(block_id);
+pub fn clear_block_cache (bcmgr: BCMgr, block_id: usize) -> Result<(), IoError> {
+        bcmgr.lock().clear_block_cache(block_id)
 }
 
 #[allow(unused)]
 /// Write specified cache back to block device without freeing cache
-pub fn flush(cache: Arc<Mutex<BlockCache>>) {
-        cache.lock().sync();
+pub fn flush(cache: Arc<Mutex<BlockCache>>) -> Result<(), IoError> {
+        cache.lock().sync()
 }
 
 #[allow(unused)]
 /// Wrapper function of flush_all of singleton block cache manager
-pub fn flush_all(bcmgr: BCMgr) {
-        bcmgr.lock().flush_all();
+pub fn flush_all(bcmgr: BCMgr) -> Result<(), IoError> {
+        bcmgr.lock().flush_all()
 }
\ No newline at end of file
@@ -1,11 +1,11 @@
 use core::{cell::Cell, sync::atomic::{AtomicUsize, Ordering}};
 
 use crate::fs::Path;
-use crate::{fs::{CommonFile, DirFile, File, SeekOp, file::FileStatus}, memory::VirtAddr};
+use crate::{fs::{CommonFile, DirFile, File, file::FileStatus, release_dropped_flocks}, memory::VirtAddr};
 use alloc::{string::ToString, sync::Arc, vec::Vec};
 use alloc::string::String;
 use super::{CharDeviceFile, DeviceFile, device_file::BlockDeviceFile};
-use crate::drivers::BLOCK_DEVICE;
+use crate::drivers::{BLOCK_DEVICE, BlockDevice, IoError, LoopDevice};
 use lazy_static::*;
 use crate::process::ErrNo;
 
@@ -28,15 +28,15 @@ impl SDAWrapper {
 }
 
 impl BlockDeviceFile for SDAWrapper {
-    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IoError> {
         BLOCK_DEVICE.read_block(block_id, buf)
     }
 
-    fn write_block(&self, block_id: usize, buf: &[u8]) {
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IoError> {
         BLOCK_DEVICE.write_block(block_id, buf)
     }
 
-    fn clear_block(&self, block_id: usize) {
+    fn clear_block(&self, block_id: usize) -> Result<(), IoError> {
         BLOCK_DEVICE.clear_block(block_id)
     }
 }
@@ -93,7 +93,7 @@ impl File for SDAWrapper {
 		while buffer.len() - offset > self.blk_sz as usize{
 			let mut rd_buf = Vec::<u8>::new();
 			rd_buf.resize(self.blk_sz as usize, 0);
-			self.read_block(offset / self.blk_sz as usize, &mut rd_buf);
+			self.read_block(offset / self.blk_sz as usize, &mut rd_buf)?;
 			buffer[offset..(offset + self.blk_sz as usize)].copy_from_slice(&rd_buf);
 			offset += self.blk_sz as usize;
 		}
@@ -103,7 +103,7 @@ impl File for SDAWrapper {
     fn write(&self, buffer: &[u8]) -> Result<usize, ErrNo> {
         let mut offset = 0;
 		while buffer.len() - offset > self.blk_sz as usize{
-			self.write_block(offset / self.blk_sz as usize, &buffer[offset..(offset+self.blk_sz as usize)]);
+			self.write_block(offset / self.blk_sz as usize, &buffer[offset..(offset+self.blk_sz as usize)])?;
 			offset += self.blk_sz as usize;
 		}
 		Ok(offset)
@@ -114,7 +114,7 @@ impl File for SDAWrapper {
 		while buffer.len() - offset > self.blk_sz as usize{
 			let mut rd_buf = Vec::<u8>::new();
 			rd_buf.resize(self.blk_sz as usize, 0);
-			self.read_block(offset / self.blk_sz as usize, &mut rd_buf);
+			self.read_block(offset / self.blk_sz as usize, &mut rd_buf)?;
 			
 			for i in offset..(offset + self.blk_sz as usize) {
 				buffer[i] = rd_buf[i - offset];
@@ -132,7 +132,7 @@ impl File for SDAWrapper {
 			for i in 0..self.blk_sz as usize{
 				wr_buf.push(buffer[offset + i]);
 			}
-			self.write_block(offset / self.blk_sz as usize, &wr_buf);
+			self.write_block(offset / self.blk_sz as usize, &wr_buf)?;
 			offset += self.blk_sz as usize;
 		}
 		Ok(offset)
@@ -193,27 +193,24 @@ impl Drop for SDAWrapper {
     }
 }
 
+/// A file used as block device, block I/O goes through a loop device on it
 pub struct CommonFileAsBlockDevice {
     inner: Arc<dyn File>,
-    blk_sz: usize
+    dev: LoopDevice,
 }
 
 impl CommonFileAsBlockDevice {
     pub fn new(file: Arc<dyn File>, blk_sz: usize) -> Self {
-        if blk_sz & (blk_sz - 1) != 0 {
-            panic!("Block size must be power of 2!")
-        }
-
         Self {
+            dev: LoopDevice::new(file.clone(), blk_sz),
             inner: file,
-            blk_sz
         }
     }
 }
 
 impl Drop for CommonFileAsBlockDevice {
     fn drop(&mut self) {
-        // auto drop
+        release_dropped_flocks();
     }
 }
 
@@ -286,22 +283,15 @@ impl DeviceFile for CommonFileAsBlockDevice {
 }
 
 impl BlockDeviceFile for CommonFileAsBlockDevice {
-    fn read_block(&self, block_id: usize, buf: &mut [u8]) {
-        assert_eq!(buf.len(), self.blk_sz, "Buffer size != blk_sz!");
-        self.seek((self.blk_sz * block_id) as isize, SeekOp::SET).unwrap();
-        self.read(buf).unwrap();
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IoError> {
+        self.dev.read_block(block_id, buf)
     }
 
-    fn write_block(&self, block_id: usize, buf: &[u8]) {
-        assert_eq!(buf.len(), self.blk_sz, "Buffer size != blk_sz!");
-        self.seek((self.blk_sz * block_id) as isize, SeekOp::SET).unwrap();
-        self.write(buf).unwrap();
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IoError> {
+        self.dev.write_block(block_id, buf)
     }
 
-    fn clear_block(&self, block_id: usize) {
-        self.seek((self.blk_sz * block_id) as isize, SeekOp::SET).unwrap();
-        let mut v: Vec<u8> = Vec::new();
-        v.resize(self.blk_sz, 0);
-        self.write(&v).unwrap();
+    fn clear_block(&self, block_id: usize) -> Result<(), IoError> {
+        self.dev.clear_block(block_id)
     }
-}
\ No newline at end of file
+}
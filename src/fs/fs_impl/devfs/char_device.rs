@@ -12,7 +12,7 @@ use crate::fs::file::{FileStatus, FileType};
 use crate::memory::VirtAddr;
 use crate::process::current_process;
 use crate::sbi::{get_byte, get_byte_non_block_with_echo};
-use crate::sbi::put_byte;
+use crate::sbi::console_put_byte;
 use core::cell::RefCell;
 use core::usize;
 use core::convert::{TryFrom, TryInto};
@@ -68,7 +68,7 @@ impl File for SBITTY {
                 b = b'\n';
             }
 			buffer[idx] = b;
-            put_byte(b);
+            console_put_byte(b);
             // verbose!("{}, {}", b, b as char);
 			if buffer[idx] == b'\n' {
                 // verbose!("Done!");
@@ -85,7 +85,7 @@ impl File for SBITTY {
                 b = b'\n';
             }
 			buffer[idx] = b;
-            put_byte(b);
+            console_put_byte(b);
             // verbose!("{}, {}", b, b as char);
 			if buffer[idx] == b'\n' {
                 // verbose!("Done!");
@@ -344,7 +344,7 @@ impl CharDeviceFile for SBITTY {
     fn flush(&self) {
 		let mut inner_locked = self.inner.lock();
 		while !inner_locked.write_buffer.is_empty() {
-			put_byte(inner_locked.write_buffer.pop_front().unwrap());
+			console_put_byte(inner_locked.write_buffer.pop_front().unwrap());
 		}
     }
 }
\ No newline at end of file
@@ -1,8 +1,10 @@
-use crate::fs::{CommonFile, DirFile, FSFlags, FSStatus, File, VirtualFileSystem, file::FileStatus, SDA_WRAPPER};
+use crate::fs::{CommonFile, DirFile, FSFlags, FSStatus, File, VirtualFileSystem, file::FileStatus, SDA_WRAPPER, release_dropped_flocks};
 use crate::fs::Path;
-use super::{CharDeviceFile, DeviceFile, TTY0, FILE_ZERO};
-use alloc::{string::{String, ToString}, sync::Arc, vec::Vec};
+use crate::fs::file::FileType;
+use super::{CharDeviceFile, DeviceFile, TTY0, FILE_ZERO, FILE_NULL};
+use alloc::{collections::BTreeMap, string::{String, ToString}, sync::Arc, vec::Vec};
 use lazy_static::*;
+use spin::Mutex;
 use crate::process::ErrNo;
 
 lazy_static! {
@@ -10,14 +12,27 @@ lazy_static! {
     pub static ref DEV_FS_BLOCK_FOLDER: Arc<DevFSBLockFolder> = Arc::new(DevFSBLockFolder::new());
 }
 pub struct DevFS {
-
+    /// nodes made by mknod, name -> (type, major, minor)
+    nodes: Mutex<BTreeMap<String, (FileType, u32, u32)>>,
 }
 
 impl DevFS {
 	pub fn new() -> Self {
-        Self{}
-        // Do nothing?
+        Self {
+            nodes: Mutex::new(BTreeMap::new()),
+        }
 	}
+
+    /// The device behind (`major`, `minor`), using the Linux numbers
+    fn device(ftype: FileType, major: u32, minor: u32) -> Result<Arc<dyn File>, ErrNo> {
+        match (ftype, major, minor) {
+            (FileType::CharDev, 1, 3) => Ok(FILE_NULL.clone()),
+            (FileType::CharDev, 1, 5) => Ok(FILE_ZERO.clone()),
+            (FileType::CharDev, 4, _) | (FileType::CharDev, 5, 0) => Ok(TTY0.clone()),
+            (FileType::BlockDev, 8, 0) => Ok(SDA_WRAPPER.clone()),
+            _ => Err(ErrNo::NoSuchDeviceOrAddress),
+        }
+    }
 }
 
 pub struct DevFSBLockFolder {
@@ -32,7 +47,7 @@ impl DevFSBLockFolder {
 
 impl Drop for DevFSBLockFolder {
     fn drop (&mut self) {
-        // do nothing
+        release_dropped_flocks();
     }
 }
 
@@ -150,11 +165,23 @@ impl VirtualFileSystem for DevFS {
         TTY0.flush();
     }
 
+    /// nothing to write back
+    fn try_sync(&self) -> bool {
+        true
+    }
+
     fn get_status(&self) -> crate::fs::FSStatus {
-        FSStatus {
-            name: "devfs",
-            flags: FSFlags::PLACE_HOLDER,
-        }
+        FSStatus::new("devfs", FSFlags::PLACE_HOLDER)
+    }
+
+    /// device nodes take no space
+    fn statfs(&self) -> crate::fs::FSStatus {
+        let mut status = self.get_status();
+        // DEVFS_SUPER_MAGIC
+        status.magic = 0x1373;
+        status.block_size = 4096;
+        status.name_max = 255;
+        status
     }
 
     fn open(&self, abs_path: Path, mode: crate::fs::OpenMode) -> Result<alloc::sync::Arc<dyn crate::fs::File>, ErrNo> {
@@ -169,9 +196,15 @@ impl VirtualFileSystem for DevFS {
                 } else if abs_path.path[0] == "block" {
                     verbose!("Parse success: block");
                     return Ok(DEV_FS_BLOCK_FOLDER.clone());
-                } else if abs_path.path[0] == "zero" || abs_path.path[0] == "null" {
+                } else if abs_path.path[0] == "zero" {
                     verbose!("Parse success: zero");
                     return Ok(FILE_ZERO.clone());
+                } else if abs_path.path[0] == "null" {
+                    verbose!("Parse success: null");
+                    return Ok(FILE_NULL.clone());
+                } else if let Some((ftype, major, minor)) = self.nodes.lock().get(&abs_path.path[0]).copied() {
+                    verbose!("Parse success: node {}:{}", major, minor);
+                    return Self::device(ftype, major, minor);
                 }
             },
             2 => {
@@ -193,9 +226,26 @@ impl VirtualFileSystem for DevFS {
     }
 
     fn remove(&self, abs_path: Path) -> Result<(), ErrNo> {
+        if abs_path.path.len() == 1 && self.nodes.lock().remove(&abs_path.path[0]).is_some() {
+            return Ok(());
+        }
         Err(ErrNo::ReadonlyFileSystem)
     }
 
+    /// only nodes right under /dev, the device is looked up on open
+    fn mknod(&self, abs_path: Path, ftype: FileType, major: u32, minor: u32) -> Result<(), ErrNo> {
+        if abs_path.path.len() != 1 {
+            return Err(ErrNo::OperationNotPermitted);
+        }
+        let name = &abs_path.path[0];
+        let mut nodes = self.nodes.lock();
+        if ["tty0", "tty", "block", "zero", "null"].contains(&name.as_str()) || nodes.contains_key(name) {
+            return Err(ErrNo::FileExists);
+        }
+        nodes.insert(name.clone(), (ftype, major, minor));
+        Ok(())
+    }
+
     fn link(&self, to_link: alloc::sync::Arc<dyn crate::fs::File>, dest: Path) -> Result<(), ErrNo> {
         Err(ErrNo::ReadonlyFileSystem)
     }
@@ -4,6 +4,7 @@ use crate::{fs::CommonFile, memory::{UserBuffer, VirtAddr}};
 
 use super::super::super::File;
 use crate::process::ErrNo;
+use crate::drivers::IoError;
 
 pub trait SafeToPass : Copy+Clone+Send+Sync {
     
@@ -33,11 +34,11 @@ pub trait BlockDeviceFile: DeviceFile {
     /// pub const BLK_SZ = 512;
     /// let mut buf = [0u8; BLK_SZ];
     /// let block_id: isize = 10;
-    /// BLOCK_DEVICE.read_block(block_id, &mut buf)
+    /// BLOCK_DEVICE.read_block(block_id, &mut buf)?;
     /// ```
     /// # Returns
-    /// No returns
-    fn read_block(&self, block_id: usize, buf: &mut [u8]);
+    /// Err(IoError) if the device failed, `buf` is undefined then
+    fn read_block(&self, block_id: usize, buf: &mut [u8]) -> Result<(), IoError>;
 
     /// Write a block to the block device.
     /// # Description
@@ -47,22 +48,22 @@ pub trait BlockDeviceFile: DeviceFile {
     /// pub const BLK_SZ = 512;
     /// let buf = [10u8; BLK_SZ];
     /// let block_id: isize = 10;
-    /// BLOCK_DEVICE.write_block(block_id, buf)
+    /// BLOCK_DEVICE.write_block(block_id, buf)?;
     /// ```
     /// # Returns
-    /// No returns
-    fn write_block(&self, block_id: usize, buf: &[u8]);
+    /// Err(IoError) if the device failed, the block is undefined then
+    fn write_block(&self, block_id: usize, buf: &[u8]) -> Result<(), IoError>;
 
     /// Clear a spcific block in the block device.
     /// # Description
     /// Clear the block with id=`block_id` on the block device.
     /// # Examples
     /// ```
-    /// BLOCK_DEVICE.clear_block(10)
+    /// BLOCK_DEVICE.clear_block(10)?;
     /// ```
     /// # Returns
-    /// No returns
-    fn clear_block(&self, block_id: usize);
+    /// Err(IoError) if the device failed
+    fn clear_block(&self, block_id: usize) -> Result<(), IoError>;
 }
 
 // pub trait NetworkDevice : DeviceFile {
@@ -3,12 +3,18 @@ mod char_device;
 mod devfs;
 mod block_device;
 mod zero_device;
+mod null_device;
 
 pub use zero_device::{
     FZero,
     FILE_ZERO,
 };
 
+pub use null_device::{
+    FNull,
+    FILE_NULL,
+};
+
 pub use device_file::{
     DeviceFile,
     CharDeviceFile,
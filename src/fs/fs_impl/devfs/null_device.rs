@@ -0,0 +1,100 @@
+use crate::fs::{CommonFile, DirFile};
+use crate::fs::file::{FileStatus, FileType};
+use crate::fs::SeekOp;
+use super::DeviceFile;
+use super::super::super::File;
+use super::super::super::Path;
+use alloc::sync::Arc;
+use alloc::string::String;
+use alloc::string::ToString;
+use lazy_static::*;
+use crate::process::ErrNo;
+
+use crate::memory::UserBuffer;
+
+/// /dev/null, reads hit end of file at once and writes are thrown away
+pub struct FNull {}
+
+lazy_static! {
+	pub static ref FILE_NULL: Arc<FNull> = Arc::new(FNull{});
+}
+
+impl Drop for FNull {
+        fn drop(&mut self) {}
+}
+
+impl File for FNull {
+        fn seek(&self, _offset: isize, _op: SeekOp) -> Result<(), ErrNo> {
+                return Ok(());
+        }
+
+        fn get_cursor(&self) -> Result<usize, ErrNo> {
+                return Ok(0);
+        }
+
+        fn read(&self, _buffer: &mut [u8]) -> Result<usize, ErrNo> {
+                return Ok(0);
+        }
+
+        fn write(&self, buffer: &[u8]) -> Result<usize, ErrNo> {
+                return Ok(buffer.len());
+        }
+
+        fn read_user_buffer(&self, _buffer: UserBuffer) -> Result<usize, ErrNo> {
+                return Ok(0);
+        }
+
+        fn write_user_buffer(&self, buffer: UserBuffer) -> Result<usize, ErrNo> {
+                return Ok(buffer.len());
+        }
+
+        fn to_common_file<'a>(self: Arc<Self>) -> Option<Arc<dyn CommonFile + 'a>> where Self: 'a {
+                return Some(self);
+        }
+
+        fn to_dir_file<'a>(self: Arc<Self>) -> Option<Arc<dyn DirFile + 'a>> where Self: 'a {
+                return None;
+        }
+
+        fn to_device_file<'a>(self: Arc<Self>) -> Option<Arc<dyn DeviceFile + 'a>> where Self: 'a {
+                return None;
+        }
+
+        fn poll(&self) -> FileStatus {
+                FileStatus {
+			readable: 	true,
+                        writeable: 	true,
+                        size: 		0,
+                        name: 		"null".to_string(),
+                        ftype: 		FileType::CharDev,
+                        inode: 		0,
+                        dev_no: 	0,
+                        mode: 		0,
+                        block_sz: 	0,
+                        blocks: 	0,
+                        uid: 		0,
+                        gid: 		0,
+                        atime_sec: 	0,
+                        atime_nsec:	0,
+                        mtime_sec: 	0,
+                        mtime_nsec:	0,
+                        ctime_sec: 	0,
+                        ctime_nsec:	0,
+		}
+        }
+
+        fn rename(&self, _new_name: &str) -> Result<(), ErrNo> {
+                return Err(ErrNo::PermissionDenied);
+        }
+
+        fn get_vfs(&self) -> Result<Arc<(dyn crate::fs::VirtualFileSystem + 'static)>, ErrNo> {
+                Ok(super::DEV_FS.clone())
+        }
+
+        fn get_path(&self) -> Path {
+                let path = vec![String::from("null")];
+                return Path {path, must_dir: false, is_abs: true};
+        }
+}
+
+impl CommonFile for FNull {}
@@ -0,0 +1,377 @@
+//! Read-only ext2 file system implementation for oshit.
+pub mod wrapper;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem::size_of;
+use spin::Mutex;
+
+use super::cache_mgr::BlockCacheManager;
+use super::cache_mgr::BLOCK_SZ;
+use super::BlockDeviceFile;
+use super::super::Path;
+use crate::process::ErrNo;
+
+const EXT2_MAGIC: u16 = 0xEF53;
+/// Superblock always starts at byte 1024 of the device
+const SUPERBLOCK_OFFSET: usize = 1024;
+/// Inode number of the root directory
+pub const ROOT_INO: u32 = 2;
+
+/// # of direct blocks in i_block
+const N_DIRECT: usize = 12;
+/// Index of singly-indirect block in i_block
+const IND_BLOCK: usize = 12;
+/// Index of doubly-indirect block in i_block
+const DIND_BLOCK: usize = 13;
+
+/// Directory entries carry a file type byte
+const FEATURE_INCOMPAT_FILETYPE: u32 = 0x0002;
+/// Group metadata may be packed together, descriptors still tell where
+const FEATURE_INCOMPAT_FLEX_BG: u32 = 0x0200;
+/// Incompatible features we know how to read
+const FEATURE_INCOMPAT_SUPPORTED: u32 = FEATURE_INCOMPAT_FILETYPE | FEATURE_INCOMPAT_FLEX_BG;
+
+pub const S_IFMT: u16 = 0xF000;
+pub const S_IFDIR: u16 = 0x4000;
+pub const S_IFREG: u16 = 0x8000;
+pub const S_IFLNK: u16 = 0xA000;
+
+/// Superblock of ext2, fields after s_feature_ro_compat are not used
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawSuperBlock {
+        inodes_count: u32,
+        blocks_count: u32,
+        r_blocks_count: u32,
+        free_blocks_count: u32,
+        free_inodes_count: u32,
+        first_data_block: u32,
+        log_block_size: u32,
+        log_frag_size: u32,
+        blocks_per_group: u32,
+        frags_per_group: u32,
+        inodes_per_group: u32,
+        mtime: u32,
+        wtime: u32,
+        mnt_count: u16,
+        max_mnt_count: u16,
+        magic: u16,
+        state: u16,
+        errors: u16,
+        minor_rev_level: u16,
+        lastcheck: u32,
+        checkinterval: u32,
+        creator_os: u32,
+        rev_level: u32,
+        def_resuid: u16,
+        def_resgid: u16,
+        first_ino: u32,
+        inode_size: u16,
+        block_group_nr: u16,
+        feature_compat: u32,
+        feature_incompat: u32,
+        feature_ro_compat: u32,
+}
+
+/// Block group descriptor
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawGroupDesc {
+        block_bitmap: u32,
+        inode_bitmap: u32,
+        inode_table: u32,
+        free_blocks_count: u16,
+        free_inodes_count: u16,
+        used_dirs_count: u16,
+        pad: u16,
+        reserved: [u8; 12],
+}
+
+/// On-disk inode, the first 128 bytes that every revision has
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RawInode {
+        pub mode: u16,
+        pub uid: u16,
+        pub size: u32,
+        pub atime: u32,
+        pub ctime: u32,
+        pub mtime: u32,
+        pub dtime: u32,
+        pub gid: u16,
+        pub links_count: u16,
+        /// # of 512-byte sectors held by the inode
+        pub blocks: u32,
+        pub flags: u32,
+        pub osd1: u32,
+        pub block: [u32; 15],
+        pub generation: u32,
+        pub file_acl: u32,
+        /// High 32 bits of size for regular files
+        pub size_high: u32,
+        pub faddr: u32,
+        pub osd2: [u8; 12],
+}
+
+impl RawInode {
+        pub fn is_dir(&self) -> bool {
+                self.mode & S_IFMT == S_IFDIR
+        }
+
+        pub fn is_reg(&self) -> bool {
+                self.mode & S_IFMT == S_IFREG
+        }
+
+        pub fn is_link(&self) -> bool {
+                self.mode & S_IFMT == S_IFLNK
+        }
+
+        /// Target of a short symbolic link lives in i_block instead of data blocks
+        pub fn is_fast_link(&self) -> bool {
+                self.is_link() && self.blocks == 0
+        }
+
+        /// Get size of the file in bytes
+        pub fn size(&self) -> usize {
+                if self.is_reg() {
+                        (self.size as usize) | ((self.size_high as usize) << 32)
+                } else {
+                        self.size as usize
+                }
+        }
+}
+
+/// Struct that holds meta data of ext2, implements read operations also
+pub struct Ext2FS {
+        mgr: Mutex<BlockCacheManager>,
+        block_size: usize,
+        inodes_count: u32,
+        inodes_per_group: u32,
+        inode_size: usize,
+        feature_incompat: u32,
+        groups: Vec<RawGroupDesc>,
+}
+
+impl Ext2FS {
+        pub const name: &'static str = "Ext2FS (read-only, Powered by OSHIT)";
+
+        /// Load ext2 from device
+        /// # Return
+        /// Err(InvalidArgument) if the device does not hold an ext2 we can read
+        pub fn open(device: Arc<dyn BlockDeviceFile>) -> Result<Ext2FS, ErrNo> {
+                let mut fs = Ext2FS {
+                        mgr: Mutex::new(BlockCacheManager::new(device)),
+                        block_size: 1024,
+                        inodes_count: 0,
+                        inodes_per_group: 0,
+                        inode_size: 128,
+                        feature_incompat: 0,
+                        groups: Vec::new(),
+                };
+                let sb: RawSuperBlock = fs.read_obj(SUPERBLOCK_OFFSET)?;
+                if sb.magic != EXT2_MAGIC {
+                        // not an error when probing a disk of another fs
+                        verbose!("ext2: bad magic {:#x}", sb.magic);
+                        return Err(ErrNo::InvalidArgument);
+                }
+                if sb.rev_level > 0 && sb.feature_incompat & !FEATURE_INCOMPAT_SUPPORTED != 0 {
+                        error!("ext2: unsupported incompatible features {:#x}", sb.feature_incompat);
+                        return Err(ErrNo::InvalidArgument);
+                }
+                if sb.inodes_per_group == 0 || sb.log_block_size > 6 {
+                        return Err(ErrNo::InvalidArgument);
+                }
+                fs.block_size = 1024 << sb.log_block_size;
+                fs.inodes_count = sb.inodes_count;
+                fs.inodes_per_group = sb.inodes_per_group;
+                if sb.rev_level > 0 {
+                        fs.inode_size = sb.inode_size as usize;
+                        fs.feature_incompat = sb.feature_incompat;
+                }
+                let group_cnt = (sb.inodes_count + sb.inodes_per_group - 1) / sb.inodes_per_group;
+                let table = (sb.first_data_block as usize + 1) * fs.block_size;
+                for i in 0..group_cnt as usize {
+                        let desc = fs.read_obj(table + i * size_of::<RawGroupDesc>())?;
+                        fs.groups.push(desc);
+                }
+                verbose!("ext2: block size {}, {} inodes in {} groups", fs.block_size, fs.inodes_count, group_cnt);
+                return Ok(fs);
+        }
+
+        /// Get block size of current ext2
+        pub fn block_size(&self) -> usize {
+                return self.block_size;
+        }
+
+        /// Release cached blocks nobody is using
+        /// # Return
+        /// # of caches released, 0 if the fs is busy
+        pub fn drop_clean_caches(&self) -> usize {
+                match self.mgr.try_lock() {
+                        Some(mut mgr) => mgr.drop_clean(),
+                        None => 0,
+                }
+        }
+
+        /// Fill the buf with bytes of the device starting from "offset"
+        /// # Return
+        /// Err(IOError) if the device can't be read
+        fn read_bytes(&self, offset: usize, buf: &mut [u8]) -> Result<(), ErrNo> {
+                let mut mgr = self.mgr.lock();
+                let mut done = 0;
+                while done < buf.len() {
+                        let pos = offset + done;
+                        let off = pos % BLOCK_SZ;
+                        let len = core::cmp::min(BLOCK_SZ - off, buf.len() - done);
+                        let cache = mgr.get_block_cache(pos / BLOCK_SZ)?;
+                        buf[done..done + len].copy_from_slice(&cache.lock().cache[off..off + len]);
+                        done += len;
+                }
+                Ok(())
+        }
+
+        /// Read a plain on-disk structure at "offset" of the device
+        fn read_obj<T: Copy>(&self, offset: usize) -> Result<T, ErrNo> {
+                let mut buf = [0u8; 128];
+                assert!(size_of::<T>() <= buf.len());
+                self.read_bytes(offset, &mut buf[..size_of::<T>()])?;
+                Ok(unsafe { core::ptr::read_unaligned(buf.as_ptr() as *const T) })
+        }
+
+        /// Read inode "ino" from the inode table
+        pub fn read_inode(&self, ino: u32) -> Result<RawInode, ErrNo> {
+                if ino == 0 || ino > self.inodes_count {
+                        return Err(ErrNo::NoSuchFileOrDirectory);
+                }
+                let group = ((ino - 1) / self.inodes_per_group) as usize;
+                let index = ((ino - 1) % self.inodes_per_group) as usize;
+                let table = self.groups[group].inode_table as usize * self.block_size;
+                return self.read_obj(table + index * self.inode_size);
+        }
+
+        /// Get the device block that holds block "idx" of the inode
+        /// # Return
+        /// Ok(0) for a hole, Err(FileTooLarge) beyond the doubly-indirect range
+        fn block_of(&self, inode: &RawInode, idx: usize) -> Result<u32, ErrNo> {
+                let per_block = self.block_size / size_of::<u32>();
+                if idx < N_DIRECT {
+                        return Ok(inode.block[idx]);
+                }
+                let idx = idx - N_DIRECT;
+                if idx < per_block {
+                        return self.indirect(inode.block[IND_BLOCK], idx);
+                }
+                let idx = idx - per_block;
+                if idx < per_block * per_block {
+                        let ind = self.indirect(inode.block[DIND_BLOCK], idx / per_block)?;
+                        return self.indirect(ind, idx % per_block);
+                }
+                return Err(ErrNo::FileTooLarge);
+        }
+
+        /// Read entry "idx" of the indirect block "block"
+        fn indirect(&self, block: u32, idx: usize) -> Result<u32, ErrNo> {
+                if block == 0 {
+                        return Ok(0);
+                }
+                return self.read_obj(block as usize * self.block_size + idx * size_of::<u32>());
+        }
+
+        /// Fill the buf with contents of the inode starting from "offset"
+        /// # Return
+        /// # of bytes that actually read, short at the end of file
+        pub fn read_inode_data(&self, inode: &RawInode, offset: usize, buf: &mut [u8]) -> Result<usize, ErrNo> {
+                let size = inode.size();
+                if offset >= size {
+                        return Ok(0);
+                }
+                let len = core::cmp::min(buf.len(), size - offset);
+                if inode.is_fast_link() {
+                        let raw = unsafe {
+                                core::slice::from_raw_parts(inode.block.as_ptr() as *const u8, size_of::<[u32; 15]>())
+                        };
+                        buf[..len].copy_from_slice(&raw[offset..offset + len]);
+                        return Ok(len);
+                }
+                let mut read = 0;
+                while read < len {
+                        let pos = offset + read;
+                        let off = pos % self.block_size;
+                        let rlen = core::cmp::min(self.block_size - off, len - read);
+                        let block = self.block_of(inode, pos / self.block_size)?;
+                        if block == 0 {
+                                buf[read..read + rlen].fill(0);
+                        } else {
+                                self.read_bytes(block as usize * self.block_size + off, &mut buf[read..read + rlen])?;
+                        }
+                        read += rlen;
+                }
+                return Ok(read);
+        }
+
+        /// List (name, inode number) of entries in directory "dir"
+        /// # Note
+        /// "." and ".." are included
+        pub fn dir_entries(&self, dir: &RawInode) -> Result<Vec<(String, u32)>, ErrNo> {
+                if !dir.is_dir() {
+                        return Err(ErrNo::NotADirectory);
+                }
+                // a corrupted i_size can't make us allocate more than the blocks the inode holds
+                let size = core::cmp::min(dir.size(), dir.blocks as usize * 512);
+                let mut data = Vec::new();
+                data.resize(size, 0);
+                let len = self.read_inode_data(dir, 0, &mut data)?;
+                let mut entries = Vec::new();
+                let mut pos = 0;
+                while pos + 8 <= len {
+                        let ino = u32::from_le_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]);
+                        let rec_len = u16::from_le_bytes([data[pos + 4], data[pos + 5]]) as usize;
+                        let name_len = if self.feature_incompat & FEATURE_INCOMPAT_FILETYPE != 0 {
+                                data[pos + 6] as usize
+                        } else {
+                                u16::from_le_bytes([data[pos + 6], data[pos + 7]]) as usize
+                        };
+                        if rec_len < 8 || pos + 8 + name_len > len {
+                                break;
+                        }
+                        if ino != 0 {
+                                if let Ok(name) = core::str::from_utf8(&data[pos + 8..pos + 8 + name_len]) {
+                                        entries.push((String::from(name), ino));
+                                }
+                        }
+                        pos += rec_len;
+                }
+                return Ok(entries);
+        }
+
+        /// Find inode number of "name" in directory "dir"
+        pub fn lookup(&self, dir: &RawInode, name: &str) -> Result<u32, ErrNo> {
+                for (ename, ino) in self.dir_entries(dir)? {
+                        if ename == name {
+                                return Ok(ino);
+                        }
+                }
+                return Err(ErrNo::NoSuchFileOrDirectory);
+        }
+
+        /// Walk "path" from directory inode "start"
+        /// # Note
+        /// Symbolic links in the middle of the path are not followed
+        pub fn resolve(&self, start: u32, path: &Path) -> Result<(u32, RawInode), ErrNo> {
+                let mut ino = start;
+                let mut inode = self.read_inode(ino)?;
+                for name in path.path.iter() {
+                        if !inode.is_dir() {
+                                return Err(ErrNo::NotADirectory);
+                        }
+                        ino = self.lookup(&inode, name)?;
+                        inode = self.read_inode(ino)?;
+                }
+                if path.must_dir && !inode.is_dir() {
+                        return Err(ErrNo::NotADirectory);
+                }
+                return Ok((ino, inode));
+        }
+}
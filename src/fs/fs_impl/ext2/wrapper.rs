@@ -0,0 +1,231 @@
+//! Wrapper of ext2 inodes to implement the crate::fs::file::File trait.
+use alloc::{string::String, sync::Arc, vec::Vec};
+use spin::Mutex;
+use crate::fs::{CommonFile, DeviceFile, DirFile, File, FileType, release_dropped_flocks};
+use crate::fs::file::FileStatus;
+use crate::fs::fs_impl::ext2_wrapper::Ext2W;
+use crate::fs::fs_impl::vfs::OpenMode;
+use crate::process::ErrNo;
+
+use super::{Ext2FS, RawInode, S_IFMT};
+use super::super::super::Path;
+
+pub struct Ext2File {
+        fs: Arc<Ext2FS>,
+        ino: u32,
+        inode: RawInode,
+        name: String,
+        /// Absolute path of the file inside the file system
+        path: Path,
+        cursor: Mutex<usize>,
+}
+
+impl Ext2File {
+        /// Open "path" relative to directory inode "start"
+        /// # Return
+        /// Err(ReadonlyFileSystem) when asked to create, truncate or write
+        pub fn open(fs: Arc<Ext2FS>, start: u32, base: &Path, path: Path, mode: OpenMode) -> Result<Ext2File, ErrNo> {
+                let (ino, inode) = match fs.resolve(start, &path) {
+                        Ok(res) => res,
+                        Err(ErrNo::NoSuchFileOrDirectory) if mode.contains(OpenMode::CREATE) => {
+                                return Err(ErrNo::ReadonlyFileSystem);
+                        },
+                        Err(errno) => return Err(errno),
+                };
+                if mode.intersects(OpenMode::WRITE | OpenMode::TRUNCATE) {
+                        return Err(ErrNo::ReadonlyFileSystem);
+                }
+                if mode.contains(OpenMode::DIR) && !inode.is_dir() {
+                        return Err(ErrNo::NotADirectory);
+                }
+                let mut full = base.clone();
+                full.path.extend(path.path.iter().cloned());
+                full.must_dir = inode.is_dir();
+                let name = match full.path.last() {
+                        Some(name) => name.clone(),
+                        None => String::from("/"),
+                };
+                return Ok(Ext2File {
+                        fs,
+                        ino,
+                        inode,
+                        name,
+                        path: full,
+                        cursor: Mutex::new(0),
+                });
+        }
+
+        fn ftype(&self) -> FileType {
+                match self.inode.mode & S_IFMT {
+                        0x1000 => FileType::FIFO,
+                        0x2000 => FileType::CharDev,
+                        0x4000 => FileType::Directory,
+                        0x6000 => FileType::BlockDev,
+                        0x8000 => FileType::Regular,
+                        0xA000 => FileType::Link,
+                        0xC000 => FileType::Sock,
+                        _ => FileType::Unknown,
+                }
+        }
+}
+
+impl Drop for Ext2File {
+        fn drop(&mut self) {
+                release_dropped_flocks();
+        }
+}
+
+impl File for Ext2File {
+        fn seek(&self, offset: isize, op: crate::fs::SeekOp) -> Result<(), ErrNo> {
+                if self.inode.is_dir() {
+                        return Err(ErrNo::IllegalSeek);
+                }
+                let mut cursor = self.cursor.lock();
+                let base = match op {
+                        crate::fs::SeekOp::SET => 0,
+                        crate::fs::SeekOp::CUR => *cursor as isize,
+                        crate::fs::SeekOp::END => self.inode.size() as isize,
+                };
+                if base + offset < 0 {
+                        return Err(ErrNo::InvalidArgument);
+                }
+                *cursor = (base + offset) as usize;
+                Ok(())
+        }
+
+        fn get_cursor(&self) -> Result<usize, ErrNo> {
+                if self.inode.is_dir() {
+                        return Err(ErrNo::IllegalSeek);
+                }
+                Ok(*self.cursor.lock())
+        }
+
+        fn read(&self, buffer: &mut [u8]) -> Result<usize, ErrNo> {
+                if self.inode.is_dir() {
+                        return Err(ErrNo::IsADirectory);
+                }
+                let mut cursor = self.cursor.lock();
+                let read = self.fs.read_inode_data(&self.inode, *cursor, buffer)?;
+                *cursor += read;
+                Ok(read)
+        }
+
+        fn write(&self, _buffer: &[u8]) -> Result<usize, ErrNo> {
+                Err(ErrNo::ReadonlyFileSystem)
+        }
+
+        fn read_user_buffer(&self, mut buffer: crate::memory::UserBuffer) -> Result<usize, ErrNo> {
+                let mut temp_arr: Vec<u8> = Vec::new();
+                temp_arr.resize(buffer.len(), 0);
+                let read = self.read(&mut temp_arr)?;
+                buffer.write_bytes(&temp_arr[..read], 0);
+                Ok(read)
+        }
+
+        fn write_user_buffer(&self, _buffer: crate::memory::UserBuffer) -> Result<usize, ErrNo> {
+                Err(ErrNo::ReadonlyFileSystem)
+        }
+
+        fn to_common_file<'a>(self: Arc<Self>) -> Option<Arc<dyn CommonFile + 'a>> where Self: 'a {
+                Some(self)
+        }
+
+        fn to_dir_file<'a>(self: Arc<Self>) -> Option<Arc<dyn DirFile + 'a>> where Self: 'a {
+                if self.inode.is_dir() {
+                        Some(self)
+                } else {
+                        None
+                }
+        }
+
+        fn to_device_file<'a>(self: Arc<Self>) -> Option<Arc<dyn DeviceFile + 'a>> where Self: 'a {
+                None
+        }
+
+        fn poll(&self) -> FileStatus {
+                FileStatus {
+                        readable: true,
+                        writeable: false,
+                        size: self.inode.size() as u64,
+                        name: self.name.clone(),
+                        ftype: self.ftype(),
+                        inode: self.ino as u64,
+                        dev_no: 0,
+                        mode: (self.inode.mode & 0o7777) as u32,
+                        block_sz: self.fs.block_size() as u32,
+                        blocks: self.inode.blocks as u64,
+                        uid: self.inode.uid as u32,
+                        gid: self.inode.gid as u32,
+                        atime_sec: self.inode.atime,
+                        atime_nsec: 0,
+                        mtime_sec: self.inode.mtime,
+                        mtime_nsec: 0,
+                        ctime_sec: self.inode.ctime,
+                        ctime_nsec: 0,
+                }
+        }
+
+        fn rename(&self, _new_name: &str) -> Result<(), ErrNo> {
+                Err(ErrNo::ReadonlyFileSystem)
+        }
+
+        fn get_vfs(&self) -> Result<Arc<dyn crate::fs::VirtualFileSystem>, ErrNo> {
+                Ok(Arc::new(Ext2W { inner: self.fs.clone() }))
+        }
+
+        fn get_path(&self) -> Path {
+                self.path.clone()
+        }
+}
+
+impl CommonFile for Ext2File {}
+
+impl DirFile for Ext2File {
+        /// open files under dir
+        fn open(&self, path: Path, mode: OpenMode) -> Result<Arc<dyn File>, ErrNo> {
+                let file = Ext2File::open(self.fs.clone(), self.ino, &self.path, path, mode)?;
+                Ok(Arc::new(file))
+        }
+
+        fn mkdir(&self, _name: Path) -> Result<Arc<dyn File>, ErrNo> {
+                Err(ErrNo::ReadonlyFileSystem)
+        }
+
+        fn mkfile(&self, _name: Path) -> Result<Arc<dyn File>, ErrNo> {
+                Err(ErrNo::ReadonlyFileSystem)
+        }
+
+        fn remove(&self, _path: Path) -> Result<(), ErrNo> {
+                Err(ErrNo::ReadonlyFileSystem)
+        }
+
+        /// list
+        fn list(&self) -> Vec<Arc<dyn File>> {
+                let mut result = Vec::<Arc<dyn File>>::new();
+                let entries = match self.fs.dir_entries(&self.inode) {
+                        Ok(entries) => entries,
+                        Err(_) => return result,
+                };
+                for (name, ino) in entries {
+                        if name == "." || name == ".." {
+                                continue;
+                        }
+                        let inode = match self.fs.read_inode(ino) {
+                                Ok(inode) => inode,
+                                Err(_) => continue,
+                        };
+                        let mut path = self.path.clone();
+                        path.path.push(name.clone());
+                        path.must_dir = inode.is_dir();
+                        result.push(Arc::new(Ext2File {
+                                fs: self.fs.clone(),
+                                ino,
+                                inode,
+                                name,
+                                path,
+                                cursor: Mutex::new(0),
+                        }));
+                }
+                return result;
+        }
+}
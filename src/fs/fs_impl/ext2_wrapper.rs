@@ -0,0 +1,91 @@
+use alloc::sync::Arc;
+use alloc::string::String;
+
+use super::BlockDeviceFile;
+use super::cache_mgr::BLOCK_SZ;
+use super::devfs::CommonFileAsBlockDevice;
+use super::ext2::{Ext2FS, ROOT_INO};
+use super::ext2::wrapper::Ext2File;
+
+use super::vfs::*;
+
+use crate::fs::File;
+use crate::fs::Path;
+use crate::process::ErrNo;
+
+pub struct Ext2W {
+        pub inner: Arc<Ext2FS>,
+}
+
+impl Ext2W {
+        pub fn new(blk: Arc<dyn File>) -> Option<Self> {
+                verbose!("Creating ext2 fs");
+                let device: Arc<dyn BlockDeviceFile> = if let Some(dev) = blk.clone().to_device_file() {
+                        dev.to_blk_dev()?
+                } else {
+                        Arc::new(CommonFileAsBlockDevice::new(blk.clone(), BLOCK_SZ))
+                };
+                match Ext2FS::open(device) {
+                        Ok(fs) => Some(Self { inner: Arc::new(fs) }),
+                        Err(_) => None,
+                }
+        }
+}
+
+impl VirtualFileSystem for Ext2W {
+        /// nothing is ever dirty on a read-only fs
+        fn sync(&self, _wait: bool) {
+        }
+
+        fn try_sync(&self) -> bool {
+                true
+        }
+
+        /// every cached block is clean
+        fn drop_clean_caches(&self) -> usize {
+                self.inner.drop_clean_caches()
+        }
+
+        /// get status
+        fn get_status(&self) -> FSStatus {
+                return FSStatus::new(Ext2FS::name, FSFlags::empty());
+        }
+
+        fn fs_id(&self) -> usize {
+                Arc::as_ptr(&self.inner) as usize
+        }
+
+        fn open(&self, abs_path: Path, mode: OpenMode) -> Result<Arc<dyn File>, ErrNo> {
+                verbose!("Ext2 opening: {:?}", abs_path);
+                let file = Ext2File::open(self.inner.clone(), ROOT_INO, &Path::root(), abs_path, mode)?;
+                return Ok(Arc::new(file));
+        }
+
+        fn mkdir(&self, _abs_path: Path) -> Result<Arc<dyn File>, ErrNo> {
+                return Err(ErrNo::ReadonlyFileSystem);
+        }
+
+        fn mkfile(&self, _abs_path: Path) -> Result<Arc<dyn File>, ErrNo> {
+                return Err(ErrNo::ReadonlyFileSystem);
+        }
+
+        fn mktmp(&self, _abs_dir: Path, _mode: OpenMode) -> Result<Arc<dyn File>, ErrNo> {
+                return Err(ErrNo::ReadonlyFileSystem);
+        }
+
+        fn remove(&self, _abs_path: Path) -> Result<(), ErrNo> {
+                return Err(ErrNo::ReadonlyFileSystem);
+        }
+
+        fn link(&self, _to_link: Arc<dyn File>, _dest: Path) -> Result<(), ErrNo> {
+                return Err(ErrNo::ReadonlyFileSystem);
+        }
+
+        fn sym_link(&self, _abs_src: Path, _rel_dst: Path) -> Result<(), ErrNo> {
+                return Err(ErrNo::ReadonlyFileSystem);
+        }
+
+        fn rename(&self, _to_rename: Arc<dyn File>, _new_name: String) -> Result<(), ErrNo> {
+                return Err(ErrNo::ReadonlyFileSystem);
+        }
+}
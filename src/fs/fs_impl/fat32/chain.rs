@@ -18,11 +18,9 @@ impl Chain {
         const MAX_LEN:usize = 1024 * 1024;
 
         /// Get the file chain of root directory
-        pub fn root(fs: Arc<Fat32FS>) -> Result<Chain, &'static str> {
-                fs.dbr.root;
-                let chain = fs.get_chain(fs.dbr.root);
+        pub fn root(fs: Arc<Fat32FS>) -> Result<Chain, ErrNo> {
+                let chain = fs.get_chain(fs.dbr.root)?;
                 return Ok( Chain {fs: fs.clone(), chain} );
-                // return Err("error when reading root");
         }
         
         /// Create a empty file chain
@@ -47,13 +45,13 @@ impl Chain {
                 let (mut idx,clst) = self.get_cluster(offset)?;
                 let coff = offset % self.fs.cluster_size();
                 let len = buffer.len();
-                let mut read = self.fs.read_cluster(clst, coff, buffer).unwrap();
+                let mut read = self.fs.read_cluster(clst, coff, buffer)?;
                 while read < len {
                         let buf = &mut buffer[read..];
                         idx +=1 ;
                         match self.chain.get(idx) {
                                 Some(clst) => {
-                                        read += self.fs.read_cluster(*clst, 0, buf).unwrap();
+                                        read += self.fs.read_cluster(*clst, 0, buf)?;
                                 },
                                 None => {
                                         return Ok(read);
@@ -63,50 +61,84 @@ impl Chain {
                 return Ok(read);
         }
 
+        /// Grow the chain by one cluster
+        fn grow(&mut self) -> Result<u32, ErrNo> {
+                let new = if self.chain.len() == 0 {
+                        self.fs.alloc_cluster()
+                } else {
+                        self.fs.append_chain(*self.chain.last().unwrap())
+                }?;
+                self.chain.push(new);
+                return Ok(new);
+        }
+
+        /// Grow the chain by one cluster holding "buf" at "coff"
+        /// # Description
+        /// The data reaches the disk before the FAT entry linking the cluster into the chain is written,
+        /// so a crash can't leave the chain pointing at a cluster whose data was never written.
+        /// # Return
+        /// Number of bytes written into the new cluster
+        fn grow_with(&mut self, coff: usize, buf: &[u8]) -> Result<usize, ErrNo> {
+                let new = self.fs.alloc_cluster()?;
+                let written = match self.fs.write_cluster(new, coff, buf) {
+                        Ok(written) => self.fs.flush_cluster(new).map(|_| written),
+                        Err(errno) => Err(errno),
+                };
+                let linked = match (written, self.chain.last()) {
+                        (Ok(_), Some(last)) => self.fs.link_cluster(*last, new),
+                        (Ok(_), None) => Ok(()),
+                        (Err(errno), _) => Err(errno),
+                };
+                if let Err(errno) = linked {
+                        // nothing refers to the cluster yet, a failure to free it only leaks it
+                        if let Err(err) = self.fs.clear_chain(new) {
+                                error!("Fat32: cluster {} is leaked: {:?}", new, err);
+                        }
+                        return Err(errno);
+                }
+                self.chain.push(new);
+                return written;
+        }
+
         /// Write the contents of the buffer into the file chain at "offset"
         /// # Description
         /// Chain append will be performed when necessary. 
         /// If "offset" is bigger than the offset of the last byte in chain, space between them will be filled with 0.
+        /// Appended clusters are written before they are linked, see grow_with.
         /// # Return
-        /// Number of bytes that actually written
+        /// Number of bytes that actually written, short if the disk is full.  
+        /// Err(NoSpaceLeftOnDevice) if the disk is full and nothing is written.
         pub fn write(&mut self, offset: usize, buffer: &[u8]) -> Result<usize, ErrNo> {
                 // error!("who is calling the write?");
-                let (mut idx, clst) = loop {
-                        match self.get_cluster(offset) {
-                                Ok(c) => break c,
-                                Err(_msg) => {
-                                        if self.chain.len() < Chain::MAX_LEN {
-                                                let new = if self.chain.len() == 0 {
-                                                        self.fs.alloc_cluster().unwrap()
-                                                } else {
-                                                        self.fs.append_chain(*self.chain.last().unwrap()).unwrap()
-                                                };
-                                                self.chain.push(new);
-                                        } else {
-                                                return Err(ErrNo::InvalidArgument);
-                                        }
-                                },
-                        }
-                };
-                let coff = offset % self.fs.cluster_size();
+                let csize = self.fs.cluster_size();
+                let mut idx = offset / csize;
+                if idx >= Chain::MAX_LEN {
+                        return Err(ErrNo::InvalidArgument);
+                }
+                // clusters between the end of the chain and "offset" hold no data
+                while self.chain.len() < idx {
+                        self.grow()?;
+                }
+                let coff = offset % csize;
                 let len = buffer.len();
-                let mut write = self.fs.write_cluster(clst, coff, buffer).unwrap();
+                let mut write = match self.chain.get(idx) {
+                        Some(clst) => self.fs.write_cluster(*clst, coff, buffer)?,
+                        None => self.grow_with(coff, buffer)?,
+                };
                 while write < len {
                         let buf = &buffer[write..];
                         idx += 1;
                         match self.chain.get(idx) {
                                 Some(clst) => {
-                                        write += self.fs.write_cluster(*clst, 0, buf).unwrap();
+                                        write += self.fs.write_cluster(*clst, 0, buf)?;
                                 },
                                 None => {
                                         if self.chain.len() < Chain::MAX_LEN {
-                                                let new = if self.chain.len() == 0 {
-                                                        self.fs.alloc_cluster().unwrap()
-                                                } else {
-                                                        self.fs.append_chain(*self.chain.last().unwrap()).unwrap()
-                                                };
-                                                self.chain.push(new);
-                                                write += self.fs.write_cluster(new, 0, buf).unwrap();
+                                                match self.grow_with(0, buf) {
+                                                        Ok(w) => write += w,
+                                                        Err(ErrNo::NoSpaceLeftOnDevice) => return Ok(write),
+                                                        Err(errno) => return Err(errno),
+                                                }
                                         } else {
                                                 return Ok(write);
                                         }
@@ -116,10 +148,41 @@ impl Chain {
                 return Ok(write);
         }
 
+        /// Make the chain at least "len" clusters long
+        /// # Return
+        /// Err(NoSpaceLeftOnDevice) if the disk is full, clusters appended by this call are released.
+        pub fn reserve(&mut self, len: usize) -> Result<(), ErrNo> {
+                if len > Chain::MAX_LEN {
+                        return Err(ErrNo::FileTooLarge);
+                }
+                let orig = self.chain.len();
+                while self.chain.len() < len {
+                        if let Err(errno) = self.grow() {
+                                let released = if orig == 0 {
+                                        let released = match self.chain.first() {
+                                                Some(first) => self.fs.clear_chain(*first),
+                                                None => Ok(()),
+                                        };
+                                        self.chain.clear();
+                                        released
+                                } else {
+                                        self.truncate(orig)
+                                };
+                                if released.is_err() {
+                                        error!("Fat32: clusters reserved past {} are leaked", orig);
+                                }
+                                return Err(errno);
+                        }
+                }
+                return Ok(());
+        }
+
         /// Trucate chain to the specified length
-        pub fn truncate(&mut self, len: usize) -> Result<(), ()> {
+        /// # Return
+        /// Err(IOError) if the FAT can't be accessed, the chain is left as it was
+        pub fn truncate(&mut self, len: usize) -> Result<(), ErrNo> {
                 if self.chain.len() > len {
-                        self.fs.truncate_chain(self.chain[len-1]).unwrap();
+                        self.fs.truncate_chain(self.chain[len-1])?;
                         self.chain.truncate(len);
                 }
                 return Ok(());
@@ -1,12 +1,96 @@
 //! Directory entry
-use core::str::from_utf8;
 use core::mem::size_of;
 use alloc::vec::Vec;
 use alloc::string::String;
+use alloc::format;
 use super::chain::Chain;
 
 use crate::process::ErrNo;
 
+/// Days since 1970-01-01 of the date "year"-"month"-"day" in the proleptic Gregorian calendar
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+        let y = if month <= 2 { year - 1 } else { year };
+        let era = y.div_euclid(400);
+        let yoe = y - era * 400;
+        let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        era * 146097 + doe - 719468
+}
+
+/// (year, month, day) of the date "days" days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        (year, month, day)
+}
+
+/// Characters allowed in short names besides letters and digits
+const SHORT_NAME_SPECIAL: &[u8] = b"!#$%&'()-@^_`{}~";
+
+/// Largest numeric tail of a short name, "~999999" leaves 1 character of the basis
+const MAX_NUMERIC_TAIL: usize = 999999;
+
+/// Convert part of a long file name to short name characters
+/// # Return
+/// Converted characters and if anything is lost in conversion
+fn short_chars(part: &str) -> (Vec<u8>, bool) {
+        let mut lossy = false;
+        let mut result = Vec::new();
+        for c in part.chars() {
+                if c == ' ' || c == '.' {
+                        lossy = true;
+                } else if c.is_ascii_alphanumeric() || (c.is_ascii() && SHORT_NAME_SPECIAL.contains(&(c as u8))) {
+                        result.push((c as u8).to_ascii_uppercase());
+                } else {
+                        lossy = true;
+                        result.push('_' as u8);
+                }
+        }
+        return (result, lossy);
+}
+
+/// Basis of the short name of "name"
+/// # Return
+/// (name part, ext part truncated to 3, if a numeric tail is required)
+fn short_name_basis(name: &str) -> (Vec<u8>, Vec<u8>, bool) {
+        let (base, ext) = match name.rfind('.') {
+                Some(pos) if pos > 0 => (&name[..pos], &name[pos+1..]),
+                _ => (name, ""),
+        };
+        let (mut base, base_lossy) = short_chars(base);
+        let (mut ext, ext_lossy) = short_chars(ext);
+        let mut lossy = base_lossy || ext_lossy || base.len() > 8 || ext.len() > 3;
+        if base.len() == 0 {
+                base.push('_' as u8);
+                lossy = true;
+        }
+        ext.truncate(3);
+        return (base, ext, lossy);
+}
+
+/// Compare two file names ignoring case
+fn eq_ignore_case(a: &str, b: &str) -> bool {
+        a.chars().flat_map(char::to_uppercase).eq(b.chars().flat_map(char::to_uppercase))
+}
+
+/// Max # of UTF-16 units in a long file name
+const MAX_LFN_LEN: usize = 255;
+
+/// Long file names are limited to 255 UTF-16 units, not bytes
+fn check_lfn_len(name: &str) -> Result<(), ErrNo> {
+        if name.encode_utf16().count() > MAX_LFN_LEN {
+                return Err(ErrNo::FileNameTooLong);
+        }
+        return Ok(());
+}
+
 /// Directory Entry in raw
 #[derive(Clone, Copy)]
 #[repr(C, packed(1))]
@@ -112,16 +196,18 @@ impl DirEntryRaw {
         /// Set the start cluster of the file chain
         pub fn set_start(&mut self, start: u32) {
                 self.start_h = (start >> 16) as u16;
-                self.start_l = (start & 0xff) as u16;
+                self.start_l = (start & 0xffff) as u16;
         }
 
         /// Get short file name
+        /// # Note
+        /// Short names written by other systems may use an OEM code page, bytes that are not UTF-8 are replaced.
         pub fn get_name(&self) -> String {
                 let mut name = String::new();
-                name += from_utf8(&self.name).unwrap().trim();
+                name += String::from_utf8_lossy(&self.name).trim();
                 // println!("{}: {}", name.len(), name);
                 let mut ext = String::new();
-                ext += from_utf8(&self.ext).unwrap().trim();
+                ext += String::from_utf8_lossy(&self.ext).trim();
                 // debug!("ext len:{} ext: {} {} {}", ext.len(), self.ext[0], self.ext[1], self.ext[2]);
                 if ext.len() > 0 {
                         name += ".";
@@ -130,70 +216,84 @@ impl DirEntryRaw {
                 return name;
         }
 
+        /// Raw 8.3 name, name and ext
+        pub fn short_name(&self) -> [u8; 11] {
+                let mut short = [0u8; 11];
+                short[..8].copy_from_slice(&self.name);
+                short[8..].copy_from_slice(&self.ext);
+                return short;
+        }
+
+        /// Set short file name from the basis of "name"
+        /// # Description
+        /// "tail" of 0 keeps the basis (truncated to 8.3) as is, 
+        /// otherwise the numeric tail "~tail" ends the name part.
+        pub fn set_short_name(&mut self, name: &str, tail: usize) {
+                self.name = [' ' as u8; 8];
+                self.ext = [' ' as u8; 3];
+                if name == "." || name == ".." {
+                        self.name[..name.len()].copy_from_slice(name.as_bytes());
+                        return;
+                }
+                let (mut base, ext, _) = short_name_basis(name);
+                if tail > 0 {
+                        let tail = format!("~{}", tail);
+                        base.truncate(8 - tail.len());
+                        base.extend_from_slice(tail.as_bytes());
+                }
+                base.truncate(8);
+                self.name[..base.len()].copy_from_slice(&base);
+                self.ext[..ext.len()].copy_from_slice(&ext);
+        }
+
         /// Set short file name
+        /// # Note
+        /// Uses "~1" when "name" doesn't fit 8.3 without checking for collisions,
+        /// use set_unique_name when the directory is known.
         pub fn set_name(&mut self, name: &str) {
-                let b:Vec<u8> = name.bytes().collect();
-                for i in (0..b.len()).rev() {
-                        if b[i] == '.' as u8 {
-                                let name_len = i;
-                                let ext_len = b.len() - i - 1;
-                                let mut name_ok = true;
-                                if name_len > 0 && name_len <= 8 {
-                                        for j in 0..name_len {
-                                                self.name[j] = b[j].to_ascii_uppercase();
-                                        }
-                                        for j in name_len..8 {
-                                                self.name[j] = ' ' as u8;
-                                        }
-                                } else if name_len == 8 {
-                                        for j in 0..6 {
-                                                self.name[j] = b[j].to_ascii_uppercase();
-                                        }
-                                        self.name[6] = '~' as u8;
-                                        self.name[7] = '1' as u8;
-                                } else {
-                                        name_ok = false;
-                                }
-                                if name_ok {
-                                        if ext_len > 0 && ext_len <= 3 {
-                                                for j in 0..ext_len {
-                                                        self.ext[j] = b[i+1+j].to_ascii_uppercase();
-                                                }
-                                                for j in ext_len..3 {
-                                                        self.ext[j] = ' ' as u8;
-                                                }
-                                                return;
-                                        } else if ext_len > 3 {
-                                                for j in 0..3 {
-                                                        self.ext[j] = b[i+1+j].to_ascii_uppercase();
-                                                }
-                                                return;
-                                        } 
-
-                                }
+                let (_, _, lossy) = short_name_basis(name);
+                self.set_short_name(name, if lossy {1} else {0});
+        }
+
+        /// Set a short file name that differs from all of "taken"
+        /// # Description
+        /// Tries the basis first if "name" fits 8.3, then "~1", "~2"... until one is free.
+        pub fn set_unique_name(&mut self, name: &str, taken: &[[u8; 11]]) -> Result<(), ErrNo> {
+                let (_, _, lossy) = short_name_basis(name);
+                let first = if lossy {1} else {0};
+                for tail in first..=MAX_NUMERIC_TAIL {
+                        self.set_short_name(name, tail);
+                        if !taken.contains(&self.short_name()) {
+                                return Ok(());
                         }
                 }
-                if b.len() <= 8 {
-                        let name_len = b.len();
-                        for j in 0..name_len {
-                                self.name[j] = b[j].to_ascii_uppercase();
-                        }
-                        for j in name_len..8 {
-                                self.name[j] = ' ' as u8;
-                        }
-                        for j in 0..3 {
-                                self.ext[j] = ' ' as u8;
-                        }
-                } else {
-                        for j in 0..6 {
-                                self.name[j] = b[j].to_ascii_uppercase();
-                        }
-                        self.name[6] = '~' as u8;
-                        self.name[7] = '1' as u8;
-                        for j in 0..3 {
-                                self.ext[j] = ' ' as u8;
-                        }
+                return Err(ErrNo::FileExists);
+        }
+
+        /// Get modification time, in seconds since 1980-01-01 (the FAT epoch)
+        pub fn mod_time_sec(&self) -> usize {
+                let (date, time) = (self.mod_date, self.mod_sec);
+                if date == 0 {
+                        return 0;
                 }
+                let year = 1980 + (date >> 9) as i64;
+                let month = ((date >> 5) & 0xf) as i64;
+                let day = (date & 0x1f) as i64;
+                let days = (days_from_civil(year, month, day) - days_from_civil(1980, 1, 1)) as usize;
+                days * 86400
+                + (time >> 11) as usize * 3600
+                + ((time >> 5) & 0x3f) as usize * 60
+                + (time & 0x1f) as usize * 2
+        }
+
+        /// Set modification time to "secs" seconds since 1980-01-01 (the FAT epoch)
+        /// # Note
+        /// FAT keeps the time in 2 seconds
+        pub fn set_mod_time(&mut self, secs: usize) {
+                let (year, month, day) = civil_from_days((secs / 86400) as i64 + days_from_civil(1980, 1, 1));
+                let secs = secs % 86400;
+                self.mod_date = (((year - 1980).min(127) as u16) << 9) | ((month as u16) << 5) | day as u16;
+                self.mod_sec = ((secs / 3600) as u16) << 11 | (((secs / 60) % 60) as u16) << 5 | ((secs % 60) / 2) as u16;
         }
 
         /// Get check sum for extension entries
@@ -301,7 +401,7 @@ impl DirEntryExtRaw {
         pub fn new(name: &str, chksum: u8) -> Vec<DirEntryExtRaw> {
                 let mut result = Vec::<DirEntryExtRaw>::new();
                 let mut name:Vec<u16> = name.encode_utf16().collect();
-                while name[name.len() - 1] == 0 {
+                while name.len() > 0 && name[name.len() - 1] == 0 {
                         name.pop();
                 }
                 if name.len() % 13 != 0 {
@@ -373,28 +473,21 @@ impl DirEntryExtRaw {
         }
 
         /// Get the part of name that the entry holds
-        pub fn get_name(&self) -> Vec::<u8> {
-                let mut name = Vec::with_capacity(26);
-                for b in &self.name0 {
-                        if *b == 0xFF {
-                                return name;
-                        } else {
-                                name.push(*b);
-                        } 
-                }
-                for b in &self.name1 {
-                        if *b == 0xFF {
-                                return name;
-                        } else {
-                                name.push(*b);
-                        } 
-                }
-                for b in &self.name2 {
-                        if *b == 0xFF {
-                                return name;
-                        } else {
-                                name.push(*b);
-                        } 
+        /// # Return
+        /// UTF-16 units of the part, without the 0x0000 terminator and 0xFFFF padding.
+        /// Surrogate pairs may span two entries, decode only after joining all parts.
+        pub fn get_name(&self) -> Vec::<u16> {
+                let mut bytes = [0u8; 26];
+                bytes[..10].copy_from_slice(&self.name0);
+                bytes[10..22].copy_from_slice(&self.name1);
+                bytes[22..].copy_from_slice(&self.name2);
+                let mut name = Vec::with_capacity(13);
+                for i in 0..13 {
+                        let unit = u16::from_le_bytes([bytes[2*i], bytes[2*i+1]]);
+                        if unit == 0 || unit == 0xFFFF {
+                                break;
+                        }
+                        name.push(unit);
                 }
                 return name;
         }
@@ -457,9 +550,34 @@ impl DirEntryGroup {
                 return DirEntryGroup {entry, exts, offset: 0, slotsize:0 };
         }
 
+        /// Create the "." or ".." entry of a directory
+        /// # Note
+        /// They have short names only, no long name entries.
+        pub fn dot(name: &str, start: u32) -> DirEntryGroup {
+                let mut entry = DirEntryRaw::blank();
+                entry.attr = DirEntryRaw::ATTR_SUBDIR;
+                entry.set_short_name(name, 0);
+                entry.set_start(start);
+                return DirEntryGroup {entry, exts: Vec::new(), offset: 0, slotsize: 0};
+        }
+
+        /// Create a entry group whose short name differs from all of "taken"
+        pub fn new_unique(name: &str, start: u32, attr: u8, taken: &[[u8; 11]]) -> Result<DirEntryGroup, ErrNo> {
+                check_lfn_len(name)?;
+                let mut entry = DirEntryRaw::blank();
+                entry.attr = attr;
+                entry.set_unique_name(name, taken)?;
+                entry.set_start(start);
+                let exts = DirEntryExtRaw::new(name, entry.chksum());
+                return Ok(DirEntryGroup {entry, exts, offset: 0, slotsize:0 });
+        }
+
         /// Change the filename that the entries hold
-        pub fn rename(&mut self, name: &str) -> Result<(), ()> {
-                self.entry.set_name(name);
+        /// # Description
+        /// "taken" are short names already in the directory
+        pub fn rename(&mut self, name: &str, taken: &[[u8; 11]]) -> Result<(), ErrNo> {
+                check_lfn_len(name)?;
+                self.entry.set_unique_name(name, taken)?;
                 self.exts = DirEntryExtRaw::new(name, self.entry.chksum());
                 return Ok(());
         }
@@ -480,7 +598,7 @@ impl DirEntryGroup {
 
         /// Get the filename that the entries hold
         pub fn get_name(&self) -> Result<String, &'static str> {
-                let mut name = Vec::<u8>::new();
+                let mut name = Vec::<u16>::new();
                 if self.exts.len() > 0 {
                         for i in (0..self.exts.len()).rev() {
                                 name.append(&mut self.exts[i].get_name());
@@ -488,18 +606,8 @@ impl DirEntryGroup {
                                         if i != 0 {
                                                 return Err("get_name: end not end?");
                                         }
-                                        let nlen = name.len() >> 1;
-                                        let mut n = Vec::<u16>::with_capacity(nlen);
-                                        for j in 0..nlen {
-                                                let tmp:u16 = name[2*j+1] as u16;
-                                                let tmp:u16 = (tmp << 8) | (name[2*j] as u16);
-                                                if tmp == 0 {
-                                                        break;
-                                                }
-                                                n.push(tmp);
-                                        }
-                                        let name = String::from_utf16(&n).unwrap();
-                                        return Ok(name);
+                                        // unpaired surrogates from a broken entry shouldn't make the whole directory unreadable
+                                        return Ok(String::from_utf16_lossy(&name));
                                 }
                         } 
 
@@ -509,6 +617,19 @@ impl DirEntryGroup {
                 }
         }
 
+        /// If "name" refers to the group
+        /// # Description
+        /// FAT is case-insensitive but case-preserving: "name" is compared with the long name
+        /// and the short name ignoring case, while get_name keeps reporting the long name as created.
+        pub fn matches(&self, name: &str) -> bool {
+                if let Ok(lfn) = self.get_name() {
+                        if eq_ignore_case(name, &lfn) {
+                                return true;
+                        }
+                }
+                return !self.is_cur() && !self.is_par() && eq_ignore_case(name, &self.entry.get_name());
+        }
+
         /// Get the starting cluster of the file chain
         pub fn get_start(&self) -> u32{
                 return self.entry.get_start();
@@ -518,7 +639,9 @@ impl DirEntryGroup {
 /// If a directory is empty
 /// # Description
 /// "chain" is the file chain of the directory
-pub fn empty_dir(chain: &Chain) -> bool {
+/// # Return
+/// Err(IOError) if the directory can't be read
+pub fn empty_dir(chain: &Chain) -> Result<bool, ErrNo> {
         let mut offset = 0;
         loop {
                 match read_dirent_group(&chain, offset) {
@@ -526,10 +649,11 @@ pub fn empty_dir(chain: &Chain) -> bool {
                                 if group.is_cur() || group.is_par() {
                                         offset = next;
                                 } else {
-                                        return false;
+                                        return Ok(false);
                                 }
                         },
-                        Err(_) => return true,
+                        Err(ErrNo::IOError) => return Err(ErrNo::IOError),
+                        Err(_) => return Ok(true),
                 }
         } 
 }
@@ -590,7 +714,10 @@ pub fn read_dirent_group(chain: &Chain, offset: usize) -> Result<(DirEntryGroup,
 /// write_dirent_group will try to update the entries in chain first.
 /// If update failed (for example, filename gets longer or group not exist in the chain),
 /// it wirte new entried at the end of the chain, and delete the old ones (if there are). 
-pub fn write_dirent_group (chain: &mut Chain, group: &mut DirEntryGroup) -> Result<(),()> {
+/// # Return
+/// Err(NoSpaceLeftOnDevice) if the directory needs to grow but the disk is full,
+/// entries written before running out are discarded and the group is left untouched.
+pub fn write_dirent_group (chain: &mut Chain, group: &mut DirEntryGroup) -> Result<(), ErrNo> {
         if group.slotsize == 0 {
                 let mut offset = 0;
                 let mut slotsize = 0;
@@ -605,30 +732,40 @@ pub fn write_dirent_group (chain: &mut Chain, group: &mut DirEntryGroup) -> Resu
                         offset += size_of::<DirEntryRaw>();
                 }
                 group.offset = offset;
+                let start = offset;
                 for ext in &group.exts {
                         unsafe {
                                 // let buf = core::slice::from_raw_parts((ext as *const DirEntryExtRaw) as *const u8, size_of::<DirEntryExtRaw>());
                                 let buf = &*(ext as *const _ as *const [u8; size_of::<DirEntryExtRaw>()]).clone();
-                                chain.write(offset, buf).unwrap();
+                                if let Err(errno) = chain.write(offset, buf) {
+                                        rollback_dirents(chain, start, offset);
+                                        return Err(errno);
+                                }
                         } 
                         offset += size_of::<DirEntryExtRaw>();
                 }
                 unsafe {
                         let buf = &*((&group.entry as *const _) as *const [u8; size_of::<DirEntryRaw>()]).clone();
-                        chain.write(offset, buf).unwrap();
+                        if let Err(errno) = chain.write(offset, buf) {
+                                rollback_dirents(chain, start, offset);
+                                return Err(errno);
+                        }
                 }
                 group.slotsize = group.exts.len() + 1 + slotsize;
                 return Ok(());
         } else if group.slotsize < group.exts.len() + 1 {
                 let offset = group.offset;
+                let slotsize = group.slotsize;
                 group.slotsize = 0;
                 match write_dirent_group(chain, group) {
                         Ok(()) => {
                                 delete_dirent_group(chain, offset).unwrap();
                                 return Ok(());
                         },
-                        Err(msg) => {
-                                return Err(msg);
+                        Err(errno) => {
+                                group.offset = offset;
+                                group.slotsize = slotsize;
+                                return Err(errno);
                         }
                 }
         } else {
@@ -648,6 +785,16 @@ pub fn write_dirent_group (chain: &mut Chain, group: &mut DirEntryGroup) -> Resu
         }
 }
 
+/// Undo a partially written group at the end of the directory,
+/// so that the end-of-directory mark is at "start" again
+fn rollback_dirents(chain: &mut Chain, start: usize, end: usize) {
+        let mut offset = start;
+        while offset < end {
+                chain.write(offset, &[0u8]).unwrap();
+                offset += size_of::<DirEntryRaw>();
+        }
+}
+
 /// Mark the entries in chain as deleted
 pub fn delete_dirent_group(chain: &mut Chain, offset: usize) -> Result<(), ErrNo>{
         let mut buf = [0u8; size_of::<DirEntryRaw>()];
@@ -9,10 +9,13 @@ use super::super::super::parse_path;
 use super::super::super::Path;
 use super::super::super::to_string;
 use super::dirent::write_dirent_group;
+use super::dirent::{DirEntryGroup, DirEntryRaw};
+use super::chain::Chain;
 // use super::super::super::file::SeekOp;
 use crate::fs::SeekOp;
 use crate::fs::file::FileType;
 use crate::process::ErrNo;
+use crate::sbi::get_time_ms;
 
 /// File Access Mode: Read allowed
 pub const READ: usize = 1;
@@ -28,11 +31,18 @@ pub const NO_FOLLOW: usize = 16;
 pub const TRUNCATE: usize = 32;
 // const APPEND: usize = 4;
 
+/// fallocate mode: allocate clusters without changing the file size
+pub const FALLOC_FL_KEEP_SIZE: usize = 1;
+
 /// File struct of Fat32
 pub struct FileInner{
         inode: Inode,
         cursor: usize,
         mode: usize,
+        /// No directory entry refers to the file, clusters are freed on close
+        unlinked: bool,
+        /// Clusters beyond the file size were preallocated, keep them on close
+        keep_clusters: bool,
 }
 
 macro_rules! has {
@@ -46,16 +56,28 @@ macro_rules! has {
 impl FileInner {
         /// Create a file struct for "inode" with mode "mode"
         pub fn new(mut inode: Inode, mode:usize) -> FileInner {
-                if has!(mode, TRUNCATE) {
+                let truncated = has!(mode, TRUNCATE);
+                if truncated {
                         inode.set_size(0);
                 }
-                FileInner {
+                let mut file = FileInner {
                         inode,
                         cursor: 0,
                         mode,
+                        unlinked: false,
+                        keep_clusters: false,
+                };
+                if truncated {
+                        file.touch();
                 }
+                return file;
         }      
 
+        /// Set modification time of the file to now
+        fn touch(&mut self) {
+                self.inode.group.entry.set_mod_time((get_time_ms() / 1000) as usize);
+        }
+
         /// If the file is a symbolic link
         #[inline]
         pub fn is_link(&self) -> bool {
@@ -144,7 +166,11 @@ impl FileInner {
                 if !has!(self.mode, READ) {
                         return Err(ErrNo::BadFileDescriptor);
                 }
-                let left = self.inode.get_size() - self.cursor;
+                // the cursor may be past the end after a seek
+                let left = self.inode.get_size().saturating_sub(self.cursor);
+                if left == 0 {
+                        return Ok(0);
+                }
                 if left < buffer.len() {
                         buffer = &mut buffer[0..left];
                 }
@@ -174,12 +200,44 @@ impl FileInner {
                                 if self.inode.get_size() < self.cursor {
                                         self.inode.set_size(self.cursor as u32);
                                 }
+                                self.touch();
                                 return Ok(w);
                         },
                         Err(errno) => return Err(errno),
                 }
         }
 
+        /// Preallocate clusters to cover [offset, offset + len) of the file
+        /// # Description
+        /// New clusters are zero-filled. File size is extended to offset + len
+        /// unless FALLOC_FL_KEEP_SIZE is set in "mode".
+        pub fn fallocate(&mut self, mode: usize, offset: usize, len: usize) -> Result<(), ErrNo> {
+                if self.inode.is_dir() {
+                        return Err(ErrNo::IsADirectory);
+                }
+                if !has!(self.mode, WRITE) {
+                        return Err(ErrNo::BadFileDescriptor);
+                }
+                if mode & !FALLOC_FL_KEEP_SIZE != 0 {
+                        return Err(ErrNo::OperationNotSupportedOnTransportEndpoint);
+                }
+                if len == 0 {
+                        return Err(ErrNo::InvalidArgument);
+                }
+                let end = offset.checked_add(len).ok_or(ErrNo::FileTooLarge)?;
+                if end > u32::MAX as usize {
+                        return Err(ErrNo::FileTooLarge);
+                }
+                let csize = self.inode.chain.fs.cluster_size();
+                self.inode.chain.reserve((end + csize - 1) / csize)?;
+                if has!(mode, FALLOC_FL_KEEP_SIZE) {
+                        self.keep_clusters = true;
+                } else if self.inode.get_size() < end {
+                        self.inode.set_size(end as u32);
+                }
+                return Ok(());
+        }
+
         /// Open a file from file "self". "self" must be a directory.
         pub fn open(&mut self, mut path: Path, mode:usize) -> Result<FileInner, ErrNo> {
                 // let fs = self.inode.chain.fs.clone();
@@ -245,24 +303,30 @@ impl FileInner {
                         let mut parent = self.inode.find_inode_path(&path)?;
                         match parent.find_inode(&name) {
                                 Ok(_) => return Err(ErrNo::FileExists),
-                                Err(_) => {},
+                                Err(ErrNo::NoSuchFileOrDirectory) => {},
+                                Err(errno) => return Err(errno),
                         }
                         let inode = parent.new_dir(&name, 0)?;
                         return Ok(FileInner{
                                 inode,
                                 cursor: 0,
                                 mode: 0,
+                                unlinked: false,
+                                keep_clusters: false,
                         });
                 } else {
                         match self.inode.find_inode(&name) {
                                 Ok(_) => return Err(ErrNo::FileExists),
-                                Err(_) => {},
+                                Err(ErrNo::NoSuchFileOrDirectory) => {},
+                                Err(errno) => return Err(errno),
                         }
                         let inode = self.inode.new_dir(&name, 0)?;
                         return Ok(FileInner{
                                 inode,
                                 cursor: 0,
                                 mode: 0,
+                                unlinked: false,
+                                keep_clusters: false,
                         });
                 }
         }
@@ -290,13 +354,16 @@ impl FileInner {
                         let mut parent = self.inode.find_inode_path(&path)?;
                         match parent.find_inode(&name) {
                                 Ok(_) => return Err(ErrNo::FileExists),
-                                Err(_) => {},
+                                Err(ErrNo::NoSuchFileOrDirectory) => {},
+                                Err(errno) => return Err(errno),
                         }
                         let inode = parent.new_dir(&name, 0)?;
                         return Ok(FileInner{
                                 inode,
                                 cursor: 0,
                                 mode: 0,
+                                unlinked: false,
+                                keep_clusters: false,
                         });
                 } else {
                         let inode = self.inode.new_file(&name, 0)?;
@@ -304,10 +371,50 @@ impl FileInner {
                                 inode,
                                 cursor: 0,
                                 mode: 0,
+                                unlinked: false,
+                                keep_clusters: false,
                         });
                 }
         }
 
+        /// Create an unnamed regular file in directory "path" at file "self". "self" must be a directory.
+        /// # Description
+        /// No directory entry is written for the file, its clusters live as long as the file and are freed on close.
+        pub fn mktmp(&mut self, path: Path, mode: usize) -> Result<FileInner, ErrNo> {
+                if !self.inode.is_dir() {
+                        return Err(ErrNo::NotADirectory);
+                }
+                if path.is_abs && self.inode.name.len() != 0 {
+                        return Err(ErrNo::InvalidArgument);
+                }
+                let dir = if path.path.len() == 0 {
+                        self.inode.clone()
+                } else {
+                        self.inode.find_inode_path(&path)?
+                };
+                if !dir.is_dir() {
+                        return Err(ErrNo::NotADirectory);
+                }
+                let mut dir_path = dir.path.clone();
+                if dir.name.len() > 0 {
+                        dir_path.push(dir.name.clone(), true).unwrap();
+                }
+                let chain = Chain::new(dir.chain.fs.clone(), Vec::new());
+                let inode = Inode {
+                        name: String::from("#tmpfile"),
+                        path: dir_path,
+                        group: DirEntryGroup::new("#tmpfile", 0, DirEntryRaw::ATTR_FILE),
+                        chain,
+                };
+                return Ok(FileInner {
+                        inode,
+                        cursor: 0,
+                        mode,
+                        unlinked: true,
+                        keep_clusters: false,
+                });
+        }
+
         /// Delete a regular file or empty directory file at file "self". "self" must be a directory.
         pub fn remove(&mut self, mut path: Path) -> Result<(), ErrNo> {
                 if !self.inode.is_dir() {
@@ -339,20 +446,24 @@ impl FileInner {
         }
 
         /// List all files in file "self". "self" must be a directory.
-        pub fn list(&self) -> Result<Vec<FileInner>, &'static str> {
+        /// # Return
+        /// Err(IOError) if the directory can't be read
+        pub fn list(&self) -> Result<Vec<FileInner>, ErrNo> {
                 if !self.inode.is_dir() {
-                        return Err("list: not from directory");
+                        return Err(ErrNo::NotADirectory);
                 }
                 if self.inode.is_fake() {
-                        return Err("list: fake inode");
+                        return Err(ErrNo::Fat32FakeInode);
                 }
-                let inodes = self.inode.get_inodes().unwrap();
+                let inodes = self.inode.get_inodes()?;
                 let mut files = Vec::<FileInner>::new();
                 for inode in inodes {
                         files.push(FileInner {
                                 inode,
                                 cursor: 0,
                                 mode: 0,
+                                unlinked: false,
+                                keep_clusters: false,
                         })
                 }
                 return Ok(files);
@@ -360,12 +471,18 @@ impl FileInner {
 
         /// Rename the file
         pub fn rename(&mut self, new_name: &str) -> Result<(), ErrNo> {
-                let parent = self.inode.get_parent().unwrap();
+                let parent = self.inode.get_parent()?;
                 match parent.find_inode(new_name) {
+                        // changing only the case of the name
+                        Ok(inode) if inode.group.matches(&self.inode.name) => {},
                         Ok(_) => return Err(ErrNo::FileExists),
-                        Err(_) => {},
+                        Err(ErrNo::NoSuchFileOrDirectory) => {},
+                        Err(errno) => return Err(errno),
                 }
-                self.inode.group.rename(new_name).unwrap();
+                // our own short name may be reused
+                let own = self.inode.group.entry.short_name();
+                let taken: Vec<[u8; 11]> = parent.short_names()?.into_iter().filter(|name| *name != own).collect();
+                self.inode.group.rename(new_name, &taken)?;
                 self.inode.name = String::from(new_name);
                 return Ok(());
         }
@@ -378,17 +495,45 @@ impl FileInner {
                 if self.inode.name.len() == 0 {
                         return ;
                 }
+                if self.unlinked {
+                        // nothing refers to the clusters but us
+                        if self.inode.chain.chain.len() != 0 {
+                                if self.inode.chain.fs.clear_chain(self.inode.chain.chain[0]).is_err() {
+                                        error!("Failed to free clusters of unlinked {}, they are leaked", self.inode.name);
+                                }
+                                self.inode.chain.chain.clear();
+                                self.inode.set_size(0);
+                        }
+                        return ;
+                }
                 if !self.inode.is_dir() {
                         if self.inode.group.get_start() == 0 && self.inode.chain.chain.len() != 0 {
                                 self.inode.group.entry.set_start(self.inode.chain.chain[0]);
                         }
-                        let csize = self.inode.chain.fs.cluster_size();
-                        let clen = (self.inode.get_size() + csize - 1) / csize;
-                        self.inode.chain.truncate(clen).unwrap();
+                        if !self.keep_clusters {
+                                let csize = self.inode.chain.fs.cluster_size();
+                                let clen = (self.inode.get_size() + csize - 1) / csize;
+                                if self.inode.chain.truncate(clen).is_err() {
+                                        error!("Failed to free clusters beyond the end of {}", self.inode.name);
+                                }
+                        }
+                        if self.inode.chain.chain.len() == 0 {
+                                self.inode.group.entry.set_start(0);
+                        }
+                }
+                let mut parent = match self.inode.get_parent() {
+                        Ok(parent) => parent,
+                        Err(errno) => {
+                                error!("Failed to flush dirent of {}, parent not found: {:?}", self.inode.name, errno);
+                                return ;
+                        }
+                };
+                if let Err(errno) = write_dirent_group(&mut parent.chain, &mut self.inode.group) {
+                        error!("Failed to flush dirent of {}: {:?}", self.inode.name, errno);
+                }
+                if self.inode.chain.fs.sync().is_err() {
+                        error!("Failed to write back {}: I/O error", self.inode.name);
                 }
-                let mut parent = self.inode.get_parent().unwrap();
-                write_dirent_group(&mut parent.chain, &mut self.inode.group).unwrap();
-                self.inode.chain.fs.sync();
         }
 
         /// If the file is readable
@@ -419,6 +564,11 @@ impl FileInner {
                 self.inode.group.entry.created_minisec as usize * 1000000usize
         }
 
+        /// Get modification time (sec) of the file
+        pub fn mod_time_sec(&self) -> usize {
+                self.inode.group.entry.mod_time_sec()
+        }
+
         /// Get file size
         /// # Note
         /// File size of a directory file is 0
@@ -446,6 +596,17 @@ impl FileInner {
         pub fn fmode(&self) -> usize {
                 self.mode
         }
+
+        /// Get permission bits of the file, mapped from FAT attributes
+        /// # Note
+        /// Fat32 has no owner or permission, read-only attribute clears the write bits.
+        pub fn perm(&self) -> u32 {
+                if self.inode.group.entry.is_read_only() {
+                        0o555
+                } else {
+                        0o777
+                }
+        }
 }
 
 fn open_d(parent: &mut Inode, name: &str, mode:usize, dir_flag: bool, no_follow: bool) -> Result<FileInner, ErrNo> {
@@ -462,7 +623,7 @@ fn open_d(parent: &mut Inode, name: &str, mode:usize, dir_flag: bool, no_follow:
                                         Ok(path) => path,
                                         Err(err) => return Err(ErrNo::InvalidArgument),
                                 };
-                                let root = Inode::root(parent.chain.fs.clone());
+                                let root = Inode::root(parent.chain.fs.clone())?;
                                 let mut root = FileInner::new(root, 0);
                                 return root.open(path, mode);
                         }
@@ -477,7 +638,7 @@ fn open_d(parent: &mut Inode, name: &str, mode:usize, dir_flag: bool, no_follow:
                         }
                         return Ok(FileInner::new(inode, mode));
                 },
-                Err(_) => {
+                Err(ErrNo::NoSuchFileOrDirectory) => {
                         if mode & CREATE != 0 {
                                 if dir_flag {
                                         match parent.new_dir(&name, 0) {
@@ -501,6 +662,7 @@ fn open_d(parent: &mut Inode, name: &str, mode:usize, dir_flag: bool, no_follow:
                         } else {
                                 return Err(ErrNo::NoSuchFileOrDirectory);
                         }
-                }
+                },
+                Err(errno) => return Err(errno),
         }
 }
\ No newline at end of file
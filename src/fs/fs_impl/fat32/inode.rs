@@ -37,13 +37,15 @@ impl Inode {
         /// Creates a virtual inode for root directory
         /// # Note
         /// Since there is no dirent refer to root directory, we need to create a virtual one.
-        pub fn root(fs: Arc<Fat32FS>) -> Inode {
-                Inode {
-                        chain: Chain::root(fs).unwrap(),
+        /// # Return
+        /// Err(IOError) if the chain of root directory can't be read
+        pub fn root(fs: Arc<Fat32FS>) -> Result<Inode, ErrNo> {
+                Ok(Inode {
+                        chain: Chain::root(fs)?,
                         path: Path::root(),
                         group: DirEntryGroup::root(),
                         name: String::from(""),
-                }
+                })
         }
 
         /// If the inode is a symbolic link
@@ -106,16 +108,18 @@ impl Inode {
         }
 
         /// Get all the inodes in the diretory inode "self".
-        pub fn get_inodes(&self) -> Result<Vec<Inode>, &'static str> {
+        /// # Return
+        /// Err(IOError) if the directory can't be read, instead of a truncated list
+        pub fn get_inodes(&self) -> Result<Vec<Inode>, ErrNo> {
                 if !self.group.entry.is_dir() {
-                        return Err("get_inodes: not a directory");
+                        return Err(ErrNo::NotADirectory);
                 }
                 let mut offset = 0;
                 let mut inodes = Vec::<Inode>::new();
                 loop {
                         match read_dirent_group(&self.chain, offset) {
                                 Ok((group, next)) => {
-                                        let c = Chain::new(self.chain.fs.clone(), self.chain.fs.get_chain(group.get_start()));
+                                        let c = Chain::new(self.chain.fs.clone(), self.chain.fs.get_chain(group.get_start())?);
                                         let mut path = self.path.clone();
                                         if self.name.len() > 0 {
                                                 path.push(self.name.clone(), true).unwrap();
@@ -130,6 +134,7 @@ impl Inode {
                                         );
                                         offset = next;
                                 },
+                                Err(ErrNo::IOError) => return Err(ErrNo::IOError),
                                 Err(_) => return Ok(inodes),
                         }
 
@@ -137,6 +142,8 @@ impl Inode {
         }
 
         /// Find a inode in the diretory inode "self" by name.
+        /// # Return
+        /// Err(NoSuchFileOrDirectory) if there is no such entry, Err(IOError) if the directory can't be read
         pub fn find_inode(&self, name: &str) -> Result<Inode, ErrNo> {
                 if !self.group.entry.is_dir() {
                         return Err(ErrNo::NotADirectory);
@@ -147,8 +154,8 @@ impl Inode {
                                 Ok((group, next)) => {
                                         let iname = group.get_name().unwrap();
                                         debug!("find_inode: {} vs {}", name, iname);
-                                        if name.eq(&iname) {
-                                                let c = Chain::new(self.chain.fs.clone(), self.chain.fs.get_chain(group.get_start()));
+                                        if group.matches(name) {
+                                                let c = Chain::new(self.chain.fs.clone(), self.chain.fs.get_chain(group.get_start())?);
                                                 let mut p = self.path.clone();
                                                 if self.name.len() > 0 {
                                                         p.push(self.name.clone(), true).unwrap();
@@ -162,6 +169,7 @@ impl Inode {
                                         }
                                         offset = next;
                                 },
+                                Err(ErrNo::IOError) => return Err(ErrNo::IOError),
                                 Err(_) => return Err(ErrNo::NoSuchFileOrDirectory),
                         }
 
@@ -193,7 +201,7 @@ impl Inode {
 
         /// Get the parent inode of inode "self"
         pub fn get_parent(&self) -> Result<Inode, ErrNo> {
-                let root = Inode::root(self.chain.fs.clone());
+                let root = Inode::root(self.chain.fs.clone())?;
                 if self.path.path.len() == 0 {
                         return Ok(root);
                 } else {
@@ -207,7 +215,26 @@ impl Inode {
                         return Err("realize: not fake inode");
                 }
                 self.path.purge().map_err(|err| -> &str {"Path format error"})?;
-                return Ok(Inode::root(self.chain.fs.clone()).find_inode_path(&self.path).unwrap());
+                let root = Inode::root(self.chain.fs.clone()).map_err(|_| "realize: can't read root")?;
+                return Ok(root.find_inode_path(&self.path).unwrap());
+        }
+
+        /// Short names of all the entries in the directory inode "self"
+        /// # Return
+        /// Err(IOError) if the directory can't be read
+        pub fn short_names(&self) -> Result<Vec<[u8; 11]>, ErrNo> {
+                let mut names = Vec::new();
+                let mut offset = 0;
+                loop {
+                        match read_dirent_group(&self.chain, offset) {
+                                Ok((group, next)) => {
+                                        names.push(group.entry.short_name());
+                                        offset = next;
+                                },
+                                Err(ErrNo::IOError) => return Err(ErrNo::IOError),
+                                Err(_) => return Ok(names),
+                        }
+                }
         }
 
         /// Create a new inode in the directory inode "self"
@@ -223,8 +250,8 @@ impl Inode {
                 } else {
                         chain.chain[0]
                 };
-                let mut group = DirEntryGroup::new(name, start, attr);
-                write_dirent_group(&mut self.chain, &mut group).unwrap();
+                let mut group = DirEntryGroup::new_unique(name, start, attr, &self.short_names()?)?;
+                write_dirent_group(&mut self.chain, &mut group)?;
                 let mut path = self.path.clone();
                 if self.name.len() > 0 {
                         path.push(self.name.clone(), true).unwrap();
@@ -237,17 +264,22 @@ impl Inode {
         pub fn new_dir(&mut self, name: &str, attr:u8) -> Result<Inode, ErrNo> {
                 let attr = attr | DirEntryRaw::ATTR_SUBDIR;
                 let mut chain = Vec::new();
-                chain.push(self.chain.fs.alloc_cluster().unwrap());
+                chain.push(self.chain.fs.alloc_cluster()?);
                 let chain = Chain::new(self.chain.fs.clone(), chain);
                 let mut nd = match self.new(name, chain.clone(), attr) {
                         Ok(inode) => inode,
                         Err(errno) => {
-                                self.chain.fs.clear_chain(chain.chain[0]).unwrap();
+                                if self.chain.fs.clear_chain(chain.chain[0]).is_err() {
+                                        error!("Fat32: cluster {} is leaked", chain.chain[0]);
+                                }
                                 return Err(errno)
                         },
                 };
-                nd.new(".", chain, DirEntryRaw::ATTR_SUBDIR).unwrap();
-                nd.new("..", self.chain.clone(), DirEntryRaw::ATTR_SUBDIR).unwrap();
+                // "." points at the new directory, ".." at "self", whose entry start is 0 for root as FAT requires
+                let mut cur = DirEntryGroup::dot(".", chain.chain[0]);
+                write_dirent_group(&mut nd.chain, &mut cur)?;
+                let mut par = DirEntryGroup::dot("..", self.group.get_start());
+                write_dirent_group(&mut nd.chain, &mut par)?;
                 return Ok(nd);
         }
 
@@ -260,29 +292,38 @@ impl Inode {
         }
 
         /// Delete a new inode in the directory inode "self"
+        /// # Return
+        /// Err(DirectoryNotEmpty) for a directory with entries other than "." and "..",
+        /// Err(InvalidArgument) for "." and ".." themselves.
         pub fn delete_inode(&mut self, name: &String) -> Result<(), ErrNo> {
                 if !self.group.entry.is_dir() {
                         return Err(ErrNo::NotADirectory);
                 }
+                if name == "." || name == ".." {
+                        return Err(ErrNo::InvalidArgument);
+                }
                 let mut offset = 0;
                 loop {
                         match read_dirent_group(&self.chain, offset) {
                                 Ok((group, next)) => {
-                                        let iname = group.get_name().unwrap();
-                                        if name.eq(&iname) {
+                                        if group.matches(name) {
                                                 if group.entry.is_dir() {
-                                                        let chain = self.chain.fs.get_chain(group.get_start());
+                                                        let chain = self.chain.fs.get_chain(group.get_start())?;
                                                         let chain = Chain::new(self.chain.fs.clone(), chain);
-                                                        if !empty_dir(&chain) {
+                                                        if !empty_dir(&chain)? {
                                                                 return Err(ErrNo::DirectoryNotEmpty);
                                                         }
                                                 } 
-                                                self.chain.fs.clear_chain(group.get_start()).unwrap();
-                                                delete_dirent_group(&mut self.chain, offset).unwrap();
+                                                // entry first, a failure afterwards only leaks clusters
+                                                delete_dirent_group(&mut self.chain, offset)?;
+                                                if self.chain.fs.clear_chain(group.get_start()).is_err() {
+                                                        error!("Fat32: clusters of {} are leaked", name);
+                                                }
                                                 return Ok(());
                                         }
                                         offset = next;
                                 },
+                                Err(ErrNo::IOError) => return Err(ErrNo::IOError),
                                 Err(_) => return Err(ErrNo::NoSuchFileOrDirectory),
                         }
 
@@ -20,11 +20,14 @@ use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 use super::cache_mgr::BlockCacheManager;
+use super::cache_mgr::blkcache::BlockCache;
+use spin::Mutex;
 use super::cache_mgr::BLOCK_SZ;
 
 use super::BlockDeviceFile;
 use super::super::Path;
 use crate::process::ErrNo;
+use crate::drivers::IoError;
 
 use core::mem::size_of;
 
@@ -67,7 +70,7 @@ impl Fat32FS {
         /// Load Fat32 from device
         pub fn openFat32(device: Arc<dyn BlockDeviceFile>) -> Fat32FS {
                 let mut mgr = BlockCacheManager::new(device);
-                let raw_dbr = mgr.get_block_cache(0).lock().get_ref::<RAW_DBR>(0).clone();
+                let raw_dbr = mgr.get_block_cache(0).expect("get_dbr: I/O error").lock().get_ref::<RAW_DBR>(0).clone();
                 if raw_dbr.sign[0] != 0x55 || raw_dbr.sign[1] != 0xAA {
                         panic!("get_dbr: Invalid dbr");
                 }
@@ -91,7 +94,7 @@ impl Fat32FS {
                         return None;
                 }
                 let cluster = cluster - self.dbr.root;
-                if cluster > self.dbr.clst_cnt || offset as u32 > self.dbr.clst_size {
+                if cluster > self.dbr.clst_cnt || offset as u32 >= self.dbr.clst_size {
                         return None;
                 }
                 let mut sector: u32 = self.dbr.data_sec_base + self.dbr.clst_sec * cluster;
@@ -101,14 +104,16 @@ impl Fat32FS {
 
         /// Fill the buf with the contents in the cluster that starts from the offset
         /// # Return
-        /// Returns Err if cluster or offset is invalid, 
+        /// Returns Err(Fat32InvalidOffset) if cluster or offset is invalid, Err(IOError) if the disk can't be read,
         /// else return # of bytes that actually read. 
-        pub fn read_cluster(&self, cluster: u32, offset: usize, buf: &mut [u8]) ->Result<usize, &'static str> {
+        pub fn read_cluster(&self, cluster: u32, offset: usize, buf: &mut [u8]) ->Result<usize, ErrNo> {
                 if cluster >= self.dbr.clst_cnt {
-                        return Err("read_cluster: Invalid cluster");
+                        error!("read_cluster: Invalid cluster {}", cluster);
+                        return Err(ErrNo::Fat32InvalidOffset);
                 }
                 if offset as u32 >= self.dbr.clst_size {
-                        return Err("read_cluster: Invalid Offset");
+                        error!("read_cluster: Invalid Offset {}", offset);
+                        return Err(ErrNo::Fat32InvalidOffset);
                 }
                 
                 let mut len = buf.len();
@@ -117,7 +122,7 @@ impl Fat32FS {
                 while len > 0 {
                         let block = self.get_cluster_cache(cluster, offset).unwrap();
                         let off = offset as usize % BLOCK_SZ;
-                        let cache = self.inner.borrow_mut().mgr.get_block_cache(block as usize);
+                        let cache = self.inner.borrow_mut().mgr.get_block_cache(block as usize)?;
                         let rlen = BLOCK_SZ - (offset % BLOCK_SZ);
                         let rlen = if rlen > len {len} else {rlen};
                         for i in 0..rlen as usize {
@@ -135,14 +140,16 @@ impl Fat32FS {
 
         /// Write the buf into the cluster , writing starts from the offset
         /// # Return
-        /// Returns Err if cluster or offset is invalid, 
+        /// Returns Err(Fat32InvalidOffset) if cluster or offset is invalid, Err(IOError) if the disk can't be read,
         /// else return # of bytes that are actually written. 
-        pub fn write_cluster(&self, cluster: u32, offset: usize, buf: &[u8]) -> Result<usize, &'static str> {
+        pub fn write_cluster(&self, cluster: u32, offset: usize, buf: &[u8]) -> Result<usize, ErrNo> {
                 if cluster >= self.dbr.clst_cnt {
-                        return Err("write_cluster: Invalid cluster");
+                        error!("write_cluster: Invalid cluster {}", cluster);
+                        return Err(ErrNo::Fat32InvalidOffset);
                 }
                 if offset as u32 >= self.dbr.clst_size {
-                        return Err("write_cluster: Invalid Offset");
+                        error!("write_cluster: Invalid Offset {}", offset);
+                        return Err(ErrNo::Fat32InvalidOffset);
                 }
         
                 let mut len = buf.len();
@@ -151,7 +158,7 @@ impl Fat32FS {
                 while len > 0 {
                         let block = self.get_cluster_cache(cluster, offset).unwrap();
                         let off = offset as usize % BLOCK_SZ;
-                        let cache = self.inner.borrow_mut().mgr.get_block_cache(block as usize).clone();
+                        let cache = self.inner.borrow_mut().mgr.get_block_cache(block as usize)?;
                         let wlen = BLOCK_SZ - (offset % BLOCK_SZ);
                         let wlen = if wlen > len {len} else {wlen};
                         for i in 0..wlen as usize {
@@ -168,71 +175,119 @@ impl Fat32FS {
         }
 
         /// Reset the content of the cluster to 0
+        /// # Note
+        /// Blocks are zeroed lazily, a freshly allocated cluster costs no disk write
+        /// until it is actually written or the fs is flushed.
         pub fn clear_cluster(&self, cluster:u32) -> Result<(), &'static str> {
                 if cluster >= self.dbr.clst_cnt {
                         return Err("clear_cluster: Invalid cluster");
                 } 
                 if let Some(block) = self.get_cluster_cache(cluster, 0) {
                         for i in 0..(self.dbr.clst_size / BLOCK_SZ as u32) {
-                                self.inner.borrow_mut().mgr.clear_block_cache((block+i) as usize);
+                                self.inner.borrow_mut().mgr.zero_block_cache((block+i) as usize);
                         }
                 }
                 return Ok(());
         }
 
-        fn get_next_clst(&self, clst_num: u32) -> Option<u32> {
+        /// Write the cached blocks of "cluster" back to the device
+        /// # Description
+        /// Write barrier for new data: call before the FAT link to the cluster is written.
+        pub fn flush_cluster(&self, cluster: u32) -> Result<(), ErrNo> {
+                if let Some(block) = self.get_cluster_cache(cluster, 0) {
+                        for i in 0..(self.dbr.clst_size / BLOCK_SZ as u32) {
+                                self.inner.borrow_mut().mgr.flush_block((block + i) as usize)?;
+                        }
+                }
+                return Ok(());
+        }
+
+        /// Read the FAT entry of "clst_num"
+        /// # Return
+        /// Err(IOError) if the FAT can't be read or "clst_num" is out of the FAT
+        fn get_next_clst(&self, clst_num: u32) -> Result<u32, ErrNo> {
                 if clst_num >= self.fat1.len {
-                        return None;
+                        error!("Fat32: cluster {} out of the FAT", clst_num);
+                        return Err(ErrNo::IOError);
                 } 
                 let block_id = clst_num / self.fat1.clen + self.fat1.start;
                 let offset = clst_num % self.fat1.clen * size_of::<u32>() as u32;
                 // debug!("get_next: getting block cache");
-                let next = *self.inner.borrow_mut().mgr.get_block_cache(block_id as usize).lock().get_ref::<u32>(offset as usize);
-                Some(next)
+                let next = *self.fat_block(block_id)?.lock().get_ref::<u32>(offset as usize);
+                Ok(next)
         }
 
-        fn write_next_clst(&self, clst_num: u32, next: u32) -> Result<(),()> {
+        /// Cache of FAT block "block_id"
+        fn fat_block(&self, block_id: u32) -> Result<Arc<Mutex<BlockCache>>, ErrNo> {
+                self.inner.borrow_mut().mgr.get_block_cache(block_id as usize).map_err(|_| {
+                        error!("Fat32: I/O error on FAT block {}", block_id);
+                        ErrNo::IOError
+                })
+        }
+
+        /// Write the FAT entry of "clst_num" in both FATs
+        /// # Return
+        /// Err(IOError) if the FAT can't be read or "clst_num" is out of the FAT
+        fn write_next_clst(&self, clst_num: u32, next: u32) -> Result<(), ErrNo> {
                 if clst_num >= self.fat1.len {
-                        return Err(());
+                        error!("Fat32: cluster {} out of the FAT", clst_num);
+                        return Err(ErrNo::IOError);
                 }
                 let block_id = clst_num / self.fat1.clen + self.fat1.start;
                 let offset = clst_num % self.fat1.clen * size_of::<u32>() as u32;
-                *self.inner.borrow_mut().mgr.get_block_cache(block_id as usize).lock().get_mut::<u32>(offset as usize) = next;
+                *self.fat_block(block_id)?.lock().get_mut::<u32>(offset as usize) = next;
                 let block_id = block_id + self.dbr.fat_sec;
-                *self.inner.borrow_mut().mgr.get_block_cache(block_id as usize).lock().get_mut::<u32>(offset as usize) = next;
+                *self.fat_block(block_id)?.lock().get_mut::<u32>(offset as usize) = next;
                 return Ok(());
         }
 
+        /// Count the data clusters
+        /// # Description
+        /// Walks the whole FAT, there is no FSInfo sector support.
+        /// # Return
+        /// (total, free) as (dbr.clst_cnt - 2, free entries in the FAT), data clusters are numbered from 2
+        pub fn cluster_stats(&self) -> (usize, usize) {
+                let end = core::cmp::min(self.dbr.clst_cnt, self.fat1.len);
+                let free = (2..end).filter(|i| {
+                        self.get_next_clst(*i).map_or(false, |next| fat::get_type(next) == CLUSTER::Free)
+                }).count();
+                (self.dbr.clst_cnt.saturating_sub(2) as usize, free)
+        }
+
         /// Allocate a free cluster
-        pub fn alloc_cluster(&self) -> Result<u32, &'static str> {
+        /// # Return
+        /// Err(NoSpaceLeftOnDevice) if there is no free cluster, Err(IOError) if the FAT can't be accessed
+        pub fn alloc_cluster(&self) -> Result<u32, ErrNo> {
                 let mut new = 0;
                 for i in 2..self.dbr.clst_cnt {
-                        if fat::get_type(self.get_next_clst(i).unwrap()) == CLUSTER::Free {
+                        if fat::get_type(self.get_next_clst(i)?) == CLUSTER::Free {
                                 new = i;
                                 break;
                         }
                 }
                 if new != 0 {
-                        self.write_next_clst(new, 0x0FFF_FFFF).unwrap();
+                        self.write_next_clst(new, 0x0FFF_FFFF)?;
                         self.clear_cluster(new).unwrap();
                         return Ok(new);
                 } else {
-                        return Err("get_free_cluster: no free cluster found");
+                        return Err(ErrNo::NoSpaceLeftOnDevice);
                 }
         }
 
         /// Get the file chain starts from "start"
-        pub fn get_chain(&self, start: u32) -> Vec<u32> {
+        /// # Return
+        /// Err(IOError) if the FAT can't be read or the chain runs out of the FAT
+        pub fn get_chain(&self, start: u32) -> Result<Vec<u32>, ErrNo> {
                 let mut vec = Vec::new();
                 if start < 2 {
-                        return vec;
+                        return Ok(vec);
                 }
                 let mut cluster = start;
-                let mut t = fat::get_type(self.get_next_clst(cluster).unwrap());
+                let mut t = fat::get_type(self.get_next_clst(cluster)?);
                 while match t {
                         CLUSTER::Data => {
                                 vec.push(cluster);
-                                cluster = self.get_next_clst(cluster).unwrap();
+                                cluster = self.get_next_clst(cluster)?;
                                 true
                         },
                         CLUSTER::Eoc => {
@@ -244,82 +299,116 @@ impl Fat32FS {
                                 false
                         }
                 } { 
-                        if let Some(nxt_clst) = self.get_next_clst(cluster) {
-                                t = fat::get_type(nxt_clst);
-                        } else {
-                                panic!("Reached end of clustor, but priv node is not EoC, cluster={}", cluster);
-                        }
+                        t = fat::get_type(self.get_next_clst(cluster)?);
                 }
-                return vec
+                return Ok(vec);
         }
 
         /// Release the chain starts from "start"
-        pub fn clear_chain(&self, start: u32) -> Result<(),()> {
+        /// # Return
+        /// Err(IOError) if the FAT can't be accessed or the chain is broken,
+        /// clusters before the failing one are released.
+        pub fn clear_chain(&self, start: u32) -> Result<(), ErrNo> {
                 if start == 0 {
                         return Ok(());
                 }
                 let mut cur = start;
                 loop {
-                        let next = self.get_next_clst(cur).unwrap();
+                        let next = self.get_next_clst(cur)?;
                         match fat::get_type(next) {
                                 CLUSTER::Data => {
-                                        self.write_next_clst(cur,0).unwrap();
+                                        self.write_next_clst(cur,0)?;
                                         cur = next;
                                 },
                                 CLUSTER::Eoc => {
-                                        self.write_next_clst(cur, 0).unwrap();
+                                        self.write_next_clst(cur, 0)?;
                                         return Ok(());
                                 }
                                 _ => {
-                                        panic!("clean_file_chain: ?");
+                                        error!("Fat32: broken chain at cluster {}", cur);
+                                        return Err(ErrNo::IOError);
                                 }
                         }
                 }
         }
 
+        /// Make "new", allocated by alloc_cluster, follow "end" which must end a chain
+        /// # Return
+        /// Err(IOError) if the FAT can't be accessed
+        pub fn link_cluster(&self, end: u32, new: u32) -> Result<(), ErrNo> {
+                self.write_next_clst(end, new)
+        }
+
         /// Append a cluster to the chain ends at "end"
-        pub fn append_chain(&self, end: u32) -> Result<u32, &'static str> {
-                let end = match fat::get_type(self.get_next_clst(end).unwrap()) {
+        /// # Note
+        /// The new cluster is linked at once, its data is not ordered against the link.
+        /// Writers of new data use alloc_cluster, flush_cluster and link_cluster instead.
+        /// # Return
+        /// Err(NoSpaceLeftOnDevice) if there is no free cluster, Err(InvalidArgument) if "end" is not in a chain,
+        /// Err(IOError) if the FAT can't be accessed
+        pub fn append_chain(&self, end: u32) -> Result<u32, ErrNo> {
+                let end = match fat::get_type(self.get_next_clst(end)?) {
                         CLUSTER::Eoc => end,
-                        CLUSTER::Data => self.get_chain(end).pop().unwrap(),
-                        _ => return Err("append_cluster: not a chain\n"),
+                        CLUSTER::Data => self.get_chain(end)?.pop().unwrap(),
+                        _ => return Err(ErrNo::InvalidArgument),
                 };
+                let new = self.alloc_cluster()?;
+                self.write_next_clst(end, new)?;
+                return Ok(new);
+        }
 
-                if let Ok(new) = self.alloc_cluster() {
-                        self.write_next_clst(end, new).unwrap();
-                        return Ok(new);
-                } else {
-                        return Err("append_cluster: cannot find free cluster");
+        /// Truncate a chain, make "start" the last cluster of the chain.
+        pub fn truncate_chain(&self, start: u32) -> Result<(), ErrNo> {
+                self.clear_chain(start)?;
+                self.write_next_clst(start, 0x0FFF_FFFF)
+        }
+
+        /// Flush all the cache in Block Cache Manager
+        /// # Return
+        /// Err(IoError) if some block failed to be written, it stays dirty
+        pub fn sync(&self) -> Result<(), IoError> {
+                self.inner.borrow_mut().mgr.flush_all()
+        }
+
+        /// Flush the caches that are not in use
+        /// # Return
+        /// false if the file system or some cache is busy
+        pub fn try_sync(&self) -> bool {
+                match self.inner.try_borrow_mut() {
+                        Ok(mut inner) => inner.mgr.try_flush_all(),
+                        Err(_) => false,
                 }
         }
 
-        /// Truncate a chain, make "start" the last cluster of the chain.
-        pub fn truncate_chain(&self, start: u32) -> Result<(), ()> {
-                match self.clear_chain(start) {
-                        Ok(()) => {
-                                self.write_next_clst(start, 0x0FFF_FFFF).unwrap();
-                                return Ok(());
-                        },
-                        _ => {
-                                return Err(());
-                        }
+        /// Flush caches dirty since "deadline" or earlier, at most "budget" of them
+        /// # Return
+        /// # of blocks written, 0 if the fs is busy
+        pub fn flush_older(&self, deadline: u64, budget: usize) -> usize {
+                match self.inner.try_borrow_mut() {
+                        Ok(inner) => inner.mgr.flush_older(deadline, budget),
+                        Err(_) => 0,
                 }
         }
 
-        /// Flush all the cache in Block Cache Manager
-        pub fn sync(&self) {
-                self.inner.borrow_mut().mgr.flush_all();
+        /// Release clean block caches nobody is using
+        /// # Return
+        /// # of caches released, 0 if the fs is busy
+        pub fn drop_clean_caches(&self) -> usize {
+                match self.inner.try_borrow_mut() {
+                        Ok(mut inner) => inner.mgr.drop_clean(),
+                        Err(_) => 0,
+                }
         }
 }
 
 /// Create a virtual file of the root directory
-fn root_dir(fs: Arc<Fat32FS>) -> FileInner {
-        return FileInner::new(Inode::root(fs), 0); 
+fn root_dir(fs: Arc<Fat32FS>) -> Result<FileInner, ErrNo> {
+        return Ok(FileInner::new(Inode::root(fs)?, 0)); 
 }
 
 /// Open file/directory
 pub fn open(fs: Arc<Fat32FS>, abs_path: Path, mode: usize) -> Result<FileInner, ErrNo> {
-        let mut root = root_dir(fs);
+        let mut root = root_dir(fs)?;
         if abs_path == Path::root() {
                 return Ok(root);
         } else {
@@ -329,19 +418,25 @@ pub fn open(fs: Arc<Fat32FS>, abs_path: Path, mode: usize) -> Result<FileInner,
 
 /// Create directory
 pub fn mkdir(fs: Arc<Fat32FS>, abs_path: Path) -> Result<FileInner, ErrNo> {
-        let mut root = root_dir(fs);
+        let mut root = root_dir(fs)?;
         return root.mkdir(abs_path);
 }
 
 /// Create a file
 pub fn mkfile(fs: Arc<Fat32FS>, abs_path: Path) -> Result<FileInner, ErrNo> {
-        let mut root = root_dir(fs);
+        let mut root = root_dir(fs)?;
         return root.mkfile(abs_path);
 }
 
+/// Create an unnamed temporary file in directory "abs_dir"
+pub fn mktmp(fs: Arc<Fat32FS>, abs_dir: Path, mode: usize) -> Result<FileInner, ErrNo> {
+        let mut root = root_dir(fs)?;
+        return root.mktmp(abs_dir, mode);
+}
+
 /// Delete a file
 pub fn remove(fs: Arc<Fat32FS>, abs_path: Path) -> Result<(), ErrNo> {
-        let mut root = root_dir(fs);
+        let mut root = root_dir(fs)?;
         return root.remove(abs_path);
 }
 
@@ -381,7 +476,14 @@ pub fn print_file_tree(root: &Inode, indent: usize) {
                 for _i in 0..indent {
                         indent_s += "    ";
                 }
-                for inode in root.get_inodes().unwrap() {
+                let inodes = match root.get_inodes() {
+                        Ok(inodes) => inodes,
+                        Err(errno) => {
+                                println!("{}(can't list: {:?})", indent_s, errno);
+                                return;
+                        }
+                };
+                for inode in inodes {
                         print!("{}", indent_s);
                         inode.print();
                         if inode.is_dir() && !inode.is_cur() && !inode.is_par() {
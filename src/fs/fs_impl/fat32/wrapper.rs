@@ -1,7 +1,7 @@
 //! Wrapper of Fat32File to implement the crate::fs::file::File trait.
 use alloc::{sync::Arc, vec::Vec};
 use spin::Mutex;
-use crate::fs::{CommonFile, DeviceFile, DirFile, File};
+use crate::fs::{CommonFile, DeviceFile, DirFile, File, release_dropped_flocks};
 use crate::fs::{file::FileStatus, fs_impl::cache_mgr::BLOCK_SZ};
 use crate::fs::fs_impl::fat32_wrapper::Fat32W;
 use crate::fs::fs_impl::vfs::OpenMode;
@@ -20,6 +20,7 @@ unsafe impl Sync for FAT32File {}
 impl Drop for FAT32File {
 	fn drop(&mut self) {
 		self.inner.lock().close();
+		release_dropped_flocks();
 	}
 }
 
@@ -80,15 +81,15 @@ impl File for FAT32File {
 			// TODO: inode number
 			inode: 0,
 			dev_no: 0,
-			mode: inner.fmode() as u32,
+			mode: inner.perm(),
 			block_sz: BLOCK_SZ as u32,
 			blocks: (inner.size() / BLOCK_SZ) as u64,
 			uid: 0,
 			gid: 0,
 			atime_sec: inner.last_acc_time_sec() as u32,
 			atime_nsec: 0,
-			mtime_sec: inner.create_time_sec() as u32,
-			mtime_nsec: inner.create_time_nsec() as u32,
+			mtime_sec: inner.mod_time_sec() as u32,
+			mtime_nsec: 0,
 			ctime_sec: inner.create_time_sec() as u32,
 			ctime_nsec: inner.create_time_nsec() as u32,
 		}
@@ -107,7 +108,11 @@ impl File for FAT32File {
     }
 }
 
-impl CommonFile for FAT32File {}
+impl CommonFile for FAT32File {
+    fn fallocate(&self, mode: usize, offset: usize, len: usize) -> Result<(), ErrNo> {
+        self.inner.lock().fallocate(mode, offset, len)
+    }
+}
 
 impl DirFile for FAT32File {
         /// open files under dir
@@ -157,7 +162,10 @@ impl DirFile for FAT32File {
             let mut result = Vec::<Arc<dyn File>>::new();
             let files = match self.inner.lock().list() {
                 Ok(f) => f,
-                Err(msg) => return result,
+                Err(errno) => {
+                    error!("Fat32: can't list directory: {:?}", errno);
+                    return result;
+                },
             };
             for file in files {
                 result.push(Arc::new(
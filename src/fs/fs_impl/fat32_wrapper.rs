@@ -42,15 +42,47 @@ impl Fat32W {
 impl VirtualFileSystem for Fat32W {
         /// force write back all dirty
         fn sync(&self, wait: bool) {
-                self.inner.sync();
+                if self.inner.sync().is_err() {
+                        error!("Fat32: sync failed, dirty blocks are kept");
+                }
+        }
+
+        fn try_sync(&self) -> bool {
+                self.inner.try_sync()
+        }
+
+        /// write back blocks dirty for too long
+        fn flush_older(&self, deadline: u64, budget: usize) -> usize {
+                self.inner.flush_older(deadline, budget)
+        }
+
+        /// release clean block caches
+        fn drop_clean_caches(&self) -> usize {
+                self.inner.drop_clean_caches()
         }
 
         /// get status
         fn get_status(&self) -> FSStatus {
-                return FSStatus {
-                        name: Fat32FS::name,
-                        flags: FSFlags::empty(),
-                }
+                return FSStatus::new(Fat32FS::name, FSFlags::empty());
+        }
+
+        /// clusters counted from the DBR and the FAT
+        fn statfs(&self) -> FSStatus {
+                let (total, free) = self.inner.cluster_stats();
+                let mut status = self.get_status();
+                // MSDOS_SUPER_MAGIC
+                status.magic = 0x4d44;
+                status.block_size = self.inner.cluster_size();
+                status.total_blocks = total;
+                status.free_blocks = free;
+                status.avail_blocks = free;
+                // long file names
+                status.name_max = 255;
+                status
+        }
+
+        fn fs_id(&self) -> usize {
+                Arc::as_ptr(&self.inner) as usize
         }
 
         // ==================== file level ops ====================
@@ -92,6 +124,18 @@ impl VirtualFileSystem for Fat32W {
                 }
         }
 
+        fn mktmp(&self, abs_dir: Path, mode: OpenMode) -> Result<Arc<dyn File>, ErrNo> {
+                let mode = OpenMode2usize(mode);
+                match fat32::mktmp(self.inner.clone(), abs_dir, mode) {
+                        Ok(file) => return Ok(Arc::new(
+                                FAT32File {
+                                        inner: Mutex::new(file)
+                                }
+                        )),
+                        Err(msg) => return Err(msg),
+                }
+        }
+
         fn remove(&self, abs_path: Path) -> Result<(), ErrNo> {
                 return fat32::remove(self.inner.clone(), abs_path);
         }
@@ -101,7 +145,7 @@ impl VirtualFileSystem for Fat32W {
         }
 
         fn sym_link(&self, abs_src: Path, rel_dst: Path) -> Result<(), ErrNo> {
-                return fat32::sym_link(self.inner.clone(), rel_dst, abs_src);
+                return fat32::sym_link(self.inner.clone(), abs_src, rel_dst);
         }
 
         fn rename(&self, to_rename: Arc<dyn File>, new_name: String) -> Result<(), ErrNo> {
@@ -4,6 +4,11 @@ use crate::process::ErrNo;
 
 pub trait CommonFile : File {
     // fn follow_syn_link(&self) -> Arc<dyn File>;
+
+    /// Preallocate storage for [offset, offset + len) of the file
+    fn fallocate(&self, _mode: usize, _offset: usize, _len: usize) -> Result<(), ErrNo> {
+        Err(ErrNo::OperationNotSupportedOnTransportEndpoint)
+    }
 }
 pub trait DirFile : CommonFile {
     /// open files under dir
@@ -1,11 +1,14 @@
 mod vfs;
 pub mod fat32;
+pub mod ext2;
 mod cache_mgr;
 mod devfs;
 mod procfs;
+mod tmpfs;
 mod sysfs;
 mod blkdevice;
 mod fat32_wrapper;
+mod ext2_wrapper;
 mod utils;
 
 mod fs_files;
@@ -28,6 +31,14 @@ pub use fat32_wrapper::{
     Fat32W
 };
 
+pub use ext2_wrapper::{
+    Ext2W
+};
+
+pub use tmpfs::{
+    TmpFS
+};
+
 pub use procfs::{
     PROC_FS
 };
\ No newline at end of file
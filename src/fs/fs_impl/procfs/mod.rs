@@ -1,6 +1,14 @@
-use alloc::{string::ToString, sync::Arc, vec::Vec};
+use alloc::{format, string::{String, ToString}, sync::Arc, vec::Vec};
+use spin::Mutex;
 
-use crate::{fs::{File, FileStatus, Path, parse_path}, process::current_process};
+use crate::{fs::{File, FileStatus, FileType, Path, parse_path, release_dropped_flocks}, process::current_process};
+use crate::config::CLOCK_FREQ;
+use crate::process::{idle_time, last_pid, nr_processes, nr_running, nr_sleeping};
+use crate::process::stats::{context_switches, forks, user_time};
+use crate::process::loadavg::{FIXED_1, FSHIFT, load_avg};
+use crate::sbi::get_time;
+use crate::config::{FRAME_LOW_WATERMARK, KERNEL_HEAP_SIZE, PAGE_SIZE};
+use crate::memory::{free_frames, kernel_heap_capacity, kernel_heap_peak, kernel_heap_used, reclaimed_frames, slab_bytes, total_frames};
 
 use super::VirtualFileSystem;
 use crate::process::ErrNo;
@@ -11,6 +19,7 @@ pub struct ProcSelfExe {}
 
 impl Drop for ProcSelfExe {
     fn drop(&mut self) {
+        release_dropped_flocks();
     }
 }
 
@@ -97,6 +106,458 @@ impl File for ProcSelfExe {
     }
 }
 
+/// A read-only proc file holding text.
+/// # Description
+/// The content is generated when the file is opened, so a reader sees a consistent snapshot.
+pub struct ProcFile {
+    path: &'static str,
+    content: Vec<u8>,
+    cursor: Mutex<usize>,
+}
+
+impl ProcFile {
+    pub fn new(path: &'static str, content: String) -> Arc<Self> {
+        Self::from_bytes(path, content.into_bytes())
+    }
+
+    /// A proc file holding raw bytes, not necessarily text
+    pub fn from_bytes(path: &'static str, content: Vec<u8>) -> Arc<Self> {
+        Arc::new(Self {
+            path,
+            content,
+            cursor: Mutex::new(0),
+        })
+    }
+}
+
+impl Drop for ProcFile {
+    fn drop(&mut self) {
+        release_dropped_flocks();
+    }
+}
+
+impl File for ProcFile {
+    fn seek(&self, offset: isize, op: crate::fs::SeekOp) -> Result<(), ErrNo> {
+        let mut cursor = self.cursor.lock();
+        let base = match op {
+            crate::fs::SeekOp::SET => 0,
+            crate::fs::SeekOp::CUR => *cursor as isize,
+            crate::fs::SeekOp::END => self.content.len() as isize,
+        };
+        if base + offset < 0 {
+            return Err(ErrNo::InvalidArgument);
+        }
+        *cursor = (base + offset) as usize;
+        Ok(())
+    }
+
+    fn get_cursor(&self) -> Result<usize, ErrNo> {
+        Ok(*self.cursor.lock())
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, ErrNo> {
+        let mut cursor = self.cursor.lock();
+        let start = core::cmp::min(*cursor, self.content.len());
+        let len = core::cmp::min(buffer.len(), self.content.len() - start);
+        buffer[..len].copy_from_slice(&self.content[start..start + len]);
+        *cursor = start + len;
+        Ok(len)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, ErrNo> {
+        Err(ErrNo::PermissionDenied)
+    }
+
+    fn read_user_buffer(&self, mut buffer: crate::memory::UserBuffer) -> Result<usize, ErrNo> {
+        let mut cursor = self.cursor.lock();
+        let start = core::cmp::min(*cursor, self.content.len());
+        let len = core::cmp::min(buffer.len(), self.content.len() - start);
+        buffer.write_bytes(&self.content[start..start + len], 0);
+        *cursor = start + len;
+        Ok(len)
+    }
+
+    fn write_user_buffer(&self, buffer: crate::memory::UserBuffer) -> Result<usize, ErrNo> {
+        Err(ErrNo::PermissionDenied)
+    }
+
+    fn to_common_file<'a>(self: alloc::sync::Arc<Self>) -> Option<alloc::sync::Arc<dyn super::CommonFile + 'a>> where Self: 'a {
+        None
+    }
+
+    fn to_dir_file<'a>(self: alloc::sync::Arc<Self>) -> Option<alloc::sync::Arc<dyn super::DirFile + 'a>> where Self: 'a {
+        None
+    }
+
+    fn to_device_file<'a>(self: alloc::sync::Arc<Self>) -> Option<alloc::sync::Arc<dyn super::DeviceFile + 'a>> where Self: 'a {
+        None
+    }
+
+    fn poll(&self) -> crate::fs::FileStatus {
+        FileStatus {
+            readable:   true,
+            writeable:  false,
+            size:       self.content.len() as u64,
+            name:       self.path.rsplit('/').next().unwrap().to_string(),
+            ftype:      crate::fs::FileType::Regular,
+            inode:      0,
+            dev_no:     0,
+            mode:       0o444,
+            block_sz:   512,
+            blocks:     ((self.content.len() + 511) / 512) as u64,
+            uid:        0,
+            gid:        0,
+            atime_sec:  0,
+            atime_nsec: 0,
+            mtime_sec:  0,
+            mtime_nsec: 0,
+            ctime_sec:  0,
+            ctime_nsec: 0,
+        }
+    }
+
+    fn rename(&self, new_name: &str) -> Result<(), ErrNo> {
+        Err(ErrNo::ReadonlyFileSystem)
+    }
+
+    fn get_vfs(&self) -> Result<alloc::sync::Arc<dyn super::VirtualFileSystem>, ErrNo> {
+        Ok(PROC_FS.clone())
+    }
+
+    fn get_path(&self) -> crate::fs::Path {
+        parse_path(self.path).unwrap()
+    }
+}
+
+/// Paths of the open files of the current process, by fd
+fn fd_targets() -> Vec<(usize, String)> {
+    // get_path may lock, don't hold the pcb meanwhile
+    let files: Vec<(usize, Arc<dyn File>)> = current_process().unwrap().get_inner_locked().files.iter()
+        .enumerate()
+        .filter_map(|(fd, file)| file.clone().map(|file| (fd, file)))
+        .collect();
+    files.into_iter().map(|(fd, file)| (fd, file.get_path().to_string())).collect()
+}
+
+/// /proc/self/fd/N, a symlink to the path of fd N
+/// # Description
+/// The target is taken when the link is opened.
+pub struct ProcFdLink {
+    fd: usize,
+    target: String,
+}
+
+impl Drop for ProcFdLink {
+    fn drop(&mut self) {
+        release_dropped_flocks();
+    }
+}
+
+impl File for ProcFdLink {
+    fn seek(&self, _offset: isize, _op: crate::fs::SeekOp) -> Result<(), ErrNo> {
+        Err(ErrNo::IllegalSeek)
+    }
+
+    fn get_cursor(&self) -> Result<usize, ErrNo> {
+        Err(ErrNo::IllegalSeek)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, ErrNo> {
+        let len = core::cmp::min(buffer.len(), self.target.len());
+        buffer[..len].copy_from_slice(&self.target.as_bytes()[..len]);
+        Ok(len)
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, ErrNo> {
+        Err(ErrNo::PermissionDenied)
+    }
+
+    fn read_user_buffer(&self, mut buffer: crate::memory::UserBuffer) -> Result<usize, ErrNo> {
+        let len = core::cmp::min(buffer.len(), self.target.len());
+        buffer.write_bytes(&self.target.as_bytes()[..len], 0);
+        Ok(len)
+    }
+
+    fn write_user_buffer(&self, _buffer: crate::memory::UserBuffer) -> Result<usize, ErrNo> {
+        Err(ErrNo::PermissionDenied)
+    }
+
+    fn to_common_file<'a>(self: alloc::sync::Arc<Self>) -> Option<alloc::sync::Arc<dyn super::CommonFile + 'a>> where Self: 'a {
+        None
+    }
+
+    fn to_dir_file<'a>(self: alloc::sync::Arc<Self>) -> Option<alloc::sync::Arc<dyn super::DirFile + 'a>> where Self: 'a {
+        None
+    }
+
+    fn to_device_file<'a>(self: alloc::sync::Arc<Self>) -> Option<alloc::sync::Arc<dyn super::DeviceFile + 'a>> where Self: 'a {
+        None
+    }
+
+    fn poll(&self) -> crate::fs::FileStatus {
+        FileStatus {
+            readable:   true,
+            writeable:  false,
+            size:       self.target.len() as u64,
+            name:       self.fd.to_string(),
+            ftype:      crate::fs::FileType::Link,
+            inode:      0,
+            dev_no:     0,
+            mode:       0o777,
+            block_sz:   512,
+            blocks:     1,
+            uid:        0,
+            gid:        0,
+            atime_sec:  0,
+            atime_nsec: 0,
+            mtime_sec:  0,
+            mtime_nsec: 0,
+            ctime_sec:  0,
+            ctime_nsec: 0,
+        }
+    }
+
+    fn rename(&self, _new_name: &str) -> Result<(), ErrNo> {
+        Err(ErrNo::ReadonlyFileSystem)
+    }
+
+    fn get_vfs(&self) -> Result<alloc::sync::Arc<dyn super::VirtualFileSystem>, ErrNo> {
+        Ok(PROC_FS.clone())
+    }
+
+    fn get_path(&self) -> crate::fs::Path {
+        parse_path(&format!("/self/fd/{}", self.fd)).unwrap()
+    }
+}
+
+/// /proc/self/fd, one link per open fd of the process that opened it
+pub struct ProcFdDir {
+    fds: Vec<(usize, String)>,
+}
+
+impl Drop for ProcFdDir {
+    fn drop(&mut self) {
+        release_dropped_flocks();
+    }
+}
+
+impl File for ProcFdDir {
+    fn seek(&self, _offset: isize, _op: crate::fs::SeekOp) -> Result<(), ErrNo> {
+        Err(ErrNo::IllegalSeek)
+    }
+
+    fn get_cursor(&self) -> Result<usize, ErrNo> {
+        Err(ErrNo::IllegalSeek)
+    }
+
+    fn read(&self, _buffer: &mut [u8]) -> Result<usize, ErrNo> {
+        Err(ErrNo::IsADirectory)
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, ErrNo> {
+        Err(ErrNo::IsADirectory)
+    }
+
+    fn read_user_buffer(&self, _buffer: crate::memory::UserBuffer) -> Result<usize, ErrNo> {
+        Err(ErrNo::IsADirectory)
+    }
+
+    fn write_user_buffer(&self, _buffer: crate::memory::UserBuffer) -> Result<usize, ErrNo> {
+        Err(ErrNo::IsADirectory)
+    }
+
+    fn to_common_file<'a>(self: alloc::sync::Arc<Self>) -> Option<alloc::sync::Arc<dyn super::CommonFile + 'a>> where Self: 'a {
+        None
+    }
+
+    fn to_dir_file<'a>(self: alloc::sync::Arc<Self>) -> Option<alloc::sync::Arc<dyn super::DirFile + 'a>> where Self: 'a {
+        Some(self)
+    }
+
+    fn to_device_file<'a>(self: alloc::sync::Arc<Self>) -> Option<alloc::sync::Arc<dyn super::DeviceFile + 'a>> where Self: 'a {
+        None
+    }
+
+    fn poll(&self) -> crate::fs::FileStatus {
+        FileStatus {
+            readable:   true,
+            writeable:  false,
+            size:       0,
+            name:       "fd".to_string(),
+            ftype:      crate::fs::FileType::Directory,
+            inode:      0,
+            dev_no:     0,
+            mode:       0o500,
+            block_sz:   512,
+            blocks:     0,
+            uid:        0,
+            gid:        0,
+            atime_sec:  0,
+            atime_nsec: 0,
+            mtime_sec:  0,
+            mtime_nsec: 0,
+            ctime_sec:  0,
+            ctime_nsec: 0,
+        }
+    }
+
+    fn rename(&self, _new_name: &str) -> Result<(), ErrNo> {
+        Err(ErrNo::ReadonlyFileSystem)
+    }
+
+    fn get_vfs(&self) -> Result<alloc::sync::Arc<dyn super::VirtualFileSystem>, ErrNo> {
+        Ok(PROC_FS.clone())
+    }
+
+    fn get_path(&self) -> crate::fs::Path {
+        parse_path("/self/fd").unwrap()
+    }
+}
+
+impl super::CommonFile for ProcFdDir {
+
+}
+
+impl super::DirFile for ProcFdDir {
+    fn open(&self, path: crate::fs::Path, _mode: super::OpenMode) -> Result<Arc<dyn File>, ErrNo> {
+        if path.path.len() != 1 {
+            return Err(ErrNo::NoSuchFileOrDirectory);
+        }
+        let fd: usize = path.path[0].parse().map_err(|_| ErrNo::NoSuchFileOrDirectory)?;
+        self.fds.iter()
+            .find(|(n, _)| *n == fd)
+            .map(|(fd, target)| Arc::new(ProcFdLink { fd: *fd, target: target.clone() }) as Arc<dyn File>)
+            .ok_or(ErrNo::NoSuchFileOrDirectory)
+    }
+
+    fn mkdir(&self, _name: crate::fs::Path) -> Result<Arc<dyn File>, ErrNo> {
+        Err(ErrNo::ReadonlyFileSystem)
+    }
+
+    fn mkfile(&self, _name: crate::fs::Path) -> Result<Arc<dyn File>, ErrNo> {
+        Err(ErrNo::ReadonlyFileSystem)
+    }
+
+    fn remove(&self, _path: crate::fs::Path) -> Result<(), ErrNo> {
+        Err(ErrNo::ReadonlyFileSystem)
+    }
+
+    fn list(&self) -> Vec<Arc<dyn File>> {
+        self.fds.iter()
+            .map(|(fd, target)| Arc::new(ProcFdLink { fd: *fd, target: target.clone() }) as Arc<dyn File>)
+            .collect()
+    }
+}
+
+/// Open /proc/self/fd/N
+/// # Description
+/// With NO_FOLLOW the link itself is returned, for readlink.
+/// Otherwise it resolves to the file of fd N. A regular file is opened again on its fs with `mode`, with a cursor of its own as on linux.
+/// Other files, pipes and devices, have no path to open and are shared with the fd as dup would.
+fn open_fd_link(fd: &str, mode: super::OpenMode) -> Result<Arc<dyn File>, ErrNo> {
+    let fd: usize = fd.parse().map_err(|_| ErrNo::NoSuchFileOrDirectory)?;
+    if mode.contains(super::OpenMode::NO_FOLLOW) {
+        let (fd, target) = fd_targets().into_iter()
+            .find(|(n, _)| *n == fd)
+            .ok_or(ErrNo::NoSuchFileOrDirectory)?;
+        return Ok(Arc::new(ProcFdLink { fd, target }));
+    }
+    let file = current_process().unwrap().get_inner_locked().files.get(fd)
+        .cloned()
+        .flatten()
+        .ok_or(ErrNo::NoSuchFileOrDirectory)?;
+    if file.poll().ftype != FileType::Regular {
+        return Ok(file);
+    }
+    file.get_vfs()?.open(file.get_path(), mode)
+}
+
+/// Format ticks as seconds with two decimals
+fn ticks_to_secs(ticks: u64) -> String {
+    format!("{}.{:02}", ticks / CLOCK_FREQ, ticks % CLOCK_FREQ * 100 / CLOCK_FREQ)
+}
+
+/// Content of /proc/uptime: seconds since boot, and seconds spent idle.
+fn uptime() -> String {
+    format!("{} {}\n", ticks_to_secs(get_time()), ticks_to_secs(idle_time()))
+}
+
+/// Content of /proc/loadavg: load averages, running/total processes and the last pid.
+fn loadavg() -> String {
+    let avg = load_avg();
+    let mut content = String::new();
+    for load in avg.iter() {
+        content += &format!("{}.{:02} ", load >> FSHIFT, (load & (FIXED_1 - 1)) * 100 >> FSHIFT);
+    }
+    content += &format!("{}/{} {}\n", nr_running(), nr_processes(), last_pid());
+    content
+}
+
+/// Convert ticks to USER_HZ (100 Hz) jiffies
+fn ticks_to_jiffies(ticks: u64) -> u64 {
+    ticks * 100 / CLOCK_FREQ
+}
+
+/// Content of /proc/stat: cpu time split, context switches and process counters.
+fn stat() -> String {
+    let total = get_time();
+    let idle = idle_time();
+    let user = user_time();
+    let system = total.saturating_sub(idle).saturating_sub(user);
+    let cpu = format!("{} 0 {} {} 0 0 0 0 0 0", ticks_to_jiffies(user), ticks_to_jiffies(system), ticks_to_jiffies(idle));
+    let mut content = String::new();
+    content += &format!("cpu  {}\n", cpu);
+    content += &format!("cpu0 {}\n", cpu);
+    content += &format!("ctxt {}\n", context_switches());
+    // no RTC, boot time is the epoch
+    content += "btime 0\n";
+    content += &format!("processes {}\n", forks());
+    content += &format!("procs_running {}\n", nr_running());
+    content += &format!("procs_blocked {}\n", nr_sleeping());
+    content
+}
+
+/// Content of /proc/meminfo: physical frames plus kernel heap, in kB.
+fn meminfo() -> String {
+    let total = total_frames() * PAGE_SIZE + KERNEL_HEAP_SIZE;
+    // the heap grows with frames, which are no longer counted as free
+    let free = free_frames() * PAGE_SIZE + kernel_heap_capacity() - kernel_heap_used();
+    let mut content = String::new();
+    content += &format!("MemTotal:       {:8} kB\n", total / 1024);
+    content += &format!("MemFree:        {:8} kB\n", free / 1024);
+    content += &format!("MemAvailable:   {:8} kB\n", free / 1024);
+    content += &format!("SwapTotal:      {:8} kB\n", 0);
+    content += &format!("SwapFree:       {:8} kB\n", 0);
+    content += &format!("KernelHeap:     {:8} kB\n", kernel_heap_used() / 1024);
+    content += &format!("KernelHeapPeak: {:8} kB\n", kernel_heap_peak() / 1024);
+    content += &format!("KernelHeapCap:  {:8} kB\n", kernel_heap_capacity() / 1024);
+    content += &format!("Slab:           {:8} kB\n", slab_bytes() / 1024);
+    content += &format!("FreeFrames:     {:8}\n", free_frames());
+    content += &format!("LowWatermark:   {:8}\n", FRAME_LOW_WATERMARK);
+    content += &format!("Reclaimed:      {:8}\n", reclaimed_frames());
+    content
+}
+
+/// Content of /proc/self/status: identity and memory usage of the calling process, sizes in kB.
+fn self_status() -> String {
+    let proc = current_process().unwrap();
+    let mut inner = proc.get_inner_locked();
+    let name = inner.comm_name();
+    // proc0 has no parent
+    let ppid = inner.parent.as_ref().and_then(|parent| parent.upgrade()).map_or(0, |parent| parent.get_pid());
+    let rss = inner.update_rss();
+    let mut content = String::new();
+    content += &format!("Name:\t{}\n", name);
+    content += &format!("Pid:\t{}\n", proc.get_pid());
+    content += &format!("PPid:\t{}\n", ppid);
+    content += &format!("VmSize:\t{:8} kB\n", inner.layout.virtual_pages() * PAGE_SIZE / 1024);
+    content += &format!("VmHWM:\t{:8} kB\n", inner.max_rss * PAGE_SIZE / 1024);
+    content += &format!("VmRSS:\t{:8} kB\n", rss * PAGE_SIZE / 1024);
+    // 0 as long as the process never wrote an FP register
+    content += &format!("FpSaves:\t{}\n", inner.fp_saves);
+    content
+}
+
 pub struct ProcFS {}
 
 lazy_static! {
@@ -108,15 +569,43 @@ impl VirtualFileSystem for ProcFS {
 		
     }
 
+    fn try_sync(&self) -> bool {
+        true
+    }
+
     fn get_status(&self) -> super::FSStatus {
-        todo!()
+        super::FSStatus::new("proc", super::FSFlags::empty())
+    }
+
+    /// files are generated on open and take no space
+    fn statfs(&self) -> super::FSStatus {
+        let mut status = self.get_status();
+        // PROC_SUPER_MAGIC
+        status.magic = 0x9fa0;
+        status.block_size = PAGE_SIZE;
+        status.name_max = 255;
+        status
     }
 
     fn open(&self, abs_path: crate::fs::Path, mode: super::OpenMode) -> Result<alloc::sync::Arc<dyn File>, ErrNo> {
-        if abs_path.to_string() == "/self/exe" {
-			return Ok(Arc::new(ProcSelfExe{}));
-		}
-		Err(ErrNo::NoSuchFileOrDirectory)
+        if abs_path.path.len() == 3 && abs_path.path[0] == "self" && abs_path.path[1] == "fd" {
+            return open_fd_link(&abs_path.path[2], mode);
+        }
+        match abs_path.to_string().as_str() {
+            "/self/exe" => Ok(Arc::new(ProcSelfExe{})),
+            "/uptime"   => Ok(ProcFile::new("/uptime", uptime())),
+            "/loadavg"  => Ok(ProcFile::new("/loadavg", loadavg())),
+            "/stat"     => Ok(ProcFile::new("/stat", stat())),
+            "/meminfo"  => Ok(ProcFile::new("/meminfo", meminfo())),
+            "/self/status" => Ok(ProcFile::new("/self/status", self_status())),
+            "/self/cmdline" => Ok(ProcFile::from_bytes("/self/cmdline", current_process().unwrap().get_inner_locked().cmdline.clone())),
+            "/self/environ" => Ok(ProcFile::from_bytes("/self/environ", current_process().unwrap().get_inner_locked().environ.clone())),
+            "/self/fd"  => Ok(Arc::new(ProcFdDir { fds: fd_targets() })),
+            "/self/comm" => Ok(ProcFile::new("/self/comm", current_process().unwrap().get_inner_locked().comm_name() + "\n")),
+            "/self/smaps" => Ok(ProcFile::new("/self/smaps", current_process().unwrap().get_inner_locked().layout.smaps())),
+            "/self/pagetable" => Ok(ProcFile::new("/self/pagetable", current_process().unwrap().get_inner_locked().layout.pagetable.dump())),
+            _ => Err(ErrNo::NoSuchFileOrDirectory),
+        }
     }
 
     fn mkdir(&self, abs_path: crate::fs::Path) -> Result<alloc::sync::Arc<dyn File>, ErrNo> {
@@ -0,0 +1,370 @@
+//! In-memory file system, the root of the initramfs
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use super::{CommonFile, DeviceFile, DirFile, FSFlags, FSStatus, OpenMode, VirtualFileSystem};
+use crate::fs::{File, FileStatus, FileType, Path, SeekOp, release_dropped_flocks};
+use crate::memory::UserBuffer;
+use crate::process::ErrNo;
+
+enum TmpContent {
+    Regular(Vec<u8>),
+    Directory(BTreeMap<String, Arc<TmpInode>>),
+}
+
+/// A file or a directory of the tmpfs
+pub struct TmpInode {
+    ino: u64,
+    content: Mutex<TmpContent>,
+}
+
+impl TmpInode {
+    fn new(ino: u64, dir: bool) -> Arc<Self> {
+        let content = if dir {
+            TmpContent::Directory(BTreeMap::new())
+        } else {
+            TmpContent::Regular(Vec::new())
+        };
+        Arc::new(Self {
+            ino,
+            content: Mutex::new(content),
+        })
+    }
+
+    fn is_dir(&self) -> bool {
+        matches!(*self.content.lock(), TmpContent::Directory(_))
+    }
+
+    /// Size in bytes, # of entries for a directory
+    fn size(&self) -> usize {
+        match &*self.content.lock() {
+            TmpContent::Regular(data) => data.len(),
+            TmpContent::Directory(entries) => entries.len(),
+        }
+    }
+}
+
+/// File system whose files only live in memory
+/// # Description
+/// Clones share the same tree.
+#[derive(Clone)]
+pub struct TmpFS {
+    root: Arc<TmpInode>,
+    next_ino: Arc<AtomicU64>,
+}
+
+impl TmpFS {
+    pub const name: &'static str = "TmpFS (Powered by OSHIT)";
+
+    pub fn new() -> Self {
+        Self {
+            root: TmpInode::new(1, true),
+            next_ino: Arc::new(AtomicU64::new(2)),
+        }
+    }
+
+    /// Walk "names" from the root
+    fn lookup(&self, names: &[String]) -> Result<Arc<TmpInode>, ErrNo> {
+        let mut inode = self.root.clone();
+        for name in names {
+            let next = match &*inode.content.lock() {
+                TmpContent::Directory(entries) => entries.get(name).cloned().ok_or(ErrNo::NoSuchFileOrDirectory)?,
+                TmpContent::Regular(_) => return Err(ErrNo::NotADirectory),
+            };
+            inode = next;
+        }
+        Ok(inode)
+    }
+
+    /// Create a file or a directory at "abs_path", its parent must exist
+    fn create(&self, abs_path: &Path, dir: bool) -> Result<Arc<TmpInode>, ErrNo> {
+        let (name, parent) = match abs_path.path.split_last() {
+            Some(split) => split,
+            None => return Err(ErrNo::FileExists),
+        };
+        let parent = self.lookup(parent)?;
+        let mut content = parent.content.lock();
+        let entries = match &mut *content {
+            TmpContent::Directory(entries) => entries,
+            TmpContent::Regular(_) => return Err(ErrNo::NotADirectory),
+        };
+        if entries.contains_key(name) {
+            return Err(ErrNo::FileExists);
+        }
+        let inode = TmpInode::new(self.next_ino.fetch_add(1, Ordering::Relaxed), dir);
+        entries.insert(name.clone(), inode.clone());
+        Ok(inode)
+    }
+
+    fn file(&self, inode: Arc<TmpInode>, abs_path: Path, writeable: bool) -> Arc<dyn File> {
+        Arc::new(TmpFile {
+            fs: self.clone(),
+            inode,
+            path: abs_path,
+            writeable,
+            cursor: Mutex::new(0),
+        })
+    }
+}
+
+impl VirtualFileSystem for TmpFS {
+    /// nothing to write back
+    fn sync(&self, _wait: bool) {
+    }
+
+    fn try_sync(&self) -> bool {
+        true
+    }
+
+    fn get_status(&self) -> FSStatus {
+        FSStatus {
+            name: TmpFS::name,
+            flags: FSFlags::empty(),
+        }
+    }
+
+    fn open(&self, abs_path: Path, mode: OpenMode) -> Result<Arc<dyn File>, ErrNo> {
+        let inode = match self.lookup(&abs_path.path) {
+            Ok(inode) => inode,
+            Err(ErrNo::NoSuchFileOrDirectory) if mode.contains(OpenMode::CREATE) => self.create(&abs_path, false)?,
+            Err(errno) => return Err(errno),
+        };
+        let dir = inode.is_dir();
+        if !dir && (abs_path.must_dir || mode.contains(OpenMode::DIR)) {
+            return Err(ErrNo::NotADirectory);
+        }
+        if dir && mode.intersects(OpenMode::WRITE | OpenMode::TRUNCATE) {
+            return Err(ErrNo::IsADirectory);
+        }
+        if mode.contains(OpenMode::TRUNCATE) {
+            if let TmpContent::Regular(data) = &mut *inode.content.lock() {
+                data.clear();
+            }
+        }
+        let writeable = !dir && mode.intersects(OpenMode::WRITE | OpenMode::SYS);
+        Ok(self.file(inode, abs_path, writeable))
+    }
+
+    fn mkdir(&self, abs_path: Path) -> Result<Arc<dyn File>, ErrNo> {
+        let inode = self.create(&abs_path, true)?;
+        Ok(self.file(inode, abs_path, false))
+    }
+
+    fn mkfile(&self, abs_path: Path) -> Result<Arc<dyn File>, ErrNo> {
+        let inode = self.create(&abs_path, false)?;
+        Ok(self.file(inode, abs_path, true))
+    }
+
+    fn remove(&self, abs_path: Path) -> Result<(), ErrNo> {
+        let (name, parent) = match abs_path.path.split_last() {
+            Some(split) => split,
+            None => return Err(ErrNo::DeviceOrResourceBusy),
+        };
+        let parent = self.lookup(parent)?;
+        let mut content = parent.content.lock();
+        let entries = match &mut *content {
+            TmpContent::Directory(entries) => entries,
+            TmpContent::Regular(_) => return Err(ErrNo::NotADirectory),
+        };
+        match entries.get(name) {
+            None => return Err(ErrNo::NoSuchFileOrDirectory),
+            Some(inode) if inode.is_dir() && inode.size() != 0 => return Err(ErrNo::DirectoryNotEmpty),
+            Some(_) => {},
+        }
+        entries.remove(name);
+        Ok(())
+    }
+
+    fn link(&self, _to_link: Arc<dyn File>, _dest: Path) -> Result<(), ErrNo> {
+        Err(ErrNo::CrossdeviceLink)
+    }
+
+    fn sym_link(&self, _abs_src: Path, _rel_dst: Path) -> Result<(), ErrNo> {
+        Err(ErrNo::OperationNotPermitted)
+    }
+
+    fn rename(&self, _to_rename: Arc<dyn File>, _new_name: String) -> Result<(), ErrNo> {
+        Err(ErrNo::PermissionDenied)
+    }
+}
+
+/// An opened file or directory of the tmpfs
+pub struct TmpFile {
+    fs: TmpFS,
+    inode: Arc<TmpInode>,
+    path: Path,
+    writeable: bool,
+    cursor: Mutex<usize>,
+}
+
+impl TmpFile {
+    /// Absolute path of "rel" under this directory
+    fn child(&self, rel: Path) -> Path {
+        let mut path = self.path.clone();
+        path.path.extend(rel.path);
+        path.must_dir = rel.must_dir;
+        path
+    }
+}
+
+impl Drop for TmpFile {
+    fn drop(&mut self) {
+        release_dropped_flocks();
+    }
+}
+
+impl File for TmpFile {
+    fn seek(&self, offset: isize, op: SeekOp) -> Result<(), ErrNo> {
+        let size = self.inode.size();
+        let mut cursor = self.cursor.lock();
+        let base = match op {
+            SeekOp::SET => 0,
+            SeekOp::CUR => *cursor as isize,
+            SeekOp::END => size as isize,
+        };
+        if base + offset < 0 {
+            return Err(ErrNo::InvalidArgument);
+        }
+        *cursor = (base + offset) as usize;
+        Ok(())
+    }
+
+    fn get_cursor(&self) -> Result<usize, ErrNo> {
+        Ok(*self.cursor.lock())
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, ErrNo> {
+        let len = match &*self.inode.content.lock() {
+            TmpContent::Regular(data) => {
+                let mut cursor = self.cursor.lock();
+                let start = core::cmp::min(*cursor, data.len());
+                let len = core::cmp::min(buffer.len(), data.len() - start);
+                buffer[..len].copy_from_slice(&data[start..start + len]);
+                *cursor = start + len;
+                len
+            },
+            TmpContent::Directory(_) => return Err(ErrNo::IsADirectory),
+        };
+        Ok(len)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, ErrNo> {
+        if !self.writeable {
+            return Err(ErrNo::BadFileDescriptor);
+        }
+        match &mut *self.inode.content.lock() {
+            TmpContent::Regular(data) => {
+                let mut cursor = self.cursor.lock();
+                let end = *cursor + buffer.len();
+                if data.len() < end {
+                    data.resize(end, 0);
+                }
+                data[*cursor..end].copy_from_slice(buffer);
+                *cursor = end;
+                Ok(buffer.len())
+            },
+            TmpContent::Directory(_) => Err(ErrNo::IsADirectory),
+        }
+    }
+
+    fn read_user_buffer(&self, mut buffer: UserBuffer) -> Result<usize, ErrNo> {
+        let mut bytes = vec![0u8; buffer.len()];
+        let len = self.read(&mut bytes)?;
+        buffer.write_bytes(&bytes[..len], 0);
+        Ok(len)
+    }
+
+    fn write_user_buffer(&self, buffer: UserBuffer) -> Result<usize, ErrNo> {
+        self.write(&buffer.clone_bytes())
+    }
+
+    fn to_common_file<'a>(self: Arc<Self>) -> Option<Arc<dyn CommonFile + 'a>> where Self: 'a {
+        Some(self)
+    }
+
+    fn to_dir_file<'a>(self: Arc<Self>) -> Option<Arc<dyn DirFile + 'a>> where Self: 'a {
+        if self.inode.is_dir() {
+            Some(self)
+        } else {
+            None
+        }
+    }
+
+    fn to_device_file<'a>(self: Arc<Self>) -> Option<Arc<dyn DeviceFile + 'a>> where Self: 'a {
+        None
+    }
+
+    fn poll(&self) -> FileStatus {
+        let dir = self.inode.is_dir();
+        let size = if dir { 0 } else { self.inode.size() as u64 };
+        FileStatus {
+            readable:   true,
+            writeable:  self.writeable,
+            size,
+            name:       self.path.path.last().cloned().unwrap_or_else(|| "/".to_string()),
+            ftype:      if dir { FileType::Directory } else { FileType::Regular },
+            inode:      self.inode.ino,
+            dev_no:     0,
+            mode:       if dir { 0o755 } else { 0o644 },
+            block_sz:   512,
+            blocks:     (size + 511) / 512,
+            uid:        0,
+            gid:        0,
+            atime_sec:  0,
+            atime_nsec: 0,
+            mtime_sec:  0,
+            mtime_nsec: 0,
+            ctime_sec:  0,
+            ctime_nsec: 0,
+        }
+    }
+
+    fn rename(&self, _new_name: &str) -> Result<(), ErrNo> {
+        Err(ErrNo::PermissionDenied)
+    }
+
+    fn get_vfs(&self) -> Result<Arc<dyn VirtualFileSystem>, ErrNo> {
+        Ok(Arc::new(self.fs.clone()))
+    }
+
+    fn get_path(&self) -> Path {
+        self.path.clone()
+    }
+}
+
+impl CommonFile for TmpFile {}
+
+impl DirFile for TmpFile {
+    fn open(&self, path: Path, mode: OpenMode) -> Result<Arc<dyn File>, ErrNo> {
+        self.fs.open(self.child(path), mode)
+    }
+
+    fn mkdir(&self, name: Path) -> Result<Arc<dyn File>, ErrNo> {
+        self.fs.mkdir(self.child(name))
+    }
+
+    fn mkfile(&self, name: Path) -> Result<Arc<dyn File>, ErrNo> {
+        self.fs.mkfile(self.child(name))
+    }
+
+    fn remove(&self, path: Path) -> Result<(), ErrNo> {
+        self.fs.remove(self.child(path))
+    }
+
+    fn list(&self) -> Vec<Arc<dyn File>> {
+        let entries: Vec<(String, Arc<TmpInode>)> = match &*self.inode.content.lock() {
+            TmpContent::Directory(entries) => entries.iter().map(|(name, inode)| (name.clone(), inode.clone())).collect(),
+            TmpContent::Regular(_) => Vec::new(),
+        };
+        entries.into_iter().map(|(name, inode)| {
+            let mut path = self.path.clone();
+            path.path.push(name);
+            path.must_dir = inode.is_dir();
+            self.fs.file(inode, path, false)
+        }).collect()
+    }
+}
@@ -1,5 +1,6 @@
 use super::super::File;
 use super::super::Path;
+use super::super::file::FileType;
 use alloc::sync::Arc;
 use bitflags::*;
 use alloc::string::String;
@@ -19,9 +20,39 @@ bitflags! {
 pub struct FSStatus {
     pub name: &'static str,
     pub flags: FSFlags,
+    /// f_type reported by statfs, 0 if unknown
+    pub magic: usize,
+    /// size of the blocks counted below, in bytes
+    pub block_size: usize,
+    pub total_blocks: usize,
+    pub free_blocks: usize,
+    /// free blocks usable by unprivileged users
+    pub avail_blocks: usize,
+    pub total_inodes: usize,
+    pub free_inodes: usize,
+    /// longest file name
+    pub name_max: usize,
     // TODO: mounted dev etc
 }
 
+impl FSStatus {
+    /// Status with all the counts 0
+    pub fn new(name: &'static str, flags: FSFlags) -> Self {
+        Self {
+            name,
+            flags,
+            magic: 0,
+            block_size: 0,
+            total_blocks: 0,
+            free_blocks: 0,
+            avail_blocks: 0,
+            total_inodes: 0,
+            free_inodes: 0,
+            name_max: 0,
+        }
+    }
+}
+
 
 bitflags! {
     /// fs flags
@@ -43,9 +74,42 @@ pub trait VirtualFileSystem : Send + Sync {
     /// force write back all dirty
     fn sync(&self, wait: bool);
 
+    /// write back all dirty like sync, but skip whatever is locked instead of waiting on it, for the panic path
+    /// # Return
+    /// false if anything was skipped
+    fn try_sync(&self) -> bool;
+
+    /// write back at most `budget` blocks dirty since `deadline` or earlier, without waiting on busy caches
+    /// # Return
+    /// # of blocks written
+    fn flush_older(&self, _deadline: u64, _budget: usize) -> usize {
+        0
+    }
+
+    /// release cached blocks that are clean and unused, without waiting on busy caches
+    /// # Return
+    /// # of caches released
+    fn drop_clean_caches(&self) -> usize {
+        0
+    }
+
     /// get status
     fn get_status(&self) -> FSStatus;
 
+    /// get status with block and inode counts, for statfs
+    /// # Description
+    /// May walk on disk structures, unlike get_status. File systems that don't count get all zeros.
+    fn statfs(&self) -> FSStatus {
+        self.get_status()
+    }
+
+    /// identity of the mounted fs, the same for every wrapper of it
+    /// # Description
+    /// Wrappers made by `File::get_vfs()` may be fresh ones, so their addresses can't tell fs apart.
+    fn fs_id(&self) -> usize {
+        self as *const Self as *const u8 as usize
+    }
+
     // ==================== file level ops ====================
     /// create inode (read from disc etc), used for open files.  
     /// we first create it's inode, then opens it.
@@ -57,6 +121,15 @@ pub trait VirtualFileSystem : Send + Sync {
     fn mkfile(&self, abs_path: Path) -> Result<Arc<dyn File>, ErrNo>;
 
     fn remove(&self, abs_path: Path) -> Result<(), ErrNo>;
+    /// create an unnamed file in directory `abs_dir`, which is gone once closed (O_TMPFILE)
+    fn mktmp(&self, _abs_dir: Path, _mode: OpenMode) -> Result<Arc<dyn File>, ErrNo> {
+        Err(ErrNo::OperationNotSupportedOnTransportEndpoint)
+    }
+
+    /// create a special file of type `ftype` for device (`major`, `minor`)
+    fn mknod(&self, _abs_path: Path, _ftype: FileType, _major: u32, _minor: u32) -> Result<(), ErrNo> {
+        Err(ErrNo::OperationNotPermitted)
+    }
     
     fn link(&self, to_link: Arc<dyn File>, dest: Path) -> Result<(), ErrNo>;
 
@@ -0,0 +1,130 @@
+//! Initramfs loader, unpacks the cpio (newc) archive linked into the kernel into a tmpfs.
+use alloc::format;
+
+use super::{OpenMode, VirtualFileSystem, parse_path};
+use crate::process::ErrNo;
+
+const NEWC_MAGIC: &[u8] = b"070701";
+const NEWC_HEADER_LEN: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+
+/// A member of a newc archive
+struct CpioEntry<'a> {
+    name: &'a str,
+    mode: u32,
+    data: &'a [u8],
+}
+
+/// Parse the 8-digit hex field "idx" of a newc header
+fn header_field(header: &[u8], idx: usize) -> Result<u32, ErrNo> {
+    let start = NEWC_MAGIC.len() + idx * 8;
+    let digits = core::str::from_utf8(&header[start..start + 8]).map_err(|_| ErrNo::InvalidArgument)?;
+    u32::from_str_radix(digits, 16).map_err(|_| ErrNo::InvalidArgument)
+}
+
+fn align4(x: usize) -> usize {
+    (x + 3) & !3
+}
+
+/// Parse the entry at "offset" of the archive
+/// # Returns
+/// The entry and the offset of the next one, None on the trailer
+fn parse_entry(archive: &[u8], offset: usize) -> Result<Option<(CpioEntry, usize)>, ErrNo> {
+    if offset + NEWC_HEADER_LEN > archive.len() {
+        return Err(ErrNo::InvalidArgument);
+    }
+    let header = &archive[offset..offset + NEWC_HEADER_LEN];
+    if &header[..NEWC_MAGIC.len()] != NEWC_MAGIC {
+        error!("initramfs: bad cpio magic at {}", offset);
+        return Err(ErrNo::InvalidArgument);
+    }
+    let mode = header_field(header, 1)?;
+    let filesize = header_field(header, 6)? as usize;
+    let namesize = header_field(header, 11)? as usize;
+
+    let name_start = offset + NEWC_HEADER_LEN;
+    let data_start = align4(name_start + namesize);
+    let data_end = data_start + filesize;
+    if namesize == 0 || data_end > archive.len() {
+        return Err(ErrNo::InvalidArgument);
+    }
+    // namesize counts the trailing NUL
+    let name = core::str::from_utf8(&archive[name_start..name_start + namesize - 1]).map_err(|_| ErrNo::InvalidArgument)?;
+    if name == TRAILER {
+        return Ok(None);
+    }
+    let entry = CpioEntry {
+        name,
+        mode,
+        data: &archive[data_start..data_end],
+    };
+    Ok(Some((entry, align4(data_end))))
+}
+
+/// Create the entry in "fs"
+fn unpack_entry(fs: &dyn VirtualFileSystem, entry: &CpioEntry) -> Result<(), ErrNo> {
+    let name = entry.name.trim_start_matches("./").trim_start_matches('/');
+    if name.len() == 0 || name == "." {
+        return Ok(());
+    }
+    let path = parse_path(&format!("/{}", name)).map_err(|_| ErrNo::InvalidArgument)?;
+    match entry.mode & S_IFMT {
+        S_IFDIR => {
+            match fs.mkdir(path) {
+                Ok(_) | Err(ErrNo::FileExists) => Ok(()),
+                Err(errno) => Err(errno),
+            }
+        },
+        S_IFREG => {
+            let file = fs.open(path, OpenMode::SYS | OpenMode::CREATE | OpenMode::TRUNCATE)?;
+            let mut written = 0;
+            while written < entry.data.len() {
+                let len = file.write(&entry.data[written..])?;
+                if len == 0 {
+                    return Err(ErrNo::NoSpaceLeftOnDevice);
+                }
+                written += len;
+            }
+            Ok(())
+        },
+        _ => {
+            warning!("initramfs: skipping {}, unsupported mode {:o}", name, entry.mode);
+            Ok(())
+        }
+    }
+}
+
+/// Unpack a newc archive into "fs"
+/// # Returns
+/// # of entries unpacked
+pub fn unpack(archive: &[u8], fs: &dyn VirtualFileSystem) -> Result<usize, ErrNo> {
+    let mut offset = 0;
+    let mut count = 0;
+    while let Some((entry, next)) = parse_entry(archive, offset)? {
+        verbose!("initramfs: {} ({} bytes)", entry.name, entry.data.len());
+        unpack_entry(fs, &entry)?;
+        offset = next;
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// The archive linked into the kernel
+/// # Returns
+/// None if the kernel was built without one
+pub fn initramfs() -> Option<&'static [u8]> {
+    extern "C" {
+        fn _initramfs_start();
+        fn _initramfs_end();
+    }
+    let start = _initramfs_start as usize;
+    let end = _initramfs_end as usize;
+    if start == end {
+        return None;
+    }
+    Some(unsafe { core::slice::from_raw_parts(start as *const u8, end - start) })
+}
\ No newline at end of file
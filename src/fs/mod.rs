@@ -6,6 +6,9 @@ mod path;
 mod mount_manager;
 pub mod fs_impl;
 mod block_cache;
+mod initramfs;
+mod bdflush;
+mod file_lock;
 
 pub use file::{
 	File, 
@@ -36,16 +39,50 @@ pub use path::{
 pub use mount_manager::{
 	mount_fs,
 	unmount_fs,
+	sync_all,
+	try_sync_all,
+	try_flush_older,
+	try_drop_clean_caches,
 	parse,
 	open,
 	mkdir,
 	mkfile,
+	mknod,
 	remove,
+	remove_tree,
 	link,
 	sym_link,
 	rename
 };
 
+pub use file_lock::{
+	flock,
+	release_dropped_flocks,
+	test_record_lock,
+	set_record_lock,
+	release_record_locks,
+	release_process_record_locks,
+	RecordLock,
+	LOCK_SH,
+	LOCK_EX,
+	LOCK_NB,
+	LOCK_UN,
+	F_RDLCK,
+	F_WRLCK,
+	F_UNLCK
+};
+
+pub use bdflush::{
+	bdflush_tick,
+	bdflush_at,
+	next_bdflush
+};
+
+pub use initramfs::{
+	initramfs,
+	unpack
+};
+
 pub use pipe::{
 	PipeEnd,
 	make_pipe
@@ -3,11 +3,17 @@ mod mount_manager;
 pub use mount_manager::{
 	mount_fs,
 	unmount_fs,
+	sync_all,
+	try_sync_all,
+	try_flush_older,
+	try_drop_clean_caches,
 	parse,
 	open,
 	mkdir,
 	mkfile,
+	mknod,
 	remove,
+	remove_tree,
 	link,
 	sym_link,
 	rename
@@ -5,12 +5,14 @@ use super::super::parse_path;
 use super::super::Path;
 use alloc::{string::ToString};
 use alloc::string::String;
+use alloc::format;
 use spin::{Mutex, MutexGuard};
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use crate::fs::{File, OpenMode};
+use crate::fs::{File, FileType, OpenMode};
 use lazy_static::*;
 use crate::process::ErrNo;
+use crate::process::elf_cache::ELF_CACHE;
 
 /// Mount Manager (Wrapper)
 /// # Description
@@ -72,6 +74,63 @@ impl MountManager {
         self.get_inner_locked().unmount_fs(&path)
     }
 
+    /// Write back all dirty data of every mounted filesystem
+    pub fn sync_all(&self) {
+        // don't hold the mount manager while file systems do I/O
+        let mounted = self.get_inner_locked().mounted();
+        for vfs in mounted.iter() {
+            vfs.sync(true);
+        }
+    }
+
+    /// Same as sync_all, but never waits on a lock
+    /// # Return
+    /// false if the mount manager is locked, or some file system is not fully synced
+    pub fn try_sync_all(&self) -> bool {
+        let mounted = match self.inner.try_lock() {
+            Some(inner) => inner.mounted(),
+            None => return false,
+        };
+        let mut done = true;
+        for vfs in mounted.iter() {
+            done &= vfs.try_sync();
+        }
+        return done;
+    }
+
+    /// Write back blocks dirty since "deadline" or earlier, at most "budget" of them
+    /// # Description
+    /// Gives up if the mount manager is locked.
+    /// # Return
+    /// # of blocks written
+    pub fn try_flush_older(&self, deadline: u64, budget: usize) -> usize {
+        let mounted = match self.inner.try_lock() {
+            Some(inner) => inner.mounted(),
+            None => return 0,
+        };
+        let mut written = 0;
+        for vfs in mounted.iter() {
+            if written == budget {
+                break;
+            }
+            written += vfs.flush_older(deadline, budget - written);
+        }
+        return written;
+    }
+
+    /// Release clean block caches of every mounted filesystem
+    /// # Description
+    /// Gives up if the mount manager is locked.
+    /// # Return
+    /// # of caches released
+    pub fn try_drop_clean_caches(&self) -> usize {
+        let mounted = match self.inner.try_lock() {
+            Some(inner) => inner.mounted(),
+            None => return 0,
+        };
+        mounted.iter().map(|vfs| vfs.drop_clean_caches()).sum()
+    }
+
     /// get vfs and string relative to it.
     pub fn parse(&self, total_path: String) -> Result<(Arc<dyn VirtualFileSystem>, Path), ErrNo> {
         self.get_inner_locked().parse(&total_path)
@@ -92,6 +151,11 @@ impl MountManager {
         self.get_inner_locked().mkfile(abs_path)
     }
 
+    /// Create device special file
+    pub fn mknod(&self, abs_path: String, ftype: FileType, major: u32, minor: u32) -> Result<(), ErrNo> {
+        self.get_inner_locked().mknod(abs_path, ftype, major, minor)
+    }
+
     /// Delete file
     pub fn remove(&self, abs_path: String) -> Result<(), ErrNo> {
         self.get_inner_locked().remove(abs_path)
@@ -264,6 +328,22 @@ impl MountManagerInner {
         return Err(ErrNo::NoSuchFileOrDirectory);
     }
 
+    fn collect(queue: &Vec<MountNode>, result: &mut Vec<Arc<dyn VirtualFileSystem>>) {
+        for node in queue.iter() {
+            match node {
+                MountNode::FileSystem(fs) => result.push(fs.clone()),
+                MountNode::SubDir(_, sq) => MountManagerInner::collect(sq, result),
+            }
+        }
+    }
+
+    /// All mounted filesystems
+    pub fn mounted(&self) -> Vec<Arc<dyn VirtualFileSystem>> {
+        let mut result = Vec::new();
+        MountManagerInner::collect(&self.root, &mut result);
+        return result;
+    }
+
     fn find_fs(queue: &Vec<MountNode>, vfs: &Arc<dyn VirtualFileSystem>, path:&mut Vec<String>) -> Result<(),()> {
         for i in 0..queue.len() {
             match &queue[i] {
@@ -313,6 +393,11 @@ impl MountManagerInner {
         return vfs.mkfile(rel_path);
     }
 
+    pub fn mknod(&self, abs_path: String, ftype: FileType, major: u32, minor: u32) -> Result<(), ErrNo> {
+        let (vfs, rel_path) = self.parse(&abs_path)?;
+        return vfs.mknod(rel_path, ftype, major, minor);
+    }
+
     pub fn remove(&self, abs_path: String) -> Result<(), ErrNo> {
         let (vfs, rel_path) = self.parse(&abs_path)?;
         return vfs.remove(rel_path);
@@ -368,12 +453,38 @@ pub fn unmount_fs(path: String) -> Result<(), ErrNo> {
     MOUNT_MANAGER.get_inner_locked().unmount_fs(&path)
 }
 
+/// Write back all dirty data of every mounted filesystem
+pub fn sync_all() {
+    MOUNT_MANAGER.sync_all()
+}
+
+/// Write back all dirty data of every mounted filesystem, skipping whatever is locked
+/// # Description
+/// For paths that can't wait, like panic.
+pub fn try_sync_all() -> bool {
+    MOUNT_MANAGER.try_sync_all()
+}
+
+/// Write back at most "budget" blocks dirty since "deadline" or earlier, unless the mount manager is locked
+pub fn try_flush_older(deadline: u64, budget: usize) -> usize {
+    MOUNT_MANAGER.try_flush_older(deadline, budget)
+}
+
+/// Release clean block caches of every mounted filesystem, unless the mount manager is locked
+pub fn try_drop_clean_caches() -> usize {
+    MOUNT_MANAGER.try_drop_clean_caches()
+}
+
 /// get vfs and string relative to it.
 pub fn parse(total_path: String) -> Result<(Arc<dyn VirtualFileSystem>, Path), ErrNo> {
     MOUNT_MANAGER.parse(total_path)
 }
 
 pub fn open(abs_path: String, mode: OpenMode) -> Result<Arc<dyn File>, ErrNo> {
+    // FAT keeps mtime in 2 seconds, don't rely on it alone to catch a rewritten program
+    if mode.contains(OpenMode::WRITE) {
+        ELF_CACHE.lock().invalidate(&abs_path);
+    }
     MOUNT_MANAGER.open(abs_path, mode)
 }
 
@@ -385,10 +496,66 @@ pub fn mkfile(abs_path: String) -> Result<Arc<dyn File>, ErrNo> {
     MOUNT_MANAGER.mkfile(abs_path)
 }
 
+pub fn mknod(abs_path: String, ftype: FileType, major: u32, minor: u32) -> Result<(), ErrNo> {
+    MOUNT_MANAGER.mknod(abs_path, ftype, major, minor)
+}
+
 pub fn remove(abs_path: String) -> Result<(), ErrNo> {
+    ELF_CACHE.lock().invalidate(&abs_path);
     MOUNT_MANAGER.remove(abs_path)
 }
 
+/// Deepest directory remove_tree descends into, counted from "abs_path"
+const REMOVE_TREE_MAX_DEPTH: usize = 64;
+
+/// Remove "abs_path" and, if it is a directory, everything under it
+/// # Description
+/// For kernel internal cleanup, not exposed as a syscall. 
+/// Walks the tree with an explicit work stack instead of recursion, so a deep tree can't overflow the kernel stack.
+/// Symbolic links are removed themselves, never followed into, even when they point to a directory.
+/// # Return
+/// Err(FileNameTooLong) if the tree is deeper than REMOVE_TREE_MAX_DEPTH, entries removed so far stay removed.
+pub fn remove_tree(abs_path: String) -> Result<(), ErrNo> {
+    // (path, depth, if its children have been pushed)
+    let mut stack: Vec<(String, usize, bool)> = Vec::new();
+    stack.push((abs_path, 0, false));
+    while let Some((path, depth, expanded)) = stack.pop() {
+        if expanded {
+            remove(path)?;
+            continue;
+        }
+        // links are opened as themselves and removed like a regular file
+        let names: Vec<String> = match open(path.clone(), OpenMode::SYS | OpenMode::DIR | OpenMode::NO_FOLLOW) {
+            Ok(file) if file.poll().ftype == FileType::Link => {
+                drop(file);
+                remove(path)?;
+                continue;
+            },
+            Ok(file) => match file.to_dir_file() {
+                Some(dir) => dir.list().iter()
+                    .map(|f| f.poll().name)
+                    .filter(|name| name != "." && name != "..")
+                    .collect(),
+                None => Vec::new(),
+            },
+            Err(ErrNo::NotADirectory) => {
+                remove(path)?;
+                continue;
+            },
+            Err(errno) => return Err(errno),
+        };
+        if names.len() > 0 && depth == REMOVE_TREE_MAX_DEPTH {
+            return Err(ErrNo::FileNameTooLong);
+        }
+        let base = path.trim_end_matches('/').to_string();
+        stack.push((path, depth, true));
+        for name in names {
+            stack.push((format!("{}/{}", base, name), depth + 1, false));
+        }
+    }
+    return Ok(());
+}
+
 pub fn link(to_link: Arc<dyn File>, dest: String) -> Result<(), ErrNo> {
     MOUNT_MANAGER.link(to_link, dest)
 }
@@ -88,6 +88,27 @@ impl Path {
                 return Ok(());
         }
 
+        /// Resolve the path against "cwd" into a canonical absolute path
+        /// # Note
+        /// "cwd" must be an absolute path. ".." of root is root itself.
+        pub fn canonicalize(&self, cwd: &Path) -> Path {
+                let mut res = if self.is_abs {
+                        Path::root()
+                } else {
+                        cwd.clone()
+                };
+                for part in self.path.iter() {
+                        if part.eq("..") {
+                                res.path.pop();
+                        } else {
+                                res.path.push(part.clone());
+                        }
+                }
+                res.is_abs = true;
+                res.must_dir = self.must_dir || res.path.len() == 0;
+                return res;
+        }
+
         pub fn to_string(&self) -> String {
                 let mut res = String::new();
                 if !self.is_abs && self.path.len() == 0 {
@@ -177,7 +198,6 @@ impl PathParser {
                                 } else {
                                         self.path.is_abs = false;
                                         if c == '.' {
-                                                self.buf.push('.');
                                                 self.state = STATE::DirCur;
                                                 return None;
                                         } else {
@@ -199,14 +219,12 @@ impl PathParser {
                         },
                         STATE::FName => {
                                 if c == '/' {
+                                        // consecutive slashes are collapsed
                                         if self.buf.len() > 0 {
                                                 self.path.path.push(self.buf.clone());
                                                 self.buf = String::with_capacity(MAX_FILE_NAME_LENGTH);
-                                                return None;
-                                        } else {
-                                                self.result = Some(Err(PathFormatError::EmptyFileName));
-                                                return Some(Err(PathFormatError::EmptyFileName));
                                         }
+                                        return None;
                                 } else if c == '.' && self.buf.len() == 0 {
                                         self.state = STATE::DirCur;
                                         return None;
@@ -234,6 +252,8 @@ impl PathParser {
                                         self.state = STATE::DirParent;
                                         return None;
                                 } else if valid_fname_char(c) {
+                                        // a name starting with '.', like ".profile"
+                                        self.buf.push('.');
                                         self.buf.push(c);
                                         self.state = STATE::FName;
                                         return None;
@@ -246,9 +266,10 @@ impl PathParser {
                                 if c == '/' {
                                         self.state = STATE::FName;
                                         self.path.path.push(String::from(".."));
-                                        self.buf.pop();
                                         return None;
                                 } else if valid_fname_char(c) {
+                                        // a name starting with "..", like "..data"
+                                        self.buf.push_str("..");
                                         self.buf.push(c);
                                         self.state = STATE::FName;
                                         return None;
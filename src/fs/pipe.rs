@@ -1,11 +1,13 @@
 use core::cmp::min;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use alloc::{collections::VecDeque, string::ToString, sync::{Arc, Weak}, vec::Vec};
-use spin::Mutex;
+use spin::{Mutex, MutexGuard};
 
 use super::{CommonFile, DeviceFile, DirFile, File, file::FileStatus};
 use super::Path;
-use crate::process::ErrNo;
+use super::release_dropped_flocks;
+use crate::process::{ErrNo, WaitQueue, current_signal_pending};
 
 /// Pipe ring buffer and end weak references.
 pub struct Pipe {
@@ -17,6 +19,10 @@ pub struct Pipe {
     read_ends: Vec<Weak<PipeEnd>>,
     /// weak reference to write ends of pipe
     write_ends: Vec<Weak<PipeEnd>>,
+    /// readers waiting for data
+    read_queue: Arc<WaitQueue>,
+    /// writers waiting for room
+    write_queue: Arc<WaitQueue>,
 }
 
 impl Pipe {
@@ -26,7 +32,9 @@ impl Pipe {
                 size: 4096,
                 buffer:VecDeque::new(),
                 read_ends: Vec::new(),
-                write_ends: Vec::new()
+                write_ends: Vec::new(),
+                read_queue: Arc::new(WaitQueue::new()),
+                write_queue: Arc::new(WaitQueue::new()),
             }
         ))
     }
@@ -95,6 +103,20 @@ impl Pipe {
         return true;
     }
 
+    /// Check if all read end are closed.
+    /// # Return
+    /// `true` if all read end has been closed. 
+    pub fn all_read_closed(&self) -> bool {
+        self.read_ends.iter().all(|end| end.upgrade().is_none())
+    }
+
+    /// Check if the ring buffer is full
+    /// # Return
+    /// `true` if there's no room left in the buffer.
+    pub fn full(&self) -> bool {
+        return self.buffer.len() >= self.size as usize;
+    }
+
     /// Check if the ring buffer is empty
     /// # Description
     /// Check if the pipe has nothing in it.
@@ -109,6 +131,8 @@ impl Pipe {
 pub struct PipeEnd {
     /// Flags to indicate read/write privilege
     flags: FileStatus,
+    /// Return TryAgain instead of 0 when the pipe is not ready
+    nonblock: AtomicBool,
     /// shared, locked reference to Pipe (The ring buffer)
     pipe:  Arc<Mutex<Pipe>>
 }
@@ -136,6 +160,7 @@ impl PipeEnd {
                 ctime_sec:  0,
                 ctime_nsec: 0,
             },
+            nonblock: AtomicBool::new(false),
             pipe: pipe.clone()
         });
         pipe.lock().register_read(&ret);
@@ -164,11 +189,85 @@ impl PipeEnd {
                 ctime_sec:  0,
                 ctime_nsec: 0,
             },
+            nonblock: AtomicBool::new(false),
             pipe: pipe.clone()
         });
         pipe.lock().register_write(&ret);
         return ret;
     }
+
+    /// Set or clear the non-blocking mode of the pipe end
+    pub fn set_nonblock(&self, nonblock: bool) {
+        self.nonblock.store(nonblock, Ordering::Relaxed);
+    }
+
+    /// If the pipe end is in non-blocking mode
+    pub fn is_nonblock(&self) -> bool {
+        self.nonblock.load(Ordering::Relaxed)
+    }
+
+    /// The queue a blocked operation on this end sleeps on
+    /// # Description
+    /// Readers wait for data, writers wait for room.
+    pub fn wait_queue(&self) -> Arc<WaitQueue> {
+        let pipe = self.pipe.lock();
+        if self.flags.writeable {
+            pipe.write_queue.clone()
+        } else {
+            pipe.read_queue.clone()
+        }
+    }
+
+    /// Lock the pipe once it can be read from
+    /// # Description
+    /// Sleep on the read queue while the pipe is empty and someone may still write to it.  
+    /// Non-blocking ends never sleep.
+    fn lock_readable(&self, buf_len: usize) -> Result<MutexGuard<Pipe>, ErrNo> {
+        loop {
+            let pipe = self.pipe.lock();
+            if buf_len == 0 || !pipe.empty() || pipe.all_write_closed() || self.is_nonblock() {
+                return Ok(pipe);
+            }
+            let queue = pipe.read_queue.clone();
+            drop(pipe);
+            // a signal needs to be handled first
+            if current_signal_pending() {
+                return Err(ErrNo::InterruptedSystemCall);
+            }
+            queue.wait();
+        }
+    }
+
+    /// Lock the pipe once it can be written to
+    /// # Description
+    /// Sleep on the write queue while the pipe is full and someone may still read from it.  
+    /// Non-blocking ends never sleep.
+    fn lock_writable(&self, buf_len: usize) -> Result<MutexGuard<Pipe>, ErrNo> {
+        loop {
+            let pipe = self.pipe.lock();
+            if buf_len == 0 || !pipe.full() || pipe.all_read_closed() || self.is_nonblock() {
+                return Ok(pipe);
+            }
+            let queue = pipe.write_queue.clone();
+            drop(pipe);
+            // a signal needs to be handled first
+            if current_signal_pending() {
+                return Err(ErrNo::InterruptedSystemCall);
+            }
+            queue.wait();
+        }
+    }
+
+    /// Map a zero-length transfer to TryAgain in non-blocking mode
+    fn check_again(&self, len: usize, buf_len: usize, pipe: &Pipe) -> Result<usize, ErrNo> {
+        if len == 0 && buf_len != 0 && self.is_nonblock() {
+            if self.flags.readable && pipe.all_write_closed() {
+                return Ok(0);
+            }
+            return Err(ErrNo::TryAgain);
+        }
+        Ok(len)
+    }
 }
 
 impl File for PipeEnd {
@@ -181,19 +280,34 @@ impl File for PipeEnd {
     }
 
     fn read(&self, buffer: &mut [u8]) -> Result<usize, ErrNo> {
-        self.pipe.lock().read(buffer)
+        let buf_len = buffer.len();
+        let mut pipe = self.lock_readable(buf_len)?;
+        let len = pipe.read(buffer)?;
+        pipe.write_queue.wake_all();
+        self.check_again(len, buf_len, &pipe)
     }
 
     fn write(&self, buffer: &[u8]) -> Result<usize, ErrNo> {
-        self.pipe.lock().write(buffer)
+        let mut pipe = self.lock_writable(buffer.len())?;
+        let len = pipe.write(buffer)?;
+        pipe.read_queue.wake_all();
+        self.check_again(len, buffer.len(), &pipe)
     }
 
     fn read_user_buffer(&self, buffer: crate::memory::UserBuffer) -> Result<usize, ErrNo> {
-        self.pipe.lock().read_user_buffer(buffer)
+        let buf_len = buffer.len();
+        let mut pipe = self.lock_readable(buf_len)?;
+        let len = pipe.read_user_buffer(buffer)?;
+        pipe.write_queue.wake_all();
+        self.check_again(len, buf_len, &pipe)
     }
 
     fn write_user_buffer(&self, buffer: crate::memory::UserBuffer) -> Result<usize, ErrNo> {
-        self.pipe.lock().write_user_buffer(buffer)
+        let buf_len = buffer.len();
+        let mut pipe = self.lock_writable(buf_len)?;
+        let len = pipe.write_user_buffer(buffer)?;
+        pipe.read_queue.wake_all();
+        self.check_again(len, buf_len, &pipe)
     }
 
     fn to_common_file<'a>(self: Arc<Self>) -> Option<Arc<dyn CommonFile + 'a>> where Self: 'a {
@@ -231,7 +345,15 @@ impl File for PipeEnd {
 
 impl Drop for PipeEnd {
     fn drop(&mut self) {
-        // just die.
+        // wake up the other side, they may be waiting on an end that's now gone.
+        let pipe = self.pipe.lock();
+        if self.flags.writeable {
+            pipe.read_queue.wake_all();
+        } else {
+            pipe.write_queue.wake_all();
+        }
+        drop(pipe);
+        release_dropped_flocks();
     }
 }
 
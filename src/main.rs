@@ -12,7 +12,7 @@
 
 use alloc::string::ToString;
 
-use crate::{config::{U_TRAMPOLINE, TRAMPOLINE}, process::default_handlers::{def_dump_core, def_ignore, def_terminate_self}};
+use crate::{config::{U_TRAMPOLINE, TRAMPOLINE}, process::default_handlers::{def_dump_core, def_terminate_self}};
 
 global_asm!(include_str!("entry.asm"));
 global_asm!(include_str!("link_app.asm"));
@@ -36,6 +36,8 @@ pub mod config;
 pub mod version;
 mod utils;
 mod drivers;
+#[cfg(feature = "kernel_tests")]
+mod self_test;
 
 #[cfg(not(any(feature="board_qemu", feature="board_k210")))]
 compile_error!("At least one of the board_* feature should be active!");
@@ -43,13 +45,14 @@ compile_error!("At least one of the board_* feature should be active!");
 /// Main function for boot sequence
 /// # Description
 /// This is the main function, which is used during the boot sequence.
-/// Will be called by `__start()` in entry.asm, after CRT setup.
+/// Will be called by `__start()` in entry.asm, after CRT setup.  
+/// SBI passes the hart id in a0 and the address of the device tree blob in a1.
 /// # Examples
 /// **DO NOT CALL THIS FUNCTION!**
 /// # Returns
 /// never returns.
 #[no_mangle]
-pub extern "C" fn rust_main() -> !{
+pub extern "C" fn rust_main(_hartid: usize, dtb: usize) -> !{
     print!("{}", config::LOGO);
     info!("Kernel hello world!");
     info!("Vendor id = {}", sbi::get_vendor_id());
@@ -77,20 +80,42 @@ pub extern "C" fn rust_main() -> !{
     debug!("__siginfo: {:x}", __siginfo as usize);
     debug!("def_terminate_self: {:x}", def_terminate_self as usize - sutrampoline as usize + U_TRAMPOLINE);
     debug!("def_dump_core: {:x}", def_dump_core as usize - sutrampoline as usize + U_TRAMPOLINE);
-    debug!("def_ignore: {:x}", def_ignore as usize - sutrampoline as usize + U_TRAMPOLINE);
     // debug!("trampoline: {:x}", TRAMPOLINE);
     debug!("==================================");
 
-    memory::init();
+    memory::init(dtb);
+    if let Some(info) = utils::fdt::board_info() {
+        if info.console_uart && info.uart16550 != 0 {
+            sbi::set_console(sbi::ConsoleBackend::Uart16550(info.uart16550));
+            info!("Console switched to 16550 UART @ 0x{:X}", info.uart16550);
+        }
+    }
     trap::init();
 
     fs::mount_fs("/dev".to_string(), fs::DEV_FS.clone()).unwrap();
     let fat32 = fs::fs_impl::Fat32W::new(fs::open("/dev/block/sda".to_string(), fs::OpenMode::SYS).unwrap()).unwrap();
     // let root = fs::fs_impl::fat32::inode::Inode::root(fat32.inner.clone());
     // fs::fs_impl::fat32::print_file_tree(&root, 0);
-    fs::mount_fs("/".to_string(), alloc::sync::Arc::new(fat32));
+    match fs::initramfs() {
+        Some(archive) => {
+            // userspace comes from the initramfs, the disk is moved to /mnt
+            let tmpfs = fs::fs_impl::TmpFS::new();
+            match fs::unpack(archive, &tmpfs) {
+                Ok(cnt) => info!("initramfs: unpacked {} entries", cnt),
+                Err(errno) => error!("initramfs: unpack failed: {}", errno),
+            }
+            fs::mount_fs("/".to_string(), alloc::sync::Arc::new(tmpfs));
+            fs::mount_fs("/mnt".to_string(), alloc::sync::Arc::new(fat32));
+        },
+        None => {
+            fs::mount_fs("/".to_string(), alloc::sync::Arc::new(fat32));
+        },
+    }
     fs::mount_fs("/proc".to_string(), fs::PROC_FS.clone()).unwrap();
 
+    #[cfg(feature = "kernel_tests")]
+    self_test::run();
+
     process::init();
     panic!("drop off from bottom!");
 }
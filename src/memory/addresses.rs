@@ -10,6 +10,7 @@ use super::PageTableEntry;
 use core::mem::size_of;
 use crate::utils::{
     StepByOne,
+    CheckedStep,
     SimpleRange
 };
 
@@ -388,6 +389,11 @@ impl StepByOne for VirtPageNum {
     fn step(&mut self) { self.0 += 1; }
 }
 
+impl CheckedStep for VirtPageNum {
+    fn checked_forward(self, n: usize) -> Option<Self> { self.0.checked_add(n).map(Self) }
+    fn checked_backward(self, n: usize) -> Option<Self> { self.0.checked_sub(n).map(Self) }
+}
+
 impl StepByOne for PhysPageNum {
     fn step(&mut self) { self.0 += 1; }
 }
@@ -1,13 +1,16 @@
 //! Physical frame allocator for oshit kernel memory management module.
 
-use crate::config::MEM_END;
 use super::{
     PhysPageNum,
     PhysAddr
 };
 use alloc::vec::Vec;
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
 use lazy_static::*;
+use crate::config::FRAME_LOW_WATERMARK;
+use super::reclaim::reclaim;
 
 /// The frame allocator trait. Anything implemented this trait can be our frame allocator
 trait FrameAllocator {
@@ -42,11 +45,14 @@ lazy_static! {
             fn ekernel();
         }
         let start = PhysAddr::from(ekernel as usize).to_ppn_ceil();
-        let stop = PhysAddr::from(MEM_END).to_ppn();
+        let stop = PhysAddr::from(super::mem_end()).to_ppn();
         Mutex::new(StackFrameAllocator::new(start, stop))
     };
 }
 
+/// Set while free frames are below the low watermark
+static BELOW_WATERMARK: AtomicBool = AtomicBool::new(false);
+
 /// Alloc a frame.
 /// # Description
 /// Alloc a physical frame, return Some(FrameTracker) on success, and None on OOM.  
@@ -65,7 +71,20 @@ lazy_static! {
 /// # Return
 /// Some(FrameTracker) on success, None on OOM
 pub fn alloc_frame() -> Option<FrameTracker> {
-    FRAME_ALLOCATOR.lock().alloc().map(|ppn| FrameTracker::new(ppn))
+    let (mut ppn, free) = {
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        (allocator.alloc(), allocator.free_frames())
+    };
+    if free >= FRAME_LOW_WATERMARK {
+        BELOW_WATERMARK.store(false, Ordering::Relaxed);
+    } else if !BELOW_WATERMARK.swap(true, Ordering::Relaxed) || ppn.is_none() {
+        // reclaim once on crossing the watermark, and again whenever we run out
+        warning!("Free frames below low watermark: {} < {}, reclaiming.", free, FRAME_LOW_WATERMARK);
+        if reclaim() > 0 && ppn.is_none() {
+            ppn = FRAME_ALLOCATOR.lock().alloc();
+        }
+    }
+    ppn.map(|ppn| FrameTracker::new(ppn))
 }
 
 pub fn alloc_continuous(size_in_pages: usize) -> Vec<FrameTracker> {
@@ -77,10 +96,29 @@ pub fn alloc_continuous(size_in_pages: usize) -> Vec<FrameTracker> {
     res
 }
 
+/// Take continuous frames for good
+/// # Description
+/// No tracker is made, the frames are never given back. Used to grow the kernel heap.  
+/// Gives up if the allocator is locked, as the heap can run out while it is held.
+pub fn try_alloc_continuous_raw(size_in_pages: usize) -> Option<PhysPageNum> {
+    FRAME_ALLOCATOR.try_lock()?.alloc_continuous(size_in_pages)
+}
+
 pub fn free_frame(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.lock().free(ppn);
 }
 
+/// Number of physical frames managed by the frame allocator, allocated or not.
+pub fn total_frames() -> usize {
+    let allocator = FRAME_ALLOCATOR.lock();
+    allocator.end.0 - allocator.start.0
+}
+
+/// Number of physical frames that can still be allocated.
+pub fn free_frames() -> usize {
+    FRAME_ALLOCATOR.lock().free_frames()
+}
+
 /// The frame tracker, representing a physical frame.  
 /// It's created alone the alloc process, and when it's dropped it automatically free the coresponding page.
 pub struct FrameTracker {
@@ -105,17 +143,115 @@ impl Drop for FrameTracker {
     }
 }
 
+/// A frame mapped by several segments.
+/// # Description
+/// Clones refer to the same frame, which is freed when the last clone is dropped.
+#[derive(Clone)]
+pub struct SharedFrame {
+    inner: Arc<FrameTracker>
+}
+
+impl SharedFrame {
+    /// Share a frame
+    pub fn new(frame: FrameTracker) -> Self {
+        Self { inner: Arc::new(frame) }
+    }
+
+    /// Physical page number of the frame
+    pub fn ppn(&self) -> PhysPageNum {
+        self.inner.ppn
+    }
+
+    /// # of holders of the frame
+    pub fn ref_count(&self) -> usize {
+        Arc::strong_count(&self.inner)
+    }
+}
+
+lazy_static! {
+    /// The frame every untouched anonymous page reads from, never written to
+    static ref ZERO_FRAME: SharedFrame = SharedFrame::new(alloc_frame().unwrap());
+}
+
+/// A new reference to the shared zero frame
+pub fn zero_frame() -> SharedFrame {
+    ZERO_FRAME.clone()
+}
+
+/// Physical page number of the shared zero frame
+pub fn zero_ppn() -> PhysPageNum {
+    ZERO_FRAME.ppn()
+}
+
+/// A frame held by a segment, either its own one or one shared with other segments.
+pub enum SegmentFrame {
+    Owned(FrameTracker),
+    Shared(SharedFrame),
+}
+
+impl SegmentFrame {
+    /// Physical page number of the frame
+    pub fn ppn(&self) -> PhysPageNum {
+        match self {
+            SegmentFrame::Owned(frame) => frame.ppn,
+            SegmentFrame::Shared(frame) => frame.ppn(),
+        }
+    }
+
+    /// If someone else holds the frame too
+    pub fn is_shared(&self) -> bool {
+        match self {
+            SegmentFrame::Owned(_) => false,
+            SegmentFrame::Shared(frame) => frame.ref_count() > 1,
+        }
+    }
+
+    /// If this is the shared zero frame, which must be mapped read only
+    pub fn is_zero(&self) -> bool {
+        self.ppn() == zero_ppn()
+    }
+
+    /// Turn the frame into a shared one, keeping the frame
+    pub fn into_shared(self) -> SharedFrame {
+        match self {
+            SegmentFrame::Owned(frame) => SharedFrame::new(frame),
+            SegmentFrame::Shared(frame) => frame,
+        }
+    }
+}
+
+impl From<FrameTracker> for SegmentFrame {
+    fn from(frame: FrameTracker) -> Self {
+        SegmentFrame::Owned(frame)
+    }
+}
+
+impl From<SharedFrame> for SegmentFrame {
+    fn from(frame: SharedFrame) -> Self {
+        SegmentFrame::Shared(frame)
+    }
+}
+
 /// The Frame-Allocator-of-choice.
 /// A stack frame allocator, keeps records of current freed pages and unallocated pages.
 pub struct StackFrameAllocator {
+    start   : PhysPageNum,
     current : PhysPageNum,
     end     : PhysPageNum,
     freed   : Vec<PhysPageNum>
 }
 
+impl StackFrameAllocator {
+    /// Number of frames that can still be allocated
+    fn free_frames(&self) -> usize {
+        self.end.0 - self.current.0 + self.freed.len()
+    }
+}
+
 impl FrameAllocator for StackFrameAllocator {
     fn new(start: PhysPageNum, stop: PhysPageNum) -> Self {
         Self {
+            start   : start,
             current : start,
             end     : stop,
             freed   : Vec::new()
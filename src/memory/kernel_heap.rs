@@ -1,17 +1,184 @@
 //! Kernem dynamic memory allocator for oshit kernel.
 
 use buddy_system_allocator::LockedHeap;
-use crate::config::KERNEL_HEAP_SIZE;
+use crate::config::{KERNEL_HEAP_SIZE, KERNEL_HEAP_OOM_RESERVE, KERNEL_HEAP_GROW_PAGES, KERNEL_HEAP_MAX_SIZE, PAGE_SIZE};
+use super::frame_alloc::try_alloc_continuous_raw;
+use super::PhysAddr;
 use alloc::boxed::Box;
 use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use crate::process::{current_pid, in_syscall};
+
+/// The buddy heap, with the bytes handed out accounted.
+struct AccountedHeap {
+    heap: LockedHeap,
+    /// emergency heap over `OOM_RESERVE_SPACE`, for syscalls that exhausted `heap`
+    reserve: LockedHeap,
+    /// pid of the process to kill for having used `reserve`, `NO_VICTIM` if none
+    oom_victim: AtomicUsize,
+    /// bytes currently allocated from `heap`
+    used: AtomicUsize,
+    /// high-water mark of `used`
+    peak: AtomicUsize,
+    /// allocations served by `heap` since boot
+    allocs: AtomicUsize,
+    /// bytes handed to the buddy heap, `KERNEL_HEAP_SIZE` plus what it grew
+    capacity: AtomicUsize,
+    /// a grow failed, don't grow ahead of time any more
+    grow_failed: AtomicBool,
+    /// growing is allowed, see `set_kernel_heap_growable()`
+    growable: AtomicBool,
+}
+
+impl AccountedHeap {
+    /// Add frames to the heap so that `layout` fits
+    /// # Description
+    /// The frames are identity mapped in kernel space already, so nothing needs to be mapped.
+    /// # Return
+    /// False if growing is disabled, the heap is at `KERNEL_HEAP_MAX_SIZE` or there are no frames
+    fn grow(&self, layout: Layout) -> bool {
+        if !self.growable.load(Ordering::Relaxed) {
+            return false;
+        }
+        // buddy blocks are aligned to their size, leave room to align the request
+        let needed = (layout.size().max(layout.align()) * 2 + PAGE_SIZE - 1) / PAGE_SIZE;
+        let pages = needed.max(KERNEL_HEAP_GROW_PAGES);
+        let capacity = self.capacity.load(Ordering::Relaxed);
+        if capacity + pages * PAGE_SIZE > KERNEL_HEAP_MAX_SIZE {
+            self.grow_failed.store(true, Ordering::Relaxed);
+            return false;
+        }
+        let start = match try_alloc_continuous_raw(pages) {
+            Some(ppn) => PhysAddr::from(ppn).0,
+            None => {
+                self.grow_failed.store(true, Ordering::Relaxed);
+                return false;
+            }
+        };
+        unsafe {
+            self.heap.lock().add_to_heap(start, start + pages * PAGE_SIZE);
+        }
+        self.capacity.fetch_add(pages * PAGE_SIZE, Ordering::Relaxed);
+        true
+    }
+
+    /// Alloc from the buddy heap, growing it or falling back to the reserve in a syscall
+    /// # Description
+    /// The syscall may hold any lock at this point, so its process can't be killed here.
+    /// It is marked as the victim instead and gets SIGKILL on its way back to user mode, see `take_heap_oom_victim()`.  
+    /// proc0 must not die, it never gets the reserve.
+    unsafe fn alloc_or_reserve(&self, layout: Layout) -> *mut u8 {
+        let mut ptr = self.heap.alloc(layout);
+        if ptr.is_null() && self.grow(layout) {
+            ptr = self.heap.alloc(layout);
+        }
+        if !ptr.is_null() {
+            let used = self.used.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            self.peak.fetch_max(used, Ordering::Relaxed);
+            self.allocs.fetch_add(1, Ordering::Relaxed);
+            // grow ahead of time once 7/8 are in use, growing takes frames from the allocator and is best done before it is needed
+            if used > self.capacity.load(Ordering::Relaxed) / 8 * 7 && !self.grow_failed.load(Ordering::Relaxed) {
+                self.grow(layout);
+            }
+            return ptr;
+        }
+        if !in_syscall() {
+            return ptr;
+        }
+        let pid = match current_pid() {
+            Some(pid) if pid != 0 => pid,
+            _ => return ptr,
+        };
+        let ptr = self.reserve.alloc(layout);
+        if !ptr.is_null() {
+            // one victim at a time, another process on the reserve is marked if still short once the first one is gone
+            let _ = self.oom_victim.compare_exchange(NO_VICTIM, pid, Ordering::Relaxed, Ordering::Relaxed);
+        }
+        ptr
+    }
+}
+
+unsafe impl GlobalAlloc for AccountedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.alloc_or_reserve(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let reserve = OOM_RESERVE_SPACE.as_ptr() as usize..OOM_RESERVE_SPACE.as_ptr() as usize + KERNEL_HEAP_OOM_RESERVE;
+        if reserve.contains(&(ptr as usize)) {
+            self.reserve.dealloc(ptr, layout);
+            return;
+        }
+        self.heap.dealloc(ptr, layout);
+        self.used.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+/// `oom_victim` is empty
+const NO_VICTIM: usize = usize::MAX;
 
 /// The global allocator, enables us to use extern alloc crate.
 #[global_allocator]
-static KERNEL_HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static KERNEL_HEAP_ALLOCATOR: AccountedHeap = AccountedHeap {
+    heap: LockedHeap::empty(),
+    reserve: LockedHeap::empty(),
+    oom_victim: AtomicUsize::new(NO_VICTIM),
+    used: AtomicUsize::new(0),
+    peak: AtomicUsize::new(0),
+    allocs: AtomicUsize::new(0),
+    capacity: AtomicUsize::new(KERNEL_HEAP_SIZE),
+    grow_failed: AtomicBool::new(false),
+    growable: AtomicBool::new(KERNEL_HEAP_GROW_PAGES != 0),
+};
+
+/// Bytes of kernel heap currently allocated.
+pub fn kernel_heap_used() -> usize {
+    KERNEL_HEAP_ALLOCATOR.used.load(Ordering::Relaxed)
+}
+
+/// Most bytes of kernel heap ever allocated at once.
+pub fn kernel_heap_peak() -> usize {
+    KERNEL_HEAP_ALLOCATOR.peak.load(Ordering::Relaxed)
+}
+
+/// Allocations served by the kernel heap since boot.
+pub fn kernel_heap_allocs() -> usize {
+    KERNEL_HEAP_ALLOCATOR.allocs.load(Ordering::Relaxed)
+}
+
+/// Bytes the kernel heap can hold now, including what it grew.
+pub fn kernel_heap_capacity() -> usize {
+    KERNEL_HEAP_ALLOCATOR.capacity.load(Ordering::Relaxed)
+}
+
+/// Allow or forbid growing the kernel heap with frames
+/// # Description
+/// Growing is on unless `KERNEL_HEAP_GROW_PAGES` is 0. Frames the heap grew by are never given back.
+/// # Return
+/// If it was allowed before
+pub fn set_kernel_heap_growable(growable: bool) -> bool {
+    KERNEL_HEAP_ALLOCATOR.growable.swap(growable, Ordering::Relaxed)
+}
+
+/// Print kernel heap usage, for debugging leaks.
+pub fn dump_heap_usage() {
+    info!("Kernel heap: {} bytes used, {} bytes peak, {} bytes total", kernel_heap_used(), kernel_heap_peak(), kernel_heap_capacity());
+}
 
 /// The empty space to use as kernel heap.
 static mut HEAP_SPACE: [u8; KERNEL_HEAP_SIZE] = [0; KERNEL_HEAP_SIZE];
 
+/// The space of the emergency heap.
+static mut OOM_RESERVE_SPACE: [u8; KERNEL_HEAP_OOM_RESERVE] = [0; KERNEL_HEAP_OOM_RESERVE];
+
+/// Check if process `pid` lived on the emergency heap, and clear the mark
+/// # Description
+/// To be called with no lock held, the caller sends SIGKILL on true.
+pub fn take_heap_oom_victim(pid: usize) -> bool {
+    KERNEL_HEAP_ALLOCATOR.oom_victim.compare_exchange(pid, NO_VICTIM, Ordering::Relaxed, Ordering::Relaxed).is_ok()
+}
+
 /// The kernel heap test.
 /// # Description
 /// The kerenl heap test. Panic if failed.
@@ -44,15 +211,20 @@ pub fn init_kernel_heap() {
     debug!("Initializing kernel heap space...");
     verbose!("Kernel heap start @ 0x{:0X}, length 0x{:0X}", unsafe{HEAP_SPACE.as_ptr()} as usize, KERNEL_HEAP_SIZE);
     unsafe {
-        KERNEL_HEAP_ALLOCATOR.lock().init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
+        KERNEL_HEAP_ALLOCATOR.heap.lock().init(HEAP_SPACE.as_ptr() as usize, KERNEL_HEAP_SIZE);
+        KERNEL_HEAP_ALLOCATOR.reserve.lock().init(OOM_RESERVE_SPACE.as_ptr() as usize, KERNEL_HEAP_OOM_RESERVE);
     }
     heap_test();
+    dump_heap_usage();
     info!("Kernel heap initialized.");
 }
 
 /// Alloc error handler
-/// Panic on allocation error.
+/// # Description
+/// Syscalls are served from the emergency heap when the kernel heap runs out, see `alloc_or_reserve()`.
+/// Getting here means that ran out too, or the kernel itself is out of heap, it can't go on.
 #[alloc_error_handler]
 pub fn on_alloc_error(layout: core::alloc::Layout) -> ! {
+    dump_heap_usage();
     panic!("Kernel heap allocation error on allocating layout {:?}. OOM?", layout);
 }
\ No newline at end of file
@@ -6,24 +6,31 @@ use super::{
     VirtAddr,
     PhysPageNum,
     PhysAddr,
-    FrameTracker,
+    SegmentFrame,
+    SharedFrame,
     PageTable,
     PageTableEntry,
     PTEFlags,
+    MEGAPAGE_PAGES,
     alloc_frame,
+    zero_frame,
+    zero_ppn,
     UserBuffer
 };
 use core::mem::size_of;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use _core::convert::TryInto;
 use _core::fmt::Write;
 use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
+use alloc::string::String;
+use alloc::format;
 use bitflags::*;
 use crate::config::*;
 use crate::fs::{File, SeekOp};
 use crate::process::{AuxHeader, AuxType, CloneFlags};
 use core::cmp::min;
-use crate::utils::{SimpleRange, StepByOne};
+use crate::utils::{SimpleRange, StepByOne, CheckedStep};
 use lazy_static::*;
 use alloc::sync::Arc;
 use spin::{Mutex, MutexGuard};
@@ -31,6 +38,9 @@ use riscv::register::satp;
 use core::fmt::{self, Debug, Formatter};
 use crate::process::ErrNo;
 
+/// Pages handed out by get_continuous_space are below this VPN
+const MMAP_TOP_VPN: usize = 0xffff_ffff_ff00_0;
+
 lazy_static! {
     /// The kernel space memory layout.
     pub static ref KERNEL_MEM_LAYOUT: Arc<Mutex<MemLayout>> = Arc::new(Mutex::new(MemLayout::new_kernel()));
@@ -54,6 +64,8 @@ pub enum MapType {
     Framed,
     /// Virtual memory layout 
     VMA,
+    /// Anonymous mapping, zeroed physical pages are allocated on first access
+    Anonymous,
 }
 
 bitflags! {
@@ -91,8 +103,8 @@ pub struct Segment {
     /// range of the Segment, [range.start()..range.end())
     pub range   : VPNRange,
     /// allocated physical frames, aloneside with their virtual page number.  
-    /// It holds the FrameTracker so that it's not dropped.
-    pub frames  : BTreeMap<VirtPageNum, FrameTracker>,
+    /// It holds the frames so that they are not dropped, shared ones live on until every holder drops them.
+    pub frames  : BTreeMap<VirtPageNum, SegmentFrame>,
     /// the mapping type (identity or framed)
     pub map_type: MapType,
     /// the flags
@@ -136,6 +148,38 @@ impl Segment {
         }
     }
 
+    /// Permissions in the /proc/<pid>/maps style, e.g. "r-xp"
+    pub fn perm_string(&self) -> String {
+        let (r, w, x) = if self.map_type == MapType::VMA {
+            (self.vma_flags.contains(VMAFlags::R), self.vma_flags.contains(VMAFlags::W), self.vma_flags.contains(VMAFlags::X))
+        } else {
+            (self.seg_flags.contains(SegmentFlags::R), self.seg_flags.contains(SegmentFlags::W), self.seg_flags.contains(SegmentFlags::X))
+        };
+        let mut perm = String::new();
+        perm.push(if r {'r'} else {'-'});
+        perm.push(if w {'w'} else {'-'});
+        perm.push(if x {'x'} else {'-'});
+        perm.push('p');
+        perm
+    }
+
+    /// Path of the mapped file, empty if the segment is not file backed
+    pub fn backing_name(&self) -> String {
+        match &self.file {
+            Some(file) => file.get_path().to_string(),
+            None => String::new(),
+        }
+    }
+
+    /// # of pages backed by physical frames now
+    pub fn resident_pages(&self) -> usize {
+        match self.map_type {
+            MapType::Identity => self.range.get_end() - self.range.get_start(),
+            // the zero frame belongs to nobody
+            _ => self.frames.values().filter(|frame| !frame.is_zero()).count(),
+        }
+    }
+
     /// Alloc and map a page in the segment
     /// # Description
     /// Alloc and map the page `vpn` in the segment, using the `pagetable` as pagetable
@@ -152,14 +196,13 @@ impl Segment {
         match self.map_type {
             MapType::Identity => {
                 ppn = PhysPageNum(vpn.0);
-                pagetable.map(vpn, ppn, PTEFlags::from_bits(self.seg_flags.bits).unwrap());
-                Ok(())
+                pagetable.map(vpn, ppn, PTEFlags::from_bits(self.seg_flags.bits).unwrap())
             },
             MapType::Framed => {
                 if let Some(frame) = alloc_frame() {
                     ppn = frame.ppn;
-                    self.frames.insert(vpn, frame);
-                    pagetable.map(vpn, ppn, PTEFlags::from_bits(self.seg_flags.bits).unwrap());
+                    pagetable.map(vpn, ppn, PTEFlags::from_bits(self.seg_flags.bits).unwrap())?;
+                    self.frames.insert(vpn, frame.into());
                     // verbose!("Mapped framed page: {:?}<=>{:?}, flag {:?}", vpn, ppn, PTEFlags::from_bits(self.segFlags.bits).unwrap());
                     Ok(())
                 } else {
@@ -167,6 +210,10 @@ impl Segment {
                 }
                 
             },
+            MapType::Anonymous => {
+                // only the parent ptes, the leaf is filled on fault
+                pagetable.reserve(vpn)
+            },
             MapType::VMA => {
                 // let frame = alloc_frame().unwrap();
                 // ppn = frame.ppn;
@@ -185,14 +232,14 @@ impl Segment {
             return Err(ErrNo::BadAddress)
         }
         
-        let frame = alloc_frame().unwrap();
+        let frame = alloc_frame().ok_or(ErrNo::OutOfMemory)?;
         let ppn = frame.ppn;
 
         let bytes = ppn.page_ptr();
         let optfile = self.file.clone().unwrap();
         let inner_file = optfile.to_common_file().unwrap();
         let cur = inner_file.get_cursor()?;
-        let offset: isize = (va - VirtAddr::from(self.range.get_start()) - self.offset).try_into().unwrap();
+        let offset: isize = (va - VirtAddr::from(self.range.get_start()) + self.offset).try_into().unwrap();
         let offset = offset - offset % PAGE_SIZE as isize;
         inner_file.seek(offset, SeekOp::SET).unwrap();
         let res = inner_file.read(bytes);
@@ -203,12 +250,102 @@ impl Segment {
             return Err(msg);
         }
 
-        self.frames.insert(vpn, frame);
+        pagetable.map(vpn, ppn, PTEFlags::from_bits(self.vma_flags.bits).unwrap() | PTEFlags::U)?;
+        self.frames.insert(vpn, frame.into());
         verbose!("Lazy mapped: {:?} <=> {:?}", vpn, ppn);
-        pagetable.map(vpn, ppn, PTEFlags::from_bits(self.vma_flags.bits).unwrap() | PTEFlags::U);
         Ok(())
     }
     
+    /// Alloc and map a page in an anonymous segment
+    /// # Description
+    /// Alloc a zeroed frame for `vpn` and fill in the leaf pte reserved by `map_page()`.  
+    /// Only needs a shared reference to the pagetable, so that the kernel can fault in user pages while accessing them.
+    pub fn map_anonymous_page(&mut self, pagetable: &PageTable, vpn: VirtPageNum) -> Result<(), ErrNo> {
+        if self.map_type != MapType::Anonymous || vpn < self.range.get_start() || vpn >= self.range.get_end() {
+            return Err(ErrNo::BadAddress);
+        }
+        let pte = pagetable.walk(vpn).ok_or(ErrNo::BadAddress)?;
+        // a page reading from the zero frame gets its private frame now
+        let on_zero = self.frames.get(&vpn).map_or(false, |frame| frame.is_zero());
+        if pte.valid() && !on_zero {
+            return Ok(());
+        }
+        let flags = PTEFlags::from_bits(self.seg_flags.bits).unwrap() | PTEFlags::V;
+        if let Some(frame) = self.frames.get(&vpn).filter(|_| !on_zero) {
+            // touched through another pagetable sharing this segment
+            *pte = PageTableEntry::new(frame.ppn(), flags);
+            pagetable.flush_tlb(vpn);
+            return Ok(());
+        }
+        let frame = alloc_frame().ok_or(ErrNo::OutOfMemory)?;
+        *pte = PageTableEntry::new(frame.ppn, flags);
+        pagetable.flush_tlb(vpn);
+        verbose!("Anonymous page mapped: {:?} <=> {:?}", vpn, frame.ppn);
+        // drops our reference to the zero frame, if any
+        self.frames.insert(vpn, frame.into());
+        Ok(())
+    }
+
+    /// Map the zero frame read only at `vpn` in an anonymous segment
+    /// # Description
+    /// Reading an untouched page needs no frame of its own. The first write faults, and `map_anonymous_page()` gives the page a private frame.  
+    /// Not for segments shared by several pagetables, they would end up on different frames after the write.
+    pub fn map_zero_page(&mut self, pagetable: &PageTable, vpn: VirtPageNum) -> Result<(), ErrNo> {
+        if self.map_type != MapType::Anonymous || vpn < self.range.get_start() || vpn >= self.range.get_end() {
+            return Err(ErrNo::BadAddress);
+        }
+        if self.frames.contains_key(&vpn) {
+            return self.map_anonymous_page(pagetable, vpn);
+        }
+        let pte = pagetable.walk(vpn).ok_or(ErrNo::BadAddress)?;
+        if pte.valid() {
+            return Ok(());
+        }
+        let flags = (PTEFlags::from_bits(self.seg_flags.bits).unwrap() | PTEFlags::V) - PTEFlags::W;
+        *pte = PageTableEntry::new(zero_ppn(), flags);
+        pagetable.flush_tlb(vpn);
+        self.frames.insert(vpn, zero_frame().into());
+        Ok(())
+    }
+
+    /// Give every page on the zero frame a private one
+    /// # Description
+    /// Must be done before the segment is shared with another pagetable, see `map_zero_page()`.
+    pub fn unshare_zero_pages(&mut self, pagetable: &PageTable) -> Result<(), ErrNo> {
+        let on_zero: Vec<VirtPageNum> = self.frames.iter()
+            .filter(|(_, frame)| frame.is_zero())
+            .map(|(vpn, _)| *vpn)
+            .collect();
+        for vpn in on_zero {
+            self.map_anonymous_page(pagetable, vpn)?;
+        }
+        Ok(())
+    }
+
+    /// Share the frame of `vpn`, so that another segment can map it too
+    /// # Return
+    /// The shared frame, None if `vpn` has no frame in this segment
+    pub fn share_frame(&mut self, vpn: VirtPageNum) -> Option<SharedFrame> {
+        let frame = self.frames.remove(&vpn)?.into_shared();
+        self.frames.insert(vpn, frame.clone().into());
+        Some(frame)
+    }
+
+    /// Map `frame`, held by other segments too, at `vpn` with `flags`
+    /// # Description
+    /// The frame stays alive until this segment and all the others have dropped it.
+    pub fn map_shared_frame(&mut self, pagetable: &mut PageTable, vpn: VirtPageNum, frame: SharedFrame, flags: PTEFlags) -> Result<(), ErrNo> {
+        if vpn < self.range.get_start() || vpn >= self.range.get_end() || self.frames.contains_key(&vpn) {
+            return Err(ErrNo::BadAddress);
+        }
+        if pagetable.translate(vpn).map_or(false, |pte| pte.valid()) {
+            return Err(ErrNo::FileExists);
+        }
+        pagetable.map(vpn, frame.ppn(), flags)?;
+        self.frames.insert(vpn, frame.into());
+        Ok(())
+    }
+
     pub fn adjust_end(&mut self, pagetable: &mut PageTable, new_end: VirtPageNum) -> Option<()> {
         // We need to align the end to the 4K border of the page
         // let new_end = self.range.get_end() + (VirtAddr::from(sz)).0;
@@ -231,6 +368,7 @@ impl Segment {
                     for j in original_end.0..i {
                         self.unmap_page(pagetable, j.into())
                     }
+                    self.range.set_end(original_end);
                     return None;
                 }
             }
@@ -254,6 +392,11 @@ impl Segment {
         if self.map_type == MapType::Framed {
             // verbose!("Unmapping page {:?}", vpn);
             self.frames.remove(&vpn);
+        } else if self.map_type == MapType::Anonymous {
+            // never touched, or touched only through another pagetable sharing this segment
+            if self.frames.remove(&vpn).is_none() || !pagetable.translate(vpn).map_or(false, |pte| pte.valid()) {
+                return;
+            }
         } else if self.map_type == MapType::VMA {
             verbose!("Unmapping vma");
             if let Some(pte) = pagetable.walk(vpn) {
@@ -291,7 +434,22 @@ impl Segment {
     /// let mut segment: Segment = Segment::new(0x10010000.into(), 0x10020000.into(), MapType::Identity, SegmentFlags::R);
     /// segment.map_pages(pagetable);
     /// ```
+    /// Identity segments use 2 MiB megapages where alignment permits, and 4 KiB pages at the unaligned edges.
     pub fn map_pages(&mut self, pagetable: &mut PageTable) {
+        if self.map_type == MapType::Identity {
+            let flags = PTEFlags::from_bits(self.seg_flags.bits).unwrap();
+            let mut vpn = self.range.get_start();
+            while vpn < self.range.get_end() {
+                if vpn.0 % MEGAPAGE_PAGES == 0 && vpn.0 + MEGAPAGE_PAGES <= self.range.get_end().0
+                    && pagetable.map_mega(vpn, PhysPageNum(vpn.0), flags).unwrap() {
+                    vpn = VirtPageNum(vpn.0 + MEGAPAGE_PAGES);
+                } else {
+                    self.map_page(pagetable, vpn).unwrap();
+                    vpn.step();
+                }
+            }
+            return;
+        }
         for vpn in self.range {
             self.map_page(pagetable, vpn).unwrap();
         }
@@ -380,6 +538,8 @@ pub struct MemLayout {
     pub pagetable   : PageTable,
     /// The segments in this memory layout.
     pub segments    : Vec<Arc<Mutex<Segment>>>,
+    /// # of pages backed by frames in the segments, kept up to date on map, unmap and fault.
+    rss         : AtomicUsize,
 }
 
 impl MemLayout {
@@ -389,9 +549,89 @@ impl MemLayout {
             let segment = m_segment.lock();
             println!("\tSegment {}:", idx);
             println!("\t\tRange      : {:?} <=> {:?}", segment.range.get_start(), segment.range.get_end());
+            println!("\t\tMap type   : {:?}", segment.map_type);
             println!("\t\tPermissions: {:?}", segment.seg_flags);
             println!("\t\tVMA stuff  : {:?}", segment.vma_flags);
+            println!("\t\tFile       : {} @ 0x{:x}", segment.backing_name(), segment.offset);
+            println!("\t\tResident   : {} pages", segment.resident_pages());
+        }
+    }
+
+    /// # of pages backed by physical frames, summed over all segments
+    pub fn resident_pages(&self) -> usize {
+        self.rss.load(Ordering::Relaxed)
+    }
+
+    /// Account for a segment of the layout going from `before` to `after` frames.
+    /// Segments shared by CLONE_VM may be faulted in through another layout,
+    /// so the counter saturates at zero instead of wrapping.
+    fn account_frames(&self, before: usize, after: usize) {
+        if after > before {
+            self.rss.fetch_add(after - before, Ordering::Relaxed);
+        } else if before > after {
+            let _ = self.rss.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |rss| Some(rss.saturating_sub(before - after)));
+        }
+    }
+
+    /// # of pages covered by segments, touched or not
+    pub fn virtual_pages(&self) -> usize {
+        self.segments.iter().map(|m_seg| {
+            let seg = m_seg.lock();
+            seg.range.get_end() - seg.range.get_start()
+        }).sum()
+    }
+
+    /// Drop clean pages of file mappings
+    /// # Description
+    /// They are read back from the file on the next fault. Segments shared with another layout or locked are skipped,
+    /// so this is safe while a fault on this layout is being handled.  
+    /// D is set by the hart on user writes and by `mark_dirty()` on kernel writes, a page without it holds what the file does.
+    /// # Return
+    /// # of frames released, shared frames another segment still holds are not
+    pub fn reclaim_clean_pages(&mut self) -> usize {
+        let mut freed = 0;
+        for m_seg in self.segments.iter() {
+            // pages of a shared segment may be mapped in the other pagetable too
+            if Arc::strong_count(m_seg) != 1 {
+                continue;
+            }
+            let mut seg = match m_seg.try_lock() {
+                Some(seg) => seg,
+                None => continue,
+            };
+            if seg.map_type != MapType::VMA {
+                continue;
+            }
+            let clean: Vec<VirtPageNum> = seg.frames.keys().filter(|vpn| {
+                self.pagetable.translate(**vpn).map_or(false, |pte| pte.valid() && !pte.dirty())
+            }).cloned().collect();
+            let before = seg.frames.len();
+            for vpn in clean {
+                self.pagetable.unmap(vpn);
+                // a frame shared with another segment lives on
+                if seg.frames.remove(&vpn).map_or(false, |frame| !frame.is_shared()) {
+                    freed += 1;
+                }
+            }
+            self.account_frames(before, seg.frames.len());
+        }
+        freed
+    }
+
+    /// Render the segments like /proc/self/smaps
+    /// # Description
+    /// One header line per segment in the maps format, followed by its size and resident size in kB.
+    pub fn smaps(&self) -> String {
+        let mut content = String::new();
+        for m_segment in self.segments.iter() {
+            let segment = m_segment.lock();
+            let start = VirtAddr::from(segment.range.get_start()).0;
+            let stop = VirtAddr::from(segment.range.get_end()).0;
+            content += &format!("{:08x}-{:08x} {} {:08x} 00:00 0 {}\n", start, stop, segment.perm_string(), segment.offset, segment.backing_name());
+            content += &format!("Size:           {:8} kB\n", (stop - start) / 1024);
+            content += &format!("Rss:            {:8} kB\n", segment.resident_pages() * PAGE_SIZE / 1024);
         }
+        content
     }
 
 
@@ -400,6 +640,7 @@ impl MemLayout {
         Self {
             pagetable   : PageTable::new(),
             segments    : Vec::new(),
+            rss         : AtomicUsize::new(0),
         }
     }
 
@@ -427,13 +668,47 @@ impl MemLayout {
         return layout;
     }
 
-    pub fn clone_from_user(src: &MemLayout, flags: CloneFlags)-> Self {
+    /// Copy the layout of a user process for a new one
+    /// # Description
+    /// With `CloneFlags::VM` the segments are shared, otherwise their pages are copied.
+    /// Shared anonymous segments can't have pages on the zero frame, those are given frames of their own in `src` first.
+    /// # Return
+    /// Err(OutOfMemory) if there are no frames for that
+    pub fn clone_from_user(src: &MemLayout, flags: CloneFlags) -> Result<Self, ErrNo> {
+        if flags.contains(CloneFlags::VM) {
+            for m_segment in src.segments.iter() {
+                let mut segment = m_segment.lock();
+                if segment.map_type == MapType::Anonymous {
+                    let before = segment.resident_pages();
+                    let result = segment.unshare_zero_pages(&src.pagetable);
+                    src.account_frames(before, segment.resident_pages());
+                    result?;
+                }
+            }
+        }
         let mut layout = Self::new();
         layout.map_trampoline();
         for m_segment in src.segments.iter() {
             let segment = m_segment.lock();
             if flags.contains(CloneFlags::VM) {
+                drop(segment);
                 layout.add_segment(m_segment.clone());
+            } else if segment.map_type == MapType::Anonymous {
+                // only copy the pages that have been touched
+                let mut new_segment = Segment::clone_from(&segment);
+                new_segment.map_pages(&mut layout.pagetable);
+                for (vpn, frame) in segment.frames.iter() {
+                    if frame.is_zero() {
+                        new_segment.map_zero_page(&layout.pagetable, *vpn)?;
+                        continue;
+                    }
+                    new_segment.map_anonymous_page(&layout.pagetable, *vpn)?;
+                    let src_ppn = src.translate(*vpn).unwrap().ppn();
+                    let dst_ppn = layout.translate(*vpn).unwrap().ppn();
+                    dst_ppn.page_ptr().copy_from_slice(src_ppn.page_ptr());
+                }
+                layout.account_frames(0, new_segment.resident_pages());
+                layout.segments.push(Arc::new(Mutex::new(new_segment)));
             } else {
                 let new_segment = Segment::clone_from(&segment);
                 layout.add_segment(Arc::new(Mutex::new(new_segment)));
@@ -444,14 +719,17 @@ impl MemLayout {
                 }
             }
         }
-        return layout;
+        Ok(layout)
     }
 
     pub fn alter_segment(&mut self, old_end: VirtPageNum, new_end: VirtPageNum) -> Option<()> {
-        for m_segment in self.segments.iter_mut() {
+        for m_segment in self.segments.iter() {
             let mut segment = m_segment.lock();
             if segment.range.get_end() == old_end {
-                return segment.adjust_end(&mut self.pagetable, new_end)
+                let before = segment.resident_pages();
+                let result = segment.adjust_end(&mut self.pagetable, new_end);
+                self.account_frames(before, segment.resident_pages());
+                return result;
             }
         }
         error!("No segment end with {:?}", old_end);
@@ -543,13 +821,13 @@ impl MemLayout {
             let o_a_to_split = o_to_split.unwrap();
             let mut original_segment = o_a_to_split.lock();
             // TODO: support m_protect for mmaped VMAs
-            if original_segment.map_type != MapType::Framed {
+            if original_segment.map_type != MapType::Framed && original_segment.map_type != MapType::Anonymous {
                 fatal!("Cannot change access to non-Framed segments!");
                 return None;
             }
             // add split head
             if head_start < head_stop {
-                let mut head_frame_trackers: BTreeMap<VirtPageNum, FrameTracker> = BTreeMap::new();
+                let mut head_frame_trackers: BTreeMap<VirtPageNum, SegmentFrame> = BTreeMap::new();
                 for head_vpn in SimpleRange::new(head_start, head_stop) {
                     // if lazy, then no frame trackers
                     if let Some(ft) = original_segment.frames.remove(&head_vpn) {
@@ -572,7 +850,7 @@ impl MemLayout {
 
             // add split tail
             if new_stop < tail_stop {
-                let mut tail_frame_trackers: BTreeMap<VirtPageNum, FrameTracker> = BTreeMap::new();
+                let mut tail_frame_trackers: BTreeMap<VirtPageNum, SegmentFrame> = BTreeMap::new();
                 for tail_vpn in SimpleRange::new(new_stop, tail_stop) {
                     // if lazy, then no frame trackers
                     if let Some(ft) = original_segment.frames.remove(&tail_vpn) {
@@ -594,7 +872,12 @@ impl MemLayout {
 
             // change original segment to new segment
             for new_vpn in VPNRange::new(head_stop, new_stop) {
-                self.pagetable.modify_access(new_vpn, flags);
+                // pages on the zero frame stay read only until written
+                if original_segment.frames.get(&new_vpn).map_or(false, |frame| frame.is_zero()) {
+                    self.pagetable.modify_access(new_vpn, flags - PTEFlags::W);
+                } else {
+                    self.pagetable.modify_access(new_vpn, flags);
+                }
             }
             original_segment.seg_flags = flags.to_seg_flag();
             original_segment.range = VPNRange::new(head_stop, new_stop);
@@ -625,7 +908,11 @@ impl MemLayout {
     /// # Description
     /// Add a segment to this layout, map it and allocate corresponding physical pages.
     pub fn add_segment(&mut self, segment: Arc<Mutex<Segment>>) {
-        segment.lock().map_pages(&mut self.pagetable);
+        {
+            let mut locked = segment.lock();
+            locked.map_pages(&mut self.pagetable);
+            self.account_frames(0, locked.frames.len());
+        }
         self.segments.push(segment);
     }
 
@@ -643,6 +930,7 @@ impl MemLayout {
     pub fn add_segment_with_source(&mut self, mut segment: Segment, data: &[u8]) {
         segment.map_pages(&mut self.pagetable);
         segment.write(&mut self.pagetable, data);
+        self.account_frames(0, segment.frames.len());
         verbose!("add_segment_with_source Mapping Segment with start = {:?}, end = {:?}", VirtAddr::from(segment.range.get_start()), VirtAddr::from(segment.range.get_end()));
         self.segments.push(Arc::new(Mutex::new(segment)));
     }
@@ -733,11 +1021,12 @@ impl MemLayout {
         debug!(".bss mapped @ 0x{:X} ~ 0x{:X} (identity), RW--.", sbss_with_stack as usize, sbss_with_stack as usize);
         
         verbose!("Mapping rest physical memory as identical...");
+        let mem_end = super::mem_end();
         layout.add_segment(
             Arc::new(Mutex::new(
                 Segment::new(
                     VirtAddr::from(ekernel as usize), 
-                    VirtAddr::from(MEM_END),
+                    VirtAddr::from(mem_end),
                     MapType::Identity,
                     SegmentFlags::R | SegmentFlags::W,
                     VMAFlags::empty(),
@@ -746,10 +1035,10 @@ impl MemLayout {
                 )
             ))
         );
-        debug!("Physical memory mapped @ 0x{:X} ~ 0x{:X} (identity), RW--.", ekernel as usize, MEM_END);
+        debug!("Physical memory mapped @ 0x{:X} ~ 0x{:X} (identity), RW--.", ekernel as usize, mem_end);
 
         verbose!("Mapping MMIO...");
-        for pair in MMIO {
+        for pair in super::mmio_regions().iter() {
             layout.add_segment(
                 Arc::new(Mutex::new(
                     Segment::new(
@@ -773,9 +1062,11 @@ impl MemLayout {
     /// Construct a new user memory layout
     /// # Description
     /// Construct a new user memory layout, including all elf segments, user stacks and trampoline.  
-    /// Also can use bare bin file for compatbility.
-    // todo: no kernel panic on user's fault -- just fail it's syscall. use a Result to wrap the return value.
-    pub fn new_elf(elf_data: &[u8]) -> (Self, usize, usize, usize, Vec<AuxHeader>) {
+    /// Also can use bare bin file for compatbility.  
+    /// If `interp_data` is given, the interpreter is mapped at `ELF_INTERP_BASE` and the returned entry is the interpreter's.
+    /// # Return
+    /// Err(ExecFormatError) if `elf_data` or `interp_data` is not a valid elf
+    pub fn new_elf(elf_data: &[u8], interp_data: Option<&[u8]>) -> Result<(Self, usize, usize, usize, Vec<AuxHeader>), ErrNo> {
         // for i in 0..0x1000 {
         //     if i % 16 == 0 {
         //         print!("\n{:>8x}: ", i);
@@ -787,41 +1078,25 @@ impl MemLayout {
         // }
         let mut layout = Self::new();
         layout.map_trampoline();
-        let mut data_top = 0;
         if let Ok(elf) = xmas_elf::ElfFile::new(elf_data) {
             debug!("ELF parsed!");
             // debug!("header: {}", elf.header);
+            // position independent executables are linked at 0, move them somewhere sane
+            let bias = match elf.header.pt2.type_().as_type() {
+                xmas_elf::header::Type::SharedObject => ELF_DYN_BASE,
+                _ => 0
+            };
             // map segments
-            for i in 0..elf.header.pt2.ph_count() {
-                let program_header = elf.program_header(i).unwrap();
-                if program_header.get_type().unwrap() == xmas_elf::program::Type::Load {
-                    let start = VirtAddr::from(program_header.virtual_addr() as usize);
-                    let stop = VirtAddr::from((program_header.virtual_addr() + program_header.mem_size()) as usize);
-                    let mut segment_flags = SegmentFlags::U;
-                    if program_header.flags().is_read() {
-                        segment_flags |= SegmentFlags::R;
-                    }
-                    if program_header.flags().is_write() {
-                        segment_flags |= SegmentFlags::W;
-                    }
-                    if program_header.flags().is_execute() {
-                        segment_flags |= SegmentFlags::X;
-                    }
-                    let segment = Segment::new(start, stop, MapType::Framed, segment_flags, VMAFlags::empty(), None, 0);
-                    let ph_end = program_header.offset() + program_header.file_size();
-                    layout.add_segment_with_source(
-                        segment, 
-                        &elf.input[
-                        program_header.offset() as usize
-                        ..
-                        ph_end as usize
-                        ]);
-                    verbose!("App segment mapped: {:0x}<->{:0x} ==> {:?}<->{:?}, with flags={:?}", program_header.offset() as usize, ph_end as usize, start, stop, segment_flags);
-                    
-                    if data_top < stop.0 {
-                        data_top = stop.0
-                    }
-                }
+            let data_top = layout.map_elf_segments(&elf, bias);
+            // map the interpreter, it will bootstrap the program from AT_ENTRY
+            let mut entry = elf.header.pt2.entry_point() as usize + bias;
+            let mut interp_base = 0;
+            if let Some(interp_data) = interp_data {
+                let interp = xmas_elf::ElfFile::new(interp_data).map_err(|_| ErrNo::ExecFormatError)?;
+                interp_base = ELF_INTERP_BASE;
+                layout.map_elf_segments(&interp, interp_base);
+                entry = interp.header.pt2.entry_point() as usize + interp_base;
+                verbose!("Interpreter mapped at {:x}, entry {:x}", interp_base, entry);
             }
             verbose!("Data Segment top should be at {:x}", data_top);
             // map trapcontext
@@ -873,6 +1148,22 @@ impl MemLayout {
                 ))
             );
             verbose!("UserStack mapped.");
+            // reserve the stack guard, it's never backed by any frame
+            let (stack_guard_low_end, stack_guard_high_end) = Self::user_stack_guard();
+            layout.add_segment(
+                Arc::new(Mutex::new(
+                    Segment::new(
+                        stack_guard_low_end,
+                        stack_guard_high_end,
+                        MapType::Anonymous,
+                        SegmentFlags::empty(),
+                        VMAFlags::empty(),
+                        None,
+                        0
+                    )
+                ))
+            );
+            verbose!("UserStack guard reserved.");
             // debug!(".text address: {}", elf.find_section_by_name(".text").unwrap().address());
             // debug!("ph_entry_size: {}", elf.header.pt2.ph_entry_size());
             // debug!("ph_count:      {}", elf.header.pt2.ph_count());
@@ -886,25 +1177,128 @@ impl MemLayout {
             auxv.push(AuxHeader{aux_type: AuxType::NULL2b,      value: 0});
             auxv.push(AuxHeader{aux_type: AuxType::NULL2c,      value: 0});
             auxv.push(AuxHeader{aux_type: AuxType::NULL2d,      value: 0});
-            auxv.push(AuxHeader{aux_type: AuxType::HWCAP,       value: 0 as usize});
+            auxv.push(AuxHeader{aux_type: AuxType::HWCAP,       value: HWCAP});
             auxv.push(AuxHeader{aux_type: AuxType::PAGESZ,      value: PAGE_SIZE as usize});
             auxv.push(AuxHeader{aux_type: AuxType::CLKTCK,      value: 100 as usize});
-            //HACK: is this correct?
-            auxv.push(AuxHeader{aux_type: AuxType::PHDR,        value: elf.program_header(0).unwrap().virtual_addr() as usize + elf.header.pt2.ph_offset() as usize});
+            auxv.push(AuxHeader{aux_type: AuxType::PHDR,        value: Self::elf_phdr_addr(&elf) + bias});
             auxv.push(AuxHeader{aux_type: AuxType::PHENT,       value: elf.header.pt2.ph_entry_size() as usize}); // ELF64 header 64bytes
             auxv.push(AuxHeader{aux_type: AuxType::PHNUM,       value: elf.header.pt2.ph_count() as usize});
-            auxv.push(AuxHeader{aux_type: AuxType::BASE,        value: 0 as usize});
+            auxv.push(AuxHeader{aux_type: AuxType::BASE,        value: interp_base});
             auxv.push(AuxHeader{aux_type: AuxType::FLAGS,       value: 0 as usize});
-            auxv.push(AuxHeader{aux_type: AuxType::ENTRY,       value: elf.header.pt2.entry_point() as usize});
+            auxv.push(AuxHeader{aux_type: AuxType::ENTRY,       value: elf.header.pt2.entry_point() as usize + bias});
             auxv.push(AuxHeader{aux_type: AuxType::UID,         value: 0 as usize});
             auxv.push(AuxHeader{aux_type: AuxType::EUID,        value: 0 as usize});
             auxv.push(AuxHeader{aux_type: AuxType::GID,         value: 0 as usize});
             auxv.push(AuxHeader{aux_type: AuxType::EGID,        value: 0 as usize});
             auxv.push(AuxHeader{aux_type: AuxType::SECURE,      value: 0 as usize});
     
-            return (layout, data_top as usize, stack_high_end.0, elf.header.pt2.entry_point() as usize, auxv);
+            return Ok((layout, data_top as usize, stack_high_end.0, entry, auxv));
+        }
+        Err(ErrNo::ExecFormatError)
+    }
+
+    /// Map all the LOAD segments of an elf file
+    /// # Description
+    /// Map all the LOAD segments of an elf file, with every virtual address shifted by `bias`.
+    /// # Return
+    /// The top of the highest segment mapped
+    fn map_elf_segments(&mut self, elf: &xmas_elf::ElfFile, bias: usize) -> usize {
+        let mut data_top = 0;
+        for i in 0..elf.header.pt2.ph_count() {
+            let program_header = elf.program_header(i).unwrap();
+            if program_header.get_type().unwrap() == xmas_elf::program::Type::Load {
+                let start = VirtAddr::from(program_header.virtual_addr() as usize + bias);
+                let stop = VirtAddr::from((program_header.virtual_addr() + program_header.mem_size()) as usize + bias);
+                let mut segment_flags = SegmentFlags::U;
+                if program_header.flags().is_read() {
+                    segment_flags |= SegmentFlags::R;
+                }
+                if program_header.flags().is_write() {
+                    segment_flags |= SegmentFlags::W;
+                }
+                if program_header.flags().is_execute() {
+                    segment_flags |= SegmentFlags::X;
+                }
+                let segment = Segment::new(start, stop, MapType::Framed, segment_flags, VMAFlags::empty(), None, 0);
+                let ph_end = program_header.offset() + program_header.file_size();
+                self.add_segment_with_source(
+                    segment, 
+                    &elf.input[
+                    program_header.offset() as usize
+                    ..
+                    ph_end as usize
+                    ]);
+                verbose!("App segment mapped: {:0x}<->{:0x} ==> {:?}<->{:?}, with flags={:?}", program_header.offset() as usize, ph_end as usize, start, stop, segment_flags);
+                // .bss: everything past file_size reads as zero
+                if program_header.mem_size() > program_header.file_size() {
+                    let bss_start = VirtAddr::from((program_header.virtual_addr() + program_header.file_size()) as usize + bias);
+                    self.zero_range(bss_start, stop);
+                }
+                
+                if data_top < stop.0 {
+                    data_top = stop.0
+                }
+            }
         }
-        panic!("Invlid elf format.");
+        data_top
+    }
+
+    /// Zero-fill a mapped range
+    /// # Description
+    /// Zero-fill `[start, stop)`. Don't count on fresh frames being clean, the range may share a page with file data.
+    fn zero_range(&self, start: VirtAddr, stop: VirtAddr) {
+        let mut iter = start;
+        while iter < stop {
+            let page_end = min(VirtAddr::from(iter.to_vpn() + 1).0, stop.0);
+            if let Some(pte) = self.translate(iter.to_vpn()) {
+                let page_offset = iter.0 % PAGE_SIZE;
+                let len = page_end - iter.0;
+                for b in pte.ppn().page_ptr()[page_offset..page_offset + len].iter_mut() {
+                    *b = 0;
+                }
+            }
+            iter = VirtAddr::from(page_end);
+        }
+    }
+
+    /// Get the interpreter path of an elf file
+    /// # Description
+    /// Get the path in the PT_INTERP segment, or None if the elf is statically linked.
+    pub fn elf_interp(elf_data: &[u8]) -> Option<String> {
+        let elf = xmas_elf::ElfFile::new(elf_data).ok()?;
+        for i in 0..elf.header.pt2.ph_count() {
+            let program_header = elf.program_header(i).ok()?;
+            if program_header.get_type() == Ok(xmas_elf::program::Type::Interp) {
+                let start = program_header.offset() as usize;
+                let end = start + program_header.file_size() as usize;
+                let raw = elf.input.get(start..end)?;
+                let len = raw.iter().position(|c| *c == 0).unwrap_or(raw.len());
+                return core::str::from_utf8(&raw[..len]).ok().map(String::from);
+            }
+        }
+        None
+    }
+
+    /// Get the (unbiased) virtual address of the program headers of an elf file
+    /// # Description
+    /// Use PT_PHDR if present, otherwise find the LOAD segment covering the program headers in the file.
+    fn elf_phdr_addr(elf: &xmas_elf::ElfFile) -> usize {
+        let ph_offset = elf.header.pt2.ph_offset();
+        for i in 0..elf.header.pt2.ph_count() {
+            let program_header = elf.program_header(i).unwrap();
+            if program_header.get_type() == Ok(xmas_elf::program::Type::Phdr) {
+                return program_header.virtual_addr() as usize;
+            }
+        }
+        for i in 0..elf.header.pt2.ph_count() {
+            let program_header = elf.program_header(i).unwrap();
+            if program_header.get_type() == Ok(xmas_elf::program::Type::Load)
+                && program_header.offset() <= ph_offset
+                && ph_offset < program_header.offset() + program_header.file_size() {
+                return (program_header.virtual_addr() + ph_offset - program_header.offset()) as usize;
+            }
+        }
+        0
     }
 
     /// Map the trampoline code in the Memory layout
@@ -920,14 +1314,14 @@ impl MemLayout {
             VirtAddr::from(TRAMPOLINE).into(),
             PhysAddr::from(strampoline as usize).into(),
             PTEFlags::R | PTEFlags::X
-        );
+        ).expect("No frame to map the trampoline.");
         verbose!("Trampoline mapped {:?} <=> {:?}, R-X-", VirtAddr::from(TRAMPOLINE), PhysAddr::from(strampoline as usize));
         
         self.pagetable.map(
             VirtAddr::from(U_TRAMPOLINE).into(),
             PhysAddr::from(sutrampoline as usize).into(),
             PTEFlags::R | PTEFlags::X | PTEFlags::U
-        );
+        ).expect("No frame to map the trampoline.");
         verbose!("U_Trampoline mapped {:?} <=> {:?}, R-XU", VirtAddr::from(U_TRAMPOLINE), PhysAddr::from(sutrampoline as usize));
     }
 
@@ -952,6 +1346,7 @@ impl MemLayout {
         for (idx, m_segment) in self.segments.iter().enumerate() {
             let mut segment = m_segment.lock();
             if segment.range.get_start() == start {
+                self.account_frames(segment.resident_pages(), 0);
                 segment.unmap_pages(&mut self.pagetable);
                 drop(segment);
                 self.segments.remove(idx);
@@ -963,6 +1358,7 @@ impl MemLayout {
     /// Drop all segments in the layout.
     pub fn drop_all(&mut self) {
         self.segments.clear();
+        self.rss.store(0, Ordering::Relaxed);
     }
 
     /// Tranlate a chunk of user memory into kernel space
@@ -973,12 +1369,16 @@ impl MemLayout {
         let mut pages = Vec::new();
         while start < end {
             let mut vpn = start.to_vpn();
+            // the slices may be written, so pages on the zero frame are given their own
             let ppn = match self.translate(vpn) {
-                Some(pte) => pte.ppn(),
-                None => {
+                Some(pte) if pte.valid() && pte.ppn() != zero_ppn() => pte.ppn(),
+                _ if self.fault_in_anonymous(vpn) => self.translate(vpn).unwrap().ppn(),
+                _ => {
                     panic!("Invalid user addr: {:?}", start);
                 },
             };
+            // the slices may be written
+            self.mark_dirty(vpn);
             vpn.step();
             let copy_end = min(VirtAddr::from(vpn), end);    // page end or buf end
             // verbose!("vpn page end {:?}", VirtAddr::from(vpn));
@@ -994,6 +1394,96 @@ impl MemLayout {
         return pages;
     }
 
+    /// Record a kernel write to the user page `vpn`
+    /// # Description
+    /// Sets D like the hart does for user writes, so that the page is never reclaimed as clean.
+    fn mark_dirty(&self, vpn: VirtPageNum) {
+        if let Some(pte) = self.pagetable.walk(vpn).filter(|pte| pte.valid()) {
+            pte.set_dirty();
+        }
+    }
+
+    /// Get the physical page behind user address `va`, checking the user may access it
+    /// # Description
+    /// Lazy pages are faulted in first. The pte must have U, and W or R if they are in `access`.
+    /// # Return
+    /// Err(BadAddress) if the user can't access the page, Err(OutOfMemory) if it can't be faulted in
+    fn user_page(&mut self, va: VirtAddr, access: VMAFlags) -> Result<PhysPageNum, ErrNo> {
+        let vpn = va.to_vpn();
+        // a write to the zero frame faults like a missing page
+        let missing = match self.translate(vpn) {
+            Some(pte) if pte.valid() => access.contains(VMAFlags::W) && pte.ppn() == zero_ppn(),
+            _ => true,
+        };
+        if missing {
+            self.lazy_copy_vma(va, access)?;
+        }
+        let pte = self.translate(vpn).ok_or(ErrNo::BadAddress)?;
+        let permitted = pte.valid() && pte.user_acc()
+            && (!access.contains(VMAFlags::W) || pte.writable())
+            && (!access.contains(VMAFlags::R) || pte.readable());
+        if !permitted {
+            return Err(ErrNo::BadAddress);
+        }
+        Ok(pte.ppn())
+    }
+
+    /// Copy user memory at `src` into `dst`
+    /// # Description
+    /// Walks the pages one by one, the copy may have been done partly when an error is returned.
+    /// # Return
+    /// # of bytes copied, Err(BadAddress) on the first page the user can't read
+    pub fn copy_from_user(&mut self, dst: &mut [u8], src: VirtAddr) -> Result<usize, ErrNo> {
+        let mut done = 0;
+        while done < dst.len() {
+            let va = src + done;
+            let ppn = self.user_page(va, VMAFlags::R)?;
+            let len = min(PAGE_SIZE - va.page_offset(), dst.len() - done);
+            dst[done..done + len].copy_from_slice(&ppn.page_ptr()[va.page_offset()..va.page_offset() + len]);
+            done += len;
+        }
+        Ok(done)
+    }
+
+    /// Copy `src` into user memory at `dst`
+    /// # Description
+    /// Walks the pages one by one, the copy may have been done partly when an error is returned.
+    /// # Return
+    /// # of bytes copied, Err(BadAddress) on the first page the user can't write
+    pub fn copy_to_user(&mut self, dst: VirtAddr, src: &[u8]) -> Result<usize, ErrNo> {
+        let mut done = 0;
+        while done < src.len() {
+            let va = dst + done;
+            let ppn = self.user_page(va, VMAFlags::W)?;
+            self.mark_dirty(va.to_vpn());
+            let len = min(PAGE_SIZE - va.page_offset(), src.len() - done);
+            ppn.page_ptr()[va.page_offset()..va.page_offset() + len].copy_from_slice(&src[done..done + len]);
+            done += len;
+        }
+        Ok(done)
+    }
+
+    /// Patch user memory at `dst` with `src`, even if the user can't write there
+    /// # Description
+    /// For debuggers planting breakpoints in code. The pages only have to be readable by the user.  
+    /// Pages on the zero frame are refused, the patch would show up in every untouched page.
+    /// # Return
+    /// # of bytes written, Err(BadAddress) on the first page that can't be patched
+    pub fn poke_user(&mut self, dst: VirtAddr, src: &[u8]) -> Result<usize, ErrNo> {
+        let mut done = 0;
+        while done < src.len() {
+            let va = dst + done;
+            let ppn = self.user_page(va, VMAFlags::R)?;
+            if ppn == zero_ppn() {
+                return Err(ErrNo::BadAddress);
+            }
+            let len = min(PAGE_SIZE - va.page_offset(), src.len() - done);
+            ppn.page_ptr()[va.page_offset()..va.page_offset() + len].copy_from_slice(&src[done..done + len]);
+            done += len;
+        }
+        Ok(done)
+    }
+
     /// Get a c-style string from the user space.
     /// # Description
     /// Get a c-style string from the user space, that is, read until a `b'\0'` is encountered.  
@@ -1030,6 +1520,155 @@ impl MemLayout {
         return UserBuffer::new(self.get_user_data(start, len));
     }
 
+    /// Get a UserBuffer in user space, without panicking on bad address
+    /// # Description
+    /// Same as get_user_buffer, but checks every page of the area first.
+    /// # Return
+    /// The userbuffer of corresponding area, or BadAddress if any page in the area is not mapped
+    pub fn try_get_user_buffer(&self, start: VirtAddr, len: usize) -> Result<UserBuffer, ErrNo> {
+        let mut vpn = start.to_vpn();
+        let end = (start + len).to_vpn_ceil();
+        while vpn < end {
+            match self.translate(vpn) {
+                Some(pte) if pte.valid() && pte.ppn() != zero_ppn() => {},
+                _ if self.fault_in_anonymous(vpn) => {},
+                _ => return Err(ErrNo::BadAddress)
+            }
+            vpn.step();
+        }
+        return Ok(self.get_user_buffer(start, len));
+    }
+
+    /// Resize an anonymous mapping
+    /// # Description
+    /// Shrink by unmapping the tail, grow in place if the pages after the mapping are free.  
+    /// Otherwise, if `may_move`, move the frames to a new area large enough.
+    /// # Return
+    /// The (possibly new) start address of the mapping
+    pub fn remap(&mut self, old_start: VirtAddr, old_len: usize, new_len: usize, may_move: bool) -> Result<VirtAddr, ErrNo> {
+        let start_vpn = old_start.to_vpn();
+        let old_end = (old_start + old_len).to_vpn_ceil();
+        let new_end = (old_start + new_len).to_vpn_ceil();
+        let m_seg = self.segments.iter().find(|m_seg| {
+            let seg = m_seg.lock();
+            seg.range.get_start() <= start_vpn && old_end <= seg.range.get_end()
+        }).cloned().ok_or(ErrNo::BadAddress)?;
+        let (seg_end, seg_flags) = {
+            let seg = m_seg.lock();
+            if seg.map_type != MapType::Anonymous {
+                return Err(ErrNo::InvalidArgument);
+            }
+            (seg.range.get_end(), seg.seg_flags)
+        };
+
+        // shrink
+        if new_end <= old_end {
+            self.unmap_range(new_end, old_end)?;
+            return Ok(old_start);
+        }
+
+        // grow in place
+        let tail_free = seg_end == old_end
+            && Self::is_user_range(old_start, new_end.into())
+            && self.segments.iter().all(|m_seg| {
+                let seg = m_seg.lock();
+                seg.range.get_end() <= old_end || new_end <= seg.range.get_start()
+            });
+        if tail_free {
+            let mut seg = m_seg.lock();
+            let before = seg.frames.len();
+            seg.range.set_end(new_end);
+            for vpn in VPNRange::new(old_end, new_end) {
+                if let Err(err) = seg.map_page(&mut self.pagetable, vpn) {
+                    self.account_frames(before, seg.frames.len());
+                    return Err(err);
+                }
+            }
+            self.account_frames(before, seg.frames.len());
+            verbose!("mremap grown in place to {:?}", new_end);
+            return Ok(old_start);
+        }
+
+        if !may_move {
+            return Err(ErrNo::OutOfMemory);
+        }
+
+        // move the touched frames over, nothing is copied
+        let new_start = self.get_continuous_space(new_len).ok_or(ErrNo::OutOfMemory)?;
+        let mut new_seg = Segment::new(
+            new_start.into(),
+            VirtAddr::from(new_start) + new_len,
+            MapType::Anonymous,
+            seg_flags,
+            VMAFlags::empty(),
+            None,
+            0
+        );
+        new_seg.map_pages(&mut self.pagetable);
+        self.account_frames(0, new_seg.frames.len());
+        {
+            let mut seg = m_seg.lock();
+            let pte_flags = PTEFlags::from_bits(seg_flags.bits).unwrap();
+            for vpn in VPNRange::new(start_vpn, old_end) {
+                if let Some(frame) = seg.frames.remove(&vpn) {
+                    if self.pagetable.translate(vpn).map_or(false, |pte| pte.valid()) {
+                        self.pagetable.unmap(vpn);
+                    }
+                    let new_vpn = new_start + (vpn - start_vpn);
+                    // the parents were created by map_pages, this doesn't allocate
+                    if frame.is_zero() {
+                        self.pagetable.map(new_vpn, frame.ppn(), pte_flags - PTEFlags::W)?;
+                    } else {
+                        self.pagetable.map(new_vpn, frame.ppn(), pte_flags)?;
+                    }
+                    new_seg.frames.insert(new_vpn, frame);
+                }
+            }
+        }
+        self.segments.push(Arc::new(Mutex::new(new_seg)));
+        self.unmap_range(start_vpn, old_end)?;
+        verbose!("mremap moved {:?} to {:?}", start_vpn, new_start);
+        Ok(new_start.into())
+    }
+
+    /// Get the range of the user stack guard
+    /// # Description
+    /// The pages right below the initial user stack, which no access should ever hit.
+    pub fn user_stack_guard() -> (VirtAddr, VirtAddr) {
+        let high_end = VirtAddr::from(U_TRAMPOLINE) - PAGE_SIZE - USER_STACK_SIZE;
+        (high_end - USER_STACK_GUARD_PAGES * PAGE_SIZE, high_end)
+    }
+
+    /// Check if `va` lies in the user stack guard
+    pub fn in_user_stack_guard(va: VirtAddr) -> bool {
+        let (low_end, high_end) = Self::user_stack_guard();
+        low_end <= va && va < high_end
+    }
+
+    /// Fault in an anonymous page
+    /// # Description
+    /// If `vpn` is in an anonymous segment, alloc and map a zeroed page for it.
+    /// # Return
+    /// True if `vpn` is mapped afterwards
+    pub fn fault_in_anonymous(&self, vpn: VirtPageNum) -> bool {
+        for m_seg in self.segments.iter() {
+            let mut seg = m_seg.lock();
+            if seg.map_type == MapType::Anonymous && seg.range.get_start() <= vpn && vpn < seg.range.get_end() {
+                // not for the user to touch, e.g. the stack guard
+                if !seg.seg_flags.contains(SegmentFlags::U) {
+                    return false;
+                }
+                // a page on the zero frame gets a frame of its own
+                let on_zero = seg.frames.get(&vpn).map_or(false, |frame| frame.is_zero());
+                let before = seg.frames.len() - on_zero as usize;
+                let mapped = seg.map_anonymous_page(&self.pagetable, vpn).is_ok();
+                self.account_frames(before, seg.frames.len());
+                return mapped;
+            }
+        }
+        false
+    }
+
     /// Write a object into user space.
     /// # Description
     /// Write a object into user space. Can cross page boundry
@@ -1063,17 +1702,18 @@ impl MemLayout {
         let inner = file.clone().to_common_file().unwrap();
         let start_vpn = start.to_vpn();
         let stop_vpn = (min(start + inner.poll().size as usize, start + length)).to_vpn_ceil();
-        // check overlap
+        // check overlap, including either range containing the other
+        let new_range = VPNRange::new(start_vpn, stop_vpn);
         for m_seg in self.segments.iter() {
             let seg = m_seg.lock();
-            if seg.range.get_start() <= start_vpn && start_vpn < seg.range.get_end() {
-                error!("Overlapped mmap segment");
-                return Err(ErrNo::BadAddress);
-            } else if seg.range.get_start() < stop_vpn && stop_vpn < seg.range.get_end() {
+            if seg.range.intersects(&new_range) {
                 error!("Overlapped mmap segment");
                 return Err(ErrNo::BadAddress);
             }
         }
+        if self.merge_vma(&file, start_vpn, stop_vpn, flag, offset) {
+            return Ok(start);
+        }
         let segment = Segment::new(
             start_vpn.into(), 
             stop_vpn.into(), 
@@ -1087,22 +1727,87 @@ impl MemLayout {
         Ok(start)
     }
 
-    // TODO: This can be optimized.
-    pub fn get_continuous_space(&self, len: usize) -> Option<VirtPageNum> {
-        'outer: for i in 0..0xffff_ffff_ff00_0___ {
-            let stop_vpn: VirtPageNum = VirtPageNum::from(0xffff_ffff_ff00_0___) - i;
-            let start_vpn: VirtPageNum = stop_vpn - len / PAGE_SIZE;
-            
-            // check overlap
-            for m_seg in self.segments.iter() {
-                let seg = m_seg.lock();
-                if seg.range.get_start() - 1 <= start_vpn && start_vpn < seg.range.get_end() + 1 {
-                    continue 'outer;
-                } else if seg.range.get_start() - 1 < stop_vpn && stop_vpn < seg.range.get_end() + 1 {
-                    continue 'outer;
+    /// Merge `[start_vpn, stop_vpn)`, mapping `file` from `offset`, into an adjacent VMA segment
+    /// # Description
+    /// A neighbour is compatible if it maps the same file with the same flags and the file offset continues across the boundary.  
+    /// If the range bridges two compatible neighbours, the three of them become one segment.  
+    /// Segments shared with other layouts are left alone.
+    /// # Return
+    /// True if merged, and no new segment is needed
+    fn merge_vma(&mut self, file: &Arc<dyn File>, start_vpn: VirtPageNum, stop_vpn: VirtPageNum, flag: VMAFlags, offset: usize) -> bool {
+        let same_file = |seg: &Segment| match &seg.file {
+            Some(seg_file) => Arc::as_ptr(seg_file) as *const u8 == Arc::as_ptr(file) as *const u8,
+            None => false,
+        };
+        let mut prev = None;
+        let mut next = None;
+        for (idx, m_seg) in self.segments.iter().enumerate() {
+            if Arc::strong_count(m_seg) != 1 {
+                continue;
+            }
+            let seg = m_seg.lock();
+            if seg.map_type != MapType::VMA || seg.vma_flags != flag || seg.head_offset != 0 || !same_file(&seg) {
+                continue;
+            }
+            if seg.range.get_end() == start_vpn && seg.offset + (start_vpn - seg.range.get_start()) * PAGE_SIZE == offset {
+                prev = Some(idx);
+            } else if seg.range.get_start() == stop_vpn && offset + (stop_vpn - start_vpn) * PAGE_SIZE == seg.offset {
+                next = Some(idx);
+            }
+        }
+        match (prev, next) {
+            (Some(prev), next) => {
+                let mut stop = stop_vpn;
+                let mut frames = BTreeMap::new();
+                if let Some(next) = next {
+                    let next_seg = self.segments.remove(next);
+                    let mut next_seg = next_seg.lock();
+                    stop = next_seg.range.get_end();
+                    frames.append(&mut next_seg.frames);
                 }
+                let prev = if next.map_or(false, |next| next < prev) { prev - 1 } else { prev };
+                let mut seg = self.segments[prev].lock();
+                seg.range.set_end(stop);
+                seg.frames.append(&mut frames);
+            },
+            (None, Some(next)) => {
+                let mut seg = self.segments[next].lock();
+                seg.range = VPNRange::new(start_vpn, seg.range.get_end());
+                seg.offset = offset;
+            },
+            (None, None) => return false,
+        }
+        verbose!("Merged VMA [{:?}, {:?}) into a neighbour", start_vpn, stop_vpn);
+        true
+    }
+
+    /// Find `len` bytes of unmapped virtual space, as high as possible below `MMAP_TOP_VPN`
+    /// # Description
+    /// Walks a view of the segments sorted by start from high to low addresses, 
+    /// and takes the first gap large enough. Segments are assumed not to overlap.
+    /// A guard page is kept on both sides of the space, and page 0 is never handed out.
+    /// # Return
+    /// The first page of the space, None if there is no room
+    pub fn get_continuous_space(&self, len: usize) -> Option<VirtPageNum> {
+        let pages = (len + PAGE_SIZE - 1) / PAGE_SIZE;
+        let mut ranges: Vec<VPNRange> = self.segments.iter().map(|m_seg| m_seg.lock().range).collect();
+        ranges.sort_unstable_by(|a, b| b.get_start().cmp(&a.get_start()));
+        // the space must end at or below this
+        let mut stop_vpn = VirtPageNum::from(MMAP_TOP_VPN);
+        for range in ranges.iter() {
+            let low = range.get_end() + 1;
+            if low <= stop_vpn && stop_vpn - low >= pages {
+                return Some(stop_vpn - pages);
             }
-            return Some(start_vpn); 
+            stop_vpn = match range.get_start().checked_backward(1) {
+                Some(below) if below < stop_vpn => below,
+                Some(_) => stop_vpn,
+                None => return None,
+            };
+        }
+        let low = VirtPageNum::from(1);
+        if low <= stop_vpn && stop_vpn - low >= pages {
+            return Some(stop_vpn - pages);
         }
         None
     }
@@ -1114,18 +1819,87 @@ impl MemLayout {
     }
 
     pub fn lazy_copy_vma(&mut self, address: VirtAddr, access_flag: VMAFlags) -> Result<(), ErrNo> {
-        for m_seg in self.segments.iter_mut() {
+        for m_seg in self.segments.iter() {
             let mut seg = m_seg.lock();
+            if seg.map_type == MapType::Anonymous && seg.range.get_start() <= address.to_vpn() && address.to_vpn() < seg.range.get_end() {
+                if access_flag.contains(VMAFlags::W) && !seg.seg_flags.contains(SegmentFlags::W) {
+                    return Err(ErrNo::BadAddress);
+                }
+                if access_flag.contains(VMAFlags::R) && !seg.seg_flags.contains(SegmentFlags::R) {
+                    return Err(ErrNo::BadAddress);
+                }
+                // already mapped means the access itself is not permitted, unless it writes to the zero frame
+                if let Some(pte) = self.pagetable.translate(address.to_vpn()).filter(|pte| pte.valid()) {
+                    if !access_flag.contains(VMAFlags::W) || pte.ppn() != zero_ppn() {
+                        return Err(ErrNo::BadAddress);
+                    }
+                    // the page leaves the zero frame for a frame of its own
+                    seg.map_anonymous_page(&self.pagetable, address.to_vpn())?;
+                    self.account_frames(0, 1);
+                    return Ok(());
+                }
+                // reads go to the zero frame, unless other pagetables share the segment
+                if !access_flag.contains(VMAFlags::W) && Arc::strong_count(m_seg) == 1 {
+                    return seg.map_zero_page(&self.pagetable, address.to_vpn());
+                }
+                let before = seg.frames.len();
+                let result = seg.map_anonymous_page(&self.pagetable, address.to_vpn());
+                self.account_frames(before, seg.frames.len());
+                return result;
+            }
             if seg.map_type == MapType::VMA && seg.range.get_start() <= address.to_vpn() && address.to_vpn() < seg.range.get_end() {
                 if !(access_flag & seg.vma_flags).is_empty() {
                     verbose!("lazy copy triggered for {:?}", address);
-                    return seg.map_lazy_vma(&mut self.pagetable, address);
+                    let before = seg.frames.len();
+                    let result = seg.map_lazy_vma(&mut self.pagetable, address);
+                    self.account_frames(before, seg.frames.len());
+                    return result;
                 }
             }
         }
         Err(ErrNo::BadAddress)
     }
 
+    /// If `va` is in a segment, or mapped in the pagetable
+    /// # Description
+    /// Pages of a segment may not be mapped yet, they are faulted in on first access.
+    pub fn is_mapped(&self, va: VirtAddr) -> bool {
+        let vpn = va.to_vpn();
+        self.segments.iter().any(|seg| {
+            let seg = seg.lock();
+            seg.range.get_start() <= vpn && vpn < seg.range.get_end()
+        }) || self.translate(vpn).map_or(false, |pte| pte.valid())
+    }
+
+    /// Check a range for user mappings
+    /// # Description
+    /// `[start, end)` has to be non-empty and within one half of the Sv39 address space, below the user stack and its guard.
+    pub fn is_user_range(start: VirtAddr, end: VirtAddr) -> bool {
+        let (stack_guard_low_end, _) = Self::user_stack_guard();
+        start < end && (end.0 <= SV39_LOW_END || (SV39_HIGH_START <= start.0 && end <= stack_guard_low_end))
+    }
+
+    /// Unmap a range of pages
+    /// # Description
+    /// Unmap every page in `[start, end)`, splitting the segments which are partially covered.  
+    /// Holes in the range are fine.
+    pub fn unmap_range(&mut self, start: VirtPageNum, end: VirtPageNum) -> Result<(), ErrNo> {
+        let mut to_drop: Vec<(VirtPageNum, VirtPageNum)> = Vec::new();
+        for m_seg in self.segments.iter() {
+            let seg = m_seg.lock();
+            let drop_start = seg.range.get_start().max(start);
+            let drop_end = seg.range.get_end().min(end);
+            if drop_start < drop_end {
+                to_drop.push((drop_start, drop_end));
+            }
+        }
+        for (drop_start, drop_end) in to_drop {
+            self.drop_vma(drop_start, drop_end)?;
+        }
+        self.pagetable.flush_tlb_all();
+        Ok(())
+    }
+
     pub fn drop_vma(&mut self, drop_start: VirtPageNum, drop_end: VirtPageNum) -> Result<(), ErrNo> {
         verbose!("munmapping [{:?}, {:?})", drop_start, drop_end);
         
@@ -1136,7 +1910,7 @@ impl MemLayout {
             let seg_start = seg.range.get_start();
             let seg_end = seg.range.get_end();
             let start_ok = seg_start <= drop_start && drop_start < seg_end;
-            let end_ok = seg_start < drop_end && drop_start <= seg_end;            
+            let end_ok = seg_start < drop_end && drop_end <= seg_end;
             if start_ok && end_ok {
                 o_to_split = Some(m_seg.clone());
                 found = true;
@@ -1151,13 +1925,13 @@ impl MemLayout {
 
         let to_split = o_to_split.unwrap();
         let mut seg = to_split.lock();
-        if seg.map_type == MapType::Framed {
+        if seg.map_type == MapType::Framed || seg.map_type == MapType::Anonymous {
             let mut original_segment = seg;
             let seg_start = original_segment.range.get_start();
             let seg_end = original_segment.range.get_end();
             // add split head
             if seg_start < drop_start {
-                let mut head_frame_trackers: BTreeMap<VirtPageNum, FrameTracker> = BTreeMap::new();
+                let mut head_frame_trackers: BTreeMap<VirtPageNum, SegmentFrame> = BTreeMap::new();
                 for head_vpn in SimpleRange::new(seg_start, drop_start) {
                     // if lazy, then no frame trackers
                     if let Some(ft) = original_segment.frames.remove(&head_vpn) {
@@ -1179,7 +1953,7 @@ impl MemLayout {
 
             // add split tail
             if drop_end < seg_end {
-                let mut tail_frame_trackers: BTreeMap<VirtPageNum, FrameTracker> = BTreeMap::new();
+                let mut tail_frame_trackers: BTreeMap<VirtPageNum, SegmentFrame> = BTreeMap::new();
                 for tail_vpn in SimpleRange::new(drop_end, seg_end) {
                     // if lazy, then no frame trackers
                     if let Some(ft) = original_segment.frames.remove(&tail_vpn) {
@@ -1239,6 +2013,7 @@ pub fn remap_test() {
         fn erodata();
         fn sdata();
         fn edata();
+        fn ekernel();
     }
 
     verbose!("Testing kernel memory layout...");
@@ -1258,5 +2033,11 @@ pub fn remap_test() {
         kernel_space.pagetable.translate(mid_data.to_vpn()).unwrap().executable(),
         false,
     );
+    // most likely in a megapage
+    let mid_ram: VirtAddr = ((ekernel as usize + super::mem_end()) / 2).into();
+    assert_eq!(
+        kernel_space.pagetable.translate_va(mid_ram).unwrap().0,
+        mid_ram.0,
+    );
     debug!("remap_test passed!");
 }
@@ -15,7 +15,7 @@
 // .bss                    <- kernel crt  
 // =====   ekernel  =====  <- check symbol in linker_*.id
 // All managed by frame allocator
-// ===== 0x80800000 =====  <- max memory, limited by k210. // TODO make it dynamic
+// =====  mem_end() =====  <- end of RAM, from device tree or DEFAULT_MEM_END
 // 
 // ****************************************************
 // * Kernel Virtual Memory (SV39, PPN in [0, 7fffff]) *
@@ -76,8 +76,13 @@ mod kernel_heap;
 mod frame_alloc;
 mod layout;
 mod userbuffer;
+mod reclaim;
+mod slab;
+mod uaccess;
 
 use alloc::vec::Vec;
+use crate::config::{DEFAULT_MEM_END, MMIO, PAGE_SIZE};
+use crate::utils::fdt;
 
 pub use addresses::{
     VirtAddr,
@@ -93,6 +98,7 @@ pub use addresses::{
 pub use pagetable::{
     PageTable,
     PageTableEntry,
+    MEGAPAGE_PAGES,
     PTEFlags,
     get_user_data,
     get_user_cstr,
@@ -103,9 +109,15 @@ pub use pagetable::{
 
 pub use frame_alloc::{
     FrameTracker,
+    SharedFrame,
+    SegmentFrame,
+    zero_frame,
+    zero_ppn,
     alloc_frame,
     alloc_continuous,
     free_frame,
+    total_frames,
+    free_frames,
 };
 
 pub use layout::{
@@ -120,8 +132,80 @@ pub use layout::{
 
 pub use userbuffer::UserBuffer;
 
+pub use uaccess::{
+    copy_from_user,
+    copy_to_user,
+    read_from_user,
+    write_to_user,
+};
+
+pub use reclaim::{
+    reclaim,
+    reclaimed_frames,
+};
+
+pub use slab::{
+    ObjectCache,
+    CacheBox,
+    slab_bytes,
+    slab_hits,
+};
+
+pub use kernel_heap::{
+    kernel_heap_used,
+    kernel_heap_peak,
+    kernel_heap_allocs,
+    kernel_heap_capacity,
+    set_kernel_heap_growable,
+    dump_heap_usage,
+    take_heap_oom_victim,
+};
+
+/// End of physical memory
+/// # Description
+/// RAM end found in the device tree, or `DEFAULT_MEM_END` if there is none.  
+/// Kernel space identity maps and the frame allocator manages everything from `ekernel` up to here.
+pub fn mem_end() -> usize {
+    match fdt::board_info() {
+        Some(info) => info.mem_end,
+        None => DEFAULT_MEM_END,
+    }
+}
+
+/// Make instruction fetches see the stores done so far
+/// # Description
+/// A `fence.i` on this hart. The kernel only runs on hart 0, so there are no other harts to shoot down.
+pub fn flush_icache() {
+    unsafe {
+        asm!("fence.i");
+    }
+}
+
+/// (start, size) of MMIO regions to be identity mapped in kernel space
+/// # Description
+/// The static `MMIO` table together with the regions found in the device tree, as the tree may not list every device the drivers use.  
+/// Regions are widened to whole pages and overlapping ones merged, so that no page is mapped twice.
+pub fn mmio_regions() -> Vec<(usize, usize)> {
+    let mut regions = MMIO.to_vec();
+    if let Some(info) = fdt::board_info() {
+        regions.extend_from_slice(&info.mmio[..info.mmio_cnt]);
+    }
+    let mut regions: Vec<(usize, usize)> = regions.iter()
+        .map(|(start, size)| (start & !(PAGE_SIZE - 1), (start + size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)))
+        .collect();
+    regions.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::new();
+    for (start, end) in regions {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged.iter().map(|(start, end)| (*start, end - start)).collect()
+}
+
 /// Initialize the whole memory managment module.
-pub fn init() {
+pub fn init(dtb: usize) {
     debug!("Initilizing memory managment unit...");
     extern "C" {
         fn sbss();
@@ -133,6 +217,8 @@ pub fn init() {
         }
     }
     verbose!("BSS cleared.");
+    // before anything is allocated, the blob lives in RAM that the frame allocator hands out
+    fdt::init(dtb);
     kernel_heap::init_kernel_heap();
     frame_allocator_test();
     KERNEL_MEM_LAYOUT.lock().activate();
@@ -8,7 +8,10 @@ use alloc::vec::Vec;
 use core::cmp::min;
 use crate::utils::StepByOne;
 use alloc::string::String;
+use alloc::format;
 use crate::memory::SegmentFlags;
+use crate::process::ErrNo;
+use riscv::register::satp;
 
 bitflags! {
     /// Pagetable entry flags, indicating privileges.
@@ -111,6 +114,13 @@ impl PageTableEntry {
         self.flags().contains(PTEFlags::D)
     }
 
+    /// Mark the page as written
+    /// # Description
+    /// The kernel writes user pages through its own mapping, which never sets D in the user pte.
+    pub fn set_dirty(&mut self) {
+        self.bits |= PTEFlags::D.bits() as usize;
+    }
+
     /// Check if the corresponding physical page is writbale
     pub fn writable(&self) -> bool {
         (self.flags() & PTEFlags::W) != PTEFlags::empty()
@@ -124,8 +134,23 @@ impl PageTableEntry {
     pub fn user_acc(&self) -> bool {
         (self.flags() & PTEFlags::U) != PTEFlags::empty()
     }
+
+    /// Flags in the order of the bits from bit 0, "VRWXUGAD", '-' for the ones not set
+    pub fn flags_string(&self) -> String {
+        "VRWXUGAD".chars().enumerate().map(|(bit, name)| {
+            if self.bits & (1 << bit) != 0 { name } else { '-' }
+        }).collect()
+    }
+
+    /// Check if this PTE maps a page, rather than pointing to the next level
+    pub fn is_leaf(&self) -> bool {
+        self.valid() && (self.readable() || self.writable() || self.executable())
+    }
 }
 
+/// # of 4 KiB pages in a 2 MiB megapage
+pub const MEGAPAGE_PAGES: usize = 512;
+
 /// The pagetable.
 pub struct PageTable {
     /// The root physical page number for the pagetable, used in SATP
@@ -157,75 +182,225 @@ impl PageTable {
     /// # Description
     /// Similar to xv6, get pte representing vpn from pagetable, and create parent pte if not present.
     /// # Return
-    /// Return a reference to the corrersponding page table entry
-    fn walk_create(&mut self, vpn: VirtPageNum) -> &mut PageTableEntry {
+    /// Return a reference to the corrersponding page table entry, Err(OutOfMemory) if there is no frame for a parent
+    fn walk_create(&mut self, vpn: VirtPageNum) -> Result<&mut PageTableEntry, ErrNo> {
+        self.split_huge(vpn)?;
         let indexes = vpn.indexes();
         let mut ppn = self.root_ppn;
         for i in 0..3 {
             let pte = &mut ppn.read_pte()[indexes[i]];
             if i == 2 {         // leaf node, just return
-                return pte;
+                return Ok(pte);
             }
             if !pte.valid() {   // not a leaf node, yet invalid
-                let frame = alloc_frame().unwrap();
+                let frame = alloc_frame().ok_or(ErrNo::OutOfMemory)?;
                 *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
                 self.frames.push(frame);
             }
+            assert!(!pte.is_leaf(), "{:?} is in a huge page.", vpn);
             ppn = pte.ppn();
         }
         unreachable!();     // don't comment this out, or compiler will be unhappy
     }
 
-    /// Get the page table entry from the pagetable.
-    /// # Description
-    /// Get the page table entry from the pagetable. Return None if not mapped.
+    /// Find the pte mapping `vpn`
     /// # Return
-    /// Return a reference to the corrersponding page table entry, or None if not found.
-    pub fn walk(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+    /// The pte and its level, 2 for a 4 KiB page, 1 for a megapage and 0 for a gigapage, or None if not found.
+    fn find(&self, vpn: VirtPageNum) -> Option<(&mut PageTableEntry, usize)> {
         let indexes = vpn.indexes();
         let mut ppn = self.root_ppn;
         for i in 0..3 {
             let pte = &mut ppn.read_pte()[indexes[i]];
             if i == 2 {         // leaf node, just return
-                return Some(pte);
+                return Some((pte, i));
             }
             if !pte.valid() {   // not a leaf node, yet invalid
                 return None;
             }
+            if pte.is_leaf() {  // a huge page covering vpn
+                return Some((pte, i));
+            }
             ppn = pte.ppn();
         }
         unreachable!();     // don't comment this out, or compiler will be unhappy
     }
 
+    /// Get the page table entry from the pagetable.
+    /// # Description
+    /// Get the page table entry from the pagetable. Return None if not mapped.
+    /// # Return
+    /// Return a reference to the corrersponding page table entry, or None if not found.
+    /// None too for a page in a huge page, as changing its pte would change the whole huge page. Use `split_huge()` first.
+    pub fn walk(&self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
+        match self.find(vpn)? {
+            (pte, 2) => Some(pte),
+            _ => None,
+        }
+    }
+
+    /// Split the huge page covering `vpn` down to 4 KiB pages
+    /// # Description
+    /// The new ptes map the same physical pages with the same flags, so a single page in it can then be changed.
+    /// Nothing is done if `vpn` isn't in a huge page.
+    /// # Return
+    /// Err(OutOfMemory) if there is no frame for the new level
+    pub fn split_huge(&mut self, vpn: VirtPageNum) -> Result<(), ErrNo> {
+        loop {
+            let (pte, level) = match self.find(vpn) {
+                Some((pte, level)) if level < 2 => (pte, level),
+                _ => return Ok(()),
+            };
+            let frame = alloc_frame().ok_or(ErrNo::OutOfMemory)?;
+            // a gigapage becomes megapages, split again on the next round
+            let step = if level == 0 { MEGAPAGE_PAGES } else { 1 };
+            for (i, child) in frame.ppn.read_pte().iter_mut().enumerate() {
+                *child = PageTableEntry::new(pte.ppn() + i * step, pte.flags());
+            }
+            *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+            self.frames.push(frame);
+            self.flush_tlb_all();
+        }
+    }
+
     /// Map a vpn-ppn pair in the page table
     /// # Description
     /// Map a pair of virtual page and physical page, alone with specified flags.
     /// Panic on remapping.
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.walk_create(vpn);
+    /// # Return
+    /// Err(OutOfMemory) if there is no frame for the parent ptes
+    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> Result<(), ErrNo> {
+        let pte = self.walk_create(vpn)?;
         assert!(!pte.valid(), "{:?} has already been mapped.", vpn);
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        self.flush_tlb(vpn);
+        Ok(())
     }
 
+    /// Map a 2 MiB megapage
+    /// # Description
+    /// Map `MEGAPAGE_PAGES` pages from `vpn` to the ones from `ppn` with a single level 1 pte, both must be aligned to it.
+    /// # Return
+    /// Ok(false) if some page in the range is mapped already, nothing is changed then and 4 KiB pages must be used instead.
+    /// Err(OutOfMemory) if there is no frame for the level 1 table.
+    pub fn map_mega(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) -> Result<bool, ErrNo> {
+        assert!(vpn.0 % MEGAPAGE_PAGES == 0 && ppn.0 % MEGAPAGE_PAGES == 0, "Megapage {:?} => {:?} not aligned.", vpn, ppn);
+        let indexes = vpn.indexes();
+        let root = self.root_ppn;
+        let root_pte = &mut root.read_pte()[indexes[0]];
+        if !root_pte.valid() {
+            let frame = alloc_frame().ok_or(ErrNo::OutOfMemory)?;
+            *root_pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+            self.frames.push(frame);
+        }
+        if root_pte.is_leaf() {
+            return Ok(false);
+        }
+        let pte = &mut root_pte.ppn().read_pte()[indexes[1]];
+        if pte.valid() {
+            return Ok(false);
+        }
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        self.flush_tlb_all();
+        Ok(true)
+    }
+
+
+    /// Create the parent ptes of a vpn
+    /// # Description
+    /// Create the parent ptes of `vpn` without mapping it, so the leaf can be filled later through `walk()`.
+    /// # Return
+    /// Err(OutOfMemory) if there is no frame for the parent ptes
+    pub fn reserve(&mut self, vpn: VirtPageNum) -> Result<(), ErrNo> {
+        self.walk_create(vpn).map(|_| ())
+    }
 
     /// Unmap a vpn-ppn pair in the page table
     /// # Description
     /// Unmap a pair of virtual page and physical page.
     /// Panic on unmapping not mapped memory.
     pub fn unmap(&mut self, vpn: VirtPageNum) {
-        let pte = self.walk_create(vpn);
+        self.split_huge(vpn).expect("No frame to split a huge page.");
+        let pte = self.walk(vpn).unwrap_or_else(|| panic!("{:?} hasn't been mapped.", vpn));
         assert!(pte.valid(), "{:?} hasn't been mapped.", vpn);
         *pte = PageTableEntry::empty();
+        self.flush_tlb(vpn);
     }
 
     pub fn modify_access(&mut self, vpn: VirtPageNum, flags: PTEFlags) -> Option<()> {
+        self.split_huge(vpn).ok()?;
         let pte = self.walk(vpn)?;
         // assert!(pte.valid(), "{:?} has already been mapped.", vpn);
         // verbose!("Changeing {:?} flag to {:?}", vpn, flags);
         pte.modify_access(flags);
+        self.flush_tlb(vpn);
         Some(())
     }
 
+    /// If the MMU is using this pagetable now
+    fn is_active(&self) -> bool {
+        satp::read().bits() == self.get_satp()
+    }
+
+    /// Flush the TLB entry of `vpn` after its pte changed
+    /// # Description
+    /// Only needed if the pagetable is in use. Any other one, i.e. a user pagetable while in the kernel,
+    /// is switched to with a full `sfence.vma` on trap return.  
+    /// We only run on hart 0, no other hart can cache the entry.
+    pub fn flush_tlb(&self, vpn: VirtPageNum) {
+        if self.is_active() {
+            let va = VirtAddr::from(vpn);
+            unsafe {
+                asm!("sfence.vma {}, zero", in(reg) va.0);
+            }
+        }
+    }
+
+    /// Flush the whole TLB after bulk changes, if the pagetable is in use
+    pub fn flush_tlb_all(&self) {
+        if self.is_active() {
+            unsafe {
+                asm!("sfence.vma");
+            }
+        }
+    }
+
+    /// Render the pagetable as a tree
+    /// # Description
+    /// One line per valid pte, indented by its level: the index in its table and the ppn it holds.  
+    /// Leaves also show their flags and the virtual address they map, so demand paging can be watched in action.
+    pub fn dump(&self) -> String {
+        let mut content = format!("pagetable @ {:#x}\n", PhysAddr::from(self.root_ppn).0);
+        Self::dump_table(self.root_ppn, 0, 0, &mut content);
+        content
+    }
+
+    /// Render the table at `ppn` of `level`, `vpn_prefix` holds the indexes of the levels above
+    fn dump_table(ppn: PhysPageNum, level: usize, vpn_prefix: usize, content: &mut String) {
+        let indent = "    ".repeat(level);
+        for (idx, pte) in ppn.read_pte().iter().enumerate() {
+            if !pte.valid() {
+                continue;
+            }
+            let vpn = vpn_prefix << 9 | idx;
+            if pte.is_leaf() || level == 2 {
+                let mut va = vpn << (9 * (2 - level) + 12);
+                // SV39 addresses are sign extended from bit 38
+                if va & (1 << 38) != 0 {
+                    va |= !((1usize << 39) - 1);
+                }
+                let size = match level {
+                    0 => " 1G",
+                    1 => " 2M",
+                    _ => "",
+                };
+                *content += &format!("{}[{:3}] ppn {:#x} {} va {:#x}{}\n", indent, idx, pte.ppn().0, pte.flags_string(), va, size);
+            } else {
+                *content += &format!("{}[{:3}] -> ppn {:#x}\n", indent, idx, pte.ppn().0);
+                Self::dump_table(pte.ppn(), level + 1, vpn, content);
+            }
+        }
+    }
+
     /// Read and construct a pagetable from SATP value.
     /// # Description
     /// Read and construct a pagetable from SATP value, for SATP contains the root_ppn info.
@@ -243,8 +418,16 @@ impl PageTable {
     /// Translate a virtual page number to the page table entry, and return a clone of it. Return None if not found.
     /// # Return
     /// Some(PageTableEntry) containing a copy of the original pte, or None if not found.
+    /// For a huge page, the pte returned has the ppn of `vpn` itself.
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.walk(vpn).map(|pte| pte.clone())
+        let (pte, level) = self.find(vpn)?;
+        let indexes = vpn.indexes();
+        let offset = match level {
+            0 => indexes[1] * MEGAPAGE_PAGES + indexes[2],
+            1 => indexes[2],
+            _ => 0,
+        };
+        Some(PageTableEntry::new(pte.ppn() + offset, pte.flags()))
     }
 
 
@@ -254,7 +437,7 @@ impl PageTable {
     /// # Return
     /// Some(PhysAddr) containing a copy of the original pte, or None if not found.
     pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
-        self.walk(va.clone().to_vpn()).map(|pte| {
+        self.translate(va.clone().to_vpn()).map(|pte| {
             return PhysAddr::from(pte.ppn()) + va.page_offset()
         })
     }
@@ -271,7 +454,10 @@ pub fn get_user_data(satp: usize, mut start: VirtAddr, len: usize) -> Vec<&'stat
     let mut pages = Vec::new();
     while start < end {
         let mut vpn = start.to_vpn();
-        let ppn = pagetable.translate(vpn).unwrap().ppn();
+        let pte = pagetable.walk(vpn).unwrap();
+        // the slices may be written
+        pte.set_dirty();
+        let ppn = pte.ppn();
         vpn.step();
         let copy_end = min(vpn.into(), end);    // page end or buf end
         pages.push(&mut ppn.page_ptr()[
@@ -310,7 +496,9 @@ pub fn write_user_data(satp: usize, data: &[u8], mut start: VirtAddr, len: usize
     let end = start + len;
     while start < end {
         let mut vpn = start.to_vpn();
-        let ppn = pagetable.translate(vpn).unwrap().ppn();
+        let pte = pagetable.walk(vpn).unwrap();
+        pte.set_dirty();
+        let ppn = pte.ppn();
         vpn.step();
         let copy_end = min(vpn.into(), end);    // page end or buf end
         &ppn.page_ptr()[
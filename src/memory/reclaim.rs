@@ -0,0 +1,53 @@
+//! Memory reclaim
+//! # Description
+//! Run by the frame allocator when free frames drop below `FRAME_LOW_WATERMARK`.  
+//! Clean pages of file mappings are dropped, they are read back on the next fault.
+//! Cached exec images nobody is running and clean block caches are released too.  
+//! Every lock is only tried, so it is safe from the fault path, where the faulting process and its segment are locked.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use alloc::vec::Vec;
+use crate::process::{current_process, PROCESS_MANAGER};
+use crate::process::elf_cache::ELF_CACHE;
+use crate::fs::try_drop_clean_caches;
+
+/// Set while a reclaim pass runs, freeing pages never allocates frames but be safe against recursion
+static RECLAIMING: AtomicBool = AtomicBool::new(false);
+/// Frames released by reclaim since boot
+static RECLAIMED: AtomicUsize = AtomicUsize::new(0);
+
+/// Release what can be released without waiting on anyone
+/// # Return
+/// # of frames released, block caches live in the kernel heap and are not counted
+pub fn reclaim() -> usize {
+    if RECLAIMING.swap(true, Ordering::Acquire) {
+        return 0;
+    }
+    let mut procs = match PROCESS_MANAGER.try_lock() {
+        Some(manager) => manager.idle_procs(),
+        None => Vec::new(),
+    };
+    if let Some(proc) = current_process() {
+        procs.push(proc);
+    }
+    let mut freed = 0;
+    for proc in procs {
+        if let Some(mut inner) = proc.inner.try_lock() {
+            freed += inner.layout.reclaim_clean_pages();
+        }
+    }
+    // read from the file again on the next exec
+    if let Some(mut cache) = ELF_CACHE.try_lock() {
+        freed += cache.shrink_unused();
+    }
+    let caches = try_drop_clean_caches();
+    RECLAIMED.fetch_add(freed, Ordering::Relaxed);
+    RECLAIMING.store(false, Ordering::Release);
+    info!("reclaim: released {} frames and {} block caches", freed, caches);
+    freed
+}
+
+/// Frames released by reclaim since boot
+pub fn reclaimed_frames() -> usize {
+    RECLAIMED.load(Ordering::Relaxed)
+}
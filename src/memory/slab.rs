@@ -0,0 +1,262 @@
+//! Object caches for frequently allocated kernel objects
+//! # Description
+//! An `ObjectCache` hands out objects of one type from slabs, kernel heap blocks holding `SLAB_OBJECTS` objects each.
+//! Allocating takes one heap allocation per slab instead of one per object, and freed slots are reused right away.
+//! A slab is aligned to its size with a `SlabHeader` at the start, so the slab of an object is found by masking its address.
+//! Empty slabs go back to the heap, except one kept to avoid thrashing at a slab boundary.
+
+use core::alloc::Layout;
+use core::marker::PhantomData;
+use core::mem::{align_of, size_of, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use alloc::alloc::{alloc, dealloc, handle_alloc_error};
+use alloc::boxed::Box;
+use spin::Mutex;
+use crate::config::PAGE_SIZE;
+
+/// Objects in a slab
+const SLAB_OBJECTS: usize = 8;
+
+/// Bytes of kernel heap held by slabs of all caches
+static SLAB_BYTES: AtomicUsize = AtomicUsize::new(0);
+/// Objects served from a free slot of an existing slab
+static SLAB_HITS: AtomicUsize = AtomicUsize::new(0);
+
+/// Start of every slab
+struct SlabHeader {
+    next: *mut SlabHeader,
+    prev: *mut SlabHeader,
+    /// First free slot, free slots are linked through their first word
+    free: *mut usize,
+    /// Objects handed out
+    in_use: usize,
+}
+
+/// Add `slab` to the front of the list at `head`
+unsafe fn push_slab(head: &mut *mut SlabHeader, slab: *mut SlabHeader) {
+    (*slab).prev = ptr::null_mut();
+    (*slab).next = *head;
+    if !head.is_null() {
+        (**head).prev = slab;
+    }
+    *head = slab;
+}
+
+/// Take `slab` out of the list at `head`
+unsafe fn unlink_slab(head: &mut *mut SlabHeader, slab: *mut SlabHeader) {
+    let (prev, next) = ((*slab).prev, (*slab).next);
+    if prev.is_null() {
+        *head = next;
+    } else {
+        (*prev).next = next;
+    }
+    if !next.is_null() {
+        (*next).prev = prev;
+    }
+}
+
+/// The slabs of a cache, protected by the cache lock
+struct CacheInner {
+    /// Slabs with a free slot
+    partial: *mut SlabHeader,
+    /// Slabs with every slot in use
+    full: *mut SlabHeader,
+}
+
+// the slabs are only reached through the cache lock
+unsafe impl Send for CacheInner {}
+
+/// A cache of objects of type `T`
+/// # Description
+/// Meant to be a static, objects are allocated explicitly with `alloc()` and returned when their `CacheBox` drops.
+pub struct ObjectCache<T> {
+    inner: Mutex<CacheInner>,
+    /// If false, every object is a heap block of its own, see `set_enabled()`
+    enabled: AtomicBool,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> ObjectCache<T> {
+    /// Create an empty cache, slabs are allocated on first use
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(CacheInner {
+                partial: ptr::null_mut(),
+                full: ptr::null_mut(),
+            }),
+            enabled: AtomicBool::new(true),
+            _type: PhantomData,
+        }
+    }
+
+    /// Alignment of a slot, it holds either a `T` or the free list link
+    fn slot_align() -> usize {
+        align_of::<T>().max(align_of::<usize>())
+    }
+
+    /// Distance between two slots
+    fn stride() -> usize {
+        let align = Self::slot_align();
+        (size_of::<T>().max(size_of::<usize>()) + align - 1) / align * align
+    }
+
+    /// Offset of the first slot from the slab start
+    fn first_slot() -> usize {
+        let align = Self::slot_align();
+        (size_of::<SlabHeader>() + align - 1) / align * align
+    }
+
+    /// Size and alignment of a slab, the same power of two
+    fn slab_layout() -> Layout {
+        let size = (Self::first_slot() + SLAB_OBJECTS * Self::stride()).next_power_of_two().max(PAGE_SIZE);
+        Layout::from_size_align(size, size).unwrap()
+    }
+
+    /// Allocate an empty slab with every slot on its free list
+    unsafe fn new_slab() -> *mut SlabHeader {
+        let layout = Self::slab_layout();
+        let slab = alloc(layout) as *mut SlabHeader;
+        if slab.is_null() {
+            handle_alloc_error(layout);
+        }
+        let mut free: *mut usize = ptr::null_mut();
+        for i in (0..SLAB_OBJECTS).rev() {
+            let slot = (slab as usize + Self::first_slot() + i * Self::stride()) as *mut usize;
+            *slot = free as usize;
+            free = slot;
+        }
+        slab.write(SlabHeader {
+            next: ptr::null_mut(),
+            prev: ptr::null_mut(),
+            free,
+            in_use: 0,
+        });
+        SLAB_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        slab
+    }
+
+    /// Take a free slot, allocating a slab if there is none
+    fn take_slot(&self) -> *mut T {
+        let mut inner = self.inner.lock();
+        unsafe {
+            let mut slab = inner.partial;
+            if slab.is_null() {
+                slab = Self::new_slab();
+                push_slab(&mut inner.partial, slab);
+            } else {
+                SLAB_HITS.fetch_add(1, Ordering::Relaxed);
+            }
+            let slot = (*slab).free;
+            (*slab).free = *slot as *mut usize;
+            (*slab).in_use += 1;
+            if (*slab).in_use == SLAB_OBJECTS {
+                unlink_slab(&mut inner.partial, slab);
+                push_slab(&mut inner.full, slab);
+            }
+            slot as *mut T
+        }
+    }
+
+    /// Return the slot at `obj`, the object in it is dropped already
+    /// # Description
+    /// The slab is freed if it is empty and there is another slab with a free slot.
+    unsafe fn give_back(&self, obj: *mut T) {
+        let layout = Self::slab_layout();
+        let slab = (obj as usize & !(layout.size() - 1)) as *mut SlabHeader;
+        let mut inner = self.inner.lock();
+        if (*slab).in_use == SLAB_OBJECTS {
+            unlink_slab(&mut inner.full, slab);
+            push_slab(&mut inner.partial, slab);
+        }
+        let slot = obj as *mut usize;
+        *slot = (*slab).free as usize;
+        (*slab).free = slot;
+        (*slab).in_use -= 1;
+        if (*slab).in_use == 0 && (inner.partial != slab || !(*slab).next.is_null()) {
+            unlink_slab(&mut inner.partial, slab);
+            drop(inner);
+            dealloc(slab as *mut u8, layout);
+            SLAB_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        }
+    }
+
+    /// Serve objects from slabs, or give each its own heap block
+    /// # Description
+    /// On by default, turned off to compare against the plain heap. Objects keep to where they came from.
+    /// # Return
+    /// If it was on before
+    pub fn set_enabled(&self, enabled: bool) -> bool {
+        self.enabled.swap(enabled, Ordering::Relaxed)
+    }
+}
+
+impl<T: 'static> ObjectCache<T> {
+    /// Move `value` into a slot of the cache
+    pub fn alloc(&'static self, value: T) -> CacheBox<T> {
+        let from_slab = self.enabled.load(Ordering::Relaxed);
+        let obj = if from_slab {
+            self.take_slot()
+        } else {
+            Box::into_raw(Box::new(MaybeUninit::<T>::uninit())) as *mut T
+        };
+        unsafe {
+            obj.write(value);
+        }
+        CacheBox {
+            obj: NonNull::new(obj).unwrap(),
+            cache: self,
+            from_slab,
+        }
+    }
+}
+
+/// An object owned from an `ObjectCache`, returned to it on drop
+pub struct CacheBox<T: 'static> {
+    obj: NonNull<T>,
+    cache: &'static ObjectCache<T>,
+    /// False if it was allocated with the cache disabled
+    from_slab: bool,
+}
+
+unsafe impl<T: Send> Send for CacheBox<T> {}
+unsafe impl<T: Sync> Sync for CacheBox<T> {}
+
+impl<T> Deref for CacheBox<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { self.obj.as_ref() }
+    }
+}
+
+impl<T> DerefMut for CacheBox<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.obj.as_mut() }
+    }
+}
+
+impl<T> Drop for CacheBox<T> {
+    fn drop(&mut self) {
+        let obj = self.obj.as_ptr();
+        unsafe {
+            ptr::drop_in_place(obj);
+            if self.from_slab {
+                self.cache.give_back(obj);
+            } else {
+                drop(Box::from_raw(obj as *mut MaybeUninit<T>));
+            }
+        }
+    }
+}
+
+/// Bytes of kernel heap held by slabs, in use or not
+pub fn slab_bytes() -> usize {
+    SLAB_BYTES.load(Ordering::Relaxed)
+}
+
+/// Objects served from a slab that already existed, since boot
+pub fn slab_hits() -> usize {
+    SLAB_HITS.load(Ordering::Relaxed)
+}
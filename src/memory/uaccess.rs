@@ -0,0 +1,38 @@
+//! Checked access to the user space of the current process
+//! # Description
+//! Unlike `get_user_data()` and friends, nothing here panics on a bad user pointer, Err(BadAddress) is returned instead.  
+//! Note that these functions lock the current process, so use the `MemLayout` methods if the lock is already held.
+
+use core::mem::{size_of, MaybeUninit};
+use core::slice::{from_raw_parts, from_raw_parts_mut};
+use crate::process::{current_process, ErrNo};
+use super::VirtAddr;
+
+/// Copy user memory at `src` of the current process into `dst`
+/// # Return
+/// # of bytes copied, Err(BadAddress) on the first page the user can't read
+pub fn copy_from_user(dst: &mut [u8], src: VirtAddr) -> Result<usize, ErrNo> {
+    current_process().unwrap().get_inner_locked().layout.copy_from_user(dst, src)
+}
+
+/// Copy `src` into user memory at `dst` of the current process
+/// # Return
+/// # of bytes copied, Err(BadAddress) on the first page the user can't write
+pub fn copy_to_user(dst: VirtAddr, src: &[u8]) -> Result<usize, ErrNo> {
+    current_process().unwrap().get_inner_locked().layout.copy_to_user(dst, src)
+}
+
+/// Read a plain object from user memory of the current process
+pub fn read_from_user<T: Copy>(src: VirtAddr) -> Result<T, ErrNo> {
+    let mut obj = MaybeUninit::<T>::uninit();
+    let bytes = unsafe { from_raw_parts_mut(obj.as_mut_ptr() as *mut u8, size_of::<T>()) };
+    copy_from_user(bytes, src)?;
+    Ok(unsafe { obj.assume_init() })
+}
+
+/// Write a plain object into user memory of the current process
+pub fn write_to_user<T: Copy>(dst: VirtAddr, obj: &T) -> Result<(), ErrNo> {
+    let bytes = unsafe { from_raw_parts(obj as *const T as *const u8, size_of::<T>()) };
+    copy_to_user(dst, bytes)?;
+    Ok(())
+}
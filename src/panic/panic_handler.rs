@@ -1,9 +1,13 @@
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
 use crate::memory::KERNEL_MEM_LAYOUT;
 use crate::{process::current_process, sbi::shutdown};
 
+/// Set once we are in the panic handler, so that a panic during flushing does not recurse
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
 /// The panic handler.  
-/// On panic, it will print panic information then shutdown the machine.
+/// On panic, it will print panic information, flush mounted file systems then shutdown the machine.
 #[panic_handler]
 pub fn panic(info: &PanicInfo) -> ! {
     if let Some(location) = info.location() {
@@ -11,10 +15,17 @@ pub fn panic(info: &PanicInfo) -> ! {
     } else {
         fatal!("Panic @ ?:? : {}", info.message().unwrap());
     }
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        fatal!("Panicked while panicking, shutdown without flushing.");
+        shutdown();
+    }
     fatal!("KERNELMemory layout: ");
     unsafe {
         KERNEL_MEM_LAYOUT.force_unlock();
     }
     KERNEL_MEM_LAYOUT.lock().print_layout();
+    if !crate::fs::try_sync_all() {
+        fatal!("Some file systems are busy, they are not fully flushed.");
+    }
     shutdown();
 }
\ No newline at end of file
@@ -36,6 +36,12 @@ pub const SIGSYS    : usize = 31;
 pub const SIGRTMIN	: usize = 34;
 pub const SIGRTMAX	: usize = 64;
 
+/// Signals that can't be blocked, caught or ignored
+pub const SIG_UNBLOCKABLE: u64 = (1u64 << SIGKILL) | (1u64 << SIGSTOP);
+
+/// Signals whose default action is to stop the process
+pub const SIG_STOPPING: u64 = (1u64 << SIGSTOP) | (1u64 << SIGTSTP) | (1u64 << SIGTTIN) | (1u64 << SIGTTOU);
+
 #[no_mangle]
 #[link_section = ".text.u_trampoline_rust"]
 pub fn def_terminate_self(_: isize) {
@@ -47,12 +53,6 @@ pub fn def_terminate_self(_: isize) {
     }
 }
 
-#[no_mangle]
-#[link_section = ".text.u_trampoline_rust"]
-pub fn def_ignore(_: isize) {
-	// do nothing
-}
-
 #[no_mangle]
 #[link_section = ".text.u_trampoline_rust"]
 pub fn def_dump_core(_: isize) {
@@ -66,33 +66,3 @@ pub fn def_dump_core(_: isize) {
         )
     }
 }
-
-#[no_mangle]
-#[link_section = ".text.u_trampoline_rust"]
-pub static PROC_STOPPED: bool = true;
-
-#[no_mangle]
-#[link_section = ".text.u_trampoline_rust"]
-pub fn def_stop(_: isize) {
-    extern "C" {
-        fn sutrampoline();
-    }
-    unsafe {
-        let proc_stopped: *mut bool = ((&PROC_STOPPED as *const bool) as usize - sutrampoline as usize + U_TRAMPOLINE) as *mut bool;
-        *proc_stopped = true;
-        
-        while *proc_stopped {}
-    }
-}
-
-#[no_mangle]
-#[link_section = ".text.u_trampoline_rust"]
-pub fn def_cont(_: isize) {
-    extern "C" {
-        fn sutrampoline();
-    }
-    unsafe {
-        let proc_stopped: *mut bool = ((&PROC_STOPPED as *const bool) as usize - sutrampoline as usize + U_TRAMPOLINE) as *mut bool;
-        *proc_stopped = false;
-    }
-}
\ No newline at end of file
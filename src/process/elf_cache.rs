@@ -0,0 +1,177 @@
+//! A small cache of recently executed program images.  
+//! Re-executing the same binary (e.g. a shell spawning the same command over and over) is served from memory instead of going through the filesystem again.
+use crate::config::{ELF_CACHE_SHARE, PAGE_SIZE};
+use crate::fs::File;
+use crate::memory::{FrameTracker, PhysAddr, alloc_continuous, total_frames};
+use crate::process::ErrNo;
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::slice::{from_raw_parts, from_raw_parts_mut};
+use lazy_static::*;
+use spin::Mutex;
+
+/// A file image held in physically continuous frames.
+pub struct ExecImage {
+    frames: Vec<FrameTracker>,
+    len: usize,
+}
+
+impl ExecImage {
+    /// Read the whole file into a newly allocated image.
+    pub fn load(file: &Arc<dyn File>, len: usize) -> Result<Self, ErrNo> {
+        let frames = alloc_continuous(len / PAGE_SIZE + 1);
+        let head_addr: PhysAddr = frames[0].ppn.into();
+        let arr: &mut [u8] = unsafe {
+            from_raw_parts_mut(head_addr.0 as *mut u8, len)
+        };
+        let mut filled = 0;
+        while filled < len {
+            let read = file.read(&mut arr[filled..])?;
+            if read == 0 {
+                // file shrank under us, don't cache a truncated image
+                return Err(ErrNo::IOError);
+            }
+            filled += read;
+        }
+        Ok(Self { frames, len })
+    }
+
+    /// The content of the image
+    pub fn data(&self) -> &[u8] {
+        let head_addr: PhysAddr = self.frames[0].ppn.into();
+        unsafe {
+            from_raw_parts(head_addr.0 as *const u8, self.len)
+        }
+    }
+
+    /// Number of pages occupied by the image
+    pub fn pages(&self) -> usize {
+        self.frames.len()
+    }
+}
+
+struct ElfCacheEntry {
+    path: String,
+    size: u64,
+    mtime_sec: u32,
+    mtime_nsec: u32,
+    image: Arc<ExecImage>,
+}
+
+/// LRU cache of exec images, keyed by path and validated by modification time and size.
+pub struct ElfCache {
+    /// Most recently used entry at the front
+    entries: VecDeque<ElfCacheEntry>,
+    pages: usize,
+    max_pages: usize,
+}
+
+impl ElfCache {
+    /// Create a cache holding at most `max_pages` pages of images.
+    pub fn new(max_pages: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            pages: 0,
+            max_pages,
+        }
+    }
+
+    /// Look up `path`, dropping the entry if the file has changed since it was cached.
+    pub fn get(&mut self, path: &str, size: u64, mtime_sec: u32, mtime_nsec: u32) -> Option<Arc<ExecImage>> {
+        let idx = self.entries.iter().position(|e| e.path == path)?;
+        let entry = self.entries.remove(idx).unwrap();
+        if entry.size != size || entry.mtime_sec != mtime_sec || entry.mtime_nsec != mtime_nsec {
+            self.pages -= entry.image.pages();
+            return None;
+        }
+        let image = entry.image.clone();
+        self.entries.push_front(entry);
+        Some(image)
+    }
+
+    /// Insert an image, evicting least recently used entries to stay below the page limit.
+    pub fn insert(&mut self, path: String, size: u64, mtime_sec: u32, mtime_nsec: u32, image: Arc<ExecImage>) {
+        self.invalidate(&path);
+        if image.pages() > self.max_pages {
+            return;
+        }
+        self.shrink(self.max_pages - image.pages());
+        self.pages += image.pages();
+        self.entries.push_front(ElfCacheEntry { path, size, mtime_sec, mtime_nsec, image });
+    }
+
+    /// Forget the cached image of `path`, if any.
+    pub fn invalidate(&mut self, path: &str) {
+        if let Some(idx) = self.entries.iter().position(|e| e.path == path) {
+            let entry = self.entries.remove(idx).unwrap();
+            self.pages -= entry.image.pages();
+        }
+    }
+
+    /// Evict least recently used images until at most `target` pages are held.
+    /// # Return
+    /// Number of pages dropped by the cache. Images still used by a running exec are not freed until it finishes.
+    pub fn shrink(&mut self, target: usize) -> usize {
+        let before = self.pages;
+        while self.pages > target {
+            let evicted = self.entries.pop_back().unwrap();
+            self.pages -= evicted.image.pages();
+        }
+        before - self.pages
+    }
+
+    /// Drop every cached image, returning the frames to the allocator.
+    pub fn clear(&mut self) -> usize {
+        self.shrink(0)
+    }
+
+    /// Drop the images that no exec is using
+    /// # Description
+    /// Used by memory reclaim. Evicting an image still in use would not free anything.
+    /// # Return
+    /// Number of frames returned to the allocator
+    pub fn shrink_unused(&mut self) -> usize {
+        let mut freed = 0;
+        let mut pages = self.pages;
+        self.entries.retain(|entry| {
+            if Arc::strong_count(&entry.image) != 1 {
+                return true;
+            }
+            pages -= entry.image.pages();
+            freed += entry.image.pages();
+            false
+        });
+        self.pages = pages;
+        freed
+    }
+
+    /// Number of pages currently held by the cache
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    /// Most pages the cache may hold
+    pub fn max_pages(&self) -> usize {
+        self.max_pages
+    }
+}
+
+lazy_static! {
+    /// Sized from the frames of the RAM detected at boot
+    pub static ref ELF_CACHE: Mutex<ElfCache> = Mutex::new(ElfCache::new(total_frames() / ELF_CACHE_SHARE));
+}
+
+/// Get the image of the file at `path`, reading it from `file` on a cache miss.
+pub fn get_exec_image(path: &str, file: &Arc<dyn File>) -> Result<Arc<ExecImage>, ErrNo> {
+    let stat = file.poll();
+    if let Some(image) = ELF_CACHE.lock().get(path, stat.size, stat.mtime_sec, stat.mtime_nsec) {
+        verbose!("Exec image cache hit: {}", path);
+        return Ok(image);
+    }
+    let image = Arc::new(ExecImage::load(file, stat.size as usize)?);
+    ELF_CACHE.lock().insert(String::from(path), stat.size, stat.mtime_sec, stat.mtime_nsec, image.clone());
+    Ok(image)
+}
@@ -0,0 +1,52 @@
+//! Load average of the system
+//! # Description
+//! Exponentially weighted moving averages of the number of runnable processes, sampled every second.  
+//! Fixed point, like linux does: `FIXED_1` stands for 1.0.
+
+use crate::config::CLOCK_FREQ;
+use crate::sbi::get_time;
+use lazy_static::*;
+use spin::Mutex;
+use super::nr_running;
+
+/// Bits of fraction in the fixed point load averages
+pub const FSHIFT: usize = 11;
+/// 1.0 in fixed point
+pub const FIXED_1: usize = 1 << FSHIFT;
+/// Decay factors for a 1 second sample period: exp(-1/60), exp(-1/300), exp(-1/900)
+const EXP: [usize; 3] = [2014, 2041, 2046];
+
+struct LoadAvg {
+    /// 1, 5 and 15 minutes averages
+    avg: [usize; 3],
+    /// time of the next sample
+    next_sample: u64,
+}
+
+lazy_static! {
+    static ref LOAD_AVG: Mutex<LoadAvg> = Mutex::new(LoadAvg {
+        avg: [0; 3],
+        next_sample: 0,
+    });
+}
+
+/// Sample the run queue length if a second has passed since the last sample.
+/// # Description
+/// Called on each timer interrupt.
+pub fn sample_load() {
+    let now = get_time();
+    let mut load = LOAD_AVG.lock();
+    if now < load.next_sample {
+        return;
+    }
+    load.next_sample = now + CLOCK_FREQ;
+    let active = nr_running() * FIXED_1;
+    for i in 0..3 {
+        load.avg[i] = (load.avg[i] * EXP[i] + active * (FIXED_1 - EXP[i])) >> FSHIFT;
+    }
+}
+
+/// Get the 1, 5 and 15 minutes load averages, in fixed point.
+pub fn load_avg() -> [usize; 3] {
+    LOAD_AVG.lock().avg
+}
@@ -0,0 +1,98 @@
+//! Lock ordering checks for PCB locks
+//! # Description
+//! PCB locks are spin locks, taking one twice or in the wrong order hangs the hart silently.  
+//! The order enforced here: the lock of the current process comes first, other processes after.  
+//! Checks are only done in debug builds and in builds with the `kernel_tests` feature.
+
+use super::{ProcessControlBlockInner, current_process};
+use alloc::vec::Vec;
+use core::ops::{Deref, DerefMut};
+use lazy_static::*;
+use spin::{Mutex, MutexGuard};
+
+lazy_static! {
+    /// pids of PCBs currently locked through `LockedInner`
+    static ref HELD_PCB_LOCKS: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+}
+
+/// If lock order is checked in this build
+pub const LOCK_ORDER_CHECKS: bool = cfg!(debug_assertions) || cfg!(feature = "kernel_tests");
+
+/// Check that locking the PCB of `pid` with the PCBs of `held` locked keeps the lock order
+/// # Return
+/// Err with what is wrong if it doesn't
+fn check_order(held: &[usize], pid: usize, is_current: bool) -> Result<(), &'static str> {
+    if held.contains(&pid) {
+        return Err("Re-entrant lock on PCB");
+    }
+    if is_current && !held.is_empty() {
+        return Err("PCB of current process locked after other processes");
+    }
+    Ok(())
+}
+
+/// Check that locking the PCB of `pid` keeps the lock order, and record it as held.
+/// # Description
+/// Must be called before actually taking the lock, a re-entrant spin lock never returns to report anything.
+pub fn acquire_check(pid: usize) {
+    if !LOCK_ORDER_CHECKS {
+        return;
+    }
+    if let Err(msg) = check_lock(pid) {
+        panic!("{}: process {}, held: {:?}", msg, pid, *HELD_PCB_LOCKS.lock());
+    }
+    HELD_PCB_LOCKS.lock().push(pid);
+}
+
+/// Check that locking the PCB of `pid` now keeps the lock order, without locking it
+/// # Return
+/// Err with what is wrong if it doesn't
+pub fn check_lock(pid: usize) -> Result<(), &'static str> {
+    let is_current = current_process().map_or(false, |current| current.pid.0 == pid);
+    check_order(&HELD_PCB_LOCKS.lock(), pid, is_current)
+}
+
+/// Record the PCB of `pid` as no longer locked.
+pub fn release_check(pid: usize) {
+    if !LOCK_ORDER_CHECKS {
+        return;
+    }
+    let mut held = HELD_PCB_LOCKS.lock();
+    if let Some(idx) = held.iter().position(|held_pid| *held_pid == pid) {
+        held.remove(idx);
+    }
+}
+
+/// Locked PCB inner, the memory layout is reached through it.
+/// # Description
+/// Acquired through `ProcessControlBlock::lock_inner`, lock order is checked if `LOCK_ORDER_CHECKS`.
+pub struct LockedInner<'a> {
+    pid: usize,
+    inner: MutexGuard<'a, ProcessControlBlockInner>,
+}
+
+impl<'a> LockedInner<'a> {
+    pub fn new(pid: usize, inner: MutexGuard<'a, ProcessControlBlockInner>) -> Self {
+        Self { pid, inner }
+    }
+}
+
+impl<'a> Deref for LockedInner<'a> {
+    type Target = ProcessControlBlockInner;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+impl<'a> DerefMut for LockedInner<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.inner
+    }
+}
+
+impl<'a> Drop for LockedInner<'a> {
+    fn drop(&mut self) {
+        release_check(self.pid);
+    }
+}
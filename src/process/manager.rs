@@ -2,8 +2,10 @@
 
 // use super::ProcessContext;
 use super::{ProcessControlBlock, ProcessStatus, current_process};
+use crate::fs::bdflush_tick;
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use spin::Mutex;
 
 // use crate::config::*;
@@ -12,6 +14,8 @@ use lazy_static::*;
 /// The ProcessManager of choice: Round Robin.
 pub struct ProcessManager {
     pub processes: VecDeque<Arc<ProcessControlBlock>>,
+    /// Processes kept out of the run queue, stopped by a signal or sleeping on a WaitQueue.
+    pub parked: Vec<Arc<ProcessControlBlock>>,
 }
 
 unsafe impl Sync for ProcessManager {}
@@ -26,7 +30,8 @@ impl ProcessManager {
     /// Construct a new ProcessManager
     pub fn new() -> Self {
         Self {
-            processes: VecDeque::new()
+            processes: VecDeque::new(),
+            parked: Vec::new(),
         }
     }
 
@@ -37,16 +42,33 @@ impl ProcessManager {
 
     /// dequeue a new process, i.e. it's either running or dead.
     pub fn dequeue(&mut self) -> Option<Arc<ProcessControlBlock>> {
-        if let Some(process) = self.processes.pop_front() {
-            return Some(process);
+        // empty when every process is sleeping or stopped, the processor idles then.
+        return self.processes.pop_front();
+    }
+
+    /// park a stopped or sleeping process, it won't be scheduled until resumed.
+    pub fn park(&mut self, process: Arc<ProcessControlBlock>) {
+        self.parked.push(process);
+    }
+
+    /// move a parked process back to the run queue.
+    pub fn resume(&mut self, pid: usize) -> bool {
+        if let Some(idx) = self.parked.iter().position(|proc| proc.pid.0 == pid) {
+            let proc = self.parked.remove(idx);
+            self.processes.push_back(proc);
+            true
         } else {
-            warning!("No process in Process Manager!");
-            return None;
+            false
         }
     }
 
+    /// every process that is not running, either ready or parked.
+    pub fn idle_procs(&self) -> Vec<Arc<ProcessControlBlock>> {
+        self.processes.iter().chain(self.parked.iter()).cloned().collect()
+    }
+
     pub fn get_idle_proc_by_pid(&self, pid: usize) -> Option<Arc<ProcessControlBlock>> {
-        for proc in &self.processes {
+        for proc in self.processes.iter().chain(self.parked.iter()) {
             if proc.pid.0 == pid {
                 return Some(proc.clone())
             }
@@ -55,6 +77,9 @@ impl ProcessManager {
     }
 
     pub fn remove_proc_by_pid(&mut self, pid: usize) -> Option<Arc<ProcessControlBlock>> {
+        if let Some(idx) = self.parked.iter().position(|proc| proc.pid.0 == pid) {
+            return Some(self.parked.remove(idx));
+        }
         let proc_count = self.processes.len();
         for i in 0..proc_count {
             let proc = self.processes.pop_back()?;
@@ -80,6 +105,55 @@ pub fn dequeue() -> Option<Arc<ProcessControlBlock>> {
     return PROCESS_MANAGER.lock().dequeue();
 }
 
+/// park a stopped or sleeping process, it won't be scheduled until resumed.
+/// Use locked to access the manager, to prevent data racing.
+pub fn park(process: Arc<ProcessControlBlock>) {
+    PROCESS_MANAGER.lock().park(process);
+}
+
+/// move a parked process back to the run queue.
+/// Use locked to access the manager, to prevent data racing.
+pub fn resume(pid: usize) -> bool {
+    PROCESS_MANAGER.lock().resume(pid)
+}
+
+/// Fire expired interval timers of every process, and the kernel ones.
+/// # Description
+/// Called on each timer interrupt and in the idle loop. Note that all PCB locks must be released before calling this.
+pub fn check_timers() {
+    let procs = PROCESS_MANAGER.lock().idle_procs();
+    for proc in procs.iter() {
+        proc.check_timers();
+    }
+    if let Some(current) = current_process() {
+        current.check_timers();
+    }
+    bdflush_tick();
+}
+
+/// The earliest wall time deadline of the processes not running, see `ProcessControlBlock::next_deadline`.
+pub fn next_deadline() -> Option<u64> {
+    let procs = PROCESS_MANAGER.lock().idle_procs();
+    procs.iter().filter_map(|proc| proc.next_deadline()).min()
+}
+
+/// Number of processes running or ready to run.
+pub fn nr_running() -> usize {
+    PROCESS_MANAGER.lock().processes.len() + current_process().map_or(0, |_| 1)
+}
+
+/// Number of processes alive, running, ready or parked.
+pub fn nr_processes() -> usize {
+    let manager = PROCESS_MANAGER.lock();
+    manager.processes.len() + manager.parked.len() + current_process().map_or(0, |_| 1)
+}
+
+/// Number of processes sleeping on a WaitQueue.
+pub fn nr_sleeping() -> usize {
+    let parked = PROCESS_MANAGER.lock().parked.clone();
+    parked.iter().filter(|proc| proc.get_inner_locked().status == ProcessStatus::Sleeping).count()
+}
+
 pub fn get_proc_by_pid(pid: usize) -> Option<Arc<ProcessControlBlock>> {
     PROCESS_MANAGER
         .lock()
@@ -8,6 +8,13 @@ mod processor;
 mod proc0;
 pub mod default_handlers;
 pub mod kernel_stored_app_loader;
+pub mod elf_cache;
+mod wait_queue;
+mod lock_order;
+pub mod loadavg;
+pub mod stats;
+mod oom;
+pub mod ptrace;
 mod error;
 
 pub use error::ErrNo;
@@ -19,21 +26,41 @@ pub use pcb::{
     ProcessStatus,
     SignalFlags,
     default_sig_handlers,
+    default_sig_dispositions,
     SigAction,
+    SigDisposition,
+    SigInfo,
+    SI_USER,
+    SEGV_MAPERR,
+    SEGV_ACCERR,
+    ILL_ILLOPC,
+    BUS_ADRALN,
+    CLD_EXITED,
+    CLD_KILLED,
+    TASK_COMM_LEN,
     AuxType,
     AuxHeader,
-    CloneFlags
+    CloneFlags,
+    PCB_INNER_CACHE,
 };
 pub use manager::{
     enqueue,
     dequeue,
     get_proc_by_pid,
     remove_proc_by_pid,
+    park,
+    resume,
+    check_timers,
+    next_deadline,
+    nr_running,
+    nr_processes,
+    nr_sleeping,
     PROCESS_MANAGER,
 };
 pub use pid::{
     Pid,
     alloc_pid,
+    last_pid,
 };
 pub use kernel_stack::{
     kernel_stack_pos,
@@ -42,9 +69,14 @@ pub use kernel_stack::{
 
 pub use processor::{
     PROCESSOR0,
+    adopt_orphans,
+    report_exit,
 };
 
 pub use proc0::{PROC0, init_proc0};
+pub use wait_queue::{WaitQueue, wake_up};
+pub use oom::{oom_kill, select_victim};
+pub use lock_order::{LockedInner, check_lock};
 // pub use temp_app_loader::init_app_context;
 
 use crate::trap::TrapContext;
@@ -68,6 +100,22 @@ pub fn suspend_switch() {
     PROCESSOR0.suspend_switch();
 }
 
+/// Put current process to sleep and switch
+/// # Description
+/// The process stays off the run queue until woken up, see `WaitQueue`.  
+/// Note that we need to drop locks before calling this method, to avoid potential dead lock on shared resources.
+pub fn sleep_switch() {
+    PROCESSOR0.sleep_switch();
+}
+
+/// Stop current process and switch
+/// # Description
+/// Stop current process on `signal`, it stays off the run queue until a SIGCONT resumes it.  
+/// Note that we need to drop locks before calling this method, to avoid potential dead lock on shared resources.
+pub fn stop_switch(signal: usize) {
+    PROCESSOR0.stop_switch(signal);
+}
+
 /// Exit current process and switch
 /// # Description
 /// Exit current process and switch, can be used to terminate process in kernel.
@@ -100,6 +148,21 @@ pub fn current_utime() -> u64 {
 }
 
 
+/// Mark if the hart is serving a syscall of the current process.
+pub fn set_in_syscall(in_syscall: bool) {
+    PROCESSOR0.set_in_syscall(in_syscall);
+}
+
+/// Check if the hart is serving a syscall of the current process.
+pub fn in_syscall() -> bool {
+    return PROCESSOR0.in_syscall();
+}
+
+/// Get total time the processor had nothing to run, in ticks
+pub fn idle_time() -> u64 {
+    return PROCESSOR0.idle_time();
+}
+
 /// Get current process's TrapContext
 /// # Description
 /// Get current process's TrapContext
@@ -108,11 +171,24 @@ pub fn current_trap_context() -> &'static mut TrapContext {
     return PROCESSOR0.current_trap_context();
 }
 
+/// Get pid of current process, without locking anything
+pub fn current_pid() -> Option<usize> {
+    return PROCESSOR0.current_pid();
+}
+
 /// Get current process
 pub fn current_process() -> Option<Arc<ProcessControlBlock>> {      // TODO: Add multi-core support here in these current_* funcs.
     return PROCESSOR0.current();
 }
 
+/// Check if current process has a signal to be delivered
+/// # Description
+/// Check if current process has a signal to be delivered, blocking syscalls use it to bail out with EINTR.  
+/// Note that this function trys to lock current process, so can cause dead lock if the lock is already held.
+pub fn current_signal_pending() -> bool {
+    return current_process().unwrap().get_inner_locked().has_pending_signal();
+}
+
 /// Get current process's path
 /// # Description
 /// Get current process's path
@@ -0,0 +1,67 @@
+//! Out of memory killer
+//! # Description
+//! When a user allocation can't get a frame and `OOM_KILLER` is set, the process with the largest resident set is killed.
+//! Its frames are released once it gets to run and exits, so the allocating process yields and retries.
+//! With `OOM_KILLER` unset the allocation simply fails.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use crate::config::OOM_KILLER;
+use super::default_handlers::SIGKILL;
+use super::{current_process, ProcessControlBlock, ProcessStatus, PROCESS_MANAGER};
+
+/// Find the process with the most resident pages
+/// # Return
+/// The victim and if it is already dying, None if nobody holds any user memory
+pub fn select_victim() -> Option<(Arc<ProcessControlBlock>, bool)> {
+    let mut candidates: Vec<Arc<ProcessControlBlock>> = Vec::new();
+    // current first, we don't hold its lock while locking the others
+    if let Some(proc) = current_process() {
+        candidates.push(proc);
+    }
+    candidates.extend(PROCESS_MANAGER.lock().idle_procs());
+
+    let mut victim: Option<(Arc<ProcessControlBlock>, bool)> = None;
+    let mut victim_rss = 0;
+    for proc in candidates {
+        // proc0 must not die
+        if proc.pid.0 == 0 {
+            continue;
+        }
+        let inner = proc.get_inner_locked();
+        if inner.status == ProcessStatus::Zombie {
+            continue;
+        }
+        let dying = inner.pending_sig.iter().any(|sig| *sig == SIGKILL);
+        let rss = inner.layout.resident_pages();
+        drop(inner);
+        if rss > victim_rss {
+            victim_rss = rss;
+            victim = Some((proc, dying));
+        }
+    }
+    victim
+}
+
+/// Free up user memory by killing the largest process
+/// # Description
+/// Does nothing unless `OOM_KILLER` is set.
+/// Must be called without holding any PCB lock.
+/// # Return
+/// True if another process is going to release its memory, the caller should yield and then retry the allocation.
+/// False if the allocation should fail, which is also the case if the current process is the one killed.
+pub fn oom_kill() -> bool {
+    if !OOM_KILLER {
+        return false;
+    }
+    let (victim, dying) = match select_victim() {
+        Some(res) => res,
+        None => return false,
+    };
+    let is_current = current_process().map_or(false, |proc| proc.pid.0 == victim.pid.0);
+    if !dying {
+        error!("Out of memory, killing process {} ({} resident pages).", victim.pid.0, victim.get_inner_locked().layout.resident_pages());
+        victim.recv_signal(SIGKILL);
+    }
+    !is_current
+}
@@ -7,23 +7,33 @@ use crate::memory::{
     PhysAddr,
     PhysPageNum,
     VirtAddr,
-    KERNEL_MEM_LAYOUT
+    KERNEL_MEM_LAYOUT,
+    ObjectCache,
+    CacheBox,
 };
 use crate::config::*;
 use crate::trap::{
     TrapContext,
     user_trap,
-    trap_return
+    trap_return,
+    SIG_DFL,
+    SIG_IGN,
 };
 use crate::sbi::get_time;
+use crate::utils::fill_random;
+use super::stats::count_fork;
 use super::{
     Pid,
     KernelStack,
     alloc_pid,
+    resume,
+    ErrNo,
+    LockedInner,
 };
 use _core::clone;
 use _core::mem::size_of;
-use alloc::collections::{BTreeMap, VecDeque};
+use _core::slice::from_raw_parts;
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
 use spin::{
     Mutex,
     MutexGuard
@@ -125,6 +135,10 @@ pub enum ProcessStatus {
     Ready,
     /// A running process.
     Running,
+    /// A process stopped by a signal, waiting for SIGCONT.
+    Stopped,
+    /// A process sleeping on a WaitQueue.
+    Sleeping,
     /// A dead process, but it's resources are not collected yet.
     Zombie
 }
@@ -172,10 +186,13 @@ pub struct ProcessControlBlock {
     /// The kernel stack of the process. PCB holds it so the resource is not dropped.
     pub kernel_stack:   KernelStack,
     pub immu_infos:     ImmuInfos,
-    /// The mutable inner, protected by a Mutex
-    pub inner:          Mutex<ProcessControlBlockInner>,
+    /// The mutable inner, protected by a Mutex, from `PCB_INNER_CACHE`
+    pub inner:          CacheBox<Mutex<ProcessControlBlockInner>>,
 }
 
+/// Where the inners of PCBs are allocated, they are among the most frequently allocated kernel objects
+pub static PCB_INNER_CACHE: ObjectCache<Mutex<ProcessControlBlockInner>> = ObjectCache::new();
+
 #[derive(Clone, Copy)]
 pub struct SigAction {
     pub sighandler: VirtAddr,
@@ -185,6 +202,89 @@ pub struct SigAction {
     pub restorer: VirtAddr // deprecated, go with zero
 }
 
+/// What the kernel does with a signal once it is taken off the pending queue
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SigDisposition {
+    /// Run the handler in `handlers`, a user handler or the default terminate/core dump routine
+    Default,
+    /// Drop the signal
+    Ignore,
+    /// Stop the process until a SIGCONT arrives
+    Stop,
+    /// Nothing left to do, `recv_signal` has already resumed the process
+    Cont,
+}
+
+pub const SI_USER     : i32 = 0;
+pub const SEGV_MAPERR : i32 = 1;
+pub const SEGV_ACCERR : i32 = 2;
+pub const ILL_ILLOPC  : i32 = 1;
+pub const BUS_ADRALN  : i32 = 1;
+pub const CLD_EXITED  : i32 = 1;
+pub const CLD_KILLED  : i32 = 2;
+
+/// Size of the process name, including the terminating 0
+pub const TASK_COMM_LEN: usize = 16;
+
+/// Process name for the program at `path`: its file name, cut to fit in TASK_COMM_LEN with the 0
+pub fn comm_from_path(path: &str) -> [u8; TASK_COMM_LEN] {
+    let name = path.rsplit('/').next().unwrap_or("").as_bytes();
+    let mut comm = [0u8; TASK_COMM_LEN];
+    let len = core::cmp::min(name.len(), TASK_COMM_LEN - 1);
+    comm[..len].copy_from_slice(&name[..len]);
+    comm
+}
+
+/// Details of a signal handed to SA_SIGINFO handlers, laid out as siginfo_t of riscv64 linux
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct SigInfo {
+    pub si_signo: i32,
+    pub si_errno: i32,
+    pub si_code: i32,
+    _pad: i32,
+    /// si_addr of faults, or si_pid and si_uid (low and high half) of kill and SIGCHLD
+    pub si_addr: usize,
+    pub si_status: i32,
+    _pad2: i32,
+    pub si_utime: isize,
+    pub si_stime: isize,
+    _rest: [usize; 10],
+}
+
+impl SigInfo {
+    pub fn new(signal: usize, code: i32) -> Self {
+        Self {
+            si_signo: signal as i32,
+            si_errno: 0,
+            si_code: code,
+            _pad: 0,
+            si_addr: 0,
+            si_status: 0,
+            _pad2: 0,
+            si_utime: 0,
+            si_stime: 0,
+            _rest: [0; 10],
+        }
+    }
+
+    /// A fault on `addr`
+    pub fn fault(signal: usize, code: i32, addr: usize) -> Self {
+        let mut info = Self::new(signal, code);
+        info.si_addr = addr;
+        info
+    }
+
+    /// Child `pid` terminated, `status` is the exit code or the killing signal
+    pub fn child(code: i32, pid: usize, status: i32) -> Self {
+        let mut info = Self::new(SIGCHLD, code);
+        // si_uid is 0, everyone is root
+        info.si_addr = pid;
+        info.si_status = status;
+        info
+    }
+}
+
 /// The mutable part of the process control block
 pub struct ProcessControlBlockInner {
     /// The ProcessContext pointer
@@ -203,6 +303,10 @@ pub struct ProcessControlBlockInner {
     pub last_start: u64,
     /// total process executed in u mode
     pub utime: u64,
+    /// peak resident pages seen by update_rss
+    pub max_rss: usize,
+    /// Real uid, inherited from the parent. Nothing changes it yet, so everything runs as root
+    pub uid: usize,
     /// Parent of the process. proc0 has no parent.
     pub parent: Option<Weak<ProcessControlBlock>>,
     /// childres processes.
@@ -210,15 +314,21 @@ pub struct ProcessControlBlockInner {
     /// Opened file descriptors
     /// TODO: Change to hash_map<Arc<dyn VirtFile + Send + Sync>>>
     pub files: Vec<Option<Arc<dyn File>>>,
+    /// File descriptors to be closed on exec
+    pub cloexec: BTreeSet<usize>,
     /// Current working directory
     pub path: String,
     /// Exit code of the process
     pub exit_code: i32,
     /// pending signals
     pub pending_sig: VecDeque<usize>,
+    /// details of pending signals, for SA_SIGINFO handlers
+    pub pending_info: BTreeMap<usize, SigInfo>,
     /// signal handlers
     /// FIXME: THE SigAction mask HAS NO USE. USE ONLY THE pcb's sig_mask!!!
     pub handlers: BTreeMap<usize, SigAction>,
+    /// How each signal is handled, kept in step with `handlers` by `set_sig_action()`
+    pub sig_dispositions: BTreeMap<usize, SigDisposition>,
     /// signal masks
     pub sig_mask: u64,
     pub signal_trap_contexts: Vec<TrapContext>,
@@ -233,6 +343,34 @@ pub struct ProcessControlBlockInner {
     pub timer_prof_next: u64,
     pub timer_prof_int: u64,
     pub timer_prof_now: u64,
+    /// Timer ticks at which the process sleeping in nanosleep is woken up, 0 if not sleeping on the timer
+    pub wake_at: u64,
+    /// Signal that stopped the process, not yet reported to waitpid
+    pub stop_report: Option<usize>,
+    /// Process was continued, not yet reported to waitpid
+    pub cont_report: bool,
+    /// a0 of the syscall interrupted by a signal, kept to restart it
+    pub syscall_restart: Option<usize>,
+    /// Deadline of an interrupted sleep in timer ticks, a restarted sleep keeps it instead of starting over
+    pub sleep_deadline: Option<u64>,
+    /// signal mask replaced by sigsuspend, restored once the handler returns
+    pub sigsuspend_mask: Option<u64>,
+    /// PTRACE_TRACEME was called, the parent gets to see every signal first
+    pub traced: bool,
+    /// signal the tracer let through with PTRACE_CONT, delivered without stopping again
+    pub ptrace_pass: Option<usize>,
+    /// single step breakpoint planted by PTRACE_SINGLESTEP, and the bytes it replaced
+    pub step_breakpoint: Option<(VirtAddr, [u8; 2])>,
+    /// # of times the FP registers were saved on trap entry, stays 0 for integer only programs
+    pub fp_saves: usize,
+    /// name of the process, set by exec and PR_SET_NAME, 0 terminated
+    pub comm: [u8; TASK_COMM_LEN],
+    /// signal sent to the process when its parent dies, 0 for none
+    pub pdeathsig: usize,
+    /// argv of the last exec, each string 0 terminated, as in /proc/self/cmdline
+    pub cmdline: Vec<u8>,
+    /// envp of the last exec, each string 0 terminated, as in /proc/self/environ
+    pub environ: Vec<u8>,
 }
 
 impl ProcessControlBlockInner {
@@ -251,6 +389,24 @@ impl ProcessControlBlockInner {
         self.layout.print_layout();
     } 
 
+    /// Name of the process, as in /proc/self/comm
+    pub fn comm_name(&self) -> String {
+        let len = self.comm.iter().position(|c| *c == 0).unwrap_or(TASK_COMM_LEN);
+        String::from_utf8_lossy(&self.comm[..len]).to_string()
+    }
+
+    /// Resident pages of the user layout
+    /// # Description
+    /// The layout keeps the count as frames are mapped, unmapped and faulted in, so this is O(1).  
+    /// Called after the layout grows (faults, brk, mmap) to keep the peak for rusage.
+    pub fn update_rss(&mut self) -> usize {
+        let rss = self.layout.resident_pages();
+        if rss > self.max_rss {
+            self.max_rss = rss;
+        }
+        rss
+    }
+
     /// Read trap context from physical memory
     pub fn get_trap_context(&self) -> &'static mut TrapContext {
         unsafe {
@@ -276,73 +432,198 @@ impl ProcessControlBlockInner {
             |i|
                 self.files[*i].is_none()
         );
-        match empty_slot {
+        let fd = match empty_slot {
             Some(fd ) => fd,
             None => {
                 self.files.push(None);
                 self.files.len() - 1
             }
+        };
+        self.cloexec.remove(&fd);
+        fd
+    }
+
+    /// Close all file descriptors marked close-on-exec.
+    pub fn close_on_exec(&mut self) {
+        let cloexec = core::mem::replace(&mut self.cloexec, BTreeSet::new());
+        for fd in cloexec {
+            if let Some(slot) = self.files.get_mut(fd) {
+                slot.take();
+            }
+        }
+        while let Some(None) = self.files.last() {
+            self.files.pop();
+        }
+    }
+
+    /// Check if there is a signal that will be delivered on return to user mode
+    /// # Description
+    /// Ignored signals and SIGCONT, which has done its work once received, are dropped on the way out
+    /// and don't count.
+    pub fn has_pending_signal(&self) -> bool {
+        self.pending_sig.iter().any(|sig| (1u64 << sig) & self.sig_mask & !SIG_UNBLOCKABLE == 0
+            && !matches!(self.sig_disposition(*sig), SigDisposition::Ignore | SigDisposition::Cont))
+    }
+
+    /// Check if a syscall interrupted by `signal` is restarted
+    /// # Description
+    /// A stopping signal runs no handler, so the syscall always carries on after it.
+    /// Otherwise it is restarted only if the handler is installed with SA_RESTART, except for sigsuspend,
+    /// which returns once a handler has run.
+    pub fn restarts_syscall(&self, signal: usize) -> bool {
+        self.sig_disposition(signal) == SigDisposition::Stop || self.sigsuspend_mask.is_none() && self.handlers.get(&signal)
+            .map_or(false, |act| act.flags.contains(SignalFlags::RESTART))
+    }
+
+    /// Settle the syscall interrupted by a signal, if any
+    /// # Description
+    /// With `restart` the trap context is rewound to the ecall with the original a0, so the syscall runs again
+    /// once the handler returns, and a mask installed by sigsuspend is put back for it to install again.
+    /// Otherwise it fails with EINTR and a pending sleep deadline is dropped.
+    pub fn rewind_syscall(&mut self, restart: bool) {
+        if let Some(a0) = self.syscall_restart.take() {
+            if restart {
+                let trap_context = self.get_trap_context();
+                trap_context.regs[10] = a0;
+                trap_context.sepc -= 4;
+                if let Some(mask) = self.sigsuspend_mask.take() {
+                    self.sig_mask = mask;
+                }
+                return;
+            }
         }
+        self.sleep_deadline = None;
+    }
+
+    /// If the process installed a handler of its own for `signal`
+    pub fn catches_signal(&self, signal: usize) -> bool {
+        self.handlers.get(&signal).map_or(false, |act| {
+            // the default handlers live in the trampoline
+            act.flags.contains(SignalFlags::SIGINFO)
+                || (act.sighandler.0 != SIG_DFL && act.sighandler.0 != SIG_IGN && act.sighandler.0 < U_TRAMPOLINE)
+        })
+    }
+
+    /// Put `info` on the user stack for a SA_SIGINFO handler
+    /// # Description
+    /// It goes below the stack pointer, 16 bytes aligned, and the stack pointer is moved under it. Sigreturn restores it.
+    /// # Return
+    /// The address of the siginfo, Err(BadAddress) if there is no room for it on the stack
+    pub fn push_siginfo(&mut self, info: &SigInfo) -> Result<VirtAddr, ErrNo> {
+        let trap_context = self.get_trap_context();
+        let info_va = VirtAddr::from((trap_context.regs[2] - size_of::<SigInfo>()) & !0xf);
+        let bytes = unsafe { from_raw_parts(info as *const SigInfo as *const u8, size_of::<SigInfo>()) };
+        self.layout.copy_to_user(info_va, bytes)?;
+        trap_context.regs[2] = info_va.0;
+        Ok(info_va)
     }
 
     pub fn recv_signal(&mut self, signal: usize) -> Option<()> {
         if signal >= 64 {
             None
-        } else if self.sig_mask.get_bit(signal) {
+        } else if self.sig_mask.get_bit(signal) && SIG_UNBLOCKABLE & (1u64 << signal) == 0 {
             None
         } else {
             self.pending_sig.push_back(signal);
             Some(())
         }
     }
+
+    /// Install `act` for `signal`
+    /// # Description
+    /// Install `act` for `signal`, and set the disposition it implies: SIG_IGN ignores the signal,
+    /// SIG_DFL takes the default action, anything else is a handler to run.
+    /// # Return
+    /// The action previously installed
+    pub fn set_sig_action(&mut self, signal: usize, act: SigAction) -> Option<SigAction> {
+        let disposition = if act.flags.contains(SignalFlags::SIGINFO) {
+            SigDisposition::Default
+        } else if act.sighandler.0 == SIG_IGN {
+            SigDisposition::Ignore
+        } else if act.sighandler.0 == SIG_DFL {
+            default_sig_dispositions().get(&signal).copied().unwrap_or(SigDisposition::Default)
+        } else {
+            SigDisposition::Default
+        };
+        self.sig_dispositions.insert(signal, disposition);
+        self.handlers.insert(signal, act)
+    }
+
+    /// How `signal` is handled, the unblockable signals always take their default action
+    pub fn sig_disposition(&self, signal: usize) -> SigDisposition {
+        if signal == SIGSTOP {
+            SigDisposition::Stop
+        } else if signal == SIGKILL {
+            SigDisposition::Default
+        } else {
+            self.sig_dispositions.get(&signal).copied().unwrap_or(SigDisposition::Default)
+        }
+    }
+}
+
+/// Default dispositions of the signals
+/// # Description
+/// Signals that terminate or dump core run the routine in `default_sig_handlers()`, the rest are handled in kernel.
+pub fn default_sig_dispositions() -> BTreeMap<usize, SigDisposition> {
+    let mut map = BTreeMap::new();
+    for signal in 1..=SIGRTMAX {
+        map.insert(signal, SigDisposition::Default);
+    }
+    for signal in [SIGUSR1, SIGUSR2, SIGCHLD, SIGURG, SIGVTALRM, SIGWINCH, SIGIO, SIGPWR].iter() {
+        map.insert(*signal, SigDisposition::Ignore);
+    }
+    for signal in SIGRTMIN..SIGRTMAX {
+        map.insert(signal, SigDisposition::Ignore);
+    }
+    for signal in [SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU].iter() {
+        map.insert(*signal, SigDisposition::Stop);
+    }
+    map.insert(SIGCONT, SigDisposition::Cont);
+    map
 }
 
 pub fn default_sig_handlers() -> BTreeMap<usize, SigAction> {
     extern "C" {fn strampoline(); fn sutrampoline(); }
     let mut map = BTreeMap::new();
     let terminate_self_va   = VirtAddr::from(def_terminate_self as usize - sutrampoline as usize + U_TRAMPOLINE);
-    let ignore_va           = VirtAddr::from(def_ignore         as usize - sutrampoline as usize + U_TRAMPOLINE);
     let dump_core_va        = VirtAddr::from(def_dump_core      as usize - sutrampoline as usize + U_TRAMPOLINE);
-    let cont_va             = VirtAddr::from(def_cont           as usize - sutrampoline as usize + U_TRAMPOLINE);
-    let stop_va             = VirtAddr::from(def_stop           as usize - sutrampoline as usize + U_TRAMPOLINE);
     let terminate_self_va   = SigAction { sighandler: terminate_self_va, sigaction: 0.into(), mask: 0, flags: SignalFlags::empty(), restorer: 0.into()};
-    let ignore_va           = SigAction { sighandler: ignore_va        , sigaction: 0.into(), mask: 0, flags: SignalFlags::empty(), restorer: 0.into()};
     let dump_core_va        = SigAction { sighandler: dump_core_va     , sigaction: 0.into(), mask: 0, flags: SignalFlags::empty(), restorer: 0.into()};
-    let cont_va             = SigAction { sighandler: cont_va          , sigaction: 0.into(), mask: 0, flags: SignalFlags::empty(), restorer: 0.into()};
-    let stop_va             = SigAction { sighandler: stop_va          , sigaction: 0.into(), mask: 0, flags: SignalFlags::empty(), restorer: 0.into()};
+    // ignored, stopping and continuing signals are handled in kernel, see `default_sig_dispositions()`
+    let in_kernel           = SigAction { sighandler: SIG_DFL.into()   , sigaction: 0.into(), mask: 0, flags: SignalFlags::empty(), restorer: 0.into()};
     map.insert(SIGHUP   , terminate_self_va.clone());
     map.insert(SIGINT   , terminate_self_va.clone());
     map.insert(SIGQUIT  , terminate_self_va.clone());
     map.insert(SIGILL   , terminate_self_va.clone());
-    map.insert(SIGTRAP  , ignore_va        .clone());
+    map.insert(SIGTRAP  , dump_core_va     .clone());
     map.insert(SIGABRT  , dump_core_va     .clone());
     map.insert(SIGBUS   , dump_core_va     .clone());
     map.insert(SIGFPE   , dump_core_va     .clone());
     map.insert(SIGKILL  , terminate_self_va.clone());
-    map.insert(SIGUSR1  , ignore_va        .clone());
+    map.insert(SIGUSR1  , in_kernel        .clone());
     map.insert(SIGSEGV  , dump_core_va     .clone());
-    map.insert(SIGUSR2  , ignore_va        .clone());
+    map.insert(SIGUSR2  , in_kernel        .clone());
     map.insert(SIGPIPE  , terminate_self_va.clone());
     map.insert(SIGALRM  , terminate_self_va.clone());
     map.insert(SIGTERM  , terminate_self_va.clone());
     map.insert(SIGSTKFLT, terminate_self_va.clone());
-    map.insert(SIGCHLD  , ignore_va        .clone());
-    map.insert(SIGCONT  , cont_va          .clone());
-    map.insert(SIGSTOP  , stop_va          .clone());
-    map.insert(SIGTSTP  , stop_va          .clone());
-    map.insert(SIGTTIN  , stop_va          .clone());
-    map.insert(SIGTTOU  , stop_va          .clone());
-    map.insert(SIGURG   , ignore_va        .clone());
+    map.insert(SIGCHLD  , in_kernel        .clone());
+    map.insert(SIGCONT  , in_kernel        .clone());
+    map.insert(SIGSTOP  , in_kernel        .clone());
+    map.insert(SIGTSTP  , in_kernel        .clone());
+    map.insert(SIGTTIN  , in_kernel        .clone());
+    map.insert(SIGTTOU  , in_kernel        .clone());
+    map.insert(SIGURG   , in_kernel        .clone());
     map.insert(SIGXCPU  , terminate_self_va.clone());
     map.insert(SIGXFSZ  , terminate_self_va.clone());
-    map.insert(SIGVTALRM, ignore_va        .clone());
+    map.insert(SIGVTALRM, in_kernel        .clone());
     map.insert(SIGPROF  , terminate_self_va.clone());
-    map.insert(SIGWINCH , ignore_va        .clone());
-    map.insert(SIGIO    , ignore_va        .clone());
-    map.insert(SIGPWR   , ignore_va        .clone());
+    map.insert(SIGWINCH , in_kernel        .clone());
+    map.insert(SIGIO    , in_kernel        .clone());
+    map.insert(SIGPWR   , in_kernel        .clone());
     map.insert(SIGSYS   , terminate_self_va.clone());
     for i in SIGRTMIN..SIGRTMAX {
-        map.insert(i, ignore_va.clone());
+        map.insert(i, in_kernel.clone());
     }
     map
 }
@@ -361,7 +642,7 @@ impl ProcessControlBlock {
     /// # Return
     /// Return the new process control block
     pub fn new(elf_data: &[u8], path: String) -> Self {
-        let (layout, data_top, mut user_stack_top, entry, _auxv) = MemLayout::new_elf(elf_data);
+        let (layout, data_top, mut user_stack_top, entry, _auxv) = MemLayout::new_elf(elf_data, None).expect("Invlid elf format.");
         let trap_context_ppn = layout.translate(VirtAddr::from(TRAP_CONTEXT).into()).unwrap().ppn();
         let pid = alloc_pid();
         let tgid = pid.0;
@@ -384,7 +665,7 @@ impl ProcessControlBlock {
                 exec_path: path.clone(),
             },
             kernel_stack,
-            inner: Mutex::new(ProcessControlBlockInner {
+            inner: PCB_INNER_CACHE.alloc(Mutex::new(ProcessControlBlockInner {
                 context_ptr,
                 status,
                 layout,
@@ -393,6 +674,8 @@ impl ProcessControlBlock {
                 up_since: get_time(),
                 last_start: 0,
                 utime: 0,
+                max_rss: 0,
+                uid: 0,
                 parent: None,
                 children: Vec::new(),
                 files: vec![
@@ -400,10 +683,13 @@ impl ProcessControlBlock {
                     Some(stdout),
                     Some(stderr)
                 ],
+                cloexec: BTreeSet::new(),
                 path: path[..path.rfind('/').unwrap() + 1].to_string(),
                 exit_code: 0,
                 pending_sig: VecDeque::new(),
+                pending_info: BTreeMap::new(),
                 handlers: default_sig_handlers(),
+                sig_dispositions: default_sig_dispositions(),
                 sig_mask: 0,
                 last_signal: None,
                 dead_children_stime: 0,
@@ -416,8 +702,22 @@ impl ProcessControlBlock {
                 timer_prof_int: 0,
                 timer_prof_next: 0,
                 timer_prof_now: 0,
+                wake_at: 0,
+                stop_report: None,
+                cont_report: false,
+                syscall_restart: None,
+                sleep_deadline: None,
+                sigsuspend_mask: None,
+                traced: false,
+                ptrace_pass: None,
+                step_breakpoint: None,
+                fp_saves: 0,
+                comm: comm_from_path(&path),
+                pdeathsig: 0,
+                cmdline: Vec::new(),
+                environ: Vec::new(),
                 signal_trap_contexts: Vec::new()
-            }),
+            })),
         };
         let trap_context = pcb.get_inner_locked().get_trap_context();
         *trap_context = TrapContext::init(
@@ -438,11 +738,12 @@ impl ProcessControlBlock {
     /// # Description
     /// Fork a process from original process, almost identical except for physical memory mapping.
     /// # Return
-    /// Return the new process control block
-    pub fn fork(self: &Arc<ProcessControlBlock>, clone_flags: super::CloneFlags) -> Arc<ProcessControlBlock> {
+    /// Return the new process control block, Err(OutOfMemory) if the layout can't be copied
+    pub fn fork(self: &Arc<ProcessControlBlock>, clone_flags: super::CloneFlags) -> Result<Arc<ProcessControlBlock>, ErrNo> {
+        count_fork();
         let mut parent_arcpcb = self.get_inner_locked();
         // let layout = MemLayout::fork_from_user(&parent_arcpcb.layout);
-        let layout = MemLayout::clone_from_user(&parent_arcpcb.layout, clone_flags);
+        let layout = MemLayout::clone_from_user(&parent_arcpcb.layout, clone_flags)?;
         let trap_context_ppn = layout.translate(VirtAddr(TRAP_CONTEXT).into()).unwrap().ppn();
         let pid = alloc_pid();
         let kernel_stack = KernelStack::new(&pid);
@@ -460,7 +761,7 @@ impl ProcessControlBlock {
             tgid,
             immu_infos,
             kernel_stack,
-            inner: Mutex::new(ProcessControlBlockInner {
+            inner: PCB_INNER_CACHE.alloc(Mutex::new(ProcessControlBlockInner {
                 context_ptr,
                 status,
                 layout,
@@ -469,13 +770,18 @@ impl ProcessControlBlock {
                 up_since: get_time(),
                 last_start: 0,
                 utime: parent_arcpcb.utime,
+                max_rss: 0,
+                uid: parent_arcpcb.uid,
                 parent: Some(Arc::downgrade(self)),
                 children: Vec::new(),
                 files: parent_arcpcb.files.clone(),
+                cloexec: parent_arcpcb.cloexec.clone(),
                 path: parent_arcpcb.path.clone(),
                 exit_code: 0,
                 pending_sig: parent_arcpcb.pending_sig.clone(),
+                pending_info: parent_arcpcb.pending_info.clone(),
                 handlers: parent_arcpcb.handlers.clone(),
+                sig_dispositions: parent_arcpcb.sig_dispositions.clone(),
                 sig_mask: 0,
                 last_signal: None,
                 dead_children_stime: 0,
@@ -488,14 +794,29 @@ impl ProcessControlBlock {
                 timer_prof_int: parent_arcpcb.timer_prof_int,
                 timer_prof_next: parent_arcpcb.timer_prof_next,
                 timer_prof_now: parent_arcpcb.timer_prof_now,
+                wake_at: 0,
+                stop_report: None,
+                cont_report: false,
+                syscall_restart: None,
+                sleep_deadline: None,
+                sigsuspend_mask: None,
+                traced: false,
+                ptrace_pass: None,
+                step_breakpoint: None,
+                fp_saves: 0,
+                comm: parent_arcpcb.comm,
+                // not inherited, the child has a parent of its own
+                pdeathsig: 0,
+                cmdline: parent_arcpcb.cmdline.clone(),
+                environ: parent_arcpcb.environ.clone(),
                 signal_trap_contexts: Vec::new()
-            }),
+            })),
         });
 
         parent_arcpcb.children.push(pcb.clone());
         let mut trap_context: &mut TrapContext = PhysAddr::from(pcb.get_inner_locked().trap_context_ppn).get_mut();
         trap_context.kernel_sp = kernel_stack_top.0;
-        return pcb;
+        return Ok(pcb);
     }
 
     //               |========== HI ==========|
@@ -549,11 +870,19 @@ impl ProcessControlBlock {
     //               |========== LO ==========|
     /// Execute certain elf file in current process
     /// # Description
-    /// Execute certain elf file in current process. This will reset the whole memory layout and regs.
+    /// Execute certain elf file in current process. This will reset the whole memory layout and regs.  
+    /// `interp_data` is the program interpreter requested by the elf, if any.
     /// # Return
-    /// Return the argc, for this will subtitude the syscall return value.
-    pub fn exec(&self, elf_data: &[u8], path: String, argv: Vec<Vec<u8>>, envp: Vec<Vec<u8>>) -> isize {
-        let (layout, data_top, mut user_stack_top, entry, mut auxv) = MemLayout::new_elf(elf_data);
+    /// Return the argc, for this will subtitude the syscall return value.  
+    /// Err(ExecFormatError) if the elf or the interpreter is invalid, the process is left untouched then.
+    pub fn exec(&self, elf_data: &[u8], interp_data: Option<&[u8]>, path: String, mut argv: Vec<Vec<u8>>, mut envp: Vec<Vec<u8>>) -> Result<isize, ErrNo> {
+        let (layout, data_top, mut user_stack_top, entry, mut auxv) = MemLayout::new_elf(elf_data, interp_data)?;
+        // every string handed to the user has to be NUL terminated
+        for s in argv.iter_mut().chain(envp.iter_mut()) {
+            if s.last() != Some(&0) {
+                s.push(0);
+            }
+        }
         let trap_context_ppn = layout.translate(VirtAddr::from(TRAP_CONTEXT).into()).unwrap().ppn();
 
         // // user_stack_top -= (argv.len() + 1) * core::mem::size_of::<usize>();
@@ -653,22 +982,26 @@ impl ProcessControlBlock {
 
         //  ================================= rand bytes =================================
         user_stack_top -= 16;
+        let mut rand_bytes = [0u8; 16];
+        fill_random(&mut rand_bytes);
         let mut ptr = user_stack_top;
-        for i in 0u8..0xfu8 {
-            layout.write_user_data(ptr.into(), &i);
+        for b in rand_bytes.iter() {
+            layout.write_user_data(ptr.into(), b);
             ptr += 1;
         }
         let random_ptr = user_stack_top;
 
         // ================================= padding =================================
-        let padded_user_stack_top = user_stack_top - (16 + user_stack_top % 16);
-        for i in user_stack_top..padded_user_stack_top {
-            layout.write_user_data(i.into(), &(0 as u8));
+        // sp must be 16 bytes aligned once argc, argv, envp and auxv are all pushed
+        user_stack_top -= user_stack_top % 16;
+        let ptrs_cnt = 1 + argv_ptrs.len() + envp_ptrs.len();
+        if ptrs_cnt % 2 == 1 {
+            user_stack_top -= size_of::<usize>();
+            layout.write_user_data(user_stack_top.into(), &0usize);
         }
-        user_stack_top = padded_user_stack_top;
 
         // ================================= auxv content =================================
-        auxv.push(AuxHeader{aux_type: AuxType::RANDOM,  value: user_stack_top});
+        auxv.push(AuxHeader{aux_type: AuxType::RANDOM,  value: random_ptr});
         auxv.push(AuxHeader{aux_type: AuxType::EXECFN,  value: name_ptr});
         auxv.push(AuxHeader{aux_type: AuxType::NULL,    value: 0});
         user_stack_top -= auxv.len() * size_of::<AuxHeader>();
@@ -702,7 +1035,7 @@ impl ProcessControlBlock {
         user_stack_top -= size_of::<usize>();
         layout.write_user_data(user_stack_top.into(), &(argv.len()));
 
-        assert!(user_stack_top % size_of::<usize>() == 0, "SP not aligned!");
+        assert!(user_stack_top % 16 == 0, "SP not aligned!");
 
         verbose!("argv.len(): {:x}", argv.len());
         verbose!("argv_base : {:x}", argv_base );
@@ -715,6 +1048,12 @@ impl ProcessControlBlock {
 
         let mut locked_inner = self.get_inner_locked();
         locked_inner.layout = layout;     // original layout dropped, thus freed.
+        // went away with the old program
+        locked_inner.step_breakpoint = None;
+        locked_inner.fp_saves = 0;
+        locked_inner.comm = comm_from_path(&path);
+        locked_inner.cmdline = argv.concat();
+        locked_inner.environ = envp.concat();
         locked_inner.trap_context_ppn = trap_context_ppn;
         locked_inner.utime = 0;
         locked_inner.size = data_top;
@@ -722,8 +1061,11 @@ impl ProcessControlBlock {
         locked_inner.up_since = get_time();
         locked_inner.path = path[..path.rfind('/').unwrap() + 1].to_string();
         locked_inner.pending_sig = VecDeque::new();
+        locked_inner.pending_info = BTreeMap::new();
         locked_inner.handlers = default_sig_handlers();
+        locked_inner.sig_dispositions = default_sig_dispositions();
         locked_inner.sig_mask = 0;
+        locked_inner.close_on_exec();
         let mut trap_context = TrapContext::init(
             entry, 
             user_stack_top, 
@@ -737,7 +1079,7 @@ impl ProcessControlBlock {
         // trap_context.regs[13] = auxv_base;
 
         *locked_inner.get_trap_context() = trap_context;
-        return (argv_ptrs.len() - 1) as isize;
+        return Ok((argv_ptrs.len() - 1) as isize);
     }
 
     /// Get inner mutable part of the process control block.
@@ -756,6 +1098,73 @@ impl ProcessControlBlock {
         return self.inner.lock();
     }
 
+    /// Fire expired interval timers of the process.
+    /// # Description
+    /// ITIMER_REAL runs on wall time, ITIMER_VIRTUAL on user time and ITIMER_PROF on process time.  
+    /// Periodic timers are reloaded with their interval, one-shot ones are disarmed.  
+    /// A process sleeping on the timer is woken up once its `wake_at` passes.
+    pub fn check_timers(&self) {
+        let mut arcpcb = self.get_inner_locked();
+        let mut signals = Vec::new();
+        let now = get_time();
+        if arcpcb.timer_real_next != 0 && arcpcb.timer_real_next <= now {
+            if arcpcb.timer_real_int != 0 {
+                arcpcb.timer_real_next += CLOCK_FREQ / 100000 * arcpcb.timer_real_int / 10;
+            } else {
+                arcpcb.timer_real_next = 0;
+            }
+            signals.push(SIGALRM);
+        }
+        if arcpcb.timer_virt_next != 0 && arcpcb.timer_virt_next <= arcpcb.utime {
+            if arcpcb.timer_virt_int != 0 {
+                arcpcb.timer_virt_next += CLOCK_FREQ / 100000 * arcpcb.timer_virt_int / 10;
+            } else {
+                arcpcb.timer_virt_next = 0;
+            }
+            signals.push(SIGVTALRM);
+        }
+        if arcpcb.timer_prof_next != 0 && arcpcb.timer_prof_next <= arcpcb.timer_prof_now {
+            if arcpcb.timer_prof_int != 0 {
+                arcpcb.timer_prof_next += CLOCK_FREQ / 100000 * arcpcb.timer_prof_int / 10;
+            } else {
+                arcpcb.timer_prof_next = 0;
+            }
+            signals.push(SIGPROF);
+        }
+        // the timed sleep is over
+        let wake = arcpcb.wake_at != 0 && arcpcb.wake_at <= now && arcpcb.status == ProcessStatus::Sleeping;
+        if wake {
+            arcpcb.wake_at = 0;
+            arcpcb.status = ProcessStatus::Ready;
+        }
+        drop(arcpcb);
+        if wake {
+            resume(self.pid.0);
+        }
+        // through the outer recv_signal, so a sleeping process gets woken up
+        for signal in signals {
+            self.recv_signal(signal);
+        }
+    }
+
+    /// The earliest wall time deadline of the process
+    /// # Description
+    /// Either ITIMER_REAL or the end of a timed sleep. ITIMER_VIRTUAL and ITIMER_PROF only advance while the
+    /// process runs, so they never need to wake an idle hart.
+    pub fn next_deadline(&self) -> Option<u64> {
+        let arcpcb = self.get_inner_locked();
+        [arcpcb.timer_real_next, arcpcb.wake_at].iter().copied().filter(|deadline| *deadline != 0).min()
+    }
+
+    /// Lock the inner of the process, checking lock order.
+    /// # Description
+    /// Same as `get_inner_locked`, but debug and test builds panic on locking the same PCB twice,
+    /// or locking current process's PCB while other PCBs are held.
+    pub fn lock_inner(&self) -> LockedInner {
+        super::lock_order::acquire_check(self.pid.0);
+        LockedInner::new(self.pid.0, self.inner.lock())
+    }
+
     /// Get the trap context of current process.
     /// # Return
     /// A mutable reference to the trap context
@@ -783,9 +1192,41 @@ impl ProcessControlBlock {
         locked_inner.alloc_fd()
     }
 
+    /// Send `signal` along with its details for SA_SIGINFO handlers
+    pub fn recv_signal_info(&self, signal: usize, info: SigInfo) -> Option<()> {
+        self.get_inner_locked().pending_info.insert(signal, info);
+        let ret = self.recv_signal(signal);
+        if ret.is_none() {
+            self.get_inner_locked().pending_info.remove(&signal);
+        }
+        ret
+    }
+
     pub fn recv_signal(&self, signal: usize) -> Option<()> {
         info!("process {} received signal {}, pending handle", self.pid.0, signal);
         let mut locked_inner = self.get_inner_locked();
-        locked_inner.recv_signal(signal)
+        if signal < 64 && SIG_STOPPING & (1u64 << signal) != 0 {
+            // a stop cancels any continue that hasn't been handled yet
+            locked_inner.pending_sig.retain(|sig| *sig != SIGCONT);
+        } else if signal == SIGCONT {
+            locked_inner.pending_sig.retain(|sig| SIG_STOPPING & (1u64 << *sig) == 0);
+        }
+        let ret = locked_inner.recv_signal(signal);
+        // SIGCONT resumes a stopped process even if it is blocked, SIGKILL has to wake it to kill it.
+        if (signal == SIGCONT || signal == SIGKILL) && locked_inner.status == ProcessStatus::Stopped {
+            locked_inner.status = ProcessStatus::Ready;
+            locked_inner.stop_report = None;
+            locked_inner.cont_report = signal == SIGCONT;
+            drop(locked_inner);
+            resume(self.pid.0);
+            return Some(());
+        }
+        // interrupt the sleep, so the process gets to handle the signal
+        if ret.is_some() && locked_inner.status == ProcessStatus::Sleeping {
+            locked_inner.status = ProcessStatus::Ready;
+            drop(locked_inner);
+            resume(self.pid.0);
+        }
+        ret
     }
 }
@@ -17,6 +17,8 @@ impl Drop for Pid {
 struct PidAllocator {
     nxt_free: usize,
     recycled: Vec<usize>,
+    /// the most recently allocated pid
+    last: usize,
 }
 
 impl PidAllocator {
@@ -25,15 +27,18 @@ impl PidAllocator {
         PidAllocator {
             nxt_free: 0,
             recycled: Vec::new(),
+            last: 0,
         }
     }
 
     /// Alloc a new pid.
     pub fn alloc(&mut self) -> Pid {
         if let Some(res) = self.recycled.pop() {
+            self.last = res;
             return Pid(res);
         } else {
             self.nxt_free += 1;
+            self.last = self.nxt_free - 1;
             return Pid(self.nxt_free - 1);
         }
     }
@@ -56,4 +61,9 @@ lazy_static! {
 /// Alloc a pid. Note that you should hold the Pid object, or the pid will be auto recycled.
 pub fn alloc_pid() -> Pid {
     return PID_ALLOCATOR.lock().alloc();
-}
\ No newline at end of file
+}
+
+/// The most recently allocated pid.
+pub fn last_pid() -> usize {
+    return PID_ALLOCATOR.lock().last;
+}
@@ -1,20 +1,37 @@
 //! Abstract of the Processor, for future multi-core support.
 // use super::ProcessContext;
 use super::ProcessControlBlock;
+use super::ProcessControlBlockInner;
 use super::ProcessStatus;
 use crate::trap::TrapContext;
 
 // use crate::config::*;
 use core::cell::RefCell;
-use alloc::sync::Weak;
+use core::sync::atomic::{AtomicBool, Ordering};
 use lazy_static::*;
-use crate::sbi::get_time;
+use crate::sbi::{get_time, set_timer, reset_timer_trigger, TICKS_PER_SECOND};
+use crate::config::CLOCK_FREQ;
+use crate::fs::next_bdflush;
+use super::loadavg::sample_load;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use super::{
     dequeue,
     enqueue,
-    PROC0
+    park,
+    resume,
+    check_timers,
+    next_deadline,
+    PROC0,
+    PROCESS_MANAGER,
 };
+use super::default_handlers::SIGCHLD;
+use super::{SigInfo, CLD_EXITED, CLD_KILLED};
+use crate::config::{PAGE_SIZE, U_TRAMPOLINE};
+use super::stats::count_switch;
+use super::ptrace::remove_step_breakpoint;
+use crate::memory::take_heap_oom_victim;
+use crate::fs::release_process_record_locks;
 
 global_asm!(include_str!("switch.asm"));
 
@@ -26,10 +43,79 @@ extern "C" {
     );
 }
 
+/// Hand the children of the dying process `pid` over to PROC0
+/// # Description
+/// A tracee loses its tracer with it, so it is detached with its single step breakpoint taken out, and resumed if it is stopped waiting for the tracer.
+/// Children that set a signal with PR_SET_PDEATHSIG get it, as long as `pid` is still their parent.
+/// PROC0 never exits, so the signal comes only once.
+pub fn adopt_orphans(pid: usize, inner: &mut ProcessControlBlockInner) {
+    let mut stopped_tracees = Vec::new();
+    let mut death_signals = Vec::new();
+    {
+        let mut initproc_inner = PROC0.get_inner_locked();
+        for child in inner.children.drain(..) {
+            let mut child_inner = child.get_inner_locked();
+            let real_parent = child_inner.parent.as_ref()
+                .and_then(|parent| parent.upgrade())
+                .map_or(true, |parent| parent.pid.0 == pid);
+            if real_parent && child_inner.pdeathsig != 0 {
+                death_signals.push((child.clone(), child_inner.pdeathsig));
+            }
+            child_inner.parent = Some(Arc::downgrade(&PROC0));
+            if child_inner.traced {
+                child_inner.traced = false;
+                child_inner.ptrace_pass = None;
+                remove_step_breakpoint(&mut child_inner);
+                if child_inner.status == ProcessStatus::Stopped {
+                    child_inner.status = ProcessStatus::Ready;
+                    child_inner.stop_report = None;
+                    stopped_tracees.push(child.pid.0);
+                }
+            }
+            drop(child_inner);
+            initproc_inner.children.push(child);
+        }
+    }
+    // with the child locks released, the run queue is locked before them elsewhere
+    for pid in stopped_tracees {
+        resume(pid);
+    }
+    for (child, signal) in death_signals {
+        child.recv_signal(signal);
+    }
+}
+
+/// Let the parent of `process` know that it exits with `exit_code`
+/// # Description
+/// The parent takes over its times. A parent with a SIGCHLD handler also gets SIGCHLD, with the pid and the exit code,
+/// or the killing signal if the exit comes from a default handler in the trampoline.
+pub fn report_exit(process: &ProcessControlBlock, inner: &ProcessControlBlockInner, exit_code: i32) {
+    if let Some(parent_proc) = inner.parent.as_ref().and_then(|parent| parent.upgrade()) {
+        let mut parent_locked_inner = parent_proc.get_inner_locked();
+        parent_locked_inner.dead_children_stime += get_time() - inner.up_since;
+        parent_locked_inner.dead_children_utime += get_time() - inner.utime;
+        // a pending SIGCHLD fails waitpid, so only a parent with a handler hears about it
+        let notify = parent_locked_inner.catches_signal(SIGCHLD);
+        drop(parent_locked_inner);
+        if notify {
+            let sepc = inner.get_trap_context().sepc;
+            let info = match inner.last_signal {
+                Some(signal) if sepc >= U_TRAMPOLINE && sepc < U_TRAMPOLINE + PAGE_SIZE => {
+                    SigInfo::child(CLD_KILLED, process.get_pid(), signal as i32)
+                },
+                _ => SigInfo::child(CLD_EXITED, process.get_pid(), exit_code),
+            };
+            parent_proc.recv_signal_info(SIGCHLD, info);
+        }
+    }
+}
+
 /// Processor struct, Abstract representation of a Processor
 pub struct Processor {
     /// Mutable member of the processor.
     inner: RefCell<ProcessorInner>,
+    /// If the hart is serving a syscall of the current process
+    in_syscall: AtomicBool,
 }
 
 
@@ -39,6 +125,10 @@ struct ProcessorInner {
     current: Option<Arc<ProcessControlBlock>>,
     /// Idle ProcessContext work flow context pointer, used to determin next process.
     idle_context_ptr: usize,
+    /// Total time with nothing to run, in ticks
+    idle_time: u64,
+    /// Since when there has been nothing to run
+    idle_since: Option<u64>,
 }
 
 unsafe impl Sync for Processor {}
@@ -56,8 +146,11 @@ impl Processor {
         Self {
             inner: RefCell::new(ProcessorInner {
                 current: None,
-                idle_context_ptr: 0
-            })
+                idle_context_ptr: 0,
+                idle_time: 0,
+                idle_since: None,
+            }),
+            in_syscall: AtomicBool::new(false),
         }
     }
 
@@ -66,6 +159,14 @@ impl Processor {
         return self.inner.borrow_mut().current.take();
     }
 
+    /// Replace the current process, returning the previous one
+    /// # Description
+    /// Lets the self tests run syscalls on behalf of a process without switching to it.
+    #[cfg(feature = "kernel_tests")]
+    pub fn set_current(&self, current: Option<Arc<ProcessControlBlock>>) -> Option<Arc<ProcessControlBlock>> {
+        return core::mem::replace(&mut self.inner.borrow_mut().current, current);
+    }
+
     /// get a reference of the current process's pcb.
     pub fn current(&self) -> Option<Arc<ProcessControlBlock>> {
         return self.inner.borrow().current.as_ref().map(
@@ -75,6 +176,24 @@ impl Processor {
         );
     }
 
+    /// Get the pid of the current process
+    /// # Description
+    /// Safe to call from the allocator, None if the processor is being updated.
+    pub fn current_pid(&self) -> Option<usize> {
+        let inner = self.inner.try_borrow().ok()?;
+        inner.current.as_ref().map(|process| process.pid.0)
+    }
+
+    /// Mark if the hart is serving a syscall of the current process.
+    pub fn set_in_syscall(&self, in_syscall: bool) {
+        self.in_syscall.store(in_syscall, Ordering::Relaxed);
+    }
+
+    /// Check if the hart is serving a syscall of the current process.
+    pub fn in_syscall(&self) -> bool {
+        self.in_syscall.load(Ordering::Relaxed)
+    }
+
     /// Get the pointer pointing at the context ptr.
     /// By manipulating the contextn ptr, we can switch work flow.
     pub fn get_idle_context_ptr2(&self) -> *const usize {
@@ -117,10 +236,57 @@ impl Processor {
         arcpcb.timer_prof_now += get_time() - arcpcb.timer_real_start;
         drop(arcpcb);
         enqueue(process);
+        // other processes run in between, each with its own in_syscall
+        let in_syscall = self.in_syscall();
+        let idle_context_ptr2 = self.get_idle_context_ptr2();
+        unsafe {
+            __switch(context_ptr2, idle_context_ptr2);
+        }
+        self.set_in_syscall(in_syscall);
+    }
+
+    /// Put current process to sleep and switch.
+    /// # Description
+    /// Park current process outside the run queue, it won't run again until woken up.  
+    /// Note that we need to drop locks before calling this method, to avoid potential dead lock on shared resources.
+    pub fn sleep_switch(&self) {
+        let process = self.take_current().unwrap();
+        let mut arcpcb = process.get_inner_locked();
+        let context_ptr2 = &(arcpcb.context_ptr) as *const usize;
+        arcpcb.status = ProcessStatus::Sleeping;
+        arcpcb.timer_prof_now += get_time() - arcpcb.timer_real_start;
+        drop(arcpcb);
+        park(process);
+        // other processes run in between, each with its own in_syscall
+        let in_syscall = self.in_syscall();
+        let idle_context_ptr2 = self.get_idle_context_ptr2();
+        unsafe {
+            __switch(context_ptr2, idle_context_ptr2);
+        }
+        self.set_in_syscall(in_syscall);
+    }
+
+    /// Stop current process and switch.
+    /// # Description
+    /// Park current process outside the run queue, it won't run again until a SIGCONT resumes it.  
+    /// Note that we need to drop locks before calling this method, to avoid potential dead lock on shared resources.
+    pub fn stop_switch(&self, signal: usize) {
+        let process = self.take_current().unwrap();
+        let mut arcpcb = process.get_inner_locked();
+        let context_ptr2 = &(arcpcb.context_ptr) as *const usize;
+        arcpcb.status = ProcessStatus::Stopped;
+        arcpcb.stop_report = Some(signal);
+        arcpcb.cont_report = false;
+        arcpcb.timer_prof_now += get_time() - arcpcb.timer_real_start;
+        drop(arcpcb);
+        park(process);
+        // other processes run in between, each with its own in_syscall
+        let in_syscall = self.in_syscall();
         let idle_context_ptr2 = self.get_idle_context_ptr2();
         unsafe {
             __switch(context_ptr2, idle_context_ptr2);
         }
+        self.set_in_syscall(in_syscall);
     }
 
     /// Exit current process and switch
@@ -131,27 +297,25 @@ impl Processor {
         let mut arcpcb = process.get_inner_locked();
         arcpcb.status = ProcessStatus::Zombie;
         arcpcb.exit_code = exit_code;
+        // dying anyway, don't leave the mark for a later process with the same pid
+        take_heap_oom_victim(process.pid.0);
             
-        {
-            let mut initproc_inner = PROC0.get_inner_locked();
-            for child in arcpcb.children.iter() {
-                child.get_inner_locked().parent = Some(Arc::downgrade(&PROC0));
-                initproc_inner.children.push(child.clone());
-            }
-        }
+        adopt_orphans(process.pid.0, &mut arcpcb);
 
-        {
-            if let Some(parent_proc) = Weak::upgrade(&arcpcb.parent.clone().unwrap()) {
-                let mut parent_locked_inner = parent_proc.get_inner_locked();
-                parent_locked_inner.dead_children_stime += get_time() - arcpcb.up_since;
-                parent_locked_inner.dead_children_utime += get_time() - arcpcb.utime;
-            }
-        }
+        report_exit(&process, &arcpcb, exit_code);
         
-        arcpcb.children.clear();
         arcpcb.layout.drop_all();
         arcpcb.timer_prof_now += get_time() - arcpcb.timer_real_start;
+        // close the files now rather than at reaping, dropping them releases their flocks
+        let files = core::mem::take(&mut arcpcb.files);
+        arcpcb.cloexec.clear();
         drop(arcpcb);
+        drop(files);
+        // record locks belong to the whole thread group
+        let group_alive = PROCESS_MANAGER.lock().idle_procs().iter().any(|proc| proc.tgid == process.tgid);
+        if !group_alive {
+            release_process_record_locks(process.get_pid());
+        }
         drop(process);
         let _unused: usize = 0;
         let idle_context_ptr2 = self.get_idle_context_ptr2();
@@ -167,47 +331,82 @@ impl Processor {
         loop {
             if let Some(process) = dequeue() {
                 let idle_context_ptr2 = self.get_idle_context_ptr2();
-                let mut arcpcb = process.get_inner_locked();
-                let next_context_ptr2 = &(arcpcb.context_ptr) as *const usize;
-                arcpcb.status = ProcessStatus::Running;
-                let now = get_time();
-                if arcpcb.timer_real_next != 0 && arcpcb.timer_real_next < now {
-                    if arcpcb.timer_real_int != 0 {
-                        arcpcb.timer_real_next += crate::config::CLOCK_FREQ / 100000 * arcpcb.timer_real_int / 10;
-                    } else {
-                        arcpcb.timer_real_next = 0;
-                    }
-                    info!("sending SIGALRM to proc {}", process.pid.0);
-                    arcpcb.recv_signal(super::default_handlers::SIGALRM);
-                }
-                if arcpcb.timer_virt_next != 0 && arcpcb.timer_virt_next < arcpcb.utime {
-                    if arcpcb.timer_virt_int != 0 {
-                        arcpcb.timer_virt_next += crate::config::CLOCK_FREQ / 100000 * arcpcb.timer_virt_int / 10;
-                    } else {
-                        arcpcb.timer_virt_next = 0;
-                    }
-                    arcpcb.recv_signal(super::default_handlers::SIGVTALRM);
-                }
-                if arcpcb.timer_prof_next != 0 && arcpcb.timer_prof_next < arcpcb.timer_prof_now {
-                    if arcpcb.timer_prof_int != 0 {
-                        arcpcb.timer_prof_next += crate::config::CLOCK_FREQ / 100000 * arcpcb.timer_prof_int / 10;
-                    } else {
-                        arcpcb.timer_prof_next = 0;
-                    }
-                    arcpcb.recv_signal(super::default_handlers::SIGPROF);
-                }
-                arcpcb.timer_real_start = get_time();
-                drop(arcpcb);
-                self.inner.borrow_mut().current = Some(process);
+                let next_context_ptr2 = self.schedule(process);
                 unsafe {
                     __switch(idle_context_ptr2, next_context_ptr2);
                 }
             } else {
-                warning!("No process to run! Check if the proc0 is dead?");
+                self.idle();
             }
         }
     }
 
+    /// Make `process` the current process.
+    /// # Description
+    /// Ends the idle period and counts the context switch.
+    /// # Return
+    /// Return the pointer to the context pointer of the process, to switch to
+    pub fn schedule(&self, process: Arc<ProcessControlBlock>) -> *const usize {
+        self.end_idle();
+        let mut arcpcb = process.get_inner_locked();
+        let next_context_ptr2 = &(arcpcb.context_ptr) as *const usize;
+        arcpcb.status = ProcessStatus::Running;
+        arcpcb.timer_real_start = get_time();
+        drop(arcpcb);
+        self.inner.borrow_mut().current = Some(process);
+        // a process switched out in a syscall sets it back by itself
+        self.set_in_syscall(false);
+        count_switch();
+        next_context_ptr2
+    }
+
+    /// Wait for something to run, counting the time as idle.
+    /// # Description
+    /// Called when the run queue is empty, the idle period lasts until `end_idle`.
+    pub fn idle(&self) {
+        {
+            let mut inner = self.inner.borrow_mut();
+            if inner.idle_since.is_none() {
+                inner.idle_since = Some(get_time());
+            }
+        }
+        self.idle_wait();
+    }
+
+    /// End the idle period, if any, and add it to the idle time.
+    pub fn end_idle(&self) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(idle_since) = inner.idle_since.take() {
+            inner.idle_time += get_time() - idle_since;
+        }
+    }
+
+    /// Wait for something to run.
+    /// # Description
+    /// Fire the timers that are due first, they may make a sleeping process ready. If nothing is, sleep the
+    /// hart with `wfi` until the earliest deadline, or a tick if there is none, but no later than the next
+    /// background flush.
+    fn idle_wait(&self) {
+        check_timers();
+        sample_load();
+        if !PROCESS_MANAGER.lock().processes.is_empty() {
+            return;
+        }
+        let deadline = next_deadline().unwrap_or(get_time() + CLOCK_FREQ / TICKS_PER_SECOND).min(next_bdflush());
+        set_timer(deadline);
+        unsafe {
+            asm!("wfi");
+        }
+        // back to time slices for whoever runs next
+        reset_timer_trigger();
+    }
+
+    /// Get total time the processor had nothing to run, in ticks
+    pub fn idle_time(&self) -> u64 {
+        let inner = self.inner.borrow();
+        inner.idle_time + inner.idle_since.map_or(0, |idle_since| get_time() - idle_since)
+    }
+
     /// Get current process's execution time
     pub fn current_up_since(&self) -> u64 {
         let inner = self.inner.borrow();
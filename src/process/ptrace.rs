@@ -0,0 +1,129 @@
+//! Single stepping for ptrace
+//! # Description
+//! There is no single step facility outside of debug mode, so the step is done with a software breakpoint.
+//! The instruction at the pc of the stopped tracee is decoded with its registers to find the one executed after it,
+//! and a `c.ebreak` is planted there. The breakpoint trap puts the original bytes back and stops the tracee with SIGTRAP.
+
+use crate::memory::{VirtAddr, flush_icache};
+use crate::trap::TrapContext;
+use super::{ErrNo, ProcessControlBlockInner};
+
+/// `c.ebreak`, only 2 bytes so that it fits over any instruction
+const C_EBREAK: u16 = 0x9002;
+
+/// Sign extend the low `bits` bits of `val`
+fn sext(val: u32, bits: u32) -> usize {
+    (((val as u64) << (64 - bits)) as i64 >> (64 - bits)) as usize
+}
+
+/// Value of register x`idx`
+fn reg(trap_context: &TrapContext, idx: u32) -> usize {
+    match idx {
+        0 => 0,
+        idx => trap_context.regs[idx as usize],
+    }
+}
+
+/// Address of the instruction executed after `insn` at `pc`
+/// # Description
+/// Jumps and branches are evaluated with the registers in `trap_context`, anything else falls through.
+fn next_pc(pc: usize, insn: u32, trap_context: &TrapContext) -> usize {
+    if insn & 0b11 != 0b11 {
+        // compressed
+        let funct3 = (insn >> 13) & 0b111;
+        let rs1 = (insn >> 7) & 0b11111;
+        let rs2 = (insn >> 2) & 0b11111;
+        match (insn & 0b11, funct3) {
+            // c.j
+            (0b01, 0b101) => {
+                let offset = ((insn >> 12) & 1) << 11 | ((insn >> 11) & 1) << 4 | ((insn >> 9) & 0b11) << 8
+                    | ((insn >> 8) & 1) << 10 | ((insn >> 7) & 1) << 6 | ((insn >> 6) & 1) << 7
+                    | ((insn >> 3) & 0b111) << 1 | ((insn >> 2) & 1) << 5;
+                pc.wrapping_add(sext(offset, 12))
+            },
+            // c.beqz, c.bnez
+            (0b01, 0b110) | (0b01, 0b111) => {
+                let offset = ((insn >> 12) & 1) << 8 | ((insn >> 10) & 0b11) << 3 | ((insn >> 5) & 0b11) << 6
+                    | ((insn >> 3) & 0b11) << 1 | ((insn >> 2) & 1) << 5;
+                let zero = reg(trap_context, 8 + ((insn >> 7) & 0b111)) == 0;
+                if zero == (funct3 == 0b110) {
+                    pc.wrapping_add(sext(offset, 9))
+                } else {
+                    pc + 2
+                }
+            },
+            // c.jr, c.jalr
+            (0b10, 0b100) if rs2 == 0 && rs1 != 0 => reg(trap_context, rs1) & !1,
+            _ => pc + 2,
+        }
+    } else {
+        let rs1 = (insn >> 15) & 0b11111;
+        let rs2 = (insn >> 20) & 0b11111;
+        match insn & 0x7f {
+            // jal
+            0x6f => {
+                let offset = ((insn >> 31) & 1) << 20 | ((insn >> 21) & 0x3ff) << 1
+                    | ((insn >> 20) & 1) << 11 | ((insn >> 12) & 0xff) << 12;
+                pc.wrapping_add(sext(offset, 21))
+            },
+            // jalr
+            0x67 => reg(trap_context, rs1).wrapping_add(sext(insn >> 20, 12)) & !1,
+            // branches
+            0x63 => {
+                let offset = ((insn >> 31) & 1) << 12 | ((insn >> 7) & 1) << 11
+                    | ((insn >> 25) & 0x3f) << 5 | ((insn >> 8) & 0xf) << 1;
+                let (a, b) = (reg(trap_context, rs1), reg(trap_context, rs2));
+                let taken = match (insn >> 12) & 0b111 {
+                    0b000 => a == b,
+                    0b001 => a != b,
+                    0b100 => (a as isize) < (b as isize),
+                    0b101 => (a as isize) >= (b as isize),
+                    0b110 => a < b,
+                    0b111 => a >= b,
+                    _ => false,
+                };
+                if taken {
+                    pc.wrapping_add(sext(offset, 13))
+                } else {
+                    pc + 4
+                }
+            },
+            _ => pc + 4,
+        }
+    }
+}
+
+/// Plant a breakpoint after the next instruction of the stopped tracee
+/// # Description
+/// A breakpoint left from an earlier step is taken out first.
+pub fn insert_step_breakpoint(tracee: &mut ProcessControlBlockInner) -> Result<(), ErrNo> {
+    remove_step_breakpoint(tracee);
+    let trap_context = tracee.get_trap_context();
+    let pc = VirtAddr::from(trap_context.sepc);
+    let mut half = [0u8; 2];
+    tracee.layout.copy_from_user(&mut half, pc)?;
+    let mut insn = u16::from_le_bytes(half) as u32;
+    if insn & 0b11 == 0b11 {
+        tracee.layout.copy_from_user(&mut half, pc + 2)?;
+        insn |= (u16::from_le_bytes(half) as u32) << 16;
+    }
+    let target = VirtAddr::from(next_pc(pc.0, insn, trap_context));
+    let mut orig = [0u8; 2];
+    tracee.layout.copy_from_user(&mut orig, target)?;
+    tracee.layout.poke_user(target, &C_EBREAK.to_le_bytes())?;
+    flush_icache();
+    tracee.step_breakpoint = Some((target, orig));
+    Ok(())
+}
+
+/// Put back the instruction under the single step breakpoint, if there is one
+/// # Return
+/// The address of the breakpoint
+pub fn remove_step_breakpoint(tracee: &mut ProcessControlBlockInner) -> Option<VirtAddr> {
+    let (addr, orig) = tracee.step_breakpoint.take()?;
+    if let Err(errno) = tracee.layout.poke_user(addr, &orig) {
+        warning!("Failed to restore the instruction under the breakpoint @ {:?}: {}", addr, errno);
+    }
+    flush_icache();
+    Some(addr)
+}
@@ -0,0 +1,34 @@
+//! System wide scheduler statistics, reported in /proc/stat
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Number of switches into a process
+static CONTEXT_SWITCHES: AtomicUsize = AtomicUsize::new(0);
+/// Number of processes created since boot
+static FORKS: AtomicUsize = AtomicUsize::new(0);
+/// Time spent in user mode by all processes, in ticks
+static USER_TIME: AtomicU64 = AtomicU64::new(0);
+
+pub fn count_switch() {
+    CONTEXT_SWITCHES.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn count_fork() {
+    FORKS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn account_user_time(ticks: u64) {
+    USER_TIME.fetch_add(ticks, Ordering::Relaxed);
+}
+
+pub fn context_switches() -> usize {
+    CONTEXT_SWITCHES.load(Ordering::Relaxed)
+}
+
+pub fn forks() -> usize {
+    FORKS.load(Ordering::Relaxed)
+}
+
+pub fn user_time() -> u64 {
+    USER_TIME.load(Ordering::Relaxed)
+}
@@ -0,0 +1,85 @@
+//! Wait queue for processes blocking in kernel
+
+use super::{ProcessControlBlock, ProcessStatus, current_process, resume, sleep_switch};
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use spin::Mutex;
+
+/// A queue of processes sleeping until some event happens.
+/// # Description
+/// Sleeping processes are kept out of the run queue, so they don't burn their time slices polling.  
+/// Wake ups can be spurious (a signal also wakes a sleeper up), waiters should re-check their condition.
+pub struct WaitQueue {
+    waiters: Mutex<VecDeque<Weak<ProcessControlBlock>>>,
+}
+
+impl WaitQueue {
+    /// Construct an empty wait queue
+    pub fn new() -> Self {
+        Self {
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Put current process to sleep on the queue.
+    /// # Description
+    /// Returns once woken up.  
+    /// Note that we need to drop locks before calling this method, to avoid potential dead lock on shared resources.
+    pub fn wait(&self) {
+        let process = current_process().unwrap();
+        self.enqueue(&process);
+        drop(process);
+        sleep_switch();
+    }
+
+    /// Add `process` to the waiters, it is woken up with the rest once it goes to sleep.
+    pub fn enqueue(&self, process: &Arc<ProcessControlBlock>) {
+        self.waiters.lock().push_back(Arc::downgrade(process));
+    }
+
+    /// Wake up the first process still sleeping on the queue.
+    /// # Returns
+    /// `true` if a process was woken up.
+    pub fn wake_one(&self) -> bool {
+        loop {
+            let waiter = self.waiters.lock().pop_front();
+            match waiter {
+                Some(waiter) => {
+                    if let Some(process) = waiter.upgrade() {
+                        if wake_up(&process) {
+                            return true;
+                        }
+                    }
+                },
+                None => return false,
+            }
+        }
+    }
+
+    /// Wake up all processes sleeping on the queue.
+    pub fn wake_all(&self) {
+        let waiters: VecDeque<_> = self.waiters.lock().drain(..).collect();
+        for waiter in waiters {
+            if let Some(process) = waiter.upgrade() {
+                wake_up(&process);
+            }
+        }
+    }
+}
+
+/// Put a sleeping process back to the run queue.
+/// # Returns
+/// `false` if the process wasn't sleeping, e.g. it was already woken up by a signal.
+pub fn wake_up(process: &Arc<ProcessControlBlock>) -> bool {
+    // current process is running, and its lock may well be held by the caller
+    if current_process().map_or(false, |current| Arc::ptr_eq(&current, process)) {
+        return false;
+    }
+    let mut arcpcb = process.get_inner_locked();
+    if arcpcb.status != ProcessStatus::Sleeping {
+        return false;
+    }
+    arcpcb.status = ProcessStatus::Ready;
+    drop(arcpcb);
+    resume(process.pid.0)
+}
@@ -10,6 +10,13 @@ pub use sbi_funcs::{
     get_byte,get_byte_non_block_with_echo,
     put_byte,
     shutdown,
+    reboot,
+    reset,
+    SRST_TYPE_SHUTDOWN,
+    SRST_TYPE_COLD_REBOOT,
+    SRST_TYPE_WARM_REBOOT,
+    SRST_REASON_NONE,
+    SRST_REASON_SYSTEM_FAILURE,
     sbi_call,
     sbi_call_all,
     get_vendor_id
@@ -25,6 +32,11 @@ pub use primitive_io::{
     reset_color,
     log,
     LogLevel,
+    ConsoleBackend,
+    set_console,
+    console,
+    console_put_byte,
+    console_get_byte,
 };
 
 pub use timer::{
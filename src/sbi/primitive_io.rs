@@ -3,6 +3,7 @@
 
 use super::{get_byte, put_byte, get_time_ms};
 use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 /// Return the minimal log level of this build.
 fn min_log_level() -> LogLevel {
@@ -63,11 +64,75 @@ const BG_B_WHITE    :u8 = 107;
 
 const BG_DEFAULT    :u8 = 49;
 
+// ======================== console backend ========================
+
+/// 16550 receiver buffer / transmitter holding register
+const UART_RBR_THR  :usize = 0;
+/// 16550 line status register
+const UART_LSR      :usize = 5;
+/// LSR: data ready
+const UART_LSR_DR   :u8 = 1 << 0;
+/// LSR: transmitter holding register empty
+const UART_LSR_THRE :u8 = 1 << 5;
+
+/// Where console bytes go
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ConsoleBackend {
+    /// SBI legacy console, works everywhere
+    Sbi,
+    /// Memory mapped 16550 UART at the given (identity mapped) base
+    Uart16550(usize),
+}
+
+/// Base of the 16550 UART in use, 0 for SBI console
+static CONSOLE_UART: AtomicUsize = AtomicUsize::new(0);
+
+/// Switch console backend
+/// # Description
+/// The UART must be identity mapped in kernel space. SBI console is used until this is called.
+pub fn set_console(backend: ConsoleBackend) {
+    let base = match backend {
+        ConsoleBackend::Sbi => 0,
+        ConsoleBackend::Uart16550(base) => base,
+    };
+    CONSOLE_UART.store(base, Ordering::SeqCst);
+}
+
+/// Get current console backend
+pub fn console() -> ConsoleBackend {
+    match CONSOLE_UART.load(Ordering::Relaxed) {
+        0 => ConsoleBackend::Sbi,
+        base => ConsoleBackend::Uart16550(base),
+    }
+}
+
+/// Put a single byte to the console
+pub fn console_put_byte(b: u8) {
+    match console() {
+        ConsoleBackend::Sbi => put_byte(b),
+        ConsoleBackend::Uart16550(base) => unsafe {
+            while ((base + UART_LSR) as *const u8).read_volatile() & UART_LSR_THRE == 0 {}
+            ((base + UART_RBR_THR) as *mut u8).write_volatile(b);
+        },
+    }
+}
+
+/// Get a single byte from the console, block until there is one
+pub fn console_get_byte() -> u8 {
+    match console() {
+        ConsoleBackend::Sbi => get_byte(),
+        ConsoleBackend::Uart16550(base) => unsafe {
+            while ((base + UART_LSR) as *const u8).read_volatile() & UART_LSR_DR == 0 {}
+            ((base + UART_RBR_THR) as *const u8).read_volatile()
+        },
+    }
+}
+
 // ======================== utf-8 handle ========================
 
-/// Put a single char to SBI
+/// Put a single char to the console
 /// # Description
-/// Put a single char to SBI. The char will be first decoded to UTF-8 byte sequence, then output to SBI.
+/// Put a single char to the console. The char will be first decoded to UTF-8 byte sequence, then output to the console backend.
 /// # Example
 /// ```
 /// putc('你');
@@ -76,17 +141,17 @@ const BG_DEFAULT    :u8 = 49;
 pub fn putc(ch: char) {
     let mut buf = [0u8; 4];
     for code in ch.encode_utf8(&mut buf).as_bytes().iter() {
-        put_byte(*code as u8);
+        console_put_byte(*code as u8);
     }
 }
 
-/// Get a UTF-8 char from SBI
+/// Get a UTF-8 char from the console
 /// # Description
 /// This function will try to accept a utf-8 byte sequence, then decode it into a UTF-8 char.  
 /// It will return an `'�'` when an invalid utf-8 sequence is encountered.
 pub fn getc() -> char { // utf-8 to char
     let mut buf : u32;
-    let init : u8 = get_byte();
+    let init : u8 = console_get_byte();
     let length : u8;
     if init < 0b10000000 {
         return init as char;
@@ -100,7 +165,7 @@ pub fn getc() -> char { // utf-8 to char
     buf = (init & (0b01111111 >> length)) as u32;
 
     for _i in 1..length {
-        let b = get_byte();
+        let b = console_get_byte();
         if b & 0b11000000 != 0b10000000 { return '�'; }
         assert_eq!(b & 0b11000000, 0b10000000); // check utf-8 sequence
         buf <<= 6;
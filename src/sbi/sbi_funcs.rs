@@ -11,6 +11,21 @@ const SBI_REMOTE_SFENCE_VMA: usize = 6;
 const SBI_REMOTE_SFENCE_VMA_ASID: usize = 7;
 const SBI_SHUTDOWN: usize = 8;
 
+/// System Reset extension, "SRST"
+const SBI_EXT_SRST: i32 = 0x53525354;
+const SBI_EXT_SRST_RESET: i32 = 0;
+
+/// System reset type: power off
+pub const SRST_TYPE_SHUTDOWN: usize = 0;
+/// System reset type: power cycle
+pub const SRST_TYPE_COLD_REBOOT: usize = 1;
+/// System reset type: reboot without power cycle
+pub const SRST_TYPE_WARM_REBOOT: usize = 2;
+/// System reset reason: requested
+pub const SRST_REASON_NONE: usize = 0;
+/// System reset reason: something went wrong
+pub const SRST_REASON_SYSTEM_FAILURE: usize = 1;
+
 use core::convert::TryInto;
 
 /// Make a sbi call.
@@ -87,8 +102,28 @@ pub fn get_byte_non_block_with_echo() -> usize {
     return res;
 }
 
+/// Reset the system with the SBI System Reset extension
+/// # Description
+/// Only returns if the reset failed, e.g. the SBI implementation does not have the extension.
+/// # Returns
+/// The SBI error code.
+pub fn reset(reset_type: usize, reason: usize) -> isize {
+    let (err, _) = sbi_call_all(SBI_EXT_SRST, SBI_EXT_SRST_RESET, reset_type, reason, 0);
+    err as isize
+}
+
+/// Reboot the machine
+/// # Returns
+/// Only returns on failure, with the SBI error code.
+pub fn reboot() -> isize {
+    reset(SRST_TYPE_COLD_REBOOT, SRST_REASON_NONE)
+}
+
 /// Shutdown the machine
+/// # Description
+/// Falls back to the legacy shutdown call if System Reset is not available.
 pub fn shutdown() -> ! {
+    reset(SRST_TYPE_SHUTDOWN, SRST_REASON_NONE);
     sbi_call(SBI_SHUTDOWN, 0, 0, 0);
     unreachable!()
 }
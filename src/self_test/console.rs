@@ -0,0 +1,56 @@
+//! Console backend tests
+use super::fdt::FdtBuilder;
+use crate::sbi::{console, getc, putc, set_console, ConsoleBackend};
+use crate::utils::fdt;
+
+/// A board whose UART is ns16550 compatible, with `bootargs` on the command line
+fn board(bootargs: &str) -> fdt::BoardInfo {
+    let blob = FdtBuilder::new()
+        .begin("")
+            .prop("#address-cells", &[2])
+            .prop("#size-cells", &[2])
+            .begin("chosen")
+                .prop_str("bootargs", bootargs)
+            .end()
+            .begin("memory@80000000")
+                .prop("reg", &[0, 0x8000_0000, 0, 0x800_0000])
+            .end()
+            .begin("uart@10000000")
+                .prop("reg", &[0, 0x1000_0000, 0, 0x100])
+                .prop_str("compatible", "ns16550a")
+            .end()
+        .end()
+        .build();
+    fdt::parse(blob.as_ptr() as usize).unwrap()
+}
+
+/// The UART is found in the device tree and only used as console when asked for,
+/// bytes written through it come out of its transmit register, and SBI is back once switched
+pub fn uart_console_test() {
+    verbose!("Testing 16550 UART console...");
+    let info = board("earlycon console=uart");
+    assert_eq!(info.uart16550, 0x1000_0000);
+    assert!(info.console_uart);
+    assert!(!board("console=sbi").console_uart);
+
+    // registers of a UART that can always send, with 'x' received
+    let mut regs = [0u8; 8];
+    regs[0] = b'x';
+    regs[5] = 1 << 0 | 1 << 5;
+    let base = regs.as_mut_ptr() as usize;
+    let before = console();
+    // nothing can be logged while the console is the fake UART
+    set_console(ConsoleBackend::Uart16550(base));
+    assert_eq!(console(), ConsoleBackend::Uart16550(base));
+    let received = getc();
+    putc('O');
+    let sent = unsafe { (base as *const u8).read_volatile() };
+    // the last byte of its UTF-8 encoding
+    putc('é');
+    let last = unsafe { (base as *const u8).read_volatile() };
+    set_console(before);
+    assert_eq!(received, 'x');
+    assert_eq!(sent, b'O');
+    assert_eq!(last, 0xA9);
+    verbose!("16550 UART console test passed!");
+}
@@ -0,0 +1,57 @@
+//! Tests of the exec image cache
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::ram_disk::RamDisk;
+use crate::config::PAGE_SIZE;
+use crate::fs::{File, SeekOp};
+use crate::process::elf_cache::{get_exec_image, ElfCache, ExecImage, ELF_CACHE};
+
+/// A second exec of an unchanged file is served without reading it, a changed file is read again,
+/// and the cache evicts the least recently used image to stay within its limit
+pub fn elf_cache_test() {
+    verbose!("Testing exec image cache...");
+    let path = "/selftest/elf_cache";
+    let data: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+    let disk = RamDisk::new(data.clone());
+    let file: Arc<dyn File> = disk.clone();
+
+    let first = get_exec_image(path, &file).unwrap();
+    assert_eq!(first.data(), &data[..]);
+
+    // any read of the file would fail now
+    disk.set_fail_reads(true);
+    file.seek(0, SeekOp::SET).unwrap();
+    let second = get_exec_image(path, &file).unwrap();
+    assert!(Arc::ptr_eq(&first, &second));
+    disk.set_fail_reads(false);
+
+    // growing the file changes its size, so it is read again
+    file.seek(0, SeekOp::END).unwrap();
+    assert_eq!(file.write(&[0xaa; 10]).unwrap(), 10);
+    file.seek(0, SeekOp::SET).unwrap();
+    let third = get_exec_image(path, &file).unwrap();
+    assert!(!Arc::ptr_eq(&first, &third));
+    assert_eq!(third.data().len(), 5010);
+    ELF_CACHE.lock().invalidate(path);
+
+    // each image below takes two pages
+    let image = || {
+        let file: Arc<dyn File> = RamDisk::new(vec![0; PAGE_SIZE]);
+        Arc::new(ExecImage::load(&file, PAGE_SIZE).unwrap())
+    };
+    let mut cache = ElfCache::new(4);
+    cache.insert(String::from("/a"), 1, 0, 0, image());
+    cache.insert(String::from("/b"), 1, 0, 0, image());
+    assert!(cache.get("/a", 1, 0, 0).is_some());
+    cache.insert(String::from("/c"), 1, 0, 0, image());
+    assert!(cache.get("/b", 1, 0, 0).is_none());
+    assert!(cache.get("/a", 1, 0, 0).is_some());
+    assert_eq!(cache.pages(), 4);
+    assert_eq!(cache.shrink(2), 2);
+    assert!(cache.get("/c", 1, 0, 0).is_none());
+    assert_eq!(cache.clear(), 2);
+    assert_eq!(cache.pages(), 0);
+    verbose!("Exec image cache test passed!");
+}
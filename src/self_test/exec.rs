@@ -0,0 +1,195 @@
+//! Tests of loading executables
+use super::process::{spawn, tiny_elf_of_type, ENTRY, TEXT};
+use crate::config::{ELF_DYN_BASE, ELF_INTERP_BASE, HWCAP, PAGE_SIZE, USER_STACK_SIZE};
+use crate::memory::{MemLayout, VMAFlags, VirtAddr};
+use crate::process::{AuxType, ErrNo};
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+/// Find an auxv entry
+fn aux(auxv: &[crate::process::AuxHeader], aux_type: AuxType) -> usize {
+    auxv.iter().find(|a| a.aux_type as usize == aux_type as usize).unwrap().value
+}
+
+/// A position independent executable is moved to ELF_DYN_BASE, along with its entry point and auxv,
+/// while a static one stays where it is linked
+pub fn pie_load_test() {
+    verbose!("Testing PIE loading...");
+    let (layout, data_top, _, entry, auxv) = MemLayout::new_elf(&tiny_elf_of_type(3), None).unwrap(); // ET_DYN
+    assert_eq!(entry, ELF_DYN_BASE + ENTRY);
+    assert_eq!(data_top, ELF_DYN_BASE + TEXT + 124);
+    assert_eq!(aux(&auxv, AuxType::ENTRY), ELF_DYN_BASE + ENTRY);
+    assert_eq!(aux(&auxv, AuxType::PHDR), ELF_DYN_BASE + TEXT + 64);
+    let insn: u32 = layout.read_user_data(VirtAddr::from(entry));
+    assert_eq!(insn, 0x6f);
+    assert!(layout.translate(VirtAddr::from(ENTRY).into()).map_or(true, |pte| !pte.valid()));
+
+    let (_, _, _, entry, auxv) = MemLayout::new_elf(&tiny_elf_of_type(2), None).unwrap(); // ET_EXEC
+    assert_eq!(entry, ENTRY);
+    assert_eq!(aux(&auxv, AuxType::PHDR), TEXT + 64);
+    verbose!("PIE loading test passed!");
+}
+
+/// A dynamically linked ELF, whose PT_INTERP names `interp`. Its entry at TEXT+176 loops on `j .`
+fn dynamic_elf(interp: &[u8]) -> Vec<u8> {
+    let code = 64 + 56 * 2;
+    let mut elf = vec![0u8; code + 4];
+    elf[..64].copy_from_slice(&tiny_elf_of_type(2)[..64]);
+    elf[24..32].copy_from_slice(&((TEXT + code) as u64).to_le_bytes());
+    elf[56..58].copy_from_slice(&2u16.to_le_bytes());
+    // PT_LOAD, R-X
+    elf[64..68].copy_from_slice(&1u32.to_le_bytes());
+    elf[68..72].copy_from_slice(&5u32.to_le_bytes());
+    elf[80..88].copy_from_slice(&(TEXT as u64).to_le_bytes());
+    elf[88..96].copy_from_slice(&(TEXT as u64).to_le_bytes());
+    elf[96..104].copy_from_slice(&((code + 4) as u64).to_le_bytes());
+    elf[104..112].copy_from_slice(&((code + 4) as u64).to_le_bytes());
+    elf[112..120].copy_from_slice(&0x1000u64.to_le_bytes());
+    // PT_INTERP, right after the code
+    elf[120..124].copy_from_slice(&3u32.to_le_bytes());
+    elf[128..136].copy_from_slice(&((code + 4) as u64).to_le_bytes());
+    elf[152..160].copy_from_slice(&((interp.len() + 1) as u64).to_le_bytes());
+    elf[code..code + 4].copy_from_slice(&0x0000006fu32.to_le_bytes());
+    elf.extend_from_slice(interp);
+    elf.push(0);
+    elf
+}
+
+/// The interpreter named by PT_INTERP is mapped at ELF_INTERP_BASE and entered first,
+/// with AT_BASE and AT_ENTRY telling it where itself and the program are
+pub fn interp_load_test() {
+    verbose!("Testing interpreter loading...");
+    let program = dynamic_elf(b"/lib/ld-musl-riscv64.so.1");
+    assert_eq!(MemLayout::elf_interp(&program).unwrap(), "/lib/ld-musl-riscv64.so.1");
+    assert!(MemLayout::elf_interp(&tiny_elf_of_type(2)).is_none());
+
+    let interp = tiny_elf_of_type(3);
+    let (layout, _, _, entry, auxv) = MemLayout::new_elf(&program, Some(&interp[..])).unwrap();
+    assert_eq!(entry, ELF_INTERP_BASE + ENTRY);
+    assert_eq!(aux(&auxv, AuxType::BASE), ELF_INTERP_BASE);
+    assert_eq!(aux(&auxv, AuxType::ENTRY), TEXT + 176);
+    let insn: u32 = layout.read_user_data(VirtAddr::from(entry));
+    assert_eq!(insn, 0x6f);
+    let insn: u32 = layout.read_user_data(VirtAddr::from(TEXT + 176));
+    assert_eq!(insn, 0x6f);
+
+    // a broken interpreter fails the exec instead of the kernel
+    assert!(matches!(MemLayout::new_elf(&program, Some(&b"#!/bin/sh\n"[..])), Err(ErrNo::ExecFormatError)));
+    verbose!("Interpreter loading test passed!");
+}
+
+/// The part of a LOAD segment past its file size reads as zero, even when the file goes on after it
+pub fn bss_zero_test() {
+    verbose!("Testing bss zeroing...");
+    let mut elf = tiny_elf_of_type(2);
+    let bss = 0x2000;
+    elf[104..112].copy_from_slice(&((124 + bss) as u64).to_le_bytes());
+    // whatever follows the segment in the file must not leak into the bss
+    elf.extend_from_slice(&[0xa5; 64]);
+    let (layout, data_top, _, _, _) = MemLayout::new_elf(&elf, None).unwrap();
+    assert_eq!(data_top, TEXT + 124 + bss);
+    let insn: u32 = layout.read_user_data(VirtAddr::from(ENTRY));
+    assert_eq!(insn, 0x6f);
+    for off in (124..124 + bss).step_by(8) {
+        let word: u64 = layout.read_user_data(VirtAddr::from(TEXT + off));
+        assert_eq!(word, 0, "bss not zeroed at {:#x}", TEXT + off);
+    }
+    verbose!("bss zeroing test passed!");
+}
+
+/// Read a NUL terminated string out of a layout
+fn read_cstr(layout: &MemLayout, mut ptr: usize) -> Vec<u8> {
+    let mut s = Vec::new();
+    loop {
+        let c: u8 = layout.read_user_data(VirtAddr::from(ptr));
+        if c == 0 {
+            return s;
+        }
+        s.push(c);
+        ptr += 1;
+    }
+}
+
+/// The 16 bytes behind AT_RANDOM, in the auxv at `auxv`
+fn at_random(layout: &MemLayout, mut auxv: usize) -> [u8; 16] {
+    loop {
+        let aux_type: usize = layout.read_user_data(VirtAddr::from(auxv));
+        assert_ne!(aux_type, AuxType::NULL as usize, "no AT_RANDOM");
+        if aux_type == AuxType::RANDOM as usize {
+            let ptr: usize = layout.read_user_data(VirtAddr::from(auxv + 8));
+            return layout.read_user_data(VirtAddr::from(ptr));
+        }
+        auxv += 16;
+    }
+}
+
+/// The initial stack is what musl's _start expects: a 16 bytes aligned sp pointing at argc,
+/// then the NULL terminated argv and envp, then the auxv with 16 random bytes behind AT_RANDOM
+pub fn exec_stack_test() {
+    verbose!("Testing exec stack layout...");
+    let pcb = spawn();
+    let argv = vec![b"prog".to_vec(), b"arg1\0".to_vec()];
+    let envp = vec![b"A=1".to_vec()];
+    let argc = pcb.exec(&tiny_elf_of_type(2), None, "/selftest".to_string(), argv, envp).unwrap();
+    assert_eq!(argc, 2);
+
+    let inner = pcb.get_inner_locked();
+    let sp = inner.get_trap_context().regs[2];
+    let layout = &inner.layout;
+    assert_eq!(sp % 16, 0);
+    let word = |i: usize| -> usize { layout.read_user_data(VirtAddr::from(sp + i * 8)) };
+    assert_eq!(word(0), 2);
+    assert_eq!(read_cstr(layout, word(1)), b"prog");
+    assert_eq!(read_cstr(layout, word(2)), b"arg1");
+    assert_eq!(word(3), 0);
+    assert_eq!(read_cstr(layout, word(4)), b"A=1");
+    assert_eq!(word(5), 0);
+    assert!(at_random(layout, sp + 6 * 8).iter().any(|b| *b != 0));
+    verbose!("Exec stack layout test passed!");
+}
+
+/// AT_HWCAP has the IMAFDC extension bits, and every exec gets its own AT_RANDOM bytes
+pub fn auxv_test() {
+    verbose!("Testing auxv...");
+    let (_, _, _, _, auxv) = MemLayout::new_elf(&tiny_elf_of_type(2), None).unwrap();
+    let hwcap = aux(&auxv, AuxType::HWCAP);
+    assert_eq!(hwcap, HWCAP);
+    for ext in b"IMAFDC" {
+        assert_ne!(hwcap & 1 << (ext - b'A'), 0);
+    }
+
+    let pcb = spawn();
+    let mut randoms = Vec::new();
+    for _ in 0..2 {
+        pcb.exec(&tiny_elf_of_type(2), None, "/selftest".to_string(), vec![b"prog".to_vec()], Vec::new()).unwrap();
+        let inner = pcb.get_inner_locked();
+        // argc, argv[0], NULL, NULL
+        let sp = inner.get_trap_context().regs[2];
+        randoms.push(at_random(&inner.layout, sp + 4 * 8));
+    }
+    assert_ne!(randoms[0], randoms[1]);
+    verbose!("auxv test passed!");
+}
+
+/// The pages below the user stack are reserved, never backed, and no mmap can take them
+pub fn stack_guard_test() {
+    verbose!("Testing user stack guard...");
+    let (mut layout, _, stack_top, _, _) = MemLayout::new_elf(&tiny_elf_of_type(2), None).unwrap();
+    let stack_bottom = VirtAddr::from(stack_top - USER_STACK_SIZE);
+    let (low_end, high_end) = MemLayout::user_stack_guard();
+    assert_eq!(high_end, stack_bottom);
+    assert!(MemLayout::in_user_stack_guard(stack_bottom - 1));
+    assert!(MemLayout::in_user_stack_guard(low_end));
+    assert!(!MemLayout::in_user_stack_guard(stack_bottom));
+    assert!(!MemLayout::in_user_stack_guard(low_end - 1));
+
+    // the stack itself works, the guard doesn't
+    assert!(layout.try_get_user_buffer(stack_bottom, 8).is_ok());
+    assert!(layout.try_get_user_buffer(stack_bottom - 8, 8).is_err());
+    assert!(!layout.fault_in_anonymous((stack_bottom - 1).to_vpn()));
+    assert!(layout.lazy_copy_vma(stack_bottom - 1, VMAFlags::W).is_err());
+    assert!(!MemLayout::is_user_range(low_end - PAGE_SIZE, low_end + PAGE_SIZE));
+    assert!(MemLayout::is_user_range(low_end - PAGE_SIZE, low_end));
+    verbose!("User stack guard test passed!");
+}
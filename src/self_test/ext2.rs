@@ -0,0 +1,62 @@
+//! ext2 tests on a RAM disk
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::fat32::path;
+use super::ram_disk::{ext2_big_byte, ext2_image, RamDisk, EXT2_BIG_SIZE, EXT2_BLOCK, EXT2_HELLO};
+use crate::fs::fs_impl::Ext2W;
+use crate::fs::{OpenMode, SeekOp, VirtualFileSystem};
+use crate::process::ErrNo;
+
+/// Names in directory `dir`
+fn list(ext2: &Ext2W, dir: &str) -> Vec<String> {
+    let dir = ext2.open(path(dir), OpenMode::READ | OpenMode::DIR).unwrap();
+    dir.to_dir_file().unwrap().list().iter().map(|file| file.poll().name).collect()
+}
+
+/// Files of an image made like mke2fs would read back, through the indirect block,
+/// and nothing can be written
+pub fn ext2_read_only_test() {
+    verbose!("Testing ext2...");
+    let disk = RamDisk::new(ext2_image());
+    let ext2 = Ext2W::new(disk.clone()).expect("Can't open the RAM disk as ext2");
+    assert_eq!(list(&ext2, "/"), ["sub", "hello"]);
+    assert_eq!(list(&ext2, "/sub"), ["big"]);
+
+    let hello = ext2.open(path("/hello"), OpenMode::READ).unwrap();
+    assert_eq!(hello.poll().size, EXT2_HELLO.len() as u64);
+    let mut buf = vec![0u8; EXT2_BIG_SIZE + EXT2_BLOCK];
+    assert_eq!(hello.read(&mut buf).unwrap(), EXT2_HELLO.len());
+    assert_eq!(&buf[..EXT2_HELLO.len()], EXT2_HELLO);
+
+    let big = ext2.open(path("/sub/big"), OpenMode::READ).unwrap();
+    assert_eq!(big.read(&mut buf).unwrap(), EXT2_BIG_SIZE);
+    assert!((0..EXT2_BIG_SIZE).all(|i| buf[i] == ext2_big_byte(i)));
+    // the last block is behind the singly-indirect block
+    let last = 12 * EXT2_BLOCK + 10;
+    big.seek(last as isize, SeekOp::SET).unwrap();
+    assert_eq!(big.read(&mut buf[..4]).unwrap(), 4);
+    assert!((0..4).all(|i| buf[i] == ext2_big_byte(last + i)));
+
+    // read-only
+    assert!(matches!(ext2.open(path("/hello"), OpenMode::READ | OpenMode::WRITE), Err(ErrNo::ReadonlyFileSystem)));
+    assert!(matches!(ext2.open(path("/new"), OpenMode::WRITE | OpenMode::CREATE), Err(ErrNo::ReadonlyFileSystem)));
+    assert!(matches!(hello.write(b"x"), Err(ErrNo::ReadonlyFileSystem)));
+    assert!(matches!(ext2.mkdir(path("/dir")), Err(ErrNo::ReadonlyFileSystem)));
+    assert!(matches!(ext2.remove(path("/hello")), Err(ErrNo::ReadonlyFileSystem)));
+    drop(hello);
+    drop(big);
+    drop(ext2);
+    assert!(disk.contents() == ext2_image());
+
+    // a corrupted directory size only reads the blocks the directory holds
+    let mut image = ext2_image();
+    let root = 5 * EXT2_BLOCK + 128;
+    image[root + 4..root + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+    let ext2 = Ext2W::new(RamDisk::new(image)).unwrap();
+    assert_eq!(list(&ext2, "/"), ["sub", "hello"]);
+
+    // not an ext2
+    assert!(Ext2W::new(RamDisk::new(vec![0u8; 4 * EXT2_BLOCK])).is_none());
+    verbose!("ext2 test passed!");
+}
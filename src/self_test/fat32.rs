@@ -0,0 +1,476 @@
+//! FAT32 tests on a RAM disk
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::ram_disk::{fat32_image, RamDisk, DATA_SEC, RSV_SEC, FAT32_CLUSTERS, SECTOR};
+use crate::fs::fs_impl::fat32::file::FALLOC_FL_KEEP_SIZE;
+use crate::fs::fs_impl::Fat32W;
+use crate::config::{BDFLUSH_DIRTY_AGE_MS, CLOCK_FREQ};
+use crate::fs::{bdflush_at, mkdir, mkfile, mount_fs, open, parse_path, remove_tree, try_sync_all, unmount_fs};
+use crate::fs::{File, OpenMode, Path, SeekOp, VirtualFileSystem};
+use crate::sbi::get_time;
+use crate::process::ErrNo;
+use crate::syscall::{sys_fstatfs, sys_read, Statfs};
+use super::process::{as_current, install, spawn, stack};
+
+/// A fresh FAT32 on a RAM disk
+pub fn ram_fat32() -> (Arc<RamDisk>, Arc<Fat32W>) {
+    let disk = RamDisk::new(fat32_image());
+    let fat32 = Fat32W::new(disk.clone()).expect("Can't open the RAM disk as FAT32");
+    (disk, Arc::new(fat32))
+}
+
+/// An absolute path
+pub fn path(path: &str) -> Path {
+    parse_path(path).unwrap()
+}
+
+/// Names in directory `dir`
+fn list(fat32: &Fat32W, dir: &str) -> Vec<String> {
+    let dir = fat32.open(path(dir), OpenMode::READ | OpenMode::DIR).unwrap();
+    dir.to_dir_file().unwrap().list().iter().map(|file| file.poll().name).collect()
+}
+
+/// Cluster transfers stop at the end of the cluster, and offsets past it are rejected
+pub fn cluster_bounds_test() {
+    verbose!("Testing FAT32 cluster bounds...");
+    let (_disk, fat32) = ram_fat32();
+    let cluster = fat32.inner.alloc_cluster().unwrap();
+    let size = fat32.inner.cluster_size();
+    let mut buf = [0u8; 16];
+    assert_eq!(fat32.inner.write_cluster(cluster, size - 4, &[0xA5u8; 16]).unwrap(), 4);
+    assert_eq!(fat32.inner.read_cluster(cluster, size - 4, &mut buf).unwrap(), 4);
+    assert_eq!(&buf[..4], &[0xA5u8; 4]);
+    assert!(fat32.inner.get_cluster_cache(cluster, size).is_none());
+    assert!(fat32.inner.read_cluster(cluster, size, &mut buf).is_err());
+    assert!(fat32.inner.write_cluster(cluster, size, &buf).is_err());
+    fat32.inner.clear_chain(cluster).unwrap();
+    verbose!("FAT32 cluster bounds test passed!");
+}
+
+/// O_TMPFILE files have no name in the directory, and their clusters are freed on close
+pub fn tmpfile_test() {
+    verbose!("Testing FAT32 O_TMPFILE...");
+    let (_disk, fat32) = ram_fat32();
+    // clusters are allocated first fit, so this is where the file starts
+    let first = fat32.inner.alloc_cluster().unwrap();
+    fat32.inner.clear_chain(first).unwrap();
+
+    let file = fat32.mktmp(path("/"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert_eq!(file.write(&[0x5au8; 2 * SECTOR]).unwrap(), 2 * SECTOR);
+    assert!(list(&fat32, "/").is_empty());
+    file.seek(0, SeekOp::SET).unwrap();
+    let mut buf = [0u8; SECTOR];
+    assert_eq!(file.read(&mut buf).unwrap(), SECTOR);
+    assert!(buf.iter().all(|&b| b == 0x5a));
+    let next = fat32.inner.alloc_cluster().unwrap();
+    assert_eq!(next, first + 2);
+    fat32.inner.clear_chain(next).unwrap();
+
+    drop(file);
+    assert_eq!(fat32.inner.alloc_cluster().unwrap(), first);
+    verbose!("FAT32 O_TMPFILE test passed!");
+}
+
+/// A directory that can't grow on a full disk fails new entries with ENOSPC,
+/// and a long name group cut short is undone
+pub fn dir_full_test() {
+    verbose!("Testing FAT32 directory growth on a full disk...");
+    let (_disk, fat32) = ram_fat32();
+    let mut taken = Vec::new();
+    while let Ok(cluster) = fat32.inner.alloc_cluster() {
+        taken.push(cluster);
+    }
+    // the root directory is a cluster of 16 entries, these names take 2 long name entries and a short one,
+    // so the 6th name only has room for its first long name entry
+    let mut created: Vec<String> = Vec::new();
+    loop {
+        let name = format!("a_long_file_name_{}", created.len());
+        match fat32.mkfile(path(&format!("/{}", name))) {
+            Ok(_) => created.push(name),
+            Err(ErrNo::NoSpaceLeftOnDevice) => break,
+            Err(_) => panic!("Unexpected error creating {}", name),
+        }
+    }
+    assert_eq!(created.len(), 5);
+    assert_eq!(list(&fat32, "/"), created);
+
+    fat32.inner.clear_chain(taken.pop().unwrap()).unwrap();
+    let name = format!("a_long_file_name_{}", created.len());
+    fat32.mkfile(path(&format!("/{}", name))).unwrap();
+    created.push(name);
+    assert_eq!(list(&fat32, "/"), created);
+    verbose!("FAT32 directory growth test passed!");
+}
+
+/// Clusters left on the disk, counted by taking them all
+fn free_clusters(fat32: &Fat32W) -> usize {
+    let taken: Vec<u32> = core::iter::from_fn(|| fat32.inner.alloc_cluster().ok()).collect();
+    for cluster in taken.iter() {
+        fat32.inner.clear_chain(*cluster).unwrap();
+    }
+    taken.len()
+}
+
+/// Writes to a full disk are cut short then fail with ENOSPC, the size only counts what was written,
+/// and the clusters come back once the file is removed
+pub fn disk_full_test() {
+    verbose!("Testing FAT32 on a full disk...");
+    let (_disk, fat32) = ram_fat32();
+    let free = free_clusters(&fat32);
+    fat32.mkfile(path("/big")).unwrap();
+    let file = fat32.open(path("/big"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    let data = vec![0xA5u8; (free + 4) * SECTOR];
+    assert_eq!(file.write(&data).unwrap(), free * SECTOR);
+    assert_eq!(free_clusters(&fat32), 0);
+    assert!(matches!(file.write(&data[..SECTOR]), Err(ErrNo::NoSpaceLeftOnDevice)));
+    assert_eq!(file.poll().size, (free * SECTOR) as u64);
+    drop(file);
+    fat32.remove(path("/big")).unwrap();
+    assert_eq!(free_clusters(&fat32), free);
+    verbose!("FAT32 full disk test passed!");
+}
+
+/// fallocate takes the clusters up front, with FALLOC_FL_KEEP_SIZE they are kept past the end of the file on close
+pub fn fallocate_test() {
+    verbose!("Testing FAT32 fallocate...");
+    let (_disk, fat32) = ram_fat32();
+    let free = free_clusters(&fat32);
+    let create = |name: &str| {
+        fat32.mkfile(path(name)).unwrap();
+        fat32.open(path(name), OpenMode::READ | OpenMode::WRITE).unwrap()
+    };
+
+    let file = create("/grown");
+    file.clone().to_common_file().unwrap().fallocate(0, 0, 3 * SECTOR).unwrap();
+    assert_eq!(free_clusters(&fat32), free - 3);
+    assert_eq!(file.poll().size, 3 * SECTOR as u64);
+    let mut buf = [0xffu8; 3 * SECTOR];
+    assert_eq!(file.read(&mut buf).unwrap(), 3 * SECTOR);
+    assert!(buf.iter().all(|&b| b == 0));
+    drop(file);
+
+    let file = create("/kept");
+    let common = file.clone().to_common_file().unwrap();
+    common.fallocate(FALLOC_FL_KEEP_SIZE, 0, 2 * SECTOR).unwrap();
+    assert_eq!(file.poll().size, 0);
+    assert!(matches!(common.fallocate(2, 0, SECTOR), Err(ErrNo::OperationNotSupportedOnTransportEndpoint)));
+    assert!(matches!(common.fallocate(0, 0, 0), Err(ErrNo::InvalidArgument)));
+    drop(common);
+    drop(file);
+    assert_eq!(free_clusters(&fat32), free - 5);
+
+    // all or nothing on a full disk
+    let file = create("/huge");
+    let common = file.clone().to_common_file().unwrap();
+    assert!(matches!(common.fallocate(0, 0, free * SECTOR), Err(ErrNo::NoSpaceLeftOnDevice)));
+    assert_eq!(free_clusters(&fat32), free - 5);
+    assert_eq!(file.poll().size, 0);
+    verbose!("FAT32 fallocate test passed!");
+}
+
+/// Growing a file does not write its new clusters, they read as zero over stale data,
+/// a partial write zeroes the rest of its block, and the zeros reach the disk on sync
+pub fn lazy_zero_test() {
+    verbose!("Testing FAT32 lazy cluster zeroing...");
+    let mut image = fat32_image();
+    // stale data everywhere but the root directory
+    for byte in image[(DATA_SEC + 1) * SECTOR..].iter_mut() {
+        *byte = 0xAA;
+    }
+    let disk = RamDisk::new(image);
+    let fat32 = Arc::new(Fat32W::new(disk.clone()).unwrap());
+    fat32.mkfile(path("/sparse")).unwrap();
+    let file = fat32.open(path("/sparse"), OpenMode::READ | OpenMode::WRITE).unwrap();
+
+    let before = disk.writes();
+    file.clone().to_common_file().unwrap().fallocate(0, 0, 8 * SECTOR).unwrap();
+    // one write per cluster without the optimization
+    assert!(disk.writes() - before < 8);
+    let mut buf = [0xffu8; 8 * SECTOR];
+    assert_eq!(file.read(&mut buf).unwrap(), 8 * SECTOR);
+    assert!(buf.iter().all(|&b| b == 0));
+
+    file.seek(SECTOR as isize + 7, SeekOp::SET).unwrap();
+    assert_eq!(file.write(b"partial").unwrap(), 7);
+    drop(file);
+    fat32.sync(true);
+
+    // nothing stale comes back once the disk is opened again
+    let fat32 = Fat32W::new(RamDisk::new(disk.contents())).unwrap();
+    let file = fat32.open(path("/sparse"), OpenMode::READ).unwrap();
+    assert_eq!(file.read(&mut buf).unwrap(), 8 * SECTOR);
+    assert_eq!(&buf[SECTOR + 7..SECTOR + 14], b"partial");
+    buf[SECTOR + 7..SECTOR + 14].fill(0);
+    assert!(buf.iter().all(|&b| b == 0));
+    verbose!("FAT32 lazy zeroing test passed!");
+}
+
+/// The flush done on panic writes back data of files that are still open
+pub fn panic_flush_test() {
+    verbose!("Testing FAT32 flush on panic...");
+    let (disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/kept")).unwrap();
+    let file = fat32.open(path("/kept"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert_eq!(file.write(b"old data").unwrap(), 8);
+    drop(file);
+
+    // the file is kept open, closing it would sync the fs
+    let file = fat32.open(path("/kept"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert_eq!(file.write(b"new data").unwrap(), 8);
+    mount_fs("/selftest_panic".to_string(), fat32).unwrap();
+    assert!(try_sync_all());
+    unmount_fs("/selftest_panic".to_string()).unwrap();
+
+    let fat32 = Fat32W::new(RamDisk::new(disk.contents())).unwrap();
+    let mut buf = [0u8; 8];
+    let kept = fat32.open(path("/kept"), OpenMode::READ).unwrap();
+    assert_eq!(kept.read(&mut buf).unwrap(), 8);
+    assert_eq!(&buf, b"new data");
+    drop(file);
+    verbose!("FAT32 flush on panic test passed!");
+}
+
+/// The background flush writes back blocks once they are old enough, without a sync
+pub fn bdflush_test() {
+    verbose!("Testing FAT32 background flush...");
+    let (disk, fat32) = ram_fat32();
+    let data = [0x69u8; 100];
+    let on_disk = || disk.contents().windows(data.len()).any(|window| window == data);
+    fat32.mkfile(path("/aging")).unwrap();
+    mount_fs("/selftest_bdflush".to_string(), fat32.clone()).unwrap();
+
+    // the file is kept open, closing it would sync the fs
+    let dirtied = get_time();
+    let file = fat32.open(path("/aging"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert_eq!(file.write(&data).unwrap(), data.len());
+    let age = BDFLUSH_DIRTY_AGE_MS * CLOCK_FREQ / 1000;
+    // not old enough yet
+    while bdflush_at(dirtied + age - 1) != 0 {}
+    assert!(!on_disk());
+    // simulated time is past the age, other file systems may use up some runs
+    let later = get_time() + age;
+    while bdflush_at(later) != 0 {}
+    assert!(on_disk());
+    unmount_fs("/selftest_bdflush".to_string()).unwrap();
+    drop(file);
+    verbose!("FAT32 background flush test passed!");
+}
+
+/// Long names sharing a short name basis get distinct numeric tails, and each name opens its own file
+pub fn short_name_test() {
+    verbose!("Testing FAT32 short names...");
+    let (_disk, fat32) = ram_fat32();
+    let names = ["longfilename1.txt", "longfilename2.txt", "longfilename3.txt"];
+    for (i, name) in names.iter().enumerate() {
+        fat32.mkfile(path(&format!("/{}", name))).unwrap();
+        let file = fat32.open(path(&format!("/{}", name)), OpenMode::READ | OpenMode::WRITE).unwrap();
+        assert_eq!(file.write(&[i as u8]).unwrap(), 1);
+    }
+    for (i, name) in names.iter().enumerate() {
+        let file = fat32.open(path(&format!("/{}", name)), OpenMode::READ).unwrap();
+        let mut buf = [0xFFu8];
+        assert_eq!(file.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], i as u8);
+    }
+    let listed = list(&fat32, "/");
+    assert!(names.iter().all(|name| listed.iter().any(|listed| listed == name)));
+    verbose!("FAT32 short name test passed!");
+}
+
+/// Non-ASCII long names come back from the directory as they went in
+pub fn utf8_name_test() {
+    verbose!("Testing FAT32 UTF-8 long names...");
+    let (_disk, fat32) = ram_fat32();
+    // a BMP character, and one needing a UTF-16 surrogate pair
+    let names = ["donn\u{e9}es-\u{6570}\u{636e}.txt", "\u{1F600}.log"];
+    for name in names.iter() {
+        fat32.mkfile(path(&format!("/{}", name))).unwrap();
+    }
+    let listed = list(&fat32, "/");
+    for name in names.iter() {
+        assert!(listed.iter().any(|listed| listed == name), "{} is listed as {:?}", name, listed);
+        assert!(fat32.open(path(&format!("/{}", name)), OpenMode::READ).is_ok());
+    }
+    verbose!("FAT32 UTF-8 long name test passed!");
+}
+
+/// Lookups ignore case, names keep the case they were created with
+pub fn name_case_test() {
+    verbose!("Testing FAT32 name case...");
+    let (_disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/ReadMe.Txt")).unwrap();
+    let file = fat32.open(path("/ReadMe.Txt"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert_eq!(file.write(b"case").unwrap(), 4);
+    drop(file);
+    for name in ["/readme.txt", "/README.TXT", "/ReadMe.Txt"].iter() {
+        let file = fat32.open(path(name), OpenMode::READ).unwrap();
+        let mut buf = [0u8; 4];
+        assert_eq!(file.read(&mut buf).unwrap(), 4);
+        assert_eq!(&buf, b"case");
+    }
+    assert!(matches!(fat32.mkfile(path("/README.txt")), Err(ErrNo::FileExists)));
+    assert_eq!(list(&fat32, "/"), ["ReadMe.Txt"]);
+    verbose!("FAT32 name case test passed!");
+}
+
+/// New directories start with "." and ".." pointing at themselves and at their parent, 0 for the root
+pub fn dot_entries_test() {
+    verbose!("Testing FAT32 dot entries...");
+    let start = |entry: &[u8]| (u16::from_le_bytes([entry[20], entry[21]]) as u32) << 16
+        | u16::from_le_bytes([entry[26], entry[27]]) as u32;
+    let (disk, fat32) = ram_fat32();
+    fat32.mkdir(path("/d")).unwrap();
+    fat32.mkdir(path("/d/e")).unwrap();
+    fat32.inner.sync().unwrap();
+    let contents = disk.contents();
+    // clusters are taken first fit after the root at 2, so "/d" is at 3 and "/d/e" at 4
+    for (cluster, parent) in [(3, 0), (4, 3)].iter() {
+        let sector = DATA_SEC + *cluster as usize - 2;
+        let dir = &contents[sector * SECTOR..(sector + 1) * SECTOR];
+        assert_eq!(&dir[0..11], b".          ");
+        assert_eq!(&dir[32..43], b"..         ");
+        assert_eq!(dir[11] & 0x10, 0x10);
+        assert_eq!(dir[32 + 11] & 0x10, 0x10);
+        assert_eq!((start(&dir[0..32]), start(&dir[32..64])), (*cluster, *parent));
+    }
+    let listed = list(&fat32, "/d");
+    assert!([".", "..", "e"].iter().all(|name| listed.iter().any(|listed| listed == name)));
+    verbose!("FAT32 dot entries test passed!");
+}
+
+/// Empty directories and files can be removed, non-empty directories can't, "." and ".." can't be removed at all
+pub fn rmdir_test() {
+    verbose!("Testing FAT32 rmdir...");
+    let (_disk, fat32) = ram_fat32();
+    let free = free_clusters(&fat32);
+    fat32.mkdir(path("/d")).unwrap();
+    fat32.mkfile(path("/d/f")).unwrap();
+    assert!(matches!(fat32.remove(path("/d")), Err(ErrNo::DirectoryNotEmpty)));
+    fat32.remove(path("/d/f")).unwrap();
+    assert!(matches!(fat32.open(path("/d/f"), OpenMode::READ), Err(ErrNo::NoSuchFileOrDirectory)));
+    let dotdot = Path { path: vec![String::from("d"), String::from("..")], must_dir: false, is_abs: true };
+    assert!(matches!(fat32.remove(dotdot), Err(ErrNo::InvalidArgument)));
+    fat32.remove(path("/d")).unwrap();
+    assert!(matches!(fat32.open(path("/d"), OpenMode::READ | OpenMode::DIR), Err(ErrNo::NoSuchFileOrDirectory)));
+    assert_eq!(free_clusters(&fat32), free);
+    verbose!("FAT32 rmdir test passed!");
+}
+
+/// remove_tree takes down a tree three levels deep, and all its clusters are freed
+pub fn remove_tree_test() {
+    verbose!("Testing remove_tree...");
+    let (_disk, fat32) = ram_fat32();
+    mount_fs("/selftest".to_string(), fat32.clone()).unwrap();
+    let free = free_clusters(&fat32);
+    for dir in ["/selftest/a", "/selftest/a/b", "/selftest/a/b/c"].iter() {
+        mkdir(dir.to_string()).unwrap();
+        mkfile(format!("{}/empty", dir)).unwrap();
+        mkfile(format!("{}/data", dir)).unwrap();
+        let file = open(format!("{}/data", dir), OpenMode::READ | OpenMode::WRITE).unwrap();
+        assert_eq!(file.write(&[0u8; 2 * SECTOR]).unwrap(), 2 * SECTOR);
+    }
+    assert!(free_clusters(&fat32) < free);
+    remove_tree("/selftest/a".to_string()).unwrap();
+    assert!(matches!(open("/selftest/a".to_string(), OpenMode::SYS | OpenMode::DIR), Err(ErrNo::NoSuchFileOrDirectory)));
+    assert_eq!(free_clusters(&fat32), free);
+    unmount_fs("/selftest".to_string()).unwrap();
+    verbose!("remove_tree test passed!");
+}
+
+/// statfs reports the clusters of the DBR and the free ones of the FAT
+pub fn statfs_test() {
+    verbose!("Testing FAT32 statfs...");
+    let (_disk, fat32) = ram_fat32();
+    let status = fat32.statfs();
+    assert_eq!(status.magic, 0x4d44);
+    assert_eq!(status.block_size, SECTOR);
+    // DBR::from_raw takes the sector count as 0x0fff_0000 whatever the disk says, clusters are one sector
+    assert_eq!(status.total_blocks, 0x0fff_0000 - DATA_SEC - 2);
+    // the root directory takes cluster 2
+    assert_eq!(status.free_blocks, FAT32_CLUSTERS - 1);
+    assert_eq!(status.avail_blocks, status.free_blocks);
+    fat32.mkfile(path("/data")).unwrap();
+    let file = fat32.open(path("/data"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert_eq!(file.write(&[0u8; 3 * SECTOR]).unwrap(), 3 * SECTOR);
+    assert_eq!(fat32.statfs().free_blocks, FAT32_CLUSTERS - 4);
+
+    // and so does the syscall, on the fs of the fd
+    let pcb = spawn();
+    let fd = install(&pcb, file);
+    let buf = stack(&pcb, 256);
+    assert_eq!(as_current(&pcb, || sys_fstatfs(fd, buf)), 0);
+    let statfs: Statfs = pcb.get_inner_locked().layout.read_user_data(buf);
+    assert_eq!((statfs.f_type, statfs.f_bsize), (0x4d44, SECTOR as i64));
+    assert_eq!(statfs.f_blocks, status.total_blocks as u64);
+    assert_eq!(statfs.f_bfree, (FAT32_CLUSTERS - 4) as u64);
+    assert_eq!(as_current(&pcb, || sys_fstatfs(fd + 1, buf)), -(ErrNo::BadFileDescriptor as isize));
+    verbose!("FAT32 statfs test passed!");
+}
+
+/// Disk errors come back as EIO, also from a user read() and from the FAT, and the fs works again once the disk does
+pub fn io_error_test() {
+    verbose!("Testing FAT32 I/O error propagation...");
+    let (disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/data")).unwrap();
+    let file: Arc<dyn File> = fat32.open(path("/data"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert_eq!(file.write(b"hello").unwrap(), 5);
+    drop(file);
+    assert!(fat32.inner.sync().is_ok());
+    assert!(fat32.drop_clean_caches() > 0);
+    // nothing is cached, the lookup has to read the directory and the FAT
+    disk.set_fail_reads(true);
+    assert!(matches!(fat32.open(path("/data"), OpenMode::READ), Err(ErrNo::IOError)));
+    assert!(matches!(fat32.inner.alloc_cluster(), Err(ErrNo::IOError)));
+    disk.set_fail_reads(false);
+
+    let file: Arc<dyn File> = fat32.open(path("/data"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    fat32.drop_clean_caches();
+    let pcb = spawn();
+    let fd = install(&pcb, file.clone());
+    let buf = stack(&pcb, 16);
+    disk.set_fail_reads(true);
+    assert_eq!(as_current(&pcb, || sys_read(fd, buf, 5)), -(ErrNo::IOError as isize));
+    disk.set_fail_reads(false);
+    assert_eq!(as_current(&pcb, || sys_read(fd, buf, 5)), 5);
+    let read: [u8; 5] = pcb.get_inner_locked().layout.read_user_data(buf);
+    assert_eq!(&read, b"hello");
+
+    file.seek(0, SeekOp::SET).unwrap();
+    assert_eq!(file.write(b"world").unwrap(), 5);
+    drop(file);
+    pcb.get_inner_locked().files[fd] = None;
+    // blocks that failed to be written stay dirty for the next sync
+    disk.set_fail_writes(true);
+    assert!(fat32.inner.sync().is_err());
+    disk.set_fail_writes(false);
+    assert!(fat32.inner.sync().is_ok());
+    fat32.drop_clean_caches();
+    let file = fat32.open(path("/data"), OpenMode::READ).unwrap();
+    let mut buf = [0u8; 5];
+    assert_eq!(file.read(&mut buf).unwrap(), 5);
+    assert_eq!(&buf, b"world");
+    verbose!("FAT32 I/O error propagation test passed!");
+}
+
+/// Data clusters reach the disk before the FAT entries linking them
+pub fn write_order_test() {
+    verbose!("Testing FAT32 write ordering...");
+    let is_fat = |sector: &usize| (RSV_SEC..DATA_SEC).contains(sector);
+    let (disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/data")).unwrap();
+    let file: Arc<dyn File> = fat32.open(path("/data"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert!(fat32.inner.sync().is_ok());
+    disk.take_written();
+    let data = [0x5Au8; 4 * SECTOR];
+    assert_eq!(file.write(&data).unwrap(), data.len());
+    // the new clusters are on the disk, the links only in the cache
+    let written = disk.take_written();
+    assert!(written.iter().filter(|sector| **sector >= DATA_SEC).count() >= data.len() / SECTOR);
+    assert!(!written.iter().any(is_fat));
+    drop(file);
+    assert!(fat32.inner.sync().is_ok());
+    assert!(disk.take_written().iter().any(is_fat));
+    verbose!("FAT32 write ordering test passed!");
+}
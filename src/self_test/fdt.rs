@@ -0,0 +1,133 @@
+//! Device tree tests on blobs built in memory
+use alloc::vec::Vec;
+
+use crate::config::{MMIO, PAGE_SIZE};
+use crate::memory::mmio_regions;
+use crate::utils::fdt;
+
+/// A flattened device tree under construction
+pub struct FdtBuilder {
+    structure: Vec<u32>,
+    strings: Vec<u8>,
+}
+
+impl FdtBuilder {
+    pub fn new() -> Self {
+        Self {
+            structure: Vec::new(),
+            strings: Vec::new(),
+        }
+    }
+
+    /// Append `bytes` NUL terminated and padded to 4 bytes, in memory order
+    fn push_str(&mut self, bytes: &[u8]) {
+        let mut padded = bytes.to_vec();
+        padded.push(0);
+        padded.resize((padded.len() + 3) & !3, 0);
+        for chunk in padded.chunks(4) {
+            self.structure.push(u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        }
+    }
+
+    pub fn begin(&mut self, name: &str) -> &mut Self {
+        self.structure.push(1u32.to_be());
+        self.push_str(name.as_bytes());
+        self
+    }
+
+    pub fn prop(&mut self, name: &str, cells: &[u32]) -> &mut Self {
+        self.prop_head(name, cells.len() * 4);
+        self.structure.extend(cells.iter().map(|cell| cell.to_be()));
+        self
+    }
+
+    /// A string property
+    pub fn prop_str(&mut self, name: &str, value: &str) -> &mut Self {
+        self.prop_head(name, value.len() + 1);
+        self.push_str(value.as_bytes());
+        self
+    }
+
+    fn prop_head(&mut self, name: &str, len: usize) {
+        let nameoff = self.strings.len() as u32;
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        self.structure.push(3u32.to_be());
+        self.structure.push((len as u32).to_be());
+        self.structure.push(nameoff.to_be());
+    }
+
+    pub fn end(&mut self) -> &mut Self {
+        self.structure.push(2u32.to_be());
+        self
+    }
+
+    /// The blob, word aligned as the parser wants it
+    pub fn build(&mut self) -> Vec<u32> {
+        self.structure.push(9u32.to_be());
+        let header = 10;
+        let strings = header + self.structure.len();
+        self.strings.resize((self.strings.len() + 3) & !3, 0);
+        let total = (strings + self.strings.len() / 4) * 4;
+        let mut blob = vec![0u32; header];
+        blob[0] = 0xd00dfeedu32.to_be();
+        blob[1] = (total as u32).to_be();
+        blob[2] = ((header * 4) as u32).to_be();
+        blob[3] = ((strings * 4) as u32).to_be();
+        blob[5] = 17u32.to_be();
+        blob[6] = 16u32.to_be();
+        blob.extend_from_slice(&self.structure);
+        for chunk in self.strings.chunks(4) {
+            blob.push(u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]));
+        }
+        blob
+    }
+}
+
+/// RAM size and device registers are read from the tree as qemu -m 256M would pass it,
+/// adjacent MMIO registers are merged into whole pages
+pub fn fdt_parse_test() {
+    verbose!("Testing device tree parsing...");
+    let blob = FdtBuilder::new()
+        .begin("")
+            .prop("#address-cells", &[2])
+            .prop("#size-cells", &[2])
+            .begin("memory@80000000")
+                .prop("reg", &[0, 0x8000_0000, 0, 0x1000_0000])
+            .end()
+            .begin("soc")
+                .prop("#address-cells", &[2])
+                .prop("#size-cells", &[2])
+                .begin("uart@10000000")
+                    .prop("reg", &[0, 0x1000_0000, 0, 0x100])
+                .end()
+                .begin("virtio_mmio@10001000")
+                    .prop("reg", &[0, 0x1000_1000, 0, 0x1000])
+                .end()
+                .begin("rtc@101000")
+                    .prop("reg", &[0, 0x10_1000, 0, 0x1000])
+                .end()
+            .end()
+        .end()
+        .build();
+    let info = fdt::parse(blob.as_ptr() as usize).expect("Can't parse the device tree");
+    assert_eq!(info.mem_start, 0x8000_0000);
+    assert_eq!(info.mem_end, 0x9000_0000);
+    assert_eq!(&info.mmio[..info.mmio_cnt], &[(0x1000_0000usize, 0x2000usize)]);
+
+    // no memory node, or no blob at all
+    let blob = FdtBuilder::new().begin("").end().build();
+    assert!(fdt::parse(blob.as_ptr() as usize).is_none());
+    assert!(fdt::parse(0).is_none());
+
+    // whatever the board passed, the static table stays mapped, once
+    let regions = mmio_regions();
+    for (start, size) in MMIO.iter() {
+        assert!(regions.iter().any(|(s, sz)| s <= start && start + size <= s + sz));
+    }
+    for pair in regions.windows(2) {
+        assert!(pair[0].0 + pair[0].1 < pair[1].0);
+    }
+    assert!(regions.iter().all(|(start, size)| start % PAGE_SIZE == 0 && size % PAGE_SIZE == 0));
+    verbose!("Device tree parsing test passed!");
+}
@@ -0,0 +1,100 @@
+//! flock and fcntl lock tests, two open files of the same RAM FAT32 file stand for two processes
+use alloc::sync::Arc;
+
+use super::fat32::{path, ram_fat32};
+use super::process::{as_current, install, spawn, stack};
+use crate::fs::{flock, set_record_lock, test_record_lock, RecordLock};
+use crate::fs::{OpenMode, VirtualFileSystem, F_RDLCK, F_UNLCK, F_WRLCK, LOCK_EX, LOCK_NB, LOCK_SH, LOCK_UN};
+use crate::syscall::{sys_fcntl, Flock, F_GETLK, F_SETLK};
+use crate::process::{ErrNo, ProcessControlBlock};
+
+/// pids of the record lock owners, no such processes exist at boot
+const PID_A: usize = 100_001;
+const PID_B: usize = 100_002;
+
+/// A second LOCK_EX fails with EWOULDBLOCK, and the lock goes with the open file
+pub fn flock_test() {
+    verbose!("Testing flock...");
+    let (_disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/lock")).unwrap();
+    let first = fat32.open(path("/lock"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    let second = fat32.open(path("/lock"), OpenMode::READ).unwrap();
+    flock(&first, LOCK_EX).unwrap();
+    assert!(matches!(flock(&second, LOCK_EX | LOCK_NB), Err(ErrNo::TryAgain)));
+    assert!(matches!(flock(&second, LOCK_SH | LOCK_NB), Err(ErrNo::TryAgain)));
+    // shared locks go together
+    flock(&first, LOCK_SH).unwrap();
+    flock(&second, LOCK_SH | LOCK_NB).unwrap();
+    flock(&second, LOCK_UN).unwrap();
+    flock(&first, LOCK_EX | LOCK_NB).unwrap();
+    // dropping the last reference releases the lock
+    drop(first);
+    flock(&second, LOCK_EX | LOCK_NB).unwrap();
+    flock(&second, LOCK_UN).unwrap();
+    verbose!("flock test passed!");
+}
+
+/// Disjoint ranges of two processes both lock, overlapping ones conflict unless both are read locks
+pub fn record_lock_test() {
+    verbose!("Testing record locks...");
+    let lock = |ltype: usize, start: usize, end: usize, pid: usize| RecordLock { ltype, start, end, pid };
+    let (_disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/lock")).unwrap();
+    let file_a = fat32.open(path("/lock"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    let file_b = fat32.open(path("/lock"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    set_record_lock(&file_a, lock(F_WRLCK, 0, 100, PID_A), false).unwrap();
+    set_record_lock(&file_b, lock(F_WRLCK, 100, 200, PID_B), false).unwrap();
+    // F_GETLK reports the lock in the way
+    let wanted = lock(F_RDLCK, 50, 150, PID_B);
+    assert!(matches!(set_record_lock(&file_b, wanted, false), Err(ErrNo::TryAgain)));
+    let held = test_record_lock(&file_b, &wanted).unwrap();
+    assert_eq!((held.pid, held.ltype, held.start, held.end), (PID_A, F_WRLCK, 0, 100));
+    // once downgraded, read locks share the range
+    set_record_lock(&file_a, lock(F_RDLCK, 0, 100, PID_A), false).unwrap();
+    set_record_lock(&file_b, wanted, false).unwrap();
+    assert!(test_record_lock(&file_a, &lock(F_WRLCK, 0, 50, PID_A)).is_none());
+    assert!(test_record_lock(&file_a, &lock(F_WRLCK, 0, 100, PID_A)).is_some());
+    set_record_lock(&file_a, lock(F_UNLCK, 0, usize::MAX, PID_A), false).unwrap();
+    set_record_lock(&file_b, lock(F_UNLCK, 0, usize::MAX, PID_B), false).unwrap();
+    assert!(test_record_lock(&file_a, &lock(F_WRLCK, 0, usize::MAX, PID_A)).is_none());
+    verbose!("Record lock test passed!");
+}
+
+/// Record locks through fcntl: disjoint ranges of two processes both lock, F_GETLK reports the
+/// lock in the way of an overlapping one, and has nothing to test F_UNLCK against
+pub fn fcntl_lock_test() {
+    verbose!("Testing fcntl record locks...");
+    let (_disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/lock")).unwrap();
+    let pcb_a = spawn();
+    let pcb_b = spawn();
+    let fd_a = install(&pcb_a, fat32.open(path("/lock"), OpenMode::READ | OpenMode::WRITE).unwrap());
+    let fd_b = install(&pcb_b, fat32.open(path("/lock"), OpenMode::READ | OpenMode::WRITE).unwrap());
+    // fcntl on a struct flock at the top of the stack, which is left there for F_GETLK
+    let fcntl = |pcb: &Arc<ProcessControlBlock>, fd: usize, cmd: usize, ltype: usize, start: i64, len: i64| {
+        let ptr = stack(pcb, 64);
+        let fl = Flock { l_type: ltype as i16, l_whence: 0, l_start: start, l_len: len, l_pid: 0 };
+        pcb.get_inner_locked().layout.write_user_data(ptr, &fl);
+        let res = as_current(pcb, || sys_fcntl(fd, cmd, ptr.0));
+        (res, pcb.get_inner_locked().layout.read_user_data::<Flock>(ptr))
+    };
+    assert_eq!(fcntl(&pcb_a, fd_a, F_SETLK, F_WRLCK, 0, 100).0, 0);
+    assert_eq!(fcntl(&pcb_b, fd_b, F_SETLK, F_WRLCK, 100, 100).0, 0);
+    let eagain = -(ErrNo::TryAgain as isize);
+    assert_eq!(fcntl(&pcb_b, fd_b, F_SETLK, F_RDLCK, 50, 100).0, eagain);
+    let (res, held) = fcntl(&pcb_b, fd_b, F_GETLK, F_RDLCK, 50, 100);
+    assert_eq!(res, 0);
+    assert_eq!((held.l_type as usize, held.l_start, held.l_len), (F_WRLCK, 0, 100));
+    assert_eq!(held.l_pid as usize, pcb_a.get_pid());
+    // a range of its own is no conflict
+    let (res, held) = fcntl(&pcb_a, fd_a, F_GETLK, F_WRLCK, 0, 100);
+    assert_eq!(res, 0);
+    assert_eq!(held.l_type as usize, F_UNLCK);
+    let einval = -(ErrNo::InvalidArgument as isize);
+    assert_eq!(fcntl(&pcb_b, fd_b, F_GETLK, F_UNLCK, 0, 0).0, einval);
+    // l_len 0 goes on to EOF and beyond
+    assert_eq!(fcntl(&pcb_a, fd_a, F_SETLK, F_UNLCK, 0, 0).0, 0);
+    assert_eq!(fcntl(&pcb_b, fd_b, F_SETLK, F_RDLCK, 50, 50).0, 0);
+    assert_eq!(fcntl(&pcb_b, fd_b, F_SETLK, F_UNLCK, 0, 0).0, 0);
+    verbose!("fcntl record lock test passed!");
+}
@@ -0,0 +1,285 @@
+//! Tests of the file system syscalls, on the files below them
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use super::fat32::{path, ram_fat32};
+use super::process::{as_current, install, spawn, stack, UNMAPPED};
+use super::ram_disk::RamDisk;
+use crate::memory::copy_from_user;
+use crate::fs::{self, mount_fs, parse_path, unmount_fs, File, OpenMode, SeekOp, VirtualFileSystem};
+use crate::process::ErrNo;
+use crate::syscall::{send_file, sys_dup2, sys_fstat, sys_fstatat, sys_pipe, sys_readv, sys_statx, sys_sync, sys_syncfs, sys_writev};
+use crate::syscall::{AtFlags, FStat, Statx, StatxMask, O_CLOEXEC, O_NONBLOCK};
+use crate::syscall::{sys_getdents64, POSIXDType};
+use crate::syscall::{sys_mknodat, AT_FDCWD};
+
+/// Copying from an offset leaves the input cursor alone, short inputs give short copies,
+/// and an error part way returns what was copied
+pub fn sendfile_test() {
+    verbose!("Testing sendfile...");
+    let data: Vec<u8> = (0..6000).map(|i| i as u8).collect();
+    let input: Arc<dyn File> = RamDisk::new(data.clone());
+    input.seek(7, SeekOp::SET).unwrap();
+
+    let disk = RamDisk::new(Vec::new());
+    let output: Arc<dyn File> = disk.clone();
+    assert_eq!(send_file(&output, &input, Some(3), 10).unwrap(), 10);
+    assert_eq!(&disk.contents()[..], &data[3..13]);
+    assert_eq!(input.get_cursor().unwrap(), 7);
+
+    // input shorter than count
+    assert_eq!(send_file(&output, &input, Some(5990), 100).unwrap(), 10);
+    assert_eq!(&disk.contents()[10..], &data[5990..]);
+    assert_eq!(input.get_cursor().unwrap(), 7);
+
+    // the output fills up in the second chunk
+    let disk = RamDisk::new(Vec::new());
+    disk.set_size_limit(5000);
+    let output: Arc<dyn File> = disk.clone();
+    input.seek(0, SeekOp::SET).unwrap();
+    assert_eq!(send_file(&output, &input, None, 6000).unwrap(), 5000);
+    assert_eq!(input.get_cursor().unwrap(), 5000);
+    assert_eq!(&disk.contents()[..], &data[..5000]);
+    assert!(send_file(&output, &input, None, 6000).is_err());
+    assert_eq!(input.get_cursor().unwrap(), 5000);
+    verbose!("sendfile test passed!");
+}
+
+/// An iovec on an unmapped page ends the transfer, and the bytes before it are returned
+pub fn iovec_fault_test() {
+    verbose!("Testing writev/readv on a bad iovec...");
+    let pcb = spawn();
+    let disk = RamDisk::new(Vec::new());
+    let fd = install(&pcb, disk.clone());
+    let buf = stack(&pcb, 64);
+    let iov = stack(&pcb, 128);
+    {
+        let inner = pcb.get_inner_locked();
+        inner.layout.write_user_data(buf, b"hello");
+        inner.layout.write_user_data(iov, &[buf.0, 5, UNMAPPED, 8, buf.0, 5]);
+    }
+    assert_eq!(as_current(&pcb, || sys_writev(fd, iov, 3)), 5);
+    assert_eq!(&disk.contents()[..], b"hello");
+
+    pcb.get_inner_locked().layout.write_user_data(buf, &[0u8; 5]);
+    disk.seek(0, SeekOp::SET).unwrap();
+    assert_eq!(as_current(&pcb, || sys_readv(fd, iov, 3)), 5);
+    let read: [u8; 5] = pcb.get_inner_locked().layout.read_user_data(buf);
+    assert_eq!(&read, b"hello");
+    verbose!("writev/readv bad iovec test passed!");
+}
+
+/// dup2 to the same fd is a no-op, and dup2 over an open fd replaces it
+pub fn dup2_test() {
+    verbose!("Testing dup2...");
+    let pcb = spawn();
+    let first = RamDisk::new(Vec::new());
+    let second = RamDisk::new(Vec::new());
+    let fd = install(&pcb, first.clone());
+    let other = install(&pcb, second.clone());
+    assert_eq!(as_current(&pcb, || sys_dup2(fd, fd)), fd as isize);
+    assert!(pcb.get_inner_locked().files[fd].is_some());
+    assert_eq!(as_current(&pcb, || sys_dup2(other + 1, other + 1)), -1);
+
+    assert_eq!(as_current(&pcb, || sys_dup2(fd, 10)), 10);
+    pcb.get_inner_locked().files[10].as_ref().unwrap().write(b"a").unwrap();
+    assert_eq!(&first.contents()[..], b"a");
+
+    assert_eq!(as_current(&pcb, || sys_dup2(fd, other)), other as isize);
+    pcb.get_inner_locked().files[other].as_ref().unwrap().write(b"b").unwrap();
+    assert_eq!(&first.contents()[..], b"ab");
+    assert!(second.contents().is_empty());
+    verbose!("dup2 test passed!");
+}
+
+/// pipe2 flags apply to both ends
+pub fn pipe2_flags_test() {
+    verbose!("Testing pipe2 flags...");
+    let pcb = spawn();
+    let fds = stack(&pcb, 8);
+    assert_eq!(as_current(&pcb, || sys_pipe(fds, O_CLOEXEC | O_NONBLOCK)), 0);
+    let [rd, wd]: [i32; 2] = pcb.get_inner_locked().layout.read_user_data(fds);
+    let (read, write) = {
+        let inner = pcb.get_inner_locked();
+        assert!(inner.cloexec.contains(&(rd as usize)));
+        assert!(inner.cloexec.contains(&(wd as usize)));
+        (inner.files[rd as usize].clone().unwrap(), inner.files[wd as usize].clone().unwrap())
+    };
+    let mut buf = [0u8; 4];
+    assert!(matches!(read.read(&mut buf), Err(ErrNo::TryAgain)));
+    assert_eq!(write.write(b"ping").unwrap(), 4);
+    assert_eq!(read.read(&mut buf).unwrap(), 4);
+    pcb.get_inner_locked().close_on_exec();
+    assert!(pcb.get_inner_locked().files.iter().all(|file| file.is_some()));
+    assert_eq!(pcb.get_inner_locked().files.len(), 3);
+    verbose!("pipe2 flags test passed!");
+}
+
+/// statx agrees with fstat, and only claims the fields FAT32 has
+pub fn statx_test() {
+    verbose!("Testing statx...");
+    let (_disk, fat32) = ram_fat32();
+    let file = fat32.mkfile(parse_path("/statx").unwrap()).unwrap();
+    assert_eq!(file.write(b"hello").unwrap(), 5);
+    let pcb = spawn();
+    let fd = install(&pcb, file);
+    let path = stack(&pcb, 8);
+    let stat_ptr = stack(&pcb, 512);
+    let statx_ptr = stack(&pcb, 1024);
+    pcb.get_inner_locked().layout.write_user_data(path, &0u8);
+    let want = (StatxMask::BASIC_STATS | StatxMask::BTIME).bits();
+    assert_eq!(as_current(&pcb, || sys_fstat(fd, stat_ptr)), 0);
+    assert_eq!(as_current(&pcb, || sys_statx(fd, path, AtFlags::AT_EMPTY_PATH.bits(), want, statx_ptr)), 0);
+    let (stat, statx) = {
+        let inner = pcb.get_inner_locked();
+        let stat: FStat = inner.layout.read_user_data(stat_ptr);
+        let statx: Statx = inner.layout.read_user_data(statx_ptr);
+        (stat, statx)
+    };
+    assert_eq!(statx.stx_size, 5);
+    assert_eq!(statx.stx_size, stat.st_size as u64);
+    assert_eq!(statx.stx_mode as u32, stat.st_mode);
+    let mask = StatxMask::from_bits_truncate(statx.stx_mask);
+    assert!(mask.contains(StatxMask::TYPE | StatxMask::MODE | StatxMask::SIZE | StatxMask::BLOCKS | StatxMask::MTIME));
+    assert!(!mask.intersects(StatxMask::INO | StatxMask::BTIME));
+    verbose!("statx test passed!");
+}
+
+/// fstatat on an empty path with AT_EMPTY_PATH stats the fd itself
+pub fn fstatat_empty_path_test() {
+    verbose!("Testing fstatat with AT_EMPTY_PATH...");
+    let (_disk, fat32) = ram_fat32();
+    let file = fat32.mkfile(parse_path("/empty_path").unwrap()).unwrap();
+    assert_eq!(file.write(b"0123456789").unwrap(), 10);
+    let pcb = spawn();
+    let fd = install(&pcb, file);
+    let path = stack(&pcb, 8);
+    let stat_ptr = stack(&pcb, 512);
+    let statat_ptr = stack(&pcb, 1024);
+    pcb.get_inner_locked().layout.write_user_data(path, &0u8);
+    assert_eq!(as_current(&pcb, || sys_fstat(fd, stat_ptr)), 0);
+    assert_eq!(as_current(&pcb, || sys_fstatat(fd, path, statat_ptr, AtFlags::AT_EMPTY_PATH.bits())), 0);
+    let (stat, statat) = {
+        let inner = pcb.get_inner_locked();
+        let stat: FStat = inner.layout.read_user_data(stat_ptr);
+        let statat: FStat = inner.layout.read_user_data(statat_ptr);
+        (stat, statat)
+    };
+    assert_eq!(statat.st_size, 10);
+    assert_eq!(statat.st_size, stat.st_size);
+    assert_eq!(statat.st_mode, stat.st_mode);
+    // without the flag an empty path is ENOENT
+    assert_ne!(as_current(&pcb, || sys_fstatat(fd, path, statat_ptr, 0)), 0);
+    verbose!("fstatat AT_EMPTY_PATH test passed!");
+}
+
+/// Data written through an fd is on the device after sync, or after syncfs on that fd
+pub fn sync_test() {
+    verbose!("Testing sync and syncfs...");
+    let pcb = spawn();
+    let on_disk = |disk: &RamDisk, data: &[u8]| disk.contents().windows(data.len()).any(|window| window == data);
+
+    // files are kept open, closing them would sync the fs
+    let (disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/synced")).unwrap();
+    let fd = install(&pcb, fat32.open(path("/synced"), OpenMode::READ | OpenMode::WRITE).unwrap());
+    let data = [0x3Cu8; 100];
+    assert_eq!(pcb.get_inner_locked().files[fd].clone().unwrap().write(&data).unwrap(), data.len());
+    assert!(!on_disk(&disk, &data));
+    mount_fs("/selftest_sync".to_string(), fat32).unwrap();
+    assert_eq!(as_current(&pcb, || sys_sync()), 0);
+    assert!(on_disk(&disk, &data));
+    unmount_fs("/selftest_sync".to_string()).unwrap();
+
+    // syncfs works on file systems that are not mounted too
+    let (disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/synced")).unwrap();
+    let fd = install(&pcb, fat32.open(path("/synced"), OpenMode::READ | OpenMode::WRITE).unwrap());
+    let data = [0xC3u8; 100];
+    assert_eq!(pcb.get_inner_locked().files[fd].clone().unwrap().write(&data).unwrap(), data.len());
+    assert!(!on_disk(&disk, &data));
+    assert_eq!(as_current(&pcb, || sys_syncfs(fd)), 0);
+    assert!(on_disk(&disk, &data));
+    assert_eq!(as_current(&pcb, || sys_syncfs(fd + 1)), -(ErrNo::BadFileDescriptor as isize));
+    verbose!("sync and syncfs test passed!");
+}
+
+/// getdents64 gives a symlink d_type LNK, fills no more than `len` with whole records, picks up where
+/// the last call stopped and returns 0 at the end of the directory
+pub fn getdents64_test() {
+    verbose!("Testing getdents64...");
+    let (_disk, fat32) = ram_fat32();
+    fat32.mkdir(path("/links")).unwrap();
+    let long_name = "a_file_name_longer_than_eight_dot_three.txt";
+    fat32.mkfile(path(&format!("/links/{}", long_name))).unwrap();
+    fat32.sym_link(path(&format!("/links/{}", long_name)), path("/links/link")).unwrap();
+    let dir = fat32.open(path("/links"), OpenMode::READ | OpenMode::DIR).unwrap();
+    let pcb = spawn();
+    let fd = install(&pcb, dir);
+    let buf = stack(&pcb, 1024);
+
+    // (d_type, name) of the records in the first `filled` bytes of buf
+    let records = |filled: usize| {
+        let mut bytes = vec![0u8; filled];
+        as_current(&pcb, || assert!(matches!(copy_from_user(&mut bytes, buf), Ok(_))));
+        let mut entries = Vec::new();
+        let mut at = 0;
+        while at < filled {
+            let reclen = u16::from_ne_bytes([bytes[at + 16], bytes[at + 17]]) as usize;
+            assert_eq!(reclen % 8, 0);
+            let name = &bytes[at + 19..at + reclen];
+            let len = name.iter().position(|c| *c == 0).unwrap();
+            entries.push((bytes[at + 18], String::from_utf8(name[..len].to_vec()).unwrap()));
+            at += reclen;
+        }
+        assert_eq!(at, filled);
+        entries
+    };
+    // not even the fixed part of a record fits
+    assert_eq!(as_current(&pcb, || sys_getdents64(fd, buf, 16)), -(ErrNo::InvalidArgument as isize));
+    let mut entries = Vec::new();
+    loop {
+        // just room for the record of the long name
+        let filled = as_current(&pcb, || sys_getdents64(fd, buf, 64));
+        assert!(filled >= 0 && filled <= 64);
+        if filled == 0 {
+            break;
+        }
+        entries.extend(records(filled as usize));
+    }
+    assert!(entries.contains(&(POSIXDType::LNK as u8, "link".to_string())));
+    assert!(entries.contains(&(POSIXDType::REG as u8, long_name.to_string())));
+    // still at the end
+    assert_eq!(as_current(&pcb, || sys_getdents64(fd, buf, 1024)), 0);
+    verbose!("getdents64 test passed!");
+}
+
+/// A char node made with the numbers of /dev/null reads end of file and swallows writes,
+/// making it twice is EEXIST and FAT32 refuses device nodes
+pub fn mknod_test() {
+    verbose!("Testing mknodat...");
+    let pcb = spawn();
+    let name = stack(&pcb, 64);
+    let null_dev = (1 << 8) | 3;
+    pcb.get_inner_locked().layout.write_user_data(name, b"/dev/selftest_null\0");
+    assert_eq!(as_current(&pcb, || sys_mknodat(AT_FDCWD as usize, name, 0o020666, null_dev)), 0);
+    let node = fs::open("/dev/selftest_null".to_string(), OpenMode::READ | OpenMode::WRITE).unwrap();
+    let mut buf = [0x5Au8; 16];
+    assert_eq!(node.read(&mut buf).unwrap(), 0);
+    assert_eq!(node.write(b"gone").unwrap(), 4);
+    assert_eq!(node.read(&mut buf).unwrap(), 0);
+    assert_eq!(as_current(&pcb, || sys_mknodat(AT_FDCWD as usize, name, 0o020666, null_dev)), -(ErrNo::FileExists as isize));
+    // only char and block nodes
+    pcb.get_inner_locked().layout.write_user_data(name, b"/dev/selftest_reg\0");
+    assert_eq!(as_current(&pcb, || sys_mknodat(AT_FDCWD as usize, name, 0o100666, 0)), -(ErrNo::OperationNotPermitted as isize));
+    fs::remove("/dev/selftest_null".to_string()).unwrap();
+    assert!(fs::open("/dev/selftest_null".to_string(), OpenMode::READ).is_err());
+
+    let (_disk, fat32) = ram_fat32();
+    mount_fs("/selftest_mknod".to_string(), fat32).unwrap();
+    pcb.get_inner_locked().layout.write_user_data(name, b"/selftest_mknod/null\0");
+    assert_eq!(as_current(&pcb, || sys_mknodat(AT_FDCWD as usize, name, 0o020666, null_dev)), -(ErrNo::OperationNotPermitted as isize));
+    unmount_fs("/selftest_mknod".to_string()).unwrap();
+    verbose!("mknodat test passed!");
+}
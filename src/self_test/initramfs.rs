@@ -0,0 +1,63 @@
+//! initramfs tests
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::fat32::path;
+use crate::fs::fs_impl::TmpFS;
+use crate::fs::{unpack, OpenMode, VirtualFileSystem};
+use crate::process::ErrNo;
+
+/// Append a newc member to `archive`
+fn newc(archive: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+    let fields = [0, mode, 0, 0, 1, 0, data.len() as u32, 0, 0, 0, 0, name.len() as u32 + 1, 0];
+    archive.extend_from_slice(b"070701");
+    for field in fields.iter() {
+        archive.extend_from_slice(format!("{:08X}", field).as_bytes());
+    }
+    archive.extend_from_slice(name.as_bytes());
+    archive.push(0);
+    archive.resize((archive.len() + 3) & !3, 0);
+    archive.extend_from_slice(data);
+    archive.resize((archive.len() + 3) & !3, 0);
+}
+
+/// Contents of `name` in `fs`
+fn read_all(fs: &TmpFS, name: &str) -> Vec<u8> {
+    let file = fs.open(path(name), OpenMode::READ).unwrap();
+    let mut buf = vec![0u8; file.poll().size as usize + 1];
+    let len = file.read(&mut buf).unwrap();
+    buf.truncate(len);
+    buf
+}
+
+/// A cpio archive is unpacked into a tmpfs, directories first then the files in them
+pub fn unpack_test() {
+    verbose!("Testing initramfs unpack...");
+    let mut archive = Vec::new();
+    newc(&mut archive, ".", 0o040755, b"");
+    newc(&mut archive, "bin", 0o040755, b"");
+    newc(&mut archive, "bin/init", 0o100755, b"\x7fELF not really");
+    newc(&mut archive, "etc", 0o040755, b"");
+    newc(&mut archive, "etc/motd", 0o100644, b"Welcome to OSHIT!\n");
+    newc(&mut archive, "etc/empty", 0o100644, b"");
+    newc(&mut archive, "dev/console", 0o020600, b"");
+    let entries = 7;
+    newc(&mut archive, "TRAILER!!!", 0, b"");
+
+    let tmpfs = TmpFS::new();
+    assert_eq!(unpack(&archive, &tmpfs).unwrap(), entries);
+    assert_eq!(read_all(&tmpfs, "/bin/init"), b"\x7fELF not really");
+    assert_eq!(read_all(&tmpfs, "/etc/motd"), b"Welcome to OSHIT!\n");
+    assert_eq!(read_all(&tmpfs, "/etc/empty"), b"");
+    let etc = tmpfs.open(path("/etc"), OpenMode::READ | OpenMode::DIR).unwrap();
+    let names: Vec<String> = etc.to_dir_file().unwrap().list().iter().map(|file| file.poll().name).collect();
+    assert_eq!(names, ["empty", "motd"]);
+    // special files are skipped
+    assert!(matches!(tmpfs.open(path("/dev"), OpenMode::READ), Err(ErrNo::NoSuchFileOrDirectory)));
+    assert!(matches!(tmpfs.remove(path("/etc")), Err(ErrNo::DirectoryNotEmpty)));
+
+    // a truncated archive is rejected
+    let tmpfs = TmpFS::new();
+    assert!(matches!(unpack(&archive[..archive.len() - 60], &tmpfs), Err(ErrNo::InvalidArgument)));
+    verbose!("initramfs unpack test passed!");
+}
@@ -0,0 +1,29 @@
+//! Tests of the PCB lock order checks
+use super::process::{as_current, spawn};
+use crate::process::check_lock;
+
+/// Locking the current process after another one, or the same one twice, is caught before it hangs
+pub fn lock_order_test() {
+    verbose!("Testing PCB lock order checks...");
+    let current = spawn();
+    let other = spawn();
+    as_current(&current, || {
+        assert!(check_lock(current.pid.0).is_ok());
+        assert!(check_lock(other.pid.0).is_ok());
+
+        // the right order: current first, then the others
+        let current_inner = current.lock_inner();
+        assert!(check_lock(current.pid.0).is_err());
+        let other_inner = other.lock_inner();
+        assert!(check_lock(other.pid.0).is_err());
+        drop(other_inner);
+        drop(current_inner);
+
+        // an inversion: current after another one is held
+        let other_inner = other.lock_inner();
+        assert_eq!(check_lock(current.pid.0), Err("PCB of current process locked after other processes"));
+        drop(other_inner);
+        assert!(check_lock(current.pid.0).is_ok());
+    });
+    verbose!("PCB lock order test passed!");
+}
@@ -0,0 +1,61 @@
+//! Loop device tests
+use alloc::sync::Arc;
+
+use super::fat32::{path, ram_fat32};
+use super::ram_disk::{RamDisk, SECTOR};
+use crate::drivers::{BlockDevice, LoopDevice};
+use crate::fs::{File, OpenMode, SeekOp, VirtualFileSystem};
+
+/// Blocks written through a loop device on a FAT32 file are in the file, and read back after reopening it
+pub fn loop_device_test() {
+    verbose!("Testing loop device...");
+    let (_disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/image")).unwrap();
+    let file: Arc<dyn File> = fat32.open(path("/image"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    let dev = LoopDevice::new(file.clone(), SECTOR);
+    assert_eq!(dev.block_cnt(), 0);
+    for block_id in 0..4 {
+        dev.write_block(block_id, &[block_id as u8 + 1; SECTOR]).unwrap();
+    }
+    assert_eq!(dev.block_cnt(), 4);
+    // past the end reads as zeros
+    let mut buf = [0xffu8; SECTOR];
+    dev.read_block(8, &mut buf).unwrap();
+    assert!(buf.iter().all(|&b| b == 0));
+    drop(dev);
+    drop(file);
+    let file: Arc<dyn File> = fat32.open(path("/image"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert_eq!(file.poll().size, 4 * SECTOR as u64);
+    let dev = LoopDevice::new(file.clone(), SECTOR);
+    for block_id in 0..4 {
+        dev.read_block(block_id, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == block_id as u8 + 1));
+    }
+    file.seek(SECTOR as isize, SeekOp::SET).unwrap();
+    let mut byte = [0u8; 1];
+    assert_eq!(file.read(&mut byte).unwrap(), 1);
+    assert_eq!(byte[0], 2);
+    verbose!("Loop device test passed!");
+}
+
+/// Each transfer lands at its own block, and the file cursor is left where it was
+pub fn loop_cursor_test() {
+    verbose!("Testing loop device cursor handling...");
+    let disk = RamDisk::new(vec![0u8; 8 * SECTOR]).unwrap();
+    let dev = LoopDevice::new(disk.clone(), SECTOR);
+    let block = |n: usize| disk.contents()[n * SECTOR..(n + 1) * SECTOR].to_vec();
+    disk.seek(3, SeekOp::SET).unwrap();
+    dev.write_block(5, &[5u8; SECTOR]).unwrap();
+    dev.write_block(2, &[2u8; SECTOR]).unwrap();
+    assert_eq!(disk.get_cursor().unwrap(), 3);
+    assert!(block(5).iter().all(|&b| b == 5));
+    assert!(block(2).iter().all(|&b| b == 2));
+    dev.clear_block(5).unwrap();
+    assert!(block(5).iter().all(|&b| b == 0));
+    assert!(block(2).iter().all(|&b| b == 2));
+    let mut buf = [0u8; SECTOR];
+    dev.read_block(2, &mut buf).unwrap();
+    assert!(buf.iter().all(|&b| b == 2));
+    assert_eq!(disk.get_cursor().unwrap(), 3);
+    verbose!("Loop device cursor handling test passed!");
+}
@@ -0,0 +1,485 @@
+//! Tests of the memory management
+use alloc::alloc::{alloc, dealloc};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::mem::transmute;
+use spin::Mutex;
+
+use super::process::{as_current, spawn};
+use super::ram_disk::RamDisk;
+use crate::config::{ELF_CACHE_SHARE, FRAME_LOW_WATERMARK, KERNEL_HEAP_SIZE, PAGE_SIZE};
+use crate::memory::{alloc_frame, free_frames, kernel_heap_capacity, kernel_heap_peak, kernel_heap_used, mem_end, reclaimed_frames, take_heap_oom_victim, total_frames};
+use crate::memory::{kernel_heap_allocs, set_kernel_heap_growable, slab_bytes, slab_hits};
+use crate::fs::File;
+use crate::memory::{copy_from_user, copy_to_user, zero_ppn};
+use crate::syscall::{sys_mmap, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+use crate::memory::{flush_icache, SharedFrame, PageTable, PhysPageNum, PTEFlags, KERNEL_MEM_LAYOUT, MEGAPAGE_PAGES};
+use crate::memory::{MapType, MemLayout, Segment, SegmentFlags, VMAFlags, VPNRange, VirtAddr, VirtPageNum};
+use crate::process::elf_cache::{ExecImage, ELF_CACHE};
+use crate::process::{enqueue, remove_proc_by_pid, CloneFlags, ErrNo, PCB_INNER_CACHE};
+use crate::utils::fdt;
+use crate::process::set_in_syscall;
+
+/// Heap usage follows allocations and frees byte for byte, and the peak keeps the high-water mark
+pub fn heap_accounting_test() {
+    verbose!("Testing kernel heap accounting...");
+    let used = kernel_heap_used();
+    let block: Vec<u8> = Vec::with_capacity(3 * PAGE_SIZE);
+    assert_eq!(kernel_heap_used(), used + 3 * PAGE_SIZE);
+    assert!(kernel_heap_peak() >= kernel_heap_used());
+    assert!(kernel_heap_used() <= KERNEL_HEAP_SIZE);
+    drop(block);
+    assert_eq!(kernel_heap_used(), used);
+    assert!(kernel_heap_peak() >= used + 3 * PAGE_SIZE);
+    verbose!("Kernel heap accounting test passed!");
+}
+
+/// A syscall that runs out of heap goes on with the reserve and only marks its own process to be killed,
+/// the kernel itself gets no reserve
+pub fn oom_reserve_test() {
+    verbose!("Testing kernel heap exhaustion in a syscall...");
+    let victim = spawn();
+    let bystander = spawn();
+    let used = kernel_heap_used();
+    let page = Layout::from_size_align(PAGE_SIZE, PAGE_SIZE).unwrap();
+    // growing would just take frames until there are none
+    let growable = set_kernel_heap_growable(false);
+
+    // take every block of a page or more, smaller ones are left for the logs
+    let mut blocks: Vec<(*mut u8, Layout)> = Vec::with_capacity(kernel_heap_capacity() / PAGE_SIZE);
+    let mut size = KERNEL_HEAP_SIZE;
+    while size >= PAGE_SIZE {
+        let layout = Layout::from_size_align(size, PAGE_SIZE).unwrap();
+        loop {
+            let ptr = unsafe { alloc(layout) };
+            if ptr.is_null() {
+                break;
+            }
+            blocks.push((ptr, layout));
+        }
+        size /= 2;
+    }
+    assert!(unsafe { alloc(page) }.is_null());
+
+    set_in_syscall(true);
+    let ptr = as_current(&victim, || unsafe { alloc(page) });
+    set_in_syscall(false);
+    assert!(!ptr.is_null());
+    assert!(!take_heap_oom_victim(bystander.pid.0));
+    assert!(take_heap_oom_victim(victim.pid.0));
+    assert!(!take_heap_oom_victim(victim.pid.0));
+
+    unsafe {
+        dealloc(ptr, page);
+        for (ptr, layout) in blocks.iter() {
+            dealloc(*ptr, *layout);
+        }
+    }
+    drop(blocks);
+    set_kernel_heap_growable(growable);
+    assert_eq!(kernel_heap_used(), used);
+    verbose!("Kernel heap exhaustion test passed!");
+}
+
+/// A block larger than the heap left gets frames added to the heap,
+/// with growing off it is a plain allocation failure
+pub fn heap_grow_test() {
+    verbose!("Testing kernel heap growth...");
+    let capacity = kernel_heap_capacity();
+    let layout = Layout::from_size_align(KERNEL_HEAP_SIZE, PAGE_SIZE).unwrap();
+    let ptr = unsafe { alloc(layout) };
+    assert!(!ptr.is_null());
+    assert!(kernel_heap_capacity() > capacity);
+    assert!(kernel_heap_used() <= kernel_heap_capacity());
+    unsafe { dealloc(ptr, layout) };
+
+    let growable = set_kernel_heap_growable(false);
+    let capacity = kernel_heap_capacity();
+    let huge = Layout::from_size_align(2 * capacity, PAGE_SIZE).unwrap();
+    assert!(unsafe { alloc(huge) }.is_null());
+    assert_eq!(kernel_heap_capacity(), capacity);
+    set_kernel_heap_growable(growable);
+    verbose!("Kernel heap growth test passed!");
+}
+
+/// Forking N times takes fewer heap allocations with the PCB object cache than without,
+/// and the slabs go back to the heap once the children are gone
+pub fn object_cache_test() {
+    verbose!("Testing object cache...");
+    const FORKS: usize = 32;
+    let parent = spawn();
+    let fork_allocs = || {
+        let allocs = kernel_heap_allocs();
+        let children: Vec<_> = (0..FORKS).map(|_| parent.fork(CloneFlags::empty()).unwrap()).collect();
+        let taken = kernel_heap_allocs() - allocs;
+        let slabs = slab_bytes();
+        parent.get_inner_locked().children.clear();
+        drop(children);
+        (taken, slabs)
+    };
+    let enabled = PCB_INNER_CACHE.set_enabled(false);
+    let (without, _) = fork_allocs();
+    PCB_INNER_CACHE.set_enabled(true);
+    let hits = slab_hits();
+    let (with, slabs) = fork_allocs();
+    PCB_INNER_CACHE.set_enabled(enabled);
+    assert!(with < without, "{} heap allocations with the cache, {} without", with, without);
+    assert!(slab_hits() - hits >= FORKS / 2);
+    // the children filled several slabs, all but one empty slab went back to the heap
+    assert!(slab_bytes() < slabs);
+    verbose!("Object cache test passed!");
+}
+
+/// Kernel address to run the code of `icache_flush_test()` from, nothing is mapped there
+const ICACHE_TEST_VA: usize = 0x7000_0000;
+
+/// Code written to a page, mapped executable and flushed runs, and so does code rewritten after it ran
+pub fn icache_flush_test() {
+    verbose!("Testing instruction cache flush...");
+    // li a0, 42; ret
+    const LI_A0_42: u32 = 0x02a0_0513;
+    const LI_A0_7: u32 = 0x0070_0513;
+    const RET: u32 = 0x0000_8067;
+    let frame = alloc_frame().unwrap();
+    let code = frame.ppn.page_ptr().as_mut_ptr() as *mut u32;
+    let vpn = VirtPageNum::from(VirtAddr::from(ICACHE_TEST_VA));
+    assert!(KERNEL_MEM_LAYOUT.lock().translate(vpn).map_or(true, |pte| !pte.valid()));
+    KERNEL_MEM_LAYOUT.lock().pagetable.map(vpn, frame.ppn, PTEFlags::R | PTEFlags::X).unwrap();
+    unsafe {
+        asm!("sfence.vma");
+        code.write_volatile(LI_A0_42);
+        code.add(1).write_volatile(RET);
+    }
+    flush_icache();
+    let run: extern "C" fn() -> usize = unsafe { transmute(ICACHE_TEST_VA) };
+    assert_eq!(run(), 42);
+    unsafe { code.write_volatile(LI_A0_7) };
+    flush_icache();
+    assert_eq!(run(), 7);
+    KERNEL_MEM_LAYOUT.lock().pagetable.unmap(vpn);
+    unsafe { asm!("sfence.vma") };
+    verbose!("Instruction cache flush test passed!");
+}
+
+/// Kernel address the pages of `tlb_flush_test()` are mapped at, nothing is mapped there
+const TLB_TEST_VA: usize = 0x7000_1000;
+
+/// A page is accessed right after its pte changed, a stale TLB entry would fault or reach the old frame
+pub fn tlb_flush_test() {
+    verbose!("Testing TLB flush on pte changes...");
+    let first = alloc_frame().unwrap();
+    let second = alloc_frame().unwrap();
+    first.ppn.page_ptr()[0] = 1;
+    second.ppn.page_ptr()[0] = 2;
+    let vpn = VirtPageNum::from(VirtAddr::from(TLB_TEST_VA));
+    let page = TLB_TEST_VA as *mut u8;
+    let mut kernel = KERNEL_MEM_LAYOUT.lock();
+    assert!(kernel.translate(vpn).map_or(true, |pte| !pte.valid()));
+    kernel.pagetable.map(vpn, first.ppn, PTEFlags::R).unwrap();
+    assert_eq!(unsafe { page.read_volatile() }, 1);
+    // the read-only entry is in the TLB now
+    kernel.pagetable.modify_access(vpn, PTEFlags::R | PTEFlags::W).unwrap();
+    unsafe { page.write_volatile(3) };
+    assert_eq!(first.ppn.page_ptr()[0], 3);
+    kernel.pagetable.unmap(vpn);
+    kernel.pagetable.map(vpn, second.ppn, PTEFlags::R).unwrap();
+    assert_eq!(unsafe { page.read_volatile() }, 2);
+    kernel.pagetable.unmap(vpn);
+    verbose!("TLB flush test passed!");
+}
+
+/// A page in a megapage is split out to be changed or unmapped, the rest of the megapage keeps its mapping
+pub fn megapage_split_test() {
+    verbose!("Testing megapage split...");
+    let mut pagetable = PageTable::new();
+    let vpn = VirtPageNum(3 * MEGAPAGE_PAGES);
+    let ppn = PhysPageNum(5 * MEGAPAGE_PAGES);
+    assert!(pagetable.map_mega(vpn, ppn, PTEFlags::R | PTEFlags::W).unwrap());
+    assert!(!pagetable.map_mega(vpn, ppn, PTEFlags::R).unwrap());
+    let mid = VirtPageNum(vpn.0 + 100);
+    assert_eq!(pagetable.translate(mid).unwrap().ppn().0, ppn.0 + 100);
+    // its pte is the one of the whole megapage
+    assert!(pagetable.walk(mid).is_none());
+    pagetable.modify_access(mid, PTEFlags::R).unwrap();
+    assert!(pagetable.walk(mid).is_some());
+    assert!(!pagetable.translate(mid).unwrap().writable());
+    pagetable.unmap(VirtPageNum(mid.0 + 1));
+    assert!(!pagetable.translate(VirtPageNum(mid.0 + 1)).unwrap().valid());
+    for &offset in &[0, 99, 100, 102, MEGAPAGE_PAGES - 1] {
+        let pte = pagetable.translate(VirtPageNum(vpn.0 + offset)).unwrap();
+        assert_eq!(pte.ppn().0, ppn.0 + offset);
+        assert_eq!(pte.writable(), offset != 100);
+    }
+    verbose!("Megapage split test passed!");
+}
+
+/// The dump lists a mapped page as a leaf with its flags and address, under the tables leading to it
+pub fn pagetable_dump_test() {
+    verbose!("Testing pagetable dump...");
+    let mut pagetable = PageTable::new();
+    let va = 0x1234_5000;
+    let ppn = PhysPageNum(0x8_0123);
+    pagetable.map(VirtAddr::from(va).into(), ppn, PTEFlags::R | PTEFlags::W | PTEFlags::U).unwrap();
+    pagetable.map_mega(VirtPageNum(MEGAPAGE_PAGES), PhysPageNum(MEGAPAGE_PAGES), PTEFlags::R | PTEFlags::X).unwrap();
+    let dump = pagetable.dump();
+    let lines: Vec<&str> = dump.lines().collect();
+    // title, root pte, level 1 pte, the leaf, the level 1 megapage leaf
+    assert_eq!(lines.len(), 5, "{}", dump);
+    assert!(lines[1].starts_with("[  0] -> ppn"));
+    assert!(lines[2].starts_with("    [  1] ppn 0x200 VR-X---- va 0x200000 2M"), "{}", dump);
+    assert!(lines[3].starts_with("    [145] -> ppn"), "{}", dump);
+    assert_eq!(lines[4], "        [325] ppn 0x80123 VRW-U--- va 0x12345000");
+    verbose!("Pagetable dump test passed!");
+}
+
+/// A frame shared by two segments outlives the first one dropped, and is freed with the last one
+pub fn shared_frame_test() {
+    verbose!("Testing shared frames...");
+    let start = VirtAddr::from(0x1000_0000);
+    let vpn = start.to_vpn();
+    let new_segment = || Segment::new(start, start + PAGE_SIZE, MapType::Anonymous, SegmentFlags::R | SegmentFlags::W | SegmentFlags::U, VMAFlags::empty(), None, 0);
+    let (mut first_pagetable, mut second_pagetable) = (PageTable::new(), PageTable::new());
+    let (mut first, mut second) = (new_segment(), new_segment());
+    let frame = alloc_frame().unwrap();
+    let ppn = frame.ppn;
+    ppn.page_ptr()[0] = 0x42;
+    let shared = SharedFrame::new(frame);
+    let flags = PTEFlags::R | PTEFlags::W | PTEFlags::U;
+    first.map_shared_frame(&mut first_pagetable, vpn, shared.clone(), flags).unwrap();
+    second.map_shared_frame(&mut second_pagetable, vpn, shared, flags).unwrap();
+    assert!(first.frames[&vpn].is_shared());
+    assert_eq!(second_pagetable.translate(vpn).unwrap().ppn(), ppn);
+
+    let free = free_frames();
+    drop(first);
+    assert_eq!(free_frames(), free);
+    assert!(!second.frames[&vpn].is_shared());
+    assert_eq!(ppn.page_ptr()[0], 0x42);
+    drop(second);
+    assert_eq!(free_frames(), free + 1);
+    verbose!("Shared frames test passed!");
+}
+
+/// Reading untouched anonymous pages maps the zero frame and takes no frames, writing a page takes exactly one.
+/// A CLONE_VM child can't share pages on the zero frame, the clone fails if there are no frames to give them their own
+pub fn zero_page_test() {
+    verbose!("Testing zero page...");
+    const PAGES: usize = 64;
+    let pcb = spawn();
+    let start = as_current(&pcb, || sys_mmap(VirtAddr::from(0), PAGES * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0));
+    assert!(start > 0);
+    let page = |idx: usize| VirtAddr::from(start as usize + idx * PAGE_SIZE);
+    let ppn = |idx: usize| pcb.get_inner_locked().layout.translate(page(idx).into()).unwrap().ppn();
+    let free = free_frames();
+    let rss = pcb.get_inner_locked().layout.resident_pages();
+    for idx in 0..PAGES {
+        pcb.get_inner_locked().layout.lazy_copy_vma(page(idx), VMAFlags::R).unwrap();
+        let mut buf = [0xffu8; 16];
+        as_current(&pcb, || assert!(matches!(copy_from_user(&mut buf, page(idx) + 8), Ok(16))));
+        assert_eq!(buf, [0; 16]);
+    }
+    assert!((0..PAGES).all(|idx| ppn(idx) == zero_ppn()));
+    assert_eq!(free_frames(), free);
+    assert_eq!(pcb.get_inner_locked().layout.resident_pages(), rss);
+
+    as_current(&pcb, || assert!(matches!(copy_to_user(page(5) + 1, &[0x42]), Ok(1))));
+    assert_eq!(free_frames(), free - 1);
+    assert_eq!(pcb.get_inner_locked().layout.resident_pages(), rss + 1);
+    assert!(ppn(5) != zero_ppn() && ppn(4) == zero_ppn() && ppn(6) == zero_ppn());
+    assert_eq!(ppn(5).page_ptr()[1], 0x42);
+    assert!(zero_ppn().page_ptr().iter().all(|byte| *byte == 0));
+
+    let mut hoard = Vec::new();
+    while let Some(frame) = alloc_frame() {
+        hoard.push(frame);
+    }
+    assert!(matches!(pcb.fork(CloneFlags::VM), Err(ErrNo::OutOfMemory)));
+    drop(hoard);
+    let child = pcb.fork(CloneFlags::VM).unwrap();
+    assert!((0..PAGES).all(|idx| ppn(idx) != zero_ppn()));
+    pcb.get_inner_locked().children.clear();
+    drop(child);
+    verbose!("Zero page test passed!");
+}
+
+/// The frame allocator, the identity map and the exec image cache are sized from the RAM found at boot,
+/// not from the 8 MiB of the k210
+pub fn detected_ram_test() {
+    verbose!("Testing memory sizing from detected RAM...");
+    extern "C" {
+        fn ekernel();
+    }
+    let end = mem_end();
+    if let Some(info) = fdt::board_info() {
+        assert_eq!(end, info.mem_end);
+    }
+    let first = (ekernel as usize + PAGE_SIZE - 1) / PAGE_SIZE;
+    assert_eq!(total_frames(), end / PAGE_SIZE - first);
+    if end > 0x8080_0000 {
+        assert!(total_frames() > 0x8080_0000 / PAGE_SIZE - first);
+    }
+    // the last byte of RAM is mapped, or this faults
+    unsafe { ((end - 1) as *const u8).read_volatile() };
+    assert_eq!(ELF_CACHE.lock().max_pages(), total_frames() / ELF_CACHE_SHARE);
+    verbose!("Memory sizing test passed!");
+}
+
+/// Put a segment over `[start, stop)` in `layout`, without mapping anything
+fn push_range(layout: &mut MemLayout, start: usize, stop: usize) {
+    let segment = Segment::new(VirtPageNum::from(start).into(), VirtPageNum::from(stop).into(), MapType::VMA, SegmentFlags::empty(), VMAFlags::R, None, 0);
+    layout.segments.push(Arc::new(Mutex::new(segment)));
+}
+
+/// If `[start, start + pages)` misses every segment of `layout`
+fn is_free(layout: &MemLayout, start: VirtPageNum, pages: usize) -> bool {
+    let range = VPNRange::new(start, VirtPageNum::from(start.0 + pages));
+    layout.segments.iter().all(|m_seg| !m_seg.lock().range.intersects(&range))
+}
+
+/// A segment at VPN 0 doesn't underflow the search for free space
+pub fn vpn0_space_test() {
+    verbose!("Testing continuous space search with a segment at VPN 0...");
+    let mut layout = MemLayout::new();
+    // the search starts right below the top of the mmap area
+    let top = layout.get_continuous_space(PAGE_SIZE).unwrap().0 + 1;
+    push_range(&mut layout, 0, 4);
+    push_range(&mut layout, top - 16, top);
+    let start = layout.get_continuous_space(8 * PAGE_SIZE).unwrap();
+    assert!(start.0 >= 4 && start.0 + 8 <= top);
+    assert!(is_free(&layout, start, 8));
+    // VPN 4 alone is left, too small for a page and the guard page above it
+    push_range(&mut layout, 5, top - 16);
+    assert!(layout.get_continuous_space(PAGE_SIZE).is_none());
+    drop(layout);
+    verbose!("Continuous space search with a segment at VPN 0 test passed!");
+}
+
+/// With many mappings one page apart, the space found is below all of them and overlaps none
+pub fn many_mappings_space_test() {
+    verbose!("Testing continuous space search with many mappings...");
+    const N: usize = 512;
+    let mut layout = MemLayout::new();
+    let top = layout.get_continuous_space(PAGE_SIZE).unwrap().0 + 1;
+    // pushed in no particular order, the search sorts them
+    for i in (0..N).map(|i| i * 7 % N) {
+        push_range(&mut layout, top - 2 * (i + 1), top - 2 * i - 1);
+    }
+    let start = layout.get_continuous_space(2 * PAGE_SIZE).unwrap();
+    assert!(is_free(&layout, start, 2));
+    assert!(start.0 + 2 < top - 2 * N);
+    drop(layout);
+    verbose!("Continuous space search with many mappings test passed!");
+}
+
+/// A map containing or contained in an existing one is rejected, not only one straddling its ends
+pub fn vma_overlap_test() {
+    verbose!("Testing VMA overlap checks...");
+    let file: Arc<dyn File> = RamDisk::new(vec![0u8; 4 * PAGE_SIZE]);
+    let mut layout = MemLayout::new();
+    let base = 0x1000_0000;
+    layout.add_vma(file.clone(), base.into(), VMAFlags::R, 0, 4 * PAGE_SIZE).unwrap();
+    // inside the existing one
+    assert!(matches!(layout.add_vma(file.clone(), (base + PAGE_SIZE).into(), VMAFlags::W, 0, PAGE_SIZE), Err(ErrNo::BadAddress)));
+    // around an existing one
+    let base = 0x2000_0000;
+    layout.add_vma(file.clone(), (base + PAGE_SIZE).into(), VMAFlags::R, 0, PAGE_SIZE).unwrap();
+    assert!(matches!(layout.add_vma(file.clone(), base.into(), VMAFlags::W, 0, 4 * PAGE_SIZE), Err(ErrNo::BadAddress)));
+    // the same range
+    assert!(matches!(layout.add_vma(file.clone(), (base + PAGE_SIZE).into(), VMAFlags::W, 0, PAGE_SIZE), Err(ErrNo::BadAddress)));
+    assert_eq!(layout.segments.len(), 2);
+    drop(layout);
+    verbose!("VMA overlap test passed!");
+}
+
+/// A fault in a file map reads the file at the offset the map was made with
+pub fn vma_offset_test() {
+    verbose!("Testing VMA file offset...");
+    let data: Vec<u8> = (0..3 * PAGE_SIZE).map(|i| (i / PAGE_SIZE + 1) as u8).collect();
+    let file: Arc<dyn File> = RamDisk::new(data);
+    let mut layout = MemLayout::new();
+    let base = 0x1000_0000;
+    layout.add_vma(file, base.into(), VMAFlags::R, PAGE_SIZE, 2 * PAGE_SIZE).unwrap();
+    layout.lazy_copy_vma((base + PAGE_SIZE).into(), VMAFlags::R).unwrap();
+    // the second page of the map is the third page of the file
+    let read: [u8; 8] = layout.read_user_data((base + PAGE_SIZE).into());
+    assert_eq!(read, [3u8; 8]);
+    drop(layout);
+    verbose!("VMA file offset test passed!");
+}
+
+/// Adjacent maps of a file at continuing offsets end up in one segment
+pub fn vma_merge_test() {
+    verbose!("Testing VMA merging...");
+    let data: Vec<u8> = (0..4 * PAGE_SIZE).map(|i| (i / PAGE_SIZE + 1) as u8).collect();
+    let file: Arc<dyn File> = RamDisk::new(data);
+    let mut layout = MemLayout::new();
+    let base = 0x1000_0000;
+    for i in 0..3 {
+        layout.add_vma(file.clone(), (base + i * PAGE_SIZE).into(), VMAFlags::R, i * PAGE_SIZE, PAGE_SIZE).unwrap();
+    }
+    assert_eq!(layout.segments.len(), 1);
+    {
+        let seg = layout.segments[0].lock();
+        assert_eq!((seg.range.get_start().0, seg.range.get_end().0), (base / PAGE_SIZE, base / PAGE_SIZE + 3));
+        assert_eq!(seg.offset, 0);
+    }
+    // the merged segment still reads each page from where it was mapped
+    layout.lazy_copy_vma((base + 2 * PAGE_SIZE).into(), VMAFlags::R).unwrap();
+    let read: [u8; 8] = layout.read_user_data((base + 2 * PAGE_SIZE).into());
+    assert_eq!(read, [3u8; 8]);
+    // the middle one mapped last bridges its two neighbours
+    let base = 0x2000_0000;
+    for i in [0, 2, 1].iter() {
+        layout.add_vma(file.clone(), (base + i * PAGE_SIZE).into(), VMAFlags::R, i * PAGE_SIZE, PAGE_SIZE).unwrap();
+    }
+    assert_eq!(layout.segments.len(), 2);
+    // a gap in the file offsets or other flags keep them apart
+    let base = 0x3000_0000;
+    layout.add_vma(file.clone(), base.into(), VMAFlags::R, 0, PAGE_SIZE).unwrap();
+    layout.add_vma(file.clone(), (base + PAGE_SIZE).into(), VMAFlags::R, 2 * PAGE_SIZE, PAGE_SIZE).unwrap();
+    layout.add_vma(file.clone(), (base + 2 * PAGE_SIZE).into(), VMAFlags::R | VMAFlags::W, 3 * PAGE_SIZE, PAGE_SIZE).unwrap();
+    assert_eq!(layout.segments.len(), 5);
+    drop(layout);
+    verbose!("VMA merging test passed!");
+}
+
+/// Going below the low watermark reclaims clean file pages and unused exec images,
+/// pages written by the kernel are dirty and stay
+pub fn low_watermark_reclaim_test() {
+    verbose!("Testing reclaim below the low watermark...");
+    let data: Vec<u8> = (0..2 * PAGE_SIZE).map(|i| (i / PAGE_SIZE + 1) as u8).collect();
+    let file: Arc<dyn File> = RamDisk::new(data);
+    let pcb = spawn();
+    let base = 0x1000_0000;
+    {
+        let mut inner = pcb.get_inner_locked();
+        inner.layout.add_vma(file.clone(), base.into(), VMAFlags::R | VMAFlags::W, 0, 2 * PAGE_SIZE).unwrap();
+        inner.layout.lazy_copy_vma(base.into(), VMAFlags::R).unwrap();
+        inner.layout.lazy_copy_vma((base + PAGE_SIZE).into(), VMAFlags::R).unwrap();
+        inner.layout.write_user_data((base + PAGE_SIZE).into(), &0x55u8);
+    }
+    enqueue(pcb.clone());
+    let image = Arc::new(ExecImage::load(&file, PAGE_SIZE).unwrap());
+    ELF_CACHE.lock().insert("/selftest/reclaim".into(), PAGE_SIZE as u64, 0, 0, image);
+    let reclaimed = reclaimed_frames();
+
+    let mut frames = Vec::new();
+    // crossing the watermark reclaims, which may lift us above it for a while
+    while free_frames() >= FRAME_LOW_WATERMARK {
+        frames.push(alloc_frame().unwrap());
+    }
+    // the clean page and the two frames of the image
+    assert!(reclaimed_frames() >= reclaimed + 3);
+    assert!(ELF_CACHE.lock().get("/selftest/reclaim", PAGE_SIZE as u64, 0, 0).is_none());
+    drop(frames);
+
+    let inner = pcb.get_inner_locked();
+    assert!(!inner.layout.translate(VirtAddr::from(base).into()).map_or(false, |pte| pte.valid()));
+    assert!(inner.layout.translate(VirtAddr::from(base + PAGE_SIZE).into()).map_or(false, |pte| pte.valid()));
+    let written: u8 = inner.layout.read_user_data((base + PAGE_SIZE).into());
+    assert_eq!(written, 0x55);
+    drop(inner);
+    remove_proc_by_pid(pcb.pid.0).unwrap();
+    verbose!("Low watermark reclaim test passed!");
+}
@@ -0,0 +1,133 @@
+//! Boot time self tests
+//! # Description
+//! Built with the `kernel_tests` feature, i.e. `make run KERNEL_TESTS=y`.
+//! Run after the file systems are mounted and before the first process, a failing test panics
+//! like the tests `memory::init()` runs.
+mod ram_disk;
+mod loop_device;
+mod memory;
+mod fdt;
+mod console;
+mod fat32;
+mod ext2;
+mod initramfs;
+mod fs_syscall;
+mod process;
+mod process_syscall;
+mod path;
+mod elf_cache;
+mod exec;
+mod signal;
+mod wait_queue;
+mod lock_order;
+mod time;
+mod procfs;
+mod trap;
+mod file_lock;
+
+pub fn run() {
+    info!("Running self tests...");
+    memory::heap_accounting_test();
+    memory::oom_reserve_test();
+    memory::heap_grow_test();
+    memory::object_cache_test();
+    memory::icache_flush_test();
+    memory::tlb_flush_test();
+    memory::megapage_split_test();
+    memory::pagetable_dump_test();
+    memory::shared_frame_test();
+    memory::zero_page_test();
+    memory::vpn0_space_test();
+    memory::many_mappings_space_test();
+    memory::vma_overlap_test();
+    memory::vma_offset_test();
+    memory::vma_merge_test();
+    memory::low_watermark_reclaim_test();
+    fdt::fdt_parse_test();
+    memory::detected_ram_test();
+    console::uart_console_test();
+    path::parse_path_test();
+    path::canonicalize_test();
+    fat32::cluster_bounds_test();
+    fat32::tmpfile_test();
+    fat32::dir_full_test();
+    fat32::disk_full_test();
+    fat32::fallocate_test();
+    fat32::lazy_zero_test();
+    fat32::panic_flush_test();
+    fat32::bdflush_test();
+    fat32::short_name_test();
+    fat32::utf8_name_test();
+    fat32::name_case_test();
+    fat32::dot_entries_test();
+    fat32::rmdir_test();
+    fat32::remove_tree_test();
+    fat32::statfs_test();
+    fat32::io_error_test();
+    fat32::write_order_test();
+    ext2::ext2_read_only_test();
+    initramfs::unpack_test();
+    fs_syscall::sendfile_test();
+    fs_syscall::iovec_fault_test();
+    fs_syscall::dup2_test();
+    fs_syscall::pipe2_flags_test();
+    fs_syscall::statx_test();
+    fs_syscall::fstatat_empty_path_test();
+    fs_syscall::sync_test();
+    fs_syscall::getdents64_test();
+    fs_syscall::mknod_test();
+    file_lock::flock_test();
+    file_lock::record_lock_test();
+    file_lock::fcntl_lock_test();
+    process_syscall::getcwd_test();
+    process_syscall::chdir_test();
+    process_syscall::mmap_fixed_test();
+    process_syscall::mmap_lazy_test();
+    process_syscall::mremap_test();
+    process_syscall::sysinfo_test();
+    process_syscall::oom_victim_test();
+    process_syscall::uaccess_test();
+    process_syscall::icache_syscalls_test();
+    process_syscall::process_vm_test();
+    process_syscall::ptrace_test();
+    process_syscall::single_step_test();
+    process_syscall::prctl_test();
+    process_syscall::hostname_test();
+    process_syscall::domainname_test();
+    process_syscall::reboot_test();
+    elf_cache::elf_cache_test();
+    exec::pie_load_test();
+    exec::interp_load_test();
+    exec::bss_zero_test();
+    exec::exec_stack_test();
+    exec::auxv_test();
+    exec::stack_guard_test();
+    signal::unblockable_test();
+    signal::disposition_test();
+    signal::restart_test();
+    signal::sigsuspend_test();
+    signal::siginfo_test();
+    signal::sigchld_info_test();
+    signal::pdeathsig_test();
+    signal::fault_signal_test();
+    signal::unaligned_access_test();
+    trap::fp_context_test();
+    trap::lazy_fp_test();
+    wait_queue::wait_queue_test();
+    wait_queue::pipe_wait_test();
+    lock_order::lock_order_test();
+    time::clock_nanosleep_test();
+    time::itimer_real_test();
+    time::setitimer_test();
+    time::idle_time_test();
+    procfs::uptime_test();
+    procfs::stat_test();
+    procfs::smaps_test();
+    procfs::status_rss_test();
+    procfs::fd_dir_test();
+    procfs::cmdline_environ_test();
+    loop_device::loop_device_test();
+    loop_device::loop_cursor_test();
+    crate::drivers::sdcard::crc_retry_test();
+    info!("Self tests passed.");
+}
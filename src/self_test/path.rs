@@ -0,0 +1,48 @@
+//! Path parsing tests
+use crate::fs::parse_path;
+
+/// Empty components and "." are dropped, a trailing "/" or "." means a directory
+pub fn parse_path_test() {
+    verbose!("Testing parse_path...");
+    // (path, components, is_abs, must_dir)
+    let cases: [(&str, &[&str], bool, bool); 8] = [
+        ("/", &[], true, true),
+        ("//", &[], true, true),
+        ("/a//b/", &["a", "b"], true, true),
+        ("//a/b", &["a", "b"], true, false),
+        ("a/./b", &["a", "b"], false, false),
+        ("./a", &["a"], false, false),
+        ("/a/b/.", &["a", "b"], true, true),
+        ("../a", &["..", "a"], false, false),
+    ];
+    for (path, components, is_abs, must_dir) in cases.iter() {
+        let parsed = parse_path(path).unwrap();
+        assert_eq!(parsed.path, *components, "parse_path({:?})", path);
+        assert_eq!(parsed.is_abs, *is_abs, "parse_path({:?})", path);
+        assert_eq!(parsed.must_dir, *must_dir, "parse_path({:?})", path);
+    }
+    verbose!("parse_path test passed!");
+}
+
+/// ".." goes up a level and stops at root, relative paths start from the cwd
+pub fn canonicalize_test() {
+    verbose!("Testing Path::canonicalize...");
+    let cwd = parse_path("/usr/lib").unwrap();
+    // (path, canonical path)
+    let cases: [(&str, &[&str]); 7] = [
+        ("..", &["usr"]),
+        ("../../..", &[]),
+        ("./a/../b", &["usr", "lib", "b"]),
+        ("/x/./y/..", &["x"]),
+        ("/..", &[]),
+        ("a/b/../c", &["usr", "lib", "a", "c"]),
+        ("a//b/", &["usr", "lib", "a", "b"]),
+    ];
+    for (path, components) in cases.iter() {
+        let canonical = parse_path(path).unwrap().canonicalize(&cwd);
+        assert_eq!(canonical.path, *components, "canonicalize({:?})", path);
+        assert!(canonical.is_abs, "canonicalize({:?})", path);
+    }
+    assert!(parse_path("../..").unwrap().canonicalize(&cwd).must_dir);
+    verbose!("Path::canonicalize test passed!");
+}
@@ -0,0 +1,83 @@
+//! A process to run syscalls for
+//! # Description
+//! The process is never scheduled. The tests make it the current process around the syscalls,
+//! which reach its memory through its page table.
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::fs::File;
+use crate::memory::VirtAddr;
+use crate::process::{ProcessControlBlock, PROCESSOR0};
+
+/// Where the code of the process is loaded
+pub const TEXT: usize = 0x10000;
+
+/// Entry point of the process, right after the program headers
+pub const ENTRY: usize = TEXT + 120;
+
+/// An address no segment covers
+pub const UNMAPPED: usize = 0x200000;
+
+/// A RISC-V ELF of type `e_type`, with a single R-X segment at TEXT covering the headers,
+/// whose entry loops on `j .`
+pub fn tiny_elf_of_type(e_type: u16) -> Vec<u8> {
+    let mut elf = vec![0u8; 64 + 56 + 4];
+    // ELF header
+    elf[0..4].copy_from_slice(b"\x7fELF");
+    elf[4] = 2; // 64 bit
+    elf[5] = 1; // little endian
+    elf[6] = 1;
+    elf[16..18].copy_from_slice(&e_type.to_le_bytes());
+    elf[18..20].copy_from_slice(&0xF3u16.to_le_bytes()); // RISC-V
+    elf[20..24].copy_from_slice(&1u32.to_le_bytes());
+    elf[24..32].copy_from_slice(&(ENTRY as u64).to_le_bytes());
+    elf[32..40].copy_from_slice(&64u64.to_le_bytes());
+    elf[52..54].copy_from_slice(&64u16.to_le_bytes());
+    elf[54..56].copy_from_slice(&56u16.to_le_bytes());
+    elf[56..58].copy_from_slice(&1u16.to_le_bytes());
+    elf[58..60].copy_from_slice(&64u16.to_le_bytes());
+    // PT_LOAD, R-X
+    elf[64..68].copy_from_slice(&1u32.to_le_bytes());
+    elf[68..72].copy_from_slice(&5u32.to_le_bytes());
+    elf[72..80].copy_from_slice(&0u64.to_le_bytes());
+    elf[80..88].copy_from_slice(&(TEXT as u64).to_le_bytes());
+    elf[88..96].copy_from_slice(&(TEXT as u64).to_le_bytes());
+    elf[96..104].copy_from_slice(&124u64.to_le_bytes());
+    elf[104..112].copy_from_slice(&124u64.to_le_bytes());
+    elf[112..120].copy_from_slice(&0x1000u64.to_le_bytes());
+    // j .
+    elf[120..124].copy_from_slice(&0x0000006fu32.to_le_bytes());
+    elf
+}
+
+/// A statically linked executable, see `tiny_elf_of_type`
+fn tiny_elf() -> Vec<u8> {
+    tiny_elf_of_type(2) // ET_EXEC
+}
+
+/// A new process, with the stdios open
+pub fn spawn() -> Arc<ProcessControlBlock> {
+    Arc::new(ProcessControlBlock::new(&tiny_elf(), "/selftest".to_string()))
+}
+
+/// Run `f` with `pcb` as the current process
+pub fn as_current<T>(pcb: &Arc<ProcessControlBlock>, f: impl FnOnce() -> T) -> T {
+    let prev = PROCESSOR0.set_current(Some(pcb.clone()));
+    let res = f();
+    PROCESSOR0.set_current(prev);
+    res
+}
+
+/// Scratch memory on the user stack, `n` bytes below its top
+pub fn stack(pcb: &Arc<ProcessControlBlock>, n: usize) -> VirtAddr {
+    let sp = pcb.get_inner_locked().get_trap_context().regs[2];
+    VirtAddr::from(sp - n)
+}
+
+/// Put a file in the fd table
+pub fn install(pcb: &Arc<ProcessControlBlock>, file: Arc<dyn File>) -> usize {
+    let mut inner = pcb.get_inner_locked();
+    inner.files.push(Some(file));
+    inner.files.len() - 1
+}
@@ -0,0 +1,589 @@
+//! Tests of the process syscalls, run for a test process
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use alloc::sync::Arc;
+use core::convert::TryInto;
+
+use super::fat32::{path, ram_fat32};
+use super::process::{as_current, spawn, stack};
+use super::procfs::read_proc;
+use crate::config::{PAGE_SIZE, SV39_LOW_END};
+use crate::memory::{copy_from_user, copy_to_user, free_frames, VirtAddr, VMAFlags};
+use crate::process::{adopt_orphans, enqueue, nr_processes, oom_kill, park, remove_proc_by_pid, select_victim, CloneFlags, ProcessStatus, ErrNo, PROC0};
+use crate::process::default_handlers::{SIGTRAP, SIGUSR1};
+use crate::process::ptrace::remove_step_breakpoint;
+use crate::fs::{mount_fs, sync_all, unmount_fs, OpenMode, VirtualFileSystem};
+use crate::syscall::{sys_process_vm_readv, sys_process_vm_writev};
+use crate::syscall::{sys_prctl, PR_GET_NAME, PR_GET_PDEATHSIG, PR_SET_NAME, PR_SET_PDEATHSIG};
+use crate::syscall::{sys_ptrace, sys_waitpid, PTRACE_CONT, PTRACE_GETREGS, PTRACE_PEEKDATA, PTRACE_POKEDATA, PTRACE_SINGLESTEP, PTRACE_TRACEME, WNOHANG};
+use crate::syscall::{sys_membarrier, sys_mprotect, MEMBARRIER_CMD_GLOBAL, MEMBARRIER_CMD_QUERY, PROT_EXEC};
+use crate::syscall::{sys_chdir, sys_clock_nanosleep, sys_getcwd, sys_gettimeofday, sys_info, sys_setdomainname, sys_sethostname, sys_sigsuspend, sys_uname, sys_mmap, sys_mremap, sys_munmap, sys_reboot, SysInfo};
+use crate::syscall::{MAP_ANONYMOUS, MAP_FIXED, MAP_PRIVATE, MREMAP_MAYMOVE, PROT_READ, PROT_WRITE};
+
+/// getcwd fails with ERANGE when the path and its NUL don't fit
+pub fn getcwd_test() {
+    verbose!("Testing getcwd...");
+    let pcb = spawn();
+    let buf = stack(&pcb, 64);
+    pcb.get_inner_locked().path = "/tmp/".to_string();
+    assert_eq!(as_current(&pcb, || sys_getcwd(buf, 4)), -(ErrNo::MathResultNotRepresentable as isize));
+    assert_eq!(as_current(&pcb, || sys_getcwd(buf, 5)), buf.0 as isize);
+    let cwd: [u8; 5] = pcb.get_inner_locked().layout.read_user_data(buf);
+    assert_eq!(&cwd, b"/tmp\0");
+    verbose!("getcwd test passed!");
+}
+
+/// chdir resolves "." and ".." against the cwd, and refuses what is not a directory
+pub fn chdir_test() {
+    verbose!("Testing chdir...");
+    let pcb = spawn();
+    let path = stack(&pcb, 64);
+    let chdir = |dir: &[u8]| {
+        pcb.get_inner_locked().layout.get_user_buffer(path, dir.len()).write_bytes(dir, 0);
+        as_current(&pcb, || sys_chdir(path))
+    };
+    assert_eq!(chdir(b"dev/block/../\0"), 0);
+    assert_eq!(pcb.get_inner_locked().path, "/dev/");
+    assert_eq!(chdir(b"./block/./\0"), 0);
+    assert_eq!(pcb.get_inner_locked().path, "/dev/block/");
+    assert_eq!(chdir(b"../../..\0"), 0);
+    assert_eq!(pcb.get_inner_locked().path, "/");
+    assert!(chdir(b"/dev/tty0\0") < 0);
+    assert!(chdir(b"/no_such_dir\0") < 0);
+    assert_eq!(pcb.get_inner_locked().path, "/");
+    verbose!("chdir test passed!");
+}
+
+/// A MAP_FIXED mapping replaces the pages it overlaps and leaves the rest of the old mapping,
+/// and bad fixed ranges fail with EINVAL
+pub fn mmap_fixed_test() {
+    verbose!("Testing mmap MAP_FIXED...");
+    let pcb = spawn();
+    let prot = PROT_READ | PROT_WRITE;
+    let anon = MAP_PRIVATE | MAP_ANONYMOUS;
+    let mmap = |start: usize, len: usize, flags: usize| {
+        as_current(&pcb, || sys_mmap(VirtAddr::from(start), len, prot, flags, usize::MAX, 0))
+    };
+    let old = mmap(0, 2 * PAGE_SIZE, anon) as usize;
+    for page in 0..2 {
+        pcb.get_inner_locked().layout.write_user_data(VirtAddr::from(old + page * PAGE_SIZE), &0xaau8);
+    }
+    assert_eq!(mmap(old + PAGE_SIZE, PAGE_SIZE, anon | MAP_FIXED), (old + PAGE_SIZE) as isize);
+    let kept: u8 = pcb.get_inner_locked().layout.read_user_data(VirtAddr::from(old));
+    let replaced: u8 = pcb.get_inner_locked().layout.read_user_data(VirtAddr::from(old + PAGE_SIZE));
+    assert_eq!((kept, replaced), (0xaa, 0));
+
+    let einval = -(ErrNo::InvalidArgument as isize);
+    assert_eq!(mmap(old + 1, PAGE_SIZE, anon | MAP_FIXED), einval);
+    assert_eq!(mmap(old, 0, anon | MAP_FIXED), einval);
+    // wraps around
+    assert_eq!(mmap(usize::MAX - PAGE_SIZE + 1, 2 * PAGE_SIZE, anon | MAP_FIXED), einval);
+    // into the hole between the two halves
+    assert_eq!(mmap(SV39_LOW_END - PAGE_SIZE, 2 * PAGE_SIZE, anon | MAP_FIXED), einval);
+    // over the user stack
+    let sp = pcb.get_inner_locked().get_trap_context().regs[2];
+    assert_eq!(mmap(sp & !(PAGE_SIZE - 1), PAGE_SIZE, anon | MAP_FIXED), einval);
+    verbose!("mmap MAP_FIXED test passed!");
+}
+
+/// Anonymous mappings take frames only for the pages touched, and give them back on munmap
+pub fn mmap_lazy_test() {
+    verbose!("Testing lazy anonymous mmap...");
+    let pcb = spawn();
+    let len = 64 << 20;
+    let before = free_frames();
+    let start = as_current(&pcb, || {
+        sys_mmap(VirtAddr::from(0), len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0)
+    }) as usize;
+    let mapped = free_frames();
+    // page tables only
+    assert!(before - mapped < 64, "{} frames taken by mmap", before - mapped);
+
+    for page in [0, 1000, len / PAGE_SIZE - 1].iter() {
+        let addr = VirtAddr::from(start + page * PAGE_SIZE);
+        let zero: u64 = pcb.get_inner_locked().layout.read_user_data(addr);
+        assert_eq!(zero, 0);
+        pcb.get_inner_locked().layout.write_user_data(addr, &0x55u64);
+    }
+    assert_eq!(mapped - free_frames(), 3);
+    let page: u64 = pcb.get_inner_locked().layout.read_user_data(VirtAddr::from(start + 1000 * PAGE_SIZE));
+    assert_eq!(page, 0x55);
+
+    assert_eq!(as_current(&pcb, || sys_munmap(VirtAddr::from(start), len)), 0);
+    assert_eq!(free_frames(), mapped);
+    verbose!("Lazy anonymous mmap test passed!");
+}
+
+/// mremap grows a mapping in place when the pages after it are free, and moves it with
+/// MREMAP_MAYMOVE otherwise, keeping the contents either way
+pub fn mremap_test() {
+    verbose!("Testing mremap...");
+    let pcb = spawn();
+    let mmap = |start: usize, len: usize, flags: usize| {
+        as_current(&pcb, || {
+            sys_mmap(VirtAddr::from(start), len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS | flags, usize::MAX, 0)
+        }) as usize
+    };
+    let mremap = |start: usize, old_len: usize, new_len: usize, flags: usize| {
+        as_current(&pcb, || sys_mremap(VirtAddr::from(start), old_len, new_len, flags, VirtAddr::from(0)))
+    };
+    let read = |addr: usize| -> u64 { pcb.get_inner_locked().layout.read_user_data(VirtAddr::from(addr)) };
+    let write = |addr: usize, val: u64| pcb.get_inner_locked().layout.write_user_data(VirtAddr::from(addr), &val);
+
+    // make room after the mapping, then grow into it
+    let start = mmap(0, 4 * PAGE_SIZE, 0);
+    assert_eq!(as_current(&pcb, || sys_munmap(VirtAddr::from(start + PAGE_SIZE), 3 * PAGE_SIZE)), 0);
+    write(start, 1);
+    assert_eq!(mremap(start, PAGE_SIZE, 2 * PAGE_SIZE, 0), start as isize);
+    write(start + PAGE_SIZE, 2);
+    assert_eq!((read(start), read(start + PAGE_SIZE)), (1, 2));
+
+    // block the pages after it
+    assert_eq!(mmap(start + 2 * PAGE_SIZE, PAGE_SIZE, MAP_FIXED), start + 2 * PAGE_SIZE);
+    assert_eq!(mremap(start, 2 * PAGE_SIZE, 3 * PAGE_SIZE, 0), -(ErrNo::OutOfMemory as isize));
+    let moved = mremap(start, 2 * PAGE_SIZE, 3 * PAGE_SIZE, MREMAP_MAYMOVE) as usize;
+    assert_ne!(moved, start);
+    assert_eq!((read(moved), read(moved + PAGE_SIZE), read(moved + 2 * PAGE_SIZE)), (1, 2, 0));
+    // the old range is gone
+    assert!(pcb.get_inner_locked().layout.try_get_user_buffer(VirtAddr::from(start), 8).is_err());
+    verbose!("mremap test passed!");
+}
+
+/// sysinfo reports the RAM and counts the processes alive
+pub fn sysinfo_test() {
+    verbose!("Testing sysinfo...");
+    let pcb = spawn();
+    let other = spawn();
+    let buf = stack(&pcb, 256);
+    let sysinfo = || {
+        assert_eq!(as_current(&pcb, || sys_info(buf)), 0);
+        let info: SysInfo = pcb.get_inner_locked().layout.read_user_data(buf);
+        info
+    };
+
+    let info = sysinfo();
+    assert!(info.totalram > 0);
+    assert!(info.freeram > 0 && info.freeram <= info.totalram);
+    assert_eq!(info.mem_unit, 1);
+    assert_eq!(info.totalswap, 0);
+    let alive = info.procs;
+    assert_eq!(alive as usize, as_current(&pcb, nr_processes));
+    enqueue(other.clone());
+    assert_eq!(sysinfo().procs, alive + 1);
+    remove_proc_by_pid(other.pid.0).unwrap();
+    assert_eq!(sysinfo().procs, alive);
+    verbose!("sysinfo test passed!");
+}
+
+/// The OOM killer picks the process with the largest resident set
+pub fn oom_victim_test() {
+    verbose!("Testing OOM victim selection...");
+    let procs: Vec<_> = [2usize, 12, 5].iter().map(|&pages| {
+        let pcb = spawn();
+        let start = as_current(&pcb, || {
+            sys_mmap(VirtAddr::from(0), pages * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0)
+        });
+        assert!(start > 0);
+        for page in 0..pages {
+            pcb.get_inner_locked().layout.lazy_copy_vma((start as usize + page * PAGE_SIZE).into(), VMAFlags::W).unwrap();
+        }
+        enqueue(pcb.clone());
+        pcb
+    }).collect();
+    let (victim, dying) = select_victim().unwrap();
+    assert_eq!(victim.pid.0, procs[1].pid.0);
+    assert!(!dying);
+    // off by default, the allocation just fails
+    assert!(!oom_kill());
+    for pcb in procs.iter() {
+        remove_proc_by_pid(pcb.pid.0).unwrap();
+    }
+    verbose!("OOM victim selection test passed!");
+}
+
+/// reboot refuses bad magic numbers and commands without doing anything,
+/// and what it does before the reset, syncing every mounted file system, reaches the disk
+pub fn reboot_test() {
+    verbose!("Testing reboot...");
+    const MAGIC1: u32 = 0xfee1dead;
+    const MAGIC2: u32 = 672274793;
+    const CMD_CAD_ON: u32 = 0x89ABCDEF;
+    const CMD_POWER_OFF: u32 = 0x4321FEDC;
+    let pcb = spawn();
+    let einval = -(ErrNo::InvalidArgument as isize);
+    assert_eq!(as_current(&pcb, || sys_reboot(MAGIC1, 1234, CMD_POWER_OFF, VirtAddr(0))), einval);
+    assert_eq!(as_current(&pcb, || sys_reboot(0, MAGIC2, CMD_POWER_OFF, VirtAddr(0))), einval);
+    assert_eq!(as_current(&pcb, || sys_reboot(MAGIC1, MAGIC2, 0x1234, VirtAddr(0))), einval);
+    assert_eq!(as_current(&pcb, || sys_reboot(MAGIC1, MAGIC2, CMD_CAD_ON, VirtAddr(0))), 0);
+
+    // the file is kept open, closing it would sync the fs
+    let (disk, fat32) = ram_fat32();
+    let data = [0x5Au8; 100];
+    fat32.mkfile(path("/dirty")).unwrap();
+    let file = fat32.open(path("/dirty"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert_eq!(file.write(&data).unwrap(), data.len());
+    let on_disk = |disk: &[u8]| disk.windows(data.len()).any(|window| window == data);
+    assert!(!on_disk(&disk.contents()));
+    mount_fs("/selftest_reboot".to_string(), fat32).unwrap();
+    sync_all();
+    unmount_fs("/selftest_reboot".to_string()).unwrap();
+    assert!(on_disk(&disk.contents()));
+    drop(file);
+    verbose!("reboot test passed!");
+}
+
+/// Checked user copies stop with EFAULT at an unmapped page, after copying what comes before it,
+/// and syscalls using them fail instead of panicking
+pub fn uaccess_test() {
+    verbose!("Testing checked user copies...");
+    let pcb = spawn();
+    let start = as_current(&pcb, || sys_mmap(VirtAddr::from(0), 2 * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0));
+    assert!(start > 0);
+    let start = start as usize;
+    let hole = VirtAddr::from(start + PAGE_SIZE);
+    assert_eq!(as_current(&pcb, || sys_munmap(hole, PAGE_SIZE)), 0);
+    let straddle = VirtAddr::from(start + PAGE_SIZE - 8);
+    as_current(&pcb, || {
+        assert!(matches!(copy_to_user(straddle, &[0x5a; 16]), Err(ErrNo::BadAddress)));
+        let mut buf = [0u8; 16];
+        assert!(matches!(copy_from_user(&mut buf, straddle), Err(ErrNo::BadAddress)));
+        // the part before the hole was copied
+        assert!(matches!(copy_from_user(&mut buf[..8], straddle), Ok(8)));
+        assert_eq!(buf[..8], [0x5a; 8]);
+    });
+    let efault = -(ErrNo::BadAddress as isize);
+    let across = VirtAddr::from(start + PAGE_SIZE - 4);
+    assert_eq!(as_current(&pcb, || sys_gettimeofday(across)), efault);
+    assert_eq!(as_current(&pcb, || sys_uname(across)), efault);
+    assert_eq!(as_current(&pcb, || sys_clock_nanosleep(0, 0, across, VirtAddr::from(0))), efault);
+    assert_eq!(as_current(&pcb, || sys_sigsuspend(across)), efault);
+    verbose!("Checked user copies test passed!");
+}
+
+/// mprotect makes code written to a page executable, membarrier takes the commands it reports
+pub fn icache_syscalls_test() {
+    verbose!("Testing mprotect to executable and membarrier...");
+    let pcb = spawn();
+    let start = as_current(&pcb, || sys_mmap(VirtAddr::from(0), PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0));
+    assert!(start > 0);
+    let start = VirtAddr::from(start as usize);
+    // li a0, 42; ret
+    let code = [0x13, 0x05, 0xa0, 0x02, 0x67, 0x80, 0x00, 0x00];
+    as_current(&pcb, || assert!(matches!(copy_to_user(start, &code), Ok(8))));
+    assert_eq!(as_current(&pcb, || sys_mprotect(start, PAGE_SIZE, PROT_READ | PROT_EXEC)), 0);
+    let pte = pcb.get_inner_locked().layout.translate(start.into()).unwrap();
+    assert!(pte.executable() && !pte.writable());
+    let mut read = [0u8; 8];
+    as_current(&pcb, || assert!(matches!(copy_from_user(&mut read, start), Ok(8))));
+    assert_eq!(read, code);
+
+    let supported = sys_membarrier(MEMBARRIER_CMD_QUERY, 0);
+    assert!(supported > 0 && supported as usize & MEMBARRIER_CMD_GLOBAL != 0);
+    assert_eq!(sys_membarrier(MEMBARRIER_CMD_GLOBAL, 0), 0);
+    let einval = -(ErrNo::InvalidArgument as isize);
+    assert_eq!(sys_membarrier(MEMBARRIER_CMD_GLOBAL, 1), einval);
+    assert_eq!(sys_membarrier(1 << 20, 0), einval);
+    verbose!("mprotect to executable and membarrier test passed!");
+}
+
+/// A parent reads a variable from the memory of its child and writes it back, a copy stopping at an unmapped page
+/// returns what was moved before it, and a process with another uid or no relation to the target is refused
+pub fn process_vm_test() {
+    verbose!("Testing process_vm_readv/writev...");
+    let parent = spawn();
+    let area = as_current(&parent, || sys_mmap(VirtAddr::from(0), 3 * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0));
+    assert!(area > 0);
+    let area = area as usize;
+    // page 0 holds the iovecs and the local buffer, the variable is in page 1
+    let (local_iov, remote_iov, buffer, variable) = (area, area + 16, area + 256, area + PAGE_SIZE);
+    let value = 0x1234_5678_9abc_def0u64.to_le_bytes();
+    as_current(&parent, || assert!(matches!(copy_to_user(VirtAddr::from(variable), &value), Ok(8))));
+    let child = parent.fork(CloneFlags::empty()).unwrap();
+    enqueue(child.clone());
+    let pid = child.pid.0;
+    // the copy in the parent is gone, what is read must come from the child
+    as_current(&parent, || assert!(matches!(copy_to_user(VirtAddr::from(variable), &[0; 8]), Ok(8))));
+    let set_iovs = |remote: usize, len: usize| as_current(&parent, || {
+        let iovs = [buffer, len, remote, len];
+        let bytes: Vec<u8> = iovs.iter().flat_map(|word| word.to_le_bytes().to_vec()).collect();
+        assert!(matches!(copy_to_user(VirtAddr::from(local_iov), &bytes), Ok(32)));
+    });
+    let readv = || as_current(&parent, || sys_process_vm_readv(pid, VirtAddr::from(local_iov), 1, VirtAddr::from(remote_iov), 1, 0));
+    let writev = || as_current(&parent, || sys_process_vm_writev(pid, VirtAddr::from(local_iov), 1, VirtAddr::from(remote_iov), 1, 0));
+    let read_buffer = || {
+        let mut read = [0u8; 8];
+        as_current(&parent, || assert!(matches!(copy_from_user(&mut read, VirtAddr::from(buffer)), Ok(8))));
+        read
+    };
+
+    set_iovs(variable, 8);
+    assert_eq!(readv(), 8);
+    assert_eq!(read_buffer(), value);
+    as_current(&parent, || assert!(matches!(copy_to_user(VirtAddr::from(buffer), &[0x11; 8]), Ok(8))));
+    assert_eq!(writev(), 8);
+    let mut written = [0u8; 8];
+    assert!(matches!(child.get_inner_locked().layout.copy_from_user(&mut written, VirtAddr::from(variable)), Ok(8)));
+    assert_eq!(written, [0x11; 8]);
+
+    // only the 4 bytes before the hole are moved
+    let hole = area + 2 * PAGE_SIZE;
+    assert_eq!(as_current(&child, || sys_munmap(VirtAddr::from(hole), PAGE_SIZE)), 0);
+    set_iovs(hole - 4, 8);
+    assert_eq!(readv(), 4);
+    set_iovs(hole, 8);
+    assert_eq!(readv(), -(ErrNo::BadAddress as isize));
+
+    set_iovs(variable, 8);
+    let eperm = -(ErrNo::OperationNotPermitted as isize);
+    parent.get_inner_locked().uid = 1000;
+    assert_eq!(readv(), eperm);
+    child.get_inner_locked().uid = 1000;
+    assert_eq!(readv(), 8);
+    let stranger = spawn();
+    stranger.get_inner_locked().uid = 1000;
+    assert_eq!(as_current(&stranger, || sys_process_vm_readv(pid, VirtAddr::from(0), 0, VirtAddr::from(0), 0, 0)), eperm);
+    stranger.get_inner_locked().uid = 0;
+    assert_eq!(as_current(&stranger, || sys_process_vm_readv(pid, VirtAddr::from(0), 0, VirtAddr::from(0), 0, 0)), 0);
+
+    remove_proc_by_pid(pid).unwrap();
+    parent.get_inner_locked().children.clear();
+    verbose!("process_vm_readv/writev test passed!");
+}
+
+/// A child traced with PTRACE_TRACEME is reported by waitpid when it stops, its registers and memory are read at the stop
+/// and PTRACE_CONT puts it back on the run queue. When the tracer dies, PROC0 adopts it detached and running.
+pub fn ptrace_test() {
+    verbose!("Testing ptrace...");
+    let parent = spawn();
+    let area = as_current(&parent, || sys_mmap(VirtAddr::from(0), PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0));
+    assert!(area > 0);
+    let area = area as usize;
+    // the registers and the wait status go to the parent, the variable is read from the child
+    let (regs, status, variable) = (area, area + 256, area + 512);
+    as_current(&parent, || assert!(matches!(copy_to_user(VirtAddr::from(variable), &0x55aau64.to_le_bytes()), Ok(8))));
+    let child = parent.fork(CloneFlags::empty()).unwrap();
+    let pid = child.pid.0;
+    enqueue(child.clone());
+    let ptrace = |request: usize, addr: usize, data: usize| as_current(&parent, || sys_ptrace(request, pid, VirtAddr::from(addr), data));
+
+    assert_eq!(as_current(&child, || sys_ptrace(PTRACE_TRACEME, 0, VirtAddr::from(0), 0)), 0);
+    assert_eq!(as_current(&child, || sys_ptrace(PTRACE_TRACEME, 0, VirtAddr::from(0), 0)), -(ErrNo::OperationNotPermitted as isize));
+    // only a stopped tracee can be looked at
+    assert_eq!(ptrace(PTRACE_GETREGS, 0, regs), -(ErrNo::NoSuchProcess as isize));
+
+    // the tracee stops the way trap_return stops it on a signal
+    let stop = || {
+        let tracee = remove_proc_by_pid(pid).unwrap();
+        let mut tracee_inner = tracee.get_inner_locked();
+        tracee_inner.status = ProcessStatus::Stopped;
+        tracee_inner.stop_report = Some(SIGTRAP);
+        drop(tracee_inner);
+        park(tracee);
+    };
+    stop();
+    {
+        let trap_context = child.get_trap_context();
+        trap_context.sepc = 0x1000;
+        trap_context.regs[10] = 42;
+    }
+    // reported without WUNTRACED
+    assert_eq!(as_current(&parent, || sys_waitpid(pid as isize, VirtAddr::from(status), WNOHANG)), pid as isize);
+    let mut wstatus = [0u8; 4];
+    as_current(&parent, || assert!(matches!(copy_from_user(&mut wstatus, VirtAddr::from(status)), Ok(4))));
+    assert_eq!(i32::from_le_bytes(wstatus), ((SIGTRAP as i32) << 8) | 0x7f);
+
+    assert_eq!(ptrace(PTRACE_GETREGS, 0, regs), 0);
+    let mut words = [0u8; 32 * 8];
+    as_current(&parent, || assert!(matches!(copy_from_user(&mut words, VirtAddr::from(regs)), Ok(256))));
+    let reg = |i: usize| usize::from_le_bytes(words[i * 8..i * 8 + 8].try_into().unwrap());
+    assert_eq!(reg(0), 0x1000);
+    assert_eq!(reg(10), 42);
+
+    as_current(&parent, || assert!(matches!(copy_to_user(VirtAddr::from(variable), &[0; 8]), Ok(8))));
+    assert_eq!(ptrace(PTRACE_PEEKDATA, variable, regs), 0);
+    let mut word = [0u8; 8];
+    as_current(&parent, || assert!(matches!(copy_from_user(&mut word, VirtAddr::from(regs)), Ok(8))));
+    assert_eq!(u64::from_le_bytes(word), 0x55aa);
+    assert_eq!(ptrace(PTRACE_POKEDATA, variable, 0x77), 0);
+    assert!(matches!(child.get_inner_locked().layout.copy_from_user(&mut word, VirtAddr::from(variable)), Ok(8)));
+    assert_eq!(u64::from_le_bytes(word), 0x77);
+
+    assert_eq!(ptrace(PTRACE_CONT, 0, 0), 0);
+    assert!(child.get_inner_locked().status == ProcessStatus::Ready);
+    assert_eq!(ptrace(PTRACE_GETREGS, 0, regs), -(ErrNo::NoSuchProcess as isize));
+
+    // the tracer exits with the tracee stopped
+    stop();
+    adopt_orphans(parent.pid.0, &mut parent.get_inner_locked());
+    {
+        let child_inner = child.get_inner_locked();
+        assert!(!child_inner.traced && child_inner.ptrace_pass.is_none());
+        assert!(child_inner.status == ProcessStatus::Ready && child_inner.stop_report.is_none());
+        assert!(Arc::ptr_eq(&child_inner.parent.as_ref().unwrap().upgrade().unwrap(), &PROC0));
+    }
+    assert!(parent.get_inner_locked().children.is_empty());
+
+    remove_proc_by_pid(pid).unwrap();
+    PROC0.get_inner_locked().children.retain(|proc| proc.pid.0 != pid);
+    verbose!("ptrace test passed!");
+}
+
+/// A traced child is single stepped through an addi, a taken jal and a not taken beq, each step plants the breakpoint
+/// on the next instruction to run and the trap puts the original one back. PTRACE_CONT takes out a breakpoint left behind.
+pub fn single_step_test() {
+    verbose!("Testing ptrace single step...");
+    let parent = spawn();
+    let child = parent.fork(CloneFlags::empty()).unwrap();
+    let pid = child.pid.0;
+    enqueue(child.clone());
+    let code = as_current(&child, || sys_mmap(VirtAddr::from(0), PAGE_SIZE, PROT_READ | PROT_WRITE | PROT_EXEC, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0));
+    assert!(code > 0);
+    let code = code as usize;
+    // addi a0, a0, 1; jal x0, 8; addi a0, a0, 1; beq a0, x0, 8; addi a0, a0, 1
+    let insns: [u32; 5] = [0x0015_0513, 0x0080_006f, 0x0015_0513, 0x0005_0463, 0x0015_0513];
+    let bytes: Vec<u8> = insns.iter().flat_map(|insn| insn.to_le_bytes().to_vec()).collect();
+    as_current(&child, || assert!(matches!(copy_to_user(VirtAddr::from(code), &bytes), Ok(20))));
+    assert_eq!(as_current(&child, || sys_ptrace(PTRACE_TRACEME, 0, VirtAddr::from(0), 0)), 0);
+    {
+        let trap_context = child.get_trap_context();
+        trap_context.sepc = code;
+        trap_context.regs[10] = 1;
+    }
+    // the tracee stops on the trap, the way trap_return stops it
+    let stop = || {
+        let tracee = remove_proc_by_pid(pid).unwrap();
+        let mut tracee_inner = tracee.get_inner_locked();
+        tracee_inner.status = ProcessStatus::Stopped;
+        tracee_inner.stop_report = Some(SIGTRAP);
+        drop(tracee_inner);
+        park(tracee);
+    };
+    let half_at = |addr: usize| {
+        let mut half = [0u8; 2];
+        assert!(matches!(child.get_inner_locked().layout.copy_from_user(&mut half, VirtAddr::from(addr)), Ok(2)));
+        u16::from_le_bytes(half)
+    };
+    let step = |next: usize| {
+        assert_eq!(as_current(&parent, || sys_ptrace(PTRACE_SINGLESTEP, pid, VirtAddr::from(0), 0)), 0);
+        assert_eq!(half_at(next), 0x9002);
+        // the instruction runs and the c.ebreak after it traps
+        child.get_trap_context().sepc = next;
+        assert_eq!(remove_step_breakpoint(&mut child.get_inner_locked()).map(|addr| addr.0), Some(next));
+        assert_eq!(half_at(next), bytes[next - code] as u16 | (bytes[next - code + 1] as u16) << 8);
+        stop();
+    };
+
+    stop();
+    step(code + 4);
+    step(code + 12);
+    // a0 is not 0, the branch falls through
+    step(code + 16);
+
+    // a breakpoint left by a step that didn't trap yet goes away on PTRACE_CONT
+    child.get_trap_context().sepc = code;
+    assert_eq!(as_current(&parent, || sys_ptrace(PTRACE_SINGLESTEP, pid, VirtAddr::from(0), 0)), 0);
+    assert_eq!(half_at(code + 4), 0x9002);
+    stop();
+    assert_eq!(as_current(&parent, || sys_ptrace(PTRACE_CONT, pid, VirtAddr::from(0), 0)), 0);
+    assert_eq!(half_at(code + 4), 0x006f);
+    assert!(child.get_inner_locked().step_breakpoint.is_none());
+
+    remove_proc_by_pid(pid).unwrap();
+    parent.get_inner_locked().children.clear();
+    verbose!("ptrace single step test passed!");
+}
+
+/// PR_SET_NAME cuts the name to TASK_COMM_LEN with the 0, PR_GET_NAME and /proc/self/comm read it back.
+/// PR_SET_PDEATHSIG keeps a valid signal for PR_GET_PDEATHSIG.
+pub fn prctl_test() {
+    verbose!("Testing prctl...");
+    let pcb = spawn();
+    let name = stack(&pcb, 64);
+    let got = stack(&pcb, 32);
+    assert_eq!(as_current(&pcb, || read_proc("/self/comm")), "selftest\n");
+    as_current(&pcb, || assert!(matches!(copy_to_user(name, b"a-very-long-thread-name\0"), Ok(24))));
+    assert_eq!(as_current(&pcb, || sys_prctl(PR_SET_NAME, name.0)), 0);
+    assert_eq!(as_current(&pcb, || sys_prctl(PR_GET_NAME, got.0)), 0);
+    let mut comm = [0u8; 16];
+    as_current(&pcb, || assert!(matches!(copy_from_user(&mut comm, got), Ok(16))));
+    assert_eq!(&comm, b"a-very-long-thr\0");
+    assert_eq!(as_current(&pcb, || read_proc("/self/comm")), "a-very-long-thr\n");
+    assert!(as_current(&pcb, || read_proc("/self/status")).starts_with("Name:\ta-very-long-thr\n"));
+    assert_eq!(as_current(&pcb, || sys_prctl(0x1234, 0)), -(ErrNo::InvalidArgument as isize));
+
+    let child = pcb.fork(CloneFlags::empty()).unwrap();
+    // fork copies the name but not the death signal
+    assert_eq!(child.get_inner_locked().comm_name(), "a-very-long-thr");
+    assert_eq!(as_current(&child, || sys_prctl(PR_SET_PDEATHSIG, 64)), -(ErrNo::InvalidArgument as isize));
+    assert_eq!(as_current(&child, || sys_prctl(PR_SET_PDEATHSIG, SIGUSR1)), 0);
+    let sig = stack(&child, 8);
+    assert_eq!(as_current(&child, || sys_prctl(PR_GET_PDEATHSIG, sig.0)), 0);
+    let mut word = [0u8; 4];
+    as_current(&child, || assert!(matches!(copy_from_user(&mut word, sig), Ok(4))));
+    assert_eq!(i32::from_le_bytes(word), SIGUSR1 as i32);
+    verbose!("prctl test passed!");
+}
+
+/// sethostname is for root and leaves room for the 0, uname reports the new name and the machine as riscv64
+pub fn hostname_test() {
+    verbose!("Testing sethostname...");
+    let pcb = spawn();
+    let name = stack(&pcb, 1024);
+    let uts = stack(&pcb, 512);
+    // sysname, nodename, release, version, machine, domainname
+    let field = |i: usize| {
+        let mut buf = [0u8; 65];
+        as_current(&pcb, || assert!(matches!(copy_from_user(&mut buf, VirtAddr::from(uts.0 + i * 65)), Ok(65))));
+        let len = buf.iter().position(|c| *c == 0).unwrap();
+        buf[..len].to_vec()
+    };
+    as_current(&pcb, || assert!(matches!(copy_to_user(name, &[b'h'; 65]), Ok(65))));
+    assert_eq!(as_current(&pcb, || sys_uname(uts)), 0);
+    let old = field(1);
+    assert_eq!(field(4), b"riscv64");
+
+    assert_eq!(as_current(&pcb, || sys_sethostname(name, 65)), -(ErrNo::InvalidArgument as isize));
+    assert_eq!(as_current(&pcb, || sys_sethostname(name, 64)), 0);
+    assert_eq!(as_current(&pcb, || sys_uname(uts)), 0);
+    assert_eq!(field(1), [b'h'; 64].to_vec());
+    pcb.get_inner_locked().uid = 1000;
+    as_current(&pcb, || assert!(matches!(copy_to_user(name, b"box"), Ok(3))));
+    assert_eq!(as_current(&pcb, || sys_sethostname(name, 3)), -(ErrNo::OperationNotPermitted as isize));
+    pcb.get_inner_locked().uid = 0;
+    assert_eq!(as_current(&pcb, || sys_sethostname(name, 3)), 0);
+    assert_eq!(as_current(&pcb, || sys_uname(uts)), 0);
+    assert_eq!(field(1), b"box");
+
+    as_current(&pcb, || assert!(matches!(copy_to_user(name, &old), Ok(_))));
+    assert_eq!(as_current(&pcb, || sys_sethostname(name, old.len())), 0);
+    verbose!("sethostname test passed!");
+}
+
+/// setdomainname takes names shorter than the utsname field, uname reports the domain name next to the hostname
+pub fn domainname_test() {
+    verbose!("Testing setdomainname...");
+    let pcb = spawn();
+    let name = stack(&pcb, 1024);
+    let uts = stack(&pcb, 512);
+    // nodename and domainname
+    let names = || {
+        assert_eq!(as_current(&pcb, || sys_uname(uts)), 0);
+        let field = |i: usize| {
+            let mut buf = [0u8; 65];
+            as_current(&pcb, || assert!(matches!(copy_from_user(&mut buf, VirtAddr::from(uts.0 + i * 65)), Ok(65))));
+            let len = buf.iter().position(|c| *c == 0).unwrap();
+            buf[..len].to_vec()
+        };
+        (field(1), field(5))
+    };
+    let (hostname, old) = names();
+    as_current(&pcb, || assert!(matches!(copy_to_user(name, b"example.org"), Ok(11))));
+    assert_eq!(as_current(&pcb, || sys_setdomainname(name, 65)), -(ErrNo::InvalidArgument as isize));
+    assert_eq!(as_current(&pcb, || sys_setdomainname(name, 11)), 0);
+    assert_eq!(names(), (hostname.clone(), b"example.org".to_vec()));
+
+    as_current(&pcb, || assert!(matches!(copy_to_user(name, &old), Ok(_))));
+    assert_eq!(as_current(&pcb, || sys_setdomainname(name, old.len())), 0);
+    assert_eq!(names(), (hostname, old));
+    verbose!("setdomainname test passed!");
+}
@@ -0,0 +1,197 @@
+//! Tests of the files generated by procfs
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::config::{CLOCK_FREQ, PAGE_SIZE};
+use super::fat32::{path, ram_fat32};
+use super::process::{as_current, install, spawn, tiny_elf_of_type};
+use crate::fs::{make_pipe, parse_path, File, FileType, OpenMode, VirtualFileSystem, PROC_FS};
+use crate::process::stats::context_switches;
+use crate::process::{CloneFlags, ProcessControlBlock, PROCESSOR0};
+use crate::sbi::get_time;
+use crate::memory::{VirtAddr, VMAFlags};
+use crate::syscall::{sys_mmap, sys_munmap, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+
+/// Read a whole proc file as text
+pub fn read_proc(path: &str) -> String {
+    let file = PROC_FS.open(parse_path(path).unwrap(), OpenMode::READ).unwrap();
+    let mut buf = [0u8; 1024];
+    let len = file.read(&mut buf).unwrap();
+    String::from_utf8(buf[..len].to_vec()).unwrap()
+}
+
+/// Parse a "seconds.hundredths" field into hundredths
+fn hundredths(field: &str) -> u64 {
+    let (sec, frac) = field.split_once('.').unwrap();
+    assert_eq!(frac.len(), 2);
+    sec.parse::<u64>().unwrap() * 100 + frac.parse::<u64>().unwrap()
+}
+
+/// Uptime goes up between two reads, loadavg has the linux layout
+pub fn uptime_test() {
+    verbose!("Testing /proc/uptime...");
+    let first = read_proc("/uptime");
+    let fields: Vec<&str> = first.trim_end().split(' ').collect();
+    assert_eq!(fields.len(), 2);
+    let start = get_time();
+    while get_time() < start + CLOCK_FREQ / 50 {}
+    let second = read_proc("/uptime");
+    assert!(hundredths(second.split(' ').next().unwrap()) > hundredths(fields[0]));
+
+    let loadavg = read_proc("/loadavg");
+    let fields: Vec<&str> = loadavg.trim_end().split(' ').collect();
+    assert_eq!(fields.len(), 5);
+    for field in &fields[..3] {
+        hundredths(field);
+    }
+    let (running, total) = fields[3].split_once('/').unwrap();
+    assert!(running.parse::<usize>().unwrap() <= total.parse::<usize>().unwrap());
+    fields[4].parse::<usize>().unwrap();
+    verbose!("/proc/uptime test passed!");
+}
+
+/// Value of the `name` line of /proc/stat
+fn stat_field(stat: &str, name: &str) -> Vec<u64> {
+    let line = stat.lines().find(|line| line.split(' ').next() == Some(name)).unwrap();
+    line.split(' ').skip(1).filter(|field| !field.is_empty()).map(|field| field.parse().unwrap()).collect()
+}
+
+/// Each reschedule counts as a context switch, and /proc/stat has the linux layout
+pub fn stat_test() {
+    verbose!("Testing /proc/stat...");
+    let pcb = spawn();
+    let before = stat_field(&read_proc("/stat"), "ctxt")[0];
+    let prev = PROCESSOR0.set_current(None);
+    for _ in 0..3 {
+        PROCESSOR0.schedule(pcb.clone());
+    }
+    PROCESSOR0.set_current(prev);
+    let stat = read_proc("/stat");
+    assert_eq!(stat_field(&stat, "ctxt")[0], before + 3);
+    assert_eq!(context_switches() as u64, before + 3);
+
+    assert_eq!(stat_field(&stat, "cpu").len(), 10);
+    assert_eq!(stat_field(&stat, "cpu0").len(), 10);
+    for name in ["btime", "processes", "procs_running", "procs_blocked"].iter() {
+        assert_eq!(stat_field(&stat, name).len(), 1);
+    }
+    verbose!("/proc/stat test passed!");
+}
+
+/// A file map shows up in /proc/self/smaps with its path and permissions
+pub fn smaps_test() {
+    verbose!("Testing /proc/self/smaps...");
+    let pcb = spawn();
+    let (_disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/mapped")).unwrap();
+    let file = fat32.open(path("/mapped"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert_eq!(file.write(&[0x11u8; 100]).unwrap(), 100);
+    let fd = install(&pcb, file);
+    let start = as_current(&pcb, || sys_mmap(VirtAddr::from(0), 100, PROT_READ, MAP_PRIVATE, fd, 0));
+    assert!(start > 0);
+    let smaps = as_current(&pcb, || {
+        let file = PROC_FS.open(parse_path("/self/smaps").unwrap(), OpenMode::READ).unwrap();
+        let mut content = Vec::new();
+        let mut buf = [0u8; 512];
+        loop {
+            let len = file.read(&mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            content.extend_from_slice(&buf[..len]);
+        }
+        String::from_utf8(content).unwrap()
+    });
+    let header = smaps.lines().find(|line| line.starts_with(&format!("{:08x}-", start))).unwrap();
+    assert!(header.ends_with("/mapped"), "{}", header);
+    assert_eq!(header.split(' ').nth(1), Some("r--p"));
+    assert!(smaps.lines().any(|line| line.starts_with("Rss:")));
+    verbose!("/proc/self/smaps test passed!");
+}
+
+/// VmRSS field of /proc/self/status in kB
+fn vm_rss(pcb: &Arc<ProcessControlBlock>) -> usize {
+    let status = as_current(pcb, || read_proc("/self/status"));
+    let line = status.lines().find(|line| line.starts_with("VmRSS:")).unwrap();
+    line.split_whitespace().nth(1).unwrap().parse().unwrap()
+}
+
+/// Faulting in anonymous pages raises VmRSS, unmapping them brings it back down
+pub fn status_rss_test() {
+    verbose!("Testing /proc/self/status VmRSS...");
+    let pcb = spawn();
+    let base = vm_rss(&pcb);
+    let start = as_current(&pcb, || sys_mmap(VirtAddr::from(0), 4 * PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0));
+    assert!(start > 0);
+    let start = start as usize;
+    // nothing is touched yet
+    assert_eq!(vm_rss(&pcb), base);
+    for page in 0..3 {
+        pcb.get_inner_locked().layout.lazy_copy_vma((start + page * PAGE_SIZE).into(), VMAFlags::W).unwrap();
+    }
+    assert_eq!(vm_rss(&pcb), base + 3 * PAGE_SIZE / 1024);
+    assert_eq!(as_current(&pcb, || sys_munmap(VirtAddr::from(start), 4 * PAGE_SIZE)), 0);
+    assert_eq!(vm_rss(&pcb), base);
+    verbose!("/proc/self/status VmRSS test passed!");
+}
+
+/// /proc/self/fd lists a link per open fd, readlink gives the path of the file. Following the link opens
+/// a regular file again with a cursor of its own, a pipe is the same open file.
+pub fn fd_dir_test() {
+    verbose!("Testing /proc/self/fd...");
+    let pcb = spawn();
+    let (_disk, fat32) = ram_fat32();
+    fat32.mkfile(path("/linked")).unwrap();
+    let file = fat32.open(path("/linked"), OpenMode::READ | OpenMode::WRITE).unwrap();
+    assert_eq!(file.write(b"0123456789").unwrap(), 10);
+    let fd = install(&pcb, file.clone());
+    let (read_end, _write_end) = make_pipe();
+    let pipe_fd = install(&pcb, read_end.clone());
+
+    let names: Vec<String> = as_current(&pcb, || {
+        let dir = PROC_FS.open(parse_path("/self/fd").unwrap(), OpenMode::READ | OpenMode::DIR).unwrap();
+        dir.to_dir_file().unwrap().list().iter().map(|link| link.poll().name).collect()
+    });
+    assert!(names.contains(&fd.to_string()) && names.contains(&pipe_fd.to_string()));
+    let link = as_current(&pcb, || PROC_FS.open(parse_path(&format!("/self/fd/{}", fd)).unwrap(), OpenMode::READ | OpenMode::NO_FOLLOW)).unwrap();
+    assert!(link.poll().ftype == FileType::Link);
+    let mut target = [0u8; 64];
+    let len = link.read(&mut target).unwrap();
+    assert_eq!(&target[..len], b"/linked");
+
+    // the fd is at the end, the reopened file starts over
+    let reopened = as_current(&pcb, || PROC_FS.open(parse_path(&format!("/self/fd/{}", fd)).unwrap(), OpenMode::READ)).unwrap();
+    // by address, the vtables of the same type may differ
+    let same = |a: &Arc<dyn File>, b: *const u8| Arc::as_ptr(a) as *const u8 == b;
+    assert!(!same(&reopened, Arc::as_ptr(&file) as *const u8));
+    let mut buf = [0u8; 4];
+    assert_eq!(reopened.read(&mut buf).unwrap(), 4);
+    assert_eq!(&buf, b"0123");
+    let pipe = as_current(&pcb, || PROC_FS.open(parse_path(&format!("/self/fd/{}", pipe_fd)).unwrap(), OpenMode::READ)).unwrap();
+    assert!(same(&pipe, Arc::as_ptr(&read_end) as *const u8));
+    assert!(matches!(as_current(&pcb, || PROC_FS.open(parse_path("/self/fd/999").unwrap(), OpenMode::READ)), Err(_)));
+    verbose!("/proc/self/fd test passed!");
+}
+
+/// /proc/self/cmdline and /proc/self/environ hold the argv and envp of the last exec, each string 0 terminated,
+/// and poll reports their length. A forked child has the same.
+pub fn cmdline_environ_test() {
+    verbose!("Testing /proc/self/cmdline and /proc/self/environ...");
+    let pcb = spawn();
+    let argv = vec![b"prog".to_vec(), b"-v\0".to_vec(), b"x y".to_vec()];
+    let envp = vec![b"HOME=/".to_vec(), b"TERM=vt100".to_vec()];
+    pcb.exec(&tiny_elf_of_type(2), None, "/selftest".to_string(), argv, envp).unwrap();
+    let child = pcb.fork(CloneFlags::empty()).unwrap();
+    for proc in [&pcb, &child].iter() {
+        for (file, expected) in [("/self/cmdline", &b"prog\0-v\0x y\0"[..]), ("/self/environ", &b"HOME=/\0TERM=vt100\0"[..])].iter() {
+            let opened = as_current(proc, || PROC_FS.open(parse_path(file).unwrap(), OpenMode::READ)).unwrap();
+            assert_eq!(opened.poll().size, expected.len() as u64);
+            let mut buf = [0u8; 64];
+            let len = opened.read(&mut buf).unwrap();
+            assert_eq!(&buf[..len], *expected);
+        }
+    }
+    pcb.get_inner_locked().children.clear();
+    verbose!("/proc/self/cmdline and /proc/self/environ test passed!");
+}
@@ -0,0 +1,344 @@
+//! A disk in memory for the self tests
+//! # Description
+//! A plain file holding a disk image, mounted through the loop device like an image file on the SD card.
+//! Reads and writes can be made to fail to walk the error paths, and the sectors written are logged in order.
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Mutex;
+
+use crate::fs::fs_impl::Fat32W;
+use crate::fs::{CommonFile, DeviceFile, DirFile, File, FileStatus, FileType, Path, SeekOp, VirtualFileSystem};
+use crate::fs::{parse_path, OpenMode};
+use crate::memory::UserBuffer;
+use crate::process::ErrNo;
+
+/// Sector size of the images, the block size of the loop device
+pub const SECTOR: usize = 512;
+
+/// Sectors before the first FAT
+pub const RSV_SEC: usize = 32;
+
+/// Sectors of a FAT, a sector holds 128 entries
+pub const FAT_SEC: usize = 1;
+
+/// Data clusters of the images, the first two FAT entries are reserved
+pub const FAT32_CLUSTERS: usize = FAT_SEC * SECTOR / 4 - 2;
+
+/// First data sector, cluster 2 starts here
+pub const DATA_SEC: usize = RSV_SEC + 2 * FAT_SEC;
+
+struct RamDiskInner {
+    data: Vec<u8>,
+    cursor: usize,
+    /// first sector of each write, in order
+    written: Vec<usize>,
+}
+
+pub struct RamDisk {
+    inner: Mutex<RamDiskInner>,
+    fail_reads: AtomicBool,
+    fail_writes: AtomicBool,
+    size_limit: AtomicUsize,
+    writes: AtomicUsize,
+}
+
+impl RamDisk {
+    pub fn new(data: Vec<u8>) -> Arc<Self> {
+        Arc::new(Self {
+            inner: Mutex::new(RamDiskInner {
+                data,
+                cursor: 0,
+                written: Vec::new(),
+            }),
+            fail_reads: AtomicBool::new(false),
+            fail_writes: AtomicBool::new(false),
+            size_limit: AtomicUsize::new(usize::MAX),
+            writes: AtomicUsize::new(0),
+        })
+    }
+
+    /// Make every read fail with EIO, or work again
+    pub fn set_fail_reads(&self, fail: bool) {
+        self.fail_reads.store(fail, Ordering::Relaxed);
+    }
+
+    /// Make every write fail with EIO, or work again
+    pub fn set_fail_writes(&self, fail: bool) {
+        self.fail_writes.store(fail, Ordering::Relaxed);
+    }
+
+    /// Writes stop short at `limit` bytes, and fail with ENOSPC past it
+    pub fn set_size_limit(&self, limit: usize) {
+        self.size_limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// # of writes that reached the disk
+    pub fn writes(&self) -> usize {
+        self.writes.load(Ordering::Relaxed)
+    }
+
+    /// A copy of the contents of the disk
+    pub fn contents(&self) -> Vec<u8> {
+        self.inner.lock().data.clone()
+    }
+
+    /// Sectors written since the last call, in order
+    pub fn take_written(&self) -> Vec<usize> {
+        core::mem::take(&mut self.inner.lock().written)
+    }
+}
+
+impl Drop for RamDisk {
+    fn drop(&mut self) {
+        release_dropped_flocks();
+    }
+}
+
+impl File for RamDisk {
+    fn seek(&self, offset: isize, op: SeekOp) -> Result<(), ErrNo> {
+        let mut inner = self.inner.lock();
+        let base = match op {
+            SeekOp::SET => 0,
+            SeekOp::CUR => inner.cursor as isize,
+            SeekOp::END => inner.data.len() as isize,
+        };
+        if base + offset < 0 {
+            return Err(ErrNo::InvalidArgument);
+        }
+        inner.cursor = (base + offset) as usize;
+        Ok(())
+    }
+
+    fn get_cursor(&self) -> Result<usize, ErrNo> {
+        Ok(self.inner.lock().cursor)
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, ErrNo> {
+        if self.fail_reads.load(Ordering::Relaxed) {
+            return Err(ErrNo::IOError);
+        }
+        let mut inner = self.inner.lock();
+        let start = inner.cursor.min(inner.data.len());
+        let len = buffer.len().min(inner.data.len() - start);
+        buffer[..len].copy_from_slice(&inner.data[start..start + len]);
+        inner.cursor = start + len;
+        Ok(len)
+    }
+
+    /// Writing past the end extends the disk, up to the size limit
+    fn write(&self, buffer: &[u8]) -> Result<usize, ErrNo> {
+        if self.fail_writes.load(Ordering::Relaxed) {
+            return Err(ErrNo::IOError);
+        }
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        let limit = self.size_limit.load(Ordering::Relaxed);
+        let mut inner = self.inner.lock();
+        let start = inner.cursor;
+        if start >= limit && !buffer.is_empty() {
+            return Err(ErrNo::NoSpaceLeftOnDevice);
+        }
+        let end = (start + buffer.len()).min(limit);
+        if inner.data.len() < end {
+            inner.data.resize(end, 0);
+        }
+        inner.data[start..end].copy_from_slice(&buffer[..end - start]);
+        inner.cursor = end;
+        inner.written.push(start / SECTOR);
+        Ok(end - start)
+    }
+
+    fn read_user_buffer(&self, mut buffer: UserBuffer) -> Result<usize, ErrNo> {
+        let mut bytes = vec![0u8; buffer.len()];
+        let len = self.read(&mut bytes)?;
+        buffer.write_bytes(&bytes[..len], 0);
+        Ok(len)
+    }
+
+    fn write_user_buffer(&self, buffer: UserBuffer) -> Result<usize, ErrNo> {
+        self.write(&buffer.clone_bytes())
+    }
+
+    fn to_common_file<'a>(self: Arc<Self>) -> Option<Arc<dyn CommonFile + 'a>> where Self: 'a {
+        Some(self)
+    }
+
+    fn to_dir_file<'a>(self: Arc<Self>) -> Option<Arc<dyn DirFile + 'a>> where Self: 'a {
+        None
+    }
+
+    fn to_device_file<'a>(self: Arc<Self>) -> Option<Arc<dyn DeviceFile + 'a>> where Self: 'a {
+        None
+    }
+
+    fn poll(&self) -> FileStatus {
+        let size = self.inner.lock().data.len() as u64;
+        FileStatus {
+            readable:   true,
+            writeable:  true,
+            size,
+            name:       "ramdisk".to_string(),
+            ftype:      FileType::Regular,
+            inode:      0,
+            dev_no:     0,
+            mode:       0,
+            block_sz:   SECTOR as u32,
+            blocks:     size / SECTOR as u64,
+            uid:        0,
+            gid:        0,
+            atime_sec:  0,
+            atime_nsec: 0,
+            mtime_sec:  0,
+            mtime_nsec: 0,
+            ctime_sec:  0,
+            ctime_nsec: 0,
+        }
+    }
+
+    fn rename(&self, _new_name: &str) -> Result<(), ErrNo> {
+        Err(ErrNo::PermissionDenied)
+    }
+
+    /// Not in any fs
+    fn get_vfs(&self) -> Result<Arc<dyn VirtualFileSystem>, ErrNo> {
+        Err(ErrNo::NoSuchFileOrDirectory)
+    }
+
+    fn get_path(&self) -> Path {
+        Path {
+            path: vec!["ramdisk".to_string()],
+            must_dir: false,
+            is_abs: true,
+        }
+    }
+}
+
+impl CommonFile for RamDisk {}
+
+/// An empty FAT32 image of one sector clusters, with the root directory at cluster 2
+pub fn fat32_image() -> Vec<u8> {
+    let mut image = vec![0u8; (DATA_SEC + FAT32_CLUSTERS) * SECTOR];
+    let sectors = image.len() / SECTOR;
+    // DBR
+    image[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    image[0x03..0x0B].copy_from_slice(b"OSHITTST");
+    image[0x0B..0x0D].copy_from_slice(&(SECTOR as u16).to_le_bytes());
+    image[0x0D] = 1;
+    image[0x0E..0x10].copy_from_slice(&(RSV_SEC as u16).to_le_bytes());
+    image[0x10] = 2;
+    image[0x15] = 0xF8;
+    image[0x20..0x24].copy_from_slice(&(sectors as u32).to_le_bytes());
+    image[0x24..0x28].copy_from_slice(&(FAT_SEC as u32).to_le_bytes());
+    image[0x2C..0x30].copy_from_slice(&2u32.to_le_bytes());
+    image[0x30..0x32].copy_from_slice(&1u16.to_le_bytes());
+    image[0x32..0x34].copy_from_slice(&6u16.to_le_bytes());
+    image[0x47..0x52].copy_from_slice(b"SELFTEST   ");
+    image[0x52..0x5A].copy_from_slice(b"FAT32   ");
+    image[510] = 0x55;
+    image[511] = 0xAA;
+    // both FATs: media, reserved, end of the root directory chain
+    for fat in 0..2 {
+        let base = (RSV_SEC + fat * FAT_SEC) * SECTOR;
+        for (i, entry) in [0x0FFF_FFF8u32, 0x0FFF_FFFF, 0x0FFF_FFFF].iter().enumerate() {
+            image[base + i * 4..base + i * 4 + 4].copy_from_slice(&entry.to_le_bytes());
+        }
+    }
+    image
+}
+
+/// Block size of the ext2 images
+pub const EXT2_BLOCK: usize = 1024;
+
+/// Contents of /hello in the ext2 images
+pub const EXT2_HELLO: &[u8] = b"Hello from ext2!\n";
+
+/// Blocks of /sub/big in the ext2 images, past the direct blocks
+pub const EXT2_BIG_BLOCKS: usize = 13;
+
+/// Size of /sub/big in the ext2 images
+pub const EXT2_BIG_SIZE: usize = EXT2_BIG_BLOCKS * EXT2_BLOCK - 100;
+
+/// Byte at offset `i` of /sub/big
+pub fn ext2_big_byte(i: usize) -> u8 {
+    (i % 251) as u8
+}
+
+/// Write inode `ino` of the ext2 image, holding `held` blocks, the inode table starts at block 5
+fn ext2_inode(image: &mut [u8], ino: usize, mode: u16, size: usize, held: usize, blocks: &[u32]) {
+    let base = 5 * EXT2_BLOCK + (ino - 1) * 128;
+    image[base..base + 2].copy_from_slice(&mode.to_le_bytes());
+    image[base + 4..base + 8].copy_from_slice(&(size as u32).to_le_bytes());
+    image[base + 26..base + 28].copy_from_slice(&1u16.to_le_bytes());
+    let sectors = held * EXT2_BLOCK / SECTOR;
+    image[base + 28..base + 32].copy_from_slice(&(sectors as u32).to_le_bytes());
+    for (i, block) in blocks.iter().enumerate() {
+        image[base + 40 + i * 4..base + 44 + i * 4].copy_from_slice(&block.to_le_bytes());
+    }
+}
+
+/// Fill directory block `block` with (inode, file type, name) entries
+fn ext2_dir(image: &mut [u8], block: usize, entries: &[(u32, u8, &str)]) {
+    let mut pos = block * EXT2_BLOCK;
+    for (i, (ino, ftype, name)) in entries.iter().enumerate() {
+        let rec_len = if i + 1 == entries.len() {
+            (block + 1) * EXT2_BLOCK - pos
+        } else {
+            (8 + name.len() + 3) & !3
+        };
+        image[pos..pos + 4].copy_from_slice(&ino.to_le_bytes());
+        image[pos + 4..pos + 6].copy_from_slice(&(rec_len as u16).to_le_bytes());
+        image[pos + 6] = name.len() as u8;
+        image[pos + 7] = *ftype;
+        image[pos + 8..pos + 8 + name.len()].copy_from_slice(name.as_bytes());
+        pos += rec_len;
+    }
+}
+
+/// An ext2 image of one group, as mke2fs would lay it out:
+/// /hello, and /sub/big that needs the singly-indirect block
+pub fn ext2_image() -> Vec<u8> {
+    const INODES: usize = 16;
+    const BLOCKS: usize = 24;
+    const ROOT: u32 = 2;
+    const SUB: u32 = 12;
+    const HELLO: u32 = 13;
+    const BIG: u32 = 14;
+    let mut image = vec![0u8; BLOCKS * EXT2_BLOCK];
+    // superblock
+    let sb = EXT2_BLOCK;
+    image[sb..sb + 4].copy_from_slice(&(INODES as u32).to_le_bytes());
+    image[sb + 4..sb + 8].copy_from_slice(&(BLOCKS as u32).to_le_bytes());
+    image[sb + 20..sb + 24].copy_from_slice(&1u32.to_le_bytes());
+    image[sb + 32..sb + 36].copy_from_slice(&8192u32.to_le_bytes());
+    image[sb + 40..sb + 44].copy_from_slice(&(INODES as u32).to_le_bytes());
+    image[sb + 56..sb + 58].copy_from_slice(&0xEF53u16.to_le_bytes());
+    image[sb + 76..sb + 80].copy_from_slice(&1u32.to_le_bytes());
+    image[sb + 84..sb + 88].copy_from_slice(&11u32.to_le_bytes());
+    image[sb + 88..sb + 90].copy_from_slice(&128u16.to_le_bytes());
+    // filetype in directory entries
+    image[sb + 96..sb + 100].copy_from_slice(&2u32.to_le_bytes());
+    // group descriptor: block bitmap, inode bitmap, inode table
+    let gd = 2 * EXT2_BLOCK;
+    for (i, block) in [3u32, 4, 5].iter().enumerate() {
+        image[gd + i * 4..gd + i * 4 + 4].copy_from_slice(&block.to_le_bytes());
+    }
+    ext2_inode(&mut image, ROOT as usize, 0o040755, EXT2_BLOCK, 1, &[7]);
+    ext2_inode(&mut image, SUB as usize, 0o040755, EXT2_BLOCK, 1, &[8]);
+    ext2_inode(&mut image, HELLO as usize, 0o100644, EXT2_HELLO.len(), 1, &[9]);
+    // 12 direct blocks, the 13th through the singly-indirect block 23
+    let mut big = [0u32; 13];
+    for i in 0..12 {
+        big[i] = 10 + i as u32;
+    }
+    big[12] = 23;
+    ext2_inode(&mut image, BIG as usize, 0o100644, EXT2_BIG_SIZE, EXT2_BIG_BLOCKS + 1, &big);
+    image[23 * EXT2_BLOCK..23 * EXT2_BLOCK + 4].copy_from_slice(&22u32.to_le_bytes());
+    ext2_dir(&mut image, 7, &[(ROOT, 2, "."), (ROOT, 2, ".."), (SUB, 2, "sub"), (HELLO, 1, "hello")]);
+    ext2_dir(&mut image, 8, &[(SUB, 2, "."), (ROOT, 2, ".."), (BIG, 1, "big")]);
+    image[9 * EXT2_BLOCK..9 * EXT2_BLOCK + EXT2_HELLO.len()].copy_from_slice(EXT2_HELLO);
+    for i in 0..EXT2_BIG_SIZE {
+        image[10 * EXT2_BLOCK + i] = ext2_big_byte(i);
+    }
+    image
+}
@@ -0,0 +1,386 @@
+//! Tests of signal delivery and the signal syscalls
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use super::process::{as_current, spawn, stack};
+use crate::config::{PAGE_SIZE, U_TRAMPOLINE};
+use crate::memory::{copy_from_user, copy_to_user, VirtAddr};
+use crate::process::default_handlers::{SIGBUS, SIGCHLD, SIGCONT, SIGILL, SIGINT, SIGKILL, SIGSEGV, SIGSTOP, SIGTERM, SIGTSTP, SIGUSR1};
+use crate::process::{adopt_orphans, park, remove_proc_by_pid, report_exit, PROC0, CloneFlags, ErrNo, ProcessStatus, SigAction, SigDisposition, SignalFlags};
+use crate::process::{ProcessControlBlock, BUS_ADRALN, CLD_EXITED, CLD_KILLED, ILL_ILLOPC, SEGV_ACCERR, SEGV_MAPERR};
+use crate::syscall::{sys_nanosleep, sys_pipe, sys_read, sys_sigaction, sys_sigprocmask, sys_sigreturn, sys_sigsuspend, TimeSPEC, SIG_BLOCK, SIG_SETMASK};
+use crate::syscall::{sys_prctl, PR_SET_PDEATHSIG};
+use crate::syscall::{sys_mmap, sys_munmap, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+use crate::trap::{emulate_unaligned, send_fault_signal, send_sigsegv, SIG_DFL, SIG_IGN};
+
+/// SIGKILL and SIGSTOP can't be caught or blocked, and still arrive after a process tried
+pub fn unblockable_test() {
+    verbose!("Testing SIGKILL and SIGSTOP...");
+    let pcb = spawn();
+    let act = stack(&pcb, 256);
+    let mask = stack(&pcb, 8);
+    let einval = -(ErrNo::InvalidArgument as isize);
+    assert_eq!(as_current(&pcb, || sys_sigaction(SIGKILL, act, 0.into())), einval);
+    assert_eq!(as_current(&pcb, || sys_sigaction(SIGSTOP, act, 0.into())), einval);
+    // only looking is fine
+    assert_eq!(as_current(&pcb, || sys_sigaction(SIGKILL, 0.into(), act)), 0);
+
+    pcb.get_inner_locked().layout.write_user_data(mask, &u64::MAX);
+    assert_eq!(as_current(&pcb, || sys_sigprocmask(SIG_SETMASK, 0.into(), mask)), 0);
+    assert_eq!(as_current(&pcb, || sys_sigprocmask(SIG_BLOCK, 0.into(), mask)), 0);
+    let mut inner = pcb.get_inner_locked();
+    assert_eq!(inner.sig_mask, !((1u64 << SIGKILL) | (1u64 << SIGSTOP)));
+    assert!(inner.recv_signal(SIGUSR1).is_none());
+    assert!(inner.recv_signal(SIGKILL).is_some());
+    assert_eq!(inner.pending_sig.iter().copied().collect::<alloc::vec::Vec<_>>(), [SIGKILL]);
+    verbose!("SIGKILL and SIGSTOP test passed!");
+}
+
+/// sigaction keeps the per-signal disposition in step, and SIGCONT resumes a stopped process
+pub fn disposition_test() {
+    verbose!("Testing signal dispositions...");
+    let pcb = spawn();
+    let act = stack(&pcb, 256);
+    {
+        let inner = pcb.get_inner_locked();
+        assert_eq!(inner.sig_disposition(SIGINT), SigDisposition::Default);
+        assert_eq!(inner.sig_disposition(SIGCHLD), SigDisposition::Ignore);
+        assert_eq!(inner.sig_disposition(SIGTSTP), SigDisposition::Stop);
+        assert_eq!(inner.sig_disposition(SIGCONT), SigDisposition::Cont);
+    }
+
+    let set = |signal: usize, handler: usize| {
+        let new_act = SigAction { sighandler: handler.into(), sigaction: 0.into(), mask: 0, flags: SignalFlags::empty(), restorer: 0.into() };
+        pcb.get_inner_locked().layout.write_user_data(act, &new_act);
+        assert_eq!(as_current(&pcb, || sys_sigaction(signal, act, 0.into())), 0);
+        pcb.get_inner_locked().sig_disposition(signal)
+    };
+    assert_eq!(set(SIGINT, SIG_IGN), SigDisposition::Ignore);
+    assert_eq!(set(SIGINT, SIG_DFL), SigDisposition::Default);
+    assert_eq!(set(SIGTSTP, SIG_IGN), SigDisposition::Ignore);
+    assert_eq!(set(SIGTSTP, SIG_DFL), SigDisposition::Stop);
+    assert_eq!(set(SIGCHLD, 0x10000), SigDisposition::Default);
+    assert_eq!(set(SIGCHLD, SIG_DFL), SigDisposition::Ignore);
+    assert_eq!(as_current(&pcb, || sys_sigaction(0, act, 0.into())), -(ErrNo::InvalidArgument as isize));
+    // whatever the table says, SIGSTOP stops
+    pcb.get_inner_locked().sig_dispositions.insert(SIGSTOP, SigDisposition::Ignore);
+    assert_eq!(pcb.get_inner_locked().sig_disposition(SIGSTOP), SigDisposition::Stop);
+
+    // SIGCONT wakes a stopped process even when blocked
+    {
+        let mut inner = pcb.get_inner_locked();
+        inner.status = ProcessStatus::Stopped;
+        inner.stop_report = Some(SIGSTOP);
+        inner.sig_mask |= 1u64 << SIGCONT;
+    }
+    park(pcb.clone());
+    assert!(pcb.recv_signal(SIGCONT).is_some());
+    {
+        let inner = pcb.get_inner_locked();
+        assert!(inner.status == ProcessStatus::Ready);
+        assert!(inner.stop_report.is_none());
+        assert!(inner.cont_report);
+    }
+    // back on the run queue
+    assert!(remove_proc_by_pid(pcb.pid.0).is_some());
+    verbose!("Signal disposition test passed!");
+}
+
+/// A blocking syscall interrupted by a signal fails with EINTR, and is rewound to the ecall
+/// only if the handler has SA_RESTART. A restarted sleep keeps its deadline.
+pub fn restart_test() {
+    verbose!("Testing SA_RESTART...");
+    let pcb = spawn();
+    let act = stack(&pcb, 256);
+    let fds = stack(&pcb, 8);
+    let buf = stack(&pcb, 16);
+    let eintr = -(ErrNo::InterruptedSystemCall as isize);
+    assert_eq!(as_current(&pcb, || sys_pipe(fds, 0)), 0);
+    let [rd, _wd]: [i32; 2] = pcb.get_inner_locked().layout.read_user_data(fds);
+
+    let new_act = SigAction { sighandler: 0x10000.into(), sigaction: 0.into(), mask: 0, flags: SignalFlags::RESTART, restorer: 0.into() };
+    pcb.get_inner_locked().layout.write_user_data(act, &new_act);
+    assert_eq!(as_current(&pcb, || sys_sigaction(SIGUSR1, act, 0.into())), 0);
+    let new_act = SigAction { flags: SignalFlags::empty(), ..new_act };
+    pcb.get_inner_locked().layout.write_user_data(act, &new_act);
+    assert_eq!(as_current(&pcb, || sys_sigaction(SIGINT, act, 0.into())), 0);
+
+    // the empty pipe would block, the pending signal breaks it off
+    assert!(pcb.recv_signal(SIGUSR1).is_some());
+    assert_eq!(as_current(&pcb, || sys_read(rd as usize, buf, 16)), eintr);
+    let interrupted = |inner: &mut crate::process::ProcessControlBlockInner| {
+        let trap_context = inner.get_trap_context();
+        trap_context.sepc = 0x10004;
+        trap_context.regs[10] = eintr as usize;
+        inner.syscall_restart = Some(rd as usize);
+    };
+    {
+        let mut inner = pcb.get_inner_locked();
+        interrupted(&mut *inner);
+        assert!(inner.restarts_syscall(SIGUSR1));
+        inner.rewind_syscall(true);
+        let trap_context = inner.get_trap_context();
+        assert_eq!((trap_context.sepc, trap_context.regs[10]), (0x10000, rd as usize));
+        // without SA_RESTART the EINTR stands
+        interrupted(&mut *inner);
+        assert!(!inner.restarts_syscall(SIGINT));
+        inner.rewind_syscall(false);
+        let trap_context = inner.get_trap_context();
+        assert_eq!((trap_context.sepc, trap_context.regs[10]), (0x10004, eintr as usize));
+        assert!(inner.syscall_restart.is_none());
+        // a stop runs no handler, the syscall always carries on after it
+        assert!(inner.restarts_syscall(SIGTSTP));
+    }
+
+    // an interrupted sleep reports the time left, and a restart sleeps only that long
+    let req = stack(&pcb, 64);
+    let rem = stack(&pcb, 32);
+    pcb.get_inner_locked().layout.write_user_data(req, &TimeSPEC { tvsec: 100, tvnsec: 0 });
+    assert_eq!(as_current(&pcb, || sys_nanosleep(req, rem)), eintr);
+    let left: TimeSPEC = pcb.get_inner_locked().layout.read_user_data(rem);
+    assert!(left.tvsec < 100 && left.tvsec >= 99);
+    let deadline = pcb.get_inner_locked().sleep_deadline.unwrap();
+    {
+        let mut inner = pcb.get_inner_locked();
+        interrupted(&mut *inner);
+        inner.rewind_syscall(true);
+    }
+    assert_eq!(as_current(&pcb, || sys_nanosleep(req, 0.into())), eintr);
+    assert_eq!(pcb.get_inner_locked().sleep_deadline, Some(deadline));
+    // a sleep that isn't restarted starts over
+    pcb.get_inner_locked().rewind_syscall(false);
+    assert!(pcb.get_inner_locked().sleep_deadline.is_none());
+    let bad = TimeSPEC { tvsec: 0, tvnsec: 1000000000 };
+    pcb.get_inner_locked().layout.write_user_data(req, &bad);
+    assert_eq!(as_current(&pcb, || sys_nanosleep(req, rem)), -(ErrNo::InvalidArgument as isize));
+    verbose!("SA_RESTART test passed!");
+}
+
+/// sigsuspend wakes only for a signal that gets delivered, never restarts after a handler,
+/// and the old mask is back once the handler returns
+pub fn sigsuspend_test() {
+    verbose!("Testing sigsuspend...");
+    let pcb = spawn();
+    let act = stack(&pcb, 256);
+    let mask = stack(&pcb, 8);
+    let eintr = -(ErrNo::InterruptedSystemCall as isize);
+    let new_act = SigAction { sighandler: 0x10000.into(), sigaction: 0.into(), mask: 0, flags: SignalFlags::RESTART, restorer: 0.into() };
+    pcb.get_inner_locked().layout.write_user_data(act, &new_act);
+    assert_eq!(as_current(&pcb, || sys_sigaction(SIGUSR1, act, 0.into())), 0);
+
+    let old_mask = 1u64 << SIGUSR1;
+    {
+        let mut inner = pcb.get_inner_locked();
+        inner.sig_mask = old_mask;
+        // blocked, ignored and continuing signals don't wake anyone
+        assert!(inner.recv_signal(SIGCHLD).is_some());
+        assert!(inner.recv_signal(SIGCONT).is_some());
+        assert!(inner.recv_signal(SIGUSR1).is_none());
+        inner.pending_sig.push_back(SIGUSR1);
+        assert!(!inner.has_pending_signal());
+        inner.layout.write_user_data(mask, &(1u64 << SIGINT));
+    }
+    // unblocking the pending signal returns right away
+    assert_eq!(as_current(&pcb, || sys_sigsuspend(mask)), eintr);
+    {
+        let mut inner = pcb.get_inner_locked();
+        assert_eq!(inner.sig_mask, 1u64 << SIGINT);
+        assert_eq!(inner.sigsuspend_mask, Some(old_mask));
+        assert!(inner.has_pending_signal());
+        // the handler has SA_RESTART, still sigsuspend returns EINTR once it ran
+        inner.syscall_restart = Some(mask.0);
+        assert!(!inner.restarts_syscall(SIGUSR1));
+        inner.rewind_syscall(false);
+        // what trap_return does when delivering
+        let trap_context = inner.get_trap_context().clone();
+        inner.signal_trap_contexts.push(trap_context);
+        inner.sig_mask |= 1u64 << SIGUSR1;
+        inner.last_signal = Some(SIGUSR1);
+        inner.pending_sig.clear();
+    }
+    as_current(&pcb, sys_sigreturn);
+    {
+        let mut inner = pcb.get_inner_locked();
+        assert_eq!(inner.sig_mask, old_mask);
+        assert!(inner.sigsuspend_mask.is_none());
+        // a stop restarts sigsuspend, which installs its mask again
+        inner.sigsuspend_mask = Some(old_mask);
+        inner.sig_mask = 0;
+        inner.syscall_restart = Some(mask.0);
+        inner.rewind_syscall(true);
+        assert_eq!(inner.sig_mask, old_mask);
+        assert!(inner.sigsuspend_mask.is_none());
+    }
+    verbose!("sigsuspend test passed!");
+}
+
+/// Take the pending `signal` of `pcb` and push its siginfo the way trap_return does for a SA_SIGINFO handler
+/// # Return
+/// (si_signo, si_code, si_addr, si_status) as the handler reads them
+fn handler_siginfo(pcb: &Arc<ProcessControlBlock>, signal: usize) -> (i32, i32, usize, i32) {
+    let mut inner = pcb.get_inner_locked();
+    assert_eq!(inner.pending_sig.pop_front(), Some(signal));
+    let info = inner.pending_info.remove(&signal).unwrap();
+    let sp = inner.get_trap_context().regs[2];
+    let info_va = inner.push_siginfo(&info).unwrap();
+    assert!(info_va.0 < sp && info_va.0 % 16 == 0);
+    assert_eq!(inner.get_trap_context().regs[2], info_va.0);
+    inner.get_trap_context().regs[2] = sp;
+    let mut bytes = [0u8; 28];
+    assert!(matches!(inner.layout.copy_from_user(&mut bytes, info_va), Ok(28)));
+    let word = |offset: usize| i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    (word(0), word(8), usize::from_le_bytes(bytes[16..24].try_into().unwrap()), word(24))
+}
+
+/// Install a SA_SIGINFO handler for `signal`
+fn catch_with_info(pcb: &Arc<ProcessControlBlock>, signal: usize) {
+    let act = stack(pcb, 256);
+    let new_act = SigAction { sighandler: 0.into(), sigaction: 0x10000.into(), mask: 0, flags: SignalFlags::SIGINFO, restorer: 0.into() };
+    pcb.get_inner_locked().layout.write_user_data(act, &new_act);
+    assert_eq!(as_current(pcb, || sys_sigaction(signal, act, 0.into())), 0);
+}
+
+/// A SIGSEGV for a fault hands the faulting address to a SA_SIGINFO handler, with SEGV_MAPERR for an unmapped page
+/// and SEGV_ACCERR for a mapped page the access isn't permitted on
+pub fn siginfo_test() {
+    verbose!("Testing SIGSEGV siginfo...");
+    let pcb = spawn();
+    catch_with_info(&pcb, SIGSEGV);
+    let area = as_current(&pcb, || sys_mmap(VirtAddr::from(0), 2 * PAGE_SIZE, PROT_READ, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0));
+    assert!(area > 0);
+    let area = area as usize;
+    let unmapped = area + PAGE_SIZE;
+    assert_eq!(as_current(&pcb, || sys_munmap(VirtAddr::from(unmapped), PAGE_SIZE)), 0);
+
+    send_sigsegv(&pcb, unmapped + 8);
+    assert_eq!(handler_siginfo(&pcb, SIGSEGV), (SIGSEGV as i32, SEGV_MAPERR, unmapped + 8, 0));
+    // a write to the read only page
+    send_sigsegv(&pcb, area + 16);
+    assert_eq!(handler_siginfo(&pcb, SIGSEGV), (SIGSEGV as i32, SEGV_ACCERR, area + 16, 0));
+    verbose!("SIGSEGV siginfo test passed!");
+}
+
+/// A parent with a SA_SIGINFO SIGCHLD handler reads the pid and exit code of its child from si_pid and si_status,
+/// or the signal that killed it with CLD_KILLED
+pub fn sigchld_info_test() {
+    verbose!("Testing SIGCHLD siginfo...");
+    let parent = spawn();
+    catch_with_info(&parent, SIGCHLD);
+    let child = parent.fork(CloneFlags::empty()).unwrap();
+
+    // si_pid is where si_addr of faults goes
+    report_exit(&child, &child.get_inner_locked(), 3);
+    assert_eq!(handler_siginfo(&parent, SIGCHLD), (SIGCHLD as i32, CLD_EXITED, child.pid.0, 3));
+    // the default handler of SIGINT runs in the trampoline and exits
+    child.get_inner_locked().last_signal = Some(SIGINT);
+    child.get_trap_context().sepc = U_TRAMPOLINE + 8;
+    report_exit(&child, &child.get_inner_locked(), 0);
+    assert_eq!(handler_siginfo(&parent, SIGCHLD), (SIGCHLD as i32, CLD_KILLED, child.pid.0, SIGINT as i32));
+
+    parent.get_inner_locked().children.clear();
+    verbose!("SIGCHLD siginfo test passed!");
+}
+
+/// A child that set PR_SET_PDEATHSIG gets the signal for its handler once when its parent exits,
+/// a child that has another parent by then doesn't
+pub fn pdeathsig_test() {
+    verbose!("Testing parent death signal...");
+    let parent = spawn();
+    let child = parent.fork(CloneFlags::empty()).unwrap();
+    let stepchild = parent.fork(CloneFlags::empty()).unwrap();
+    let act = stack(&child, 256);
+    let new_act = SigAction { sighandler: 0x10000.into(), sigaction: 0.into(), mask: 0, flags: SignalFlags::empty(), restorer: 0.into() };
+    child.get_inner_locked().layout.write_user_data(act, &new_act);
+    assert_eq!(as_current(&child, || sys_sigaction(SIGTERM, act, 0.into())), 0);
+    for pcb in [&child, &stepchild].iter() {
+        assert_eq!(as_current(pcb, || sys_prctl(PR_SET_PDEATHSIG, SIGTERM)), 0);
+    }
+    // reparented, but still on the list of the exiting process
+    stepchild.get_inner_locked().parent = Some(Arc::downgrade(&PROC0));
+
+    adopt_orphans(parent.pid.0, &mut parent.get_inner_locked());
+    {
+        let inner = child.get_inner_locked();
+        assert_eq!(inner.pending_sig.iter().copied().collect::<Vec<_>>(), [SIGTERM]);
+        assert!(inner.catches_signal(SIGTERM) && inner.has_pending_signal());
+    }
+    assert!(stepchild.get_inner_locked().pending_sig.is_empty());
+    // adopted by PROC0, a second exit of the old parent finds no children
+    adopt_orphans(parent.pid.0, &mut parent.get_inner_locked());
+    assert_eq!(child.get_inner_locked().pending_sig.len(), 1);
+
+    PROC0.get_inner_locked().children.retain(|proc| proc.pid.0 != child.pid.0 && proc.pid.0 != stepchild.pid.0);
+    verbose!("parent death signal test passed!");
+}
+
+/// An illegal instruction raises SIGILL on its address and an unaligned 8 byte load SIGBUS on the address loaded,
+/// only the faulting process is signalled and by default both terminate it
+pub fn fault_signal_test() {
+    verbose!("Testing SIGILL and SIGBUS...");
+    let pcb = spawn();
+    let bystander = spawn();
+    {
+        let inner = pcb.get_inner_locked();
+        for signal in [SIGILL, SIGBUS].iter() {
+            assert_eq!(inner.sig_disposition(*signal), SigDisposition::Default);
+            assert!(!inner.catches_signal(*signal));
+        }
+    }
+    catch_with_info(&pcb, SIGILL);
+    catch_with_info(&pcb, SIGBUS);
+
+    // illegal instruction at 0x10400, then ld a0, 0(a1) at 0x10404 with a1 = 0x20003
+    assert!(send_fault_signal(&pcb, 2, 0, 0x10400));
+    assert_eq!(handler_siginfo(&pcb, SIGILL), (SIGILL as i32, ILL_ILLOPC, 0x10400, 0));
+    assert!(send_fault_signal(&pcb, 4, 0x20003, 0x10404));
+    assert_eq!(handler_siginfo(&pcb, SIGBUS), (SIGBUS as i32, BUS_ADRALN, 0x20003, 0));
+    // an ecall is no fault
+    assert!(!send_fault_signal(&pcb, 8, 0, 0x10408));
+    assert!(pcb.get_inner_locked().pending_sig.is_empty());
+    assert!(bystander.get_inner_locked().pending_sig.is_empty());
+    verbose!("SIGILL and SIGBUS test passed!");
+}
+
+/// A misaligned 4 byte store and the load of it back are carried out byte by byte, the loaded word is sign extended
+/// and the pc steps over each instruction. An instruction that is no integer load or store is left alone.
+pub fn unaligned_access_test() {
+    verbose!("Testing misaligned access emulation...");
+    let pcb = spawn();
+    let area = as_current(&pcb, || sys_mmap(VirtAddr::from(0), PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0));
+    assert!(area > 0);
+    let area = area as usize;
+    // sw a1, 1(a0); lw a2, 1(a0); c.ld s0, 0(s1); fence
+    let insns: Vec<u8> = [0x00b5_20a3u32, 0x0015_2603].iter().flat_map(|insn| insn.to_le_bytes().to_vec())
+        .chain(0x6080u16.to_le_bytes().iter().copied())
+        .chain(0x0ff0_000fu32.to_le_bytes().iter().copied())
+        .collect();
+    as_current(&pcb, || assert!(matches!(copy_to_user(VirtAddr::from(area), &insns), Ok(14))));
+    let data = area + 0x101;
+    {
+        let trap_context = pcb.get_trap_context();
+        trap_context.sepc = area;
+        trap_context.regs[10] = data - 1;
+        trap_context.regs[11] = 0xffff_ffff_8765_4321;
+    }
+
+    let emulate = |addr: usize| {
+        let trap_context = pcb.get_trap_context();
+        emulate_unaligned(&mut pcb.get_inner_locked().layout, trap_context, addr)
+    };
+    assert!(emulate(data));
+    assert_eq!(pcb.get_trap_context().sepc, area + 4);
+    let mut stored = [0u8; 6];
+    as_current(&pcb, || assert!(matches!(copy_from_user(&mut stored, VirtAddr::from(data - 1)), Ok(6))));
+    assert_eq!(stored, [0, 0x21, 0x43, 0x65, 0x87, 0]);
+    assert!(emulate(data));
+    assert_eq!(pcb.get_trap_context().sepc, area + 8);
+    assert_eq!(pcb.get_trap_context().regs[12], 0xffff_ffff_8765_4321);
+    // the compressed load takes 2 bytes
+    pcb.get_trap_context().regs[9] = data;
+    assert!(emulate(data));
+    assert_eq!(pcb.get_trap_context().sepc, area + 10);
+    assert_eq!(pcb.get_trap_context().regs[8], 0x8765_4321);
+    assert!(!emulate(data));
+    assert_eq!(pcb.get_trap_context().sepc, area + 10);
+    verbose!("misaligned access emulation test passed!");
+}
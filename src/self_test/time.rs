@@ -0,0 +1,194 @@
+//! Tests of sleeping and the timers
+use super::process::{as_current, spawn, stack};
+use crate::config::CLOCK_FREQ;
+use crate::process::default_handlers::{SIGALRM, SIGINT};
+use crate::process::{idle_time, park, remove_proc_by_pid, ErrNo, ProcessStatus, PROCESSOR0, PROCESS_MANAGER};
+use crate::sbi::get_time;
+use crate::syscall::{sys_clock_nanosleep, sys_setitimer, itimerval, timeval, TimeSPEC, CLOCK_MONOTONIC, ITIMER_REAL, TIMER_ABSTIME};
+
+/// Relative and absolute sleeps, what an interrupted one leaves behind, and bad arguments
+pub fn clock_nanosleep_test() {
+    verbose!("Testing clock_nanosleep...");
+    let pcb = spawn();
+    let req = stack(&pcb, 64);
+    let rem = stack(&pcb, 32);
+    let einval = -(ErrNo::InvalidArgument as isize);
+    let eintr = -(ErrNo::InterruptedSystemCall as isize);
+    let set = |time: TimeSPEC| pcb.get_inner_locked().layout.write_user_data(req, &time);
+
+    assert_eq!(TimeSPEC { tvsec: u64::MAX, tvnsec: 0 }.to_ticks(), u64::MAX);
+    let time = TimeSPEC::from_ticks(3 * CLOCK_FREQ + CLOCK_FREQ / 2);
+    assert_eq!((time.tvsec, time.tvnsec), (3, 500000000));
+    assert_eq!(time.to_ticks(), 3 * CLOCK_FREQ + CLOCK_FREQ / 2);
+
+    set(TimeSPEC { tvsec: 0, tvnsec: 0 });
+    assert_eq!(as_current(&pcb, || sys_clock_nanosleep(CLOCK_MONOTONIC, 0, req, rem)), 0);
+    assert_eq!(as_current(&pcb, || sys_clock_nanosleep(42, 0, req, rem)), einval);
+    set(TimeSPEC { tvsec: 0, tvnsec: 1000000000 });
+    assert_eq!(as_current(&pcb, || sys_clock_nanosleep(CLOCK_MONOTONIC, 0, req, rem)), einval);
+    // a deadline already passed doesn't sleep at all
+    set(TimeSPEC::from_ticks(get_time()));
+    assert_eq!(as_current(&pcb, || sys_clock_nanosleep(CLOCK_MONOTONIC, TIMER_ABSTIME, req, rem)), 0);
+
+    assert!(pcb.recv_signal(SIGINT).is_some());
+    // an interrupted absolute sleep has no time left to report, a restart just uses the same deadline
+    let untouched = TimeSPEC { tvsec: 7, tvnsec: 7 };
+    pcb.get_inner_locked().layout.write_user_data(rem, &untouched);
+    set(TimeSPEC::from_ticks(get_time() + 100 * CLOCK_FREQ));
+    assert_eq!(as_current(&pcb, || sys_clock_nanosleep(CLOCK_MONOTONIC, TIMER_ABSTIME, req, rem)), eintr);
+    let left: TimeSPEC = pcb.get_inner_locked().layout.read_user_data(rem);
+    assert_eq!((left.tvsec, left.tvnsec), (7, 7));
+    assert!(pcb.get_inner_locked().sleep_deadline.is_none());
+
+    // a relative one does, and keeps its deadline for a restart
+    set(TimeSPEC { tvsec: 100, tvnsec: 0 });
+    assert_eq!(as_current(&pcb, || sys_clock_nanosleep(CLOCK_MONOTONIC, 0, req, rem)), eintr);
+    let left: TimeSPEC = pcb.get_inner_locked().layout.read_user_data(rem);
+    assert_eq!(left.tvsec, 99);
+    let deadline = pcb.get_inner_locked().sleep_deadline.unwrap();
+    assert_eq!(as_current(&pcb, || sys_clock_nanosleep(CLOCK_MONOTONIC, 0, req, rem)), eintr);
+    assert_eq!(pcb.get_inner_locked().sleep_deadline, Some(deadline));
+    verbose!("clock_nanosleep test passed!");
+}
+
+/// A 100 ms ITIMER_REAL fires SIGALRM on time from the timer check, and a timed sleep is woken up by it
+pub fn itimer_real_test() {
+    verbose!("Testing ITIMER_REAL...");
+    let pcb = spawn();
+    let new = stack(&pcb, 64);
+    let ms = CLOCK_FREQ / 1000;
+    let alarms = || pcb.get_inner_locked().pending_sig.iter().filter(|sig| **sig == SIGALRM).count();
+    let arm = |interval_usec: i64, value_usec: i64| {
+        let timer = itimerval {
+            it_interval: timeval { tv_sec: 0, tv_usec: interval_usec },
+            it_value: timeval { tv_sec: 0, tv_usec: value_usec },
+        };
+        pcb.get_inner_locked().layout.write_user_data(new, &timer);
+        assert_eq!(as_current(&pcb, || sys_setitimer(ITIMER_REAL, new, 0.into())), 0);
+    };
+
+    let armed = get_time();
+    arm(0, 100000);
+    let deadline = pcb.next_deadline().unwrap();
+    assert!(deadline >= armed + 100 * ms && deadline <= get_time() + 100 * ms);
+    pcb.check_timers();
+    assert_eq!(alarms(), 0);
+    while get_time() < deadline {}
+    pcb.check_timers();
+    assert!(get_time() < deadline + 10 * ms);
+    assert_eq!(alarms(), 1);
+    // one-shot, it's gone
+    assert!(pcb.next_deadline().is_none());
+    pcb.check_timers();
+    assert_eq!(alarms(), 1);
+
+    // a periodic one is reloaded
+    arm(50000, 10000);
+    let first = pcb.next_deadline().unwrap();
+    while get_time() < first {}
+    pcb.check_timers();
+    assert_eq!(alarms(), 2);
+    assert_eq!(pcb.next_deadline(), Some(first + 50 * ms));
+    pcb.get_inner_locked().timer_real_next = 0;
+    assert!(pcb.next_deadline().is_none());
+
+    // a sleeper whose deadline passed goes back to the run queue
+    {
+        let mut inner = pcb.get_inner_locked();
+        inner.wake_at = get_time();
+        inner.status = ProcessStatus::Sleeping;
+    }
+    park(pcb.clone());
+    assert_eq!(pcb.next_deadline(), Some(pcb.get_inner_locked().wake_at));
+    pcb.check_timers();
+    assert!(pcb.get_inner_locked().status == ProcessStatus::Ready);
+    assert!(PROCESS_MANAGER.lock().processes.iter().any(|proc| proc.pid.0 == pcb.pid.0));
+    assert!(pcb.next_deadline().is_none());
+    remove_proc_by_pid(pcb.pid.0).unwrap();
+    verbose!("ITIMER_REAL test passed!");
+}
+
+/// setitimer hands back the old value, a zero value disarms and bad values change nothing
+pub fn setitimer_test() {
+    verbose!("Testing setitimer...");
+    let pcb = spawn();
+    let new = stack(&pcb, 64);
+    let old = stack(&pcb, 32);
+    let einval = -(ErrNo::InvalidArgument as isize);
+    let set = |sec: i64, usec: i64| {
+        let timer = itimerval {
+            it_interval: timeval { tv_sec: 0, tv_usec: 0 },
+            it_value: timeval { tv_sec: sec, tv_usec: usec },
+        };
+        pcb.get_inner_locked().layout.write_user_data(new, &timer);
+        as_current(&pcb, || sys_setitimer(ITIMER_REAL, new, old))
+    };
+    let left = || {
+        let timer: itimerval = pcb.get_inner_locked().layout.read_user_data(old);
+        (timer.it_value.tv_sec, timer.it_value.tv_usec)
+    };
+
+    // alarm(1), then alarm(2) returns what was left of the first one
+    assert_eq!(set(1, 0), 0);
+    assert_eq!(left(), (0, 0));
+    let deadline = pcb.next_deadline().unwrap();
+    assert_eq!(set(2, 0), 0);
+    let (sec, usec) = left();
+    assert!(sec == 1 || (sec == 0 && usec > 900000));
+    assert!(pcb.next_deadline().unwrap() > deadline);
+
+    // nothing is touched by a bad value
+    let deadline = pcb.next_deadline();
+    let untouched = itimerval {
+        it_interval: timeval { tv_sec: 7, tv_usec: 7 },
+        it_value: timeval { tv_sec: 7, tv_usec: 7 },
+    };
+    pcb.get_inner_locked().layout.write_user_data(old, &untouched);
+    assert_eq!(set(0, 1000000), einval);
+    assert_eq!(set(-1, 0), einval);
+    assert_eq!(set(0, -1), einval);
+    assert_eq!(set(i64::MAX, 0), einval);
+    assert_eq!(set(i64::MAX / 1000000, 0), einval);
+    assert_eq!(as_current(&pcb, || sys_setitimer(42, new, old)), einval);
+    assert_eq!(left(), (7, 7));
+    assert_eq!(pcb.next_deadline(), deadline);
+
+    // alarm(0) disarms
+    assert_eq!(set(0, 0), 0);
+    assert_eq!(left().0, 1);
+    assert!(pcb.next_deadline().is_none());
+    assert_eq!(set(0, 0), 0);
+    assert_eq!(left(), (0, 0));
+    remove_proc_by_pid(pcb.pid.0).unwrap();
+    verbose!("setitimer test passed!");
+}
+
+/// Idle time stands still while a process runs, and goes up while every process sleeps
+pub fn idle_time_test() {
+    verbose!("Testing idle time...");
+    let pcb = spawn();
+    let ms = CLOCK_FREQ / 1000;
+
+    let before = idle_time();
+    as_current(&pcb, || {
+        let start = get_time();
+        while get_time() < start + 50 * ms {}
+    });
+    assert!(idle_time() - before < ms);
+
+    {
+        let mut inner = pcb.get_inner_locked();
+        inner.wake_at = get_time() + 50 * ms;
+        inner.status = ProcessStatus::Sleeping;
+    }
+    park(pcb.clone());
+    let before = idle_time();
+    while pcb.get_inner_locked().status == ProcessStatus::Sleeping {
+        PROCESSOR0.idle();
+    }
+    PROCESSOR0.end_idle();
+    let idle = idle_time() - before;
+    assert!(idle >= 50 * ms && idle < 100 * ms);
+    remove_proc_by_pid(pcb.pid.0).unwrap();
+    verbose!("idle time test passed!");
+}
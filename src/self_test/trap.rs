@@ -0,0 +1,94 @@
+//! Tests of the trap context
+use alloc::vec::Vec;
+use super::process::{as_current, spawn};
+use crate::config::PAGE_SIZE;
+use crate::memory::{copy_to_user, VirtAddr};
+use crate::syscall::{sys_mmap, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE};
+use crate::trap::{enable_fp_on_demand, save_user_fp, TrapContext, FS_CLEAN, FS_DIRTY, FS_INITIAL, FS_OFF};
+
+/// `fadd.d f1, f1, f2`, the kernel is built without F/D
+fn fadd_f1_f2() {
+    unsafe {
+        asm!(".word 0x0220f0d3");
+    }
+}
+
+/// Two processes keep adding f2 to f1 in turns, each switch restores the FP registers of the one to run and
+/// saves them once it dirtied them, so neither sees the sums of the other. FP state that is off is left alone.
+pub fn fp_context_test() {
+    verbose!("Testing FP context switch...");
+    let mut contexts = [TrapContext::init(0, 0, 0, 0, 0), TrapContext::init(0, 0, 0, 0, 0)];
+    // f1, f2 of each
+    let start = [(1.0f64, 0.5f64), (100.0, 2.0)];
+    for (cx, (f1, f2)) in contexts.iter_mut().zip(start.iter()) {
+        cx.fregs[1] = f1.to_bits() as usize;
+        cx.fregs[2] = f2.to_bits() as usize;
+        cx.set_fs(FS_CLEAN);
+    }
+    // off until the process asks for it
+    assert_eq!(TrapContext::init(0, 0, 0, 0, 0).fs(), FS_OFF);
+    for _ in 0..3 {
+        for cx in contexts.iter_mut() {
+            cx.restore_fp();
+            fadd_f1_f2();
+            // the hardware marks FS dirty, sstatus is saved with it on trap entry
+            cx.set_fs(FS_DIRTY);
+            cx.save_fp();
+            assert_eq!(cx.fs(), FS_CLEAN);
+        }
+    }
+    assert_eq!(f64::from_bits(contexts[0].fregs[1] as u64), 2.5);
+    assert_eq!(f64::from_bits(contexts[1].fregs[1] as u64), 106.0);
+    assert_eq!(f64::from_bits(contexts[1].fregs[2] as u64), 2.0);
+
+    // a process that never touched FP has nothing saved, whatever is in the registers
+    let mut off = TrapContext::init(0, 0, 0, 0, 0);
+    off.set_fs(FS_OFF);
+    off.save_fp();
+    assert!(off.fregs.iter().all(|reg| *reg == 0));
+    verbose!("FP context switch test passed!");
+}
+
+/// A process starts with FP off and an integer only one never has FP saved. Its first FP instruction turns FP on
+/// to be retried, after which every trap entry with the registers dirtied saves them once.
+pub fn lazy_fp_test() {
+    verbose!("Testing lazy FP enabling...");
+    let pcb = spawn();
+    let area = as_current(&pcb, || sys_mmap(VirtAddr::from(0), PAGE_SIZE, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, usize::MAX, 0));
+    assert!(area > 0);
+    let area = area as usize;
+    // addi a0, a0, 1; fadd.d f1, f1, f2
+    let insns: Vec<u8> = [0x0015_0513u32, 0x0220_f0d3].iter().flat_map(|insn| insn.to_le_bytes().to_vec()).collect();
+    as_current(&pcb, || assert!(matches!(copy_to_user(VirtAddr::from(area), &insns), Ok(8))));
+    let enable = || {
+        let trap_context = pcb.get_trap_context();
+        enable_fp_on_demand(&mut pcb.get_inner_locked().layout, trap_context)
+    };
+
+    assert!(pcb.get_trap_context().fp_off());
+    save_user_fp(&pcb);
+    assert_eq!(pcb.get_inner_locked().fp_saves, 0);
+    // an integer instruction is illegal for some other reason
+    pcb.get_trap_context().sepc = area;
+    assert!(!enable());
+    assert!(pcb.get_trap_context().fp_off());
+    save_user_fp(&pcb);
+    assert_eq!(pcb.get_inner_locked().fp_saves, 0);
+
+    pcb.get_trap_context().sepc = area + 4;
+    assert!(enable());
+    assert_eq!(pcb.get_trap_context().fs(), FS_INITIAL);
+    assert_eq!(pcb.get_trap_context().sepc, area + 4);
+    // FP on already, the trap is a real illegal instruction
+    assert!(!enable());
+    // on but untouched
+    save_user_fp(&pcb);
+    assert_eq!(pcb.get_inner_locked().fp_saves, 0);
+    pcb.get_trap_context().set_fs(FS_DIRTY);
+    save_user_fp(&pcb);
+    assert_eq!(pcb.get_inner_locked().fp_saves, 1);
+    assert_eq!(pcb.get_trap_context().fs(), FS_CLEAN);
+    save_user_fp(&pcb);
+    assert_eq!(pcb.get_inner_locked().fp_saves, 1);
+    verbose!("lazy FP enabling test passed!");
+}
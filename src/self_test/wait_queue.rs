@@ -0,0 +1,93 @@
+//! Tests of the WaitQueue blocking primitive
+use alloc::sync::Arc;
+
+use super::process::spawn;
+use crate::fs::{make_pipe, File};
+use crate::process::default_handlers::SIGINT;
+use crate::process::{park, remove_proc_by_pid, ProcessControlBlock, ProcessStatus, WaitQueue, PROCESS_MANAGER};
+
+/// Put `pcb` to sleep on `queue` the way `WaitQueue::wait()` does, minus the switch
+fn sleep_on(queue: &WaitQueue, pcb: &Arc<ProcessControlBlock>) {
+    queue.enqueue(pcb);
+    pcb.get_inner_locked().status = ProcessStatus::Sleeping;
+    park(pcb.clone());
+}
+
+fn runnable(pcb: &Arc<ProcessControlBlock>) -> bool {
+    pcb.get_inner_locked().status == ProcessStatus::Ready
+        && PROCESS_MANAGER.lock().processes.iter().any(|proc| Arc::ptr_eq(proc, pcb))
+}
+
+/// A producer wakes the blocked consumers, a sleeper already woken by a signal or gone is skipped
+pub fn wait_queue_test() {
+    verbose!("Testing wait queue...");
+    let queue = WaitQueue::new();
+    let consumer = spawn();
+    let other = spawn();
+    sleep_on(&queue, &consumer);
+    sleep_on(&queue, &other);
+    assert!(!runnable(&consumer) && !runnable(&other));
+
+    assert!(queue.wake_one());
+    assert!(runnable(&consumer) && !runnable(&other));
+    assert!(queue.wake_one());
+    assert!(runnable(&other));
+    assert!(!queue.wake_one());
+    remove_proc_by_pid(consumer.pid.0).unwrap();
+    remove_proc_by_pid(other.pid.0).unwrap();
+
+    // a signal interrupts the sleep, the producer finds nobody left to wake
+    sleep_on(&queue, &consumer);
+    assert!(consumer.recv_signal(SIGINT).is_some());
+    assert!(runnable(&consumer));
+    assert!(!queue.wake_one());
+    remove_proc_by_pid(consumer.pid.0).unwrap();
+
+    // exited waiters are skipped, the rest wake all at once
+    let gone = spawn();
+    queue.enqueue(&gone);
+    drop(gone);
+    sleep_on(&queue, &consumer);
+    sleep_on(&queue, &other);
+    queue.wake_all();
+    assert!(runnable(&consumer) && runnable(&other));
+    remove_proc_by_pid(consumer.pid.0).unwrap();
+    remove_proc_by_pid(other.pid.0).unwrap();
+    verbose!("wait queue test passed!");
+}
+
+/// A reader blocked on an empty pipe stays off the run queue until data shows up or the writers are gone,
+/// a writer blocked on a full pipe until a reader makes room
+pub fn pipe_wait_test() {
+    verbose!("Testing pipe wait queues...");
+    let (rd, wr) = make_pipe();
+    let (_other_rd, other_wr) = make_pipe();
+    let reader = spawn();
+    let writer = spawn();
+
+    sleep_on(&rd.wait_queue(), &reader);
+    assert_eq!(other_wr.write(b"elsewhere").unwrap(), 9);
+    assert!(!runnable(&reader));
+    assert_eq!(wr.write(b"data").unwrap(), 4);
+    assert!(runnable(&reader));
+    remove_proc_by_pid(reader.pid.0).unwrap();
+
+    // fill it up, the writer sleeps until a read makes room
+    assert_eq!(wr.write(&[0u8; 4092]).unwrap(), 4092);
+    sleep_on(&wr.wait_queue(), &writer);
+    let mut buf = [0u8; 4];
+    assert_eq!(rd.read(&mut buf).unwrap(), 4);
+    assert_eq!(&buf, b"data");
+    assert!(runnable(&writer));
+    remove_proc_by_pid(writer.pid.0).unwrap();
+
+    // the last writer going away is an EOF for the reader
+    let mut rest = [0u8; 4096];
+    assert_eq!(rd.read(&mut rest).unwrap(), 4092);
+    sleep_on(&rd.wait_queue(), &reader);
+    drop(wr);
+    assert!(runnable(&reader));
+    remove_proc_by_pid(reader.pid.0).unwrap();
+    assert_eq!(rd.read(&mut buf).unwrap(), 0);
+    verbose!("pipe wait queue test passed!");
+}
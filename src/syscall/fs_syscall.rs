@@ -4,15 +4,17 @@
 use crate::fs::Path;
 use crate::fs::parse_path;
 use crate::fs::to_string;
-use crate::fs::{self, File, OpenMode, make_pipe, mkdir, open, remove, FileType};
-use crate::memory::{VirtAddr};
-use crate::process::{current_process, suspend_switch, ErrNo};
+use crate::fs::{self, File, CommonFile, OpenMode, make_pipe, mkdir, mknod, open, remove, FileType};
+use crate::memory::{VirtAddr, UserBuffer, copy_to_user, read_from_user, write_to_user};
+use crate::process::{current_process, ErrNo};
 use alloc::string::ToString;
 use alloc::string::String;
 // use alloc::vec::Vec;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
+use lazy_static::*;
+use spin::Mutex;
 use alloc::vec::Vec;
-use core::{convert::TryInto, mem::size_of};
+use core::mem::size_of;
 use bitflags::*;
 
 /// The special "file descriptor" indicating that the path is relative path to process's current working directory. 
@@ -26,7 +28,7 @@ fn get_file_fd(dirfd: usize) -> Result<Arc<dyn File>, ErrNo> {
         // debug!("path: {}", arcpcb.path);
         return open(arcpcb.path.clone(), OpenMode::empty());
     } else {
-        if dirfd > arcpcb.files.len() {
+        if dirfd >= arcpcb.files.len() {
             return Err(ErrNo::BadFileDescriptor);
         } 
         if let Some(file) = &arcpcb.files[dirfd] {
@@ -142,6 +144,26 @@ pub fn sys_openat(fd: i32, file_name: VirtAddr, flags: u32, _: u32) -> isize {
     }
     verbose!("Openat flag: {:x}", flags);
 
+    if flags as usize & O_TMPFILE == O_TMPFILE {
+        if !fs_flags.contains(OpenMode::WRITE) {
+            return -(ErrNo::InvalidArgument as isize);
+        }
+        let res = get_file(fd as usize, path, OpenMode::READ | OpenMode::DIR)
+            .and_then(|dir| dir.get_vfs()?.mktmp(dir.get_path(), fs_flags | OpenMode::READ));
+        return match res {
+            Ok(file) => {
+                let mut arcpcb = process.get_inner_locked();
+                let new_fd = arcpcb.alloc_fd();
+                arcpcb.files[new_fd] = Some(file);
+                new_fd as isize
+            },
+            Err(errno) => {
+                error!("sys_openat: O_TMPFILE failed with {:?} on {}", errno, path);
+                -(errno as isize)
+            }
+        };
+    }
+
     match get_file(fd as usize, path, fs_flags) {
         Ok(file) => {
             let mut arcpcb = process.get_inner_locked();
@@ -167,13 +189,14 @@ pub fn sys_close(fd: usize) -> isize {
         return -1;
     }
 
-    let file = &mut arcpcb.files[fd];
-    if file.is_some() {
-        file.take();
-    } else {
-        error!("Invalid FD");
-        return -1;
-    }
+    arcpcb.cloexec.remove(&fd);
+    let file = match arcpcb.files[fd].take() {
+        Some(file) => file,
+        None => {
+            error!("Invalid FD");
+            return -1;
+        }
+    };
 
     loop {
         if arcpcb.files.len() == 0 {
@@ -185,10 +208,135 @@ pub fn sys_close(fd: usize) -> isize {
             break;
         }
     }
+    drop(arcpcb);
+    fs::release_record_locks(&file, process.get_pid());
     verbose!("Fd closed");
     return 0;
 }
 
+/// Apply or remove an advisory lock on the open file `fd`
+pub fn sys_flock(fd: usize, operation: usize) -> isize {
+    let file = {
+        let process = current_process().unwrap();
+        let arcpcb = process.get_inner_locked();
+        match arcpcb.files.get(fd) {
+            Some(Some(file)) => file.clone(),
+            _ => return -(ErrNo::BadFileDescriptor as isize),
+        }
+    };
+    match fs::flock(&file, operation) {
+        Ok(()) => 0,
+        Err(errno) => -(errno as isize),
+    }
+}
+
+pub const F_GETFD: usize = 1;
+pub const F_SETFD: usize = 2;
+pub const F_GETLK: usize = 5;
+pub const F_SETLK: usize = 6;
+pub const F_SETLKW: usize = 7;
+pub const FD_CLOEXEC: usize = 1;
+
+/// struct flock of fcntl record locks
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct Flock {
+    pub l_type: i16,
+    pub l_whence: i16,
+    pub l_start: i64,
+    pub l_len: i64,
+    pub l_pid: i32,
+}
+
+/// Turn the range described by `fl` into absolute offsets of `file`
+/// # Return
+/// The lock asked for by process `pid`, Err(InvalidArgument) for a bad type or a range starting before 0
+fn flock_to_record(file: &Arc<dyn File>, fl: &Flock, pid: usize) -> Result<fs::RecordLock, ErrNo> {
+    let ltype = match fl.l_type as usize {
+        ltype @ (fs::F_RDLCK | fs::F_WRLCK | fs::F_UNLCK) => ltype,
+        _ => return Err(ErrNo::InvalidArgument),
+    };
+    let base = match fl.l_whence {
+        0 => 0,
+        1 => file.get_cursor()? as i64,
+        2 => file.poll().size as i64,
+        _ => return Err(ErrNo::InvalidArgument),
+    };
+    let start = base.checked_add(fl.l_start).ok_or(ErrNo::InvalidArgument)?;
+    // a negative length locks the bytes before start
+    let (start, end) = match fl.l_len {
+        0 => (start, usize::MAX),
+        len if len > 0 => (start, start.checked_add(len).ok_or(ErrNo::InvalidArgument)? as usize),
+        len => (start + len, start as usize),
+    };
+    if start < 0 {
+        return Err(ErrNo::InvalidArgument);
+    }
+    Ok(fs::RecordLock { ltype, start: start as usize, end, pid })
+}
+
+pub fn sys_fcntl_inner(fd: usize, cmd: usize, arg: usize) -> Result<usize, ErrNo> {
+    let process = current_process().ok_or(ErrNo::NoSuchProcess)?;
+    let mut arcpcb = process.get_inner_locked();
+    let file = arcpcb.files.get(fd).ok_or(ErrNo::BadFileDescriptor)?.clone().ok_or(ErrNo::BadFileDescriptor)?;
+    match cmd {
+        F_GETFD => Ok(if arcpcb.cloexec.contains(&fd) { FD_CLOEXEC } else { 0 }),
+        F_SETFD => {
+            if arg & FD_CLOEXEC != 0 {
+                arcpcb.cloexec.insert(fd);
+            } else {
+                arcpcb.cloexec.remove(&fd);
+            }
+            Ok(0)
+        },
+        F_GETLK | F_SETLK | F_SETLKW => {
+            drop(arcpcb);
+            let ptr = VirtAddr::from(arg);
+            let mut fl: Flock = read_from_user(ptr)?;
+            let lock = flock_to_record(&file, &fl, process.get_pid())?;
+            if cmd == F_GETLK {
+                // there is nothing to test an unlock against
+                if lock.ltype == fs::F_UNLCK {
+                    return Err(ErrNo::InvalidArgument);
+                }
+                match fs::test_record_lock(&file, &lock) {
+                    Some(held) => {
+                        fl.l_type = held.ltype as i16;
+                        fl.l_whence = 0;
+                        fl.l_start = held.start as i64;
+                        fl.l_len = if held.end == usize::MAX { 0 } else { (held.end - held.start) as i64 };
+                        fl.l_pid = held.pid as i32;
+                    },
+                    None => fl.l_type = fs::F_UNLCK as i16,
+                }
+                write_to_user(ptr, &fl)?;
+                return Ok(0);
+            }
+            // the fd must be open for the kind of access the lock guards
+            let status = file.poll();
+            if (lock.ltype == fs::F_RDLCK && !status.readable) || (lock.ltype == fs::F_WRLCK && !status.writeable) {
+                return Err(ErrNo::BadFileDescriptor);
+            }
+            fs::set_record_lock(&file, lock, cmd == F_SETLKW)?;
+            Ok(0)
+        },
+        _ => Err(ErrNo::InvalidArgument),
+    }
+}
+
+/// Manipulate the file descriptor fd
+/// # Description
+/// Only the close-on-exec flag and record locks are supported.
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    match sys_fcntl_inner(fd, cmd, arg) {
+        Ok(res) => res as isize,
+        Err(errno) => {
+            debug!("fcntl failed: {}", errno);
+            -(errno as isize)
+        }
+    }
+}
+
 /// Write to spcific fd.
 /// # Returns
 /// How many bytes hace been really written to the fd.
@@ -208,6 +356,8 @@ pub fn sys_write(fd: usize, buf: VirtAddr, len: usize) -> isize {
                 drop(arcpcb);
                 match file.write_user_buffer(buf) {
                     Ok(size) => size as isize,
+                    Err(ErrNo::NoSpaceLeftOnDevice) => -(ErrNo::NoSpaceLeftOnDevice as isize),
+                    Err(ErrNo::IOError) => -(ErrNo::IOError as isize),
                     Err(msg) => {
                         error!("Write failed with msg \"{}\"", msg);
                         -1
@@ -232,42 +382,79 @@ pub struct iovec {
     pub iov_len: usize
 }
 
+/// Collect the user buffers described by iov, with a single lock on the pcb.
+/// # Description
+/// Collecting stops at the first iovec that points to unmapped memory.
+/// # Return
+/// The file and the buffers collected, or BadAddress if the first iovec is already invalid.
+fn get_iov_buffers(fd: usize, iov: VirtAddr, iovcnt: usize) -> Result<(Arc<dyn File>, Vec<UserBuffer>), ErrNo> {
+    let process = current_process().unwrap();
+    let arcpcb = process.get_inner_locked();
+
+    let file = arcpcb.files.get(fd).ok_or(ErrNo::BadFileDescriptor)?.clone().ok_or(ErrNo::BadFileDescriptor)?;
+    let mut bufs = Vec::with_capacity(iovcnt);
+    for i in 0..iovcnt {
+        let iov_addr = iov + size_of::<iovec>() * i;
+        let iov_struct: iovec = match arcpcb.layout.try_get_user_buffer(iov_addr, size_of::<iovec>()) {
+            Ok(buf) => buf.read(0),
+            Err(errno) => {
+                if i == 0 {
+                    return Err(errno);
+                }
+                break;
+            }
+        };
+        match arcpcb.layout.try_get_user_buffer(VirtAddr::from(iov_struct.iov_base), iov_struct.iov_len) {
+            Ok(buf) => bufs.push(buf),
+            Err(errno) => {
+                if i == 0 {
+                    return Err(errno);
+                }
+                break;
+            }
+        }
+    }
+    Ok((file, bufs))
+}
+
 /// Write multiple buffers of data described by iov to the file descriptor
+/// # Description
+/// If an error occurs after some data has been written, the bytes written so far are returned.
+/// Writing stops at the first short write.
 /// # Returns
 /// How many bytes hace been really written to the fd.
 pub fn sys_writev(fd: usize, iov: VirtAddr, iovcnt: usize) -> isize {
-    let process = current_process().unwrap();
-    let arcpcb = process.get_inner_locked();
-    
-    if fd as usize > arcpcb.files.len() {
-        error!("Invalid FD");
-        return -1;
-    }
+    let (file, bufs) = match get_iov_buffers(fd, iov, iovcnt) {
+        Ok(res) => res,
+        Err(msg) => {
+            error!("Writev failed with msg \"{}\"", msg);
+            return -1;
+        }
+    };
 
     let mut ret = 0;
-    match &arcpcb.files[fd] {
-        Some(file) => {
-            let file = file.clone();
-            for i in 0..iovcnt {
-                let iov_addr = iov + size_of::<iovec>() * i;
-                let iov_struct: iovec = arcpcb.layout.read_user_data(iov_addr);
-                let buf = arcpcb.layout.get_user_buffer(VirtAddr::from(iov_struct.iov_base), iov_struct.iov_len);
-                match file.write_user_buffer(buf) {
-                    Ok(size) => { ret += size as isize; },
-                    Err(msg) => {
-                        error!("Read failed with msg \"{}\"", msg);
-                        return -1;
+    for buf in bufs {
+        let len = buf.len();
+        match file.write_user_buffer(buf) {
+            Ok(size) => {
+                ret += size as isize;
+                if size < len {
+                    break;
+                }
+            },
+            Err(msg) => {
+                if ret == 0 {
+                    if let ErrNo::NoSpaceLeftOnDevice = msg {
+                        return -(msg as isize);
                     }
+                    error!("Write failed with msg \"{}\"", msg);
+                    return -1;
                 }
+                break;
             }
-            drop(arcpcb);
-            ret
-        },
-        None => {
-            error!("No such file descriptor!");
-            return -1;
         }
     }
+    ret
 }
 
 /// Read from spcific fd.
@@ -291,6 +478,8 @@ pub fn sys_read(fd: usize, buf: VirtAddr, len: usize) -> isize {
                 drop(arcpcb);
                 match file.read_user_buffer(buf) {
                     Ok(size) => size as isize,
+                    Err(ErrNo::InterruptedSystemCall) => -(ErrNo::InterruptedSystemCall as isize),
+                    Err(ErrNo::IOError) => -(ErrNo::IOError as isize),
                     Err(msg) => {
                         error!("Read failed with msg \"{}\"", msg);
                         -1
@@ -309,52 +498,71 @@ pub fn sys_read(fd: usize, buf: VirtAddr, len: usize) -> isize {
 }
 
 /// Read multiple buffers of data described by iov to the file descriptor
+/// # Description
+/// If an error occurs after some data has been read, the bytes read so far are returned.
+/// Reading stops at the first short read.
 /// # Returns
 /// How many bytes hace been really read from the fd.
 pub fn sys_readv(fd: usize, iov: VirtAddr, iovcnt: usize) -> isize {
-    let process = current_process().unwrap();
-    let arcpcb = process.get_inner_locked();
-    
-    if fd as usize > arcpcb.files.len() {
-        error!("Invalid FD");
-        return -1;
-    }
+    let (file, bufs) = match get_iov_buffers(fd, iov, iovcnt) {
+        Ok(res) => res,
+        Err(msg) => {
+            error!("Readv failed with msg \"{}\"", msg);
+            return -1;
+        }
+    };
 
     let mut ret = 0;
-    match &arcpcb.files[fd] {
-        Some(file) => {
-            let file = file.clone();
-            for i in 0..iovcnt {
-                let iov_addr = iov + size_of::<iovec>() * i;
-                let iov_struct: iovec = arcpcb.layout.read_user_data(iov_addr);
-                let buf = arcpcb.layout.get_user_buffer(VirtAddr::from(iov_struct.iov_base), iov_struct.iov_len);
-                match file.read_user_buffer(buf) {
-                    Ok(size) => { ret += size as isize; },
-                    Err(msg) => {
-                        error!("Read failed with msg \"{}\"", msg);
-                        return -1;
-                    }
+    for buf in bufs {
+        let len = buf.len();
+        match file.read_user_buffer(buf) {
+            Ok(size) => {
+                ret += size as isize;
+                if size < len {
+                    break;
                 }
+            },
+            Err(msg) => {
+                error!("Read failed with msg \"{}\"", msg);
+                if ret == 0 {
+                    return -1;
+                }
+                break;
             }
-            drop(arcpcb);
-            ret
-        },
-        None => {
-            error!("No such file descriptor!");
-            return -1;
         }
     }
+    ret
 }
 
+/// Open flag: set close-on-exec on the new fd
+pub const O_CLOEXEC: usize = 0o2000000;
+/// Open flag: non-blocking I/O
+pub const O_NONBLOCK: usize = 0o4000;
+pub const O_TMPFILE: usize = 0o20200000;
+
 /// Create a pipe, and write the two FDs into the `pipe` array.
-pub fn sys_pipe(pipe: VirtAddr) -> isize {
+/// # Description
+/// This is pipe2, `flags` may contain O_CLOEXEC and O_NONBLOCK, which apply to both ends.
+pub fn sys_pipe(pipe: VirtAddr, flags: usize) -> isize {
+    if flags & !(O_CLOEXEC | O_NONBLOCK) != 0 {
+        error!("sys_pipe: unsupported flags {:#x}", flags);
+        return -1;
+    }
     let process = current_process().unwrap();
     let mut arcpcb = process.get_inner_locked();
     let (read, write) = make_pipe();
+    if flags & O_NONBLOCK != 0 {
+        read.set_nonblock(true);
+        write.set_nonblock(true);
+    }
     let wd = arcpcb.alloc_fd();
     arcpcb.files[wd] = Some(write);
     let rd = arcpcb.alloc_fd();
     arcpcb.files[rd] = Some(read);
+    if flags & O_CLOEXEC != 0 {
+        arcpcb.cloexec.insert(rd);
+        arcpcb.cloexec.insert(wd);
+    }
     verbose!("pipe fd: rd {}, wd {}", rd, wd);
     arcpcb.layout.write_user_data(pipe, &(rd as i32));
     arcpcb.layout.write_user_data(pipe + size_of::<i32>(), &(wd as i32));
@@ -383,22 +591,56 @@ pub fn sys_dup(fd: usize) -> isize {
 }
 
 /// Duplicate a file descriptor, and place it into a specified fd.
-pub fn sys_dup3(old_fd: usize, new_fd: usize, _: usize) -> isize {
+/// # Description
+/// Unlike dup3, dup2 to the same fd is a no-op that checks old_fd is valid and returns it.  
+/// Reached through the private `SYSCALL_DUP2` number only, linux on riscv64 has no dup2.
+pub fn sys_dup2(old_fd: usize, new_fd: usize) -> isize {
+    if old_fd == new_fd {
+        let process = current_process().unwrap();
+        let arcpcb = process.get_inner_locked();
+        if let Some(Some(_)) = arcpcb.files.get(old_fd) {
+            return new_fd as isize;
+        } else {
+            error!("No such file descriptor.");
+            return -1;
+        }
+    }
+    sys_dup3(old_fd, new_fd, 0)
+}
+
+/// Duplicate a file descriptor, and place it into a specified fd.
+pub fn sys_dup3(old_fd: usize, new_fd: usize, flags: usize) -> isize {
     let process = current_process().unwrap();
     let mut arcpcb = process.get_inner_locked();
     
-    if old_fd as usize > arcpcb.files.len() {
+    if old_fd as usize >= arcpcb.files.len() {
         error!("Invalid FD");
         return -1;
     }
 
+    if old_fd == new_fd {
+        error!("dup3: old_fd equals new_fd");
+        return -1;
+    }
+
     if let Some(src) = arcpcb.files[old_fd].clone() {
+        let mut replaced = None;
         if arcpcb.files.len() <= new_fd {
             arcpcb.files.resize(new_fd + 1, None);
         } else if arcpcb.files[new_fd].is_some() {
-            arcpcb.files[new_fd].take();
+            replaced = arcpcb.files[new_fd].take();
         }
         arcpcb.files[new_fd] = Some(src);
+        if flags & O_CLOEXEC != 0 {
+            arcpcb.cloexec.insert(new_fd);
+        } else {
+            arcpcb.cloexec.remove(&new_fd);
+        }
+        drop(arcpcb);
+        // new_fd is closed silently, its locks go like on close
+        if let Some(file) = replaced {
+            fs::release_record_locks(&file, process.get_pid());
+        }
         new_fd as isize
     } else {
         error!("No such file descriptor.");
@@ -406,15 +648,31 @@ pub fn sys_dup3(old_fd: usize, new_fd: usize, _: usize) -> isize {
     }
 }
 
-/// The Linux style dirent struct
-#[repr(C)]
-#[derive(Copy, Clone, Debug)]
-pub struct dirent {
-    d_ino: u64,
-    d_off: u64,
-    d_reclen: u16,
-    d_type: u8,
-    d_name: [u8; 128],
+/// Size of the fixed part of the Linux style dirent64, i.e. d_ino, d_off, d_reclen and d_type. The name follows.
+const DIRENT64_HEADER: usize = 19;
+
+lazy_static! {
+    /// Next entry getdents64 returns for each open directory, as directory files have no cursor
+    static ref DIR_POS: Mutex<Vec<(Weak<dyn File>, usize)>> = Mutex::new(Vec::new());
+}
+
+/// Check if `owner` is the open file `file`
+fn same_open_file(owner: &Weak<dyn File>, file: &Arc<dyn File>) -> bool {
+    Weak::as_ptr(owner) as *const u8 == Arc::as_ptr(file) as *const u8
+}
+
+fn dir_pos(file: &Arc<dyn File>) -> usize {
+    DIR_POS.lock().iter().find(|(owner, _)| same_open_file(owner, file)).map_or(0, |(_, pos)| *pos)
+}
+
+fn set_dir_pos(file: &Arc<dyn File>, pos: usize) {
+    let mut positions = DIR_POS.lock();
+    // directories closed since
+    positions.retain(|(owner, _)| owner.strong_count() != 0);
+    match positions.iter_mut().find(|(owner, _)| same_open_file(owner, file)) {
+        Some((_, old)) => *old = pos,
+        None => positions.push((Arc::downgrade(file), pos)),
+    }
 }
 
 #[repr(u8)]
@@ -442,44 +700,53 @@ fn ftype2posix(ft: FileType) -> POSIXDType {
     }
 }
 
-/// Get dirents of a directory.
-pub fn sys_getdents64(fd: usize, buf: VirtAddr, len: usize) -> isize {
+/// Fill `buf` with the dirent64 records of the entries of directory `fd` not returned yet
+/// # Return
+/// # of bytes filled, 0 at the end of the directory. Err(InvalidArgument) if the next record doesn't fit in `len`.
+fn getdents64_inner(fd: usize, buf: VirtAddr, len: usize) -> Result<usize, ErrNo> {
     let process = current_process().unwrap();
-    let arcpcb = process.get_inner_locked();
-    let mut last_ptr = buf;
-    
-    if fd as usize > arcpcb.files.len() {
-        error!("Invalid FD");
-        return -1;
+    let file = process.get_inner_locked().files.get(fd).cloned().flatten().ok_or(ErrNo::BadFileDescriptor)?;
+    let dir = file.clone().to_dir_file().ok_or(ErrNo::NotADirectory)?;
+    let entries = dir.list();
+    let mut pos = dir_pos(&file);
+    let mut records: Vec<u8> = Vec::new();
+    while pos < entries.len() {
+        let f_stat = entries[pos].poll();
+        verbose!("current file: {:?}", f_stat);
+        let name = f_stat.name.as_bytes();
+        // name is NUL terminated, records are 8 bytes aligned
+        let reclen = (DIRENT64_HEADER + name.len() + 1 + 7) & !7;
+        if records.len() + reclen > len {
+            break;
+        }
+        records.extend_from_slice(&f_stat.inode.to_ne_bytes());
+        // d_off is where the next record starts
+        records.extend_from_slice(&((pos + 1) as u64).to_ne_bytes());
+        records.extend_from_slice(&(reclen as u16).to_ne_bytes());
+        records.push(ftype2posix(f_stat.ftype) as u8);
+        records.extend_from_slice(name);
+        records.resize(records.len() + reclen - DIRENT64_HEADER - name.len(), 0);
+        pos += 1;
     }
-    
-    if let Some(file) = arcpcb.files[fd].clone() {
-        if let Some(dir) = file.to_dir_file() {
-            for f in dir.list() {
-                let f_stat = f.poll();
-                let mut dirent_item = dirent {
-                    // TODO: d_ino
-                    d_ino : 0,
-                    d_off : size_of::<dirent>().try_into().unwrap(),
-                    d_reclen: f_stat.name.len() as u16,
-                    d_name: [0; 128],
-                    d_type: ftype2posix(f_stat.ftype) as u8,
-                };
-                verbose!("current file: {:?}", f_stat);
-                let name_bytes = f_stat.name.as_bytes();
-                dirent_item.d_name[0..name_bytes.len()].copy_from_slice(&name_bytes);
-                arcpcb.layout.write_user_data(last_ptr, &dirent_item);
-                last_ptr = last_ptr + size_of::<dirent>();
-            }
-            verbose!("Getdents64 returns {}", (last_ptr - buf));
-            (last_ptr - buf) as i32 as isize
-        } else {
-            error!("Not a directory.");
-            -1
+    if records.is_empty() && pos < entries.len() {
+        return Err(ErrNo::InvalidArgument);
+    }
+    copy_to_user(buf, &records)?;
+    set_dir_pos(&file, pos);
+    Ok(records.len())
+}
+
+/// Get dirents of a directory.
+pub fn sys_getdents64(fd: usize, buf: VirtAddr, len: usize) -> isize {
+    match getdents64_inner(fd, buf, len) {
+        Ok(filled) => {
+            verbose!("Getdents64 returns {}", filled);
+            filled as isize
+        },
+        Err(errno) => {
+            debug!("getdents64 failed: {}", errno);
+            -(errno as isize)
         }
-    } else {
-        error!("No such file descriptor.");
-        -1
     }
 }
 
@@ -499,7 +766,7 @@ pub fn sys_unlink(dirfd: i32, path: VirtAddr, _: usize) -> isize{
         Ok(()) => return 0,
         Err(msg) => {
             error!("sys_unlink:{}", msg);
-            return -1;
+            return -(msg as isize);
         }
     };
 }
@@ -525,8 +792,61 @@ pub fn sys_mkdirat(dirfd: usize, path: VirtAddr, _: usize) -> isize {
     }
 }
 
+/// Create the device node `path` relative to `dirfd`
+/// # Description
+/// Absolute paths and paths relative to cwd go through the mount manager,
+/// paths relative to an opened directory go to the file system of that directory.
+fn make_node_at(dirfd: usize, path: &str, ftype: FileType, major: u32, minor: u32) -> Result<(), ErrNo> {
+    let path = parse_path(path).map_err(|_| ErrNo::NoSuchFileOrDirectory)?;
+    if path.path.len() == 0 {
+        return Err(ErrNo::FileExists);
+    }
+    if path.is_abs || dirfd == AT_FDCWD as usize {
+        let cwd = current_process().unwrap().get_inner_locked().path.clone();
+        let cwd = parse_path(&cwd).map_err(|_| ErrNo::NoSuchFileOrDirectory)?;
+        mknod(path.canonicalize(&cwd).to_string(), ftype, major, minor)
+    } else {
+        let dir = get_file_fd(dirfd)?;
+        if dir.clone().to_dir_file().is_none() {
+            return Err(ErrNo::NotADirectory);
+        }
+        let mut abs_path = dir.get_path();
+        abs_path.merge(path).map_err(|_| ErrNo::InvalidArgument)?;
+        dir.get_vfs()?.mknod(abs_path, ftype, major, minor)
+    }
+}
+
+pub fn sys_mknodat(dirfd: usize, path: VirtAddr, mode: usize, dev: usize) -> isize {
+    let proc = current_process().unwrap();
+    let buf = proc.get_inner_locked().layout.get_user_cstr(path);
+    let path = match core::str::from_utf8(&buf) {
+        Ok(p) => p,
+        Err(_) => {
+            error!("sys_mknodat: {}: invalid path string", ErrNo::InvalidArgument as isize);
+            return -(ErrNo::InvalidArgument as isize);
+        },
+    };
+    let ftype = match mode & 0o170000 {
+        0o020000 => FileType::CharDev,
+        0o060000 => FileType::BlockDev,
+        // regular files, fifos and sockets are made with openat / mkfifo, not here
+        _ => return -(ErrNo::OperationNotPermitted as isize),
+    };
+    // same encoding as glibc's makedev()
+    let major = ((dev >> 8) & 0xfff) as u32 | ((dev >> 32) & !0xfff) as u32;
+    let minor = (dev & 0xff) as u32 | ((dev >> 12) & !0xff) as u32;
+    debug!("mknod: {} {:?} {}:{}", path, ftype, major, minor);
+    match make_node_at(dirfd, path, ftype, major, minor) {
+        Ok(()) => 0,
+        Err(msg) => {
+            error!("sys_mknodat: {}: {}", msg as isize, msg);
+            -(msg as isize)
+        },
+    }
+}
+
 #[repr(C)]
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub struct FStat {
     pub st_dev: u64,
     pub st_ino: u64,
@@ -591,11 +911,19 @@ bitflags! {
         const AT_SYMLINK_FOLLOW     = 0x400;
         const AT_NO_AUTOMOUNT       = 0x800;
         const AT_EMPTY_PATH         = 0x1000;
+        const AT_STATX_FORCE_SYNC   = 0x2000;
+        const AT_STATX_DONT_SYNC    = 0x4000;
     }
 }
 
-fn fstatat(fd: usize, path: &str, ptr: VirtAddr, flags: AtFlags) -> Result<(), ErrNo> {
-    if path.len() == 0 && !flags.contains(AtFlags::AT_EMPTY_PATH) {
+/// Resolve the file a stat-like syscall refers to
+/// # Description
+/// An empty path with AT_EMPTY_PATH refers to the file behind fd itself (or cwd for AT_FDCWD).
+fn get_stat_file(fd: usize, path: &str, flags: AtFlags) -> Result<Arc<dyn File>, ErrNo> {
+    if path.len() == 0 {
+        if flags.contains(AtFlags::AT_EMPTY_PATH) {
+            return get_file_fd(fd);
+        }
         return Err(ErrNo::NoSuchFileOrDirectory);
     }
     let mode = if flags.contains(AtFlags::AT_SYMLINK_NOFOLLOW) {
@@ -605,43 +933,21 @@ fn fstatat(fd: usize, path: &str, ptr: VirtAddr, flags: AtFlags) -> Result<(), E
     };
 
     match get_file(fd, path, mode) {
-        Ok(file) => {
-            match getFStat(&file) {
-                Ok(stat) => {
-                    verbose!("Stat: {:?}", stat);
-                    current_process().unwrap()
-                        .get_inner_locked()
-                        .layout.write_user_data(ptr, &stat);
-                    return Ok(());
-                },
-                Err(msg) => return Err(msg),
-            }
-        },
-        Err(ErrNo::IsADirectory) => {
-            match get_file(fd, path, mode | OpenMode::DIR) {
-                Ok(file) => {
-                    match getFStat(&file) {
-                        Ok(stat) => {
-                            verbose!("Stat: {:?}", stat);
-                            current_process().unwrap()
-                                .get_inner_locked()
-                                .layout.write_user_data(ptr, &stat);
-                            return Ok(());
-                        },
-                        Err(msg) => return Err(msg),
-                    }
-                },
-                Err(errno) => {
-                    return Err(errno);
-                },
-            }
-        },
-        Err(errno) => {
-            return Err(errno);
-        }
+        Err(ErrNo::IsADirectory) => get_file(fd, path, mode | OpenMode::DIR),
+        res => res,
     }
 }
 
+fn fstatat(fd: usize, path: &str, ptr: VirtAddr, flags: AtFlags) -> Result<(), ErrNo> {
+    let file = get_stat_file(fd, path, flags)?;
+    let stat = getFStat(&file)?;
+    verbose!("Stat: {:?}", stat);
+    current_process().unwrap()
+        .get_inner_locked()
+        .layout.write_user_data(ptr, &stat);
+    return Ok(());
+}
+
 pub fn sys_fstat(fd: usize, ptr: VirtAddr) -> isize {
     match fstatat(fd, &"", ptr, AtFlags::AT_EMPTY_PATH) {
         Ok(()) => return 0,
@@ -654,7 +960,10 @@ pub fn sys_fstat(fd: usize, ptr: VirtAddr) -> isize {
 
 
 pub fn sys_fstatat(dirfd: usize, path: VirtAddr, ptr: VirtAddr, flags:usize) -> isize{
-    let buf = current_process().unwrap().get_inner_locked().layout.get_user_cstr(path);
+    let mut buf = current_process().unwrap().get_inner_locked().layout.get_user_cstr(path);
+    if buf.last() == Some(&0) {
+        buf.pop();
+    }
     let path = match core::str::from_utf8(&buf) {
         Ok(path) => path,
         Err(_) => {
@@ -697,6 +1006,133 @@ pub fn sys_ioctl(fd: usize, request: u64, argp: VirtAddr) -> isize {
     }
 }
 
+pub fn sys_fallocate_inner(fd: usize, mode: usize, offset: usize, len: usize) -> Result<(), ErrNo> {
+    let proc = current_process().ok_or(ErrNo::NoSuchProcess)?;
+    let file = proc.get_inner_locked().files.get(fd).ok_or(ErrNo::BadFileDescriptor)?.clone().ok_or(ErrNo::BadFileDescriptor)?;
+    let common_file = file.to_common_file().ok_or(ErrNo::NotSuchDevice)?;
+    common_file.fallocate(mode, offset, len)
+}
+
+/// Preallocate disk space for the file referred by fd
+/// # Returns
+/// 0 on success, -ENOSPC if the disk can't hold the range.
+pub fn sys_fallocate(fd: usize, mode: usize, offset: usize, len: usize) -> isize {
+    match sys_fallocate_inner(fd, mode, offset, len) {
+        Ok(()) => 0,
+        Err(errno) => {
+            debug!("fallocate failed: {}", errno);
+            -(errno as isize)
+        }
+    }
+}
+
+/// Write back all dirty data of every mounted filesystem
+pub fn sys_sync() -> isize {
+    fs::sync_all();
+    0
+}
+
+pub fn sys_syncfs_inner(fd: usize) -> Result<(), ErrNo> {
+    let proc = current_process().ok_or(ErrNo::NoSuchProcess)?;
+    let file = proc.get_inner_locked().files.get(fd).ok_or(ErrNo::BadFileDescriptor)?.clone().ok_or(ErrNo::BadFileDescriptor)?;
+    file.get_vfs()?.sync(true);
+    Ok(())
+}
+
+/// Write back all dirty data of the filesystem containing fd
+pub fn sys_syncfs(fd: usize) -> isize {
+    match sys_syncfs_inner(fd) {
+        Ok(()) => 0,
+        Err(errno) => {
+            debug!("syncfs failed: {}", errno);
+            -(errno as isize)
+        }
+    }
+}
+
+/// struct statfs of riscv64 linux
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct Statfs {
+    pub f_type: i64,
+    pub f_bsize: i64,
+    pub f_blocks: u64,
+    pub f_bfree: u64,
+    pub f_bavail: u64,
+    pub f_files: u64,
+    pub f_ffree: u64,
+    pub f_fsid: [i32; 2],
+    pub f_namelen: i64,
+    pub f_frsize: i64,
+    pub f_flags: i64,
+    pub f_spare: [i64; 4],
+}
+
+/// Fill the statfs of the filesystem containing `file` into `buf`
+fn write_statfs(file: Arc<dyn File>, buf: VirtAddr) -> Result<(), ErrNo> {
+    let status = file.get_vfs()?.statfs();
+    let statfs = Statfs {
+        f_type: status.magic as i64,
+        f_bsize: status.block_size as i64,
+        f_blocks: status.total_blocks as u64,
+        f_bfree: status.free_blocks as u64,
+        f_bavail: status.avail_blocks as u64,
+        f_files: status.total_inodes as u64,
+        f_ffree: status.free_inodes as u64,
+        f_fsid: [0; 2],
+        f_namelen: status.name_max as i64,
+        f_frsize: status.block_size as i64,
+        f_flags: 0,
+        f_spare: [0; 4],
+    };
+    write_to_user(buf, &statfs)
+}
+
+pub fn sys_statfs_inner(path: VirtAddr, buf: VirtAddr) -> Result<(), ErrNo> {
+    let proc = current_process().ok_or(ErrNo::NoSuchProcess)?;
+    let mut pbuf = proc.get_inner_locked().layout.get_user_cstr(path);
+    if pbuf.last() == Some(&0) {
+        pbuf.pop();
+    }
+    let path = core::str::from_utf8(&pbuf).map_err(|_| ErrNo::InvalidArgument)?;
+    if path.len() == 0 {
+        return Err(ErrNo::NoSuchFileOrDirectory);
+    }
+    let file = match get_file(AT_FDCWD as usize, path, OpenMode::SYS) {
+        Err(ErrNo::IsADirectory) => get_file(AT_FDCWD as usize, path, OpenMode::SYS | OpenMode::DIR)?,
+        file => file?,
+    };
+    write_statfs(file, buf)
+}
+
+/// Get the block and inode counts of the filesystem containing `path`
+pub fn sys_statfs(path: VirtAddr, buf: VirtAddr) -> isize {
+    match sys_statfs_inner(path, buf) {
+        Ok(()) => 0,
+        Err(errno) => {
+            debug!("statfs failed: {}", errno);
+            -(errno as isize)
+        }
+    }
+}
+
+pub fn sys_fstatfs_inner(fd: usize, buf: VirtAddr) -> Result<(), ErrNo> {
+    let proc = current_process().ok_or(ErrNo::NoSuchProcess)?;
+    let file = proc.get_inner_locked().files.get(fd).ok_or(ErrNo::BadFileDescriptor)?.clone().ok_or(ErrNo::BadFileDescriptor)?;
+    write_statfs(file, buf)
+}
+
+/// Get the block and inode counts of the filesystem containing fd
+pub fn sys_fstatfs(fd: usize, buf: VirtAddr) -> isize {
+    match sys_fstatfs_inner(fd, buf) {
+        Ok(()) => 0,
+        Err(errno) => {
+            debug!("fstatfs failed: {}", errno);
+            -(errno as isize)
+        }
+    }
+}
+
 pub fn read_linux_fstat(file: Arc<dyn File>) -> FStat {
     let f_stat = file.poll();
     let mut linux_mode: u32 = 0;
@@ -735,9 +1171,6 @@ pub fn sys_fstatat_new(fd: i32, path: VirtAddr, ptr: VirtAddr, flags:usize) -> i
     let arcpcb = process.get_inner_locked();
     let mut buf = arcpcb.layout.get_user_cstr(path);
     buf = buf[..buf.len() - 1].to_vec(); // remove \0
-    if buf.len() > 1 && buf[0] == b'/' && buf[1] == b'/' {
-        buf = buf[2..].to_vec();
-    }
     let mut fs_flags = OpenMode::SYS;
     if flags.contains(AtFlags::AT_SYMLINK_NOFOLLOW) {
         fs_flags |= OpenMode::NO_FOLLOW;
@@ -745,19 +1178,16 @@ pub fn sys_fstatat_new(fd: i32, path: VirtAddr, ptr: VirtAddr, flags:usize) -> i
 
     if let Ok(mut path) = core::str::from_utf8(&buf) {
         verbose!("Path: {}", path);
-        if path.starts_with("/") {
-            if let Ok(file) = open(path.to_string(), OpenMode::SYS) {
-                arcpcb.layout.write_user_data(ptr, &(read_linux_fstat(file)));
+        if path.len() == 0 && flags.contains(AtFlags::AT_EMPTY_PATH) {
+            if let Some(Some(file)) = arcpcb.files.get(fd as usize) {
+                arcpcb.layout.write_user_data(ptr, &(read_linux_fstat(file.clone())));
                 return 0;
             }
             return -1;
-        } else if flags.contains(AtFlags::AT_EMPTY_PATH) {
-            if let Some(slot) = arcpcb.files.get(fd as usize) {
-                if let Some(file) = slot {
-                    arcpcb.layout.write_user_data(ptr, &(read_linux_fstat(file.clone())));
-                    return 0;
-                }
-                return -1;
+        } else if path.starts_with("/") {
+            if let Ok(file) = open(path.to_string(), OpenMode::SYS) {
+                arcpcb.layout.write_user_data(ptr, &(read_linux_fstat(file)));
+                return 0;
             }
             return -1;
         } else if fd == AT_FDCWD {
@@ -786,61 +1216,249 @@ pub fn sys_fstatat_new(fd: i32, path: VirtAddr, ptr: VirtAddr, flags:usize) -> i
     return -1;
 }
 
+bitflags! {
+    pub struct StatxMask: u32 {
+        const TYPE          = 0x0001;
+        const MODE          = 0x0002;
+        const NLINK         = 0x0004;
+        const UID           = 0x0008;
+        const GID           = 0x0010;
+        const ATIME         = 0x0020;
+        const MTIME         = 0x0040;
+        const CTIME         = 0x0080;
+        const INO           = 0x0100;
+        const SIZE          = 0x0200;
+        const BLOCKS        = 0x0400;
+        const BASIC_STATS   = 0x07ff;
+        const BTIME         = 0x0800;
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct StatxTimestamp {
+    pub tv_sec: i64,
+    pub tv_nsec: u32,
+    pub __reserved: i32,
+}
+
+impl StatxTimestamp {
+    fn new(sec: u32, nsec: u32) -> Self {
+        Self {
+            tv_sec: sec as i64,
+            tv_nsec: nsec,
+            __reserved: 0,
+        }
+    }
+}
+
+/// The Linux style statx struct
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Statx {
+    pub stx_mask: u32,
+    pub stx_blksize: u32,
+    pub stx_attributes: u64,
+    pub stx_nlink: u32,
+    pub stx_uid: u32,
+    pub stx_gid: u32,
+    pub stx_mode: u16,
+    pub __spare0: u16,
+    pub stx_ino: u64,
+    pub stx_size: u64,
+    pub stx_blocks: u64,
+    pub stx_attributes_mask: u64,
+    pub stx_atime: StatxTimestamp,
+    pub stx_btime: StatxTimestamp,
+    pub stx_ctime: StatxTimestamp,
+    pub stx_mtime: StatxTimestamp,
+    pub stx_rdev_major: u32,
+    pub stx_rdev_minor: u32,
+    pub stx_dev_major: u32,
+    pub stx_dev_minor: u32,
+    pub __spare2: [u64; 14],
+}
+
+/// Fill a statx from the file status
+/// # Description
+/// Every field the file has is filled whatever `mask` asks for, `stx_mask` tells which ones those are.
+/// btime is not tracked, and inode 0 means the file system has no inode numbers (FAT32).
+fn getStatx(file: &Arc<dyn File>) -> Statx {
+    let f_stat = file.poll();
+    let fstat = read_linux_fstat(file.clone());
+    let mut filled = StatxMask::BASIC_STATS;
+    let mut statx = Statx::default();
+    statx.stx_blksize = f_stat.block_sz;
+    statx.stx_dev_major = (f_stat.dev_no >> 8) as u32;
+    statx.stx_dev_minor = (f_stat.dev_no & 0xff) as u32;
+    statx.stx_mode = fstat.st_mode as u16;
+    statx.stx_nlink = fstat.st_nlink;
+    statx.stx_uid = f_stat.uid;
+    statx.stx_gid = f_stat.gid;
+    statx.stx_atime = StatxTimestamp::new(f_stat.atime_sec, f_stat.atime_nsec);
+    statx.stx_mtime = StatxTimestamp::new(f_stat.mtime_sec, f_stat.mtime_nsec);
+    statx.stx_ctime = StatxTimestamp::new(f_stat.ctime_sec, f_stat.ctime_nsec);
+    if f_stat.inode != 0 {
+        statx.stx_ino = f_stat.inode;
+    } else {
+        filled.remove(StatxMask::INO);
+    }
+    statx.stx_size = f_stat.size;
+    statx.stx_blocks = f_stat.blocks;
+    statx.stx_mask = filled.bits();
+    statx
+}
+
+fn statx(dirfd: usize, path: &str, flags: AtFlags, ptr: VirtAddr) -> Result<(), ErrNo> {
+    if flags.contains(AtFlags::AT_STATX_FORCE_SYNC | AtFlags::AT_STATX_DONT_SYNC) {
+        return Err(ErrNo::InvalidArgument);
+    }
+    let file = get_stat_file(dirfd, path, flags)?;
+    // DONT_SYNC: metadata from poll() is already the cached copy
+    if flags.contains(AtFlags::AT_STATX_FORCE_SYNC) {
+        if let Ok(vfs) = file.get_vfs() {
+            vfs.sync(true);
+        }
+    }
+    let statx = getStatx(&file);
+    verbose!("Statx: {:?}", statx);
+    current_process().unwrap()
+        .get_inner_locked()
+        .layout.write_user_data(ptr, &statx);
+    Ok(())
+}
+
+/// Get file status (extended)
+/// # Description
+/// The requested mask is only a hint, every field the file has is filled.
+pub fn sys_statx(dirfd: usize, path: VirtAddr, flags: usize, _mask: u32, ptr: VirtAddr) -> isize {
+    let mut buf = current_process().unwrap().get_inner_locked().layout.get_user_cstr(path);
+    if buf.last() == Some(&0) {
+        buf.pop();
+    }
+    let path = match core::str::from_utf8(&buf) {
+        Ok(path) => path,
+        Err(_) => {
+            debug!("sys_statx: invalid path string");
+            return -(ErrNo::InvalidArgument as isize);
+        }
+    };
+    let flags = match AtFlags::from_bits(flags) {
+        Some(flags) => flags,
+        None => {
+            debug!("sys_statx: invalid flags");
+            return -(ErrNo::InvalidArgument as isize);
+        },
+    };
+    match statx(dirfd, path, flags, ptr) {
+        Ok(()) => 0,
+        Err(errno) => {
+            debug!("sys_statx: {}", errno);
+            -(errno as isize)
+        }
+    }
+}
+
 pub const SEND_FILE_CHUNK_SZ: usize = 4096;
 
-fn sys_sendfile_wrapper(write_fd: usize, read_fd: usize, offset_ptr: VirtAddr, mut count: usize) -> Result<usize, ErrNo> {
+fn sys_sendfile_wrapper(write_fd: usize, read_fd: usize, offset_ptr: VirtAddr, count: usize) -> Result<usize, ErrNo> {
     let proc = current_process().unwrap();
     let locked_inner = proc.get_inner_locked();
 
-    let mut result: usize = 0;
     let write_file = locked_inner.files.get(write_fd).ok_or(ErrNo::BadFileDescriptor)?.clone().ok_or(ErrNo::BadFileDescriptor)?;
     let read_file = locked_inner.files.get(read_fd).ok_or(ErrNo::BadFileDescriptor)?.clone().ok_or(ErrNo::BadFileDescriptor)?;
 
+    let mut offset = None;
     if offset_ptr.0 != 0 {
-        let offset: u32 = locked_inner.layout.read_user_data(offset_ptr);
-        read_file.seek(offset as isize, fs::SeekOp::SET)?;
+        let start: i64 = locked_inner.layout.read_user_data(offset_ptr);
+        if start < 0 {
+            return Err(ErrNo::InvalidArgument);
+        }
+        offset = Some(start as usize);
     }
 
     drop(locked_inner);
     drop(proc);
 
+    let result = send_file(&write_file, &read_file, offset, count)?;
+
+    if let Some(start) = offset {
+        let final_offset = (start + result) as i64;
+        let proc = current_process().unwrap();
+        let locked_inner = proc.get_inner_locked();
+        locked_inner.layout.write_user_data(offset_ptr, &final_offset);
+    }
+
+    Ok(result)
+}
+
+/// Copy at most `count` bytes from `read_file` to `write_file`.
+/// # Description
+/// With an `offset`, copying starts from it and the cursor of `read_file` is left untouched.
+/// A regular input shorter than `count` gives a short copy. An error after some bytes have been
+/// copied ends the copy early.
+/// # Returns
+/// The number of bytes copied, or the error if nothing was copied.
+pub fn send_file(write_file: &Arc<dyn File>, read_file: &Arc<dyn File>, offset: Option<usize>, count: usize) -> Result<usize, ErrNo> {
+    let mut saved_cursor = None;
+    if let Some(offset) = offset {
+        saved_cursor = Some(read_file.get_cursor()?);
+        read_file.seek(offset as isize, fs::SeekOp::SET)?;
+    }
+    let result = send_file_inner(write_file, read_file, count);
+    if let Some(cursor) = saved_cursor {
+        read_file.seek(cursor as isize, fs::SeekOp::SET)?;
+    }
+    result
+}
+
+fn send_file_inner(write_file: &Arc<dyn File>, read_file: &Arc<dyn File>, mut count: usize) -> Result<usize, ErrNo> {
     verbose!("Sending from {} to {}, initial offset @ {}", read_file.poll().name, write_file.poll().name, read_file.get_cursor()?);
 
-    count = _core::cmp::min(read_file.poll().size as usize - read_file.get_cursor()? as usize, count);
+    let stat = read_file.poll();
+    let is_regular = stat.ftype == FileType::Regular;
+    if is_regular {
+        count = _core::cmp::min((stat.size as usize).saturating_sub(read_file.get_cursor()?), count);
+    }
 
+    let mut result: usize = 0;
+    let mut buf: Vec<u8> = Vec::with_capacity(SEND_FILE_CHUNK_SZ);
     while count > 0 {
         let mut move_sz = _core::cmp::min(count, SEND_FILE_CHUNK_SZ);
-        let mut buf: Vec<u8> = Vec::with_capacity(move_sz);
         buf.resize(move_sz, 0);
         verbose!("Trying to send {} bytes", move_sz);
-        loop {
-            move_sz = read_file.read(&mut buf)?;
-            if move_sz != 0 {
-                break;
-            } else {
-                suspend_switch();
-            }
+        // blocking reads only come back empty at the end of input
+        move_sz = match read_file.read(&mut buf[..move_sz]) {
+            Ok(read) => read,
+            Err(errno) if result == 0 => return Err(errno),
+            Err(_) => return Ok(result),
+        };
+        // input is shorter than count, short copy
+        if move_sz == 0 {
+            break;
         }
-        buf = buf[..move_sz].to_vec();
-        let mut write_sz_left = move_sz;
-        while write_sz_left > 0 {
-            let write_sz = write_file.write(&mut buf)?;
-            buf = buf[..write_sz].to_vec();
-            write_sz_left -= write_sz;
+        let mut written = 0;
+        while written < move_sz {
+            // blocking writes only come back empty when no one is left to read
+            let write_sz = match write_file.write(&buf[written..move_sz]) {
+                Ok(0) => Err(ErrNo::BrokenPipe),
+                other => other,
+            };
+            match write_sz {
+                Ok(write_sz) => written += write_sz,
+                Err(errno) => {
+                    // give back what was read but not written
+                    read_file.seek(-((move_sz - written) as isize), fs::SeekOp::CUR).ok();
+                    result += written;
+                    return if result == 0 { Err(errno) } else { Ok(result) };
+                }
+            }
         }
         count -= move_sz;
         result += move_sz;
         verbose!("Sended {} bytes, {} remaining", move_sz, count);
     }
 
-    let proc = current_process().unwrap();
-    let locked_inner = proc.get_inner_locked();
-    
-    if offset_ptr.0 != 0 {
-        let final_offset = read_file.get_cursor()? as i32;
-        locked_inner.layout.write_user_data(offset_ptr, &final_offset);
-    }
-
     Ok(result)
 }
 
@@ -7,12 +7,18 @@ use crate::utils::print_kernel_stack;
 pub const SYSCALL_GETCWD            : usize = 17;
 pub const SYSCALL_DUP               : usize = 23;
 pub const SYSCALL_DUP3              : usize = 24;
+pub const SYSCALL_FCNTL             : usize = 25;
 pub const SYSCALL_IOCTL             : usize = 29;
+pub const SYSCALL_FLOCK             : usize = 32;
+pub const SYSCALL_MKNODAT           : usize = 33;
 pub const SYSCALL_MKDIRAT           : usize = 34;
 pub const SYSCALL_UNLINKAT          : usize = 35;
 pub const SYSCALL_LINKAT            : usize = 37;
 pub const SYSCALL_UMOUNT2           : usize = 39;
 pub const SYSCALL_MOUNT             : usize = 40;
+pub const SYSCALL_STATFS            : usize = 43;
+pub const SYSCALL_FSTATFS           : usize = 44;
+pub const SYSCALL_FALLOCATE         : usize = 47;
 pub const SYSCALL_CHDIR             : usize = 49;
 pub const SYSCALL_OPENAT            : usize = 56;
 pub const SYSCALL_OPEN              : usize = 56;
@@ -29,6 +35,7 @@ pub const SYSCALL_PPOLL             : usize = 73;
 pub const SYSCALL_READLINKAT        : usize = 78;
 pub const SYSCALL_FSTATAT           : usize = 79;
 pub const SYSCALL_FSTAT             : usize = 80;
+pub const SYSCALL_SYNC              : usize = 81;
 pub const SYSCALL_EXIT              : usize = 93;
 pub const SYSCALL_EXIT_GROUP        : usize = 94;
 pub const SYSCALL_SET_TID_ADDRESS   : usize = 96;
@@ -36,15 +43,22 @@ pub const SYSCALL_NANOSLEEP         : usize = 101;
 pub const SYSCALL_GETITIMER         : usize = 102;
 pub const SYSCALL_SETITIMER         : usize = 103;
 pub const SYSCALL_CLOCK_GETTIME     : usize = 113;
+pub const SYSCALL_CLOCK_NANOSLEEP   : usize = 115;
+pub const SYSCALL_PTRACE            : usize = 117;
 pub const SYSCALL_SCHED_YIELD       : usize = 124;
 pub const SYSCALL_KILL              : usize = 129;
 pub const SYSCALL_TGKILL            : usize = 131;
+pub const SYSCALL_RT_SIGSUSPEND      : usize = 133;
 pub const SYSCALL_SIGACTION         : usize = 134;
 pub const SYSCALL_SIGPROCMASK       : usize = 135;
 pub const SYSCALL_SIGRETURN         : usize = 139;
+pub const SYSCALL_REBOOT            : usize = 142;
 pub const SYSCALL_TIMES             : usize = 153;
 pub const SYSCALL_UNAME             : usize = 160;
+pub const SYSCALL_SETHOSTNAME       : usize = 161;
+pub const SYSCALL_SETDOMAINNAME     : usize = 162;
 pub const SYSCALL_GETRUSAGE         : usize = 165;
+pub const SYSCALL_PRCTL             : usize = 167;
 pub const SYSCALL_GETTIMEOFDAY      : usize = 169;
 pub const SYSCALL_GETPID            : usize = 172;
 pub const SYSCALL_GETPPID           : usize = 173;
@@ -56,12 +70,23 @@ pub const SYSCALL_GETTID            : usize = 178;
 pub const SYSCALL_SYSINFO           : usize = 179;
 pub const SYSCALL_BRK               : usize = 214;
 pub const SYSCALL_MUNMAP            : usize = 215;
+pub const SYSCALL_MREMAP            : usize = 216;
 pub const SYSCALL_CLONE             : usize = 220;  // is this sys_fork?
 pub const SYSCALL_EXECVE            : usize = 221;  // is this sys_exec?
 pub const SYSCALL_MMAP              : usize = 222;
 pub const SYSCALL_MPROTECT          : usize = 226;
+pub const SYSCALL_RISCV_FLUSH_ICACHE: usize = 259;
 pub const SYSCALL_WAIT4             : usize = 260;  // is this sys_waitpid?
 pub const SYSCALL_WAITPID           : usize = 260;
+pub const SYSCALL_SYNCFS            : usize = 267;
+pub const SYSCALL_PROCESS_VM_READV  : usize = 270;
+pub const SYSCALL_PROCESS_VM_WRITEV : usize = 271;
+pub const SYSCALL_MEMBARRIER        : usize = 283;
+pub const SYSCALL_STATX             : usize = 291;
+/// Private ABI, riscv64 linux has no dup2 and libc emulates it with fcntl and dup3.  
+/// 1041 is the number asm-generic had for dup2 with `__ARCH_WANT_SYSCALL_NO_FLAGS`, which riscv never enables,
+/// so it can't collide with a real syscall. Only programs built for this kernel call it.
+pub const SYSCALL_DUP2              : usize = 1041;
 
 mod fs_syscall;
 mod process_syscall;
@@ -76,16 +101,39 @@ pub use fs_syscall::{
     sys_close,
     sys_pipe,
     sys_dup,
+    sys_dup2,
     sys_dup3,
     sys_getdents64,
+    POSIXDType,
     sys_unlink,
     sys_fstatat,
     sys_fstatat_new,
     sys_fstat, 
+    sys_statx,
+    AtFlags,
+    FStat,
+    Statx,
+    StatxMask,
     sys_readlinkat,
     sys_mkdirat,
+    sys_mknodat,
+    AT_FDCWD,
+    sys_flock,
+    sys_fcntl,
+    Flock,
+    F_GETLK,
+    F_SETLK,
     sys_ioctl,
+    sys_fallocate,
+    sys_sync,
+    sys_syncfs,
+    sys_statfs,
+    sys_fstatfs,
+    Statfs,
     sys_sendfile,
+    send_file,
+    O_CLOEXEC,
+    O_NONBLOCK,
     sys_ppoll,
 };
 pub use process_syscall::{
@@ -96,6 +144,7 @@ pub use process_syscall::{
     sys_clone,
     sys_exec,
     sys_waitpid,
+    WNOHANG,
     sys_getpid,
     sys_getppid,
     sys_getcwd,
@@ -103,27 +152,67 @@ pub use process_syscall::{
     sys_brk,
     sys_mmap,
     sys_munmap,
+    sys_mremap,
     sys_sigreturn,
     sys_sigaction,
     sys_sigprocmask,
+    sys_sigsuspend,
     sys_kill,
     sys_mprotect,
+    sys_membarrier,
+    sys_riscv_flush_icache,
+    MEMBARRIER_CMD_QUERY,
+    MEMBARRIER_CMD_GLOBAL,
+    sys_process_vm_readv,
+    sys_process_vm_writev,
+    sys_ptrace,
+    PTRACE_TRACEME,
+    PTRACE_PEEKDATA,
+    PTRACE_POKEDATA,
+    PTRACE_CONT,
+    PTRACE_SINGLESTEP,
+    PTRACE_GETREGS,
+    sys_prctl,
+    PR_SET_PDEATHSIG,
+    PR_GET_PDEATHSIG,
+    PR_SET_NAME,
+    PR_GET_NAME,
     sys_gettid,
     sys_tgkill,
     sys_getitimer,
     sys_setitimer,
+    itimerval,
+    timeval,
+    ITIMER_REAL,
+    PROT_READ,
+    PROT_WRITE,
+    PROT_EXEC,
+    MAP_PRIVATE,
+    MAP_FIXED,
+    MAP_ANONYMOUS,
+    MREMAP_MAYMOVE,
+    SIG_BLOCK,
+    SIG_SETMASK,
 };
 pub use trivial_syscall::{
     sys_time, 
     sys_uname,
+    sys_sethostname,
+    sys_setdomainname,
     sys_gettimeofday,
     sys_nanosleep,
+    sys_clock_nanosleep,
+    CLOCK_MONOTONIC,
+    TIMER_ABSTIME,
     sys_info,
+    SysInfo,
     sys_getuid,
     sys_geteuid,
     sys_getgid,
     sys_getegid,
-    sys_getrusage
+    sys_getrusage,
+    TimeSPEC,
+    sys_reboot,
 };
 
 use process_syscall::sys_set_tid_address;
@@ -182,26 +271,41 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_GETPID          => {CALL_SYSCALL!(sys_getpid)},
         SYSCALL_GETPPID         => {CALL_SYSCALL!(sys_getppid)},
         SYSCALL_GETCWD          => {CALL_SYSCALL!(sys_getcwd, VirtAddr::from(args[0]), args[1])},
+        SYSCALL_REBOOT          => {CALL_SYSCALL!(sys_reboot, args[0] as u32, args[1] as u32, args[2] as u32, VirtAddr::from(args[3]))},
         SYSCALL_TIMES           => {CALL_SYSCALL!(sys_time, VirtAddr::from(args[0]))},
         SYSCALL_GETTIMEOFDAY    => {CALL_SYSCALL!(sys_gettimeofday, VirtAddr::from(args[0]))},
         SYSCALL_UNAME           => {CALL_SYSCALL!(sys_uname, VirtAddr::from(args[0]))},
-        SYSCALL_PIPE            => {CALL_SYSCALL!(sys_pipe, VirtAddr::from(args[0]))},
+        SYSCALL_SETHOSTNAME     => {CALL_SYSCALL!(sys_sethostname, VirtAddr::from(args[0]), args[1])},
+        SYSCALL_SETDOMAINNAME   => {CALL_SYSCALL!(sys_setdomainname, VirtAddr::from(args[0]), args[1])},
+        SYSCALL_PIPE            => {CALL_SYSCALL!(sys_pipe, VirtAddr::from(args[0]), args[1])},
         SYSCALL_DUP             => {CALL_SYSCALL!(sys_dup, args[0])},
+        SYSCALL_DUP2            => {CALL_SYSCALL!(sys_dup2, args[0], args[1])},
         SYSCALL_DUP3            => {CALL_SYSCALL!(sys_dup3, args[0], args[1], args[2])},
         SYSCALL_OPENAT          => {CALL_SYSCALL!(sys_openat, args[0] as i32, VirtAddr::from(args[1]), args[2] as u32, args[3] as u32)},
         SYSCALL_CLOSE           => {CALL_SYSCALL!(sys_close, args[0])},
+        SYSCALL_FALLOCATE       => {CALL_SYSCALL!(sys_fallocate, args[0], args[1], args[2], args[3])},
+        SYSCALL_SYNC            => {CALL_SYSCALL!(sys_sync)},
+        SYSCALL_SYNCFS          => {CALL_SYSCALL!(sys_syncfs, args[0])},
+        SYSCALL_STATFS          => {CALL_SYSCALL!(sys_statfs, VirtAddr::from(args[0]), VirtAddr::from(args[1]))},
+        SYSCALL_FSTATFS         => {CALL_SYSCALL!(sys_fstatfs, args[0], VirtAddr::from(args[1]))},
         SYSCALL_CHDIR           => {CALL_SYSCALL!(sys_chdir, VirtAddr::from(args[0]))},
         SYSCALL_GETDENTS64      => {CALL_SYSCALL!(sys_getdents64, args[0], VirtAddr::from(args[1]), args[2])},
         SYSCALL_NANOSLEEP       => {CALL_SYSCALL!(sys_nanosleep, VirtAddr::from(args[0]), VirtAddr::from(args[1]))},
+        SYSCALL_CLOCK_NANOSLEEP => {CALL_SYSCALL!(sys_clock_nanosleep, args[0], args[1], VirtAddr::from(args[2]), VirtAddr::from(args[3]))},
         SYSCALL_BRK             => {CALL_SYSCALL!(sys_brk, args[0])},
         SYSCALL_MMAP            => {CALL_SYSCALL!(sys_mmap, VirtAddr::from(args[0]), args[1], args[2], args[3], args[4], args[5])},
         SYSCALL_UNLINKAT        => {CALL_SYSCALL!(sys_unlink, args[0] as i32, VirtAddr::from(args[1]), args[2])},
+        SYSCALL_FCNTL           => {CALL_SYSCALL!(sys_fcntl, args[0], args[1], args[2])},
+        SYSCALL_FLOCK           => {CALL_SYSCALL!(sys_flock, args[0], args[1])},
+        SYSCALL_MKNODAT         => {CALL_SYSCALL!(sys_mknodat, args[0], VirtAddr::from(args[1]), args[2], args[3])},
         SYSCALL_MKDIRAT         => {CALL_SYSCALL!(sys_mkdirat, args[0], VirtAddr::from(args[1]), args[2])},
         SYSCALL_READLINKAT      => {CALL_SYSCALL!(sys_readlinkat, args[0], VirtAddr::from(args[1]), VirtAddr::from(args[2]), args[3])},
         // SYSCALL_FSTATAT         => {CALL_SYSCALL!(sys_fstatat_new, args[0] as i32, VirtAddr::from(args[1]), VirtAddr::from(args[2]), args[3])},
         SYSCALL_FSTATAT         => {CALL_SYSCALL!(sys_fstatat, args[0], VirtAddr::from(args[1]), VirtAddr::from(args[2]), args[3])},
         SYSCALL_FSTAT           => {CALL_SYSCALL!(sys_fstat, args[0], VirtAddr::from(args[1]))},
+        SYSCALL_STATX           => {CALL_SYSCALL!(sys_statx, args[0], VirtAddr::from(args[1]), args[2], args[3] as u32, VirtAddr::from(args[4]))},
         SYSCALL_MUNMAP          => {CALL_SYSCALL!(sys_munmap, VirtAddr::from(args[0]), args[1])},
+        SYSCALL_MREMAP          => {CALL_SYSCALL!(sys_mremap, VirtAddr::from(args[0]), args[1], args[2], args[3], VirtAddr::from(args[4]))},
         SYSCALL_READV           => {CALL_SYSCALL!(sys_readv, args[0], VirtAddr::from(args[1]), args[2])},
         SYSCALL_WRITEV          => {CALL_SYSCALL!(sys_writev, args[0], VirtAddr::from(args[1]), args[2])},
         SYSCALL_SYSINFO         => {CALL_SYSCALL!(sys_info, VirtAddr::from(args[0]))},
@@ -213,8 +317,15 @@ pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
         SYSCALL_SIGRETURN       => {CALL_SYSCALL!(sys_sigreturn)},
         SYSCALL_SIGACTION       => {CALL_SYSCALL!(sys_sigaction, args[0], VirtAddr::from(args[1]), VirtAddr::from(args[2]))},
         SYSCALL_SIGPROCMASK     => {CALL_SYSCALL!(sys_sigprocmask, args[0] as isize, VirtAddr::from(args[1]), VirtAddr::from(args[2]))},
+        SYSCALL_RT_SIGSUSPEND   => {CALL_SYSCALL!(sys_sigsuspend, VirtAddr::from(args[0]))},
         SYSCALL_KILL            => {CALL_SYSCALL!(sys_kill, args[0] as isize, args[1])},
         SYSCALL_MPROTECT        => {CALL_SYSCALL!(sys_mprotect, VirtAddr::from(args[0]), args[1], args[2])},
+        SYSCALL_MEMBARRIER      => {CALL_SYSCALL!(sys_membarrier, args[0], args[1])},
+        SYSCALL_RISCV_FLUSH_ICACHE => {CALL_SYSCALL!(sys_riscv_flush_icache, VirtAddr::from(args[0]), VirtAddr::from(args[1]), args[2])},
+        SYSCALL_PROCESS_VM_READV => {CALL_SYSCALL!(sys_process_vm_readv, args[0], VirtAddr::from(args[1]), args[2], VirtAddr::from(args[3]), args[4], args[5])},
+        SYSCALL_PROCESS_VM_WRITEV => {CALL_SYSCALL!(sys_process_vm_writev, args[0], VirtAddr::from(args[1]), args[2], VirtAddr::from(args[3]), args[4], args[5])},
+        SYSCALL_PTRACE          => {CALL_SYSCALL!(sys_ptrace, args[0], args[1], VirtAddr::from(args[2]), args[3])},
+        SYSCALL_PRCTL           => {CALL_SYSCALL!(sys_prctl, args[0], args[1])},
         SYSCALL_GETTID          => {CALL_SYSCALL!(sys_gettid)}
         SYSCALL_IOCTL           => {CALL_SYSCALL!(sys_ioctl, args[0], args[1] as u64, VirtAddr::from(args[2]))},
         SYSCALL_SENDFILE        => {CALL_SYSCALL!(sys_sendfile, args[0], args[1], VirtAddr::from(args[2]), args[3])}
@@ -1,16 +1,21 @@
 //! Process related syscalls.
 use core::mem::size_of;
 use core::slice::{from_raw_parts, from_raw_parts_mut};
-use crate::process::{PROC0, ProcessControlBlockInner, remove_proc_by_pid};
+use crate::process::{adopt_orphans, ProcessControlBlockInner, remove_proc_by_pid};
 
 use crate::config::PAGE_SIZE;
 use crate::config::CLOCK_FREQ;
-use crate::process::{CloneFlags, PROCESS_MANAGER, current_path, current_process, enqueue, exit_switch, get_proc_by_pid, suspend_switch, ErrNo};
+use crate::process::elf_cache::get_exec_image;
+use crate::process::default_handlers::{SIG_UNBLOCKABLE, SIGTRAP};
+use crate::process::ptrace::{insert_step_breakpoint, remove_step_breakpoint};
+use crate::process::{CloneFlags, TASK_COMM_LEN, PROCESS_MANAGER, current_path, current_process, enqueue, exit_switch, get_proc_by_pid, suspend_switch, sleep_switch, oom_kill, resume, ProcessControlBlock, ErrNo};
 
-use crate::memory::{PhysAddr, Segment, VMAFlags, VirtAddr, alloc_continuous, get_user_cstr, SegmentFlags, PTEFlags};
+use crate::memory::{PhysAddr, Segment, VMAFlags, VirtAddr, alloc_continuous, get_user_cstr, MemLayout, SegmentFlags, PTEFlags, flush_icache, copy_from_user, copy_to_user, read_from_user, write_to_user};
+use super::fs_syscall::iovec;
 
 use crate::process::{
     current_satp,
+    ProcessControlBlock,
     ProcessStatus,
     SigAction
 };
@@ -26,7 +31,9 @@ use spin::{Mutex, MutexGuard};
 
 use crate::fs::{
     File,
+    FileType,
     open,
+    parse_path,
     OpenMode
 };
 
@@ -44,6 +51,23 @@ pub const PROT_NONE		    :usize = 0x0		;/* page can not be accessed */
 pub const PROT_GROWSDOWN    :usize = 0x01000000	;/* mprotect flag: extend change to start of growsdown vma */
 pub const PROT_GROWSUP	    :usize = 0x02000000	;/* mprotect flag: extend change to end of growsup vma */
 
+pub const MAP_SHARED	    :usize = 0x01		;/* Share changes */
+pub const MAP_PRIVATE	    :usize = 0x02		;/* Changes are private */
+pub const MAP_FIXED		    :usize = 0x10		;/* Interpret addr exactly */
+pub const MAP_ANONYMOUS	    :usize = 0x20		;/* don't use a file */
+
+pub const MEMBARRIER_CMD_QUERY                       :usize = 0;
+pub const MEMBARRIER_CMD_GLOBAL                      :usize = 1 << 0;
+pub const MEMBARRIER_CMD_GLOBAL_EXPEDITED            :usize = 1 << 1;
+pub const MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED   :usize = 1 << 2;
+pub const MEMBARRIER_CMD_PRIVATE_EXPEDITED           :usize = 1 << 3;
+pub const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED  :usize = 1 << 4;
+pub const MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE :usize = 1 << 5;
+pub const MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE :usize = 1 << 6;
+
+pub const MREMAP_MAYMOVE    :usize = 0x1		;/* may move the mapping to a new address */
+pub const MREMAP_FIXED	    :usize = 0x2		;/* move to new_address, unsupported */
+
 /// Give up CPU.
 pub fn sys_yield() -> isize {
     suspend_switch();
@@ -61,7 +85,10 @@ pub fn sys_exit(code: i32) -> ! {
 #[deprecated]
 pub fn sys_fork() -> isize {
     let current_proc = current_process().unwrap();
-    let new_proc = current_proc.fork(CloneFlags::from_bits_truncate(0));
+    let new_proc = match current_proc.fork(CloneFlags::from_bits_truncate(0)) {
+        Ok(new_proc) => new_proc,
+        Err(errno) => return -(errno as isize),
+    };
     let new_pid = new_proc.pid.0;
     // return 0 for child process in a0
     new_proc.get_inner_locked().get_trap_context().regs[10] = 0;
@@ -73,7 +100,10 @@ pub fn sys_fork() -> isize {
 /// TODO: Finish it.
 pub fn sys_clone(clone_flags: CloneFlags, stack: usize, parent_tid_ptr: VirtAddr, _tls: usize, child_tid_ptr: VirtAddr) -> isize {
     let current_proc = current_process().unwrap();
-    let new_proc = current_proc.fork(clone_flags);
+    let new_proc = match current_proc.fork(clone_flags) {
+        Ok(new_proc) => new_proc,
+        Err(errno) => return -(errno as isize),
+    };
     let new_pid = new_proc.pid.0;
     // return 0 for child process in a0
     new_proc.get_inner_locked().get_trap_context().regs[10] = 0;
@@ -110,14 +140,16 @@ pub fn sys_exec(app_path_ptr: VirtAddr, argv: VirtAddr, envp: VirtAddr) -> isize
         path.push_str(app_path.as_str());
         app_path = path;
     }
-    if app_path.starts_with("//") {
-        app_path = app_path.get(1..).unwrap().to_string();
-    }
     verbose!("Exec {}", app_path);
 
     match sys_exec_inner(app_path, argv, envp) {
         Ok(res) => {
             // current_process().unwrap().get_inner_locked().layout.print_layout();
+            // a traced process stops before running the new program, so that the tracer can set it up
+            let proc = current_process().unwrap();
+            if proc.get_inner_locked().traced {
+                proc.recv_signal(SIGTRAP);
+            }
             0
         },
         Err(msg) => {
@@ -227,15 +259,9 @@ fn load_args(locked_inner: &MutexGuard<ProcessControlBlockInner>, start_ptr: Vir
 fn do_exec(mut app_path: String, argv: Vec<Vec<u8>>, envp: Vec<Vec<u8>>) -> Result<isize, ErrNo> {
     let elf_file = open(app_path.clone(), OpenMode::READ)?;
     verbose!("File found {}", app_path);
-    let length = elf_file.poll().size as usize;
-    // alloc continious pages
-    let page_holder = alloc_continuous(length / PAGE_SIZE + 1);
-    let head_addr: PhysAddr = page_holder[0].ppn.into();
-    let head_ptr = head_addr.0 as *mut u8;
-    let arr: &mut [u8] = unsafe {
-        from_raw_parts_mut(head_ptr, length)
-    };
-    elf_file.read(arr)?;
+    // the image is held until exec is done copying it into the new layout
+    let image = get_exec_image(&app_path, &elf_file)?;
+    let arr = image.data();
 
     if arr.len() >= 2 && arr[0] == b'#' && arr[1] == b'!' {
         let mut vdq_argv: VecDeque<Vec<u8>> = VecDeque::from(argv);
@@ -318,7 +344,16 @@ fn do_exec(mut app_path: String, argv: Vec<Vec<u8>>, envp: Vec<Vec<u8>>) -> Resu
         for (idx, a) in envp.iter().enumerate() {
             verbose!("envp [{}]: {}", idx, core::str::from_utf8(a).unwrap());
         }
-        Ok(current_process().unwrap().exec(arr, app_path, argv, envp))
+        // dynamically linked, load the interpreter as well
+        let interp = match MemLayout::elf_interp(arr) {
+            Some(interp_path) => {
+                verbose!("Interpreter requested: {}", interp_path);
+                let interp_file = open(interp_path.clone(), OpenMode::READ)?;
+                Some(get_exec_image(&interp_path, &interp_file)?)
+            },
+            None => None
+        };
+        current_process().unwrap().exec(arr, interp.as_ref().map(|i| i.data()), app_path, argv, envp)
     }
 }
 
@@ -327,24 +362,41 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: VirtAddr, options: isize) -> isize
     info!("Waitpid {} called by {}!", pid, current_process().unwrap().pid.0);
     loop {
         let proc = current_process().unwrap();
-        let mut locked_inner = proc.get_inner_locked();
+        let mut locked_inner = proc.lock_inner();
 
-        if !locked_inner.pending_sig.is_empty() {
+        if locked_inner.has_pending_signal() {
             info!("Self received signal, failing waitpid");
-            return -1;
+            return -(ErrNo::InterruptedSystemCall as isize);
         }
 
         let mut corpse: Option<usize> = None;
+        let mut report: Option<(usize, i32)> = None;
         for (idx, child) in locked_inner.children.iter().enumerate() {
             if pid == -1 || pid as usize == child.get_pid() {
-                if child.get_inner_locked().status == ProcessStatus::Zombie {
+                let mut child_inner = child.lock_inner();
+                if child_inner.status == ProcessStatus::Zombie {
                     corpse = Some(idx);
+                } else if (options & WUNTRACED != 0 || child_inner.traced) && child_inner.status == ProcessStatus::Stopped && child_inner.stop_report.is_some() {
+                    let signal = child_inner.stop_report.take().unwrap();
+                    report = Some((child.get_pid(), ((signal as i32) << 8) | 0x7f));
+                    break;
+                } else if options & WCONTINUED != 0 && child_inner.cont_report {
+                    child_inner.cont_report = false;
+                    report = Some((child.get_pid(), 0xffff));
+                    break;
                 }
             }
         }
+        if let Some((child_pid, status)) = report {
+            if exit_code_ptr.0 != 0 {
+                locked_inner.layout.write_user_data(exit_code_ptr, &status);
+            }
+            debug!("Waitpid returned! (caller {}, child {} status {:#x})", proc.pid.0, child_pid, status);
+            return child_pid as isize;
+        }
         if let Some(idx) = corpse {
             let child_proc = locked_inner.children.remove(idx);
-            let child_arcpcb = child_proc.get_inner_locked();
+            let child_arcpcb = child_proc.lock_inner();
             assert_eq!(Arc::strong_count(&child_proc), 1, "This child process seems to be referenced more then once.");
             if exit_code_ptr.0 != 0 {
                 locked_inner.layout.write_user_data(exit_code_ptr, &((child_arcpcb.exit_code as i32) << 8));
@@ -383,27 +435,59 @@ pub fn sys_getcwd(buf: VirtAddr, size: usize) -> isize {
 
     let proc = current_process().unwrap();
     let locked_inner = proc.get_inner_locked();
-    let mut buffer = locked_inner.layout.get_user_buffer(buf, size);
-    buffer.write_bytes(locked_inner.path.as_bytes(), 0);
+    let mut path = locked_inner.path.as_bytes();
+    // cwd is kept with a trailing '/', which is not part of its canonical form
+    if path.len() > 1 && path.ends_with(b"/") {
+        path = &path[..path.len() - 1];
+    }
+    // path plus the trailing NUL must fit
+    if path.len() + 1 > size {
+        return -(ErrNo::MathResultNotRepresentable as isize);
+    }
+    let mut buffer = locked_inner.layout.get_user_buffer(buf, path.len() + 1);
+    buffer.write_bytes(path, 0);
+    buffer.write_bytes(&[0u8], path.len());
     return buf.0 as isize;
 }
 
 /// Change the current working directory.
+/// # Description
+/// The path is resolved against the current working directory, and stored in canonical form (always ends with '/').
 pub fn sys_chdir(buf: VirtAddr) -> isize {
     verbose!("chdir start");
     let proc = current_process().unwrap();
     let mut locked_inner = proc.get_inner_locked();
-    if let Ok (dir_str) = core::str::from_utf8(&locked_inner.layout.get_user_cstr(buf)) {
-        if let Ok (_) = open(dir_str.to_string(), OpenMode::READ) {
-            locked_inner.path = dir_str.to_string();
+    let dir_str = match core::str::from_utf8(&locked_inner.layout.get_user_cstr(buf)) {
+        Ok(dir_str) => dir_str.to_string(),
+        Err(_) => {
+            error!("Invalid charactor in chdir");
+            return -(ErrNo::InvalidArgument as isize);
+        }
+    };
+    let (path, cwd) = match (parse_path(&dir_str), parse_path(&locked_inner.path)) {
+        (Ok(path), Ok(cwd)) => (path, cwd),
+        _ => {
+            error!("chdir: invalid path {}", dir_str);
+            return -(ErrNo::NoSuchFileOrDirectory as isize);
+        }
+    };
+    let mut path = path.canonicalize(&cwd);
+    path.must_dir = true;
+    let path = path.to_string();
+    match open(path.clone(), OpenMode::READ | OpenMode::DIR) {
+        Ok(dir) => {
+            if dir.poll().ftype != FileType::Directory {
+                error!("chdir: {} is not a directory", path);
+                return -(ErrNo::NotADirectory as isize);
+            }
+            verbose!("chdir: {}", path);
+            locked_inner.path = path;
             return 0;
-        } else {
+        },
+        Err(errno) => {
             error!("No such directory!");
-            return -1;
+            return -(errno as isize);
         }
-    } else {
-        error!("Invalid charactor in chdir");
-        return -1;
     }
 }
 
@@ -412,22 +496,40 @@ pub fn sys_brk(sz: usize) -> isize {
         return current_process().unwrap().get_inner_locked().size as isize;
     }
     let proc = current_process().unwrap();
-    let mut locked_inner = proc.get_inner_locked();
-    let original_size = locked_inner.size;
-    if locked_inner.layout.alter_segment(VirtAddr::from(original_size).to_vpn_ceil(), VirtAddr::from(sz).to_vpn_ceil()).is_some() {
-        locked_inner.size = sz as usize;
-        sz as isize
-    } else {
-        fatal!("sbrk failed! OOM!");
-        -1
+    loop {
+        let mut locked_inner = proc.get_inner_locked();
+        let original_size = locked_inner.size;
+        if locked_inner.layout.alter_segment(VirtAddr::from(original_size).to_vpn_ceil(), VirtAddr::from(sz).to_vpn_ceil()).is_some() {
+            locked_inner.size = sz as usize;
+            locked_inner.update_rss();
+            return sz as isize;
+        }
+        drop(locked_inner);
+        // only growing the heap can run out of memory
+        if sz <= original_size || !oom_kill() {
+            fatal!("sbrk failed! OOM!");
+            return -1;
+        }
+        // let the victim exit and release its frames
+        suspend_switch();
     }
 }
 
-pub fn sys_mmap(mut start: VirtAddr, len: usize, prot: usize, _: usize, fd: usize, offset: usize) -> isize {
+pub fn sys_mmap(mut start: VirtAddr, len: usize, prot: usize, flags: usize, fd: usize, offset: usize) -> isize {
     let proc = current_process().unwrap();
     let mut locked_inner = proc.get_inner_locked();
+    if flags & MAP_FIXED != 0 {
+        // the address is not a hint, whatever is there gets replaced
+        let end = match start.0.checked_add(len) {
+            Some(end) if start.0 % PAGE_SIZE == 0 && MemLayout::is_user_range(start, end.into()) => VirtAddr::from(end),
+            _ => return -(ErrNo::InvalidArgument as isize),
+        };
+        if let Err(errno) = locked_inner.layout.unmap_range(start.to_vpn(), end.to_vpn_ceil()) {
+            return -(errno as isize);
+        }
+    }
     if fd == usize::MAX {
-        // if start.0 == 0 {
+        if flags & MAP_FIXED == 0 {
             match locked_inner.layout.get_continuous_space(len) {
                 Some(start_vpn) => {
                     start = start_vpn.into();
@@ -439,7 +541,7 @@ pub fn sys_mmap(mut start: VirtAddr, len: usize, prot: usize, _: usize, fd: usiz
                     return -1;
                 }
             }
-        // }
+        }
 
         let mut flags = SegmentFlags::empty();
         if prot & PROT_NONE == 0 {
@@ -458,13 +560,14 @@ pub fn sys_mmap(mut start: VirtAddr, len: usize, prot: usize, _: usize, fd: usiz
             Segment::new(
                 start, 
                 start + len, 
-                crate::memory::MapType::Framed, 
+                crate::memory::MapType::Anonymous, 
                 flags, 
                 VMAFlags::empty(), 
                 None, 
                 0
             )
         )));
+        locked_inner.update_rss();
         return start.0 as isize;
     } else if let Some(file) = locked_inner.files[fd].clone() {
         if let Ok(addr) = locked_inner.layout.add_vma(file, start, VMAFlags::from_bits((prot << 1) as u8).unwrap(), offset, len) {
@@ -478,7 +581,7 @@ pub fn sys_munmap(start: VirtAddr, len: usize) -> isize {
     verbose!("sys_munmap");
     let proc = current_process().unwrap();
     let mut locked_inner = proc.get_inner_locked();
-    match locked_inner.layout.drop_vma(start.into(), (start + len).to_vpn_ceil()) {
+    match locked_inner.layout.unmap_range(start.into(), (start + len).to_vpn_ceil()) {
         Ok(()) => 0,
         Err(msg) => {
             error!("munmap failed: {}", msg);
@@ -487,6 +590,21 @@ pub fn sys_munmap(start: VirtAddr, len: usize) -> isize {
     }
 }
 
+/// Resize an anonymous mapping, moving it if allowed and needed.
+pub fn sys_mremap(old_addr: VirtAddr, old_size: usize, new_size: usize, flags: usize, _new_addr: VirtAddr) -> isize {
+    verbose!("sys_mremap");
+    if old_addr.0 % PAGE_SIZE != 0 || new_size == 0 || flags & !MREMAP_MAYMOVE != 0
+        || old_addr.0.checked_add(old_size.max(new_size)).is_none() {
+        return -(ErrNo::InvalidArgument as isize);
+    }
+    let proc = current_process().unwrap();
+    let mut locked_inner = proc.get_inner_locked();
+    match locked_inner.layout.remap(old_addr, old_size, new_size, flags & MREMAP_MAYMOVE != 0) {
+        Ok(addr) => addr.0 as isize,
+        Err(errno) => -(errno as isize)
+    }
+}
+
 pub fn sys_kill(target_pid: isize, signal: usize) -> isize {
     if target_pid == 0 {
         let parent = current_process().unwrap();
@@ -503,9 +621,10 @@ pub fn sys_kill(target_pid: isize, signal: usize) -> isize {
             0
         }
     } else if target_pid == -1 {
-        let pm_inner = PROCESS_MANAGER.lock();
+        // collect first, delivering SIGCONT needs the manager to resume stopped processes
+        let procs = PROCESS_MANAGER.lock().idle_procs();
         let mut all_fail = true;
-        for proc in &pm_inner.processes {
+        for proc in &procs {
             // hard code: init process never dies.
             if proc.pid.0 != 0 {
                 if proc.recv_signal(signal).is_some() {
@@ -552,12 +671,15 @@ pub fn sys_tgkill(target_tgid: isize, target_tid: isize, signal: usize) -> isize
 // TODO: consider edge cases of act is nullptr
 // TODO: reference to https://elixir.bootlin.com/linux/latest/source/kernel/signal.c#L4015 (do_sigaction), implement reporting unsupport
 pub fn sys_sigaction(signum: usize, act_ptr: VirtAddr, old_act_ptr: VirtAddr) -> isize {
+    if act_ptr.0 != 0 && (signum == 0 || signum >= 64 || SIG_UNBLOCKABLE & (1u64 << signum) != 0) {
+        return -(ErrNo::InvalidArgument as isize);
+    }
     let proc = current_process().unwrap();
     let mut locked_inner = proc.get_inner_locked();
 
     if act_ptr.0 != 0 {
         let new_act: SigAction = locked_inner.layout.read_user_data(act_ptr);
-        let old_act_op = locked_inner.handlers.insert(signum, new_act);
+        let old_act_op = locked_inner.set_sig_action(signum, new_act);
     
         if old_act_ptr.0 != 0 {
             if let Some(mut old_act) = old_act_op {
@@ -589,9 +711,10 @@ pub const SIG_SETMASK   : isize = 2;
 
 pub fn sys_sigprocmask(how: isize, oldmask: VirtAddr, newmask: VirtAddr) -> isize {
     let proc = current_process().unwrap();
-    let mut locked_inner = proc.get_inner_locked();
+    let mut locked_inner = proc.lock_inner();
     if oldmask.0 != 0 {
-        locked_inner.layout.write_user_data(oldmask, &locked_inner.sig_mask);
+        let old_mask = locked_inner.sig_mask;
+        locked_inner.layout.write_user_data(oldmask, &old_mask);
     }
 
     let new_mask: u64 = if newmask.0 == 0 {
@@ -609,10 +732,39 @@ pub fn sys_sigprocmask(how: isize, oldmask: VirtAddr, newmask: VirtAddr) -> isiz
     } else {
         return -1;
     }
+    // silently ignored, as linux does
+    locked_inner.sig_mask &= !SIG_UNBLOCKABLE;
 
     0
 }
 
+/// Replace the signal mask and wait for a signal.
+/// # Description
+/// The original mask is put back after the handler of the signal returns.  
+/// The process sleeps off the run queue and is woken by `recv_signal`.
+/// Checking for signals and switching away happens with no chance for a signal to slip in between,
+/// so a signal unblocked by `mask` can't get lost.
+/// # Returns
+/// Always -EINTR.
+pub fn sys_sigsuspend(mask: VirtAddr) -> isize {
+    let new_mask: u64 = match read_from_user(mask) {
+        Ok(mask) => mask,
+        Err(errno) => return -(errno as isize),
+    };
+    let proc = current_process().unwrap();
+    let mut locked_inner = proc.lock_inner();
+    let old_mask = locked_inner.sig_mask;
+    locked_inner.sigsuspend_mask = Some(old_mask);
+    locked_inner.sig_mask = new_mask & !SIG_UNBLOCKABLE;
+    // parked until recv_signal wakes us with a signal the new mask lets through
+    while !locked_inner.has_pending_signal() {
+        drop(locked_inner);
+        sleep_switch();
+        locked_inner = proc.lock_inner();
+    }
+    -(ErrNo::InterruptedSystemCall as isize)
+}
+
 pub fn sys_sigreturn() -> isize {
     // go check trap.asm -> __restore_to_signal_handler
     let proc = current_process().unwrap();
@@ -624,7 +776,11 @@ pub fn sys_sigreturn() -> isize {
         locked_inner.write_trap_context(&old_trap_context);
         locked_inner.sig_mask &= !(1u64 << last_signal);
         locked_inner.last_signal = None;
-        0
+        if let Some(mask) = locked_inner.sigsuspend_mask.take() {
+            locked_inner.sig_mask = mask;
+        }
+        // the return value lands in a0, which must be the one of the restored context
+        old_trap_context.regs[10] as isize
     } else {
         -1
     }
@@ -653,6 +809,10 @@ pub fn sys_mprotect(addr: VirtAddr, len: usize, prot: usize) -> isize {
     match locked_inner.layout.modify_access(addr.into(), len, flags, grow_up, grow_down) {
         Some(_) => {
             // locked_inner.layout.print_layout();
+            // code may have been written to the range before it became executable
+            if flags.contains(PTEFlags::X) {
+                flush_icache();
+            }
             0
         },
         None => {
@@ -662,10 +822,315 @@ pub fn sys_mprotect(addr: VirtAddr, len: usize, prot: usize) -> isize {
     }
 }
 
+/// Issue memory barriers on all threads
+/// # Description
+/// There is only one hart and it runs one thread at a time, so a fence on it is all every command needs.
+/// The fence.i also makes code just written visible to instruction fetch, as the SYNC_CORE commands require.
+/// # Return
+/// The supported commands for MEMBARRIER_CMD_QUERY, 0 otherwise, -EINVAL on unknown commands or flags
+pub fn sys_membarrier(cmd: usize, flags: usize) -> isize {
+    let supported = MEMBARRIER_CMD_GLOBAL
+        | MEMBARRIER_CMD_GLOBAL_EXPEDITED
+        | MEMBARRIER_CMD_REGISTER_GLOBAL_EXPEDITED
+        | MEMBARRIER_CMD_PRIVATE_EXPEDITED
+        | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED
+        | MEMBARRIER_CMD_PRIVATE_EXPEDITED_SYNC_CORE
+        | MEMBARRIER_CMD_REGISTER_PRIVATE_EXPEDITED_SYNC_CORE;
+    if flags != 0 {
+        return -(ErrNo::InvalidArgument as isize);
+    }
+    if cmd == MEMBARRIER_CMD_QUERY {
+        return supported as isize;
+    }
+    if cmd & supported == 0 || cmd.count_ones() != 1 {
+        return -(ErrNo::InvalidArgument as isize);
+    }
+    unsafe {
+        asm!("fence rw, rw");
+    }
+    flush_icache();
+    0
+}
+
+/// Make instruction fetch see code written to [start, end)
+/// # Description
+/// The range and flags don't matter, a fence.i covers all of memory on the only hart.
+pub fn sys_riscv_flush_icache(_start: VirtAddr, _end: VirtAddr, _flags: usize) -> isize {
+    flush_icache();
+    0
+}
+
+/// Max # of iovecs in a single process_vm_readv/writev
+const IOV_MAX: usize = 1024;
+
+/// Read an iovec array from the current process
+fn read_iovecs(iov: VirtAddr, iovcnt: usize) -> Result<Vec<iovec>, ErrNo> {
+    if iovcnt > IOV_MAX {
+        return Err(ErrNo::InvalidArgument);
+    }
+    let mut iovecs = Vec::with_capacity(iovcnt);
+    for i in 0..iovcnt {
+        iovecs.push(read_from_user::<iovec>(iov + size_of::<iovec>() * i)?);
+    }
+    Ok(iovecs)
+}
+
+/// If `proc` may read and write the memory of `target`
+/// # Description
+/// Root may access any process. Anybody else needs to be its parent, and either have the real uid of the target or trace it.
+fn may_access_memory(proc: &Arc<ProcessControlBlock>, target: &Arc<ProcessControlBlock>) -> bool {
+    if Arc::ptr_eq(proc, target) {
+        return true;
+    }
+    let uid = proc.get_inner_locked().uid;
+    let target_inner = target.get_inner_locked();
+    let is_parent = target_inner.parent.as_ref()
+        .and_then(|parent| parent.upgrade())
+        .map_or(false, |parent| Arc::ptr_eq(&parent, proc));
+    uid == 0 || (is_parent && (uid == target_inner.uid || target_inner.traced))
+}
+
+/// Move data between the current process and process `pid`
+/// # Description
+/// Both iovec arrays are in the current process, the buffers of `remote_iov` are in the target.  
+/// Data goes through a kernel buffer, a chunk at a time that crosses no page on either side, so that only one process is locked at once
+/// and a chunk is either moved whole or not at all.
+/// # Return
+/// # of bytes moved, stopping at the first bad page. -EFAULT if nothing could be moved.
+fn process_vm_rw(pid: usize, local_iov: VirtAddr, liovcnt: usize, remote_iov: VirtAddr, riovcnt: usize, flags: usize, write: bool) -> isize {
+    if flags != 0 {
+        return -(ErrNo::InvalidArgument as isize);
+    }
+    let target = match get_proc_by_pid(pid) {
+        Some(target) => target,
+        None => return -(ErrNo::NoSuchProcess as isize),
+    };
+    if !may_access_memory(&current_process().unwrap(), &target) {
+        return -(ErrNo::OperationNotPermitted as isize);
+    }
+    let (local, remote) = match (read_iovecs(local_iov, liovcnt), read_iovecs(remote_iov, riovcnt)) {
+        (Ok(local), Ok(remote)) => (local, remote),
+        (Err(errno), _) | (_, Err(errno)) => return -(errno as isize),
+    };
+
+    let mut buf = alloc::vec![0u8; PAGE_SIZE];
+    let mut moved = 0;
+    let (mut l, mut l_off) = (0, 0);
+    let (mut r, mut r_off) = (0, 0);
+    while l < local.len() && r < remote.len() {
+        if l_off == local[l].iov_len {
+            l += 1;
+            l_off = 0;
+            continue;
+        }
+        if r_off == remote[r].iov_len {
+            r += 1;
+            r_off = 0;
+            continue;
+        }
+        let local_va = VirtAddr::from(local[l].iov_base + l_off);
+        let remote_va = VirtAddr::from(remote[r].iov_base + r_off);
+        let len = (local[l].iov_len - l_off).min(remote[r].iov_len - r_off)
+            .min(PAGE_SIZE - local_va.page_offset())
+            .min(PAGE_SIZE - remote_va.page_offset());
+        let res = if write {
+            match copy_from_user(&mut buf[..len], local_va) {
+                Ok(_) => target.get_inner_locked().layout.copy_to_user(remote_va, &buf[..len]),
+                Err(errno) => Err(errno),
+            }
+        } else {
+            let res = target.get_inner_locked().layout.copy_from_user(&mut buf[..len], remote_va);
+            res.and_then(|_| copy_to_user(local_va, &buf[..len]))
+        };
+        if let Err(errno) = res {
+            if moved == 0 {
+                return -(errno as isize);
+            }
+            break;
+        }
+        moved += len;
+        l_off += len;
+        r_off += len;
+    }
+    moved as isize
+}
+
+/// Read the memory of process `pid`, e.g. for a debugger
+/// # Return
+/// # of bytes read
+pub fn sys_process_vm_readv(pid: usize, local_iov: VirtAddr, liovcnt: usize, remote_iov: VirtAddr, riovcnt: usize, flags: usize) -> isize {
+    process_vm_rw(pid, local_iov, liovcnt, remote_iov, riovcnt, flags, false)
+}
+
+/// Write the memory of process `pid`, e.g. for a debugger
+/// # Return
+/// # of bytes written
+pub fn sys_process_vm_writev(pid: usize, local_iov: VirtAddr, liovcnt: usize, remote_iov: VirtAddr, riovcnt: usize, flags: usize) -> isize {
+    process_vm_rw(pid, local_iov, liovcnt, remote_iov, riovcnt, flags, true)
+}
+
+pub const PTRACE_TRACEME    : usize = 0;
+pub const PTRACE_PEEKTEXT   : usize = 1;
+pub const PTRACE_PEEKDATA   : usize = 2;
+pub const PTRACE_POKETEXT   : usize = 4;
+pub const PTRACE_POKEDATA   : usize = 5;
+pub const PTRACE_CONT       : usize = 7;
+pub const PTRACE_SINGLESTEP : usize = 9;
+pub const PTRACE_GETREGS    : usize = 12;
+pub const PTRACE_SETREGS    : usize = 13;
+
+/// Find the child `pid` of the current process, stopped under PTRACE_TRACEME
+fn stopped_tracee(pid: usize) -> Result<Arc<ProcessControlBlock>, ErrNo> {
+    let proc = current_process().unwrap();
+    let tracee = proc.get_inner_locked().children.iter()
+        .find(|child| child.pid.0 == pid)
+        .cloned()
+        .ok_or(ErrNo::NoSuchProcess)?;
+    let tracee_inner = tracee.get_inner_locked();
+    if !tracee_inner.traced || tracee_inner.status != ProcessStatus::Stopped {
+        return Err(ErrNo::NoSuchProcess);
+    }
+    drop(tracee_inner);
+    Ok(tracee)
+}
+
+fn ptrace_inner(request: usize, pid: usize, addr: VirtAddr, data: usize) -> Result<(), ErrNo> {
+    if request == PTRACE_TRACEME {
+        let proc = current_process().unwrap();
+        let mut inner = proc.get_inner_locked();
+        if inner.traced {
+            return Err(ErrNo::OperationNotPermitted);
+        }
+        inner.traced = true;
+        return Ok(());
+    }
+    let tracee = stopped_tracee(pid)?;
+    match request {
+        PTRACE_PEEKTEXT | PTRACE_PEEKDATA => {
+            // the word goes to *data, glibc hands back the value
+            let mut word = [0u8; size_of::<usize>()];
+            tracee.get_inner_locked().layout.copy_from_user(&mut word, addr)?;
+            write_to_user(VirtAddr::from(data), &usize::from_ne_bytes(word))
+        },
+        PTRACE_POKETEXT => {
+            // code is usually read only, patch it anyway
+            tracee.get_inner_locked().layout.poke_user(addr, &data.to_ne_bytes())?;
+            Ok(())
+        },
+        PTRACE_POKEDATA => {
+            tracee.get_inner_locked().layout.copy_to_user(addr, &data.to_ne_bytes())?;
+            Ok(())
+        },
+        PTRACE_GETREGS => {
+            // user_regs_struct: pc followed by x1 ~ x31
+            let trap_context = tracee.get_trap_context();
+            let mut regs = trap_context.regs;
+            regs[0] = trap_context.sepc;
+            write_to_user(VirtAddr::from(data), &regs)
+        },
+        PTRACE_SETREGS => {
+            let regs: [usize; 32] = read_from_user(VirtAddr::from(data))?;
+            let trap_context = tracee.get_trap_context();
+            trap_context.regs[1..].copy_from_slice(&regs[1..]);
+            trap_context.sepc = regs[0];
+            Ok(())
+        },
+        PTRACE_CONT | PTRACE_SINGLESTEP => {
+            if data >= 64 {
+                return Err(ErrNo::InvalidArgument);
+            }
+            let mut tracee_inner = tracee.get_inner_locked();
+            if request == PTRACE_SINGLESTEP {
+                // stops again with SIGTRAP after one instruction
+                insert_step_breakpoint(&mut tracee_inner)?;
+            } else {
+                remove_step_breakpoint(&mut tracee_inner);
+            }
+            if data != 0 {
+                // deliver the signal the tracee stopped on, or another one
+                tracee_inner.pending_sig.push_front(data);
+                tracee_inner.ptrace_pass = Some(data);
+            }
+            tracee_inner.status = ProcessStatus::Ready;
+            tracee_inner.stop_report = None;
+            drop(tracee_inner);
+            resume(tracee.pid.0);
+            Ok(())
+        },
+        _ => Err(ErrNo::InvalidArgument),
+    }
+}
+
+/// Trace a child process, minimal subset for debuggers
+/// # Description
+/// The child calls PTRACE_TRACEME. It then stops on every signal it gets and after exec, the parent learns about it from waitpid.  
+/// While it is stopped the parent can peek and poke its memory and registers, and let it go on with PTRACE_CONT or PTRACE_SINGLESTEP.
+/// # Return
+/// 0 on success, -ESRCH if `pid` is not a stopped tracee of the caller
+pub fn sys_ptrace(request: usize, pid: usize, addr: VirtAddr, data: usize) -> isize {
+    match ptrace_inner(request, pid, addr, data) {
+        Ok(()) => 0,
+        Err(errno) => -(errno as isize),
+    }
+}
+
+pub const PR_SET_PDEATHSIG  : usize = 1;
+pub const PR_GET_PDEATHSIG  : usize = 2;
+pub const PR_SET_NAME       : usize = 15;
+pub const PR_GET_NAME       : usize = 16;
+
+fn prctl_inner(option: usize, arg2: usize) -> Result<(), ErrNo> {
+    let proc = current_process().unwrap();
+    match option {
+        PR_SET_NAME => {
+            // up to TASK_COMM_LEN - 1 bytes, longer names are cut
+            let mut comm = [0u8; TASK_COMM_LEN];
+            for i in 0..TASK_COMM_LEN - 1 {
+                copy_from_user(&mut comm[i..i + 1], VirtAddr::from(arg2 + i))?;
+                if comm[i] == 0 {
+                    break;
+                }
+            }
+            proc.get_inner_locked().comm = comm;
+            Ok(())
+        },
+        PR_GET_NAME => {
+            let comm = proc.get_inner_locked().comm;
+            copy_to_user(VirtAddr::from(arg2), &comm)?;
+            Ok(())
+        },
+        PR_SET_PDEATHSIG => {
+            if arg2 >= 64 {
+                return Err(ErrNo::InvalidArgument);
+            }
+            proc.get_inner_locked().pdeathsig = arg2;
+            Ok(())
+        },
+        PR_GET_PDEATHSIG => {
+            let pdeathsig = proc.get_inner_locked().pdeathsig as i32;
+            write_to_user(VirtAddr::from(arg2), &pdeathsig)
+        },
+        _ => Err(ErrNo::InvalidArgument),
+    }
+}
+
+/// Operations on the calling process
+/// # Description
+/// Only the process name (PR_SET_NAME/PR_GET_NAME) and the parent death signal (PR_SET_PDEATHSIG/PR_GET_PDEATHSIG) are supported.  
+/// The name is at most TASK_COMM_LEN bytes including the terminating 0, and shows in /proc/self/comm.
+/// # Return
+/// 0 on success, -EINVAL on unknown options or bad signals, -EFAULT on bad buffers
+pub fn sys_prctl(option: usize, arg2: usize) -> isize {
+    match prctl_inner(option, arg2) {
+        Ok(()) => 0,
+        Err(errno) => -(errno as isize),
+    }
+}
+
 pub fn sys_exit_group(exit_status: i32) -> ! {
     let proc = current_process().unwrap();
     let mut pids: Vec<usize> = Vec::new();
-    for process in  PROCESS_MANAGER.lock().processes.iter() {
+    for process in PROCESS_MANAGER.lock().idle_procs().iter() {
         if process.tgid == proc.tgid {
             pids.push(process.pid.0);
         }
@@ -679,13 +1144,8 @@ pub fn sys_exit_group(exit_status: i32) -> ! {
         group_inner.exit_code = exit_status;
         
         // adopt children
-        let mut initproc_inner = PROC0.get_inner_locked();
-        for child in group_inner.children.iter() {
-            child.get_inner_locked().parent = Some(Arc::downgrade(&PROC0));
-            initproc_inner.children.push(child.clone());
-        }
+        adopt_orphans(group_process.pid.0, &mut group_inner);
         
-        group_inner.children.clear();
         group_inner.layout.drop_all();
         group_inner.utime = group_inner.utime + get_time() - group_inner.last_start;
     }
@@ -701,138 +1161,133 @@ pub fn sys_gettid() -> isize {
 
 #[repr(C)]
 #[derive(Copy, Clone)]
-struct timeval {
-    tv_sec: i64,
-    tv_usec: i64,
+pub struct timeval {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
 }
 
 #[repr(C)]
 #[derive(Copy, Clone)]
-struct itimerval {
-    it_interval: timeval,
-    it_value: timeval,
+pub struct itimerval {
+    pub it_interval: timeval,
+    pub it_value: timeval,
 }
 
-const ITIMER_REAL: i32 = 0;
-const ITIMER_VIRTUAL: i32 = 1;
-const ITIMER_PROF: i32 = 2;
+pub const ITIMER_REAL: i32 = 0;
+pub const ITIMER_VIRTUAL: i32 = 1;
+pub const ITIMER_PROF: i32 = 2;
+
+impl timeval {
+    /// Convert from microseconds
+    fn from_usec(usec: u64) -> Self {
+        Self {
+            tv_sec: (usec / 1000000) as i64,
+            tv_usec: (usec % 1000000) as i64,
+        }
+    }
+
+    /// Convert from timer ticks
+    fn from_ticks(ticks: u64) -> Self {
+        Self::from_usec(ticks * 10 / (CLOCK_FREQ / 100000))
+    }
+
+    /// Microseconds in the value, `None` if it is out of range
+    fn to_usec(&self) -> Option<u64> {
+        if self.tv_sec < 0 || self.tv_usec < 0 || self.tv_usec > 999999 {
+            return None;
+        }
+        let usec = self.tv_sec.checked_mul(1000000)?.checked_add(self.tv_usec)?;
+        Some(usec as u64)
+    }
+
+    /// Timer ticks in the value, `None` if it is out of range
+    fn to_ticks(&self) -> Option<u64> {
+        Some(self.to_usec()?.checked_mul(CLOCK_FREQ / 100000)? / 10)
+    }
+}
 
+/// Get value of an interval timer.
+/// # Description
+/// Intervals are kept in microseconds, expirations in timer ticks measured on the clock of the timer:
+/// wall time for ITIMER_REAL, user time for ITIMER_VIRTUAL and process time for ITIMER_PROF.  
+/// There is no alarm syscall on riscv64, libc builds `alarm()` on top of this and `sys_setitimer`.
 pub fn sys_getitimer(which: i32, old: VirtAddr) -> isize {
     let process = current_process().unwrap();
     let mut lock = process.get_inner_locked();
-    match which {
-        ITIMER_REAL => {
-            let now = get_time() * 1000 / CLOCK_FREQ;
-            let val = if now <= lock.timer_real_next {
-                lock.timer_real_int as i64
-            } else {
-                (now - lock.timer_real_next) as i64
-            };
-            if old.0 != 0 {
-                let tmp = itimerval {
-                    it_interval: timeval {
-                        tv_sec: (lock.timer_real_int / 1000) as i64,
-                        tv_usec: (lock.timer_real_int % 1000) as i64,
-                    },
-                    it_value: timeval {
-                        tv_sec: val / 1000,
-                        tv_usec: val % 1000,
-                    },
-                };
-                lock.layout.write_user_data(old, &tmp);
-            }
-        },
-        ITIMER_VIRTUAL => {
-            let now = lock.utime * 1000 / CLOCK_FREQ;
-            let val = if now <= lock.timer_virt_next {
-                lock.timer_virt_int as i64
-            } else {
-                (now - lock.timer_virt_next) as i64
-            };
-            if old.0 != 0 {
-                let tmp = itimerval {
-                    it_interval: timeval {
-                        tv_sec: (lock.timer_virt_int / 1000) as i64,
-                        tv_usec: (lock.timer_virt_int % 1000) as i64,
-                    },
-                    it_value: timeval {
-                        tv_sec: val / 1000,
-                        tv_usec: val % 1000,
-                    },
-                };
-                lock.layout.write_user_data(old, &tmp);
-            }
-        },
-        ITIMER_PROF => {
-            let now = lock.timer_prof_now * 1000 / CLOCK_FREQ;
-            let val = if now <= lock.timer_prof_next {
-                lock.timer_prof_int as i64
-            } else {
-                (now - lock.timer_prof_next) as i64
-            };
-            if old.0 != 0 {
-                let tmp = itimerval {
-                    it_interval: timeval {
-                        tv_sec: (lock.timer_prof_int / 1000) as i64,
-                        tv_usec: (lock.timer_prof_int % 1000) as i64,
-                    },
-                    it_value: timeval {
-                        tv_sec: val / 1000,
-                        tv_usec: val % 1000,
-                    },
-                };
-                lock.layout.write_user_data(old, &tmp);
-            }
-        },
+    let (interval, next, now) = match which {
+        ITIMER_REAL     => (lock.timer_real_int, lock.timer_real_next, get_time()),
+        ITIMER_VIRTUAL  => (lock.timer_virt_int, lock.timer_virt_next, lock.utime),
+        ITIMER_PROF     => (lock.timer_prof_int, lock.timer_prof_next, lock.timer_prof_now),
         _ => {
             error!("sys_getitimer: invalid which");
-            return -1;
+            return -(ErrNo::InvalidArgument as isize);
         }
+    };
+    if old.0 != 0 {
+        // a disarmed timer reads as zero
+        let left = if next == 0 { 0 } else { next.saturating_sub(now) };
+        let tmp = itimerval {
+            it_interval: timeval::from_usec(interval),
+            it_value: timeval::from_ticks(left),
+        };
+        lock.layout.write_user_data(old, &tmp);
     }
     return 0;
 }
 
+/// Arm or disarm an interval timer.
+/// # Description
+/// The new value is checked before anything changes: a bad `which`, a negative field, `tv_usec` over 999999 or a value
+/// that overflows the timer ticks fails with EINVAL, and leaves both the timer and `old` untouched.
 pub fn sys_setitimer(which: i32, new: VirtAddr, old: VirtAddr) -> isize {
     info!("sys_setitimer: {} {:#18X} {:#18X}", which, new.0, old.0);
-    if old.0 != 0 {
-        if sys_getitimer(which, old) == -1 {
-            return -1;
-        }
-    }
     let process = current_process().unwrap();
-    info!("sys_setitimer: pid {}", process.pid.0);
-    let mut lock = process.get_inner_locked();
-    let new: itimerval = lock.layout.read_user_data(new);
+    let new: itimerval = process.get_inner_locked().layout.read_user_data(new);
     info!("sys_setitimer: {} {} {} {} {}", which, new.it_interval.tv_sec, new.it_interval.tv_usec, new.it_value.tv_sec, new.it_value.tv_usec);
-    if new.it_interval.tv_sec < 0 || new.it_interval.tv_usec < 0 || new.it_interval.tv_usec > 999999 {
-        error!("sys_setitimer: invalid new value");
-        return -1;
-    }
-    if new.it_value.tv_sec < 0 || new.it_value.tv_usec < 0 || new.it_value.tv_usec > 999999 {
-        error!("sys_setitimer: invalid new value");
-        return -1;
+    let (interval, value) = match (new.it_interval.to_ticks(), new.it_value.to_ticks()) {
+        (Some(_), Some(value)) => (new.it_interval.to_usec().unwrap(), value),
+        _ => {
+            error!("sys_setitimer: invalid new value");
+            return -(ErrNo::InvalidArgument as isize);
+        }
+    };
+    let now = match which {
+        ITIMER_REAL     => get_time(),
+        ITIMER_VIRTUAL  => process.get_inner_locked().utime,
+        ITIMER_PROF     => process.get_inner_locked().timer_prof_now,
+        _ => {
+            error!("sys_setitimer: invalid which");
+            return -(ErrNo::InvalidArgument as isize);
+        }
+    };
+    // a zero value disarms the timer, e.g. alarm(0)
+    let next = if new.it_value.to_usec() == Some(0) {
+        0
+    } else if let Some(next) = now.checked_add(value) {
+        next
+    } else {
+        error!("sys_setitimer: expiration overflows");
+        return -(ErrNo::InvalidArgument as isize);
+    };
+    if old.0 != 0 {
+        sys_getitimer(which, old);
     }
+    let mut lock = process.get_inner_locked();
     match which {
         ITIMER_REAL => {
-            let now = get_time();
-            lock.timer_real_int = (new.it_interval.tv_sec * 1000000 + new.it_interval.tv_usec) as u64;
-            lock.timer_real_next = (new.it_value.tv_sec * 1000000 + new.it_value.tv_usec) as u64 * (CLOCK_FREQ / 100000) / 10 + now;
+            lock.timer_real_int = interval;
+            lock.timer_real_next = next;
             info!("timer_real_int = {}", lock.timer_real_int);
             info!("timer_real_next = {}", lock.timer_real_next);
-            info!("timer_real_now = {}", now);
         },
         ITIMER_VIRTUAL => {
-            lock.timer_virt_int = (new.it_interval.tv_sec * 1000000 + new.it_interval.tv_usec) as u64;
-            lock.timer_virt_next = (new.it_value.tv_sec * 1000000 + new.it_value.tv_usec) as u64 * (CLOCK_FREQ / 100000) / 10;
-        },
-        ITIMER_PROF => {
-            lock.timer_prof_int = (new.it_interval.tv_sec * 1000000 + new.it_interval.tv_usec) as u64;
-            lock.timer_prof_next = (new.it_value.tv_sec * 1000000 + new.it_value.tv_usec) as u64 * (CLOCK_FREQ / 100000) / 10;
+            lock.timer_virt_int = interval;
+            lock.timer_virt_next = next;
         },
         _ => {
-            error!("sys_setitimer: invalid which");
-            return -1;
-        }
+            lock.timer_prof_int = interval;
+            lock.timer_prof_next = next;
+        },
     }
     return 0;
 }
\ No newline at end of file
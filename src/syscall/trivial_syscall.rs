@@ -1,9 +1,13 @@
 //! Trivial system calls.
-use crate::{process::{ProcessStatus, current_process, suspend_switch}, sbi::{TICKS_PER_SECOND, get_time}};
-use crate::memory::{VirtAddr};
+use crate::{process::{ErrNo, ProcessStatus, current_process, current_signal_pending, sleep_switch}, sbi::{TICKS_PER_SECOND, get_time}};
+use crate::memory::{VirtAddr, copy_from_user, free_frames, kernel_heap_capacity, kernel_heap_used, read_from_user, total_frames, write_to_user};
+use crate::process::nr_processes;
+use crate::process::loadavg::{FSHIFT, load_avg};
 use crate::config::*;
 use crate::version::*;
 use core::{convert::TryInto};
+use spin::Mutex;
+use lazy_static::*;
 
 /// Linux style tms
 #[repr(C)]
@@ -46,14 +50,38 @@ pub struct TimeSPEC {
     pub tvnsec: u32,
 }
 
+impl TimeSPEC {
+    /// Convert to timer ticks
+    /// # Description
+    /// Saturates at u64::MAX, e.g. for a sleep of i64::MAX seconds.
+    pub fn to_ticks(&self) -> u64 {
+        self.tvsec.saturating_mul(CLOCK_FREQ).saturating_add(self.tvnsec as u64 * CLOCK_FREQ / 1000000000)
+    }
+
+    /// Convert from timer ticks
+    pub fn from_ticks(ticks: u64) -> Self {
+        Self {
+            tvsec: ticks / CLOCK_FREQ,
+            tvnsec: (ticks % CLOCK_FREQ * 1000000000 / CLOCK_FREQ) as u32,
+        }
+    }
+}
+
+pub const CLOCK_REALTIME: usize = 0;
+pub const CLOCK_MONOTONIC: usize = 1;
+
+pub const TIMER_ABSTIME: usize = 1;
+
 /// Since we don't have RTC, we return seconds and nanoseconds since boot.
 pub fn sys_gettimeofday(ts: VirtAddr) -> isize {
     let time = TimeSPEC {
         tvsec: crate::sbi::get_time_ms()/1000,
         tvnsec: (crate::sbi::get_time() * (1000000000 / CLOCK_FREQ) % 1000000000) as u32 ,
     };
-    current_process().unwrap().get_inner_locked().layout.write_user_data(ts, &time);
-    0
+    match write_to_user(ts, &time) {
+        Ok(()) => 0,
+        Err(errno) => -(errno as isize),
+    }
 }
 
 
@@ -69,6 +97,50 @@ pub struct UTSName {
     domainname  : [u8; UTSNAME_LEN],
 }
 
+lazy_static! {
+    /// Hostname reported in uname, 0 padded
+    static ref HOSTNAME: Mutex<[u8; UTSNAME_LEN]> = Mutex::new(uts_field(NODENAME));
+    /// NIS domain name reported in uname, 0 padded
+    static ref DOMAIN: Mutex<[u8; UTSNAME_LEN]> = Mutex::new(uts_field(DOMAINNAME));
+}
+
+/// A uname field holding `name`, which may or may not end with 0
+fn uts_field(name: &[u8]) -> [u8; UTSNAME_LEN] {
+    let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+    let mut field = [0u8; UTSNAME_LEN];
+    field[..len].copy_from_slice(&name[..len]);
+    field
+}
+
+/// Replace a uname field with `len` bytes from the user
+/// # Return
+/// 0 on success, -EPERM if not root, -EINVAL if too long, -EFAULT on bad name
+fn set_uts_field(field: &Mutex<[u8; UTSNAME_LEN]>, name: VirtAddr, len: usize) -> isize {
+    if sys_geteuid() != 0 {
+        return -(ErrNo::OperationNotPermitted as isize);
+    }
+    // the last byte is kept for the terminating 0
+    if len >= UTSNAME_LEN {
+        return -(ErrNo::InvalidArgument as isize);
+    }
+    let mut buf = [0u8; UTSNAME_LEN];
+    if let Err(errno) = copy_from_user(&mut buf[..len], name) {
+        return -(errno as isize);
+    }
+    *field.lock() = buf;
+    0
+}
+
+/// Set the hostname reported by uname
+pub fn sys_sethostname(name: VirtAddr, len: usize) -> isize {
+    set_uts_field(&HOSTNAME, name, len)
+}
+
+/// Set the NIS domain name reported by uname
+pub fn sys_setdomainname(name: VirtAddr, len: usize) -> isize {
+    set_uts_field(&DOMAIN, name, len)
+}
+
 /// Rsturn system informations.
 pub fn sys_uname(uts_va: VirtAddr) -> isize {
     let mut uts: UTSName = UTSName {
@@ -80,39 +152,138 @@ pub fn sys_uname(uts_va: VirtAddr) -> isize {
         domainname : [0u8; UTSNAME_LEN] ,
     };
     uts.sysname   [0..SYSNAME   .len()].clone_from_slice(SYSNAME      );
-    uts.nodename  [0..NODENAME  .len()].clone_from_slice(NODENAME     );
+    uts.nodename = *HOSTNAME.lock();
     uts.release   [0..RELEASE   .len()].clone_from_slice(RELEASE      );
     uts.version   [0..VERSION   .len()].clone_from_slice(VERSION      );
     uts.machine   [0..MACHINE   .len()].clone_from_slice(MACHINE      );
-    uts.domainname[0..DOMAINNAME.len()].clone_from_slice(DOMAINNAME   );
+    uts.domainname = *DOMAIN.lock();
 
-    current_process().unwrap().get_inner_locked().layout.write_user_data(uts_va, &uts);
-    0
+    match write_to_user(uts_va, &uts) {
+        Ok(()) => 0,
+        Err(errno) => -(errno as isize),
+    }
+}
+
+/// Sleep until timer reaches `deadline` ticks.
+/// # Description
+/// The process is parked off the run queue, and woken up by the timer once `deadline` passes.
+/// # Returns
+/// Err(InterruptedSystemCall) if woken up early by a signal.
+fn sleep_until(deadline: u64) -> Result<(), ErrNo> {
+    while get_time() < deadline {
+        if current_signal_pending() {
+            return Err(ErrNo::InterruptedSystemCall);
+        }
+        current_process().unwrap().get_inner_locked().wake_at = deadline;
+        sleep_switch();
+        current_process().unwrap().get_inner_locked().wake_at = 0;
+    }
+    Ok(())
 }
 
 /// Sleep for a specified time.
-pub fn sys_nanosleep(req: VirtAddr, _: VirtAddr) -> isize{
-    let req: TimeSPEC = current_process().unwrap().get_inner_locked().layout.read_user_data(req);
-    while get_time() / CLOCK_FREQ < req.tvsec {
-        suspend_switch();
+pub fn sys_nanosleep(req: VirtAddr, rem: VirtAddr) -> isize{
+    sys_clock_nanosleep(CLOCK_MONOTONIC, 0, req, rem)
+}
+
+/// Sleep for a specified time, or until an absolute time with TIMER_ABSTIME.
+/// # Description
+/// Since we don't have RTC, both clocks count from boot.  
+/// An interrupted relative sleep writes the time left to `rem`. If it is restarted by SA_RESTART, it sleeps until
+/// the deadline of the interrupted one rather than for the full time again.
+pub fn sys_clock_nanosleep(clock_id: usize, flags: usize, req: VirtAddr, rem: VirtAddr) -> isize {
+    if clock_id != CLOCK_REALTIME && clock_id != CLOCK_MONOTONIC {
+        return -(ErrNo::InvalidArgument as isize);
     }
-    while (get_time() * (1000000000 / CLOCK_FREQ)) % 1000000000 < req.tvnsec as u64 {
-        suspend_switch();
+    let req: TimeSPEC = match read_from_user(req) {
+        Ok(req) => req,
+        Err(errno) => return -(errno as isize),
+    };
+    let proc = current_process().unwrap();
+    let mut arcpcb = proc.get_inner_locked();
+    if req.tvnsec >= 1000000000 {
+        return -(ErrNo::InvalidArgument as isize);
+    }
+    let restarted = arcpcb.sleep_deadline.take();
+    let deadline = if flags & TIMER_ABSTIME != 0 {
+        req.to_ticks()
+    } else {
+        restarted.unwrap_or_else(|| get_time().saturating_add(req.to_ticks()))
+    };
+    drop(arcpcb);
+    drop(proc);
+    match sleep_until(deadline) {
+        Ok(()) => 0,
+        Err(errno) => {
+            if flags & TIMER_ABSTIME == 0 {
+                current_process().unwrap().get_inner_locked().sleep_deadline = Some(deadline);
+                if rem.0 != 0 {
+                    let left = TimeSPEC::from_ticks(deadline.saturating_sub(get_time()));
+                    if let Err(errno) = write_to_user(rem, &left) {
+                        return -(errno as isize);
+                    }
+                }
+            }
+            -(errno as isize)
+        }
     }
+}
 
-    0
+/// Linux style sysinfo
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SysInfo {
+    pub uptime      : i64,
+    pub loads       : [u64; 3],
+    pub totalram    : u64,
+    pub freeram     : u64,
+    pub sharedram   : u64,
+    pub bufferram   : u64,
+    pub totalswap   : u64,
+    pub freeswap    : u64,
+    pub procs       : u16,
+    pub pad         : u16,
+    pub totalhigh   : u64,
+    pub freehigh    : u64,
+    pub mem_unit    : u32,
 }
 
+/// Fixed point shift of loads in sysinfo
+const SI_LOAD_SHIFT: usize = 16;
+
+/// Return overall system statistics.
+/// # Description
+/// RAM figures are in bytes: the physical frames plus the kernel heap. There's no swap.
 pub fn sys_info(sysinfo: VirtAddr) -> isize {
-    // TODO
+    let avg = load_avg();
+    let info = SysInfo {
+        uptime      : (get_time() / CLOCK_FREQ) as i64,
+        loads       : [
+            (avg[0] << (SI_LOAD_SHIFT - FSHIFT)) as u64,
+            (avg[1] << (SI_LOAD_SHIFT - FSHIFT)) as u64,
+            (avg[2] << (SI_LOAD_SHIFT - FSHIFT)) as u64,
+        ],
+        totalram    : (total_frames() * PAGE_SIZE + KERNEL_HEAP_SIZE) as u64,
+        freeram     : (free_frames() * PAGE_SIZE + kernel_heap_capacity() - kernel_heap_used()) as u64,
+        sharedram   : 0,
+        bufferram   : 0,
+        totalswap   : 0,
+        freeswap    : 0,
+        procs       : nr_processes() as u16,
+        pad         : 0,
+        totalhigh   : 0,
+        freehigh    : 0,
+        mem_unit    : 1,
+    };
+    current_process().unwrap().get_inner_locked().layout.write_user_data(sysinfo, &info);
     return 0;
 }
 
 pub fn sys_getuid() -> isize {
-    return 0;
+    return current_process().unwrap().get_inner_locked().uid as isize;
 }
 pub fn sys_geteuid() -> isize {
-    return 0;
+    return current_process().unwrap().get_inner_locked().uid as isize;
 }
 pub fn sys_getgid() -> isize {
     return 0;
@@ -121,6 +292,48 @@ pub fn sys_getegid() -> isize {
     return 0;
 }
 
+const LINUX_REBOOT_MAGIC1         : u32 = 0xfee1dead;
+const LINUX_REBOOT_MAGIC2         : u32 = 672274793;
+const LINUX_REBOOT_MAGIC2A        : u32 = 85072278;
+const LINUX_REBOOT_MAGIC2B        : u32 = 369367448;
+const LINUX_REBOOT_MAGIC2C        : u32 = 537993216;
+
+const LINUX_REBOOT_CMD_RESTART    : u32 = 0x01234567;
+const LINUX_REBOOT_CMD_HALT       : u32 = 0xCDEF0123;
+const LINUX_REBOOT_CMD_CAD_ON     : u32 = 0x89ABCDEF;
+const LINUX_REBOOT_CMD_CAD_OFF    : u32 = 0x00000000;
+const LINUX_REBOOT_CMD_POWER_OFF  : u32 = 0x4321FEDC;
+
+/// Reboot or power off the machine
+/// # Description
+/// All mounted filesystems are synced before the SBI reset.
+/// # Returns
+/// Does not return on success, -EPERM if not root, -EINVAL on bad magic or command.
+pub fn sys_reboot(magic1: u32, magic2: u32, cmd: u32, _arg: VirtAddr) -> isize {
+    if sys_geteuid() != 0 {
+        return -(ErrNo::OperationNotPermitted as isize);
+    }
+    if magic1 != LINUX_REBOOT_MAGIC1 || ![LINUX_REBOOT_MAGIC2, LINUX_REBOOT_MAGIC2A, LINUX_REBOOT_MAGIC2B, LINUX_REBOOT_MAGIC2C].contains(&magic2) {
+        return -(ErrNo::InvalidArgument as isize);
+    }
+    match cmd {
+        LINUX_REBOOT_CMD_CAD_ON | LINUX_REBOOT_CMD_CAD_OFF => 0,
+        LINUX_REBOOT_CMD_HALT | LINUX_REBOOT_CMD_POWER_OFF => {
+            info!("Powering off...");
+            crate::fs::sync_all();
+            crate::sbi::shutdown();
+        },
+        LINUX_REBOOT_CMD_RESTART => {
+            info!("Restarting system...");
+            crate::fs::sync_all();
+            let err = crate::sbi::reboot();
+            error!("SBI reboot failed with {}", err);
+            -(ErrNo::FunctionNotImplemented as isize)
+        },
+        _ => -(ErrNo::InvalidArgument as isize),
+    }
+}
+
 
 const RUSAGE_SELF     : i32 = 0;
 const RUSAGE_CHILDREN : i32 = -1;
@@ -157,7 +370,8 @@ pub struct RUSage {
 
 pub fn sys_getrusage(who: i32, rusage_ptr: VirtAddr) -> isize {
     let process = current_process().unwrap();
-    let arcpcb = process.get_inner_locked();
+    let mut arcpcb = process.get_inner_locked();
+    arcpcb.update_rss();
 
     let rusage = match who {
         RUSAGE_SELF | RUSAGE_CHILDREN | RUSAGE_BOTH => {
@@ -178,7 +392,7 @@ pub fn sys_getrusage(who: i32, rusage_ptr: VirtAddr) -> isize {
                     tvsec: (u_time / CLOCK_FREQ) as u32,
                     tvnsec: (u_time % CLOCK_FREQ * 1000000) as u32,
                 },
-                maxrss:     0,
+                maxrss:     (arcpcb.max_rss * PAGE_SIZE / 1024) as u32,
                 ixrss:      0,
                 idrss:      arcpcb.size as u32,
                 isrss:      USER_STACK_SIZE  as u32,
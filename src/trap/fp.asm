@@ -0,0 +1,86 @@
+# Save and load the floating point registers of a user process
+# the kernel is built without F/D, so fsd/fld are encoded by hand
+    .section .text
+    .globl __save_fp
+    .globl __load_fp
+    .align 2
+__save_fp:
+    # a0: &fregs, fcsr follows f31
+    # FP instructions trap unless FS is on
+    li t0, 0x6000
+    csrs sstatus, t0
+    .word 0x00053027    # fsd f0, 0*8(a0)
+    .word 0x00153427    # fsd f1, 1*8(a0)
+    .word 0x00253827    # fsd f2, 2*8(a0)
+    .word 0x00353c27    # fsd f3, 3*8(a0)
+    .word 0x02453027    # fsd f4, 4*8(a0)
+    .word 0x02553427    # fsd f5, 5*8(a0)
+    .word 0x02653827    # fsd f6, 6*8(a0)
+    .word 0x02753c27    # fsd f7, 7*8(a0)
+    .word 0x04853027    # fsd f8, 8*8(a0)
+    .word 0x04953427    # fsd f9, 9*8(a0)
+    .word 0x04a53827    # fsd f10, 10*8(a0)
+    .word 0x04b53c27    # fsd f11, 11*8(a0)
+    .word 0x06c53027    # fsd f12, 12*8(a0)
+    .word 0x06d53427    # fsd f13, 13*8(a0)
+    .word 0x06e53827    # fsd f14, 14*8(a0)
+    .word 0x06f53c27    # fsd f15, 15*8(a0)
+    .word 0x09053027    # fsd f16, 16*8(a0)
+    .word 0x09153427    # fsd f17, 17*8(a0)
+    .word 0x09253827    # fsd f18, 18*8(a0)
+    .word 0x09353c27    # fsd f19, 19*8(a0)
+    .word 0x0b453027    # fsd f20, 20*8(a0)
+    .word 0x0b553427    # fsd f21, 21*8(a0)
+    .word 0x0b653827    # fsd f22, 22*8(a0)
+    .word 0x0b753c27    # fsd f23, 23*8(a0)
+    .word 0x0d853027    # fsd f24, 24*8(a0)
+    .word 0x0d953427    # fsd f25, 25*8(a0)
+    .word 0x0da53827    # fsd f26, 26*8(a0)
+    .word 0x0db53c27    # fsd f27, 27*8(a0)
+    .word 0x0fc53027    # fsd f28, 28*8(a0)
+    .word 0x0fd53427    # fsd f29, 29*8(a0)
+    .word 0x0fe53827    # fsd f30, 30*8(a0)
+    .word 0x0ff53c27    # fsd f31, 31*8(a0)
+    csrr t0, 0x003    # fcsr
+    sd t0, 32*8(a0)
+    ret
+
+__load_fp:
+    # a0: &fregs, fcsr follows f31
+    li t0, 0x6000
+    csrs sstatus, t0
+    .word 0x00053007    # fld f0, 0*8(a0)
+    .word 0x00853087    # fld f1, 1*8(a0)
+    .word 0x01053107    # fld f2, 2*8(a0)
+    .word 0x01853187    # fld f3, 3*8(a0)
+    .word 0x02053207    # fld f4, 4*8(a0)
+    .word 0x02853287    # fld f5, 5*8(a0)
+    .word 0x03053307    # fld f6, 6*8(a0)
+    .word 0x03853387    # fld f7, 7*8(a0)
+    .word 0x04053407    # fld f8, 8*8(a0)
+    .word 0x04853487    # fld f9, 9*8(a0)
+    .word 0x05053507    # fld f10, 10*8(a0)
+    .word 0x05853587    # fld f11, 11*8(a0)
+    .word 0x06053607    # fld f12, 12*8(a0)
+    .word 0x06853687    # fld f13, 13*8(a0)
+    .word 0x07053707    # fld f14, 14*8(a0)
+    .word 0x07853787    # fld f15, 15*8(a0)
+    .word 0x08053807    # fld f16, 16*8(a0)
+    .word 0x08853887    # fld f17, 17*8(a0)
+    .word 0x09053907    # fld f18, 18*8(a0)
+    .word 0x09853987    # fld f19, 19*8(a0)
+    .word 0x0a053a07    # fld f20, 20*8(a0)
+    .word 0x0a853a87    # fld f21, 21*8(a0)
+    .word 0x0b053b07    # fld f22, 22*8(a0)
+    .word 0x0b853b87    # fld f23, 23*8(a0)
+    .word 0x0c053c07    # fld f24, 24*8(a0)
+    .word 0x0c853c87    # fld f25, 25*8(a0)
+    .word 0x0d053d07    # fld f26, 26*8(a0)
+    .word 0x0d853d87    # fld f27, 27*8(a0)
+    .word 0x0e053e07    # fld f28, 28*8(a0)
+    .word 0x0e853e87    # fld f29, 29*8(a0)
+    .word 0x0f053f07    # fld f30, 30*8(a0)
+    .word 0x0f853f87    # fld f31, 31*8(a0)
+    ld t0, 32*8(a0)
+    csrw 0x003, t0    # fcsr
+    ret
@@ -0,0 +1,44 @@
+//! Lazy enabling of the FPU
+//! # Description
+//! Processes start with sstatus.FS = Off, so their first FP instruction traps as an illegal instruction.
+//! The trap turns FS on and the instruction runs again. From then on the hardware marks FS dirty
+//! whenever the process writes an FP register, and only then are they saved on trap entry.
+
+use crate::memory::{MemLayout, VirtAddr};
+use super::TrapContext;
+use super::unaligned::fetch;
+
+/// fflags, frm and fcsr
+const FP_CSRS: core::ops::RangeInclusive<u32> = 0x001..=0x003;
+
+/// If `insn` needs the FPU
+fn is_fp_instruction(insn: u32) -> bool {
+    if insn & 0b11 != 0b11 {
+        // c.fld, c.fsd, c.fldsp, c.fsdsp
+        let funct3 = (insn >> 13) & 0b111;
+        return matches!((insn & 0b11, funct3), (0b00, 0b001) | (0b00, 0b101) | (0b10, 0b001) | (0b10, 0b101));
+    }
+    match insn & 0x7f {
+        // LOAD-FP, STORE-FP, fmadd, fmsub, fnmsub, fnmadd, OP-FP
+        0x07 | 0x27 | 0x43 | 0x47 | 0x4b | 0x4f | 0x53 => true,
+        // csr access to the FP csrs
+        0x73 => (insn >> 12) & 0b111 != 0 && FP_CSRS.contains(&(insn >> 20)),
+        _ => false,
+    }
+}
+
+/// Turn the FPU on if the illegal instruction at sepc is an FP one used with FS = Off
+/// # Return
+/// True if the instruction should simply be retried
+pub fn enable_fp_on_demand(layout: &mut MemLayout, trap_context: &mut TrapContext) -> bool {
+    if !trap_context.fp_off() {
+        return false;
+    }
+    match fetch(layout, VirtAddr::from(trap_context.sepc)) {
+        Some(insn) if is_fp_instruction(insn) => {
+            trap_context.enable_fp();
+            true
+        },
+        _ => false,
+    }
+}
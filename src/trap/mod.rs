@@ -1,6 +1,10 @@
 //! OSHIT Trap Handle unit.
 mod trap_context;
+mod fp;
 mod trap_handler;
+mod unaligned;
 
-pub use trap_context::TrapContext;
-pub use trap_handler::{init, user_trap, trap_return};
\ No newline at end of file
+pub use trap_context::{TrapContext, FS_OFF, FS_INITIAL, FS_CLEAN, FS_DIRTY};
+pub use unaligned::emulate_unaligned;
+pub use fp::enable_fp_on_demand;
+pub use trap_handler::{init, user_trap, trap_return, send_sigsegv, send_fault_signal, save_user_fp, SIG_DFL, SIG_IGN};
\ No newline at end of file
@@ -1,5 +1,23 @@
 use riscv::register::sstatus::{Sstatus, self, SPP};
 
+global_asm!(include_str!("./fp.asm"));
+
+/// FS field of sstatus, state of the FP registers
+const SSTATUS_FS    : usize = 0b11 << 13;
+/// FP instructions trap
+pub const FS_OFF    : usize = 0;
+/// FP registers hold their initial values
+pub const FS_INITIAL: usize = 1 << 13;
+/// FP registers match the saved ones
+pub const FS_CLEAN  : usize = 2 << 13;
+/// FP registers changed since they were last saved
+pub const FS_DIRTY  : usize = 3 << 13;
+
+extern "C" {
+    fn __save_fp(fregs: *mut usize);
+    fn __load_fp(fregs: *const usize);
+}
+
 /// The trap context, including all registers and some CSRs for context switching.
 #[repr(C)]
 #[derive(Clone, Copy)]
@@ -10,6 +28,10 @@ pub struct TrapContext {
     pub kernel_satp     : usize,
     pub kernel_sp       : usize,
     pub user_trap       : usize,
+    /// f0 ~ f31, kept after the fields used by trap.asm so their offsets stay the same
+    pub fregs           : [usize; 32],
+    /// must follow `fregs`, fp.asm stores it as the 33rd register
+    pub fcsr            : usize,
 }
 
 impl TrapContext {
@@ -37,8 +59,59 @@ impl TrapContext {
             kernel_satp,
             kernel_sp,
             user_trap,
+            fregs: [0; 32],
+            fcsr: 0,
         };
         context.set_sp(sp);
+        // FP stays off until the first FP instruction traps, integer only programs never pay for saving it
+        context.set_fs(FS_OFF);
         return context;
     }
+
+    /// FS field of the saved sstatus
+    pub fn fs(&self) -> usize {
+        // Sstatus is a plain usize inside
+        let bits = unsafe { *(&self.sstatus as *const Sstatus as *const usize) };
+        bits & SSTATUS_FS
+    }
+
+    /// Set the FS field of the saved sstatus
+    pub fn set_fs(&mut self, fs: usize) {
+        let bits = unsafe { &mut *(&mut self.sstatus as *mut Sstatus as *mut usize) };
+        *bits = (*bits & !SSTATUS_FS) | (fs & SSTATUS_FS);
+    }
+
+    /// FP instructions of the process trap
+    pub fn fp_off(&self) -> bool {
+        self.fs() == FS_OFF
+    }
+
+    /// Let the process use FP, starting from the saved registers
+    pub fn enable_fp(&mut self) {
+        self.set_fs(FS_INITIAL);
+    }
+
+    /// Save the FP registers if the user changed them
+    /// # Description
+    /// Called on trap entry, before anything else can run on this hart.
+    /// Processes that never touched FP have nothing to save.
+    /// # Return
+    /// If the registers were saved
+    pub fn save_fp(&mut self) -> bool {
+        if self.fs() == FS_DIRTY {
+            unsafe { __save_fp(self.fregs.as_mut_ptr()) };
+            self.set_fs(FS_CLEAN);
+            return true;
+        }
+        false
+    }
+
+    /// Load the saved FP registers back before returning to user
+    /// # Description
+    /// Another process may have used the FP registers in the meantime, so they are loaded whenever FP is on.
+    pub fn restore_fp(&self) {
+        if self.fs() != FS_OFF {
+            unsafe { __load_fp(self.fregs.as_ptr()) };
+        }
+    }
 }
\ No newline at end of file
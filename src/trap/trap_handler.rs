@@ -1,6 +1,6 @@
 //! Trap handler of oshit kernel
 use super::TrapContext;
-use crate::{memory::{VirtAddr, PhysAddr}, process::{current_process, default_sig_handlers}, syscall::syscall, trap};
+use crate::{memory::{VirtAddr, PhysAddr}, process::{current_process, default_sig_handlers}, syscall::{syscall, SYSCALL_RT_SIGSUSPEND}, trap};
 use alloc::sync::Arc;
 use riscv::register::{
     stvec,      // s trap vector base address register
@@ -17,10 +17,17 @@ use crate::sbi::{
     reset_timer_trigger,
     get_time,
 };
-use crate::process::{suspend_switch, exit_switch};
+use crate::process::{suspend_switch, exit_switch, stop_switch, check_timers, set_in_syscall, oom_kill};
 use crate::config::*;
-use crate::process::{current_trap_context, current_satp, SignalFlags};
-use crate::memory::VMAFlags;
+use crate::process::{current_trap_context, current_satp, SignalFlags, SigDisposition, ErrNo};
+use crate::memory::{VMAFlags, MemLayout, take_heap_oom_victim};
+use crate::process::default_handlers::{SIG_UNBLOCKABLE, SIGKILL, SIGCHLD, SIGTRAP, SIGSEGV, SIGBUS, SIGILL};
+use crate::process::{ProcessControlBlock, SigInfo, SI_USER, SEGV_MAPERR, SEGV_ACCERR, BUS_ADRALN, ILL_ILLOPC};
+use crate::process::ptrace::remove_step_breakpoint;
+use crate::process::loadavg::sample_load;
+use super::unaligned::emulate_unaligned;
+use super::fp::enable_fp_on_demand;
+use crate::process::stats::account_user_time;
 
 global_asm!(include_str!("./trap.asm"));
 
@@ -71,7 +78,9 @@ fn puser_start() {
 fn puser_end() {
     if let Some(process) = current_process() {
         let mut lock = process.get_inner_locked();
-        lock.utime += get_time() - lock.last_start;
+        let delta = get_time() - lock.last_start;
+        lock.utime += delta;
+        account_user_time(delta);
     }
 }
 
@@ -84,6 +93,7 @@ fn puser_end() {
 #[no_mangle]
 pub fn user_trap(_cx: &mut TrapContext) -> ! {
     set_kernel_trap_entry();
+    save_user_fp(&current_process().unwrap());
     puser_end();
     let scause = scause::read();
     let stval = stval::read();
@@ -91,19 +101,29 @@ pub fn user_trap(_cx: &mut TrapContext) -> ! {
         Trap::Exception(Exception::UserEnvCall) => {
             let mut cx = current_trap_context();
             cx.sepc += 4;   // so that we don't stuck at one instruction
-            let result = syscall(cx.regs[17], [
+            let args = [
                 cx.regs[10], 
                 cx.regs[11], 
                 cx.regs[12],
                 cx.regs[13],
                 cx.regs[14],
                 cx.regs[15],
-            ]) as usize;   // exec syscall in s-mode
+            ];
+            set_in_syscall(true);
+            let result = syscall(cx.regs[17], args) as usize;   // exec syscall in s-mode
+            set_in_syscall(false);
             cx =  current_trap_context();
             cx.regs[10] = result as usize;
+            // sigsuspend is never restarted
+            if result as isize == -(ErrNo::InterruptedSystemCall as isize) && cx.regs[17] != SYSCALL_RT_SIGSUSPEND {
+                // a0 is overwritten by the return value, keep it in case the syscall gets restarted
+                current_process().unwrap().get_inner_locked().syscall_restart = Some(args[0]);
+            }
         },
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             reset_timer_trigger();
+            check_timers();
+            sample_load();
             suspend_switch();
         },
         // Store page fault, check vma
@@ -111,44 +131,88 @@ pub fn user_trap(_cx: &mut TrapContext) -> ! {
             verbose!("Store Page Fault!");
             let proc = current_process().unwrap();
             let mut arcpcb = proc.get_inner_locked();
-            if let Err(msg) = arcpcb.layout.lazy_copy_vma(stval.into(), VMAFlags::W) {
+            if MemLayout::in_user_stack_guard(stval.into()) {
                 error!(
-                    "{:?} in application {}, bad addr = {:#x}, bad instruction = {:#x}, {}",
-                    scause.cause(),
+                    "Stack overflow in application {}, bad addr = {:#x}, bad instruction = {:#x}",
                     proc.pid.0,
                     stval,
-                    arcpcb.get_trap_context().sepc,
-                    msg
+                    arcpcb.get_trap_context().sepc
                 );
                 drop(arcpcb);
-                proc.recv_signal(crate::process::default_handlers::SIGSEGV);
-                // proc.print_debug_msg();
+                send_sigsegv(&proc, stval);
                 suspend_switch();
+            } else if let Err(msg) = arcpcb.layout.lazy_copy_vma(stval.into(), VMAFlags::W) {
+                if let ErrNo::OutOfMemory = msg {
+                    drop(arcpcb);
+                    if !oom_kill() {
+                        error!("Out of memory in application {}, bad addr = {:#x}", proc.pid.0, stval);
+                        send_sigsegv(&proc, stval);
+                    }
+                    // let the victim release its frames, the access faults again when we get back
+                    suspend_switch();
+                } else {
+                    error!(
+                        "{:?} in application {}, bad addr = {:#x}, bad instruction = {:#x}, {}",
+                        scause.cause(),
+                        proc.pid.0,
+                        stval,
+                        arcpcb.get_trap_context().sepc,
+                        msg
+                    );
+                    drop(arcpcb);
+                    send_sigsegv(&proc, stval);
+                    // proc.print_debug_msg();
+                    suspend_switch();
+                }
+            } else {
+                arcpcb.update_rss();
             }
         },
         Trap::Exception(Exception::LoadPageFault) => {
             verbose!("Load Page Fault");
             let proc = current_process().unwrap();
             let mut arcpcb = proc.get_inner_locked();
-            if let Err(msg) = arcpcb.layout.lazy_copy_vma(stval.into(), VMAFlags::R) {
+            if MemLayout::in_user_stack_guard(stval.into()) {
                 error!(
-                    "{:?} in application, bad addr = {:#x}, bad instruction = {:#x}, {}",
-                    scause.cause(),
+                    "Stack overflow in application {}, bad addr = {:#x}, bad instruction = {:#x}",
+                    proc.pid.0,
                     stval,
-                    arcpcb.get_trap_context().sepc,
-                    msg
+                    arcpcb.get_trap_context().sepc
                 );
-                if let Some(pte) = arcpcb.layout.pagetable.walk(VirtAddr::from(stval).into()) {
-                    error!("Pagetable entry flags: {:?}", pte.flags());
-                } else {
-                    error!("No such pagetable entry");
-                }
-                arcpcb.layout.print_layout();
-
                 drop(arcpcb);
-                proc.recv_signal(crate::process::default_handlers::SIGSEGV);
-                // proc.print_debug_msg();
+                send_sigsegv(&proc, stval);
                 suspend_switch();
+            } else if let Err(msg) = arcpcb.layout.lazy_copy_vma(stval.into(), VMAFlags::R) {
+                if let ErrNo::OutOfMemory = msg {
+                    drop(arcpcb);
+                    if !oom_kill() {
+                        error!("Out of memory in application {}, bad addr = {:#x}", proc.pid.0, stval);
+                        send_sigsegv(&proc, stval);
+                    }
+                    // let the victim release its frames, the access faults again when we get back
+                    suspend_switch();
+                } else {
+                    error!(
+                        "{:?} in application, bad addr = {:#x}, bad instruction = {:#x}, {}",
+                        scause.cause(),
+                        stval,
+                        arcpcb.get_trap_context().sepc,
+                        msg
+                    );
+                    if let Some(pte) = arcpcb.layout.pagetable.walk(VirtAddr::from(stval).into()) {
+                        error!("Pagetable entry flags: {:?}", pte.flags());
+                    } else {
+                        error!("No such pagetable entry");
+                    }
+                    arcpcb.layout.print_layout();
+
+                    drop(arcpcb);
+                    send_sigsegv(&proc, stval);
+                    // proc.print_debug_msg();
+                    suspend_switch();
+                }
+            } else {
+                arcpcb.update_rss();
             }
         },
         // TODO: Core dump and/or terminate user program and continue
@@ -171,21 +235,37 @@ pub fn user_trap(_cx: &mut TrapContext) -> ! {
                 error!("No such pagetable entry");
             }
             drop(arcpcb);
-            proc.recv_signal(crate::process::default_handlers::SIGSEGV);
+            send_sigsegv(&proc, stval);
             // proc.print_debug_msg();
             suspend_switch();
         }
-        Trap::Exception(Exception::IllegalInstruction) => {
-            error!(
-                "{:?} in application {}, bad inst = {:#x} @ {:#x}",
-                scause.cause(),
-                current_process().unwrap().pid.0,
-                stval,
-                current_trap_context().sepc,
-            );
-            current_process().unwrap().recv_signal(crate::process::default_handlers::SIGKILL);
-            // current_process().unwrap().print_debug_msg();
-            suspend_switch();
+        Trap::Exception(Exception::Breakpoint) => {
+            let proc = current_process().unwrap();
+            let mut arcpcb = proc.get_inner_locked();
+            let sepc = arcpcb.get_trap_context().sepc;
+            if arcpcb.step_breakpoint.map_or(false, |(addr, _)| addr.0 == sepc) {
+                // single step done, the original instruction runs once the tracee goes on
+                remove_step_breakpoint(&mut arcpcb);
+            }
+            drop(arcpcb);
+            // a tracee stops on it with the pc on the breakpoint, anybody else gets the default action unless it handles SIGTRAP
+            info!("Breakpoint in application {} @ {:#x}", proc.pid.0, sepc);
+            proc.recv_signal(SIGTRAP);
+        }
+        // older riscv crates decode misaligned loads and stores as Unknown, so faults are matched by their scause code
+        Trap::Exception(_) if FAULT_CODES.contains(&scause.code()) => {
+            let proc = current_process().unwrap();
+            let cx = current_trap_context();
+            let misaligned_access = scause.code() == LOAD_MISALIGNED || scause.code() == STORE_MISALIGNED;
+            if scause.code() == ILLEGAL_INSTRUCTION && enable_fp_on_demand(&mut proc.get_inner_locked().layout, cx) {
+                verbose!("Enabled FP for application {}", proc.pid.0);
+            } else if EMULATE_UNALIGNED && misaligned_access && emulate_unaligned(&mut proc.get_inner_locked().layout, cx, stval) {
+                verbose!("Emulated misaligned access to {:#x} in application {}", stval, proc.pid.0);
+            } else {
+                send_fault_signal(&proc, scause.code(), stval, cx.sepc);
+                // proc.print_debug_msg();
+                suspend_switch();
+            }
         }
         _ => {
             let cx = current_trap_context();
@@ -199,19 +279,55 @@ pub fn user_trap(_cx: &mut TrapContext) -> ! {
     trap_return();
 }
 
-#[allow(unused)]
-struct SigInfo {
-    si_signo     :i32,	/* Signal number */
-    si_errno     :i32,	/* An errno value */
-    si_code      :i32,	/* Signal code */
-    si_trapno    :i32,	/* Trap number that caused hardware-generated signal (unused on most architectures) */
-    si_pid       :u32,	/* Sending process ID */
-    si_uid       :u32,	/* Real user ID of sending process */
-    si_status    :i32,	/* Exit value or signal */
-    si_utime     :i32,	/* User time consumed */
-    si_stime     :i32,	/* System time consumed */
+/// Save the FP registers of `proc` on trap entry, if it dirtied them
+/// # Description
+/// Counted in `fp_saves`, integer only processes keep FS off and never get here.
+pub fn save_user_fp(proc: &ProcessControlBlock) {
+    let mut inner = proc.get_inner_locked();
+    if inner.get_trap_context().save_fp() {
+        inner.fp_saves += 1;
+    }
+}
+
+/// Send SIGSEGV for a bad access to `addr`
+/// # Description
+/// si_code tells an unmapped address from an access the mapping doesn't permit.
+pub fn send_sigsegv(proc: &ProcessControlBlock, addr: usize) {
+    let mapped = proc.get_inner_locked().layout.is_mapped(VirtAddr::from(addr));
+    let code = if mapped { SEGV_ACCERR } else { SEGV_MAPERR };
+    proc.recv_signal_info(SIGSEGV, SigInfo::fault(SIGSEGV, code, addr));
 }
 
+/// scause exception codes
+const INSTRUCTION_MISALIGNED: usize = 0;
+const ILLEGAL_INSTRUCTION: usize = 2;
+const LOAD_MISALIGNED: usize = 4;
+const STORE_MISALIGNED: usize = 6;
+/// Exceptions that are the fault of the instruction itself, see `send_fault_signal()`
+const FAULT_CODES: [usize; 4] = [INSTRUCTION_MISALIGNED, ILLEGAL_INSTRUCTION, LOAD_MISALIGNED, STORE_MISALIGNED];
+
+/// Send the signal for a fault of the instruction at `sepc`, by scause exception `code`
+/// # Description
+/// An illegal instruction raises SIGILL on `sepc`, a misaligned jump, load or store SIGBUS on the address in `stval`.
+/// Only the faulting process is signalled.
+/// # Return
+/// false if `code` is not one of `FAULT_CODES`
+pub fn send_fault_signal(proc: &ProcessControlBlock, code: usize, stval: usize, sepc: usize) -> bool {
+    let info = match code {
+        ILLEGAL_INSTRUCTION => SigInfo::fault(SIGILL, ILL_ILLOPC, sepc),
+        INSTRUCTION_MISALIGNED | LOAD_MISALIGNED | STORE_MISALIGNED => SigInfo::fault(SIGBUS, BUS_ADRALN, stval),
+        _ => return false,
+    };
+    error!(
+        "Exception {} in application {}, bad addr = {:#x}, bad instruction @ {:#x}",
+        code,
+        proc.pid.0,
+        stval,
+        sepc,
+    );
+    proc.recv_signal_info(info.si_signo as usize, info);
+    true
+}
 
 pub const SIG_DFL: usize = 0;
 pub const SIG_IGN: usize = 1;
@@ -225,6 +341,11 @@ pub fn trap_return() -> ! {
     set_user_trap_entry();
 
     let current = current_process().unwrap();
+    // the syscall let go of all its locks by now
+    if take_heap_oom_victim(current.pid.0) {
+        error!("Kernel heap exhausted in syscall of process {}, killing it.", current.pid.0);
+        current.recv_signal(SIGKILL);
+    }
     let mut arcpcb = current.get_inner_locked();    
     let mut to_process: Option<(usize, usize)> = None;
 
@@ -232,7 +353,7 @@ pub fn trap_return() -> ! {
         // if *sig.1 == crate::process::default_handlers::SIGALRM {
         //     info!("SIGALRM received");
         // }
-        if ((1u64 << sig.1) & (&arcpcb.sig_mask)) == 0 {
+        if ((1u64 << sig.1) & (&arcpcb.sig_mask) & !SIG_UNBLOCKABLE) == 0 {
             to_process = Some((sig.0, *sig.1));
             break;
         }
@@ -258,15 +379,50 @@ pub fn trap_return() -> ! {
         }
 
         arcpcb.pending_sig.remove(idx);
+        if arcpcb.traced && signal != SIGKILL && arcpcb.ptrace_pass.take() != Some(signal) {
+            // signal-delivery-stop, the tracer decides what becomes of the signal with PTRACE_CONT
+            let tracer = arcpcb.parent.as_ref().and_then(|parent| parent.upgrade());
+            arcpcb.rewind_syscall(true);
+            drop(arcpcb);
+            drop(current);
+            if let Some(tracer) = tracer {
+                // waitpid reports the stop anyway, don't interrupt it with a signal nobody handles
+                if tracer.get_inner_locked().catches_signal(SIGCHLD) {
+                    tracer.recv_signal(SIGCHLD);
+                }
+            }
+            stop_switch(signal);
+            trap_return();
+        }
+        match arcpcb.sig_disposition(signal) {
+            SigDisposition::Default => {
+                let restart = arcpcb.restarts_syscall(signal);
+                arcpcb.rewind_syscall(restart);
+            },
+            SigDisposition::Ignore | SigDisposition::Cont => {
+                // ignored, or already resumed by recv_signal, look for the next one
+                drop(arcpcb);
+                drop(current);
+                trap_return();
+            },
+            SigDisposition::Stop => {
+                // leave the run queue until SIGCONT arrives, the syscall carries on after that
+                arcpcb.rewind_syscall(true);
+                drop(arcpcb);
+                drop(current);
+                stop_switch(signal);
+                trap_return();
+            },
+        }
         let terminate_self_va = crate::process::default_handlers::def_terminate_self as usize - sutrampoline as usize + U_TRAMPOLINE;
-        let ignore_va = crate::process::default_handlers::def_ignore as usize - sutrampoline as usize + U_TRAMPOLINE;
-        let handler_va = if let Some(act) = arcpcb.handlers.get(&signal) {
+        let handler_va = if SIG_UNBLOCKABLE & (1u64 << signal) != 0 {
+            // whatever the handler table says, these always take the default action
+            default_sig_handlers()[&signal].sighandler.0 as usize - sutrampoline as usize + U_TRAMPOLINE
+        } else if let Some(act) = arcpcb.handlers.get(&signal) {
             if act.flags.contains(SignalFlags::SIGINFO) {
                 act.sigaction.0
             } else if act.sighandler.0 == SIG_DFL {
                 default_sig_handlers()[&signal].sighandler.0 as usize - sutrampoline as usize + U_TRAMPOLINE
-            } else if act.sighandler.0 == SIG_IGN {
-                ignore_va
             } else if act.sighandler.0 == SIG_ERR{
                 terminate_self_va
             } else {
@@ -275,23 +431,13 @@ pub fn trap_return() -> ! {
         } else {
             terminate_self_va
         };
-        // let sig_info = SigInfo {
-        //     si_signo:   signal as i32,
-        //     si_errno:   0,
-        //     si_code:    32767,     // SI_NOINFO
-        //     si_trapno:  0,
-        //     si_pid:     0,
-        //     si_uid:     0,
-        //     si_status:  0,
-        //     si_utime:   0,
-        //     si_stime:   0,
-        // };
-        // let siginfo_va = VirtAddr::from(__siginfo as usize - strampoline as usize + TRAMPOLINE);
-        // verbose!("siginfo va = {:?}", siginfo_va);
-        // arcpcb.layout.write_user_data(siginfo_va, &sig_info);
-        
+
+        let siginfo = SIG_UNBLOCKABLE & (1u64 << signal) == 0 && arcpcb.handlers.get(&signal)
+            .map_or(false, |act| act.flags.contains(SignalFlags::SIGINFO));
+        let info = arcpcb.pending_info.remove(&signal).unwrap_or(SigInfo::new(signal, SI_USER));
+
         if arcpcb.handlers.get(&signal).unwrap().flags.contains(SignalFlags::RESETHAND) {
-            arcpcb.handlers.insert(signal, crate::process::default_sig_handlers()[&signal]);
+            arcpcb.set_sig_action(signal, crate::process::default_sig_handlers()[&signal]);
         }
         
         // mask itself
@@ -304,7 +450,20 @@ pub fn trap_return() -> ! {
         
         trap_context.regs[1] = __user_call_sigreturn as usize - sutrampoline as usize + U_TRAMPOLINE;
         trap_context.sepc = handler_va;
-        trap_context.regs[11] = signal;
+        if siginfo {
+            // handler(signo, siginfo, ucontext), the siginfo goes on the user stack
+            trap_context.regs[10] = signal;
+            trap_context.regs[11] = match arcpcb.push_siginfo(&info) {
+                Ok(info_va) => info_va.0,
+                Err(_) => {
+                    warning!("No room for siginfo on the user stack of {}", current.pid.0);
+                    0
+                },
+            };
+            trap_context.regs[12] = 0;
+        } else {
+            trap_context.regs[11] = signal;
+        }
         info!("triggered signal for {}, pc going to: {:x}", current.pid.0, handler_va);
         
         drop(arcpcb);
@@ -314,9 +473,10 @@ pub fn trap_return() -> ! {
         restore_vec = __restore as usize - strampoline as usize + TRAMPOLINE;
         arg0 = trap_cx_ptr;
         arg1 = user_satp;
-        // arg4 = __siginfo as usize - strampoline as usize + TRAMPOLINE;
     } else {
         verbose!("no pending signal for proc {}", current.pid.0);
+        // whatever interrupted the syscall was ignored, so it carries on
+        arcpcb.rewind_syscall(true);
         drop(arcpcb);
         drop(current);
         drop(to_process);
@@ -333,6 +493,7 @@ pub fn trap_return() -> ! {
         arg1 = user_satp;
     }
 
+    current_trap_context().restore_fp();
     puser_start();
     
     unsafe {
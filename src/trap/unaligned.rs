@@ -0,0 +1,106 @@
+//! Emulation of misaligned user memory accesses
+//! # Description
+//! The hardware may trap on misaligned loads and stores instead of handling them.
+//! The integer ones, compressed or not, are decoded and done byte by byte through the user pagetable.
+//! Float, atomic and anything else undecoded is left to SIGBUS.
+
+use crate::memory::{MemLayout, VirtAddr};
+use super::TrapContext;
+
+/// A decoded load or store
+struct Access {
+    /// bytes accessed
+    width: usize,
+    /// sign extend a loaded value
+    signed: bool,
+    store: bool,
+    /// rd of a load, rs2 of a store
+    reg: usize,
+    /// length of the instruction
+    len: usize,
+}
+
+impl Access {
+    fn new(width: usize, signed: bool, store: bool, reg: u32, len: usize) -> Self {
+        Self { width, signed, store, reg: reg as usize, len }
+    }
+}
+
+/// Decode an integer load or store
+fn decode(insn: u32) -> Option<Access> {
+    if insn & 0b11 != 0b11 {
+        // compressed, x8 ~ x15 for the 3 bit registers
+        let funct3 = (insn >> 13) & 0b111;
+        let reg_c = 8 + ((insn >> 2) & 0b111);
+        let rd = (insn >> 7) & 0b11111;
+        let rs2 = (insn >> 2) & 0b11111;
+        return match (insn & 0b11, funct3) {
+            (0b00, 0b010) => Some(Access::new(4, true, false, reg_c, 2)),   // c.lw
+            (0b00, 0b011) => Some(Access::new(8, true, false, reg_c, 2)),   // c.ld
+            (0b00, 0b110) => Some(Access::new(4, false, true, reg_c, 2)),   // c.sw
+            (0b00, 0b111) => Some(Access::new(8, false, true, reg_c, 2)),   // c.sd
+            (0b10, 0b010) => Some(Access::new(4, true, false, rd, 2)),      // c.lwsp
+            (0b10, 0b011) => Some(Access::new(8, true, false, rd, 2)),      // c.ldsp
+            (0b10, 0b110) => Some(Access::new(4, false, true, rs2, 2)),     // c.swsp
+            (0b10, 0b111) => Some(Access::new(8, false, true, rs2, 2)),     // c.sdsp
+            _ => None,
+        };
+    }
+    let funct3 = (insn >> 12) & 0b111;
+    match insn & 0x7f {
+        // lb lh lw ld lbu lhu lwu
+        0x03 if funct3 != 0b111 => {
+            let width = 1 << (funct3 & 0b11);
+            Some(Access::new(width, funct3 < 0b100, false, (insn >> 7) & 0b11111, 4))
+        },
+        // sb sh sw sd
+        0x23 if funct3 < 0b100 => Some(Access::new(1 << funct3, false, true, (insn >> 20) & 0b11111, 4)),
+        _ => None,
+    }
+}
+
+/// Fetch the instruction at `pc`
+pub(super) fn fetch(layout: &mut MemLayout, pc: VirtAddr) -> Option<u32> {
+    let mut half = [0u8; 2];
+    layout.copy_from_user(&mut half, pc).ok()?;
+    let mut insn = u16::from_le_bytes(half) as u32;
+    if insn & 0b11 == 0b11 {
+        layout.copy_from_user(&mut half, pc + 2).ok()?;
+        insn |= (u16::from_le_bytes(half) as u32) << 16;
+    }
+    Some(insn)
+}
+
+/// Carry out the misaligned access to `addr` that trapped at sepc, and step over it
+/// # Return
+/// False if the instruction can't be emulated or the memory can't be accessed, the process should get SIGBUS then
+pub fn emulate_unaligned(layout: &mut MemLayout, trap_context: &mut TrapContext, addr: usize) -> bool {
+    let access = match fetch(layout, VirtAddr::from(trap_context.sepc)).and_then(decode) {
+        Some(access) => access,
+        None => return false,
+    };
+    let mut bytes = [0u8; 8];
+    if access.store {
+        let val = if access.reg == 0 { 0 } else { trap_context.regs[access.reg] };
+        bytes = val.to_le_bytes();
+        if layout.copy_to_user(VirtAddr::from(addr), &bytes[..access.width]).is_err() {
+            return false;
+        }
+    } else {
+        if layout.copy_from_user(&mut bytes[..access.width], VirtAddr::from(addr)).is_err() {
+            return false;
+        }
+        let shift = 64 - access.width * 8;
+        let val = u64::from_le_bytes(bytes);
+        let val = if access.signed {
+            ((val << shift) as i64 >> shift) as u64
+        } else {
+            val
+        };
+        if access.reg != 0 {
+            trap_context.regs[access.reg] = val as usize;
+        }
+    }
+    trap_context.sepc += access.len;
+    true
+}
@@ -0,0 +1,242 @@
+//! Minimal flattened device tree parser
+//! # Description
+//! Only walks the structure block to find the RAM range and the registers of devices we drive.
+//! Nothing is allocated, so it can run before the kernel heap is up.
+
+use spin::Mutex;
+use lazy_static::*;
+use crate::config::PAGE_SIZE;
+use super::strlen;
+
+const FDT_MAGIC         : u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE    : u32 = 1;
+const FDT_END_NODE      : u32 = 2;
+const FDT_PROP          : u32 = 3;
+const FDT_NOP           : u32 = 4;
+const FDT_END           : u32 = 9;
+
+/// Max nesting of nodes we keep track of
+const MAX_DEPTH: usize = 16;
+/// Max # of (merged) MMIO regions recorded
+pub const MAX_MMIO_REGIONS: usize = 16;
+/// Nodes whose registers are identity mapped for the drivers
+const MMIO_NODES: &[&[u8]] = &[b"virtio_mmio", b"uart", b"serial"];
+/// "compatible" strings of UARTs we can drive directly
+const UART_16550_COMPAT: &[&[u8]] = &[b"ns16550a", b"ns16550"];
+/// Kernel command line option that selects the 16550 UART as console
+const CONSOLE_UART_ARG: &[u8] = b"console=uart";
+
+/// What the device tree tells us about the board
+#[derive(Clone, Copy, Debug)]
+pub struct BoardInfo {
+    pub mem_start: usize,
+    pub mem_end: usize,
+    /// (start, size) of page aligned MMIO regions, the first `mmio_cnt` are valid
+    pub mmio: [(usize, usize); MAX_MMIO_REGIONS],
+    pub mmio_cnt: usize,
+    /// Base of a ns16550 compatible UART, 0 if there is none
+    pub uart16550: usize,
+    /// /chosen/bootargs has `console=uart`
+    pub console_uart: bool,
+}
+
+lazy_static! {
+    static ref BOARD_INFO: Mutex<Option<BoardInfo>> = Mutex::new(None);
+}
+
+fn be32(addr: usize) -> u32 {
+    u32::from_be(unsafe { (addr as *const u32).read_volatile() })
+}
+
+fn align4(addr: usize) -> usize {
+    (addr + 3) & !3
+}
+
+fn cstr(addr: usize) -> &'static [u8] {
+    let ptr = addr as *const u8;
+    unsafe { core::slice::from_raw_parts(ptr, strlen(ptr)) }
+}
+
+/// Read a number made of `cells` big endian u32 at `addr`
+fn read_cells(addr: usize, cells: u32) -> usize {
+    let mut res = 0usize;
+    for i in 0..cells as usize {
+        res = (res << 32) | be32(addr + i * 4) as usize;
+    }
+    res
+}
+
+/// Node name without the unit address, "memory@80000000" -> "memory"
+fn base_name(name: &[u8]) -> &[u8] {
+    match name.iter().position(|c| *c == b'@') {
+        Some(pos) => &name[..pos],
+        None => name,
+    }
+}
+
+impl BoardInfo {
+    fn empty() -> Self {
+        Self {
+            mem_start: 0,
+            mem_end: 0,
+            mmio: [(0, 0); MAX_MMIO_REGIONS],
+            mmio_cnt: 0,
+            uart16550: 0,
+            console_uart: false,
+        }
+    }
+
+    /// Record a MMIO range, rounded to pages and merged with overlapping or adjacent ones
+    fn add_mmio(&mut self, start: usize, size: usize) {
+        let mut end = (start + size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let mut start = start & !(PAGE_SIZE - 1);
+        let mut i = 0;
+        while i < self.mmio_cnt {
+            let (s, sz) = self.mmio[i];
+            if s <= end && start <= s + sz {
+                start = core::cmp::min(start, s);
+                end = core::cmp::max(end, s + sz);
+                self.mmio_cnt -= 1;
+                self.mmio[i] = self.mmio[self.mmio_cnt];
+            } else {
+                i += 1;
+            }
+        }
+        if self.mmio_cnt == MAX_MMIO_REGIONS {
+            warning!("fdt: too many MMIO regions, dropping 0x{:X} ~ 0x{:X}", start, end);
+            return;
+        }
+        self.mmio[self.mmio_cnt] = (start, end - start);
+        self.mmio_cnt += 1;
+    }
+
+    /// Handle the "reg" property of node `name`, `cells` are (#address-cells, #size-cells) of its parent
+    fn add_reg(&mut self, name: &[u8], cells: (u32, u32), val: usize, len: usize) {
+        let name = base_name(name);
+        let entry = (cells.0 + cells.1) as usize * 4;
+        if entry == 0 {
+            return;
+        }
+        if name == &b"memory"[..] {
+            if self.mem_end == 0 && len >= entry {
+                self.mem_start = read_cells(val, cells.0);
+                self.mem_end = self.mem_start + read_cells(val + cells.0 as usize * 4, cells.1);
+            }
+        } else if MMIO_NODES.contains(&name) {
+            let mut off = 0;
+            while off + entry <= len {
+                let start = read_cells(val + off, cells.0);
+                let size = read_cells(val + off + cells.0 as usize * 4, cells.1);
+                self.add_mmio(start, size);
+                off += entry;
+            }
+        }
+    }
+}
+
+/// If the stringlist property at `val` contains one of `wanted`
+fn has_string(val: usize, len: usize, wanted: &[&[u8]]) -> bool {
+    let list = unsafe { core::slice::from_raw_parts(val as *const u8, len) };
+    list.split(|c| *c == 0).any(|s| wanted.contains(&s))
+}
+
+/// If the string property at `val` contains `word` as a whitespace separated word
+fn has_word(val: usize, len: usize, word: &[u8]) -> bool {
+    let s = unsafe { core::slice::from_raw_parts(val as *const u8, len) };
+    s.split(|c| *c == 0 || *c == b' ').any(|w| w == word)
+}
+
+/// Parse the device tree blob at `dtb`
+/// # Return
+/// None if there is no valid blob or it has no memory node
+pub fn parse(dtb: usize) -> Option<BoardInfo> {
+    if dtb == 0 || dtb % 4 != 0 || be32(dtb) != FDT_MAGIC {
+        return None;
+    }
+    let mut p = dtb + be32(dtb + 8) as usize;
+    let strings = dtb + be32(dtb + 12) as usize;
+    let mut info = BoardInfo::empty();
+    // (#address-cells, #size-cells) declared by the node at each depth
+    let mut cells = [(2u32, 1u32); MAX_DEPTH];
+    let mut names: [&[u8]; MAX_DEPTH] = [&b""[..]; MAX_DEPTH];
+    // first register address and ns16550 compatibility of the node at each depth
+    let mut reg_start = [0usize; MAX_DEPTH];
+    let mut is_16550 = [false; MAX_DEPTH];
+    let mut depth = 0;
+    loop {
+        let token = be32(p);
+        p += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(p);
+                p = align4(p + name.len() + 1);
+                depth += 1;
+                if depth >= MAX_DEPTH {
+                    warning!("fdt: device tree too deep");
+                    return None;
+                }
+                names[depth] = name;
+                cells[depth] = (2, 1);
+                reg_start[depth] = 0;
+                is_16550[depth] = false;
+            },
+            FDT_END_NODE => {
+                if depth == 0 {
+                    return None;
+                }
+                if is_16550[depth] && reg_start[depth] != 0 && info.uart16550 == 0 {
+                    info.uart16550 = reg_start[depth];
+                }
+                depth -= 1;
+            },
+            FDT_PROP => {
+                let len = be32(p) as usize;
+                let prop = cstr(strings + be32(p + 4) as usize);
+                let val = p + 8;
+                p = align4(val + len);
+                match prop {
+                    b"#address-cells" => cells[depth].0 = be32(val),
+                    b"#size-cells" => cells[depth].1 = be32(val),
+                    b"reg" if depth > 0 => {
+                        info.add_reg(names[depth], cells[depth - 1], val, len);
+                        reg_start[depth] = read_cells(val, cells[depth - 1].0);
+                    },
+                    b"compatible" => is_16550[depth] = has_string(val, len, UART_16550_COMPAT),
+                    b"bootargs" if base_name(names[depth]) == &b"chosen"[..] => {
+                        info.console_uart = has_word(val, len, CONSOLE_UART_ARG);
+                    },
+                    _ => {},
+                }
+            },
+            FDT_NOP => {},
+            FDT_END => break,
+            _ => {
+                warning!("fdt: bad token {} @ 0x{:X}", token, p - 4);
+                return None;
+            }
+        }
+    }
+    if info.mem_end == 0 {
+        return None;
+    }
+    Some(info)
+}
+
+/// Parse the blob passed by SBI and remember the result
+pub fn init(dtb: usize) {
+    let info = parse(dtb);
+    match &info {
+        Some(info) => {
+            info!("fdt: memory 0x{:X} ~ 0x{:X}, {} MMIO regions", info.mem_start, info.mem_end, info.mmio_cnt);
+        },
+        None => {
+            info!("fdt: no usable device tree @ 0x{:X}, using static board config", dtb);
+        }
+    }
+    *BOARD_INFO.lock() = info;
+}
+
+/// Board info found in the device tree, None if there is none
+pub fn board_info() -> Option<BoardInfo> {
+    *BOARD_INFO.lock()
+}
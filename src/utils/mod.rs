@@ -1,8 +1,11 @@
 mod range;
 mod mem_op;
+mod random;
+pub mod fdt;
 
 pub use range::{
     StepByOne,
+    CheckedStep,
     SimpleRange,
 };
 
@@ -11,6 +14,11 @@ pub use mem_op::{
     strlen
 };
 
+pub use random::{
+    rand_u64,
+    fill_random
+};
+
 
 
 use crate::config::PAGE_SIZE;
@@ -0,0 +1,32 @@
+//! A tiny pseudo random number generator, for AT_RANDOM and the like.  
+//! xorshift64*, reseeded with the timer on every call. Not cryptographically secure.
+#![allow(unused)]
+use crate::sbi::get_time;
+use lazy_static::*;
+use spin::Mutex;
+
+lazy_static! {
+    static ref RAND_STATE: Mutex<u64> = Mutex::new(0x2545_f491_4f6c_dd1d);
+}
+
+/// Get a pseudo random u64
+pub fn rand_u64() -> u64 {
+    let mut state = RAND_STATE.lock();
+    let mut x = *state ^ get_time();
+    if x == 0 {
+        x = 0x2545_f491_4f6c_dd1d;
+    }
+    x ^= x >> 12;
+    x ^= x << 25;
+    x ^= x >> 27;
+    *state = x;
+    x.wrapping_mul(0x2545_f491_4f6c_dd1d)
+}
+
+/// Fill `buf` with pseudo random bytes
+pub fn fill_random(buf: &mut [u8]) {
+    for chunk in buf.chunks_mut(8) {
+        let r = rand_u64().to_le_bytes();
+        chunk.copy_from_slice(&r[..chunk.len()]);
+    }
+}
@@ -6,6 +6,14 @@ pub trait StepByOne {
     fn step(&mut self);
 }
 
+/// Steps that may run out of the representable range
+pub trait CheckedStep: Sized {
+    /// `self + n`, None on overflow
+    fn checked_forward(self, n: usize) -> Option<Self>;
+    /// `self - n`, None on underflow
+    fn checked_backward(self, n: usize) -> Option<Self>;
+}
+
 #[derive(Copy, Clone)]
 pub struct SimpleRange<T> where
     T: StepByOne + Copy + PartialEq + PartialOrd + Debug, {
@@ -18,6 +26,20 @@ impl<T> SimpleRange<T> where
         assert!(start <= end, "start {:?} > end {:?}!", start, end);
         Self { l: start, r: end }
     }
+    /// Same as new, but None instead of panic if `start > end`
+    pub fn try_new(start: T, end: T) -> Option<Self> {
+        if start <= end {
+            Some(Self { l: start, r: end })
+        } else {
+            None
+        }
+    }
+    pub fn is_empty(&self) -> bool { self.l == self.r }
+    pub fn contains(&self, t: T) -> bool { self.l <= t && t < self.r }
+    /// If the two ranges share at least one element
+    pub fn intersects(&self, other: &Self) -> bool {
+        self.l < other.r && other.l < self.r
+    }
     pub fn get_start(&self) -> T { self.l }
     pub fn get_end(&self) -> T { self.r }
     pub fn set_end(&mut self, r: T) { self.r = r }
@@ -45,7 +67,8 @@ impl<T> Iterator for SimpleRangeIterator<T> where
     T: StepByOne + Copy + PartialEq + PartialOrd + Debug, {
     type Item = T;
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current == self.end {
+        // >= rather than ==, so that a bad range ends instead of stepping to overflow
+        if self.current >= self.end {
             None
         } else {
             let t = self.current;